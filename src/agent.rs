@@ -0,0 +1,283 @@
+// Stable async API over the analysis pipelines, for Rust applications and
+// non-MCP frontends. Nothing here exposes MCP types: requests are plain
+// structs and results are typed, with errors as `AgentError`.
+use crate::config::Config;
+use crate::errors::ToolError;
+use crate::feasibility::FeasibilityReport;
+use crate::forge::PublishTarget;
+use crate::health::HealthReport;
+use crate::llm::GenerationParams;
+use crate::paths::PathCheck;
+use crate::plan::{self, ManifestEntry, OutputFormat, StructuredPlan, Verbosity};
+use crate::references::Reference;
+use crate::server::{CodeAgentServer, ContextParams, ToolOutput};
+use crate::usage::TokenUsage;
+use schemars::JsonSchema;
+use serde::Serialize;
+
+#[derive(Debug, thiserror::Error)]
+pub enum AgentError {
+    // Its `kind` says why, as in the MCP tools' structured errors.
+    #[error("{0}")]
+    Pipeline(ToolError),
+    #[error("Gemini returned a plan that does not match the expected JSON schema: {0}")]
+    InvalidPlan(serde_json::Error),
+}
+
+// What to analyze.
+#[derive(Clone, Default)]
+pub struct Scope {
+    // Absolute path, .zip/.tar.gz/.tgz/.tar archive, or git URL (`url#branch`).
+    pub directory: String,
+    // Analyzed together with `directory`, each with an equal share of the token limit.
+    pub directories: Vec<String>,
+    // Analyze `directory` as of this commit instead of the working tree.
+    pub commit: Option<String>,
+}
+
+impl Scope {
+    pub fn new(directory: impl Into<String>) -> Self {
+        Self {
+            directory: directory.into(),
+            ..Default::default()
+        }
+    }
+}
+
+// How context is built and how many passes run; the defaults match the MCP
+// tools called without options.
+#[derive(Clone, Default)]
+pub struct RunOptions {
+    // Ignored by `explain_code` and `generate_patch`, which have fixed formats.
+    pub format: OutputFormat,
+    pub use_retrieval: bool,
+    // None uses the server's FILE_SELECTION_TOOLS setting.
+    pub file_selection: Option<bool>,
+    // Send declarations plus the files defining the prompt's symbols instead
+    // of the full report.
+    pub skeleton: bool,
+    // None uses the server's MIRROR_DIRECTORIES setting.
+    pub mirror: Option<bool>,
+    // None uses the default two passes.
+    pub passes: Option<u8>,
+    // None generates a single analysis.
+    pub candidates: Option<u8>,
+    // Append a corrections section from a verification pass; markdown only.
+    pub verify: bool,
+    pub compact_detail: bool,
+    // Detail of the final answer, from a summary to complete code; not
+    // available for diffs.
+    pub verbosity: Verbosity,
+    // Temperature, top_p, max_tokens, and stop sequences; unset fields use
+    // the server's per-tool and global settings.
+    pub generation: GenerationParams,
+    // Redact string and numeric literals from the context; refused for
+    // `generate_patch`, whose diff must match the original files.
+    pub redact_literals: bool,
+    // Per-call token budget; the context is shrunk to fit it. None uses the
+    // server's MAX_TOTAL_TOKENS_PER_CALL setting.
+    pub max_total_tokens: Option<u64>,
+    // Context size limit in tokens, up to the server's MAX_TOKEN_CHAR_LIMIT.
+    // None uses TOKEN_CHAR_LIMIT.
+    pub token_limit: Option<usize>,
+    // Check plans' dependencies and library APIs against the registries and
+    // locked versions; ignored by `explain_code` and `generate_patch`.
+    pub verify_feasibility: bool,
+    // Return the prompts each pass would send as the result's text instead
+    // of calling Gemini; no session or plan is created.
+    pub dry_run: bool,
+    // Post the result to a GitHub, GitLab, or Bitbucket issue or pull
+    // request; needs that forge's token.
+    pub publish: Option<PublishTarget>,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct PlanResult {
+    // Markdown plan, JSON plan, or validated diff with its check report.
+    pub text: String,
+    pub session_id: String,
+    // ID in the plan store, if it could be saved.
+    pub plan_id: Option<String>,
+    // Parsed plan, for `OutputFormat::Json`.
+    pub plan: Option<StructuredPlan>,
+    // Change manifest of a markdown plan, if the model produced a parseable one.
+    pub manifest: Option<Vec<ManifestEntry>>,
+    pub assumptions: Vec<String>,
+    pub open_questions: Vec<String>,
+    pub references: Vec<Reference>,
+    // API usage and estimated cost of the call.
+    pub usage: TokenUsage,
+    // With `RunOptions::verify_feasibility`, for markdown and JSON plans.
+    pub feasibility: Option<FeasibilityReport>,
+    // Whether each file the plan mentions exists, is created by it, or is
+    // missing; for markdown and JSON plans.
+    pub paths: Option<Vec<PathCheck>>,
+    // URL the result was published to, with `RunOptions::publish`.
+    pub published: Option<String>,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct Answer {
+    pub text: String,
+    pub session_id: String,
+    pub references: Vec<Reference>,
+    pub usage: TokenUsage,
+    pub published: Option<String>,
+}
+
+#[derive(Clone)]
+pub struct Agent {
+    server: CodeAgentServer,
+}
+
+impl Agent {
+    pub fn new(config: Config) -> Self {
+        Self {
+            server: CodeAgentServer::new(config),
+        }
+    }
+
+    pub async fn plan_feature(&self, scope: &Scope, prompt: &str, options: RunOptions) -> Result<PlanResult, AgentError> {
+        self.plan("plan_feature", scope, prompt, options).await
+    }
+
+    pub async fn plan_bug_fix(&self, scope: &Scope, bug_description: &str, options: RunOptions) -> Result<PlanResult, AgentError> {
+        self.plan("plan_bug_fix", scope, bug_description, options).await
+    }
+
+    pub async fn explain_code(&self, scope: &Scope, query: &str, options: RunOptions) -> Result<Answer, AgentError> {
+        let output = self.run("explain_code", scope, query, options, OutputFormat::Markdown).await?;
+        Ok(answer(output))
+    }
+
+    // The answer's text is the diff followed by its hunk-by-hunk check report.
+    pub async fn generate_patch(&self, scope: &Scope, change_request: &str, options: RunOptions) -> Result<Answer, AgentError> {
+        let output = self.run("generate_patch", scope, change_request, options, OutputFormat::Diff).await?;
+        Ok(answer(output))
+    }
+
+    // Runs any pipeline in `pipeline::PIPELINES` by tool name, in
+    // `options.format`. `prompt` is sent as the request as-is, so tools that
+    // build it from structured input (issue lists, hotspot metrics) need it
+    // built by the caller.
+    pub async fn run_pipeline(&self, tool: &str, scope: &Scope, prompt: &str, options: RunOptions) -> Result<Answer, AgentError> {
+        let format = options.format;
+        let output = self.run(tool, scope, prompt, options, format).await?;
+        Ok(answer(output))
+    }
+
+    // Continues a session returned by any of the calls above.
+    pub async fn follow_up(&self, session_id: &str, question: &str) -> Result<String, AgentError> {
+        self.server
+            .continue_session(session_id, question.to_string())
+            .await
+            .map_err(AgentError::Pipeline)
+    }
+
+    // Checks codebase_viewer and every API key without a planning call.
+    pub async fn health(&self) -> HealthReport {
+        self.server.health().await
+    }
+
+    // As `health`, but reuses the last report while it is younger than
+    // HEALTH_CACHE_SECS, for frequent probes.
+    pub async fn cached_health(&self) -> HealthReport {
+        self.server.cached_health().await
+    }
+
+    async fn plan(&self, tool: &str, scope: &Scope, prompt: &str, options: RunOptions) -> Result<PlanResult, AgentError> {
+        let format = options.format;
+        let output = self.run(tool, scope, prompt, options, format).await?;
+        let references = output.references.unwrap_or_default();
+        Ok(match format {
+            OutputFormat::Json if !output.dry_run => {
+                let parsed = plan::parse_structured_plan(&output.text).map_err(AgentError::InvalidPlan)?;
+                PlanResult {
+                    assumptions: parsed.assumptions.clone(),
+                    open_questions: parsed.open_questions.clone(),
+                    plan: Some(parsed),
+                    manifest: None,
+                    text: output.text,
+                    session_id: output.session_id,
+                    plan_id: output.plan_id,
+                    references,
+                    usage: output.usage,
+                    feasibility: output.feasibility,
+                    paths: output.paths,
+                    published: output.published,
+                }
+            }
+            OutputFormat::Markdown if !output.dry_run => {
+                let clarifications = plan::parse_clarifications(&output.text);
+                PlanResult {
+                    manifest: plan::parse_manifest(&output.text),
+                    assumptions: clarifications.assumptions,
+                    open_questions: clarifications.open_questions,
+                    plan: None,
+                    text: output.text,
+                    session_id: output.session_id,
+                    plan_id: output.plan_id,
+                    references,
+                    usage: output.usage,
+                    feasibility: output.feasibility,
+                    paths: output.paths,
+                    published: output.published,
+                }
+            }
+            // Diffs and dry-run prompt previews are text only.
+            _ => PlanResult {
+                manifest: None,
+                assumptions: Vec::new(),
+                open_questions: Vec::new(),
+                plan: None,
+                text: output.text,
+                session_id: output.session_id,
+                plan_id: output.plan_id,
+                references,
+                usage: output.usage,
+                feasibility: None,
+                paths: None,
+                published: output.published,
+            },
+        })
+    }
+
+    async fn run(&self, tool: &str, scope: &Scope, prompt: &str, options: RunOptions, format: OutputFormat) -> Result<ToolOutput, AgentError> {
+        let context = ContextParams {
+            use_retrieval: Some(options.use_retrieval),
+            file_selection: options.file_selection,
+            skeleton: Some(options.skeleton),
+            commit: scope.commit.clone(),
+            directories: (!scope.directories.is_empty()).then(|| scope.directories.clone()),
+            mirror: options.mirror,
+            references: Some(true),
+            passes: options.passes,
+            candidates: options.candidates,
+            verify: Some(options.verify),
+            compact_detail: Some(options.compact_detail),
+            verbosity: Some(options.verbosity),
+            generation: Some(options.generation),
+            redact_literals: Some(options.redact_literals),
+            max_total_tokens: options.max_total_tokens,
+            token_limit: options.token_limit,
+            shrink_to_budget: None,
+            verify_feasibility: Some(options.verify_feasibility),
+            dry_run: Some(options.dry_run),
+            publish: options.publish,
+        };
+        self.server
+            .run_tool(tool, &scope.directory, prompt, &context, format)
+            .await
+            .map_err(AgentError::Pipeline)
+    }
+}
+
+fn answer(output: ToolOutput) -> Answer {
+    Answer {
+        text: output.text,
+        session_id: output.session_id,
+        references: output.references.unwrap_or_default(),
+        usage: output.usage,
+        published: output.published,
+    }
+}
@@ -0,0 +1,330 @@
+use crate::files;
+use crate::redact;
+use crate::plan::{ChangeType, FileChange, StructuredPlan};
+use anyhow::{Context, Result};
+use std::path::{Component, Path, PathBuf};
+
+pub struct PreparedChange {
+    pub path: PathBuf,
+    pub change_type: ChangeType,
+    pub old_content: Option<String>,
+    pub new_content: Option<String>,
+    pub diff: String,
+}
+
+pub struct PreparedPlan {
+    pub changes: Vec<PreparedChange>,
+    pub skipped: Vec<String>,
+}
+
+impl PreparedPlan {
+    pub fn diff(&self) -> String {
+        self.changes.iter().map(|c| c.diff.as_str()).collect()
+    }
+
+    // The token binds a write to the exact diff the caller reviewed in a dry run.
+    pub fn confirmation_token(&self) -> String {
+        files::content_hash(&self.diff())[..16].to_string()
+    }
+}
+
+pub async fn prepare_plan(root: &Path, plan: &StructuredPlan) -> Result<PreparedPlan> {
+    let mut changes = Vec::new();
+    let mut skipped = Vec::new();
+
+    for change in &plan.file_changes {
+        match prepare_change(root, change).await {
+            Ok(prepared) => changes.push(prepared),
+            Err(e) => skipped.push(format!("{}: {e:#}", change.path)),
+        }
+    }
+
+    Ok(PreparedPlan { changes, skipped })
+}
+
+// Files that are documentation as a whole, which a comment-only write may
+// create and edit freely.
+const DOCUMENTATION_EXTENSIONS: &[&str] = &["md", "markdown", "mdx", "rst", "adoc", "txt"];
+
+// `prepare_plan` for document_code's `write`, which changes comments only:
+// deleting a file, replacing a whole file, creating anything but a
+// documentation file, and edits to a source file's code are skipped.
+pub async fn prepare_documentation(root: &Path, plan: &StructuredPlan) -> Result<PreparedPlan> {
+    let mut changes = Vec::new();
+    let mut skipped = Vec::new();
+
+    for change in &plan.file_changes {
+        let prepared = match check_documentation_change(change) {
+            Ok(()) => prepare_change(root, change).await.and_then(|prepared| check_comments_only(&prepared).map(|()| prepared)),
+            Err(e) => Err(e),
+        };
+        match prepared {
+            Ok(prepared) => changes.push(prepared),
+            Err(e) => skipped.push(format!("{}: {e:#}", change.path)),
+        }
+    }
+
+    Ok(PreparedPlan { changes, skipped })
+}
+
+fn is_documentation_file(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| DOCUMENTATION_EXTENSIONS.contains(&e.to_ascii_lowercase().as_str()))
+}
+
+fn check_documentation_change(change: &FileChange) -> Result<()> {
+    match change.change_type {
+        ChangeType::Delete => Err(anyhow::anyhow!("documentation cannot delete files")),
+        ChangeType::Create if !is_documentation_file(Path::new(&change.path)) => {
+            Err(anyhow::anyhow!("documentation can only create Markdown, reStructuredText, AsciiDoc, or text files"))
+        }
+        ChangeType::Modify if change.content.is_some() => Err(anyhow::anyhow!("documentation must use exact edits, not replace the whole file")),
+        _ => Ok(()),
+    }
+}
+
+// A modified source file must have the same code once comments are removed.
+fn check_comments_only(change: &PreparedChange) -> Result<()> {
+    if change.change_type != ChangeType::Modify || is_documentation_file(&change.path) {
+        return Ok(());
+    }
+    let old = redact::code_tokens(&change.path, change.old_content.as_deref().unwrap_or(""));
+    let new = redact::code_tokens(&change.path, change.new_content.as_deref().unwrap_or(""));
+    match (old, new) {
+        (Some(old), Some(new)) if old == new => Ok(()),
+        (Some(_), Some(_)) => Err(anyhow::anyhow!("edits change code, not only comments")),
+        _ => Err(anyhow::anyhow!("comments cannot be told apart from code in this file type; apply manually")),
+    }
+}
+
+async fn prepare_change(root: &Path, change: &FileChange) -> Result<PreparedChange> {
+    let relative = safe_relative_path(&change.path)?;
+    let full_path = contained_path(root, &relative).await?;
+    let existing = tokio::fs::read_to_string(&full_path).await.ok();
+    let label = relative.display().to_string();
+
+    let new_content = match change.change_type {
+        ChangeType::Create => {
+            if existing.is_some() {
+                return Err(anyhow::anyhow!("file already exists"));
+            }
+            let content = change
+                .content
+                .clone()
+                .or_else(|| (!change.code_snippets.is_empty()).then(|| change.code_snippets.join("\n\n")))
+                .context("no content provided for new file")?;
+            Some(content)
+        }
+        ChangeType::Modify => {
+            let mut content = existing.clone().context("file does not exist")?;
+            if let Some(full) = &change.content {
+                content = full.clone();
+            } else if change.edits.is_empty() {
+                return Err(anyhow::anyhow!("no exact edits provided; apply manually"));
+            }
+            for edit in &change.edits {
+                match content.matches(edit.find.as_str()).count() {
+                    1 => content = content.replacen(&edit.find, &edit.replace, 1),
+                    0 => return Err(anyhow::anyhow!("edit target not found: {:?}", preview(&edit.find))),
+                    n => return Err(anyhow::anyhow!("edit target is ambiguous ({n} matches): {:?}", preview(&edit.find))),
+                }
+            }
+            Some(content)
+        }
+        ChangeType::Delete => {
+            existing.as_ref().context("file does not exist")?;
+            None
+        }
+    };
+
+    let old = existing.as_deref().unwrap_or("");
+    let new = new_content.as_deref().unwrap_or("");
+    let diff = similar::TextDiff::from_lines(old, new)
+        .unified_diff()
+        .header(&format!("a/{label}"), &format!("b/{label}"))
+        .to_string();
+
+    Ok(PreparedChange {
+        path: relative,
+        change_type: change.change_type,
+        old_content: existing,
+        new_content,
+        diff,
+    })
+}
+
+pub async fn write_plan(root: &Path, prepared: &PreparedPlan) -> Result<()> {
+    for change in &prepared.changes {
+        // Checked again: the tree may have changed since the dry run.
+        let full_path = contained_path(root, &change.path)
+            .await
+            .with_context(|| format!("Refusing to write {}", change.path.display()))?;
+        match &change.new_content {
+            Some(content) => {
+                if let Some(parent) = full_path.parent() {
+                    tokio::fs::create_dir_all(parent)
+                        .await
+                        .with_context(|| format!("Failed to create directory for {}", change.path.display()))?;
+                }
+                tokio::fs::write(&full_path, content)
+                    .await
+                    .with_context(|| format!("Failed to write {}", change.path.display()))?;
+            }
+            None => tokio::fs::remove_file(&full_path)
+                .await
+                .with_context(|| format!("Failed to delete {}", change.path.display()))?,
+        }
+        tracing::info!("Applied {:?} to {}", change.change_type, full_path.display());
+    }
+    Ok(())
+}
+
+pub fn safe_relative_path(path: &str) -> Result<PathBuf> {
+    let path = PathBuf::from(path.trim_start_matches("./"));
+    if path.components().any(|c| !matches!(c, Component::Normal(_))) {
+        return Err(anyhow::anyhow!("path must be relative to the codebase root without '..'"));
+    }
+    Ok(path)
+}
+
+// `relative` joined to the canonical `root`. `..` is already rejected, but a
+// symlinked directory inside the repository could still lead outside it, so
+// the nearest existing ancestor must resolve under the root, and the file
+// itself must not be a symlink.
+pub(crate) async fn contained_path(root: &Path, relative: &Path) -> Result<PathBuf> {
+    let root = tokio::fs::canonicalize(root)
+        .await
+        .with_context(|| format!("Failed to resolve {}", root.display()))?;
+    let full_path = root.join(relative);
+
+    let mut ancestor = full_path.parent();
+    while let Some(dir) = ancestor {
+        if let Ok(real) = tokio::fs::canonicalize(dir).await {
+            if !real.starts_with(&root) {
+                return Err(anyhow::anyhow!("path leads outside the codebase root through a symlink"));
+            }
+            break;
+        }
+        ancestor = dir.parent();
+    }
+    if tokio::fs::symlink_metadata(&full_path).await.is_ok_and(|m| m.file_type().is_symlink()) {
+        return Err(anyhow::anyhow!("path is a symlink"));
+    }
+    Ok(full_path)
+}
+
+fn preview(text: &str) -> String {
+    text.lines().next().unwrap_or_default().chars().take(80).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plan::TextEdit;
+    use crate::testing::TempRepo;
+
+    fn plan(change_type: ChangeType, path: &str, content: &str) -> StructuredPlan {
+        StructuredPlan {
+            summary: String::new(),
+            file_changes: vec![FileChange {
+                path: path.to_string(),
+                change_type,
+                description: String::new(),
+                code_snippets: Vec::new(),
+                content: Some(content.to_string()),
+                edits: Vec::new(),
+                language: None,
+            }],
+            steps: Vec::new(),
+            risks: Vec::new(),
+            assumptions: Vec::new(),
+            open_questions: Vec::new(),
+        }
+    }
+
+    fn edit(path: &str, find: &str, replace: &str) -> StructuredPlan {
+        let mut plan = plan(ChangeType::Modify, path, "");
+        plan.file_changes[0].content = None;
+        plan.file_changes[0].edits = vec![TextEdit { find: find.to_string(), replace: replace.to_string() }];
+        plan
+    }
+
+    async fn documentation_skipped(repo: &TempRepo, plan: &StructuredPlan) -> Vec<String> {
+        prepare_documentation(repo.path(), plan).await.unwrap().skipped
+    }
+
+    #[tokio::test]
+    async fn documentation_writes_comments_only() {
+        let repo = TempRepo::with_files(&[("src/lib.rs", "pub fn add(a: i32) -> i32 {\n    a + 1\n}\n"), ("README.md", "# App\n")]).unwrap();
+
+        let comment = edit("src/lib.rs", "pub fn add", "/// Adds one to `a`.\npub fn add");
+        assert!(documentation_skipped(&repo, &comment).await.is_empty());
+        assert!(documentation_skipped(&repo, &edit("README.md", "# App", "# App\n\nAdds numbers.")).await.is_empty());
+        assert!(documentation_skipped(&repo, &plan(ChangeType::Create, "README.draft.md", "# Draft")).await.is_empty());
+
+        let code = edit("src/lib.rs", "a + 1", "a + 2 // now two");
+        assert!(documentation_skipped(&repo, &code).await[0].contains("change code"));
+        assert!(documentation_skipped(&repo, &plan(ChangeType::Modify, "src/lib.rs", "// all new\n")).await[0].contains("whole file"));
+        assert!(documentation_skipped(&repo, &plan(ChangeType::Delete, "src/lib.rs", "")).await[0].contains("delete"));
+        assert!(documentation_skipped(&repo, &plan(ChangeType::Create, "src/new.rs", "// new\n")).await[0].contains("can only create"));
+    }
+
+    #[tokio::test]
+    async fn writes_inside_the_root() {
+        let repo = TempRepo::with_files(&[("src/lib.rs", "old")]).unwrap();
+        let prepared = prepare_plan(repo.path(), &plan(ChangeType::Create, "src/new/mod.rs", "new")).await.unwrap();
+        assert!(prepared.skipped.is_empty());
+        write_plan(repo.path(), &prepared).await.unwrap();
+        assert_eq!(std::fs::read_to_string(repo.path().join("src/new/mod.rs")).unwrap(), "new");
+    }
+
+    #[test]
+    fn rejects_paths_leaving_the_root() {
+        assert!(safe_relative_path("../outside.rs").is_err());
+        assert!(safe_relative_path("/etc/passwd").is_err());
+        assert!(safe_relative_path("src/../../outside.rs").is_err());
+        assert_eq!(safe_relative_path("./src/lib.rs").unwrap(), PathBuf::from("src/lib.rs"));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn skips_changes_through_symlinked_directories() {
+        let outside = TempRepo::with_files(&[("target.rs", "secret")]).unwrap();
+        let repo = TempRepo::new().unwrap();
+        std::os::unix::fs::symlink(outside.path(), repo.path().join("link")).unwrap();
+
+        for change_type in [ChangeType::Modify, ChangeType::Delete] {
+            let prepared = prepare_plan(repo.path(), &plan(change_type, "link/target.rs", "new")).await.unwrap();
+            assert!(prepared.changes.is_empty());
+            assert!(prepared.skipped[0].contains("through a symlink"), "{:?}", prepared.skipped);
+        }
+        let prepared = prepare_plan(repo.path(), &plan(ChangeType::Create, "link/nested/new.rs", "new")).await.unwrap();
+        assert!(prepared.changes.is_empty());
+        assert_eq!(std::fs::read_to_string(outside.path().join("target.rs")).unwrap(), "secret");
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn skips_symlinked_files() {
+        let outside = TempRepo::with_files(&[("target.rs", "secret")]).unwrap();
+        let repo = TempRepo::new().unwrap();
+        std::os::unix::fs::symlink(outside.path().join("target.rs"), repo.path().join("lib.rs")).unwrap();
+
+        let prepared = prepare_plan(repo.path(), &plan(ChangeType::Modify, "lib.rs", "new")).await.unwrap();
+        assert!(prepared.changes.is_empty());
+        assert!(prepared.skipped[0].contains("is a symlink"), "{:?}", prepared.skipped);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn refuses_writes_redirected_after_the_dry_run() {
+        let outside = TempRepo::new().unwrap();
+        let repo = TempRepo::new().unwrap();
+        let prepared = prepare_plan(repo.path(), &plan(ChangeType::Create, "generated/new.rs", "new")).await.unwrap();
+        std::os::unix::fs::symlink(outside.path(), repo.path().join("generated")).unwrap();
+
+        assert!(write_plan(repo.path(), &prepared).await.is_err());
+        assert!(!outside.path().join("new.rs").exists());
+    }
+}
@@ -0,0 +1,202 @@
+use anyhow::{Context, Result};
+use std::fs::File;
+use std::io::Read;
+use std::path::{Component, Path, PathBuf};
+
+const MAX_ENTRIES: usize = 100_000;
+
+pub fn is_archive(path: &Path) -> bool {
+    let name = path.file_name().and_then(|n| n.to_str()).unwrap_or_default().to_ascii_lowercase();
+    [".zip", ".tar.gz", ".tgz", ".tar"].iter().any(|ext| name.ends_with(ext))
+}
+
+pub fn extract(archive_path: &Path, dest: &Path, max_bytes: u64) -> Result<()> {
+    let name = archive_path.to_string_lossy().to_ascii_lowercase();
+    let file = File::open(archive_path)
+        .with_context(|| format!("Failed to open archive '{}'", archive_path.display()))?;
+
+    if name.ends_with(".zip") {
+        extract_zip(file, dest, max_bytes)
+    } else if name.ends_with(".tar") {
+        extract_tar(file, dest, max_bytes)
+    } else {
+        extract_tar(flate2::read::GzDecoder::new(file), dest, max_bytes)
+    }
+}
+
+fn extract_zip(file: File, dest: &Path, max_bytes: u64) -> Result<()> {
+    let mut archive = zip::ZipArchive::new(file).context("Failed to read zip archive")?;
+    if archive.len() > MAX_ENTRIES {
+        return Err(anyhow::anyhow!("Archive has too many entries ({} > {})", archive.len(), MAX_ENTRIES));
+    }
+
+    let mut budget = ExtractionBudget::new(max_bytes);
+    for i in 0..archive.len() {
+        let mut entry = archive.by_index(i).context("Failed to read zip entry")?;
+        // enclosed_name rejects absolute paths and '..' traversal (zip-slip).
+        let Some(relative) = entry.enclosed_name() else {
+            tracing::warn!("Skipping unsafe zip entry path: {}", entry.name());
+            continue;
+        };
+        if entry.is_dir() {
+            std::fs::create_dir_all(dest.join(&relative))?;
+            continue;
+        }
+        if entry.is_symlink() {
+            tracing::warn!("Skipping symlink in archive: {}", relative.display());
+            continue;
+        }
+        budget.write(&mut entry, &dest.join(&relative))?;
+    }
+    Ok(())
+}
+
+fn extract_tar<R: Read>(reader: R, dest: &Path, max_bytes: u64) -> Result<()> {
+    let mut archive = tar::Archive::new(reader);
+    let mut budget = ExtractionBudget::new(max_bytes);
+
+    for (count, entry) in archive.entries().context("Failed to read tar archive")?.enumerate() {
+        if count >= MAX_ENTRIES {
+            return Err(anyhow::anyhow!("Archive has too many entries (> {})", MAX_ENTRIES));
+        }
+        let mut entry = entry.context("Failed to read tar entry")?;
+        let relative = entry.path().context("Invalid tar entry path")?.into_owned();
+        let Some(relative) = enclosed(&relative) else {
+            tracing::warn!("Skipping unsafe tar entry path: {}", relative.display());
+            continue;
+        };
+
+        let entry_type = entry.header().entry_type();
+        if entry_type.is_dir() {
+            std::fs::create_dir_all(dest.join(&relative))?;
+        } else if entry_type.is_file() {
+            budget.write(&mut entry, &dest.join(&relative))?;
+        } else {
+            tracing::debug!("Skipping non-regular tar entry: {}", relative.display());
+        }
+    }
+    Ok(())
+}
+
+fn enclosed(path: &Path) -> Option<PathBuf> {
+    let mut out = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(part) => out.push(part),
+            Component::CurDir => {}
+            _ => return None,
+        }
+    }
+    (!out.as_os_str().is_empty()).then_some(out)
+}
+
+// Tracks bytes actually written rather than trusting archive headers, which
+// protects against decompression bombs that under-report their size.
+struct ExtractionBudget {
+    remaining: u64,
+    max_bytes: u64,
+}
+
+impl ExtractionBudget {
+    fn new(max_bytes: u64) -> Self {
+        Self { remaining: max_bytes, max_bytes }
+    }
+
+    fn write(&mut self, reader: &mut impl Read, target: &Path) -> Result<()> {
+        if let Some(parent) = target.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let mut out = File::create(target)
+            .with_context(|| format!("Failed to create '{}'", target.display()))?;
+        let written = std::io::copy(&mut reader.take(self.remaining + 1), &mut out)?;
+        if written > self.remaining {
+            return Err(anyhow::anyhow!(
+                "Archive exceeds the extraction size limit of {} bytes",
+                self.max_bytes
+            ));
+        }
+        self.remaining -= written;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempRepo;
+    use std::io::Write;
+
+    // `dest` sits one level down, so an entry escaping it with `..` would
+    // land in the repo.
+    fn extract_into(repo: &TempRepo, archive: &str) -> PathBuf {
+        let dest = repo.path().join("dest");
+        std::fs::create_dir_all(&dest).unwrap();
+        extract(&repo.path().join(archive), &dest, 1 << 20).unwrap();
+        dest
+    }
+
+    #[test]
+    fn zip_skips_unsafe_paths_and_symlinks() {
+        let repo = TempRepo::new().unwrap();
+        let options = zip::write::SimpleFileOptions::default();
+        let mut zip = zip::ZipWriter::new(File::create(repo.path().join("code.zip")).unwrap());
+        for name in ["src/lib.rs", "../escaped.rs", "/absolute.rs"] {
+            zip.start_file(name, options).unwrap();
+            zip.write_all(b"fn main() {}").unwrap();
+        }
+        zip.add_symlink("link", "/etc/passwd", options).unwrap();
+        zip.finish().unwrap();
+
+        let dest = extract_into(&repo, "code.zip");
+        assert!(dest.join("src/lib.rs").is_file());
+        assert!(!repo.path().join("escaped.rs").exists());
+        assert!(!dest.join("absolute.rs").exists());
+        assert!(std::fs::symlink_metadata(dest.join("link")).is_err());
+    }
+
+    #[test]
+    fn tar_skips_unsafe_paths_and_symlinks() {
+        let repo = TempRepo::new().unwrap();
+        let mut tar = tar::Builder::new(File::create(repo.path().join("code.tar")).unwrap());
+        for name in ["src/lib.rs", "../escaped.rs"] {
+            let mut header = tar::Header::new_gnu();
+            // Written directly: `set_path` refuses `..`, as a hostile archive would not.
+            header.as_old_mut().name[..name.len()].copy_from_slice(name.as_bytes());
+            header.set_size(12);
+            header.set_mode(0o644);
+            header.set_cksum();
+            tar.append(&header, &b"fn main() {}"[..]).unwrap();
+        }
+        let mut link = tar::Header::new_gnu();
+        link.set_entry_type(tar::EntryType::Symlink);
+        link.set_size(0);
+        tar.append_link(&mut link, "link", "/etc/passwd").unwrap();
+        tar.finish().unwrap();
+        drop(tar);
+
+        let dest = extract_into(&repo, "code.tar");
+        assert!(dest.join("src/lib.rs").is_file());
+        assert!(!repo.path().join("escaped.rs").exists());
+        assert!(std::fs::symlink_metadata(dest.join("link")).is_err());
+    }
+
+    #[test]
+    fn enforces_the_size_limit() {
+        let repo = TempRepo::new().unwrap();
+        let mut zip = zip::ZipWriter::new(File::create(repo.path().join("big.zip")).unwrap());
+        zip.start_file("big.txt", zip::write::SimpleFileOptions::default()).unwrap();
+        zip.write_all(&[b'a'; 2048]).unwrap();
+        zip.finish().unwrap();
+
+        let error = extract(&repo.path().join("big.zip"), &repo.path().join("dest"), 1024).unwrap_err();
+        assert!(error.to_string().contains("size limit"), "{error}");
+    }
+
+    #[test]
+    fn enclosed_keeps_only_relative_paths() {
+        assert_eq!(enclosed(Path::new("./src/lib.rs")), Some(PathBuf::from("src/lib.rs")));
+        assert_eq!(enclosed(Path::new("src/../../x")), None);
+        assert_eq!(enclosed(Path::new("/etc/passwd")), None);
+        assert_eq!(enclosed(Path::new(".")), None);
+    }
+}
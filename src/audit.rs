@@ -0,0 +1,130 @@
+use crate::files;
+use crate::segments::Segments;
+use crate::usage;
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::io::AsyncWriteExt;
+
+tokio::task_local! {
+    static DIRECTORY: String;
+}
+
+// Runs `future` with the API calls it makes logged against `directory`.
+pub async fn in_directory<F: Future>(directory: &str, future: F) -> F::Output {
+    DIRECTORY.scope(directory.to_string(), future).await
+}
+
+// One request sent to a model provider. Only hashes and sizes are stored, so
+// the log records what left the machine without holding a copy of it.
+#[derive(Serialize)]
+pub struct AuditEntry {
+    pub timestamp: u64,
+    pub tool: Option<String>,
+    pub directory: Option<String>,
+    // "chat" or "embeddings".
+    pub endpoint: String,
+    // API base the request was sent to.
+    pub destination: String,
+    pub model: String,
+    // SHA-256 of the request's messages or embedding inputs.
+    pub prompt_sha256: String,
+    // Characters sent; the content itself is redacted.
+    pub context_chars: usize,
+    // None when the request failed.
+    pub response_sha256: Option<String>,
+    pub error: Option<String>,
+}
+
+// Opt-in JSONL log of every request sent to a model provider, for auditing
+// which code left the machine. The file is rotated to `.1`, `.2`, ... once it
+// would exceed `max_bytes`, keeping `keep` rotated files.
+pub struct AuditLog {
+    path: PathBuf,
+    max_bytes: u64,
+    keep: usize,
+    // Serializes appends and rotation.
+    write: tokio::sync::Mutex<()>,
+}
+
+impl AuditLog {
+    pub fn new(path: PathBuf, max_bytes: u64, keep: usize) -> Self {
+        Self {
+            path,
+            max_bytes,
+            keep,
+            write: tokio::sync::Mutex::new(()),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // Logs one request. `sent` is what was sent and `outcome` the response
+    // text or the error; a failure to write is logged, not returned, so
+    // auditing never fails a call.
+    pub async fn record(&self, endpoint: &str, destination: &str, model: &str, sent: &Segments, outcome: Result<&str, String>) {
+        let (response_sha256, error) = match outcome {
+            Ok(response) => (Some(files::content_hash(response)), None),
+            Err(e) => (None, Some(e)),
+        };
+        let entry = AuditEntry {
+            timestamp: SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0),
+            tool: usage::current_tool(),
+            directory: DIRECTORY.try_with(Clone::clone).ok(),
+            endpoint: endpoint.to_string(),
+            destination: destination.to_string(),
+            model: model.to_string(),
+            prompt_sha256: sent.content_hash(),
+            context_chars: sent.chars(),
+            response_sha256,
+            error,
+        };
+        if let Err(e) = self.append(&entry).await {
+            tracing::error!("Failed to write audit log '{}': {e:#}", self.path.display());
+        }
+    }
+
+    async fn append(&self, entry: &AuditEntry) -> Result<()> {
+        let mut line = serde_json::to_string(entry).context("Failed to serialize audit entry")?;
+        line.push('\n');
+
+        let _guard = self.write.lock().await;
+        if let Some(parent) = self.path.parent() {
+            tokio::fs::create_dir_all(parent)
+                .await
+                .context("Failed to create audit log directory")?;
+        }
+        let size = tokio::fs::metadata(&self.path).await.map(|m| m.len()).unwrap_or(0);
+        if size > 0 && size + line.len() as u64 > self.max_bytes {
+            self.rotate().await?;
+        }
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .await
+            .context("Failed to open audit log")?;
+        file.write_all(line.as_bytes())
+            .await
+            .context("Failed to write audit log")
+    }
+
+    // log -> log.1 -> log.2 ...; the oldest beyond `keep` is deleted.
+    async fn rotate(&self) -> Result<()> {
+        let rotated = |n: usize| PathBuf::from(format!("{}.{n}", self.path.display()));
+        if self.keep == 0 {
+            return tokio::fs::remove_file(&self.path).await.context("Failed to rotate audit log");
+        }
+        let _ = tokio::fs::remove_file(rotated(self.keep)).await;
+        for n in (1..self.keep).rev() {
+            let _ = tokio::fs::rename(rotated(n), rotated(n + 1)).await;
+        }
+        tokio::fs::rename(&self.path, rotated(1))
+            .await
+            .context("Failed to rotate audit log")
+    }
+}
@@ -0,0 +1,221 @@
+use crate::forge::{self, Forge, Issue, PublishMode, PublishTarget, MAX_COMMENTS};
+use anyhow::{bail, Context, Result};
+use futures::future::BoxFuture;
+use reqwest::Method;
+use serde::Deserialize;
+use serde_json::json;
+
+// Bitbucket documents no limit; bodies are kept to GitHub's so long results
+// are cut the same way on every forge.
+const MAX_BODY_CHARS: usize = 65_536;
+
+#[derive(Deserialize)]
+struct User {
+    nickname: Option<String>,
+    display_name: Option<String>,
+}
+
+impl User {
+    fn name(self) -> Option<String> {
+        self.nickname.or(self.display_name)
+    }
+}
+
+#[derive(Deserialize, Default)]
+struct Content {
+    raw: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Comment {
+    user: Option<User>,
+    #[serde(default)]
+    content: Content,
+}
+
+#[derive(Deserialize)]
+struct Page<T> {
+    values: Vec<T>,
+}
+
+#[derive(Deserialize)]
+struct IssueResponse {
+    title: String,
+    #[serde(default)]
+    content: Content,
+    state: String,
+    reporter: Option<User>,
+    kind: Option<String>,
+    priority: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Link {
+    href: String,
+}
+
+#[derive(Deserialize)]
+struct Links {
+    html: Option<Link>,
+}
+
+#[derive(Deserialize)]
+struct Published {
+    id: u64,
+    links: Option<Links>,
+}
+
+// Reads issues from, and comments on, Bitbucket Cloud through its REST API
+// (2.0). Bitbucket Data Center's API is different and not supported.
+pub struct Bitbucket {
+    http: reqwest::Client,
+    api_base: String,
+    token: Option<String>,
+    // With a username, the token is sent as an app password (basic auth)
+    // instead of as an access token.
+    username: Option<String>,
+}
+
+impl Bitbucket {
+    pub fn new(api_base: String, token: Option<String>, username: Option<String>) -> Self {
+        Self { http: forge::http_client(), api_base: api_base.trim_end_matches('/').to_string(), token, username }
+    }
+
+    pub fn serves(&self, host: &str) -> bool {
+        host == "bitbucket.org"
+    }
+
+    // `url` is https://bitbucket.org/<workspace>/<repo>/issues/<n>.
+    async fn fetch_issue(&self, url: &str) -> Result<Issue> {
+        let (repo, id) = match parse_url(url)? {
+            BitbucketUrl { repo, item: Some(("issues", id)) } => (repo, id),
+            _ => bail!("'{url}' is not an issue URL; expected https://bitbucket.org/<workspace>/<repo>/issues/<number>"),
+        };
+        let endpoint = format!("{}/repositories/{repo}/issues/{id}", self.api_base);
+
+        let issue: IssueResponse = self.send(Method::GET, &endpoint, None).await?;
+        let comments: Page<Comment> = self.send(Method::GET, &format!("{endpoint}/comments?pagelen={MAX_COMMENTS}"), None).await?;
+
+        Ok(Issue {
+            url: url.to_string(),
+            title: issue.title,
+            state: issue.state,
+            author: issue.reporter.and_then(User::name),
+            // Bitbucket issues have a kind and priority instead of labels.
+            labels: issue.kind.into_iter().chain(issue.priority).collect(),
+            body: issue.content.raw.unwrap_or_default(),
+            comments: comments
+                .values
+                .into_iter()
+                .filter_map(|c| {
+                    let body = c.content.raw.filter(|b| !b.trim().is_empty())?;
+                    Some((c.user.and_then(User::name).unwrap_or_else(|| "ghost".to_string()), body))
+                })
+                .collect(),
+        })
+    }
+
+    fn check(&self, target: &PublishTarget) -> Result<()> {
+        if self.token.is_none() {
+            bail!("publishing to Bitbucket needs BITBUCKET_TOKEN");
+        }
+        match (target.mode, parse_url(&target.url)?.item) {
+            (PublishMode::Comment, Some(_)) => Ok(()),
+            (PublishMode::Comment, None) => bail!("'comment' needs an issue or pull request URL, not '{}'", target.url),
+            _ => bail!("Bitbucket results can only be published as a 'comment'; 'description' and 'draft_pr' are GitHub only"),
+        }
+    }
+
+    // Returns the URL of the new comment.
+    async fn post(&self, target: &PublishTarget, body: &str) -> Result<String> {
+        self.check(target)?;
+        let BitbucketUrl { repo, item: Some((kind, id)) } = parse_url(&target.url)? else {
+            bail!("'{}' is not an issue or pull request URL", target.url);
+        };
+        let body = forge::truncate_body(body, MAX_BODY_CHARS, "Bitbucket");
+        let url = format!("{}/repositories/{repo}/{kind}/{id}/comments", self.api_base);
+        let published: Published = self.send(Method::POST, &url, Some(json!({ "content": { "raw": body } }))).await?;
+        Ok(match published.links.and_then(|l| l.html) {
+            Some(link) => link.href,
+            None => {
+                let page = target.url.split(['#', '?']).next().unwrap_or_default().trim_end_matches('/');
+                format!("{page}#comment-{}", published.id)
+            }
+        })
+    }
+
+    async fn send<T: serde::de::DeserializeOwned>(&self, method: Method, url: &str, body: Option<serde_json::Value>) -> Result<T> {
+        let mut request = self.http.request(method, url);
+        request = match (&self.username, &self.token) {
+            (Some(username), Some(token)) => request.basic_auth(username, Some(token)),
+            (None, Some(token)) => request.bearer_auth(token),
+            (_, None) => request,
+        };
+        if let Some(body) = &body {
+            request = request.json(body);
+        }
+        let response = request.send().await.with_context(|| format!("Failed to reach {url}"))?;
+        let status = response.status();
+        if status.is_success() {
+            return response.json().await.with_context(|| format!("Invalid response from {url}"));
+        }
+
+        // Bitbucket explains refusals in `error.message`.
+        let detail = response.json::<serde_json::Value>().await.ok();
+        let message = detail.as_ref().and_then(|d| d["error"]["message"].as_str()).unwrap_or("no details");
+        match (status, &self.token) {
+            (reqwest::StatusCode::NOT_FOUND, Some(_)) => {
+                bail!("{url} was not found, BITBUCKET_TOKEN cannot access it, or the repository has no issue tracker")
+            }
+            (reqwest::StatusCode::NOT_FOUND, None) => {
+                bail!("{url} was not found; set BITBUCKET_TOKEN to read private repositories")
+            }
+            _ => bail!("Bitbucket refused {url} ({status}: {message})"),
+        }
+    }
+}
+
+impl Forge for Bitbucket {
+    fn name(&self) -> &'static str {
+        "Bitbucket"
+    }
+
+    fn issue<'a>(&'a self, url: &'a str) -> BoxFuture<'a, Result<Issue>> {
+        Box::pin(self.fetch_issue(url))
+    }
+
+    fn check_target(&self, target: &PublishTarget) -> Result<()> {
+        self.check(target)
+    }
+
+    fn publish<'a>(&'a self, target: &'a PublishTarget, _title: &'a str, body: &'a str) -> BoxFuture<'a, Result<String>> {
+        Box::pin(self.post(target, body))
+    }
+}
+
+// A repository, issue, or pull request URL.
+struct BitbucketUrl {
+    // `<workspace>/<repo>`.
+    repo: String,
+    // ("issues" or "pullrequests", id); None for the repository itself.
+    item: Option<(&'static str, u64)>,
+}
+
+fn parse_url(url: &str) -> Result<BitbucketUrl> {
+    let invalid = || format!("'{url}' is not a Bitbucket repository, issue, or pull request URL");
+    let parts = forge::path_segments(url).with_context(invalid)?;
+    let repo = match parts.as_slice() {
+        [workspace, repo, ..] => format!("{workspace}/{}", repo.trim_end_matches(".git")),
+        _ => bail!(invalid()),
+    };
+    // Issue URLs may end in a title slug: /issues/12/crash-on-start.
+    let item = match parts[2..] {
+        [] => None,
+        [kind @ ("issues" | "pull-requests"), number, ..] => {
+            let number = number.parse().with_context(|| format!("'{number}' in '{url}' is not an issue or pull request number"))?;
+            Some((if kind == "issues" { "issues" } else { "pullrequests" }, number))
+        }
+        _ => bail!(invalid()),
+    };
+    Ok(BitbucketUrl { repo, item })
+}
@@ -0,0 +1,113 @@
+use crate::embeddings::cosine_similarity;
+use crate::llm::{GeminiClient, LlmError};
+use crate::segments::Segments;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+const MAX_ENTRIES: usize = 500;
+
+#[derive(Serialize, Deserialize, Clone)]
+struct CacheEntry {
+    tool: String,
+    report_hash: String,
+    prompt: String,
+    vector: Vec<f32>,
+    response: String,
+}
+
+pub struct SemanticCache {
+    path: PathBuf,
+    model: String,
+    threshold: f32,
+    entries: Mutex<Vec<CacheEntry>>,
+}
+
+pub struct CacheKey {
+    tool: String,
+    report_hash: String,
+    prompt: String,
+    vector: Vec<f32>,
+}
+
+impl SemanticCache {
+    pub fn load(path: PathBuf, model: String, threshold: f32) -> Self {
+        let entries = std::fs::read(&path)
+            .ok()
+            .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+            .unwrap_or_default();
+
+        Self {
+            path,
+            model,
+            threshold,
+            entries: Mutex::new(entries),
+        }
+    }
+
+    pub async fn key(&self, client: &GeminiClient, tool: &str, context: &Segments, prompt: &str) -> Result<CacheKey, LlmError> {
+        let vector = client
+            .embed(&self.model, vec![prompt.to_string()])
+            .await?
+            .pop()
+            .ok_or(LlmError::NoContent)?;
+
+        Ok(CacheKey {
+            tool: tool.to_string(),
+            report_hash: context.content_hash(),
+            prompt: prompt.to_string(),
+            vector,
+        })
+    }
+
+    // Answers are only reused for the exact same codebase context; the embedding
+    // comparison decides whether the question is close enough.
+    pub fn lookup(&self, key: &CacheKey) -> Option<String> {
+        let entries = self.entries.lock().unwrap();
+        let best = entries
+            .iter()
+            .filter(|e| e.tool == key.tool && e.report_hash == key.report_hash)
+            .map(|e| (cosine_similarity(&e.vector, &key.vector), e))
+            .max_by(|a, b| a.0.total_cmp(&b.0))?;
+
+        if best.0 < self.threshold {
+            return None;
+        }
+
+        tracing::info!(
+            "Semantic cache hit for '{}' (similarity {:.3} to \"{}\")",
+            key.tool,
+            best.0,
+            best.1.prompt
+        );
+        Some(best.1.response.clone())
+    }
+
+    pub async fn insert(&self, key: CacheKey, response: &str) -> Result<()> {
+        let snapshot = {
+            let mut entries = self.entries.lock().unwrap();
+            entries.push(CacheEntry {
+                tool: key.tool,
+                report_hash: key.report_hash,
+                prompt: key.prompt,
+                vector: key.vector,
+                response: response.to_string(),
+            });
+            if entries.len() > MAX_ENTRIES {
+                let excess = entries.len() - MAX_ENTRIES;
+                entries.drain(..excess);
+            }
+            serde_json::to_vec(&*entries).context("Failed to serialize semantic cache")?
+        };
+
+        if let Some(parent) = self.path.parent() {
+            tokio::fs::create_dir_all(parent)
+                .await
+                .context("Failed to create semantic cache directory")?;
+        }
+        tokio::fs::write(&self.path, snapshot)
+            .await
+            .context("Failed to write semantic cache")
+    }
+}
@@ -0,0 +1,198 @@
+use crate::llm::PipelinePrompts;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::io::AsyncWriteExt;
+
+// Stored outputs are capped so the log stays readable.
+const MAX_STORED_CHARS: usize = 20_000;
+
+// Candidate prompt text for one tool; omitted fields keep the built-in prompt.
+#[derive(Deserialize)]
+struct PromptOverride {
+    analysis_system: Option<String>,
+    detail_system: Option<String>,
+    detail_instruction: Option<String>,
+}
+
+struct Candidate {
+    analysis_system: Option<&'static str>,
+    detail_system: Option<&'static str>,
+    detail_instruction: Option<&'static str>,
+}
+
+// Runs a sample of requests through candidate prompt templates alongside the
+// built-in ones. Callers always get the built-in result; both outputs are
+// logged so a template change can be judged before it replaces the default.
+pub struct PromptCanary {
+    fraction: f64,
+    candidates: HashMap<String, Candidate>,
+    log_path: PathBuf,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Comparison {
+    pub timestamp: u64,
+    pub tool: String,
+    pub prompt: String,
+    pub baseline_chars: usize,
+    pub canary_chars: usize,
+    // Word-level similarity of the two outputs, 0.0 to 1.0.
+    pub similarity: f32,
+    pub canary_ms: u128,
+    pub canary_error: Option<String>,
+    pub baseline_output: String,
+    pub canary_output: String,
+}
+
+impl PromptCanary {
+    pub fn load(templates: &Path, fraction: f64, log_path: PathBuf) -> Result<Self> {
+        let raw = std::fs::read_to_string(templates)
+            .with_context(|| format!("Failed to read prompt canary file '{}'", templates.display()))?;
+        let overrides: HashMap<String, PromptOverride> = serde_json::from_str(&raw)
+            .with_context(|| format!("Invalid prompt canary file '{}'", templates.display()))?;
+
+        // Loaded once at startup, so leaking gives the &'static str that
+        // PipelinePrompts uses without copying the templates per request.
+        let leak = |s: Option<String>| s.map(|s| &*Box::leak(s.into_boxed_str()));
+        let candidates = overrides
+            .into_iter()
+            .map(|(tool, o)| {
+                let candidate = Candidate {
+                    analysis_system: leak(o.analysis_system),
+                    detail_system: leak(o.detail_system),
+                    detail_instruction: leak(o.detail_instruction),
+                };
+                (tool, candidate)
+            })
+            .collect();
+
+        Ok(Self {
+            fraction: fraction.clamp(0.0, 1.0),
+            candidates,
+            log_path,
+        })
+    }
+
+    pub fn tools(&self) -> Vec<&str> {
+        self.candidates.keys().map(String::as_str).collect()
+    }
+
+    // The candidate prompts for `tool` if this request was sampled.
+    pub fn sample(&self, tool: &str, baseline: &PipelinePrompts) -> Option<PipelinePrompts> {
+        let candidate = self.candidates.get(tool)?;
+        let roll = (uuid::Uuid::new_v4().as_u128() % 10_000) as f64 / 10_000.0;
+        if roll >= self.fraction {
+            return None;
+        }
+
+        Some(PipelinePrompts {
+            name: baseline.name,
+            analysis_system: candidate.analysis_system.unwrap_or(baseline.analysis_system),
+            request_label: baseline.request_label,
+            analysis_label: baseline.analysis_label,
+            detail_system: candidate.detail_system.unwrap_or(baseline.detail_system),
+            detail_instruction: candidate.detail_instruction.unwrap_or(baseline.detail_instruction),
+            manifest: baseline.manifest,
+        })
+    }
+
+    pub async fn record(
+        &self,
+        tool: &str,
+        prompt: &str,
+        baseline: &str,
+        canary: Result<String, String>,
+        canary_ms: u128,
+    ) -> Result<()> {
+        let (canary_output, canary_error) = match canary {
+            Ok(output) => (output, None),
+            Err(e) => (String::new(), Some(e)),
+        };
+        let similarity = if canary_error.is_some() {
+            0.0
+        } else {
+            similar::TextDiff::from_words(baseline, canary_output.as_str()).ratio()
+        };
+
+        let comparison = Comparison {
+            timestamp: SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0),
+            tool: tool.to_string(),
+            prompt: truncate(prompt),
+            baseline_chars: baseline.len(),
+            canary_chars: canary_output.len(),
+            similarity,
+            canary_ms,
+            canary_error,
+            baseline_output: truncate(baseline),
+            canary_output: truncate(&canary_output),
+        };
+
+        let mut line = serde_json::to_string(&comparison).context("Failed to serialize canary comparison")?;
+        line.push('\n');
+        if let Some(parent) = self.log_path.parent() {
+            tokio::fs::create_dir_all(parent)
+                .await
+                .context("Failed to create prompt canary log directory")?;
+        }
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.log_path)
+            .await
+            .context("Failed to open prompt canary log")?;
+        file.write_all(line.as_bytes())
+            .await
+            .context("Failed to write prompt canary log")
+    }
+
+    // Per-tool summary of the recorded comparisons as a markdown table.
+    pub async fn report(&self) -> Result<String> {
+        let log = match tokio::fs::read_to_string(&self.log_path).await {
+            Ok(log) => log,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e).context("Failed to read prompt canary log"),
+        };
+
+        let mut by_tool: BTreeMap<String, Vec<Comparison>> = BTreeMap::new();
+        for comparison in log.lines().filter_map(|l| serde_json::from_str::<Comparison>(l).ok()) {
+            by_tool.entry(comparison.tool.clone()).or_default().push(comparison);
+        }
+        if by_tool.is_empty() {
+            return Ok(format!("No canary comparisons recorded yet in '{}'.", self.log_path.display()));
+        }
+
+        let mut out = String::from("| Tool | Comparisons | Canary errors | Mean similarity | Mean length ratio (canary/baseline) | Mean canary latency |\n|---|---|---|---|---|---|\n");
+        for (tool, comparisons) in &by_tool {
+            let succeeded: Vec<_> = comparisons.iter().filter(|c| c.canary_error.is_none()).collect();
+            let count = succeeded.len().max(1) as f64;
+            let similarity = succeeded.iter().map(|c| c.similarity as f64).sum::<f64>() / count;
+            let ratio = succeeded
+                .iter()
+                .map(|c| c.canary_chars as f64 / c.baseline_chars.max(1) as f64)
+                .sum::<f64>()
+                / count;
+            let latency = succeeded.iter().map(|c| c.canary_ms as f64).sum::<f64>() / count / 1000.0;
+            out.push_str(&format!(
+                "| {} | {} | {} | {:.2} | {:.2} | {:.1}s |\n",
+                tool,
+                comparisons.len(),
+                comparisons.len() - succeeded.len(),
+                similarity,
+                ratio,
+                latency
+            ));
+        }
+        out.push_str(&format!("\nFull outputs are in '{}'.", self.log_path.display()));
+        Ok(out)
+    }
+}
+
+fn truncate(text: &str) -> String {
+    match text.char_indices().nth(MAX_STORED_CHARS) {
+        Some((idx, _)) => format!("{}…", &text[..idx]),
+        None => text.to_string(),
+    }
+}
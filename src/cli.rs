@@ -0,0 +1,284 @@
+// Command-line frontend over the `agent` facade, for scripts and CI jobs that
+// do not speak MCP: each subcommand runs one pipeline and prints or saves its
+// result. Logs go to stderr so stdout carries only the result.
+use crate::agent::{Agent, RunOptions, Scope};
+use crate::git;
+use crate::forge::{PublishMode, PublishTarget};
+use crate::llm::GenerationParams;
+use crate::plan::{OutputFormat, Verbosity};
+use anyhow::{Context, Result};
+use clap::{Args, Subcommand, ValueEnum};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Plan a new feature.
+    PlanFeature {
+        #[command(flatten)]
+        run: RunArgs,
+        #[arg(long, value_enum, default_value_t = PlanFormat::Markdown)]
+        format: PlanFormat,
+    },
+    /// Plan a bug fix.
+    PlanBugFix {
+        #[command(flatten)]
+        run: RunArgs,
+        #[arg(long, value_enum, default_value_t = PlanFormat::Markdown)]
+        format: PlanFormat,
+    },
+    /// Answer a question about the code.
+    ExplainCode {
+        #[command(flatten)]
+        run: RunArgs,
+    },
+    /// Generate a unified diff, checked hunk by hunk against the files.
+    GeneratePatch {
+        #[command(flatten)]
+        run: RunArgs,
+    },
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum PlanFormat {
+    Markdown,
+    Json,
+}
+
+#[derive(Args, Debug)]
+pub struct RunArgs {
+    /// Codebase directory (relative paths are resolved), a .zip/.tar.gz/.tgz/.tar archive, or a git URL (`url#branch`).
+    #[arg(long = "dir")]
+    directory: String,
+    /// Additional directory analyzed together with --dir; repeatable.
+    #[arg(long = "also-dir")]
+    directories: Vec<String>,
+    /// Analyze --dir as of this commit (SHA, tag, or branch).
+    #[arg(long)]
+    commit: Option<String>,
+    /// The feature request, bug description, question, or change request; `-` reads it from stdin.
+    #[arg(long)]
+    prompt: String,
+    /// Write the result to this file instead of stdout.
+    #[arg(long, short)]
+    output: Option<PathBuf>,
+    #[arg(long)]
+    use_retrieval: bool,
+    /// Let the model pick the files it needs from the file tree first.
+    #[arg(long)]
+    file_selection: Option<bool>,
+    /// Send the declarations of every file plus the files defining symbols named in the prompt.
+    #[arg(long)]
+    skeleton: bool,
+    /// Copy the directory to local storage before analysis.
+    #[arg(long)]
+    mirror: Option<bool>,
+    /// 1, 2 (default), or 3 (adds a critic pass).
+    #[arg(long)]
+    passes: Option<u8>,
+    /// Independent analyses (1-5) judged and merged before the detail pass.
+    #[arg(long)]
+    candidates: Option<u8>,
+    /// Append a corrections section from a verification pass (markdown only).
+    #[arg(long)]
+    verify: bool,
+    #[arg(long)]
+    compact_detail: bool,
+    /// Detail of the final answer: a summary for reviewing the approach, or complete code for implementing it.
+    #[arg(long, value_enum, default_value_t = VerbosityArg::Standard)]
+    verbosity: VerbosityArg,
+    /// Sampling temperature, 0 to 2.
+    #[arg(long)]
+    temperature: Option<f32>,
+    /// Nucleus sampling probability mass, above 0 and at most 1.
+    #[arg(long)]
+    top_p: Option<f32>,
+    /// Maximum tokens in the response.
+    #[arg(long)]
+    max_output_tokens: Option<u64>,
+    /// Sequence at which the response stops; repeat for up to 4.
+    #[arg(long)]
+    stop: Vec<String>,
+    #[arg(long)]
+    redact_literals: bool,
+    /// Hard cap on the tokens the call may use across all passes.
+    #[arg(long)]
+    max_total_tokens: Option<u64>,
+    /// Context size limit in tokens for this call, instead of TOKEN_CHAR_LIMIT.
+    #[arg(long)]
+    token_limit: Option<usize>,
+    /// Check a plan's new dependencies and library APIs against the registries.
+    #[arg(long)]
+    verify_feasibility: bool,
+    /// Print the prompts each pass would send instead of calling the model.
+    #[arg(long)]
+    dry_run: bool,
+    /// Publish the result to this GitHub, GitLab, or Bitbucket issue, pull request, or merge request URL, or GitHub repository URL with --publish-as draft-pr.
+    #[arg(long)]
+    publish: Option<String>,
+    #[arg(long, value_enum, default_value_t = PublishAs::Comment, requires = "publish")]
+    publish_as: PublishAs,
+    /// Branch with the changes, for --publish-as draft-pr.
+    #[arg(long, requires = "publish")]
+    head: Option<String>,
+    /// Branch the draft pull request merges into; defaults to the repository's default branch.
+    #[arg(long, requires = "publish")]
+    base: Option<String>,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum PublishAs {
+    /// A comment on the issue or pull request.
+    Comment,
+    /// The pull request's description.
+    Description,
+    /// A new draft pull request from --head.
+    DraftPr,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum VerbosityArg {
+    /// Decisions, affected files, order of work, and risks as short bullets.
+    Summary,
+    Standard,
+    /// Complete code for every file and step.
+    Exhaustive,
+}
+
+impl RunArgs {
+    fn scope(&self) -> Scope {
+        Scope {
+            directory: absolute(&self.directory),
+            directories: self.directories.iter().map(|d| absolute(d)).collect(),
+            commit: self.commit.clone(),
+        }
+    }
+
+    fn options(&self, format: OutputFormat) -> RunOptions {
+        RunOptions {
+            format,
+            use_retrieval: self.use_retrieval,
+            file_selection: self.file_selection,
+            skeleton: self.skeleton,
+            mirror: self.mirror,
+            passes: self.passes,
+            candidates: self.candidates,
+            verify: self.verify,
+            compact_detail: self.compact_detail,
+            verbosity: self.verbosity.into(),
+            generation: GenerationParams {
+                temperature: self.temperature,
+                top_p: self.top_p,
+                max_tokens: self.max_output_tokens,
+                stop: (!self.stop.is_empty()).then(|| self.stop.clone()),
+            },
+            redact_literals: self.redact_literals,
+            max_total_tokens: self.max_total_tokens,
+            token_limit: self.token_limit,
+            verify_feasibility: self.verify_feasibility,
+            dry_run: self.dry_run,
+            publish: self.publish.as_ref().map(|url| PublishTarget {
+                url: url.clone(),
+                mode: self.publish_as.into(),
+                head: self.head.clone(),
+                base: self.base.clone(),
+                title: None,
+            }),
+        }
+    }
+
+    fn prompt(&self) -> Result<String> {
+        if self.prompt != "-" {
+            return Ok(self.prompt.clone());
+        }
+        let mut prompt = String::new();
+        std::io::stdin().read_to_string(&mut prompt).context("Failed to read the prompt from stdin")?;
+        Ok(prompt)
+    }
+}
+
+impl From<PlanFormat> for OutputFormat {
+    fn from(format: PlanFormat) -> Self {
+        match format {
+            PlanFormat::Markdown => OutputFormat::Markdown,
+            PlanFormat::Json => OutputFormat::Json,
+        }
+    }
+}
+
+impl From<VerbosityArg> for Verbosity {
+    fn from(verbosity: VerbosityArg) -> Self {
+        match verbosity {
+            VerbosityArg::Summary => Verbosity::Summary,
+            VerbosityArg::Standard => Verbosity::Standard,
+            VerbosityArg::Exhaustive => Verbosity::Exhaustive,
+        }
+    }
+}
+
+impl From<PublishAs> for PublishMode {
+    fn from(publish_as: PublishAs) -> Self {
+        match publish_as {
+            PublishAs::Comment => PublishMode::Comment,
+            PublishAs::Description => PublishMode::Description,
+            PublishAs::DraftPr => PublishMode::DraftPr,
+        }
+    }
+}
+
+// Runs `command` and writes its result to stdout or `--output`. Failures are
+// returned, so the process exits non-zero.
+pub async fn run(agent: &Agent, command: Command) -> Result<()> {
+    let (text, output) = match command {
+        Command::PlanFeature { run, format } => {
+            let prompt = run.prompt()?;
+            let result = agent.plan_feature(&run.scope(), &prompt, run.options(format.into())).await?;
+            log_plan_id(result.plan_id.as_deref());
+            (result.text, run.output)
+        }
+        Command::PlanBugFix { run, format } => {
+            let prompt = run.prompt()?;
+            let result = agent.plan_bug_fix(&run.scope(), &prompt, run.options(format.into())).await?;
+            log_plan_id(result.plan_id.as_deref());
+            (result.text, run.output)
+        }
+        Command::ExplainCode { run } => {
+            let prompt = run.prompt()?;
+            let answer = agent.explain_code(&run.scope(), &prompt, run.options(OutputFormat::Markdown)).await?;
+            (answer.text, run.output)
+        }
+        Command::GeneratePatch { run } => {
+            let prompt = run.prompt()?;
+            let answer = agent.generate_patch(&run.scope(), &prompt, run.options(OutputFormat::Diff)).await?;
+            (answer.text, run.output)
+        }
+    };
+
+    match output {
+        Some(path) => {
+            tokio::fs::write(&path, &text)
+                .await
+                .with_context(|| format!("Failed to write '{}'", path.display()))?;
+            tracing::info!("Wrote the result to {}", path.display());
+        }
+        None => println!("{text}"),
+    }
+    Ok(())
+}
+
+fn log_plan_id(plan_id: Option<&str>) {
+    if let Some(plan_id) = plan_id {
+        tracing::info!("Saved as plan {plan_id}");
+    }
+}
+
+// The tools take absolute paths; archives and git URLs are passed through.
+fn absolute(directory: &str) -> String {
+    if git::parse_remote(directory).is_some() {
+        return directory.to_string();
+    }
+    match Path::new(directory).canonicalize() {
+        Ok(path) => path.to_string_lossy().into_owned(),
+        Err(_) => directory.to_string(),
+    }
+}
@@ -0,0 +1,581 @@
+use crate::apply::safe_relative_path;
+use crate::files::{self, SourceFile};
+use crate::imports;
+use anyhow::{bail, Context, Result};
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+// Index files looked for at the codebase root, most detailed first: SCIP and
+// LSIF also record where each symbol is referenced, ctags only where it is
+// defined.
+const INDEX_FILES: &[(&str, IndexFormat)] = &[
+    ("index.scip", IndexFormat::Scip),
+    ("dump.lsif", IndexFormat::Lsif),
+    ("index.lsif", IndexFormat::Lsif),
+    ("tags", IndexFormat::Ctags),
+    (".tags", IndexFormat::Ctags),
+    (".git/tags", IndexFormat::Ctags),
+];
+
+// Shorter words in a request are not looked up.
+const MIN_IDENTIFIER_CHARS: usize = 3;
+// Symbols defined in more files than this (`new`, `run`, `init`) say little
+// about which file a request means.
+pub(crate) const MAX_DEFINING_FILES: usize = 20;
+// Symbols and files per symbol listed in a file selection hint.
+const MAX_HINT_SYMBOLS: usize = 30;
+const MAX_HINT_FILES: usize = 5;
+
+// SCIP's `SymbolRole.Definition` bit.
+const SCIP_DEFINITION_ROLE: u64 = 1;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum IndexFormat {
+    Ctags,
+    Lsif,
+    Scip,
+}
+
+impl IndexFormat {
+    fn name(self) -> &'static str {
+        match self {
+            IndexFormat::Ctags => "ctags",
+            IndexFormat::Lsif => "LSIF",
+            IndexFormat::Scip => "SCIP",
+        }
+    }
+}
+
+// Symbol names, lowercased, mapped to the files that define and reference
+// them. Paths are relative to the codebase root.
+pub struct SymbolIndex {
+    pub format: IndexFormat,
+    // The index file, relative to the codebase root.
+    pub file: PathBuf,
+    definitions: HashMap<String, Vec<PathBuf>>,
+    references: HashMap<String, Vec<PathBuf>>,
+}
+
+// A file that defines or references symbols named in a request.
+pub struct RankedFile {
+    pub path: PathBuf,
+    pub score: f32,
+    pub defines: Vec<String>,
+    pub references: Vec<String>,
+}
+
+impl SymbolIndex {
+    fn new(format: IndexFormat, file: &str) -> Self {
+        Self { format, file: PathBuf::from(file), definitions: HashMap::new(), references: HashMap::new() }
+    }
+
+    fn define(&mut self, name: &str, path: PathBuf) {
+        add(&mut self.definitions, name, path);
+    }
+
+    fn reference(&mut self, name: &str, path: PathBuf) {
+        add(&mut self.references, name, path);
+    }
+
+    pub fn symbols(&self) -> usize {
+        self.definitions.len()
+    }
+
+    fn describe(&self) -> String {
+        format!("{} index (`{}`)", self.format.name(), self.file.display())
+    }
+
+    // The identifiers in `text` that the index defines, in the order they
+    // appear, with their defining files.
+    fn lookup<'a>(&'a self, text: &'a str) -> Vec<(&'a str, &'a [PathBuf])> {
+        identifiers(text)
+            .into_iter()
+            .filter_map(|name| Some((name, self.definitions.get(&name.to_lowercase())?.as_slice())))
+            .filter(|(_, files)| files.len() <= MAX_DEFINING_FILES)
+            .collect()
+    }
+
+    // Files ranked by the symbols from `text` they define, then reference.
+    // A symbol weighs less the more files define it.
+    pub fn rank_files(&self, text: &str) -> Vec<RankedFile> {
+        let mut ranked: HashMap<&Path, RankedFile> = HashMap::new();
+        fn entry<'m, 'p>(ranked: &'m mut HashMap<&'p Path, RankedFile>, path: &'p Path) -> &'m mut RankedFile {
+            ranked.entry(path).or_insert_with(|| RankedFile { path: path.to_path_buf(), score: 0.0, defines: Vec::new(), references: Vec::new() })
+        }
+        for (name, defined_in) in self.lookup(text) {
+            let weight = 1.0 / defined_in.len() as f32;
+            for path in defined_in {
+                let file = entry(&mut ranked, path);
+                file.score += 2.0 * weight;
+                file.defines.push(name.to_string());
+            }
+            for path in self.references.get(&name.to_lowercase()).into_iter().flatten() {
+                if defined_in.contains(path) {
+                    continue;
+                }
+                let file = entry(&mut ranked, path);
+                file.score += weight;
+                file.references.push(name.to_string());
+            }
+        }
+        let mut ranked: Vec<RankedFile> = ranked.into_values().collect();
+        ranked.sort_by(|a, b| b.score.total_cmp(&a.score).then_with(|| a.path.cmp(&b.path)));
+        ranked
+    }
+
+    // Where the symbols named in `text` are defined, for the file selection
+    // pass. None if `text` names none.
+    pub fn render_hint(&self, text: &str) -> Option<String> {
+        let found = self.lookup(text);
+        if found.is_empty() {
+            return None;
+        }
+        let mut hint = format!("Symbols named in the request, from the repository's {}:\n", self.describe());
+        for (name, defined_in) in found.into_iter().take(MAX_HINT_SYMBOLS) {
+            hint.push_str(&format!("- `{name}`: defined in {}", list_paths(defined_in)));
+            let referenced: Vec<PathBuf> = self
+                .references
+                .get(&name.to_lowercase())
+                .into_iter()
+                .flatten()
+                .filter(|p| !defined_in.contains(p))
+                .cloned()
+                .collect();
+            if !referenced.is_empty() {
+                hint.push_str(&format!("; referenced in {}", list_paths(&referenced)));
+            }
+            hint.push('\n');
+        }
+        Some(hint)
+    }
+}
+
+fn add(map: &mut HashMap<String, Vec<PathBuf>>, name: &str, path: PathBuf) {
+    let paths = map.entry(name.to_lowercase()).or_default();
+    if !paths.contains(&path) {
+        paths.push(path);
+    }
+}
+
+fn list_paths(paths: &[PathBuf]) -> String {
+    let mut listed: Vec<String> = paths.iter().take(MAX_HINT_FILES).map(|p| format!("`{}`", p.display())).collect();
+    if paths.len() > MAX_HINT_FILES {
+        listed.push(format!("{} more", paths.len() - MAX_HINT_FILES));
+    }
+    listed.join(", ")
+}
+
+// Words in `text` that could be identifiers, first occurrence only.
+// `Foo::bar` and `foo.bar` yield both parts.
+pub(crate) fn identifiers(text: &str) -> Vec<&str> {
+    let mut seen = HashSet::new();
+    text.split(|c: char| !(c.is_alphanumeric() || c == '_'))
+        .filter(|w| w.chars().count() >= MIN_IDENTIFIER_CHARS && !w.starts_with(|c: char| c.is_ascii_digit()))
+        .filter(|w| seen.insert(w.to_lowercase()))
+        .collect()
+}
+
+// Parsed indexes by index file, reparsed when the file changes. Files that
+// failed to parse are remembered as None so they are not retried each call.
+#[derive(Default)]
+pub struct CodeIndexes {
+    loaded: Mutex<HashMap<PathBuf, LoadedIndex>>,
+}
+
+// The index file's modification time when parsed, and the parsed index.
+type LoadedIndex = (SystemTime, Option<Arc<SymbolIndex>>);
+
+impl CodeIndexes {
+    // The index at the first of `roots` that has a usable one.
+    pub async fn find(&self, roots: &[&Path]) -> Option<Arc<SymbolIndex>> {
+        for root in roots {
+            for (name, format) in INDEX_FILES {
+                let path = root.join(name);
+                let Ok(modified) = tokio::fs::metadata(&path).await.and_then(|m| m.modified()) else {
+                    continue;
+                };
+                let cached = self.loaded.lock().unwrap().get(&path).filter(|(at, _)| *at == modified).map(|(_, index)| index.clone());
+                let index = match cached {
+                    Some(index) => index,
+                    None => {
+                        let index = parse(root, &path, *format, name).await;
+                        self.loaded.lock().unwrap().insert(path, (modified, index.clone()));
+                        index
+                    }
+                };
+                if index.is_some() {
+                    return index;
+                }
+            }
+        }
+        None
+    }
+}
+
+async fn parse(root: &Path, path: &Path, format: IndexFormat, name: &'static str) -> Option<Arc<SymbolIndex>> {
+    let started = std::time::Instant::now();
+    let (root, file) = (root.to_path_buf(), path.to_path_buf());
+    match tokio::task::spawn_blocking(move || load(&root, &file, format, name)).await {
+        Ok(Ok(index)) => {
+            tracing::info!("Loaded {} with {} symbols in {:.1?}", index.describe(), index.symbols(), started.elapsed());
+            Some(Arc::new(index))
+        }
+        Ok(Err(e)) => {
+            tracing::warn!("Ignoring code index '{}': {e:#}", path.display());
+            None
+        }
+        Err(e) => {
+            tracing::warn!("Code index task for '{}' panicked: {e}", path.display());
+            None
+        }
+    }
+}
+
+fn load(root: &Path, path: &Path, format: IndexFormat, name: &str) -> Result<SymbolIndex> {
+    let data = std::fs::read(path).with_context(|| format!("Failed to read '{}'", path.display()))?;
+    let mut index = SymbolIndex::new(format, name);
+    match format {
+        IndexFormat::Ctags => parse_ctags(&mut index, &String::from_utf8_lossy(&data), root),
+        IndexFormat::Lsif => parse_lsif(&mut index, &String::from_utf8_lossy(&data), root)?,
+        IndexFormat::Scip => parse_scip(&mut index, &data)?,
+    }
+    if index.definitions.is_empty() {
+        bail!("no symbol definitions found");
+    }
+    Ok(index)
+}
+
+// An index's path for a file, relative to `root`; None for files outside it.
+fn relative(root: &Path, file: &str) -> Option<PathBuf> {
+    let path = Path::new(file);
+    let path = if path.is_absolute() { path.strip_prefix(root).ok()? } else { path };
+    safe_relative_path(&path.to_string_lossy()).ok()
+}
+
+// Universal/Exuberant ctags: `name<TAB>file<TAB>address...` per line, after
+// `!_TAG_` header lines. Paths are relative to where ctags ran, taken to be
+// the codebase root.
+fn parse_ctags(index: &mut SymbolIndex, data: &str, root: &Path) {
+    for line in data.lines().filter(|l| !l.starts_with("!_")) {
+        let mut fields = line.split('\t');
+        if let (Some(name), Some(file)) = (fields.next(), fields.next()) {
+            if let Some(path) = relative(root, file) {
+                index.define(name, path);
+            }
+        }
+    }
+}
+
+// LSIF element IDs may be numbers or strings; both are keyed by their JSON text.
+fn lsif_id(value: &Value) -> String {
+    value.to_string()
+}
+
+#[derive(Default)]
+struct LsifGraph {
+    project_root: Option<String>,
+    documents: HashMap<String, String>,
+    // (range, symbol name) for ranges tagged as definitions.
+    definitions: Vec<(String, String)>,
+    range_documents: HashMap<String, String>,
+    // Range to result set.
+    next: HashMap<String, String>,
+    // Range or result set to reference result.
+    reference_results: HashMap<String, String>,
+    // Result to the documents of its items.
+    items: HashMap<String, Vec<String>>,
+}
+
+impl LsifGraph {
+    fn visit(&mut self, element: &Value) {
+        let id = lsif_id(&element["id"]);
+        let out = lsif_id(&element["outV"]);
+        match (element["type"].as_str(), element["label"].as_str()) {
+            (Some("vertex"), Some("metaData")) => self.project_root = element["projectRoot"].as_str().map(str::to_string),
+            (Some("vertex"), Some("document")) => {
+                if let Some(uri) = element["uri"].as_str() {
+                    self.documents.insert(id, uri.to_string());
+                }
+            }
+            (Some("vertex"), Some("range")) => {
+                let tag = &element["tag"];
+                if let (Some("definition"), Some(name)) = (tag["type"].as_str(), tag["text"].as_str()) {
+                    self.definitions.push((id, name.to_string()));
+                }
+            }
+            (Some("edge"), Some("contains")) => {
+                for range in element["inVs"].as_array().into_iter().flatten() {
+                    self.range_documents.insert(lsif_id(range), out.clone());
+                }
+            }
+            (Some("edge"), Some("next")) => {
+                self.next.insert(out, lsif_id(&element["inV"]));
+            }
+            (Some("edge"), Some("textDocument/references")) => {
+                self.reference_results.insert(out, lsif_id(&element["inV"]));
+            }
+            (Some("edge"), Some("item")) => {
+                // `document` became `shard` in LSIF 0.6.
+                let document = element.get("document").or_else(|| element.get("shard"));
+                if let Some(document) = document {
+                    self.items.entry(out).or_default().push(lsif_id(document));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn path(&self, document: &str, root: &Path) -> Option<PathBuf> {
+        let uri = self.documents.get(document)?;
+        let project_root = self.project_root.as_deref().map(|r| r.trim_end_matches('/'));
+        match project_root.and_then(|r| uri.strip_prefix(r)) {
+            Some(rest) => relative(root, &percent_decode(rest.trim_start_matches('/'))),
+            None => relative(root, &percent_decode(uri.strip_prefix("file://").unwrap_or(uri))),
+        }
+    }
+}
+
+// LSIF as JSON lines or, from older emitters, one JSON array. Only ranges
+// tagged as definitions name a symbol.
+fn parse_lsif(index: &mut SymbolIndex, data: &str, root: &Path) -> Result<()> {
+    let mut graph = LsifGraph::default();
+    if data.trim_start().starts_with('[') {
+        let elements: Vec<Value> = serde_json::from_str(data).context("Invalid LSIF JSON")?;
+        elements.iter().for_each(|e| graph.visit(e));
+    } else {
+        for (number, line) in data.lines().enumerate().filter(|(_, l)| !l.trim().is_empty()) {
+            let element: Value = serde_json::from_str(line).with_context(|| format!("Invalid LSIF element on line {}", number + 1))?;
+            graph.visit(&element);
+        }
+    }
+
+    for (range, name) in &graph.definitions {
+        let Some(path) = graph.range_documents.get(range).and_then(|d| graph.path(d, root)) else {
+            continue;
+        };
+        index.define(name, path);
+        let result_set = graph.next.get(range).unwrap_or(range);
+        let references = graph.reference_results.get(result_set).or_else(|| graph.reference_results.get(range));
+        for document in references.and_then(|r| graph.items.get(r)).into_iter().flatten() {
+            if let Some(path) = graph.path(document, root) {
+                index.reference(name, path);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = (bytes[i] == b'%').then(|| text.get(i + 1..i + 3)).flatten().and_then(|h| u8::from_str_radix(h, 16).ok());
+        match hex {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+// SCIP's protobuf `Index`: documents (field 2), each with a relative_path (1)
+// and occurrences (2) of a symbol (2) with symbol_roles (3).
+fn parse_scip(index: &mut SymbolIndex, data: &[u8]) -> Result<()> {
+    for field in Fields(data) {
+        let (2, Field::Bytes(document)) = field? else {
+            continue;
+        };
+        let mut path = None;
+        let mut occurrences = Vec::new();
+        for field in Fields(document) {
+            match field? {
+                (1, Field::Bytes(text)) => path = safe_relative_path(&String::from_utf8_lossy(text)).ok(),
+                (2, Field::Bytes(occurrence)) => occurrences.push(occurrence),
+                _ => {}
+            }
+        }
+        let Some(path) = path else {
+            continue;
+        };
+        for occurrence in occurrences {
+            let mut symbol = None;
+            let mut roles = 0;
+            for field in Fields(occurrence) {
+                match field? {
+                    (2, Field::Bytes(text)) => symbol = std::str::from_utf8(text).ok(),
+                    (3, Field::Varint(value)) => roles = value,
+                    _ => {}
+                }
+            }
+            let Some(name) = symbol.and_then(scip_name) else {
+                continue;
+            };
+            if roles & SCIP_DEFINITION_ROLE != 0 {
+                index.define(name, path.clone());
+            } else {
+                index.reference(name, path.clone());
+            }
+        }
+    }
+    Ok(())
+}
+
+// The last identifier of a global SCIP symbol (`<scheme> <manager> <package>
+// <version> <descriptors>`), e.g. `retry` for `... src/net/Client#retry().`.
+// Local symbols are only visible within one file and are skipped.
+fn scip_name(symbol: &str) -> Option<&str> {
+    if symbol.starts_with("local ") {
+        return None;
+    }
+    let descriptors = symbol.splitn(5, ' ').nth(4)?;
+    descriptors
+        .split(|c: char| "/#.:!()[]`".contains(c))
+        .rfind(|part| part.starts_with(|c: char| c.is_alphabetic() || c == '_'))
+}
+
+enum Field<'a> {
+    Varint(u64),
+    Bytes(&'a [u8]),
+    Fixed,
+}
+
+// The fields of a protobuf message as (number, value).
+struct Fields<'a>(&'a [u8]);
+
+impl<'a> Fields<'a> {
+    fn varint(&mut self) -> Result<u64> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let Some((&byte, rest)) = self.0.split_first() else {
+                bail!("truncated SCIP index");
+            };
+            self.0 = rest;
+            value |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        bail!("invalid varint in SCIP index")
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        if len > self.0.len() {
+            bail!("truncated SCIP index");
+        }
+        let (taken, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(taken)
+    }
+
+    fn field(&mut self) -> Result<(u64, Field<'a>)> {
+        let key = self.varint()?;
+        let value = match key & 7 {
+            0 => Field::Varint(self.varint()?),
+            1 => self.take(8).map(|_| Field::Fixed)?,
+            2 => {
+                let len = self.varint()? as usize;
+                Field::Bytes(self.take(len)?)
+            }
+            5 => self.take(4).map(|_| Field::Fixed)?,
+            wire => bail!("unsupported protobuf wire type {wire} in SCIP index"),
+        };
+        Ok((key >> 3, value))
+    }
+}
+
+impl<'a> Iterator for Fields<'a> {
+    type Item = Result<(u64, Field<'a>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.0.is_empty() {
+            return None;
+        }
+        let field = self.field();
+        if field.is_err() {
+            self.0 = &[];
+        }
+        Some(field)
+    }
+}
+
+// Report of the `top_k` files that define or reference the symbols named in
+// `prompt`, read from `root` with the usual ignore rules, followed by the
+// files they import up to `import_depth` hops away. None if the request
+// names no indexed symbol that is in the codebase.
+pub async fn retrieve_context(index: &SymbolIndex, root: &Path, prompt: &str, top_k: usize, char_limit: usize, import_depth: usize) -> Result<Option<String>> {
+    let ranked = index.rank_files(prompt);
+    if ranked.is_empty() {
+        return Ok(None);
+    }
+
+    let walk_root = root.to_path_buf();
+    let paths = tokio::task::spawn_blocking(move || files::source_paths(&walk_root))
+        .await
+        .context("File listing task panicked")??;
+    let available: HashSet<PathBuf> = paths.iter().filter_map(|(p, _)| p.strip_prefix(root).ok().map(Path::to_path_buf)).collect();
+
+    let mut selected = Vec::new();
+    let mut source_files = Vec::new();
+    for file in ranked.into_iter().filter(|f| available.contains(&f.path)) {
+        // Binary files fail UTF-8 decoding and are not useful context anyway.
+        let Ok(content) = tokio::fs::read_to_string(root.join(&file.path)).await else {
+            continue;
+        };
+        source_files.push(SourceFile { path: file.path.clone(), content });
+        selected.push(file);
+        if selected.len() == top_k {
+            break;
+        }
+    }
+    if selected.is_empty() {
+        return Ok(None);
+    }
+
+    tracing::info!("Selected {} of {} files for '{}' from its {}", selected.len(), available.len(), root.display(), index.describe());
+    let mut context = format!(
+        "The following {} files were selected from the repository's {} as defining or using the symbols named in the request, out of {} files in the codebase:\n",
+        selected.len(),
+        index.describe(),
+        available.len()
+    );
+    for file in &selected {
+        let mut uses = Vec::new();
+        if !file.defines.is_empty() {
+            uses.push(format!("defines {}", file.defines.iter().map(|n| format!("`{n}`")).collect::<Vec<_>>().join(", ")));
+        }
+        if !file.references.is_empty() {
+            uses.push(format!("uses {}", file.references.iter().map(|n| format!("`{n}`")).collect::<Vec<_>>().join(", ")));
+        }
+        context.push_str(&format!("- `{}` ({})\n", file.path.display(), uses.join("; ")));
+    }
+    // Imported files are read from disk as they are found.
+    let (source_files, imported) = if import_depth == 0 {
+        (source_files, Vec::new())
+    } else {
+        let root = root.to_path_buf();
+        tokio::task::spawn_blocking(move || {
+            let targets: Vec<&SourceFile> = source_files.iter().collect();
+            let imported = imports::follow(&available, |path| std::fs::read_to_string(root.join(path)).ok(), &targets, import_depth);
+            (source_files, imported)
+        })
+        .await
+        .context("Import resolution task panicked")?
+    };
+    context.push_str(&imports::render_list(&imported));
+    context.push('\n');
+
+    let mut source_files: Vec<&SourceFile> = source_files.iter().collect();
+    source_files.extend(imported.iter().map(|entry| &entry.file));
+    context.push_str(&files::render_files(&source_files, char_limit.saturating_sub(context.len())));
+    Ok(Some(context))
+}
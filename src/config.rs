@@ -1,10 +1,522 @@
-use std::path::PathBuf;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use crate::llm::GeminiClient;
+use std::time::Duration;
+use anyhow::{bail, Context, Result};
+use crate::audit::AuditLog;
+use crate::cache::SemanticCache;
+use crate::canary::PromptCanary;
+use crate::code_index::CodeIndexes;
+use crate::embeddings::EmbeddingConfig;
+use crate::external::{ReportCheck, DEFAULT_MIN_REPORT_RATIO};
+use crate::failed::FailedQueue;
+use crate::fairness::FairShare;
+use crate::feasibility::Registries;
+use crate::bitbucket::Bitbucket;
+use crate::forge::Forges;
+use crate::gc::GcConfig;
+use crate::github::GitHub;
+use crate::gitlab::GitLab;
+use crate::health::HealthCache;
+use crate::jira::Jira;
+use crate::llm::{EmbeddingSidecar, Escalation, FallbackModel, GeminiClient, GenerationDefaults, GenerationParams};
+use crate::mock::MockLlm;
+use crate::plans::PlanStore;
+use crate::process::ProcessMetrics;
+use crate::retry::{self, RetryPolicy};
+use crate::session::SessionStore;
+use crate::templates::PromptTemplates;
+use crate::tree_hash::TreeHashes;
+use crate::usage::PriceTable;
+use crate::workspace::WorkspaceConfig;
 
 #[derive(Clone)]
 pub struct Config {
     pub codebase_viewer_path: Arc<PathBuf>,
+    pub viewer_timeout: Duration,
+    pub report_check: Arc<ReportCheck>,
+    pub process_metrics: Arc<ProcessMetrics>,
     pub gemini_client: Arc<GeminiClient>,
     pub token_char_limit: usize,
+    // The largest context a call may ask for with `token_limit`
+    // (MAX_TOKEN_CHAR_LIMIT), in characters.
+    pub max_token_char_limit: usize,
+    // Requests longer than this are condensed before the pipeline runs
+    // (`spec`); None (PROMPT_CHAR_LIMIT=0) sends them as written.
+    pub prompt_char_limit: Option<usize>,
+    // Default per-call token budget; None means unlimited.
+    pub max_total_tokens: Option<u64>,
+    // Server-wide and per-tool generation parameters, which a call's
+    // `generation` overrides field by field.
+    pub generation: Arc<GenerationDefaults>,
+    pub embedding: EmbeddingConfig,
+    // ctags/LSIF/SCIP indexes found in codebases, used for retrieval and
+    // file selection; None when USE_CODE_INDEXES=false.
+    pub code_indexes: Option<Arc<CodeIndexes>>,
+    pub file_selection_tools: HashSet<String>,
+    // Whether files the request names, or whose identifiers it mentions, are
+    // always sent (INCLUDE_MENTIONED_FILES).
+    pub include_mentioned_files: bool,
+    // Redact literals on every call (REDACT_LITERALS), whatever the caller asks.
+    pub redact_literals: bool,
+    // Whether tools may write into the codebases they analyze (ALLOW_WRITES):
+    // `apply_plan` with a confirmation token and `document_code` with `write`.
+    pub allow_writes: bool,
+    pub semantic_cache: Option<Arc<SemanticCache>>,
+    pub workspace: WorkspaceConfig,
+    pub gc: GcConfig,
+    pub sessions: Arc<SessionStore>,
+    pub failed_requests: Arc<FailedQueue>,
+    pub saved_plans: Arc<PlanStore>,
+    pub prompt_canary: Option<Arc<PromptCanary>>,
+    pub prompt_templates: Option<Arc<PromptTemplates>>,
+    pub registries: Arc<Registries>,
+    pub forges: Arc<Forges>,
+    pub jira: Arc<Jira>,
+    pub tree_hashes: Arc<TreeHashes>,
+    // How often `tree_hashes` rescans the directories in use; None disables
+    // the background refresh (TREE_HASH_REFRESH_SECS=0).
+    pub tree_hash_refresh: Option<Duration>,
+    pub health_cache: Arc<HealthCache>,
+    // How long `/healthz` serves the last report before checking again
+    // (HEALTH_CACHE_SECS).
+    pub health_cache_ttl: Duration,
+}
+
+impl Config {
+    // Builds the configuration from the environment variables documented in
+    // the README, as the server binary does. `codebase_viewer_path` takes
+    // precedence over CODEBASE_VIEWER_PATH.
+    pub fn from_env(codebase_viewer_path: Option<PathBuf>) -> Result<Self> {
+        Self::from_vars(codebase_viewer_path, |name| std::env::var(name).ok())
+    }
+
+    // Builds the configuration from `vars`, looked up by variable name,
+    // instead of the process environment, so several configurations can
+    // coexist in one process (see `testing::Harness`).
+    pub fn from_vars(codebase_viewer_path: Option<PathBuf>, vars: impl Fn(&str) -> Option<String>) -> Result<Self> {
+        let var = |name: &str| vars(name).ok_or(std::env::VarError::NotPresent);
+        let codebase_viewer_path = codebase_viewer_path
+            .or_else(|| var("CODEBASE_VIEWER_PATH").ok().map(PathBuf::from))
+            .context("CODEBASE_VIEWER_PATH must be set via --codebase-viewer-path flag or environment variable")?;
+
+        let mock = match var("LLM_PROVIDER").as_deref() {
+            Err(_) | Ok("gemini") => None,
+            Ok("mock") => {
+                let rules = var("MOCK_RESPONSES_FILE").ok().map(PathBuf::from);
+                tracing::warn!("LLM_PROVIDER=mock: responses are canned and nothing is sent to a model");
+                Some(MockLlm::load(rules.as_deref())?)
+            }
+            Ok(other) => bail!("Unknown LLM_PROVIDER '{other}'; expected 'gemini' or 'mock'"),
+        };
+
+        let api_keys = if let Ok(keys_str) = var("GEMINI_API_KEYS") {
+            keys_str
+                .split(',')
+                .map(|s| s.trim().to_string())
+                .filter(|s| !s.is_empty())
+                .collect::<Vec<String>>()
+        } else if let Ok(single_key) = var("GEMINI_API_KEY") {
+            vec![single_key]
+        } else if mock.is_some() {
+            // The mock provider needs no key; one placeholder keeps the key pool usable.
+            vec!["mock".to_string()]
+        } else {
+            bail!("Either GEMINI_API_KEY or GEMINI_API_KEYS environment variable must be set");
+        };
+
+        if api_keys.is_empty() {
+            bail!("No valid API keys found in environment variables");
+        }
+
+        tracing::info!("Initialized with {} API key(s) for rotation", api_keys.len());
+
+        let gemini_model = var("GEMINI_MODEL").ok();
+        let max_in_flight_per_key = var("MAX_IN_FLIGHT_PER_KEY")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .filter(|&n: &usize| n > 0);
+        if let Some(limit) = max_in_flight_per_key {
+            tracing::info!("Request fan-out enabled: up to {} in-flight request(s) per API key", limit);
+        }
+        let mut gemini_client = GeminiClient::new(api_keys, gemini_model, max_in_flight_per_key);
+        if let Some(mock) = mock {
+            gemini_client = gemini_client.with_mock(mock);
+        }
+
+        if let Ok(fast_model) = var("ESCALATION_FAST_MODEL") {
+            let threshold = var("ESCALATION_THRESHOLD")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(7);
+            tracing::info!("Model escalation enabled: '{}' first, escalating below {}/10", fast_model, threshold);
+            gemini_client = gemini_client.with_escalation(Escalation { fast_model, threshold });
+        }
+
+        let defaults = RetryPolicy::default();
+        let secs = |name: &str, default: Duration| {
+            var(name)
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .and_then(|s| Duration::try_from_secs_f64(s).ok())
+                .unwrap_or(default)
+        };
+        let retry = RetryPolicy {
+            max_attempts: var("RETRY_MAX_ATTEMPTS")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(defaults.max_attempts),
+            base_delay: secs("RETRY_BASE_DELAY_SECS", defaults.base_delay),
+            multiplier: var("RETRY_MULTIPLIER")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(defaults.multiplier),
+            jitter: var("RETRY_JITTER")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(defaults.jitter),
+            max_delay: secs("RETRY_MAX_DELAY_SECS", defaults.max_delay),
+            retry_on: match var("RETRY_ON") {
+                Ok(spec) => retry::parse_classes(&spec)?,
+                Err(_) => defaults.retry_on.clone(),
+            },
+        };
+        retry.validate()?;
+        gemini_client = gemini_client.with_retry_policy(retry);
+
+        if let Ok(chain) = var("MODEL_FALLBACKS") {
+            let api_key = var("FALLBACK_API_KEY").ok();
+            let fallbacks: Vec<FallbackModel> = chain
+                .split(',')
+                .map(str::trim)
+                .filter(|e| !e.is_empty())
+                .map(|entry| FallbackModel::parse(entry, api_key.as_deref()))
+                .collect();
+            if fallbacks.iter().any(|f| f.model.is_empty()) {
+                bail!("Invalid MODEL_FALLBACKS '{chain}'; expected comma-separated 'model' or 'model@api_base' entries");
+            }
+            let labels: Vec<String> = fallbacks.iter().map(FallbackModel::label).collect();
+            tracing::info!("Model fallback chain: {}", labels.join(" -> "));
+            gemini_client = gemini_client.with_fallbacks(fallbacks);
+        }
+
+        if let Ok(api_base) = var("EMBEDDING_API_BASE") {
+            tracing::info!("Embeddings served by sidecar at {}", api_base);
+            let api_key = var("EMBEDDING_API_KEY").ok();
+            gemini_client = gemini_client.with_embedding_sidecar(EmbeddingSidecar { api_base, api_key });
+        }
+
+        if let Ok(path) = var("AUDIT_LOG_FILE") {
+            let max_bytes = var("AUDIT_LOG_MAX_BYTES")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(10 * 1024 * 1024);
+            let keep = var("AUDIT_LOG_KEEP")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(5);
+            tracing::info!("Audit log of model traffic enabled at {}", path);
+            gemini_client = gemini_client.with_audit_log(AuditLog::new(PathBuf::from(path), max_bytes, keep));
+        }
+
+        if let Ok(spec) = var("MODEL_PRICES") {
+            gemini_client = gemini_client.with_prices(PriceTable::parse(&spec)?);
+        }
+
+        if let Some(quota) = var("SESSION_TOKEN_QUOTA").ok().and_then(|s| s.parse().ok()) {
+            let window = var("SESSION_QUOTA_WINDOW_SECS")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(3600);
+            let max_wait = var("SESSION_QUOTA_MAX_WAIT_SECS")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(60);
+            tracing::info!("Fair-share quotas enabled: {} tokens per session or client per {}s", quota, window);
+            gemini_client = gemini_client.with_fair_share(FairShare::new(quota, Duration::from_secs(window), Duration::from_secs(max_wait)));
+        }
+
+        let request_timeout = var("LLM_REQUEST_TIMEOUT_SECS")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(600);
+        // 0 disables the limit; a zero-length timeout would fail every request.
+        if request_timeout > 0 {
+            gemini_client = gemini_client.with_request_timeout(Duration::from_secs(request_timeout));
+        }
+        let gemini_client = Arc::new(gemini_client);
+
+        let viewer_timeout = var("VIEWER_TIMEOUT_SECS")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(300);
+        let report_check = ReportCheck {
+            header: var("VIEWER_REPORT_HEADER").ok().filter(|s| !s.is_empty()),
+            footer: var("VIEWER_REPORT_FOOTER").ok().filter(|s| !s.is_empty()),
+            min_size_ratio: var("VIEWER_REPORT_MIN_RATIO")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(DEFAULT_MIN_REPORT_RATIO),
+        };
+
+        let token_char_limit = var("TOKEN_CHAR_LIMIT")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(200_000);
+        let max_token_char_limit = var("MAX_TOKEN_CHAR_LIMIT")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(1_000_000)
+            .max(token_char_limit);
+
+        // A fifth of the default report size.
+        let prompt_char_limit = var("PROMPT_CHAR_LIMIT")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(40_000);
+
+        let max_total_tokens = var("MAX_TOTAL_TOKENS_PER_CALL").ok().and_then(|s| s.parse().ok());
+
+        let generation = GenerationDefaults::new(
+            GenerationParams {
+                temperature: var("GENERATION_TEMPERATURE").ok().and_then(|s| s.parse().ok()),
+                top_p: var("GENERATION_TOP_P").ok().and_then(|s| s.parse().ok()),
+                max_tokens: var("GENERATION_MAX_TOKENS").ok().and_then(|s| s.parse().ok()),
+                stop: var("GENERATION_STOP")
+                    .ok()
+                    .map(|s| serde_json::from_str(&s))
+                    .transpose()
+                    .context("GENERATION_STOP must be a JSON array of strings")?,
+            },
+            var("TOOL_GENERATION_PARAMS").ok().as_deref(),
+        )?;
+
+        let data_dir = var("AGENT_DATA_DIR")
+            .map(PathBuf::from)
+            .unwrap_or_else(|_| std::env::temp_dir().join("ai_code_agent"));
+
+        let embedding = EmbeddingConfig {
+            model: var("EMBEDDING_MODEL").unwrap_or_else(|_| "text-embedding-004".to_string()),
+            index_dir: data_dir.join("embeddings"),
+            top_k: var("RAG_TOP_K")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(40),
+            adaptive: var("RAG_ADAPTIVE_TOP_K")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(true),
+            import_depth: var("IMPORT_DEPTH")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(1),
+        };
+
+        let code_indexes = var("USE_CODE_INDEXES")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(true)
+            .then(|| Arc::new(CodeIndexes::default()));
+
+        let file_selection_tools = var("FILE_SELECTION_TOOLS")
+            .map(|s| {
+                s.split(',')
+                    .map(|t| t.trim().to_string())
+                    .filter(|t| !t.is_empty())
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let include_mentioned_files = var("INCLUDE_MENTIONED_FILES")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(true);
+
+        let redact_literals = var("REDACT_LITERALS")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(false);
+        if redact_literals {
+            tracing::info!("Literal redaction enabled for every call");
+        }
+
+        let allow_writes = var("ALLOW_WRITES")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(true);
+
+        let semantic_cache = var("SEMANTIC_CACHE_THRESHOLD")
+            .ok()
+            .and_then(|s| s.parse::<f32>().ok())
+            .map(|threshold| {
+                tracing::info!("Semantic response cache enabled with similarity threshold {}", threshold);
+                Arc::new(SemanticCache::load(
+                    data_dir.join("semantic_cache.json"),
+                    embedding.model.clone(),
+                    threshold,
+                ))
+            });
+
+        // Canonicalized so symlinked paths are compared by where they lead.
+        let allowed_roots = match var("ALLOWED_WORKSPACE_ROOTS") {
+            Ok(roots) if !roots.trim().is_empty() => {
+                let mut canonical = Vec::new();
+                for root in roots.split(',').map(str::trim).filter(|r| !r.is_empty()) {
+                    if !Path::new(root).is_absolute() {
+                        bail!("ALLOWED_WORKSPACE_ROOTS entry '{root}' must be an absolute path");
+                    }
+                    canonical.push(Path::new(root).canonicalize().with_context(|| format!("Invalid ALLOWED_WORKSPACE_ROOTS entry '{root}'"))?);
+                }
+                Some(canonical)
+            }
+            _ => None,
+        };
+        let process_metrics = Arc::new(ProcessMetrics::default());
+        let workspace = WorkspaceConfig {
+            root_dir: data_dir.join("workspaces"),
+            max_archive_bytes: var("MAX_ARCHIVE_BYTES")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(500 * 1024 * 1024),
+            clone_timeout: Duration::from_secs(
+                var("CLONE_TIMEOUT_SECS")
+                    .ok()
+                    .and_then(|s| s.parse().ok())
+                    .filter(|&s| s > 0)
+                    .unwrap_or(300),
+            ),
+            max_clone_bytes: var("MAX_CLONE_BYTES")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(500 * 1024 * 1024),
+            process_metrics: process_metrics.clone(),
+            mirror: var("MIRROR_DIRECTORIES")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(false),
+            allowed_roots,
+        };
+
+        let session_ttl = var("SESSION_TTL_SECS")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(3600);
+
+        let prompt_canary = match var("PROMPT_CANARY_FILE") {
+            Ok(path) => {
+                let fraction = var("PROMPT_CANARY_FRACTION")
+                    .ok()
+                    .and_then(|s| s.parse().ok())
+                    .unwrap_or(0.1);
+                let canary = PromptCanary::load(&PathBuf::from(path), fraction, data_dir.join("prompt_canary.jsonl"))?;
+                tracing::info!("Prompt canary enabled for {:?} on {:.0}% of requests", canary.tools(), fraction * 100.0);
+                Some(Arc::new(canary))
+            }
+            Err(_) => None,
+        };
+
+        let prompt_templates = match var("PROMPT_TEMPLATES_DIR") {
+            Ok(dir) => {
+                let templates = PromptTemplates::load(&PathBuf::from(dir))?;
+                tracing::info!("Prompt templates loaded for {:?}", templates.tools());
+                Some(Arc::new(templates))
+            }
+            Err(_) => None,
+        };
+
+        let registries = Registries::new(
+            var("CRATES_INDEX_DIR").ok().map(PathBuf::from),
+            var("REGISTRY_OFFLINE").ok().and_then(|s| s.parse().ok()).unwrap_or(false),
+        );
+
+        let token = |name: &str| var(name).ok().filter(|t| !t.is_empty());
+        let forges = Forges {
+            github: GitHub::new(
+                var("GITHUB_API_URL").unwrap_or_else(|_| "https://api.github.com".to_string()),
+                token("GITHUB_TOKEN"),
+            ),
+            gitlab: GitLab::new(
+                var("GITLAB_URL").unwrap_or_else(|_| "https://gitlab.com".to_string()),
+                token("GITLAB_TOKEN"),
+            ),
+            bitbucket: Bitbucket::new(
+                var("BITBUCKET_API_URL").unwrap_or_else(|_| "https://api.bitbucket.org/2.0".to_string()),
+                token("BITBUCKET_TOKEN"),
+                token("BITBUCKET_USERNAME"),
+            ),
+        };
+        let jira = Jira::new(
+            token("JIRA_URL"),
+            token("JIRA_TOKEN"),
+            token("JIRA_EMAIL"),
+            token("JIRA_ACCEPTANCE_FIELD"),
+        );
+
+        let gc_interval = var("GC_INTERVAL_SECS")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(3600);
+        let max_cache_bytes = var("MAX_CACHE_BYTES")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(2 * 1024 * 1024 * 1024);
+        let gc = GcConfig {
+            cache_dir: embedding.index_dir.clone(),
+            workspace_dir: workspace.root_dir.clone(),
+            max_cache_bytes: (max_cache_bytes > 0).then_some(max_cache_bytes),
+            max_clone_age: Duration::from_secs(
+                var("MAX_CLONE_AGE_SECS")
+                    .ok()
+                    .and_then(|s| s.parse().ok())
+                    .unwrap_or(6 * 3600),
+            ),
+            // Runs older than twice the timeout have been killed.
+            max_temp_age: Duration::from_secs(viewer_timeout * 2),
+            interval: (gc_interval > 0).then(|| Duration::from_secs(gc_interval)),
+        };
+
+        let tree_hash_refresh = var("TREE_HASH_REFRESH_SECS")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(60);
+
+        let health_cache_ttl = var("HEALTH_CACHE_SECS")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(60);
+
+        Ok(Config {
+            codebase_viewer_path: Arc::new(codebase_viewer_path),
+            viewer_timeout: Duration::from_secs(viewer_timeout),
+            report_check: Arc::new(report_check),
+            process_metrics,
+            gemini_client,
+            token_char_limit,
+            max_token_char_limit,
+            prompt_char_limit: (prompt_char_limit > 0).then_some(prompt_char_limit),
+            max_total_tokens,
+            generation: Arc::new(generation),
+            embedding,
+            code_indexes,
+            file_selection_tools,
+            include_mentioned_files,
+            redact_literals,
+            allow_writes,
+            semantic_cache,
+            workspace,
+            gc,
+            sessions: Arc::new(SessionStore::new(Duration::from_secs(session_ttl))),
+            failed_requests: Arc::new(FailedQueue::load(data_dir.join("failed_requests.json"))),
+            saved_plans: Arc::new(PlanStore::load(data_dir.join("plans.json"))),
+            prompt_canary,
+            prompt_templates,
+            registries: Arc::new(registries),
+            forges: Arc::new(forges),
+            jira: Arc::new(jira),
+            tree_hashes: Arc::new(TreeHashes::default()),
+            tree_hash_refresh: (tree_hash_refresh > 0).then(|| Duration::from_secs(tree_hash_refresh)),
+            health_cache: Arc::new(HealthCache::default()),
+            health_cache_ttl: Duration::from_secs(health_cache_ttl),
+        })
+    }
 }
@@ -0,0 +1,329 @@
+use crate::external;
+use crate::files::{self, SourceFile};
+use crate::imports;
+use crate::llm::GeminiClient;
+use crate::redact;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+
+const EMBED_BATCH_SIZE: usize = 64;
+const EMBED_INPUT_CHARS: usize = 8_000;
+
+// Adaptive top-K: observations needed before a size is judged, the share of
+// verified references below which more files are retrieved, and the share at
+// or above which fewer are tried.
+const MIN_TUNING_RUNS: u32 = 3;
+const UNDER_GROUNDED: f32 = 0.85;
+const WELL_GROUNDED: f32 = 0.97;
+const MIN_TOP_K: usize = 5;
+// Older observations are halved once a size has this many, so the tuning
+// follows the codebase as it changes.
+const TUNING_WINDOW: u32 = 20;
+
+#[derive(Clone)]
+pub struct EmbeddingConfig {
+    pub model: String,
+    pub index_dir: PathBuf,
+    // Starting top-K; the per-repository value when `adaptive` is set.
+    pub top_k: usize,
+    pub adaptive: bool,
+    // Hops of imports followed from retrieved and selected files; 0 sends
+    // only those files.
+    pub import_depth: usize,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct EmbeddingIndex {
+    model: String,
+    // `TreeHashes` root hash of the files the entries were last refreshed
+    // against; while it matches, the index is current.
+    #[serde(default)]
+    tree_hash: Option<String>,
+    entries: HashMap<PathBuf, IndexEntry>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct IndexEntry {
+    hash: String,
+    vector: Vec<f32>,
+}
+
+// Per-repository retrieval size learned from how well answers built on it
+// were grounded, stored next to the embedding index.
+#[derive(Serialize, Deserialize, Default)]
+struct RetrievalTuning {
+    top_k: usize,
+    stats: BTreeMap<usize, GroundingStats>,
+}
+
+#[derive(Serialize, Deserialize, Default, Clone, Copy)]
+struct GroundingStats {
+    runs: u32,
+    verified: u32,
+    total: u32,
+}
+
+impl GroundingStats {
+    fn rate(&self) -> f32 {
+        if self.total == 0 {
+            1.0
+        } else {
+            self.verified as f32 / self.total as f32
+        }
+    }
+}
+
+impl RetrievalTuning {
+    // Records how many of an answer's `path:line` references checked out, then
+    // moves to a larger top-K if answers at the current size cite code that
+    // does not exist, or tries a smaller one if they are reliably grounded.
+    fn record(&mut self, verified: usize, total: usize, default_top_k: usize) {
+        let current = self.top_k;
+        let stats = self.stats.entry(current).or_default();
+        stats.runs += 1;
+        stats.verified += verified as u32;
+        stats.total += total as u32;
+        if stats.runs > TUNING_WINDOW {
+            *stats = GroundingStats { runs: stats.runs / 2, verified: stats.verified / 2, total: stats.total / 2 };
+        }
+        let stats = *stats;
+        if stats.runs < MIN_TUNING_RUNS {
+            return;
+        }
+
+        let judged = |k: usize| self.stats.get(&k).filter(|s| s.runs >= MIN_TUNING_RUNS).map(|s| s.rate());
+        let next = if stats.rate() < UNDER_GROUNDED {
+            let larger = (current * 3 / 2).min(default_top_k * 4);
+            (larger > current && judged(larger).is_none_or(|rate| rate > stats.rate())).then_some(larger)
+        } else if stats.rate() >= WELL_GROUNDED {
+            let smaller = (current * 2 / 3).max(MIN_TOP_K);
+            (smaller < current && judged(smaller).is_none_or(|rate| rate >= WELL_GROUNDED)).then_some(smaller)
+        } else {
+            None
+        };
+        if let Some(next) = next {
+            tracing::info!(
+                "Retrieval top-K {} -> {} ({:.0}% of references verified over {} run(s))",
+                current,
+                next,
+                stats.rate() * 100.0,
+                stats.runs
+            );
+            self.top_k = next;
+        }
+    }
+}
+
+pub async fn retrieve_context(
+    client: &GeminiClient,
+    config: &EmbeddingConfig,
+    target_path: &Path,
+    index_key: &Path,
+    tree_hash: Option<&str>,
+    prompt: &str,
+    token_char_limit: usize,
+) -> Result<String> {
+    let source_files = external::collect_files(target_path).await?;
+
+    // Redacted contents are embedded into an index of their own.
+    let redacting = redact::active();
+    let index_name = if redacting {
+        format!("{}:redacted", index_key.to_string_lossy())
+    } else {
+        index_key.to_string_lossy().into_owned()
+    };
+    let index_path = config.index_dir.join(format!("{}.json", files::content_hash(&index_name)));
+    let mut index = load_index(&index_path, &client.embedding_id(&config.model)).await;
+
+    if tree_hash.is_none() || index.tree_hash.as_deref() != tree_hash {
+        refresh_index(client, config, &mut index, &source_files, redacting).await?;
+        index.tree_hash = tree_hash.map(str::to_string);
+        save_index(&index_path, &index).await?;
+    }
+
+    let top_k = if config.adaptive {
+        load_tuning(config, index_key).await.top_k
+    } else {
+        config.top_k
+    };
+
+    let query = client
+        .embed(&config.model, vec![prompt.to_string()])
+        .await?
+        .pop()
+        .context("Embedding API returned no vector for the prompt")?;
+
+    let mut scored: Vec<(f32, &SourceFile)> = source_files
+        .iter()
+        .filter_map(|f| index.entries.get(&f.path).map(|e| (cosine_similarity(&query, &e.vector), f)))
+        .collect();
+    scored.sort_by(|a, b| b.0.total_cmp(&a.0));
+    scored.truncate(top_k);
+
+    tracing::info!(
+        "Retrieved {} of {} files for '{}'",
+        scored.len(),
+        source_files.len(),
+        target_path.display()
+    );
+
+    let mut context = format!(
+        "The following {} files were selected as the most relevant to the request out of {} files in the codebase:\n",
+        scored.len(),
+        source_files.len()
+    );
+    for (score, file) in &scored {
+        context.push_str(&format!("- `{}` (similarity {:.3})\n", file.path.display(), score));
+    }
+    let mut selected: Vec<&SourceFile> = scored.into_iter().map(|(_, f)| f).collect();
+    let imported = imports::follow_loaded(&source_files, &selected, config.import_depth);
+    context.push_str(&imports::render_list(&imported));
+    context.push('\n');
+
+    selected.extend(imported.iter().map(|entry| &entry.file));
+    context.push_str(&files::render_files(&selected, token_char_limit.saturating_sub(context.len())));
+
+    Ok(context)
+}
+
+// Feeds the grounding of an answer built on retrieved context back into the
+// repository's top-K. The outcome is attributed to the current top-K, which
+// only differs from the one used if another request changed it meanwhile.
+pub async fn record_grounding(config: &EmbeddingConfig, index_key: &Path, verified: usize, total: usize) -> Result<()> {
+    if !config.adaptive || total == 0 {
+        return Ok(());
+    }
+    let mut tuning = load_tuning(config, index_key).await;
+    tuning.record(verified, total, config.top_k);
+
+    let path = tuning_path(config, index_key);
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent)
+            .await
+            .context("Failed to create embedding index directory")?;
+    }
+    let bytes = serde_json::to_vec(&tuning).context("Failed to serialize retrieval tuning")?;
+    tokio::fs::write(&path, bytes)
+        .await
+        .context("Failed to write retrieval tuning")
+}
+
+fn tuning_path(config: &EmbeddingConfig, index_key: &Path) -> PathBuf {
+    config
+        .index_dir
+        .join(format!("{}.tuning.json", files::content_hash(&index_key.to_string_lossy())))
+}
+
+async fn load_tuning(config: &EmbeddingConfig, index_key: &Path) -> RetrievalTuning {
+    let tuning = match tokio::fs::read(tuning_path(config, index_key)).await {
+        Ok(bytes) => serde_json::from_slice::<RetrievalTuning>(&bytes).ok(),
+        Err(_) => None,
+    };
+    match tuning {
+        Some(tuning) if tuning.top_k > 0 => tuning,
+        _ => RetrievalTuning {
+            top_k: config.top_k,
+            stats: BTreeMap::new(),
+        },
+    }
+}
+
+async fn refresh_index(
+    client: &GeminiClient,
+    config: &EmbeddingConfig,
+    index: &mut EmbeddingIndex,
+    source_files: &[SourceFile],
+    redacting: bool,
+) -> Result<()> {
+    index.entries.retain(|path, _| source_files.iter().any(|f| &f.path == path));
+
+    let stale: Vec<(&SourceFile, String)> = source_files
+        .iter()
+        .map(|f| (f, files::content_hash(&f.content)))
+        .filter(|(f, hash)| index.entries.get(&f.path).is_none_or(|e| &e.hash != hash))
+        .collect();
+
+    if stale.is_empty() {
+        return Ok(());
+    }
+
+    tracing::info!("Embedding {} new or changed files", stale.len());
+
+    let jobs: Vec<_> = stale
+        .chunks(EMBED_BATCH_SIZE)
+        .map(|batch| async move {
+            let inputs = batch
+                .iter()
+                .map(|(f, _)| {
+                    let content = if redacting { redact::source(&f.path, &f.content) } else { f.content.clone() };
+                    let body: String = content.chars().take(EMBED_INPUT_CHARS).collect();
+                    format!("{}\n{}", f.path.display(), body)
+                })
+                .collect();
+
+            let vectors = client.embed(&config.model, inputs).await?;
+            if vectors.len() != batch.len() {
+                return Err(anyhow::anyhow!(
+                    "Embedding API returned {} vectors for {} inputs",
+                    vectors.len(),
+                    batch.len()
+                ));
+            }
+            Ok((batch, vectors))
+        })
+        .collect();
+
+    for result in client.fan_out(jobs).await {
+        let (batch, vectors) = result?;
+        for ((file, hash), vector) in batch.iter().zip(vectors) {
+            index.entries.insert(file.path.clone(), IndexEntry { hash: hash.clone(), vector });
+        }
+    }
+
+    Ok(())
+}
+
+async fn load_index(path: &Path, model: &str) -> EmbeddingIndex {
+    let index = match tokio::fs::read(path).await {
+        Ok(bytes) => serde_json::from_slice::<EmbeddingIndex>(&bytes).ok(),
+        Err(_) => None,
+    };
+
+    match index {
+        Some(index) if index.model == model => index,
+        _ => EmbeddingIndex {
+            model: model.to_string(),
+            tree_hash: None,
+            entries: HashMap::new(),
+        },
+    }
+}
+
+async fn save_index(path: &Path, index: &EmbeddingIndex) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent)
+            .await
+            .context("Failed to create embedding index directory")?;
+    }
+    let bytes = serde_json::to_vec(index).context("Failed to serialize embedding index")?;
+    tokio::fs::write(path, bytes)
+        .await
+        .context("Failed to write embedding index")
+}
+
+// Vectors of different lengths come from different models and are unrelated.
+pub fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    if a.len() != b.len() {
+        return 0.0;
+    }
+    let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let norm_a = a.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let norm_b = b.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm_a == 0.0 || norm_b == 0.0 {
+        0.0
+    } else {
+        dot / (norm_a * norm_b)
+    }
+}
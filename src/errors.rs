@@ -0,0 +1,179 @@
+use crate::keys::ErrorClass;
+use crate::llm::LlmError;
+use async_openai::error::OpenAIError;
+use rmcp::model::{CallToolResult, Content};
+use schemars::JsonSchema;
+use serde::Serialize;
+
+// Why a tool call failed, for clients that branch on it instead of parsing
+// the message. Sent as `error.kind` in the result's structured content.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorKind {
+    // A parameter is out of range or conflicts with another.
+    InvalidParams,
+    // The directory, archive, git URL, or commit could not be opened, or is
+    // not what the call needs (e.g. a git checkout).
+    InvalidPath,
+    // An unknown session, plan, or queued request ID.
+    NotFound,
+    // The codebase report, file tree, or retrieval index could not be built.
+    ReportGeneration,
+    // The context does not fit max_total_tokens or the model's context window.
+    ContextTooLarge,
+    // The caller's fair share or the provider's quota is used up.
+    LlmQuota,
+    LlmRateLimited,
+    LlmTimeout,
+    // Every API key was rejected; see `key`.
+    LlmUnauthorized,
+    // The provider refused the request itself (e.g. an unknown model).
+    LlmInvalidRequest,
+    // Server, network, or other unexpected provider errors after all retries
+    // and fallbacks.
+    LlmUnavailable,
+    // The model answered, but not in the form the tool needs (an empty or
+    // unparseable response, an invalid diff or plan).
+    InvalidOutput,
+    // Jira, a forge, or a package registry could not be reached or refused.
+    ExternalService,
+    // The server is configured not to do this (e.g. ALLOW_WRITES=false).
+    Disabled,
+    Internal,
+}
+
+impl ErrorKind {
+    // Failures that may clear up if the same call is made again later.
+    pub fn retryable(self) -> bool {
+        matches!(
+            self,
+            ErrorKind::LlmQuota | ErrorKind::LlmRateLimited | ErrorKind::LlmTimeout | ErrorKind::LlmUnavailable | ErrorKind::ExternalService
+        )
+    }
+}
+
+#[derive(Debug, Clone, Serialize, JsonSchema, thiserror::Error)]
+#[error("{message}")]
+pub struct ToolError {
+    pub kind: ErrorKind,
+    pub message: String,
+    pub retryable: bool,
+    // ID in the failed request queue, for `replay_failed`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub queued_as: Option<String>,
+    // The masked API key, for llm_unauthorized.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+    // Estimated tokens and the budget they exceed, for context_too_large
+    // from max_total_tokens.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub estimated_tokens: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub budget: Option<u64>,
+}
+
+impl ToolError {
+    pub fn new(kind: ErrorKind, message: impl Into<String>) -> Self {
+        Self {
+            kind,
+            message: message.into(),
+            retryable: kind.retryable(),
+            queued_as: None,
+            key: None,
+            estimated_tokens: None,
+            budget: None,
+        }
+    }
+
+    pub fn invalid_params(message: impl Into<String>) -> Self {
+        Self::new(ErrorKind::InvalidParams, message)
+    }
+
+    pub fn invalid_path(message: impl Into<String>) -> Self {
+        Self::new(ErrorKind::InvalidPath, message)
+    }
+
+    pub fn not_found(message: impl Into<String>) -> Self {
+        Self::new(ErrorKind::NotFound, message)
+    }
+
+    pub fn report(message: impl Into<String>) -> Self {
+        Self::new(ErrorKind::ReportGeneration, message)
+    }
+
+    // A model call that failed with `error`, described as `action` (e.g.
+    // "Failed to generate feature plan from Gemini").
+    pub fn llm(action: &str, error: &LlmError) -> Self {
+        let kind = match error {
+            LlmError::QuotaExceeded(_) => ErrorKind::LlmQuota,
+            LlmError::InvalidRequest { status: 413, .. } => ErrorKind::ContextTooLarge,
+            // Gemini's answer to a prompt over the model's input limit.
+            LlmError::InvalidRequest { message, .. } if message.contains("exceeds the maximum number of tokens") => ErrorKind::ContextTooLarge,
+            LlmError::InvalidRequest { .. } => ErrorKind::LlmInvalidRequest,
+            LlmError::NoContent | LlmError::Api(OpenAIError::JSONDeserialize(_)) => ErrorKind::InvalidOutput,
+            _ => match error.class() {
+                ErrorClass::QuotaExhausted => ErrorKind::LlmQuota,
+                ErrorClass::RateLimited => ErrorKind::LlmRateLimited,
+                ErrorClass::Timeout => ErrorKind::LlmTimeout,
+                ErrorClass::Unauthorized => ErrorKind::LlmUnauthorized,
+                ErrorClass::Server | ErrorClass::Network => ErrorKind::LlmUnavailable,
+                // Other HTTP statuses and provider errors, and a model list
+                // that never ended.
+                ErrorClass::Other if matches!(error, LlmError::Http { .. } | LlmError::Pagination(_) | LlmError::Api(OpenAIError::Reqwest(_) | OpenAIError::ApiError(_))) => ErrorKind::LlmUnavailable,
+                ErrorClass::Other => ErrorKind::Internal,
+            },
+        };
+        let mut tool_error = Self::new(kind, format!("{action}: {error}"));
+        if let LlmError::Unauthorized { key, .. } = error {
+            tool_error.key = Some(key.clone());
+        }
+        tool_error
+    }
+
+    pub fn context_too_large(message: impl Into<String>, estimated_tokens: u64, budget: u64) -> Self {
+        Self {
+            estimated_tokens: Some(estimated_tokens),
+            budget: Some(budget),
+            ..Self::new(ErrorKind::ContextTooLarge, message)
+        }
+    }
+
+    // Prefixes the message, keeping the kind (e.g. which directory failed).
+    pub fn context(self, prefix: &str) -> Self {
+        Self {
+            message: format!("{prefix}: {}", self.message),
+            ..self
+        }
+    }
+}
+
+// An error result with the message as text and the error as structured
+// content under `error`.
+impl From<ToolError> for CallToolResult {
+    fn from(error: ToolError) -> Self {
+        let mut result = CallToolResult::error(vec![Content::text(error.message.clone())]);
+        result.structured_content = Some(serde_json::json!({ "error": error }));
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kind(error: LlmError) -> ErrorKind {
+        ToolError::llm("Failed", &error).kind
+    }
+
+    #[test]
+    fn only_unusable_answers_are_invalid_output() {
+        assert_eq!(kind(LlmError::NoContent), ErrorKind::InvalidOutput);
+        let parse_error = serde_json::from_str::<serde_json::Value>("{").unwrap_err();
+        assert_eq!(kind(LlmError::Api(OpenAIError::JSONDeserialize(parse_error))), ErrorKind::InvalidOutput);
+
+        assert_eq!(kind(LlmError::Http { status: 409, message: String::new() }), ErrorKind::LlmUnavailable);
+        assert_eq!(kind(LlmError::Http { status: 503, message: String::new() }), ErrorKind::LlmUnavailable);
+        assert_eq!(kind(LlmError::Pagination(String::new())), ErrorKind::LlmUnavailable);
+        assert_eq!(kind(LlmError::Api(OpenAIError::InvalidArgument(String::new()))), ErrorKind::Internal);
+    }
+}
@@ -1,11 +1,201 @@
+use crate::files::{self, SourceFile};
+use crate::imports;
+use crate::process::{self, ProcessError, ProcessMetrics};
+use crate::ranking;
+use crate::redact;
 use anyhow::{Context, Result};
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 use tokio::process::Command;
 
+// The report embeds every staged file, so one much smaller than the source
+// was cut short.
+pub const DEFAULT_MIN_REPORT_RATIO: f64 = 0.25;
+
+// Files named in the note on files left out of a trimmed report.
+const MAX_LISTED_LEFT_OUT: usize = 50;
+
+// Expectations a complete codebase_viewer report meets, to catch a viewer
+// that exits successfully after writing only part of its report.
+#[derive(Clone)]
+pub struct ReportCheck {
+    // Text the report must start and end with, for viewer versions whose
+    // format has fixed markers.
+    pub header: Option<String>,
+    pub footer: Option<String>,
+    // Minimum report size as a fraction of the staged source bytes; 0 disables.
+    pub min_size_ratio: f64,
+}
+
+impl Default for ReportCheck {
+    fn default() -> Self {
+        Self {
+            header: None,
+            footer: None,
+            min_size_ratio: DEFAULT_MIN_REPORT_RATIO,
+        }
+    }
+}
+
+impl ReportCheck {
+    // Why `report` looks incomplete, or None if it passes.
+    fn problem(&self, report: &str, staged_bytes: u64) -> Option<String> {
+        if report.trim().is_empty() && staged_bytes > 0 {
+            return Some("the report is empty".to_string());
+        }
+        if let Some(header) = &self.header {
+            if !report.trim_start().starts_with(header.as_str()) {
+                return Some(format!("the report does not start with {header:?}"));
+            }
+        }
+        if let Some(footer) = &self.footer {
+            if !report.trim_end().ends_with(footer.as_str()) {
+                return Some(format!("the report does not end with {footer:?}"));
+            }
+        }
+        let min_bytes = staged_bytes as f64 * self.min_size_ratio;
+        if (report.len() as f64) < min_bytes {
+            return Some(format!(
+                "the report is {} bytes for {staged_bytes} bytes of source files, below the minimum of {:.0}",
+                report.len(),
+                min_bytes
+            ));
+        }
+        None
+    }
+}
+
+// codebase_viewer runs on a staged copy containing only the files that pass
+// .gitignore/.agentignore filtering, so ignored files (build output, secrets)
+// never reach the report even though it is invoked with --all. When the files
+// exceed `token_char_limit`, only the most important ones are staged
+// (`ranking::trim_to_limit`) and the rest are listed at the end. A report
+// that fails `check` is generated once more, and refused if it fails again.
 pub async fn generate_codebase_report(
     viewer_path: &Path,
     target_path: &Path,
     token_char_limit: usize,
+    timeout: Duration,
+    metrics: &ProcessMetrics,
+    check: &ReportCheck,
+) -> Result<String> {
+    let staging = std::env::temp_dir().join(format!("report-src-{}", uuid::Uuid::new_v4()));
+    let name = target_path.file_name().map(|n| n.to_os_string()).unwrap_or_else(|| "codebase".into());
+    let staged_target = staging.join(name);
+
+    let result = async {
+        let mut source_files = collect_files(target_path).await?;
+        let left_out = ranking::trim_to_limit(&mut source_files, token_char_limit);
+        if !left_out.is_empty() {
+            tracing::warn!(
+                "'{}' exceeds the character limit ({token_char_limit}); leaving out {} of its lowest-ranked files",
+                target_path.display(),
+                left_out.len()
+            );
+        }
+        let staged_bytes = stage_files(source_files, &staged_target, redact::active()).await?;
+        let mut report = run_viewer(viewer_path, &staged_target, timeout, metrics).await?;
+        if let Some(problem) = check.problem(&report, staged_bytes) {
+            tracing::warn!(
+                "codebase_viewer report for '{}' looks incomplete ({problem}); generating it again",
+                target_path.display()
+            );
+            report = run_viewer(viewer_path, &staged_target, timeout, metrics).await?;
+            if let Some(problem) = check.problem(&report, staged_bytes) {
+                tracing::error!("codebase_viewer report for '{}' is incomplete again ({problem})", target_path.display());
+                anyhow::bail!(
+                    "codebase_viewer produced an incomplete report twice ({problem}); refusing to plan against partial context. Check the viewer on this directory or adjust VIEWER_REPORT_MIN_RATIO/VIEWER_REPORT_HEADER/VIEWER_REPORT_FOOTER"
+                );
+            }
+        }
+        let note = left_out_note(&left_out);
+        let mut report = truncate_report(report, token_char_limit.saturating_sub(note.len()));
+        report.push_str(&note);
+        Ok(report)
+    }
+    .await;
+
+    let _ = tokio::fs::remove_dir_all(&staging).await;
+    result
+}
+
+// Runs codebase_viewer on a one-file sample, as health_check's end-to-end
+// test of the binary, and checks that the report contains the file.
+pub async fn check_viewer(viewer_path: &Path, timeout: Duration, metrics: &ProcessMetrics) -> Result<()> {
+    let staging = std::env::temp_dir().join(format!("report-src-{}", uuid::Uuid::new_v4()));
+    let sample = staging.join("health");
+    let result = async {
+        tokio::fs::create_dir_all(&sample).await.context("Failed to create staging directory")?;
+        tokio::fs::write(sample.join("main.rs"), "fn main() {}\n").await.context("Failed to stage the sample file")?;
+        let report = run_viewer(viewer_path, &sample, timeout, metrics).await?;
+        if !report.contains("main.rs") {
+            anyhow::bail!("codebase_viewer ran, but its report does not mention the sample file");
+        }
+        Ok(())
+    }
+    .await;
+    let _ = tokio::fs::remove_dir_all(&staging).await;
+    result
+}
+
+// The first line of `codebase_viewer --version`, if the binary supports it.
+pub async fn viewer_version(viewer_path: &Path, metrics: &ProcessMetrics) -> Option<String> {
+    let mut cmd = Command::new(viewer_path);
+    cmd.arg("--version");
+    let output = process::run("codebase_viewer", cmd, Duration::from_secs(10), metrics).await.ok()?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let version = stdout.lines().next()?.trim();
+    (output.status.success() && !version.is_empty()).then(|| version.to_string())
+}
+
+// Combines per-directory reports, labeling each with its directory.
+pub fn label_reports(reports: &[(String, String)]) -> String {
+    let mut combined = String::new();
+    for (i, (label, report)) in reports.iter().enumerate() {
+        combined.push_str(&format!(
+            "# Codebase {}: `{label}`\n\nPaths in this section are relative to `{label}`.\n\n{report}\n\n",
+            i + 1
+        ));
+    }
+    combined
+}
+
+// Copies the files that pass .gitignore/.agentignore filtering from
+// `target_path` into `dest`, returning the bytes copied. With `redact`, the
+// copies have their literals redacted (`redact::source`).
+pub async fn stage_filtered_copy(target_path: &Path, dest: &Path, redact: bool) -> Result<u64> {
+    stage_files(collect_files(target_path).await?, dest, redact).await
+}
+
+async fn stage_files(mut source_files: Vec<SourceFile>, dest: &Path, redact: bool) -> Result<u64> {
+    if redact {
+        for file in &mut source_files {
+            file.content = redact::source(&file.path, &file.content);
+        }
+    }
+    let dest = dest.to_path_buf();
+
+    tokio::task::spawn_blocking(move || -> Result<u64> {
+        std::fs::create_dir_all(&dest).context("Failed to create staging directory")?;
+        for file in &source_files {
+            let path = dest.join(&file.path);
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent).context("Failed to create staging directory")?;
+            }
+            std::fs::write(&path, &file.content)
+                .with_context(|| format!("Failed to stage '{}'", file.path.display()))?;
+        }
+        Ok(source_files.iter().map(|f| f.content.len() as u64).sum())
+    })
+    .await
+    .context("Staging task panicked")?
+}
+
+async fn run_viewer(
+    viewer_path: &Path,
+    target_path: &Path,
+    timeout: Duration,
+    metrics: &ProcessMetrics,
 ) -> Result<String> {
     let temp_dir = std::env::temp_dir();
     let temp_file_path = temp_dir.join(format!("report-{}.md", uuid::Uuid::new_v4()));
@@ -20,7 +210,18 @@ pub async fn generate_codebase_report(
         .arg(&temp_file_path)
         .arg("--all");
 
-    let output = cmd.output().await.context("Failed to execute codebase_viewer")?;
+    let output = match process::run("codebase_viewer", cmd, timeout, metrics).await {
+        Ok(output) => output,
+        Err(e) => {
+            let _ = tokio::fs::remove_file(&temp_file_path).await;
+            return Err(match e {
+                ProcessError::TimedOut { .. } => anyhow::anyhow!(
+                    "{e}; raise VIEWER_TIMEOUT_SECS or point at a smaller directory"
+                ),
+                ProcessError::Spawn(e) => e,
+            });
+        }
+    };
 
     if !output.status.success() {
         let stderr = String::from_utf8_lossy(&output.stderr);
@@ -31,12 +232,15 @@ pub async fn generate_codebase_report(
         ));
     }
 
-    let mut report = tokio::fs::read_to_string(&temp_file_path)
+    let report = tokio::fs::read_to_string(&temp_file_path)
         .await
-        .context("Failed to read generated report file")?;
+        .context("Failed to read generated report file");
 
     let _ = tokio::fs::remove_file(&temp_file_path).await;
+    report
+}
 
+fn truncate_report(mut report: String, token_char_limit: usize) -> String {
     if report.len() > token_char_limit {
         tracing::warn!(
             "Report length ({}) exceeds character limit ({}). Truncating.",
@@ -48,6 +252,119 @@ pub async fn generate_codebase_report(
             report.push_str("\n\n--- REPORT TRUNCATED DUE TO TOKEN LIMIT ---");
         }
     }
+    report
+}
+
+// Lists the files `ranking::trim_to_limit` left out of a report, so the model
+// knows they exist and can ask for them.
+fn left_out_note(left_out: &[PathBuf]) -> String {
+    if left_out.is_empty() {
+        return String::new();
+    }
+    let mut note = format!(
+        "\n\n--- REPORT TRUNCATED DUE TO TOKEN LIMIT: {} lower-ranked files left out ---\n",
+        left_out.len()
+    );
+    for path in left_out.iter().take(MAX_LISTED_LEFT_OUT) {
+        note.push_str(&format!("- `{}`\n", path.display()));
+    }
+    if left_out.len() > MAX_LISTED_LEFT_OUT {
+        note.push_str(&format!("- ... and {} more\n", left_out.len() - MAX_LISTED_LEFT_OUT));
+    }
+    note
+}
+
+pub async fn generate_file_tree(target_path: &Path) -> Result<String> {
+    let source_files = collect_files(target_path).await?;
+
+    let mut tree = String::new();
+    for file in &source_files {
+        tree.push_str(&format!("{} ({} bytes)\n", file.path.display(), file.content.len()));
+    }
+    Ok(tree)
+}
+
+pub async fn generate_selected_files_report(
+    target_path: &Path,
+    selected: &[String],
+    token_char_limit: usize,
+    import_depth: usize,
+) -> Result<String> {
+    let source_files = collect_files(target_path).await?;
+
+    // Only paths that exist in the walked tree are honored, so a model reply can
+    // never pull in files outside the target directory.
+    let selected: Vec<&SourceFile> = selected
+        .iter()
+        .filter_map(|s| {
+            let wanted = PathBuf::from(s.trim().trim_start_matches("./"));
+            source_files.iter().find(|f| f.path == wanted)
+        })
+        .collect();
+
+    if selected.is_empty() {
+        return Err(anyhow::anyhow!("None of the files selected by the model exist in '{}'", target_path.display()));
+    }
+
+    tracing::info!(
+        "Including {} selected files out of {} in '{}'",
+        selected.len(),
+        source_files.len(),
+        target_path.display()
+    );
+
+    let imported = imports::follow_loaded(&source_files, &selected, import_depth);
+    let mut report = String::from("Files selected for this request:\n");
+    for file in &selected {
+        report.push_str(&format!("- `{}`\n", file.path.display()));
+    }
+    report.push_str(&imports::render_list(&imported));
+    report.push('\n');
+    let mut rendered = selected;
+    rendered.extend(imported.iter().map(|entry| &entry.file));
+    report.push_str(&files::render_files(&rendered, token_char_limit.saturating_sub(report.len())));
 
     Ok(report)
 }
+
+// Report of the files whose relative paths are mentioned in `text`, re-read
+// from disk. Returns None if the text mentions no existing file.
+pub async fn generate_referenced_files_report(
+    target_path: &Path,
+    text: &str,
+    token_char_limit: usize,
+) -> Result<Option<String>> {
+    let source_files = collect_files(target_path).await?;
+
+    let referenced: Vec<&SourceFile> = source_files
+        .iter()
+        .filter(|f| text.contains(&f.path.to_string_lossy().replace('\\', "/")))
+        .collect();
+
+    if referenced.is_empty() {
+        return Ok(None);
+    }
+
+    tracing::info!(
+        "Including {} referenced files out of {} in '{}'",
+        referenced.len(),
+        source_files.len(),
+        target_path.display()
+    );
+
+    let mut report = String::from("Files referenced by the analysis:\n");
+    for file in &referenced {
+        report.push_str(&format!("- `{}`\n", file.path.display()));
+    }
+    report.push('\n');
+    report.push_str(&files::render_files(&referenced, token_char_limit.saturating_sub(report.len())));
+
+    Ok(Some(report))
+}
+
+pub async fn collect_files(target_path: &Path) -> Result<Vec<SourceFile>> {
+    let root = target_path.to_path_buf();
+    tokio::task::spawn_blocking(move || files::collect_source_files(&root))
+        .await
+        .context("File collection task panicked")?
+}
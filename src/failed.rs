@@ -0,0 +1,102 @@
+use crate::plan::OutputFormat;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const MAX_ENTRIES: usize = 200;
+
+// A pipeline request that failed at the LLM stage, kept so it can be replayed
+// once the provider recovers.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct FailedRequest {
+    pub id: String,
+    pub tool: String,
+    pub directory: String,
+    pub prompt: String,
+    pub format: OutputFormat,
+    pub options: serde_json::Value,
+    pub report_hash: String,
+    pub error: String,
+    pub failed_at: u64,
+    pub attempts: u32,
+}
+
+pub struct FailedQueue {
+    path: PathBuf,
+    entries: Mutex<Vec<FailedRequest>>,
+}
+
+impl FailedQueue {
+    pub fn load(path: PathBuf) -> Self {
+        let entries = std::fs::read(&path)
+            .ok()
+            .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+            .unwrap_or_default();
+
+        Self {
+            path,
+            entries: Mutex::new(entries),
+        }
+    }
+
+    pub fn list(&self) -> Vec<FailedRequest> {
+        self.entries.lock().unwrap().clone()
+    }
+
+    pub fn get(&self, id: &str) -> Option<FailedRequest> {
+        self.entries.lock().unwrap().iter().find(|e| e.id == id).cloned()
+    }
+
+    pub async fn push(&self, mut request: FailedRequest) -> Result<()> {
+        request.failed_at = now();
+        let snapshot = {
+            let mut entries = self.entries.lock().unwrap();
+            entries.push(request);
+            if entries.len() > MAX_ENTRIES {
+                let excess = entries.len() - MAX_ENTRIES;
+                entries.drain(..excess);
+            }
+            serde_json::to_vec(&*entries).context("Failed to serialize failed request queue")?
+        };
+        self.persist(snapshot).await
+    }
+
+    pub async fn record_retry(&self, id: &str, error: &str) -> Result<()> {
+        let snapshot = {
+            let mut entries = self.entries.lock().unwrap();
+            if let Some(entry) = entries.iter_mut().find(|e| e.id == id) {
+                entry.error = error.to_string();
+                entry.failed_at = now();
+                entry.attempts += 1;
+            }
+            serde_json::to_vec(&*entries).context("Failed to serialize failed request queue")?
+        };
+        self.persist(snapshot).await
+    }
+
+    pub async fn remove(&self, id: &str) -> Result<()> {
+        let snapshot = {
+            let mut entries = self.entries.lock().unwrap();
+            entries.retain(|e| e.id != id);
+            serde_json::to_vec(&*entries).context("Failed to serialize failed request queue")?
+        };
+        self.persist(snapshot).await
+    }
+
+    async fn persist(&self, snapshot: Vec<u8>) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            tokio::fs::create_dir_all(parent)
+                .await
+                .context("Failed to create failed request queue directory")?;
+        }
+        tokio::fs::write(&self.path, snapshot)
+            .await
+            .context("Failed to write failed request queue")
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
@@ -0,0 +1,171 @@
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::future::Future;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+tokio::task_local! {
+    static CONSUMER: String;
+}
+
+// Runs `future` with its API calls counted against `consumer`'s quota: a
+// follow-up session, an MCP client, or a REST caller. The innermost consumer
+// wins; calls outside any consumer are not limited.
+pub async fn as_consumer<F: Future>(consumer: String, future: F) -> F::Output {
+    CONSUMER.scope(consumer, future).await
+}
+
+fn current_consumer() -> Option<String> {
+    CONSUMER.try_with(Clone::clone).ok()
+}
+
+#[derive(Debug)]
+pub struct QuotaExceeded {
+    pub consumer: String,
+    pub used: u64,
+    pub quota: u64,
+    pub window: Duration,
+    pub resume_at: SystemTime,
+}
+
+impl fmt::Display for QuotaExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let wait = self.resume_at.duration_since(SystemTime::now()).unwrap_or_default();
+        write!(
+            f,
+            "Token quota exceeded for '{}': {} of {} tokens used in the last {}; resuming at {} (in {})",
+            self.consumer,
+            self.used,
+            self.quota,
+            format_duration(self.window),
+            format_utc(self.resume_at),
+            format_duration(wait)
+        )
+    }
+}
+
+// Per-consumer token quotas over a sliding window, so one long session or
+// batch job cannot monopolize the key pool. A consumer over its quota waits
+// for the window to free up if that takes at most `max_wait`, and is refused
+// with the time it may resume otherwise. Usage is only known after a response,
+// so concurrent calls can overshoot the quota by their in-flight requests.
+pub struct FairShare {
+    quota: u64,
+    window: Duration,
+    max_wait: Duration,
+    usage: Mutex<HashMap<String, VecDeque<(Instant, u64)>>>,
+}
+
+pub struct ConsumerUsage {
+    pub consumer: String,
+    pub used: u64,
+}
+
+impl FairShare {
+    pub fn new(quota: u64, window: Duration, max_wait: Duration) -> Self {
+        Self {
+            quota,
+            window,
+            max_wait,
+            usage: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn quota(&self) -> u64 {
+        self.quota
+    }
+
+    pub fn window(&self) -> Duration {
+        self.window
+    }
+
+    // Called before each API request.
+    pub async fn admit(&self) -> Result<(), QuotaExceeded> {
+        let Some(consumer) = current_consumer() else {
+            return Ok(());
+        };
+        loop {
+            let Some((used, wait)) = self.over_quota(&consumer) else {
+                return Ok(());
+            };
+            if wait > self.max_wait {
+                return Err(QuotaExceeded {
+                    consumer,
+                    used,
+                    quota: self.quota,
+                    window: self.window,
+                    resume_at: SystemTime::now() + wait,
+                });
+            }
+            tracing::info!("'{consumer}' is over its token quota ({used} of {}); queued for {}s", self.quota, wait.as_secs());
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    // Called with each response's token count.
+    pub fn record(&self, tokens: u64) {
+        if let Some(consumer) = current_consumer() {
+            self.usage.lock().unwrap().entry(consumer).or_default().push_back((Instant::now(), tokens));
+        }
+    }
+
+    // Consumers with usage in the current window, heaviest first.
+    pub fn usage(&self) -> Vec<ConsumerUsage> {
+        let now = Instant::now();
+        let mut usage = self.usage.lock().unwrap();
+        usage.retain(|_, entries| {
+            expire(entries, now, self.window);
+            !entries.is_empty()
+        });
+        let mut consumers: Vec<ConsumerUsage> = usage
+            .iter()
+            .map(|(consumer, entries)| ConsumerUsage {
+                consumer: consumer.clone(),
+                used: entries.iter().map(|(_, tokens)| tokens).sum(),
+            })
+            .collect();
+        consumers.sort_by_key(|c| std::cmp::Reverse(c.used));
+        consumers
+    }
+
+    // Tokens used in the window and how long until enough of them expire to
+    // bring the consumer back under quota, or None if it is under quota.
+    fn over_quota(&self, consumer: &str) -> Option<(u64, Duration)> {
+        let now = Instant::now();
+        let mut usage = self.usage.lock().unwrap();
+        let entries = usage.get_mut(consumer)?;
+        expire(entries, now, self.window);
+        let used: u64 = entries.iter().map(|(_, tokens)| tokens).sum();
+        if used < self.quota {
+            return None;
+        }
+        let mut remaining = used;
+        for (at, tokens) in entries.iter() {
+            remaining -= tokens;
+            if remaining < self.quota {
+                return Some((used, (*at + self.window).saturating_duration_since(now)));
+            }
+        }
+        Some((used, self.window))
+    }
+}
+
+fn expire(entries: &mut VecDeque<(Instant, u64)>, now: Instant, window: Duration) {
+    while entries.front().is_some_and(|(at, _)| now.duration_since(*at) >= window) {
+        entries.pop_front();
+    }
+}
+
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    match (secs / 3600, secs / 60 % 60, secs % 60) {
+        (0, 0, s) => format!("{s}s"),
+        (0, m, s) => format!("{m}m {s}s"),
+        (h, m, _) => format!("{h}h {m}m"),
+    }
+}
+
+fn format_utc(time: SystemTime) -> String {
+    let secs = time.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    format!("{:02}:{:02}:{:02} UTC", secs / 3600 % 24, secs / 60 % 60, secs % 60)
+}
@@ -0,0 +1,795 @@
+use crate::external;
+use crate::files;
+use crate::manifests::{self, Ecosystem, Manifest};
+use crate::plan::{self, StructuredPlan};
+use anyhow::{bail, Context, Result};
+use ignore::WalkBuilder;
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+// Source searched for API names, per locked package.
+const MAX_SOURCE_BYTES: u64 = 32 * 1024 * 1024;
+
+const REGISTRY_TIMEOUT: Duration = Duration::from_secs(15);
+
+// Commands that add dependencies, and the ecosystem they add to.
+const INSTALL_COMMANDS: &[(&str, Ecosystem)] = &[
+    ("cargo add ", Ecosystem::Cargo),
+    ("npm install ", Ecosystem::Npm),
+    ("npm i ", Ecosystem::Npm),
+    ("npm add ", Ecosystem::Npm),
+    ("yarn add ", Ecosystem::Npm),
+    ("pnpm add ", Ecosystem::Npm),
+    ("pip install ", Ecosystem::Pip),
+    ("pip3 install ", Ecosystem::Pip),
+    ("poetry add ", Ecosystem::Pip),
+    ("uv add ", Ecosystem::Pip),
+];
+
+// Install flags whose next argument is a value, not a package.
+const VALUE_FLAGS: &[&str] = &[
+    "--features", "-F", "--rename", "--package", "-p", "--registry", "--target", "-r", "-c", "-e", "--index-url", "-i",
+    "--extra-index-url", "--tag", "--group", "-G",
+];
+
+// Install flags that take the package from somewhere other than the registry.
+const SOURCE_FLAGS: &[&str] = &["--git", "--path"];
+
+// Paths starting with these are the standard library or the project itself.
+const NON_CRATE_ROOTS: &[&str] = &["std", "core", "alloc", "crate", "self", "super", "Self"];
+
+#[derive(Serialize, JsonSchema, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Verdict {
+    // The plan relies on something that does not exist.
+    Infeasible,
+    // The check could not run (registry unreachable, source not downloaded).
+    Unverified,
+}
+
+#[derive(Serialize, JsonSchema, Clone)]
+pub struct Finding {
+    pub verdict: Verdict,
+    // "dependency" or "api".
+    pub check: String,
+    // Package (with the requested version) or API path as the plan wrote it.
+    pub subject: String,
+    pub detail: String,
+    // Plan steps that mention the subject.
+    pub steps: Vec<String>,
+}
+
+#[derive(Serialize, JsonSchema, Clone, Default)]
+pub struct FeasibilityReport {
+    pub dependencies_checked: usize,
+    pub apis_checked: usize,
+    pub findings: Vec<Finding>,
+}
+
+// Where dependency names and versions are looked up: the crates.io, npm, and
+// PyPI APIs, or a local checkout of the crates.io index.
+pub struct Registries {
+    http: reqwest::Client,
+    crates_index: Option<PathBuf>,
+    // Skip network lookups; only the local crates.io index is consulted.
+    offline: bool,
+}
+
+impl Registries {
+    pub fn new(crates_index: Option<PathBuf>, offline: bool) -> Self {
+        let http = reqwest::Client::builder()
+            .user_agent(concat!("ai-code-agent/", env!("CARGO_PKG_VERSION")))
+            .timeout(REGISTRY_TIMEOUT)
+            .build()
+            .unwrap_or_default();
+        Self { http, crates_index, offline }
+    }
+
+    // Published versions, or None if the package does not exist.
+    async fn versions(&self, ecosystem: Ecosystem, name: &str) -> Result<Option<Vec<String>>> {
+        if let (Ecosystem::Cargo, Some(index)) = (ecosystem, &self.crates_index) {
+            return read_crates_index(index, name).await;
+        }
+        if self.offline {
+            bail!("registry lookups are disabled (REGISTRY_OFFLINE)");
+        }
+
+        let (url, field) = match ecosystem {
+            Ecosystem::Cargo => (format!("https://crates.io/api/v1/crates/{name}"), "versions"),
+            Ecosystem::Npm => (format!("https://registry.npmjs.org/{}", name.replace('/', "%2F")), "versions"),
+            Ecosystem::Pip => (format!("https://pypi.org/pypi/{name}/json"), "releases"),
+        };
+        let response = self.http.get(&url).send().await.with_context(|| format!("Failed to reach {url}"))?;
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        let body: serde_json::Value = response
+            .error_for_status()
+            .with_context(|| format!("Lookup at {url} failed"))?
+            .json()
+            .await
+            .with_context(|| format!("Invalid response from {url}"))?;
+        // crates.io lists version objects; npm and PyPI key their maps by version.
+        let versions = match &body[field] {
+            serde_json::Value::Array(entries) => entries.iter().filter_map(|v| v["num"].as_str()).map(String::from).collect(),
+            serde_json::Value::Object(map) => map.keys().cloned().collect(),
+            _ => Vec::new(),
+        };
+        Ok(Some(versions))
+    }
+}
+
+// Path of a crate's file in the crates.io index layout.
+fn index_path(name: &str) -> PathBuf {
+    match name.len() {
+        1 => Path::new("1").join(name),
+        2 => Path::new("2").join(name),
+        3 => Path::new("3").join(&name[..1]).join(name),
+        _ => Path::new(&name[..2]).join(&name[2..4]).join(name),
+    }
+}
+
+async fn read_crates_index(index: &Path, name: &str) -> Result<Option<Vec<String>>> {
+    let path = index.join(index_path(&name.to_lowercase()));
+    let content = match tokio::fs::read_to_string(&path).await {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e).with_context(|| format!("Failed to read '{}'", path.display())),
+    };
+    let versions = content
+        .lines()
+        .filter_map(|line| serde_json::from_str::<serde_json::Value>(line).ok())
+        .filter_map(|entry| entry["vers"].as_str().map(String::from))
+        .collect();
+    Ok(Some(versions))
+}
+
+// A registry dependency the plan adds or relies on.
+#[derive(PartialEq)]
+struct Mention {
+    ecosystem: Ecosystem,
+    name: String,
+    requirement: String,
+}
+
+// What the checks read from a plan: code by language, and all of its text.
+struct PlanText {
+    blocks: Vec<(&'static str, String)>,
+    prose: String,
+    steps: Vec<String>,
+}
+
+// Static checks of a plan (markdown or JSON) against package registries and
+// the locked dependency sources of the codebase at `root`: that dependencies
+// it adds exist with the requested version, and that the library APIs its
+// code uses exist in the versions the project has locked. No model calls.
+pub async fn check(registries: &Registries, root: &Path, plan_text: &str) -> Result<FeasibilityReport> {
+    let source_files = external::collect_files(root).await?;
+    let manifests = manifests::parse_all(&source_files);
+    let plan = read_plan(plan_text);
+    let mut report = FeasibilityReport::default();
+
+    let mentions = dependency_mentions(&plan, &manifests);
+    report.dependencies_checked = mentions.len();
+    let lookups = mentions.iter().map(|m| check_dependency(registries, m));
+    for (mention, finding) in mentions.iter().zip(futures::future::join_all(lookups).await) {
+        if let Some(mut finding) = finding {
+            finding.steps = steps_mentioning(&plan.steps, &mention.name);
+            report.findings.push(finding);
+        }
+    }
+
+    let internal: HashSet<String> = manifests.iter().filter_map(|m| m.package.as_deref()).map(|p| p.replace('-', "_")).collect();
+    if let Some(lock) = find_upwards(root, "Cargo.lock") {
+        let content = tokio::fs::read_to_string(&lock).await.with_context(|| format!("Failed to read '{}'", lock.display()))?;
+        let locked = parse_cargo_lock(&content);
+        let paths: Vec<Vec<String>> = rust_paths(&plan)
+            .into_iter()
+            .filter(|path| locked.contains_key(&path[0]) && !internal.contains(&path[0]))
+            .collect();
+        report.apis_checked += paths.len();
+        for mut finding in check_rust_paths(&locked, paths).await {
+            finding.steps = steps_mentioning(&plan.steps, &finding.subject);
+            report.findings.push(finding);
+        }
+    }
+    if let Some(node_modules) = find_upwards(root, "node_modules") {
+        let imports = npm_imports(&plan);
+        report.apis_checked += imports.iter().map(|(_, names)| names.len()).sum::<usize>();
+        for mut finding in check_npm_imports(&node_modules, imports).await {
+            finding.steps = steps_mentioning(&plan.steps, &finding.subject);
+            report.findings.push(finding);
+        }
+    }
+    Ok(report)
+}
+
+fn read_plan(text: &str) -> PlanText {
+    if let Ok(plan) = plan::parse_structured_plan(text) {
+        return structured_plan_text(&plan);
+    }
+
+    let mut blocks = Vec::new();
+    let mut fence: Option<(&'static str, String)> = None;
+    for line in text.lines() {
+        let trimmed = line.trim_start();
+        match (&mut fence, trimmed.strip_prefix("```")) {
+            (None, Some(info)) => fence = Some((language(info.split_whitespace().next().unwrap_or(""), ""), String::new())),
+            (Some(_), Some(_)) => blocks.extend(fence.take()),
+            (Some((_, body)), None) => {
+                body.push_str(line);
+                body.push('\n');
+            }
+            (None, None) => {}
+        }
+    }
+    let steps = text
+        .lines()
+        .map(str::trim)
+        .filter(|line| line.starts_with(['-', '*', '#']) || line.split_once(". ").is_some_and(|(n, _)| n.parse::<u32>().is_ok()))
+        .map(String::from)
+        .collect();
+    PlanText { blocks, prose: text.to_string(), steps }
+}
+
+fn structured_plan_text(plan: &StructuredPlan) -> PlanText {
+    let mut blocks = Vec::new();
+    let mut prose = vec![plan.summary.clone()];
+    prose.extend(plan.steps.iter().cloned());
+    for change in &plan.file_changes {
+        let lang = language("", &change.path);
+        prose.push(change.description.clone());
+        let code = change.content.iter().chain(change.edits.iter().map(|e| &e.replace)).chain(&change.code_snippets);
+        for text in code {
+            blocks.push((lang, text.clone()));
+            prose.push(text.clone());
+        }
+    }
+    PlanText { blocks, prose: prose.join("\n"), steps: plan.steps.clone() }
+}
+
+// Normalized language of a fenced block's info string, or of a file path.
+fn language(info: &str, path: &str) -> &'static str {
+    let name = Path::new(path).file_name().and_then(|n| n.to_str()).unwrap_or("");
+    if name == "Cargo.toml" {
+        return "toml";
+    }
+    if name == "package.json" {
+        return "json";
+    }
+    if name.starts_with("requirements") && name.ends_with(".txt") {
+        return "requirements";
+    }
+    let info = match info {
+        "" => Path::new(name).extension().and_then(|e| e.to_str()).unwrap_or(""),
+        info => info,
+    };
+    match info.to_lowercase().as_str() {
+        "toml" => "toml",
+        "json" | "jsonc" => "json",
+        "rust" | "rs" => "rust",
+        "ts" | "tsx" | "typescript" | "js" | "jsx" | "javascript" | "mjs" | "cjs" => "js",
+        _ => "",
+    }
+}
+
+fn dependency_mentions(plan: &PlanText, manifests: &[Manifest]) -> Vec<Mention> {
+    let mut found = Vec::new();
+    for (lang, text) in &plan.blocks {
+        match *lang {
+            "toml" => found.extend(toml_dependencies(text)),
+            "json" => found.extend(json_dependencies(text)),
+            "requirements" => found.extend(manifests::parse_requirements(text).into_iter().filter(|d| d.source.is_none()).map(|d| Mention {
+                ecosystem: Ecosystem::Pip,
+                name: d.name,
+                requirement: d.requirement,
+            })),
+            _ => {}
+        }
+    }
+    found.extend(install_commands(&plan.prose));
+
+    let mut mentions: Vec<Mention> = Vec::new();
+    for mention in found {
+        if !valid_name(&mention.name) || mentions.contains(&mention) {
+            continue;
+        }
+        // Workspace packages are not published, and dependencies the project
+        // already declares with the same requirement are known to resolve.
+        let declared = manifests.iter().filter(|m| m.ecosystem == mention.ecosystem).any(|m| {
+            m.package.as_deref() == Some(mention.name.as_str())
+                || m.dependencies.iter().any(|d| {
+                    d.name == mention.name && (d.source.is_some() || mention.requirement == "*" || d.requirement == mention.requirement)
+                })
+        });
+        if !declared {
+            mentions.push(mention);
+        }
+    }
+    mentions
+}
+
+fn valid_name(name: &str) -> bool {
+    let bare = name.strip_prefix('@').unwrap_or(name);
+    !bare.is_empty()
+        && name.len() <= 214
+        && bare.chars().all(|c| c.is_ascii_alphanumeric() || "-_./".contains(c))
+        && !bare.starts_with(['.', '/', '-'])
+        && (name.starts_with('@') || !name.contains('/'))
+}
+
+// Entries of `[...dependencies]` tables in a Cargo.toml fragment, line by
+// line since plans often show only part of the file.
+fn toml_dependencies(text: &str) -> Vec<Mention> {
+    let mut mentions = Vec::new();
+    let mut in_dependencies = false;
+    for line in text.lines().map(str::trim) {
+        if let Some(header) = line.strip_prefix('[') {
+            in_dependencies = header.trim_end_matches(']').ends_with("dependencies");
+            continue;
+        }
+        let Some((name, spec)) = line.split_once('=') else {
+            continue;
+        };
+        if !in_dependencies || line.starts_with('#') {
+            continue;
+        }
+        let spec = spec.trim();
+        if ["path", "git", "workspace"].iter().any(|key| spec.contains(&format!("{key} ="))) {
+            continue;
+        }
+        let requirement = match spec.strip_prefix('"') {
+            Some(version) => version.split('"').next(),
+            None => spec.split_once("version").and_then(|(_, rest)| rest.split('"').nth(1)),
+        };
+        let name = spec
+            .split_once("package")
+            .and_then(|(_, rest)| rest.split('"').nth(1))
+            .unwrap_or(name.trim().trim_matches('"'));
+        mentions.push(Mention {
+            ecosystem: Ecosystem::Cargo,
+            name: name.to_string(),
+            requirement: requirement.unwrap_or("*").to_string(),
+        });
+    }
+    mentions
+}
+
+// Dependency maps of a package.json, or of a fragment of one.
+fn json_dependencies(text: &str) -> Vec<Mention> {
+    let doc = serde_json::from_str::<serde_json::Value>(text)
+        .or_else(|_| serde_json::from_str(&format!("{{{}}}", text.trim().trim_end_matches(','))));
+    let Ok(doc) = doc else {
+        return Vec::new();
+    };
+    let mut mentions = Vec::new();
+    for field in ["dependencies", "devDependencies", "peerDependencies", "optionalDependencies"] {
+        for (name, requirement) in doc.get(field).and_then(|d| d.as_object()).into_iter().flatten() {
+            let requirement = requirement.as_str().unwrap_or("*");
+            if requirement.contains(':') || requirement.starts_with(['.', '/']) {
+                continue;
+            }
+            mentions.push(Mention { ecosystem: Ecosystem::Npm, name: name.clone(), requirement: requirement.to_string() });
+        }
+    }
+    mentions
+}
+
+// Packages named in `cargo add`, `npm install`, `pip install`, and similar
+// commands anywhere in the plan, with `name@version` or `name==version`
+// requirements split off.
+fn install_commands(text: &str) -> Vec<Mention> {
+    let mut mentions = Vec::new();
+    for line in text.lines() {
+        for (command, ecosystem) in INSTALL_COMMANDS {
+            let Some(start) = line.find(command) else {
+                continue;
+            };
+            // Inside inline code, the command ends at the closing backtick.
+            let rest = &line[start + command.len()..];
+            let rest = if line[..start].ends_with('`') { rest.split('`').next().unwrap_or(rest) } else { rest };
+            let args: Vec<&str> = rest
+                .split_whitespace()
+                .take_while(|arg| !["&&", "||", ";", "|"].contains(arg) && !arg.starts_with('`'))
+                .collect();
+            if args.iter().any(|arg| SOURCE_FLAGS.contains(arg)) {
+                continue;
+            }
+            let mut skip_value = false;
+            for arg in args {
+                let arg = arg.trim_end_matches(['.', ';', ':', ',', ')']).trim_matches(['`', '"', '\'', '(']);
+                if std::mem::take(&mut skip_value) || arg.is_empty() {
+                    continue;
+                }
+                if arg.starts_with('-') {
+                    skip_value = VALUE_FLAGS.contains(&arg);
+                    continue;
+                }
+                let (name, requirement) = match ecosystem {
+                    Ecosystem::Pip => match arg.find(['<', '>', '=', '!', '~', '[', ';']) {
+                        Some(end) => (&arg[..end], arg[end..].split(';').next().unwrap_or("*")),
+                        None => (arg, "*"),
+                    },
+                    // Scoped npm packages start with '@'; the version follows the last one.
+                    _ => match arg.rfind('@').filter(|&at| at > 0) {
+                        Some(at) => (&arg[..at], &arg[at + 1..]),
+                        None => (arg, "*"),
+                    },
+                };
+                mentions.push(Mention { ecosystem: *ecosystem, name: name.to_string(), requirement: requirement.to_string() });
+            }
+        }
+    }
+    mentions
+}
+
+async fn check_dependency(registries: &Registries, mention: &Mention) -> Option<Finding> {
+    let registry = match mention.ecosystem {
+        Ecosystem::Cargo => "crates.io",
+        Ecosystem::Npm => "npm",
+        Ecosystem::Pip => "PyPI",
+    };
+    let subject = match mention.requirement.as_str() {
+        "*" => mention.name.clone(),
+        requirement => format!("{} {requirement}", mention.name),
+    };
+    let finding = |verdict, detail| Finding { verdict, check: "dependency".to_string(), subject: subject.clone(), detail, steps: Vec::new() };
+
+    match registries.versions(mention.ecosystem, &mention.name).await {
+        Ok(None) => Some(finding(Verdict::Infeasible, format!("`{}` does not exist on {registry}", mention.name))),
+        Ok(Some(versions)) if !satisfiable(&mention.requirement, &versions) => {
+            let latest = versions.iter().max_by_key(|v| version_numbers(v)).map_or("none".to_string(), |v| format!("`{v}`"));
+            Some(finding(
+                Verdict::Infeasible,
+                format!("No published version of `{}` matches `{}` (latest: {latest})", mention.name, mention.requirement),
+            ))
+        }
+        Ok(Some(_)) => None,
+        Err(e) => Some(finding(Verdict::Unverified, format!("{registry} lookup failed: {e:#}"))),
+    }
+}
+
+// Whether a published version could satisfy `requirement`: an exact match
+// for `=` requirements, otherwise a semver-compatible version at least as
+// new. Ranges and wildcards are accepted as written.
+fn satisfiable(requirement: &str, published: &[String]) -> bool {
+    let requirement = requirement.trim();
+    if matches!(requirement, "" | "*" | "latest") || requirement.starts_with(['>', '<', '!']) || requirement.contains([',', '|', 'x', '*']) {
+        return true;
+    }
+    let exact = requirement.starts_with('=');
+    let wanted = version_numbers(requirement.trim_start_matches(['^', '~', '=', 'v', ' ']));
+    if wanted.is_empty() {
+        return true;
+    }
+    // Versions below 1.0 break compatibility at the first non-zero component.
+    let significant = wanted.iter().position(|&n| n != 0).map_or(wanted.len(), |i| i + 1);
+    published.iter().map(|v| version_numbers(v)).any(|version| {
+        if exact {
+            version.starts_with(&wanted)
+        } else {
+            version.len() >= significant && version[..significant] == wanted[..significant] && version >= wanted
+        }
+    })
+}
+
+// Leading numeric components: "1.2.3-beta" -> [1, 2, 3].
+fn version_numbers(version: &str) -> Vec<u64> {
+    version
+        .split('.')
+        .map_while(|part| {
+            let digits: String = part.chars().take_while(char::is_ascii_digit).collect();
+            digits.parse().ok()
+        })
+        .collect()
+}
+
+fn find_upwards(root: &Path, name: &str) -> Option<PathBuf> {
+    root.ancestors().map(|dir| dir.join(name)).find(|path| path.exists())
+}
+
+struct LockedCrate {
+    name: String,
+    version: String,
+    dependencies: Vec<String>,
+}
+
+// Locked packages keyed by their name as written in paths (underscores).
+// When several versions are locked, the last one listed wins.
+fn parse_cargo_lock(content: &str) -> HashMap<String, LockedCrate> {
+    let Ok(doc) = toml::from_str::<toml::Table>(content) else {
+        return HashMap::new();
+    };
+    let packages = doc.get("package").and_then(|p| p.as_array()).into_iter().flatten();
+    packages
+        .filter(|p| p.get("source").is_some())
+        .filter_map(|p| {
+            let name = p.get("name")?.as_str()?.to_string();
+            let version = p.get("version")?.as_str()?.to_string();
+            let dependencies = p
+                .get("dependencies")
+                .and_then(|d| d.as_array())
+                .into_iter()
+                .flatten()
+                .filter_map(|d| d.as_str()?.split_whitespace().next().map(String::from))
+                .collect();
+            Some((name.replace('-', "_"), LockedCrate { name, version, dependencies }))
+        })
+        .collect()
+}
+
+// `a::b::C` paths in the plan's Rust code and inline code, with `use`
+// groups (`a::{B, c::D}`) expanded one level.
+fn rust_paths(plan: &PlanText) -> Vec<Vec<String>> {
+    let mut code: Vec<&str> = plan.blocks.iter().filter(|(lang, _)| *lang == "rust").map(|(_, text)| text.as_str()).collect();
+    code.extend(plan.prose.split('`').skip(1).step_by(2));
+
+    let mut paths: Vec<Vec<String>> = Vec::new();
+    for text in code {
+        let mut rest = text;
+        while let Some(start) = rest.find("::") {
+            let head_start = rest[..start].rfind(|c: char| !(c.is_alphanumeric() || c == '_' || c == ':')).map_or(0, |i| i + 1);
+            let tail = &rest[head_start..];
+            let end = tail.find(|c: char| !(c.is_alphanumeric() || c == '_' || c == ':')).unwrap_or(tail.len());
+            let path = &tail[..end];
+            rest = &tail[end..];
+
+            let prefix: Vec<String> = path.trim_end_matches("::").split("::").map(String::from).collect();
+            if prefix.iter().any(|s| s.is_empty()) || NON_CRATE_ROOTS.contains(&prefix[0].as_str()) {
+                continue;
+            }
+            let group = path.ends_with("::").then(|| rest.strip_prefix('{').and_then(|r| r.split_once('}'))).flatten();
+            if let Some((_, after)) = group {
+                rest = after;
+            }
+            let expanded = match group {
+                Some((items, _)) => items
+                    .split(',')
+                    .map(|item| item.split(" as ").next().unwrap_or("").trim())
+                    .filter(|item| !item.is_empty() && *item != "self" && *item != "*")
+                    .map(|item| prefix.iter().cloned().chain(item.split("::").map(String::from)).collect())
+                    .collect(),
+                None if prefix.len() >= 2 => vec![prefix],
+                None => Vec::new(),
+            };
+            for path in expanded {
+                if !paths.contains(&path) {
+                    paths.push(path);
+                }
+            }
+        }
+    }
+    paths
+}
+
+async fn check_rust_paths(locked: &HashMap<String, LockedCrate>, paths: Vec<Vec<String>>) -> Vec<Finding> {
+    let Some(registry) = cargo_registry_sources() else {
+        return Vec::new();
+    };
+    let mut findings = Vec::new();
+    let mut sources: HashMap<String, Option<HashSet<String>>> = HashMap::new();
+    for path in paths {
+        let krate = &locked[&path[0]];
+        // Items are often re-exported from the crate's own dependencies.
+        let mut names = HashSet::new();
+        let mut downloaded = true;
+        for name in std::iter::once(&krate.name).chain(&krate.dependencies) {
+            let Some(dependency) = locked.get(&name.replace('-', "_")) else {
+                continue;
+            };
+            let key = format!("{}-{}", dependency.name, dependency.version);
+            if !sources.contains_key(&key) {
+                let dir = crate_source_dir(&registry, &key);
+                let identifiers = match dir {
+                    Some(dir) => tokio::task::spawn_blocking(move || identifiers(&dir, &["rs"])).await.ok(),
+                    None => None,
+                };
+                sources.insert(key.clone(), identifiers);
+            }
+            match &sources[&key] {
+                Some(identifiers) => names.extend(identifiers.iter().cloned()),
+                None if dependency.name == krate.name => downloaded = false,
+                None => {}
+            }
+        }
+
+        let subject = path.join("::");
+        if !downloaded {
+            // One note per crate is enough.
+            if !findings.iter().any(|f: &Finding| f.verdict == Verdict::Unverified && f.subject == krate.name) {
+                findings.push(Finding {
+                    verdict: Verdict::Unverified,
+                    check: "api".to_string(),
+                    subject: krate.name.clone(),
+                    detail: format!("The source of {} {} is not in the local Cargo registry; run `cargo fetch`", krate.name, krate.version),
+                    steps: Vec::new(),
+                });
+            }
+            continue;
+        }
+        if let Some(missing) = path[1..].iter().find(|segment| !names.contains(*segment)) {
+            findings.push(Finding {
+                verdict: Verdict::Infeasible,
+                check: "api".to_string(),
+                subject,
+                detail: format!("`{missing}` does not appear in {} {} (the locked version) or its dependencies", krate.name, krate.version),
+                steps: Vec::new(),
+            });
+        }
+    }
+    findings
+}
+
+fn cargo_registry_sources() -> Option<PathBuf> {
+    let home = std::env::var_os("CARGO_HOME").map(PathBuf::from).or_else(|| {
+        std::env::var_os("HOME")
+            .or_else(|| std::env::var_os("USERPROFILE"))
+            .map(|home| PathBuf::from(home).join(".cargo"))
+    })?;
+    Some(home.join("registry").join("src"))
+}
+
+// Downloaded sources live under one directory per registry mirror.
+fn crate_source_dir(registry: &Path, name_version: &str) -> Option<PathBuf> {
+    std::fs::read_dir(registry)
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path().join(name_version))
+        .find(|dir| dir.is_dir())
+}
+
+// Every identifier-like word in files with `extensions` under `dir`, up to
+// MAX_SOURCE_BYTES, skipping nested node_modules.
+fn identifiers(dir: &Path, extensions: &[&str]) -> HashSet<String> {
+    let walker = WalkBuilder::new(dir)
+        .standard_filters(false)
+        .filter_entry(|entry| entry.depth() == 0 || entry.file_name() != "node_modules")
+        .build();
+    let mut names = HashSet::new();
+    let mut read = 0;
+    for entry in walker.filter_map(|e| e.ok()) {
+        if !entry.file_type().is_some_and(|t| t.is_file()) || !files::has_extension(entry.path(), extensions) {
+            continue;
+        }
+        read += entry.metadata().map(|m| m.len()).unwrap_or(0);
+        if read > MAX_SOURCE_BYTES {
+            tracing::debug!("Stopped indexing '{}' at {MAX_SOURCE_BYTES} bytes", dir.display());
+            break;
+        }
+        let Ok(content) = std::fs::read_to_string(entry.path()) else {
+            continue;
+        };
+        for word in content.split(|c: char| !(c.is_alphanumeric() || c == '_' || c == '$')) {
+            if !word.is_empty() && !names.contains(word) {
+                names.insert(word.to_string());
+            }
+        }
+    }
+    names
+}
+
+// Named imports (`import { a, b as c } from 'pkg'`, `const { a } =
+// require('pkg')`) in the plan's JavaScript and TypeScript, by package.
+fn npm_imports(plan: &PlanText) -> Vec<(String, Vec<String>)> {
+    let mut imports: Vec<(String, Vec<String>)> = Vec::new();
+    for (_, text) in plan.blocks.iter().filter(|(lang, _)| *lang == "js") {
+        let mut rest = text.as_str();
+        while let Some(open) = rest.find('{') {
+            let Some(close) = rest[open..].find('}').map(|i| open + i) else {
+                break;
+            };
+            let names = &rest[open + 1..close];
+            let after = rest[close + 1..].trim_start();
+            rest = &rest[open + 1..];
+            if names.contains(['{', '(', ';', '=']) || !(after.starts_with("from") || after.starts_with("= require(")) {
+                continue;
+            }
+            let Some(source) = after.split(['\'', '"']).nth(1) else {
+                continue;
+            };
+            if source.starts_with(['.', '/']) || source.starts_with("node:") {
+                continue;
+            }
+            let package = match source.strip_prefix('@') {
+                Some(scoped) => format!("@{}", scoped.splitn(3, '/').take(2).collect::<Vec<_>>().join("/")),
+                None => source.split('/').next().unwrap_or(source).to_string(),
+            };
+            let names = names
+                .split(',')
+                .map(|n| n.trim().trim_start_matches("type ").split([' ', ':']).next().unwrap_or(""))
+                .filter(|n| !n.is_empty());
+            let entry = match imports.iter().position(|(p, _)| *p == package) {
+                Some(index) => &mut imports[index].1,
+                None => {
+                    imports.push((package, Vec::new()));
+                    &mut imports.last_mut().unwrap().1
+                }
+            };
+            for name in names {
+                if !entry.iter().any(|n| n == name) {
+                    entry.push(name.to_string());
+                }
+            }
+        }
+    }
+    imports
+}
+
+async fn check_npm_imports(node_modules: &Path, imports: Vec<(String, Vec<String>)>) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    for (package, names) in imports {
+        let dir = node_modules.join(&package);
+        // Not installed: a Node.js builtin, or a new dependency checked above.
+        let Ok(manifest) = tokio::fs::read_to_string(dir.join("package.json")).await else {
+            continue;
+        };
+        let version = serde_json::from_str::<serde_json::Value>(&manifest)
+            .ok()
+            .and_then(|m| m["version"].as_str().map(String::from))
+            .unwrap_or_else(|| "?".to_string());
+        let Ok(identifiers) = tokio::task::spawn_blocking(move || identifiers(&dir, &["ts", "js", "mjs", "cjs", "mts", "cts"])).await else {
+            continue;
+        };
+        for name in names.iter().filter(|n| !identifiers.contains(*n)) {
+            findings.push(Finding {
+                verdict: Verdict::Infeasible,
+                check: "api".to_string(),
+                subject: format!("{name} from '{package}'"),
+                detail: format!("`{name}` does not appear in {package} {version} (the installed version)"),
+                steps: Vec::new(),
+            });
+        }
+    }
+    findings
+}
+
+// Up to five plan steps that mention `subject` (or, for API paths and
+// imports, its last segment), so infeasible steps can be located.
+fn steps_mentioning(steps: &[String], subject: &str) -> Vec<String> {
+    let key = subject.split(" from ").next().unwrap_or(subject);
+    let short = key.rsplit("::").next().unwrap_or(key);
+    steps
+        .iter()
+        .filter(|step| step.contains(key) || (short.len() > 3 && step.contains(short)))
+        .take(5)
+        .map(|step| match step.char_indices().nth(160) {
+            Some((end, _)) => format!("{}...", &step[..end]),
+            None => step.clone(),
+        })
+        .collect()
+}
+
+pub fn render(report: &FeasibilityReport) -> String {
+    let mut out = format!(
+        "## Feasibility Check\n\nChecked {} new or changed dependencies against their registries and {} library API references against the locked versions.\n\n",
+        report.dependencies_checked, report.apis_checked
+    );
+    if report.findings.is_empty() {
+        out.push_str("No problems found.");
+        return out;
+    }
+
+    out.push_str("| Verdict | Check | Subject | Detail |\n|---|---|---|---|\n");
+    for finding in &report.findings {
+        let verdict = match finding.verdict {
+            Verdict::Infeasible => "**infeasible**",
+            Verdict::Unverified => "unverified",
+        };
+        out.push_str(&format!("| {verdict} | {} | `{}` | {} |\n", finding.check, finding.subject, finding.detail));
+    }
+
+    let affected: Vec<&Finding> = report.findings.iter().filter(|f| f.verdict == Verdict::Infeasible && !f.steps.is_empty()).collect();
+    if !affected.is_empty() {
+        out.push_str("\n### Affected Steps\n");
+        for finding in affected {
+            out.push_str(&format!("\n`{}`:\n", finding.subject));
+            for step in &finding.steps {
+                out.push_str(&format!("- {step}\n"));
+            }
+        }
+    }
+    out.trim_end().to_string()
+}
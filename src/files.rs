@@ -0,0 +1,261 @@
+use crate::languages::{self, Language};
+use crate::redact;
+use anyhow::{Context, Result};
+use ignore::WalkBuilder;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+const MAX_FILE_BYTES: u64 = 512 * 1024;
+
+// Per-repo ignore file for paths that are tracked but should never reach the
+// LLM (fixtures with secrets, vendored code, generated files).
+pub const AGENT_IGNORE_FILE: &str = ".agentignore";
+
+pub struct SourceFile {
+    pub path: PathBuf,
+    pub content: String,
+}
+
+pub fn collect_source_files(root: &Path) -> Result<Vec<SourceFile>> {
+    let mut files = Vec::new();
+    for (path, _) in source_paths(root)? {
+        // Binary files fail UTF-8 decoding and are not useful context anyway.
+        let Ok(content) = std::fs::read_to_string(&path) else {
+            continue;
+        };
+        let path = path.strip_prefix(root).unwrap_or(&path).to_path_buf();
+        files.push(SourceFile { path, content });
+    }
+
+    files.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(files)
+}
+
+// Paths and metadata of the files under `root` that pass .gitignore/.agentignore
+// filtering and the size limit, without reading them. Binary files are
+// included; `collect_source_files` drops them when they fail to decode.
+pub fn source_paths(root: &Path) -> Result<Vec<(PathBuf, std::fs::Metadata)>> {
+    let mut paths = Vec::new();
+
+    // .gitignore is honored even outside a git checkout (e.g. extracted archives).
+    let walker = WalkBuilder::new(root)
+        .require_git(false)
+        .add_custom_ignore_filename(AGENT_IGNORE_FILE)
+        .build();
+
+    for entry in walker {
+        let entry = entry.context("Failed to walk codebase directory")?;
+        if !entry.file_type().is_some_and(|t| t.is_file()) {
+            continue;
+        }
+
+        match entry.metadata() {
+            Ok(metadata) if metadata.len() <= MAX_FILE_BYTES => paths.push((entry.into_path(), metadata)),
+            _ => tracing::debug!("Skipping large or unreadable file: {}", entry.path().display()),
+        }
+    }
+    Ok(paths)
+}
+
+pub fn has_extension(path: &Path, extensions: &[&str]) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| extensions.iter().any(|x| x.eq_ignore_ascii_case(e)))
+}
+
+// Renders files in order until `char_limit`. When the rendered files span
+// several languages they are grouped under a heading per language, so the
+// model sees each ecosystem's code together.
+pub fn render_files(files: &[&SourceFile], char_limit: usize) -> String {
+    render_files_counted(files, char_limit).0
+}
+
+// As `render_files`, with the number of files rendered: the first that does
+// not fit ends the rendering, so those are a prefix of `files`.
+pub fn render_files_counted(files: &[&SourceFile], char_limit: usize) -> (String, usize) {
+    let mut blocks: Vec<(Option<&'static Language>, String)> = Vec::new();
+    let mut len = 0;
+    let mut truncated = false;
+
+    for file in files {
+        let language = language(&file.path).unwrap_or("");
+        let mut content = strip_noise(&file.content);
+        if redact::active() {
+            content = redact::source(&file.path, &content);
+        }
+        let block = format!("### `{}`\n\n{}\n\n", file.path.display(), fenced(language, &content));
+        if len + block.len() > char_limit {
+            tracing::warn!("Context reached character limit ({}). Skipping remaining files.", char_limit);
+            truncated = true;
+            break;
+        }
+        len += block.len();
+        blocks.push((languages::of(&file.path), block));
+    }
+
+    let name = |language: &Option<&'static Language>| language.map(|l| l.name);
+    let mut groups: Vec<Option<&'static Language>> = Vec::new();
+    for (language, _) in &blocks {
+        if !groups.iter().any(|g| name(g) == name(language)) {
+            groups.push(*language);
+        }
+    }
+    let mut out = String::new();
+    if groups.iter().flatten().count() < 2 {
+        blocks.iter().for_each(|(_, block)| out.push_str(block));
+    } else {
+        // Files in no known language (configuration, docs) come last.
+        groups.sort_by_key(Option::is_none);
+        for group in groups {
+            match group {
+                Some(language) => out.push_str(&format!("## {} ({}) files\n\n", language.name, language.ecosystem)),
+                None => out.push_str("## Other files\n\n"),
+            }
+            for (_, block) in blocks.iter().filter(|(l, _)| name(l) == name(&group)) {
+                out.push_str(block);
+            }
+        }
+    }
+    if truncated {
+        out.push_str("\n--- CONTEXT TRUNCATED DUE TO TOKEN LIMIT ---");
+    }
+    (out, blocks.len())
+}
+
+// `text` in a code fence tagged `language`, longer than any backtick run
+// inside it so embedded fences (markdown, docs) cannot close it early.
+pub fn fenced(language: &str, text: &str) -> String {
+    let longest = text.split(|c| c != '`').map(str::len).max().unwrap_or(0);
+    let fence = "`".repeat(longest.max(2) + 1);
+    format!("{fence}{language}\n{text}\n{fence}")
+}
+
+// Markdown code-fence language for a file, by name or extension.
+pub fn language(path: &Path) -> Option<&'static str> {
+    let name = path.file_name()?.to_str()?.to_ascii_lowercase();
+    match name.as_str() {
+        "dockerfile" | "containerfile" => return Some("dockerfile"),
+        n if n.starts_with("dockerfile.") => return Some("dockerfile"),
+        "makefile" | "gnumakefile" => return Some("makefile"),
+        "cmakelists.txt" => return Some("cmake"),
+        "gemfile" | "rakefile" => return Some("ruby"),
+        "justfile" => return Some("just"),
+        ".bashrc" | ".profile" | ".zshrc" => return Some("bash"),
+        _ => {}
+    }
+    let language = match path.extension()?.to_str()?.to_ascii_lowercase().as_str() {
+        "rs" => "rust",
+        "py" | "pyi" => "python",
+        "js" | "mjs" | "cjs" => "javascript",
+        "jsx" => "jsx",
+        "ts" | "mts" | "cts" => "typescript",
+        "tsx" => "tsx",
+        "go" => "go",
+        "java" => "java",
+        "kt" | "kts" => "kotlin",
+        "scala" => "scala",
+        "swift" => "swift",
+        "c" | "h" => "c",
+        "cc" | "cpp" | "cxx" | "hpp" | "hh" | "hxx" => "cpp",
+        "cs" => "csharp",
+        "fs" | "fsx" => "fsharp",
+        "rb" => "ruby",
+        "php" => "php",
+        "ex" | "exs" => "elixir",
+        "erl" | "hrl" => "erlang",
+        "hs" => "haskell",
+        "ml" | "mli" => "ocaml",
+        "clj" | "cljs" | "edn" => "clojure",
+        "lua" => "lua",
+        "r" => "r",
+        "dart" => "dart",
+        "zig" => "zig",
+        "sh" | "bash" => "bash",
+        "zsh" => "zsh",
+        "fish" => "fish",
+        "ps1" | "psm1" => "powershell",
+        "sql" => "sql",
+        "html" | "htm" => "html",
+        "css" => "css",
+        "scss" => "scss",
+        "less" => "less",
+        "vue" => "vue",
+        "svelte" => "svelte",
+        "json" | "jsonc" => "json",
+        "yaml" | "yml" => "yaml",
+        "toml" => "toml",
+        "xml" | "csproj" | "fsproj" | "xsd" => "xml",
+        "ini" | "cfg" => "ini",
+        "md" | "markdown" => "markdown",
+        "proto" => "protobuf",
+        "graphql" | "graphqls" | "gql" => "graphql",
+        "tf" | "hcl" => "hcl",
+        "gradle" => "groovy",
+        "nix" => "nix",
+        "mk" => "makefile",
+        _ => return None,
+    };
+    Some(language)
+}
+
+// Blanks lines that carry no meaning for the model: a leading shebang and
+// editor modelines. Lines are blanked rather than removed so that `path:line`
+// references still match the file on disk. A byte-order mark is dropped.
+pub fn strip_noise(content: &str) -> String {
+    let content = content.strip_prefix('\u{feff}').unwrap_or(content);
+    let mut out = String::with_capacity(content.len());
+    for (i, line) in content.split_inclusive('\n').enumerate() {
+        let text = line.trim_end_matches(['\n', '\r']);
+        if (i == 0 && text.starts_with("#!")) || is_modeline(text) {
+            out.push_str(&line[text.len()..]);
+        } else {
+            out.push_str(line);
+        }
+    }
+    out
+}
+
+fn is_modeline(line: &str) -> bool {
+    let comment = line.trim_start().trim_start_matches(['#', '/', '*', ';', '-', '%', '"']).trim_start();
+    comment.starts_with("vim:")
+        || comment.starts_with("vi:")
+        || comment.starts_with("ex:")
+        || (comment.starts_with("-*-") && comment.trim_end().ends_with("-*-"))
+}
+
+pub fn content_hash(content: &str) -> String {
+    format!("{:x}", Sha256::digest(content.as_bytes()))
+}
+
+// Content hash of every file, used to detect what changed between calls.
+pub type Snapshot = HashMap<PathBuf, String>;
+
+pub fn snapshot(files: &[SourceFile]) -> Snapshot {
+    files.iter().map(|f| (f.path.clone(), content_hash(&f.content))).collect()
+}
+
+pub struct SnapshotChanges<'a> {
+    pub changed: Vec<&'a SourceFile>,
+    pub deleted: Vec<PathBuf>,
+}
+
+impl SnapshotChanges<'_> {
+    pub fn is_empty(&self) -> bool {
+        self.changed.is_empty() && self.deleted.is_empty()
+    }
+}
+
+pub fn changes_since<'a>(previous: &Snapshot, files: &'a [SourceFile]) -> SnapshotChanges<'a> {
+    let changed = files
+        .iter()
+        .filter(|f| previous.get(&f.path).is_none_or(|hash| *hash != content_hash(&f.content)))
+        .collect();
+    let mut deleted: Vec<PathBuf> = previous
+        .keys()
+        .filter(|path| !files.iter().any(|f| &f.path == *path))
+        .cloned()
+        .collect();
+    deleted.sort();
+    SnapshotChanges { changed, deleted }
+}
@@ -0,0 +1,161 @@
+use crate::bitbucket::Bitbucket;
+use crate::github::GitHub;
+use crate::gitlab::GitLab;
+use anyhow::Result;
+use futures::future::BoxFuture;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+const FORGE_TIMEOUT: Duration = Duration::from_secs(15);
+
+// Comments beyond this many (one API page) are left out of the bug description.
+pub(crate) const MAX_COMMENTS: usize = 100;
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Copy, Default, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum PublishMode {
+    // A comment on the issue, pull request, or merge request.
+    #[default]
+    Comment,
+    // Replaces the description of the pull request (GitHub only).
+    Description,
+    // Opens a draft pull request from `head` with the result as its
+    // description (GitHub only).
+    DraftPr,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
+pub struct PublishTarget {
+    #[schemars(description = "Issue, pull request, or merge request URL (https://github.com/org/repo/issues/12, https://gitlab.com/group/project/-/merge_requests/34, https://bitbucket.org/workspace/repo/pull-requests/5, ...) for 'comment' and 'description', or the repository URL (https://github.com/org/repo) for 'draft_pr'.")]
+    pub url: String,
+    #[schemars(description = "'comment' (default) posts the result as a comment on the issue, pull request, or merge request. GitHub only: 'description' replaces the pull request's description, and 'draft_pr' opens a draft pull request from 'head' with the result as its description.")]
+    #[serde(default)]
+    pub mode: PublishMode,
+    #[schemars(description = "Branch with the changes, for 'draft_pr'. It must already be pushed and differ from 'base'.")]
+    pub head: Option<String>,
+    #[schemars(description = "Branch the draft pull request merges into. Defaults to the repository's default branch.")]
+    pub base: Option<String>,
+    #[schemars(description = "Title of the draft pull request. Defaults to the result's first heading.")]
+    pub title: Option<String>,
+}
+
+// An issue with the discussion on it.
+pub struct Issue {
+    pub url: String,
+    pub title: String,
+    pub state: String,
+    pub author: Option<String>,
+    pub labels: Vec<String>,
+    pub body: String,
+    // (author, body), oldest first.
+    pub comments: Vec<(String, String)>,
+}
+
+impl Issue {
+    // The issue as a bug description: title, labels, body, then the comments.
+    pub fn render(&self) -> String {
+        let mut out = format!("# {}\n\n", self.title);
+        out.push_str(&format!("Issue: {} ({})\n", self.url, self.state));
+        if let Some(author) = &self.author {
+            out.push_str(&format!("Reported by: @{author}\n"));
+        }
+        if !self.labels.is_empty() {
+            out.push_str(&format!("Labels: {}\n", self.labels.join(", ")));
+        }
+        let body = self.body.trim();
+        out.push_str(&format!("\n{}\n", if body.is_empty() { "(no description)" } else { body }));
+        if !self.comments.is_empty() {
+            out.push_str("\n## Comments\n");
+            for (author, body) in &self.comments {
+                out.push_str(&format!("\n### @{author}\n\n{}\n", body.trim()));
+            }
+        }
+        out
+    }
+}
+
+// A code hosting service that issues are read from and results published to.
+pub trait Forge: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    // Fetches the issue at `url` with its labels and comments.
+    fn issue<'a>(&'a self, url: &'a str) -> BoxFuture<'a, Result<Issue>>;
+
+    // Checks that `target` can be published to before any work is done for it.
+    fn check_target(&self, target: &PublishTarget) -> Result<()>;
+
+    // Posts `body` to `target` and returns the URL it was published at.
+    // `title` is used where the target needs one and does not name it.
+    fn publish<'a>(&'a self, target: &'a PublishTarget, title: &'a str, body: &'a str) -> BoxFuture<'a, Result<String>>;
+}
+
+// The configured forges; a URL goes to the one whose host it names.
+pub struct Forges {
+    pub github: GitHub,
+    pub gitlab: GitLab,
+    pub bitbucket: Bitbucket,
+}
+
+impl Forges {
+    // GitLab and Bitbucket are matched by their configured hosts (and
+    // GitLab's `/-/` paths); any other host is taken to be GitHub, so
+    // GitHub Enterprise URLs work with GITHUB_API_URL.
+    pub fn for_url(&self, url: &str) -> &dyn Forge {
+        let host = host(url);
+        let host = host.as_deref();
+        if host.is_some_and(|h| self.bitbucket.serves(h)) {
+            &self.bitbucket
+        } else if host.is_some_and(|h| self.gitlab.serves(h)) || url.contains("/-/") {
+            &self.gitlab
+        } else {
+            &self.github
+        }
+    }
+
+    pub async fn issue(&self, url: &str) -> Result<Issue> {
+        self.for_url(url).issue(url).await
+    }
+
+    pub fn check_target(&self, target: &PublishTarget) -> Result<()> {
+        self.for_url(&target.url).check_target(target)
+    }
+
+    pub async fn publish(&self, target: &PublishTarget, title: &str, body: &str) -> Result<String> {
+        self.for_url(&target.url).publish(target, title, body).await
+    }
+}
+
+pub(crate) fn http_client() -> reqwest::Client {
+    reqwest::Client::builder()
+        .user_agent(concat!("ai-code-agent/", env!("CARGO_PKG_VERSION")))
+        .timeout(FORGE_TIMEOUT)
+        .build()
+        .unwrap_or_default()
+}
+
+// The host of an http(s) URL, lowercased.
+pub(crate) fn host(url: &str) -> Option<String> {
+    let rest = url.trim().strip_prefix("https://").or_else(|| url.trim().strip_prefix("http://"))?;
+    let host = rest.split(['/', '?', '#']).next()?;
+    (!host.is_empty()).then(|| host.to_ascii_lowercase())
+}
+
+// The path segments of an http(s) URL, without any query or fragment.
+pub(crate) fn path_segments(url: &str) -> Option<Vec<&str>> {
+    let rest = url.trim().strip_prefix("https://").or_else(|| url.trim().strip_prefix("http://"))?;
+    let (_, path) = rest.split_once('/')?;
+    let path = path.split(['#', '?']).next().unwrap_or_default().trim_end_matches('/');
+    Some(path.split('/').filter(|s| !s.is_empty()).collect())
+}
+
+// Keeps `body` within the forge's limit of `max_chars`, cutting at a line break.
+pub(crate) fn truncate_body(body: &str, max_chars: usize, forge: &str) -> String {
+    if body.chars().count() <= max_chars {
+        return body.to_string();
+    }
+    let note = format!("\n\n*Truncated: the full result is longer than {forge} allows.*");
+    let keep: String = body.chars().take(max_chars - note.len()).collect();
+    let keep = keep.rfind('\n').map_or(keep.as_str(), |end| &keep[..end]);
+    format!("{keep}{note}")
+}
@@ -0,0 +1,257 @@
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+// Limits for the files a long-running server leaves behind. Everything else
+// under AGENT_DATA_DIR (plans, the failed request queue, the semantic cache)
+// is already capped by entry count.
+#[derive(Clone)]
+pub struct GcConfig {
+    // Embedding indexes and retrieval tuning.
+    pub cache_dir: PathBuf,
+    // Extracted archives, mirrors, clones, and commit worktrees.
+    pub workspace_dir: PathBuf,
+    // Oldest cache files are evicted above this (MAX_CACHE_BYTES); None
+    // leaves the cache unbounded.
+    pub max_cache_bytes: Option<u64>,
+    // Workspaces are removed when their call ends; one older than this
+    // (MAX_CLONE_AGE_SECS) was left by a crash. Must outlast the longest call.
+    pub max_clone_age: Duration,
+    // codebase_viewer staging files older than this belong to killed runs.
+    pub max_temp_age: Duration,
+    // How often the background pass runs; None (GC_INTERVAL_SECS=0) only
+    // collects at startup and on `gc` calls.
+    pub interval: Option<Duration>,
+}
+
+#[derive(Default)]
+pub struct Freed {
+    pub entries: usize,
+    pub bytes: u64,
+}
+
+impl Freed {
+    fn add(&mut self, bytes: u64) {
+        self.entries += 1;
+        self.bytes += bytes;
+    }
+}
+
+pub struct Report {
+    pub dry_run: bool,
+    pub cache: Freed,
+    // Size of the cache after collection.
+    pub cache_bytes: u64,
+    pub workspaces: Freed,
+    pub temp_files: Freed,
+}
+
+impl Report {
+    pub fn is_empty(&self) -> bool {
+        self.cache.entries + self.workspaces.entries + self.temp_files.entries == 0
+    }
+
+    pub fn summary(&self) -> String {
+        format!(
+            "{} {} cache files ({}), {} workspaces ({}), and {} temp files ({})",
+            if self.dry_run { "Would remove" } else { "Removed" },
+            self.cache.entries,
+            human_bytes(self.cache.bytes),
+            self.workspaces.entries,
+            human_bytes(self.workspaces.bytes),
+            self.temp_files.entries,
+            human_bytes(self.temp_files.bytes)
+        )
+    }
+
+    pub fn render(&self, config: &GcConfig) -> String {
+        let mut output = String::from(if self.dry_run { "## Garbage Collection (dry run)\n\n" } else { "## Garbage Collection\n\n" });
+        output.push_str(&format!(
+            "| Store | Limit | {} |\n|---|---|---|---|\n",
+            if self.dry_run { "Would remove | Would free" } else { "Removed | Freed" }
+        ));
+        let cache_limit = config.max_cache_bytes.map(human_bytes).unwrap_or_else(|| "unbounded".to_string());
+        let rows = [
+            ("Embedding cache", format!("{cache_limit} total"), &self.cache),
+            ("Workspaces", format!("{}s old", config.max_clone_age.as_secs()), &self.workspaces),
+            ("codebase_viewer temp files", format!("{}s old", config.max_temp_age.as_secs()), &self.temp_files),
+        ];
+        for (store, limit, freed) in rows {
+            output.push_str(&format!("| {store} | {limit} | {} | {} |\n", freed.entries, human_bytes(freed.bytes)));
+        }
+        output.push_str(&format!(
+            "\nThe embedding cache in `{}` {} {} after collection.\n",
+            config.cache_dir.display(),
+            if self.dry_run { "would hold" } else { "holds" },
+            human_bytes(self.cache_bytes)
+        ));
+        output
+    }
+}
+
+// One pass over every store. With `dry_run`, reports what would be removed
+// without deleting anything. Blocking; call from `spawn_blocking` in async code.
+pub fn collect(config: &GcConfig, dry_run: bool) -> Report {
+    let (cache, cache_bytes) = evict_cache(config, dry_run);
+    Report {
+        dry_run,
+        cache,
+        cache_bytes,
+        workspaces: remove_old_workspaces(config, dry_run),
+        temp_files: remove_orphaned_temp_files(config.max_temp_age, dry_run),
+    }
+}
+
+// Collects at startup, then every `interval`.
+pub fn spawn(config: GcConfig) {
+    tokio::spawn(async move {
+        let mut ticks = config.interval.map(tokio::time::interval);
+        loop {
+            // The first tick completes immediately.
+            if let Some(ticks) = ticks.as_mut() {
+                ticks.tick().await;
+            }
+            let pass = config.clone();
+            match tokio::task::spawn_blocking(move || collect(&pass, false)).await {
+                Ok(report) if !report.is_empty() => tracing::info!("Garbage collection: {}", report.summary()),
+                Ok(_) => {}
+                Err(e) => tracing::warn!("Garbage collection panicked: {e}"),
+            }
+            if ticks.is_none() {
+                return;
+            }
+        }
+    });
+}
+
+// Removes the least recently written files until the cache fits. An evicted
+// index is rebuilt on the next call that retrieves from its codebase.
+fn evict_cache(config: &GcConfig, dry_run: bool) -> (Freed, u64) {
+    let mut freed = Freed::default();
+    let mut files: Vec<(SystemTime, u64, PathBuf)> = std::fs::read_dir(&config.cache_dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter_map(|entry| {
+            let metadata = entry.metadata().ok().filter(|m| m.is_file())?;
+            Some((metadata.modified().ok()?, metadata.len(), entry.path()))
+        })
+        .collect();
+    let mut total: u64 = files.iter().map(|(_, size, _)| size).sum();
+    let Some(max_bytes) = config.max_cache_bytes else {
+        return (freed, total);
+    };
+    files.sort();
+    for (_, size, path) in files {
+        if total <= max_bytes {
+            break;
+        }
+        if !dry_run {
+            if let Err(e) = std::fs::remove_file(&path) {
+                tracing::warn!("Failed to evict '{}': {e}", path.display());
+                continue;
+            }
+        }
+        total -= size;
+        freed.add(size);
+    }
+    (freed, total)
+}
+
+fn remove_old_workspaces(config: &GcConfig, dry_run: bool) -> Freed {
+    let mut freed = Freed::default();
+    let Ok(entries) = std::fs::read_dir(&config.workspace_dir) else {
+        return freed;
+    };
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let id = ["archive-", "mirror-", "remote-", "commit-"].iter().find_map(|prefix| name.strip_prefix(prefix));
+        let is_dir = entry.file_type().is_ok_and(|t| t.is_dir());
+        if !is_dir || id.is_none_or(|id| uuid::Uuid::parse_str(id).is_err()) || !is_older(&entry.path(), config.max_clone_age) {
+            continue;
+        }
+        let path = entry.path();
+        let size = disk_usage(&path);
+        if !dry_run {
+            // A worktree's repository keeps an entry for it until pruned.
+            let git_dir = name.starts_with("commit-").then(|| worktree_common_dir(&path)).flatten();
+            if let Err(e) = std::fs::remove_dir_all(&path) {
+                tracing::warn!("Failed to remove stale workspace '{}': {e}", path.display());
+                continue;
+            }
+            if let Some(git_dir) = git_dir {
+                let _ = std::process::Command::new("git").arg("--git-dir").arg(git_dir).args(["worktree", "prune"]).status();
+            }
+        }
+        freed.add(size);
+    }
+    freed
+}
+
+// The repository's `.git` directory, from the worktree's `gitdir:` file
+// (`<repo>/.git/worktrees/<name>`).
+fn worktree_common_dir(worktree: &Path) -> Option<PathBuf> {
+    let contents = std::fs::read_to_string(worktree.join(".git")).ok()?;
+    let git_dir = PathBuf::from(contents.trim().strip_prefix("gitdir:")?.trim());
+    Some(git_dir.parent()?.parent()?.to_path_buf())
+}
+
+// Removes staging directories and report files left in the temp directory by
+// runs that crashed or were killed before cleaning up. Only entries older
+// than `max_age` are touched, so a second server's in-flight runs survive.
+fn remove_orphaned_temp_files(max_age: Duration, dry_run: bool) -> Freed {
+    let mut freed = Freed::default();
+    let Ok(entries) = std::fs::read_dir(std::env::temp_dir()) else {
+        return freed;
+    };
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let id = name
+            .strip_prefix("report-src-")
+            .or_else(|| name.strip_prefix("report-").and_then(|n| n.strip_suffix(".md")));
+        if id.is_none_or(|id| uuid::Uuid::parse_str(id).is_err()) || !is_older(&entry.path(), max_age) {
+            continue;
+        }
+        let path = entry.path();
+        let size = disk_usage(&path);
+        if !dry_run {
+            let result = if path.is_dir() { std::fs::remove_dir_all(&path) } else { std::fs::remove_file(&path) };
+            if let Err(e) = result {
+                tracing::warn!("Failed to remove orphaned '{}': {e}", path.display());
+                continue;
+            }
+        }
+        freed.add(size);
+    }
+    freed
+}
+
+fn is_older(path: &Path, max_age: Duration) -> bool {
+    std::fs::metadata(path)
+        .and_then(|m| m.modified())
+        .is_ok_and(|modified| modified.elapsed().unwrap_or_default() > max_age)
+}
+
+// Total size of the files under `path`, not following symlinks.
+pub(crate) fn disk_usage(path: &Path) -> u64 {
+    let Ok(metadata) = std::fs::symlink_metadata(path) else {
+        return 0;
+    };
+    if !metadata.is_dir() {
+        return metadata.len();
+    }
+    std::fs::read_dir(path)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|entry| disk_usage(&entry.path()))
+        .sum()
+}
+
+fn human_bytes(bytes: u64) -> String {
+    match bytes {
+        0..1024 => format!("{bytes} B"),
+        1024..1_048_576 => format!("{:.1} KiB", bytes as f64 / 1024.0),
+        1_048_576..1_073_741_824 => format!("{:.1} MiB", bytes as f64 / 1_048_576.0),
+        _ => format!("{:.1} GiB", bytes as f64 / 1_073_741_824.0),
+    }
+}
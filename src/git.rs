@@ -0,0 +1,227 @@
+use crate::files;
+use crate::process::{self, ProcessError, ProcessMetrics};
+use anyhow::{bail, Context, Result};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::process::Command;
+
+const REMOTE_PREFIXES: &[&str] = &["https://", "http://", "ssh://", "git://", "git@"];
+
+// Splits a remote repository URL such as `https://github.com/org/repo#branch`
+// into the clone URL and the optional branch or tag after `#`. Returns `None`
+// for local paths.
+pub fn parse_remote(directory: &str) -> Option<(&str, Option<&str>)> {
+    if !REMOTE_PREFIXES.iter().any(|prefix| directory.starts_with(prefix)) {
+        return None;
+    }
+    match directory.split_once('#') {
+        Some((url, reference)) if !reference.is_empty() => Some((url, Some(reference))),
+        Some((url, _)) => Some((url, None)),
+        None => Some((directory, None)),
+    }
+}
+
+// Clones only the tip of `reference` (or the default branch). Credential
+// prompts are disabled so a private repository fails instead of hanging, and
+// git and its transport helpers are killed after `timeout`.
+pub async fn shallow_clone(url: &str, reference: Option<&str>, dest: &Path, timeout: Duration, metrics: &ProcessMetrics) -> Result<()> {
+    if reference.is_some_and(|r| r.starts_with('-')) {
+        bail!("Invalid branch or tag '{}'", reference.unwrap_or_default());
+    }
+    let mut command = Command::new("git");
+    command
+        .env("GIT_TERMINAL_PROMPT", "0")
+        .args(["clone", "--depth", "1", "--single-branch", "--no-tags"]);
+    if let Some(reference) = reference {
+        command.args(["--branch", reference]);
+    }
+    command.arg("--").arg(url).arg(dest);
+    let output = match process::run("git", command, timeout, metrics).await {
+        Ok(output) => output,
+        Err(ProcessError::TimedOut { .. }) => bail!("git clone of '{url}' did not finish within {}s (CLONE_TIMEOUT_SECS)", timeout.as_secs()),
+        Err(ProcessError::Spawn(e)) => return Err(e),
+    };
+    if !output.status.success() {
+        bail!("git clone of '{url}' failed: {}", String::from_utf8_lossy(&output.stderr).trim());
+    }
+    Ok(())
+}
+
+pub async fn run(dir: &Path, args: &[&str]) -> Result<String> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .output()
+        .await
+        .context("Failed to run git")?;
+    if !output.status.success() {
+        bail!("git {} failed: {}", args.join(" "), String::from_utf8_lossy(&output.stderr).trim());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+// Number of commits touching each file in the last `since_days`, keyed by
+// path relative to `dir`.
+pub async fn churn(dir: &Path, since_days: u32) -> Result<HashMap<PathBuf, usize>> {
+    let since = format!("--since={since_days} days ago");
+    let log = run(dir, &["log", &since, "--relative", "--name-only", "--format=", "--", "."]).await?;
+
+    let mut counts = HashMap::new();
+    for line in log.lines().map(str::trim).filter(|l| !l.is_empty()) {
+        *counts.entry(PathBuf::from(line)).or_insert(0) += 1;
+    }
+    Ok(counts)
+}
+
+// The commits and diff of a revision range, limited to `dir`.
+pub struct RangeChanges {
+    // One entry per commit, oldest first: short hash, subject, and body.
+    pub log: String,
+    pub stat: String,
+    pub diff: String,
+    // Files whose diff was left out to stay within the size limit.
+    pub omitted: Vec<String>,
+}
+
+// Gathers the changes in `range` (`a..b`, `a...b`, or a single revision,
+// meaning everything since it). Whole file diffs are kept until the diff
+// reaches `max_diff_chars`; the rest are listed in `omitted`.
+pub async fn range_changes(dir: &Path, range: &str, max_diff_chars: usize) -> Result<RangeChanges> {
+    let range = range.trim();
+    if range.is_empty() || range.starts_with('-') {
+        bail!("Invalid revision range '{range}'");
+    }
+    let range = if range.contains("..") { range.to_string() } else { format!("{range}..HEAD") };
+
+    let log = run(dir, &["log", "--reverse", "--no-merges", "--format=%h %s%n%b%x00", &range, "--", "."]).await?;
+    let log: Vec<String> = log
+        .split('\0')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| entry.lines().map(str::trim_end).collect::<Vec<_>>().join("\n"))
+        .collect();
+    let stat = run(dir, &["diff", "--relative", "--stat", &range, "--", "."]).await?;
+    if log.is_empty() && stat.is_empty() {
+        bail!("No changes in '{range}'");
+    }
+    let full = run(dir, &["diff", "--relative", "--no-color", "--no-ext-diff", &range, "--", "."]).await?;
+
+    let mut diff = String::new();
+    let mut omitted = Vec::new();
+    for file in split_file_diffs(&full) {
+        if diff.len() + file.len() <= max_diff_chars {
+            diff.push_str(file);
+            diff.push('\n');
+        } else {
+            let path = file.lines().next().and_then(|header| header.rsplit(" b/").next()).unwrap_or("(unknown)");
+            omitted.push(path.to_string());
+        }
+    }
+    Ok(RangeChanges { log: log.join("\n\n"), stat, diff: diff.trim_end().to_string(), omitted })
+}
+
+// Splits a unified diff at each `diff --git` header.
+fn split_file_diffs(diff: &str) -> Vec<&str> {
+    let mut starts: Vec<usize> = diff.match_indices("\ndiff --git ").map(|(i, _)| i + 1).collect();
+    if diff.starts_with("diff --git ") {
+        starts.insert(0, 0);
+    }
+    starts.iter().enumerate().map(|(i, &start)| diff[start..starts.get(i + 1).copied().unwrap_or(diff.len())].trim_end()).collect()
+}
+
+// Whether `dir` is inside a git work tree.
+pub async fn is_work_tree(dir: &Path) -> bool {
+    run(dir, &["rev-parse", "--is-inside-work-tree"]).await.is_ok_and(|out| out == "true")
+}
+
+// The recent history of `path` (relative to `dir`) for root-cause analysis:
+// its last `max_commits` commits with their diffs, cut at `max_diff_chars`,
+// then which of those commits last touched which lines, from `git blame`.
+pub async fn file_history(dir: &Path, path: &str, max_commits: usize, max_diff_chars: usize) -> Result<String> {
+    if path.starts_with('-') {
+        bail!("Invalid path '{path}'");
+    }
+    let count = format!("-n{max_commits}");
+    let log = run(
+        dir,
+        &["log", &count, "--follow", "-p", "--no-color", "--no-ext-diff", "--date=short", "--format=commit %h %ad %an%n    %s", "--", path],
+    )
+    .await?;
+    if log.is_empty() {
+        bail!("'{path}' has no git history");
+    }
+    let mut out = format!("### `{path}`\n\nRecent commits, newest first:\n\n");
+    out.push_str(&files::fenced("diff", &truncate_lines(&log, max_diff_chars)));
+
+    let blame = run(dir, &["blame", "--porcelain", "-w", "--", path]).await?;
+    let mut recent = Vec::new();
+    for line in log.lines() {
+        if let Some(sha) = line.strip_prefix("commit ").and_then(|rest| rest.split(' ').next()) {
+            recent.push(sha.to_string());
+        }
+    }
+    let ranges = blame_ranges(&blame, &recent);
+    if !ranges.is_empty() {
+        out.push_str("\n\nLines last changed by those commits (git blame):\n\n");
+        for (sha, lines) in ranges {
+            out.push_str(&format!("- `{sha}`: {lines}\n"));
+        }
+    }
+    Ok(out)
+}
+
+// The lines of the blamed file each of `commits` (abbreviated hashes, newest
+// first) last changed, as `lines 3-7, 12`. Commits that own no lines are left
+// out.
+fn blame_ranges(porcelain: &str, commits: &[String]) -> Vec<(String, String)> {
+    let mut owned: HashMap<&str, Vec<usize>> = HashMap::new();
+    for line in porcelain.lines().filter(|l| !l.starts_with('\t')) {
+        let mut fields = line.split(' ');
+        let (Some(sha), Some(_), Some(final_line)) = (fields.next(), fields.next(), fields.next()) else {
+            continue;
+        };
+        if sha.len() != 40 || !sha.bytes().all(|b| b.is_ascii_hexdigit()) {
+            continue;
+        }
+        if let (Some(commit), Ok(number)) = (commits.iter().find(|c| sha.starts_with(c.as_str())), final_line.parse()) {
+            owned.entry(commit.as_str()).or_default().push(number);
+        }
+    }
+
+    let mut ranges = Vec::new();
+    for commit in commits {
+        let Some(lines) = owned.get_mut(commit.as_str()) else {
+            continue;
+        };
+        lines.sort_unstable();
+        let mut spans: Vec<(usize, usize)> = Vec::new();
+        for &line in lines.iter() {
+            match spans.last_mut() {
+                Some((_, end)) if *end + 1 == line => *end = line,
+                _ => spans.push((line, line)),
+            }
+        }
+        let label = if lines.len() == 1 { "line" } else { "lines" };
+        let spans: Vec<String> = spans
+            .into_iter()
+            .map(|(start, end)| if start == end { start.to_string() } else { format!("{start}-{end}") })
+            .collect();
+        ranges.push((commit.clone(), format!("{label} {}", spans.join(", "))));
+    }
+    ranges
+}
+
+// `text` cut at a line break to at most `max_chars`, with a note when cut.
+fn truncate_lines(text: &str, max_chars: usize) -> String {
+    if text.len() <= max_chars {
+        return text.to_string();
+    }
+    let mut end = max_chars;
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    let kept = text[..end].rfind('\n').map_or(&text[..end], |cut| &text[..cut]);
+    format!("{kept}\n... (older changes truncated)")
+}
@@ -0,0 +1,230 @@
+use crate::forge::{self, Forge, Issue, PublishMode, PublishTarget, MAX_COMMENTS};
+use anyhow::{bail, Context, Result};
+use futures::future::BoxFuture;
+use reqwest::Method;
+use serde::Deserialize;
+use serde_json::json;
+
+// GitHub rejects comment and pull request bodies longer than this.
+const MAX_BODY_CHARS: usize = 65_536;
+
+#[derive(Deserialize)]
+struct User {
+    login: String,
+}
+
+#[derive(Deserialize)]
+struct Label {
+    name: String,
+}
+
+#[derive(Deserialize)]
+struct Comment {
+    user: Option<User>,
+    body: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct IssueResponse {
+    title: String,
+    body: Option<String>,
+    state: String,
+    user: Option<User>,
+    #[serde(default)]
+    labels: Vec<Label>,
+    // Present when the number belongs to a pull request.
+    pull_request: Option<serde_json::Value>,
+}
+
+#[derive(Deserialize)]
+struct Published {
+    html_url: String,
+}
+
+#[derive(Deserialize)]
+struct Repository {
+    default_branch: String,
+}
+
+// Reads issues from, and publishes results to, the GitHub REST API or a
+// GitHub Enterprise instance's.
+pub struct GitHub {
+    http: reqwest::Client,
+    api_base: String,
+    token: Option<String>,
+}
+
+impl GitHub {
+    pub fn new(api_base: String, token: Option<String>) -> Self {
+        Self { http: forge::http_client(), api_base: api_base.trim_end_matches('/').to_string(), token }
+    }
+
+    // `url` is https://<host>/<owner>/<repo>/issues/<n>.
+    async fn fetch_issue(&self, url: &str) -> Result<Issue> {
+        let (owner, repo, number) = match parse_url(url)? {
+            GitHubUrl { owner, repo, item: Some(("issues", number)) } => (owner, repo, number),
+            _ => bail!("'{url}' is not an issue URL; expected https://github.com/<owner>/<repo>/issues/<number>"),
+        };
+        let endpoint = format!("{}/repos/{owner}/{repo}/issues/{number}", self.api_base);
+
+        let issue: IssueResponse = self.send(Method::GET, &endpoint, None).await?;
+        if issue.pull_request.is_some() {
+            bail!("'{url}' is a pull request, not an issue");
+        }
+        let comments: Vec<Comment> = self.send(Method::GET, &format!("{endpoint}/comments?per_page={MAX_COMMENTS}"), None).await?;
+
+        Ok(Issue {
+            url: url.to_string(),
+            title: issue.title,
+            state: issue.state,
+            author: issue.user.map(|u| u.login),
+            labels: issue.labels.into_iter().map(|l| l.name).collect(),
+            body: issue.body.unwrap_or_default(),
+            comments: comments
+                .into_iter()
+                .filter_map(|c| {
+                    let body = c.body.filter(|b| !b.trim().is_empty())?;
+                    Some((c.user.map(|u| u.login).unwrap_or_else(|| "ghost".to_string()), body))
+                })
+                .collect(),
+        })
+    }
+
+    fn check(&self, target: &PublishTarget) -> Result<()> {
+        if self.token.is_none() {
+            bail!("publishing to GitHub needs GITHUB_TOKEN");
+        }
+        let item = parse_url(&target.url)?.item;
+        match (target.mode, item) {
+            (PublishMode::Comment, Some(_)) | (PublishMode::Description, Some(("pull", _))) => Ok(()),
+            (PublishMode::DraftPr, None) if target.head.is_some() => Ok(()),
+            (PublishMode::DraftPr, None) => bail!("'draft_pr' needs the 'head' branch with the changes"),
+            (PublishMode::Comment, _) => bail!("'comment' needs an issue or pull request URL, not '{}'", target.url),
+            (PublishMode::Description, _) => bail!("'description' needs a pull request URL, not '{}'", target.url),
+            (PublishMode::DraftPr, _) => bail!("'draft_pr' needs a repository URL, not '{}'", target.url),
+        }
+    }
+
+    // Returns the URL of the comment or pull request. `title` is the draft
+    // pull request's title unless the target names one.
+    async fn post(&self, target: &PublishTarget, title: &str, body: &str) -> Result<String> {
+        self.check(target)?;
+        let body = forge::truncate_body(body, MAX_BODY_CHARS, "GitHub");
+        let published: Published = match (target.mode, parse_url(&target.url)?) {
+            (PublishMode::Comment, GitHubUrl { owner, repo, item: Some((_, number)) }) => {
+                let url = format!("{}/repos/{owner}/{repo}/issues/{number}/comments", self.api_base);
+                self.send(Method::POST, &url, Some(json!({ "body": body }))).await?
+            }
+            (PublishMode::Description, GitHubUrl { owner, repo, item: Some(("pull", number)) }) => {
+                let url = format!("{}/repos/{owner}/{repo}/pulls/{number}", self.api_base);
+                self.send(Method::PATCH, &url, Some(json!({ "body": body }))).await?
+            }
+            (PublishMode::DraftPr, GitHubUrl { owner, repo, item: None }) => {
+                let head = target.head.as_deref().unwrap_or_default();
+                let base = match &target.base {
+                    Some(base) => base.clone(),
+                    None => {
+                        let url = format!("{}/repos/{owner}/{repo}", self.api_base);
+                        self.send::<Repository>(Method::GET, &url, None).await?.default_branch
+                    }
+                };
+                let title = target.title.as_deref().unwrap_or(title);
+                let url = format!("{}/repos/{owner}/{repo}/pulls", self.api_base);
+                let request = json!({ "title": title, "head": head, "base": base, "body": body, "draft": true });
+                self.send(Method::POST, &url, Some(request)).await?
+            }
+            // Ruled out by `check`.
+            _ => bail!("cannot publish to '{}' as {:?}", target.url, target.mode),
+        };
+        Ok(published.html_url)
+    }
+
+    async fn send<T: serde::de::DeserializeOwned>(&self, method: Method, url: &str, body: Option<serde_json::Value>) -> Result<T> {
+        let mut request = self
+            .http
+            .request(method, url)
+            .header(reqwest::header::ACCEPT, "application/vnd.github+json")
+            .header("X-GitHub-Api-Version", "2022-11-28");
+        if let Some(token) = &self.token {
+            request = request.bearer_auth(token);
+        }
+        if let Some(body) = &body {
+            request = request.json(body);
+        }
+        let response = request.send().await.with_context(|| format!("Failed to reach {url}"))?;
+        let status = response.status();
+        if status.is_success() {
+            return response.json().await.with_context(|| format!("Invalid response from {url}"));
+        }
+
+        // GitHub explains refusals (missing scopes, existing pull requests) in `message`.
+        let detail = response.json::<serde_json::Value>().await.ok();
+        let message = detail.as_ref().and_then(|d| d["message"].as_str()).unwrap_or("no details");
+        match status {
+            reqwest::StatusCode::NOT_FOUND => match self.token {
+                Some(_) => bail!("{url} was not found, or GITHUB_TOKEN cannot access it"),
+                None => bail!("{url} was not found; set GITHUB_TOKEN to read private repositories"),
+            },
+            reqwest::StatusCode::FORBIDDEN | reqwest::StatusCode::TOO_MANY_REQUESTS if self.token.is_none() => {
+                bail!("GitHub refused {url} ({status}: {message}); set GITHUB_TOKEN to raise the rate limit")
+            }
+            _ => {
+                // Validation failures (e.g. "No commits between main and feature") are listed in `errors`.
+                let errors: Vec<&str> = detail
+                    .as_ref()
+                    .and_then(|d| d["errors"].as_array())
+                    .map(|errors| errors.iter().filter_map(|e| e["message"].as_str()).collect())
+                    .unwrap_or_default();
+                if errors.is_empty() {
+                    bail!("GitHub refused {url} ({status}: {message})");
+                }
+                bail!("GitHub refused {url} ({status}: {message}): {}", errors.join("; "))
+            }
+        }
+    }
+}
+
+impl Forge for GitHub {
+    fn name(&self) -> &'static str {
+        "GitHub"
+    }
+
+    fn issue<'a>(&'a self, url: &'a str) -> BoxFuture<'a, Result<Issue>> {
+        Box::pin(self.fetch_issue(url))
+    }
+
+    fn check_target(&self, target: &PublishTarget) -> Result<()> {
+        self.check(target)
+    }
+
+    fn publish<'a>(&'a self, target: &'a PublishTarget, title: &'a str, body: &'a str) -> BoxFuture<'a, Result<String>> {
+        Box::pin(self.post(target, title, body))
+    }
+}
+
+// A repository, issue, or pull request URL.
+struct GitHubUrl {
+    owner: String,
+    repo: String,
+    // ("issues" or "pull", number); None for the repository itself.
+    item: Option<(&'static str, u64)>,
+}
+
+// Any host is accepted so GitHub Enterprise URLs work with GITHUB_API_URL.
+fn parse_url(url: &str) -> Result<GitHubUrl> {
+    let invalid = || format!("'{url}' is not a GitHub repository, issue, or pull request URL");
+    let parts = forge::path_segments(url).with_context(invalid)?;
+    let (owner, repo) = match parts.as_slice() {
+        [owner, repo, ..] if !owner.is_empty() && !repo.is_empty() => (owner.to_string(), repo.trim_end_matches(".git").to_string()),
+        _ => bail!(invalid()),
+    };
+    let item = match parts[2..] {
+        [] => None,
+        [kind @ ("issues" | "pull"), number] => {
+            let number = number.parse().with_context(|| format!("'{number}' in '{url}' is not an issue or pull request number"))?;
+            Some((if kind == "issues" { "issues" } else { "pull" }, number))
+        }
+        _ => bail!(invalid()),
+    };
+    Ok(GitHubUrl { owner, repo, item })
+}
@@ -0,0 +1,190 @@
+use crate::forge::{self, Forge, Issue, PublishMode, PublishTarget, MAX_COMMENTS};
+use anyhow::{bail, Context, Result};
+use futures::future::BoxFuture;
+use reqwest::Method;
+use serde::Deserialize;
+use serde_json::json;
+
+// GitLab rejects notes longer than this.
+const MAX_BODY_CHARS: usize = 1_000_000;
+
+#[derive(Deserialize)]
+struct User {
+    username: String,
+}
+
+#[derive(Deserialize)]
+struct Note {
+    id: u64,
+    author: Option<User>,
+    body: Option<String>,
+    // Generated notes ("changed the description", "added label"), not discussion.
+    #[serde(default)]
+    system: bool,
+}
+
+#[derive(Deserialize)]
+struct IssueResponse {
+    title: String,
+    description: Option<String>,
+    state: String,
+    author: Option<User>,
+    #[serde(default)]
+    labels: Vec<String>,
+}
+
+// Reads issues from, and comments on, gitlab.com or a self-managed GitLab
+// instance through its REST API (v4).
+pub struct GitLab {
+    http: reqwest::Client,
+    // Web URL of the instance; the API is under `/api/v4`.
+    base_url: String,
+    token: Option<String>,
+}
+
+impl GitLab {
+    pub fn new(base_url: String, token: Option<String>) -> Self {
+        Self { http: forge::http_client(), base_url: base_url.trim_end_matches('/').to_string(), token }
+    }
+
+    pub fn serves(&self, host: &str) -> bool {
+        host == "gitlab.com" || forge::host(&self.base_url).as_deref() == Some(host)
+    }
+
+    // `url` is https://<host>/<group>/<project>/-/issues/<n>.
+    async fn fetch_issue(&self, url: &str) -> Result<Issue> {
+        let (project, iid) = match parse_url(url)? {
+            GitLabUrl { project, item: Some(("issues", iid)) } => (project, iid),
+            _ => bail!("'{url}' is not an issue URL; expected https://gitlab.com/<group>/<project>/-/issues/<number>"),
+        };
+        let endpoint = format!("{}/issues/{iid}", self.project_api(&project));
+
+        let issue: IssueResponse = self.send(Method::GET, &endpoint, None).await?;
+        let notes: Vec<Note> = self.send(Method::GET, &format!("{endpoint}/notes?sort=asc&per_page={MAX_COMMENTS}"), None).await?;
+
+        Ok(Issue {
+            url: url.to_string(),
+            title: issue.title,
+            state: issue.state,
+            author: issue.author.map(|u| u.username),
+            labels: issue.labels,
+            body: issue.description.unwrap_or_default(),
+            comments: notes
+                .into_iter()
+                .filter(|n| !n.system)
+                .filter_map(|n| {
+                    let body = n.body.filter(|b| !b.trim().is_empty())?;
+                    Some((n.author.map(|u| u.username).unwrap_or_else(|| "ghost".to_string()), body))
+                })
+                .collect(),
+        })
+    }
+
+    fn check(&self, target: &PublishTarget) -> Result<()> {
+        if self.token.is_none() {
+            bail!("publishing to GitLab needs GITLAB_TOKEN");
+        }
+        match (target.mode, parse_url(&target.url)?.item) {
+            (PublishMode::Comment, Some(_)) => Ok(()),
+            (PublishMode::Comment, None) => bail!("'comment' needs an issue or merge request URL, not '{}'", target.url),
+            _ => bail!("GitLab results can only be published as a 'comment'; 'description' and 'draft_pr' are GitHub only"),
+        }
+    }
+
+    // Returns the URL of the new note.
+    async fn post(&self, target: &PublishTarget, body: &str) -> Result<String> {
+        self.check(target)?;
+        let GitLabUrl { project, item: Some((kind, iid)) } = parse_url(&target.url)? else {
+            bail!("'{}' is not an issue or merge request URL", target.url);
+        };
+        let body = forge::truncate_body(body, MAX_BODY_CHARS, "GitLab");
+        let url = format!("{}/{kind}/{iid}/notes", self.project_api(&project));
+        let note: Note = self.send(Method::POST, &url, Some(json!({ "body": body }))).await?;
+        // Notes have no web URL of their own; they are anchors on the page.
+        let page = target.url.split(['#', '?']).next().unwrap_or_default().trim_end_matches('/');
+        Ok(format!("{page}#note_{}", note.id))
+    }
+
+    // Projects are addressed by their URL-encoded path, e.g. `group%2Fproject`.
+    fn project_api(&self, project: &str) -> String {
+        format!("{}/api/v4/projects/{}", self.base_url, project.replace('/', "%2F"))
+    }
+
+    async fn send<T: serde::de::DeserializeOwned>(&self, method: Method, url: &str, body: Option<serde_json::Value>) -> Result<T> {
+        let mut request = self.http.request(method, url);
+        if let Some(token) = &self.token {
+            request = request.header("PRIVATE-TOKEN", token);
+        }
+        if let Some(body) = &body {
+            request = request.json(body);
+        }
+        let response = request.send().await.with_context(|| format!("Failed to reach {url}"))?;
+        let status = response.status();
+        if status.is_success() {
+            return response.json().await.with_context(|| format!("Invalid response from {url}"));
+        }
+
+        // GitLab explains refusals in `message` (a string, or field errors) or `error`.
+        let detail = response.json::<serde_json::Value>().await.ok();
+        let message = detail
+            .as_ref()
+            .and_then(|d| match &d["message"] {
+                serde_json::Value::String(message) => Some(message.clone()),
+                serde_json::Value::Null => d["error"].as_str().map(str::to_string),
+                other => Some(other.to_string()),
+            })
+            .unwrap_or_else(|| "no details".to_string());
+        match (status, &self.token) {
+            (reqwest::StatusCode::NOT_FOUND, Some(_)) => bail!("{url} was not found, or GITLAB_TOKEN cannot access it"),
+            (reqwest::StatusCode::NOT_FOUND, None) => bail!("{url} was not found; set GITLAB_TOKEN to read private projects"),
+            _ => bail!("GitLab refused {url} ({status}: {message})"),
+        }
+    }
+}
+
+impl Forge for GitLab {
+    fn name(&self) -> &'static str {
+        "GitLab"
+    }
+
+    fn issue<'a>(&'a self, url: &'a str) -> BoxFuture<'a, Result<Issue>> {
+        Box::pin(self.fetch_issue(url))
+    }
+
+    fn check_target(&self, target: &PublishTarget) -> Result<()> {
+        self.check(target)
+    }
+
+    fn publish<'a>(&'a self, target: &'a PublishTarget, _title: &'a str, body: &'a str) -> BoxFuture<'a, Result<String>> {
+        Box::pin(self.post(target, body))
+    }
+}
+
+// A project, issue, or merge request URL.
+struct GitLabUrl {
+    // Full path with any subgroups, e.g. `group/subgroup/project`.
+    project: String,
+    // ("issues" or "merge_requests", iid); None for the project itself.
+    item: Option<(&'static str, u64)>,
+}
+
+fn parse_url(url: &str) -> Result<GitLabUrl> {
+    let invalid = || format!("'{url}' is not a GitLab project, issue, or merge request URL");
+    let parts = forge::path_segments(url).with_context(invalid)?;
+    let (project, rest) = match parts.iter().position(|p| *p == "-") {
+        Some(dash) => (&parts[..dash], &parts[dash + 1..]),
+        None => (&parts[..], &[][..]),
+    };
+    if project.len() < 2 {
+        bail!(invalid());
+    }
+    let item = match rest {
+        [] => None,
+        [kind @ ("issues" | "merge_requests"), number, ..] => {
+            let number = number.parse().with_context(|| format!("'{number}' in '{url}' is not an issue or merge request number"))?;
+            Some((if *kind == "issues" { "issues" } else { "merge_requests" }, number))
+        }
+        _ => bail!(invalid()),
+    };
+    Ok(GitLabUrl { project: project.join("/").trim_end_matches(".git").to_string(), item })
+}
@@ -0,0 +1,119 @@
+use crate::config::Config;
+use crate::external;
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+
+// Whether the server can serve a planning call, checked without making one:
+// codebase_viewer generates a report, and every API key authenticates.
+#[derive(Clone, Serialize, JsonSchema)]
+pub struct HealthReport {
+    pub healthy: bool,
+    pub version: String,
+    // "gemini", or "mock" with LLM_PROVIDER=mock.
+    pub provider: String,
+    pub model: String,
+    pub api_base: String,
+    // From `codebase_viewer --version`, if the binary reports one.
+    pub viewer_version: Option<String>,
+    pub checks: Vec<Check>,
+}
+
+#[derive(Clone, Serialize, JsonSchema)]
+pub struct Check {
+    // "codebase_viewer" or the masked API key.
+    pub name: String,
+    pub ok: bool,
+    pub detail: String,
+    pub elapsed_ms: u64,
+}
+
+pub async fn check(config: &Config) -> HealthReport {
+    let client = &config.gemini_client;
+    let viewer = async {
+        let started = Instant::now();
+        let result = external::check_viewer(&config.codebase_viewer_path, config.viewer_timeout, &config.process_metrics).await;
+        Check {
+            name: "codebase_viewer".to_string(),
+            ok: result.is_ok(),
+            detail: match result {
+                Ok(()) => format!("generated a report with {}", config.codebase_viewer_path.display()),
+                Err(e) => format!("{e:#}"),
+            },
+            elapsed_ms: started.elapsed().as_millis() as u64,
+        }
+    };
+    let (viewer, viewer_version, keys) = tokio::join!(
+        viewer,
+        external::viewer_version(&config.codebase_viewer_path, &config.process_metrics),
+        client.check_keys()
+    );
+
+    let mut checks = vec![viewer];
+    for (key, result) in keys {
+        checks.push(match result {
+            Ok(elapsed) => Check { name: key, ok: true, detail: "authenticated".to_string(), elapsed_ms: elapsed.as_millis() as u64 },
+            Err(e) => Check { name: key, ok: false, detail: e.to_string(), elapsed_ms: 0 },
+        });
+    }
+    HealthReport {
+        healthy: checks.iter().all(|c| c.ok),
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        provider: if client.mock().is_some() { "mock" } else { "gemini" }.to_string(),
+        model: client.model().to_string(),
+        api_base: client.api_base().to_string(),
+        viewer_version,
+        checks,
+    }
+}
+
+// The last report, for `/healthz`: load balancers and orchestrators probe it
+// every few seconds, and each check runs codebase_viewer and lists the models
+// with every key. Probes arriving while a check runs wait for its report.
+#[derive(Default)]
+pub struct HealthCache {
+    report: Mutex<Option<(Instant, HealthReport)>>,
+}
+
+impl HealthCache {
+    // The last report if it is younger than `ttl`, otherwise a new one.
+    pub async fn get(&self, config: &Config, ttl: Duration) -> HealthReport {
+        let mut cached = self.report.lock().await;
+        if let Some((checked_at, report)) = cached.as_ref() {
+            if checked_at.elapsed() < ttl {
+                return report.clone();
+            }
+        }
+        let report = check(config).await;
+        *cached = Some((Instant::now(), report.clone()));
+        report
+    }
+}
+
+impl HealthReport {
+    pub fn render(&self) -> String {
+        let mut output = format!(
+            "## Health: {}\n\nai-code-agent {} with `{}` ({}, {})",
+            if self.healthy { "OK" } else { "FAILING" },
+            self.version,
+            self.model,
+            self.provider,
+            self.api_base
+        );
+        if let Some(version) = &self.viewer_version {
+            output.push_str(&format!("; {version}"));
+        }
+        output.push_str("\n\n| Check | Status | Time | Detail |\n|---|---|---|---|\n");
+        for check in &self.checks {
+            output.push_str(&format!(
+                "| {} | {} | {}ms | {} |\n",
+                check.name,
+                if check.ok { "ok" } else { "failed" },
+                check.elapsed_ms,
+                check.detail.replace(['\n', '|'], " ")
+            ));
+        }
+        output
+    }
+}
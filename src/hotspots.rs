@@ -0,0 +1,99 @@
+use crate::external;
+use crate::files::{self, SourceFile};
+use crate::git;
+use anyhow::Result;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+const CODE_EXTENSIONS: &[&str] = &[
+    "rs", "go", "py", "rb", "java", "kt", "kts", "scala", "swift", "c", "h", "cc", "cpp", "hpp", "cs", "js", "jsx",
+    "ts", "tsx", "mjs", "cjs", "php", "ex", "exs", "erl", "clj", "dart", "lua", "sh", "vue", "svelte", "sql",
+];
+
+const BRANCH_TOKENS: &[&str] = &[
+    "if ", "if(", "else", "for ", "for(", "while", "match ", "switch", "case ", "catch", "except", "elif", "&&", "||",
+    "?", "when ",
+];
+
+pub struct FileMetrics {
+    pub path: PathBuf,
+    pub lines: usize,
+    pub branches: usize,
+    pub max_indent: usize,
+    pub commits: usize,
+}
+
+impl FileMetrics {
+    // Churn x complexity: files that are both complicated and frequently
+    // changed are where debt costs the most.
+    pub fn score(&self) -> usize {
+        self.commits.max(1) * (self.branches + self.lines / 25 + self.max_indent)
+    }
+}
+
+// Metrics for every code file under `root`, highest score first. Churn is
+// counted as zero when `root` is not a git checkout (e.g. an archive).
+pub async fn collect(root: &Path, since_days: u32) -> Result<Vec<FileMetrics>> {
+    let source_files = external::collect_files(root).await?;
+    let churn = match git::churn(root, since_days).await {
+        Ok(churn) => churn,
+        Err(e) => {
+            tracing::warn!("No git history for '{}'; ranking by complexity only: {e:#}", root.display());
+            HashMap::new()
+        }
+    };
+    Ok(measure(&source_files, &churn))
+}
+
+fn measure(source_files: &[SourceFile], churn: &HashMap<PathBuf, usize>) -> Vec<FileMetrics> {
+    let mut metrics: Vec<FileMetrics> = source_files
+        .iter()
+        .filter(|f| files::has_extension(&f.path, CODE_EXTENSIONS))
+        .map(|f| {
+            let code_lines: Vec<&str> = f.content.lines().filter(|l| !l.trim().is_empty()).collect();
+            FileMetrics {
+                path: f.path.clone(),
+                lines: code_lines.len(),
+                branches: code_lines
+                    .iter()
+                    .map(|l| BRANCH_TOKENS.iter().map(|t| l.matches(t).count()).sum::<usize>())
+                    .sum(),
+                max_indent: code_lines.iter().map(|l| indent_level(l)).max().unwrap_or(0),
+                commits: churn.get(&f.path).copied().unwrap_or(0),
+            }
+        })
+        .collect();
+
+    metrics.sort_by(|a, b| b.score().cmp(&a.score()).then_with(|| a.path.cmp(&b.path)));
+    metrics
+}
+
+pub fn render_table(metrics: &[FileMetrics], limit: usize) -> String {
+    let mut table = String::from("| Rank | File | Commits | Lines | Branch points | Max nesting | Score |\n|---|---|---|---|---|---|---|\n");
+    for (rank, m) in metrics.iter().take(limit).enumerate() {
+        table.push_str(&format!(
+            "| {} | `{}` | {} | {} | {} | {} | {} |\n",
+            rank + 1,
+            m.path.display(),
+            m.commits,
+            m.lines,
+            m.branches,
+            m.max_indent,
+            m.score()
+        ));
+    }
+    table
+}
+
+// Nesting depth estimated from leading whitespace (tabs or 4-space units).
+fn indent_level(line: &str) -> usize {
+    let mut width = 0;
+    for c in line.chars() {
+        match c {
+            ' ' => width += 1,
+            '\t' => width += 4,
+            _ => break,
+        }
+    }
+    width / 4
+}
@@ -0,0 +1,349 @@
+use crate::files::SourceFile;
+use std::collections::{HashMap, HashSet};
+use std::path::{Component, Path, PathBuf};
+
+// Imported files added to a targeted context, however many the targeted
+// files import.
+pub const MAX_IMPORTED_FILES: usize = 20;
+
+// Module path segments tried for a Rust `use` path; later ones are items.
+const MAX_RUST_SEGMENTS: usize = 3;
+
+const JS_EXTENSIONS: &[&str] = &["ts", "tsx", "js", "jsx", "mjs", "cjs", "vue", "svelte"];
+
+// A file pulled into the context because a targeted file imports it.
+pub struct Imported {
+    pub file: SourceFile,
+    // The file that imports it.
+    pub by: PathBuf,
+}
+
+// The files that `targets` import, then the files those import, and so on
+// for `depth` hops, nearest first and at most MAX_IMPORTED_FILES. `paths`
+// are the codebase's files, relative to its root, and `read` returns a
+// file's content by relative path. Imports are read with lightweight,
+// per-language line parsing and resolved only to files in `paths`, so
+// standard library and third-party imports are ignored.
+pub fn follow(paths: &HashSet<PathBuf>, read: impl Fn(&Path) -> Option<String>, targets: &[&SourceFile], depth: usize) -> Vec<Imported> {
+    let go_module = read(Path::new("go.mod")).and_then(|manifest| {
+        manifest.lines().find_map(|line| line.trim().strip_prefix("module ").map(|m| m.trim().trim_matches('"').to_string()))
+    });
+    let resolver = Resolver { paths, go_module };
+
+    let mut seen: HashSet<PathBuf> = targets.iter().map(|file| file.path.clone()).collect();
+    let mut imported = Vec::new();
+    let mut frontier: Vec<(PathBuf, String)> = targets.iter().map(|file| (file.path.clone(), file.content.clone())).collect();
+    for _ in 0..depth {
+        let mut next = Vec::new();
+        for (path, content) in &frontier {
+            for dependency in resolver.imports(path, content) {
+                if imported.len() == MAX_IMPORTED_FILES {
+                    return imported;
+                }
+                if !seen.insert(dependency.clone()) {
+                    continue;
+                }
+                let Some(content) = read(&dependency) else {
+                    continue;
+                };
+                next.push((dependency.clone(), content.clone()));
+                imported.push(Imported { file: SourceFile { path: dependency, content }, by: path.clone() });
+            }
+        }
+        if next.is_empty() {
+            break;
+        }
+        frontier = next;
+    }
+    imported
+}
+
+// `follow` over files already read, such as a collected codebase.
+pub fn follow_loaded(files: &[SourceFile], targets: &[&SourceFile], depth: usize) -> Vec<Imported> {
+    if depth == 0 {
+        return Vec::new();
+    }
+    let by_path: HashMap<&Path, &str> = files.iter().map(|file| (file.path.as_path(), file.content.as_str())).collect();
+    let paths: HashSet<PathBuf> = files.iter().map(|file| file.path.clone()).collect();
+    follow(&paths, |path| by_path.get(path).map(|content| content.to_string()), targets, depth)
+}
+
+// The list of imported files for the top of a targeted context.
+pub fn render_list(imported: &[Imported]) -> String {
+    if imported.is_empty() {
+        return String::new();
+    }
+    let mut list = String::from("Also included, as files imported by the files above:\n");
+    for entry in imported {
+        list.push_str(&format!("- `{}` (imported by `{}`)\n", entry.file.path.display(), entry.by.display()));
+    }
+    list
+}
+
+struct Resolver<'a> {
+    paths: &'a HashSet<PathBuf>,
+    // The `module` line of a root go.mod, which prefixes the codebase's own
+    // package paths.
+    go_module: Option<String>,
+}
+
+impl Resolver<'_> {
+    // Files in the codebase that the file at `path` imports, in order.
+    fn imports(&self, path: &Path, content: &str) -> Vec<PathBuf> {
+        let extension = path.extension().and_then(|e| e.to_str()).unwrap_or_default().to_ascii_lowercase();
+        let mut found = Vec::new();
+        for line in content.lines().map(str::trim) {
+            let candidates = match extension.as_str() {
+                "rs" => self.rust(path, line),
+                "py" | "pyi" => self.python(path, line),
+                "go" => self.go(line),
+                "java" | "kt" => self.jvm(line),
+                "c" | "cc" | "cpp" | "cxx" | "h" | "hh" | "hpp" => self.include(path, line),
+                e if JS_EXTENSIONS.contains(&e) => self.javascript(path, line),
+                _ => Vec::new(),
+            };
+            for candidate in candidates {
+                if candidate != path && !found.contains(&candidate) {
+                    found.push(candidate);
+                }
+            }
+        }
+        found
+    }
+
+    fn existing(&self, candidates: impl IntoIterator<Item = PathBuf>) -> Option<PathBuf> {
+        candidates.into_iter().filter_map(|c| normalize(&c)).find(|c| self.paths.contains(c))
+    }
+
+    // `mod name;` and `use crate::`, `super::`, or `self::` paths.
+    fn rust(&self, path: &Path, line: &str) -> Vec<PathBuf> {
+        let line = strip_visibility(line);
+        let module_dir = rust_module_dir(path);
+        if let Some(name) = line.strip_prefix("mod ").and_then(|rest| rest.strip_suffix(';')) {
+            let name = name.trim();
+            return self.existing([module_dir.join(format!("{name}.rs")), module_dir.join(name).join("mod.rs")]).into_iter().collect();
+        }
+        let Some(used) = line.strip_prefix("use ") else {
+            return Vec::new();
+        };
+        let used = used.trim_end_matches(';');
+        let (prefix, group) = match used.split_once('{') {
+            Some((prefix, group)) => (prefix, Some(group.trim_end_matches('}'))),
+            None => (used, None),
+        };
+        let (base, rest) = if let Some(rest) = prefix.strip_prefix("crate::") {
+            (rust_crate_root(path, self.paths), rest)
+        } else if let Some(rest) = prefix.strip_prefix("super::") {
+            (module_dir.parent().map(Path::to_path_buf).unwrap_or_default(), rest)
+        } else if let Some(rest) = prefix.strip_prefix("self::") {
+            (module_dir, rest)
+        } else {
+            return Vec::new();
+        };
+        let rest = rest.trim_end_matches("::");
+        let mut paths: Vec<String> = match group {
+            // Only the group's top level: `crate::{a, b::c}`.
+            Some(group) if !group.contains('{') => {
+                group.split(',').map(|item| [rest, item.trim()].iter().filter(|s| !s.is_empty()).copied().collect::<Vec<_>>().join("::")).collect()
+            }
+            _ => vec![rest.to_string()],
+        };
+        paths.retain(|p| !p.is_empty());
+        paths.iter().filter_map(|used| self.rust_module(&base, used)).collect()
+    }
+
+    // The deepest file among `a.rs`, `a/mod.rs`, `a/b.rs`, ... for `a::b::...`.
+    fn rust_module(&self, base: &Path, used: &str) -> Option<PathBuf> {
+        let segments: Vec<&str> = used.split("::").map(|s| s.split_whitespace().next().unwrap_or_default()).take(MAX_RUST_SEGMENTS).collect();
+        let mut deepest = None;
+        let mut dir = base.to_path_buf();
+        for segment in segments {
+            if segment.is_empty() || segment == "self" || segment == "*" {
+                break;
+            }
+            match self.existing([dir.join(format!("{segment}.rs")), dir.join(segment).join("mod.rs")]) {
+                Some(found) => deepest = Some(found),
+                None => break,
+            }
+            dir = dir.join(segment);
+        }
+        deepest
+    }
+
+    // `import a.b`, `from a.b import c`, and relative `from .x import y`.
+    fn python(&self, path: &Path, line: &str) -> Vec<PathBuf> {
+        let (module, names) = if let Some(rest) = line.strip_prefix("from ") {
+            let Some((module, names)) = rest.split_once(" import ") else {
+                return Vec::new();
+            };
+            (module.trim(), names.trim_matches(|c: char| c == '(' || c == ')' || c.is_whitespace()))
+        } else if let Some(rest) = line.strip_prefix("import ") {
+            return rest.split(',').filter_map(|m| self.python_module(path, m.split_whitespace().next().unwrap_or_default())).collect();
+        } else {
+            return Vec::new();
+        };
+        let mut found: Vec<PathBuf> = self.python_module(path, module).into_iter().collect();
+        // `from package import module` imports the module's file.
+        for name in names.split(',').map(|n| n.split_whitespace().next().unwrap_or_default()).filter(|n| !n.is_empty() && *n != "*") {
+            let joined = if module.ends_with('.') { format!("{module}{name}") } else { format!("{module}.{name}") };
+            found.extend(self.python_module(path, &joined));
+        }
+        found
+    }
+
+    fn python_module(&self, path: &Path, module: &str) -> Option<PathBuf> {
+        let dots = module.chars().take_while(|&c| c == '.').count();
+        let relative = module[dots..].replace('.', "/");
+        let bases: Vec<PathBuf> = if dots > 0 {
+            let mut base = path.parent().map(Path::to_path_buf).unwrap_or_default();
+            for _ in 1..dots {
+                base = base.parent().map(Path::to_path_buf).unwrap_or_default();
+            }
+            vec![base]
+        } else {
+            vec![PathBuf::new(), PathBuf::from("src")]
+        };
+        bases.iter().find_map(|base| {
+            let target = base.join(&relative);
+            self.existing([target.with_extension("py"), target.join("__init__.py")])
+        })
+    }
+
+    // Relative specifiers in `import`, `export ... from`, `require`, and
+    // dynamic `import()`; package imports are ignored.
+    fn javascript(&self, path: &Path, line: &str) -> Vec<PathBuf> {
+        if !(line.starts_with("import") || line.starts_with("export") || line.contains("require(") || line.contains("import(")) {
+            return Vec::new();
+        }
+        let dir = path.parent().unwrap_or(Path::new(""));
+        quoted(line)
+            .into_iter()
+            .filter(|specifier| specifier.starts_with("./") || specifier.starts_with("../"))
+            .filter_map(|specifier| {
+                let target = dir.join(specifier);
+                let mut candidates = vec![target.clone()];
+                for extension in JS_EXTENSIONS.iter().chain(&["d.ts"]) {
+                    candidates.push(PathBuf::from(format!("{}.{extension}", target.display())));
+                    candidates.push(target.join(format!("index.{extension}")));
+                }
+                self.existing(candidates)
+            })
+            .collect()
+    }
+
+    // Packages under the codebase's own module path, as their non-test files.
+    fn go(&self, line: &str) -> Vec<PathBuf> {
+        let Some(module) = &self.go_module else {
+            return Vec::new();
+        };
+        let specifiers = quoted(line);
+        let Some(package) = specifiers.first().and_then(|s| s.strip_prefix(module.as_str())) else {
+            return Vec::new();
+        };
+        let dir = Path::new(package.trim_start_matches('/'));
+        let mut files: Vec<PathBuf> = self
+            .paths
+            .iter()
+            .filter(|p| p.parent() == Some(dir) && p.extension().is_some_and(|e| e == "go") && !p.to_string_lossy().ends_with("_test.go"))
+            .cloned()
+            .collect();
+        files.sort();
+        files
+    }
+
+    // `import a.b.C;` as the file whose path ends in `a/b/C.java` (or `.kt`).
+    fn jvm(&self, line: &str) -> Vec<PathBuf> {
+        let Some(class) = line.strip_prefix("import ").map(|rest| rest.trim_end_matches(';').trim()) else {
+            return Vec::new();
+        };
+        if class.starts_with("static ") || class.ends_with('*') {
+            return Vec::new();
+        }
+        let suffix = class.replace('.', "/");
+        let mut matches: Vec<PathBuf> = self
+            .paths
+            .iter()
+            .filter(|p| {
+                let p = p.to_string_lossy().replace('\\', "/");
+                [".java", ".kt"].iter().any(|e| p.ends_with(&format!("/{suffix}{e}")) || p == format!("{suffix}{e}"))
+            })
+            .cloned()
+            .collect();
+        matches.sort();
+        matches.truncate(1);
+        matches
+    }
+
+    // `#include "x.h"`, next to the file, at the root, or under `include/`.
+    fn include(&self, path: &Path, line: &str) -> Vec<PathBuf> {
+        let Some(rest) = line.strip_prefix("#include") else {
+            return Vec::new();
+        };
+        let Some(header) = quoted(rest).into_iter().next() else {
+            return Vec::new();
+        };
+        let dir = path.parent().unwrap_or(Path::new(""));
+        self.existing([dir.join(header), PathBuf::from(header), Path::new("include").join(header)]).into_iter().collect()
+    }
+}
+
+fn strip_visibility(line: &str) -> &str {
+    let line = line.strip_prefix("pub ").unwrap_or(line);
+    match line.strip_prefix("pub(") {
+        Some(rest) => rest.split_once(')').map_or(line, |(_, rest)| rest.trim_start()),
+        None => line,
+    }
+}
+
+// The directory a Rust file's child modules live in: its own directory for
+// `mod.rs`, `lib.rs`, and `main.rs`, else a directory named after it.
+fn rust_module_dir(path: &Path) -> PathBuf {
+    let parent = path.parent().unwrap_or(Path::new("")).to_path_buf();
+    match path.file_name().and_then(|n| n.to_str()) {
+        Some("mod.rs" | "lib.rs" | "main.rs") => parent,
+        _ => parent.join(path.file_stem().unwrap_or_default()),
+    }
+}
+
+// The nearest directory above `path` holding a `lib.rs` or `main.rs`.
+fn rust_crate_root(path: &Path, paths: &HashSet<PathBuf>) -> PathBuf {
+    path.ancestors()
+        .skip(1)
+        .find(|dir| paths.contains(&dir.join("lib.rs")) || paths.contains(&dir.join("main.rs")))
+        .map(Path::to_path_buf)
+        .unwrap_or_else(|| PathBuf::from("src"))
+}
+
+// The contents of the single- or double-quoted strings on `line`.
+fn quoted(line: &str) -> Vec<&str> {
+    let mut found = Vec::new();
+    let mut rest = line;
+    while let Some(start) = rest.find(['"', '\'', '`']) {
+        let quote = rest[start..].chars().next().unwrap_or('"');
+        let after = &rest[start + 1..];
+        let Some(end) = after.find(quote) else {
+            break;
+        };
+        found.push(&after[..end]);
+        rest = &after[end + 1..];
+    }
+    found
+}
+
+// `path` with `.` and `..` resolved; None if it climbs above the root.
+fn normalize(path: &Path) -> Option<PathBuf> {
+    let mut out = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if !out.pop() {
+                    return None;
+                }
+            }
+            Component::Normal(part) => out.push(part),
+            _ => return None,
+        }
+    }
+    Some(out)
+}
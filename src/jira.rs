@@ -0,0 +1,190 @@
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::time::Duration;
+
+const JIRA_TIMEOUT: Duration = Duration::from_secs(15);
+
+// Field name looked up when JIRA_ACCEPTANCE_FIELD is not set.
+const ACCEPTANCE_FIELD_NAME: &str = "acceptance criteria";
+
+#[derive(Deserialize)]
+struct Named {
+    name: String,
+}
+
+#[derive(Deserialize)]
+struct Fields {
+    summary: String,
+    description: Option<String>,
+    status: Option<Named>,
+    issuetype: Option<Named>,
+    priority: Option<Named>,
+    #[serde(default)]
+    labels: Vec<String>,
+    // Custom fields, among them the acceptance criteria.
+    #[serde(flatten)]
+    other: HashMap<String, Value>,
+}
+
+#[derive(Deserialize)]
+struct IssueResponse {
+    key: String,
+    fields: Fields,
+    // Field ID to display name, from `expand=names`.
+    #[serde(default)]
+    names: HashMap<String, String>,
+}
+
+// A Jira ticket's requirements.
+pub struct Ticket {
+    pub key: String,
+    pub url: String,
+    pub summary: String,
+    pub issue_type: Option<String>,
+    pub status: Option<String>,
+    pub priority: Option<String>,
+    pub labels: Vec<String>,
+    pub description: String,
+    pub acceptance_criteria: Option<String>,
+}
+
+impl Ticket {
+    // The ticket as the start of a feature request.
+    pub fn render(&self) -> String {
+        let mut out = format!("# {}: {}\n\n", self.key, self.summary);
+        let details: Vec<&str> = [&self.issue_type, &self.status, &self.priority].into_iter().flatten().map(String::as_str).collect();
+        if details.is_empty() {
+            out.push_str(&format!("Ticket: {}\n", self.url));
+        } else {
+            out.push_str(&format!("Ticket: {} ({})\n", self.url, details.join(", ")));
+        }
+        if !self.labels.is_empty() {
+            out.push_str(&format!("Labels: {}\n", self.labels.join(", ")));
+        }
+        let description = self.description.trim();
+        out.push_str(&format!("\n## Description\n\n{}\n", if description.is_empty() { "(no description)" } else { description }));
+        if let Some(criteria) = &self.acceptance_criteria {
+            out.push_str(&format!("\n## Acceptance Criteria\n\n{}\n", criteria.trim()));
+        }
+        out
+    }
+}
+
+// Reads tickets from Jira Cloud, Server, or Data Center through the REST API
+// (v2, whose text fields are plain wiki markup on every edition).
+pub struct Jira {
+    http: reqwest::Client,
+    base_url: Option<String>,
+    token: Option<String>,
+    // With an email (Jira Cloud), the token is an API token sent with basic
+    // auth; without one, a personal access token sent as a bearer token.
+    email: Option<String>,
+    // ID (`customfield_10042`) or name of the acceptance criteria field.
+    acceptance_field: Option<String>,
+}
+
+impl Jira {
+    pub fn new(base_url: Option<String>, token: Option<String>, email: Option<String>, acceptance_field: Option<String>) -> Self {
+        let http = reqwest::Client::builder()
+            .user_agent(concat!("ai-code-agent/", env!("CARGO_PKG_VERSION")))
+            .timeout(JIRA_TIMEOUT)
+            .build()
+            .unwrap_or_default();
+        let base_url = base_url.map(|url| url.trim_end_matches('/').to_string());
+        Self { http, base_url, token, email, acceptance_field }
+    }
+
+    // Fetches `ticket`, an issue key (PROJ-123) or its browse URL.
+    pub async fn ticket(&self, ticket: &str) -> Result<Ticket> {
+        let Some(base_url) = &self.base_url else {
+            bail!("fetching Jira tickets needs JIRA_URL");
+        };
+        let key = parse_key(ticket)?;
+        let url = format!("{base_url}/rest/api/2/issue/{key}?expand=names");
+
+        let mut request = self.http.get(&url).header(reqwest::header::ACCEPT, "application/json");
+        request = match (&self.email, &self.token) {
+            (Some(email), Some(token)) => request.basic_auth(email, Some(token)),
+            (None, Some(token)) => request.bearer_auth(token),
+            (_, None) => request,
+        };
+        let response = request.send().await.with_context(|| format!("Failed to reach {url}"))?;
+        let status = response.status();
+        if !status.is_success() {
+            // Jira explains refusals in `errorMessages` and per-field `errors`.
+            let detail = response.json::<Value>().await.ok();
+            let messages: Vec<String> = detail
+                .as_ref()
+                .map(|d| {
+                    let listed = d["errorMessages"].as_array().into_iter().flatten().filter_map(|m| m.as_str().map(str::to_string));
+                    let fields = d["errors"].as_object().into_iter().flatten().filter_map(|(f, m)| m.as_str().map(|m| format!("{f}: {m}")));
+                    listed.chain(fields).collect()
+                })
+                .unwrap_or_default();
+            let message = if messages.is_empty() { "no details".to_string() } else { messages.join("; ") };
+            match status {
+                reqwest::StatusCode::NOT_FOUND => bail!("Jira ticket {key} was not found, or JIRA_TOKEN cannot access it ({message})"),
+                reqwest::StatusCode::UNAUTHORIZED => bail!("Jira refused the credentials for {key}; check JIRA_TOKEN and JIRA_EMAIL"),
+                _ => bail!("Jira refused {url} ({status}: {message})"),
+            }
+        }
+        let issue: IssueResponse = response.json().await.with_context(|| format!("Invalid response from {url}"))?;
+
+        let acceptance_criteria = self.acceptance_field_id(&issue).and_then(|id| issue.fields.other.get(&id)).and_then(field_text);
+        Ok(Ticket {
+            url: format!("{base_url}/browse/{}", issue.key),
+            key: issue.key,
+            summary: issue.fields.summary,
+            issue_type: issue.fields.issuetype.map(|t| t.name),
+            status: issue.fields.status.map(|s| s.name),
+            priority: issue.fields.priority.map(|p| p.name),
+            labels: issue.fields.labels,
+            description: issue.fields.description.unwrap_or_default(),
+            acceptance_criteria,
+        })
+    }
+
+    // The configured field, given by ID or by name, or the field named
+    // "Acceptance Criteria".
+    fn acceptance_field_id(&self, issue: &IssueResponse) -> Option<String> {
+        let wanted = self.acceptance_field.as_deref().unwrap_or(ACCEPTANCE_FIELD_NAME);
+        if issue.fields.other.contains_key(wanted) {
+            return Some(wanted.to_string());
+        }
+        issue.names.iter().find(|(_, name)| name.eq_ignore_ascii_case(wanted)).map(|(id, _)| id.clone())
+    }
+}
+
+// A custom field's value as text: a string, a select option's value, or a
+// list of either.
+fn field_text(value: &Value) -> Option<String> {
+    let text = match value {
+        Value::String(text) => text.clone(),
+        Value::Object(option) => option.get("value").and_then(Value::as_str)?.to_string(),
+        Value::Array(items) => items.iter().filter_map(field_text).map(|item| format!("- {item}")).collect::<Vec<_>>().join("\n"),
+        _ => return None,
+    };
+    (!text.trim().is_empty()).then_some(text)
+}
+
+// An issue key such as PROJ-123, given as is or as a .../browse/PROJ-123 URL.
+fn parse_key(ticket: &str) -> Result<String> {
+    let ticket = ticket.trim();
+    let candidate = match ticket.split_once("/browse/") {
+        Some((_, rest)) => rest.split(['/', '?', '#']).next().unwrap_or_default(),
+        None => ticket,
+    };
+    let key = candidate.to_ascii_uppercase();
+    let valid = key.split_once('-').is_some_and(|(project, number)| {
+        project.starts_with(|c: char| c.is_ascii_alphabetic())
+            && project.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+            && !number.is_empty()
+            && number.chars().all(|c| c.is_ascii_digit())
+    });
+    if !valid {
+        bail!("'{ticket}' is not a Jira issue key (e.g. PROJ-123) or browse URL");
+    }
+    Ok(key)
+}
@@ -0,0 +1,279 @@
+use std::collections::{HashSet, VecDeque};
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+// Consecutive transient failures (network, server errors) before a key is quarantined.
+const CIRCUIT_THRESHOLD: u32 = 3;
+const BASE_COOL_DOWN: Duration = Duration::from_secs(30);
+const MAX_COOL_DOWN: Duration = Duration::from_secs(3600);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ErrorClass {
+    RateLimited,
+    QuotaExhausted,
+    Unauthorized,
+    Server,
+    Network,
+    Timeout,
+    Other,
+}
+
+impl ErrorClass {
+    pub const ALL: [ErrorClass; 7] = [
+        ErrorClass::RateLimited,
+        ErrorClass::QuotaExhausted,
+        ErrorClass::Unauthorized,
+        ErrorClass::Server,
+        ErrorClass::Network,
+        ErrorClass::Timeout,
+        ErrorClass::Other,
+    ];
+
+    // Revoked and over-quota keys will not recover on the next request.
+    fn quarantines_immediately(self) -> bool {
+        matches!(self, ErrorClass::Unauthorized | ErrorClass::QuotaExhausted)
+    }
+
+    // Slow responses, and malformed or empty ones, say nothing about the key.
+    pub fn reflects_on_key(self) -> bool {
+        !matches!(self, ErrorClass::Timeout | ErrorClass::Other)
+    }
+
+    // The name used in RETRY_ON.
+    pub fn name(self) -> &'static str {
+        match self {
+            ErrorClass::RateLimited => "rate_limited",
+            ErrorClass::QuotaExhausted => "quota_exhausted",
+            ErrorClass::Unauthorized => "unauthorized",
+            ErrorClass::Server => "server",
+            ErrorClass::Network => "network",
+            ErrorClass::Timeout => "timeout",
+            ErrorClass::Other => "other",
+        }
+    }
+}
+
+impl fmt::Display for ErrorClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ErrorClass::RateLimited => "rate limited",
+            ErrorClass::QuotaExhausted => "quota exhausted",
+            ErrorClass::Unauthorized => "unauthorized",
+            ErrorClass::Server => "server error",
+            ErrorClass::Network => "network error",
+            ErrorClass::Timeout => "timeout",
+            ErrorClass::Other => "other error",
+        };
+        f.write_str(name)
+    }
+}
+
+struct KeyState {
+    key: String,
+    in_flight: Arc<Semaphore>,
+    successes: u64,
+    failures: u64,
+    consecutive_failures: u32,
+    quarantines: u32,
+    last_error: Option<ErrorClass>,
+    quarantined_until: Option<Instant>,
+}
+
+impl KeyState {
+    fn is_quarantined(&self, now: Instant) -> bool {
+        self.quarantined_until.is_some_and(|until| until > now)
+    }
+}
+
+pub struct KeyHealth {
+    pub key: String,
+    pub successes: u64,
+    pub failures: u64,
+    pub consecutive_failures: u32,
+    pub last_error: Option<ErrorClass>,
+    pub quarantined_for: Option<Duration>,
+}
+
+// A key checked out for one request. Holding it counts against the key's
+// in-flight limit.
+pub struct KeyLease {
+    pub key: String,
+    _permit: OwnedSemaphorePermit,
+}
+
+// Round-robin API keys that skips keys in quarantine and, when a per-key
+// in-flight limit is set, keys that are already at the limit.
+pub struct KeyPool {
+    keys: Mutex<VecDeque<KeyState>>,
+}
+
+impl KeyPool {
+    pub fn new(keys: Vec<String>, max_in_flight_per_key: Option<usize>) -> Self {
+        let permits = max_in_flight_per_key.unwrap_or(Semaphore::MAX_PERMITS);
+        let keys = keys
+            .into_iter()
+            .map(|key| KeyState {
+                key,
+                in_flight: Arc::new(Semaphore::new(permits)),
+                successes: 0,
+                failures: 0,
+                consecutive_failures: 0,
+                quarantines: 0,
+                last_error: None,
+                quarantined_until: None,
+            })
+            .collect();
+        Self { keys: Mutex::new(keys) }
+    }
+
+    pub fn len(&self) -> usize {
+        self.keys.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.lock().unwrap().is_empty()
+    }
+
+    // Checks out the next healthy key with spare capacity, waiting for a slot
+    // if every healthy key is busy. If every key is quarantined, the one whose
+    // cool-down ends soonest is used rather than failing outright.
+    pub async fn lease(&self) -> KeyLease {
+        self.lease_excluding(&HashSet::new()).await
+    }
+
+    // As `lease`, but never returns a key in `excluded` (the keys a request
+    // already tried) unless every key is excluded.
+    pub async fn lease_excluding(&self, excluded: &HashSet<String>) -> KeyLease {
+        let (key, in_flight) = self.next(excluded);
+        let permit = in_flight.acquire_owned().await.expect("key semaphores are never closed");
+        KeyLease { key, _permit: permit }
+    }
+
+    fn next(&self, excluded: &HashSet<String>) -> (String, Arc<Semaphore>) {
+        let mut keys = self.keys.lock().unwrap();
+        if keys.is_empty() {
+            panic!("No API keys available");
+        }
+
+        let now = Instant::now();
+        let mut candidates: Vec<usize> = (0..keys.len()).filter(|&i| !excluded.contains(&keys[i].key)).collect();
+        if candidates.is_empty() {
+            candidates = (0..keys.len()).collect();
+        }
+        let index = candidates
+            .iter()
+            .copied()
+            .find(|&i| !keys[i].is_quarantined(now) && keys[i].in_flight.available_permits() > 0)
+            .or_else(|| candidates.iter().copied().find(|&i| !keys[i].is_quarantined(now)))
+            .unwrap_or_else(|| {
+                tracing::warn!("All API keys are quarantined; using the one that recovers soonest");
+                candidates.iter().copied().min_by_key(|&i| keys[i].quarantined_until).unwrap_or(0)
+            });
+
+        let state = keys.remove(index).expect("index is in bounds");
+        let next = (state.key.clone(), state.in_flight.clone());
+        keys.push_back(state);
+        next
+    }
+
+    pub fn record_success(&self, key: &str) {
+        let mut keys = self.keys.lock().unwrap();
+        if let Some(state) = keys.iter_mut().find(|k| k.key == key) {
+            state.successes += 1;
+            state.consecutive_failures = 0;
+            state.quarantines = 0;
+            state.quarantined_until = None;
+        }
+    }
+
+    pub fn record_failure(&self, key: &str, class: ErrorClass) {
+        let mut keys = self.keys.lock().unwrap();
+        let Some(state) = keys.iter_mut().find(|k| k.key == key) else {
+            return;
+        };
+
+        state.failures += 1;
+        state.consecutive_failures += 1;
+        state.last_error = Some(class);
+
+        if class.quarantines_immediately() || state.consecutive_failures >= CIRCUIT_THRESHOLD {
+            let cool_down = BASE_COOL_DOWN
+                .saturating_mul(2u32.saturating_pow(state.quarantines))
+                .min(MAX_COOL_DOWN);
+            state.quarantines += 1;
+            state.quarantined_until = Some(Instant::now() + cool_down);
+            tracing::warn!(
+                "Quarantining API key {} for {}s after {} ({} consecutive failures)",
+                mask(&state.key),
+                cool_down.as_secs(),
+                class,
+                state.consecutive_failures
+            );
+        }
+    }
+
+    // Every key, in rotation order, for checks that must cover each one.
+    pub fn keys(&self) -> Vec<String> {
+        self.keys.lock().unwrap().iter().map(|k| k.key.clone()).collect()
+    }
+
+    pub fn health(&self) -> Vec<KeyHealth> {
+        let now = Instant::now();
+        self.keys
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|k| KeyHealth {
+                key: mask(&k.key),
+                successes: k.successes,
+                failures: k.failures,
+                consecutive_failures: k.consecutive_failures,
+                last_error: k.last_error,
+                quarantined_for: k.quarantined_until.and_then(|until| until.checked_duration_since(now)),
+            })
+            .collect()
+    }
+}
+
+pub fn mask(key: &str) -> String {
+    let tail: String = key.chars().rev().take(4).collect::<Vec<_>>().into_iter().rev().collect();
+    format!("…{tail}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn excluding(keys: &[&str]) -> HashSet<String> {
+        keys.iter().map(|k| k.to_string()).collect()
+    }
+
+    #[tokio::test]
+    async fn skips_quarantined_keys() {
+        let pool = KeyPool::new(vec!["key-a".into(), "key-b".into()], None);
+        pool.record_failure("key-a", ErrorClass::Unauthorized);
+        for _ in 0..3 {
+            assert_eq!(pool.lease().await.key, "key-b");
+        }
+    }
+
+    #[tokio::test]
+    async fn excluded_keys_are_skipped_even_when_every_key_is_quarantined() {
+        let pool = KeyPool::new(vec!["key-a".into(), "key-b".into()], None);
+        pool.record_failure("key-b", ErrorClass::Unauthorized);
+        pool.record_failure("key-a", ErrorClass::Unauthorized);
+        // key-b recovers soonest, but was already tried.
+        assert_eq!(pool.lease_excluding(&excluding(&["key-b"])).await.key, "key-a");
+        assert_eq!(pool.lease_excluding(&excluding(&["key-a"])).await.key, "key-b");
+        // With every key excluded, one is still returned.
+        assert_eq!(pool.lease_excluding(&excluding(&["key-a", "key-b"])).await.key, "key-b");
+    }
+
+    #[test]
+    fn masks_all_but_the_last_four_characters() {
+        assert_eq!(mask("AIzaSyExample1234"), "…1234");
+        assert_eq!(mask("abc"), "…abc");
+    }
+}
@@ -0,0 +1,301 @@
+use crate::files::SourceFile;
+use std::collections::HashMap;
+use std::path::{Component, Path};
+
+// A language counts towards a mixed-language repository with at least this
+// many files and this share of the source bytes, so a lone build script or a
+// handful of fixtures does not.
+const MIN_FILES: usize = 2;
+const MIN_SHARE: f64 = 0.02;
+
+// Top-level directories listed per language.
+const MAX_DIRS: usize = 4;
+
+pub struct Language {
+    pub name: &'static str,
+    pub ecosystem: &'static str,
+    extensions: &'static [&'static str],
+    // File names of the ecosystem's manifests and lockfiles.
+    manifests: &'static [&'static str],
+    // File name prefixes of formatter, linter, and compiler configuration.
+    tooling: &'static [&'static str],
+    // Suffixes or prefixes (ending in `_`) of test file names, and test directories.
+    tests: &'static [&'static str],
+    // Conventions of the ecosystem the plan must not carry over from another.
+    notes: &'static str,
+}
+
+const LANGUAGES: &[Language] = &[
+    Language {
+        name: "Rust",
+        ecosystem: "Cargo",
+        extensions: &["rs"],
+        manifests: &["Cargo.toml", "Cargo.lock"],
+        tooling: &["rustfmt.toml", ".rustfmt.toml", "clippy.toml", "rust-toolchain"],
+        tests: &["tests/", "benches/"],
+        notes: "snake_case items, CamelCase types; errors through `Result` and `?`; unit tests in `#[cfg(test)]` modules, integration tests under `tests/`; dependencies via `cargo add`",
+    },
+    Language {
+        name: "TypeScript/JavaScript",
+        ecosystem: "npm",
+        extensions: &["ts", "tsx", "mts", "cts", "js", "jsx", "mjs", "cjs", "vue", "svelte"],
+        manifests: &["package.json", "package-lock.json", "yarn.lock", "pnpm-lock.yaml", "bun.lockb"],
+        tooling: &["tsconfig", "jsconfig", ".eslintrc", "eslint.config", ".prettierrc", "prettier.config", "biome.json", "vite.config", "jest.config", "vitest.config"],
+        tests: &[".test.ts", ".test.tsx", ".test.js", ".test.jsx", ".spec.ts", ".spec.tsx", ".spec.js", "__tests__/"],
+        notes: "camelCase functions and variables, PascalCase types and components; follow the existing module system (ESM or CommonJS) and `tsconfig` strictness; dependencies via the lockfile's package manager",
+    },
+    Language {
+        name: "Python",
+        ecosystem: "pip",
+        extensions: &["py", "pyi"],
+        manifests: &["pyproject.toml", "setup.py", "setup.cfg", "requirements.txt", "poetry.lock", "uv.lock", "Pipfile"],
+        tooling: &["ruff.toml", ".ruff.toml", ".flake8", "mypy.ini", ".mypy.ini", "pytest.ini", "tox.ini", ".pylintrc"],
+        tests: &["test_", "_test.py", "conftest.py", "tests/"],
+        notes: "snake_case functions and modules, PascalCase classes; type hints where the code already uses them; dependencies in the manifest the project already uses (pyproject.toml or requirements files)",
+    },
+    Language {
+        name: "Go",
+        ecosystem: "Go modules",
+        extensions: &["go"],
+        manifests: &["go.mod", "go.sum", "go.work"],
+        tooling: &[".golangci"],
+        tests: &["_test.go"],
+        notes: "MixedCaps with exported names capitalized; errors returned as the last value and wrapped with `%w`; tests in `_test.go` files beside the code; gofmt formatting",
+    },
+    Language {
+        name: "Java/Kotlin",
+        ecosystem: "Maven/Gradle",
+        extensions: &["java", "kt", "kts"],
+        manifests: &["pom.xml", "build.gradle", "build.gradle.kts", "settings.gradle", "settings.gradle.kts"],
+        tooling: &["checkstyle", ".editorconfig", "detekt"],
+        tests: &["Test.java", "Test.kt", "src/test/"],
+        notes: "package-per-directory layout under `src/main` and `src/test`; follow the build tool already in use",
+    },
+    Language {
+        name: "C#",
+        ecosystem: ".NET",
+        extensions: &["cs"],
+        manifests: &["Directory.Build.props", "packages.config", "global.json"],
+        tooling: &[".editorconfig", "stylecop"],
+        tests: &["Tests.cs", "Test.cs", ".Tests/"],
+        notes: "PascalCase types and members; projects and references declared in `.csproj` files",
+    },
+    Language {
+        name: "Ruby",
+        ecosystem: "Bundler",
+        extensions: &["rb", "rake", "gemspec"],
+        manifests: &["Gemfile", "Gemfile.lock"],
+        tooling: &[".rubocop"],
+        tests: &["_spec.rb", "_test.rb", "spec/", "test/"],
+        notes: "snake_case methods and files, CamelCase classes; gems declared in the Gemfile",
+    },
+    Language {
+        name: "PHP",
+        ecosystem: "Composer",
+        extensions: &["php"],
+        manifests: &["composer.json", "composer.lock"],
+        tooling: &["phpcs", "phpstan", ".php-cs-fixer"],
+        tests: &["Test.php", "tests/"],
+        notes: "PSR-4 autoloading and PSR-12 style unless the code shows otherwise; packages via Composer",
+    },
+    Language {
+        name: "Swift",
+        ecosystem: "SwiftPM",
+        extensions: &["swift"],
+        manifests: &["Package.swift", "Package.resolved", "Podfile"],
+        tooling: &[".swiftlint", ".swiftformat"],
+        tests: &["Tests.swift", "Tests/"],
+        notes: "lowerCamelCase members, UpperCamelCase types; targets declared in Package.swift or the Xcode project",
+    },
+    Language {
+        name: "C/C++",
+        ecosystem: "CMake/Make",
+        extensions: &["c", "h", "cc", "cpp", "cxx", "hpp", "hh", "hxx"],
+        manifests: &["CMakeLists.txt", "Makefile", "meson.build", "conanfile.txt", "vcpkg.json"],
+        tooling: &[".clang-format", ".clang-tidy"],
+        tests: &["_test.cc", "_test.cpp", "test/", "tests/"],
+        notes: "header/source pairs; new files registered in the existing build files",
+    },
+    Language {
+        name: "Elixir",
+        ecosystem: "Mix",
+        extensions: &["ex", "exs"],
+        manifests: &["mix.exs", "mix.lock"],
+        tooling: &[".formatter.exs", ".credo"],
+        tests: &["_test.exs", "test/"],
+        notes: "snake_case functions, CamelCase modules; dependencies in mix.exs",
+    },
+    Language {
+        name: "Dart",
+        ecosystem: "pub",
+        extensions: &["dart"],
+        manifests: &["pubspec.yaml", "pubspec.lock"],
+        tooling: &["analysis_options.yaml"],
+        tests: &["_test.dart", "test/"],
+        notes: "lowerCamelCase members, UpperCamelCase types, snake_case files; dependencies in pubspec.yaml",
+    },
+];
+
+// The language a source file is written in, by extension.
+pub fn of(path: &Path) -> Option<&'static Language> {
+    let extension = path.extension()?.to_str()?;
+    LANGUAGES.iter().find(|l| l.extensions.iter().any(|e| e.eq_ignore_ascii_case(extension)))
+}
+
+// A language's part of a repository.
+pub struct Share {
+    pub language: &'static Language,
+    pub files: usize,
+    pub bytes: usize,
+    // Top-level directories with the most of its files, as (directory, files);
+    // "." for files at the root.
+    pub dirs: Vec<(String, usize)>,
+    pub manifests: Vec<String>,
+    pub tooling: Vec<String>,
+    pub test_files: usize,
+    pub test_example: Option<String>,
+}
+
+// The languages of a repository that mixes several, largest first. Returns
+// nothing when only one language is substantial.
+pub fn detect(source_files: &[SourceFile]) -> Vec<Share> {
+    let mut shares: HashMap<&'static str, Share> = HashMap::new();
+    let mut dirs: HashMap<&'static str, HashMap<String, usize>> = HashMap::new();
+    for file in source_files {
+        let Some(language) = of(&file.path) else {
+            continue;
+        };
+        let share = shares.entry(language.name).or_insert_with(|| Share {
+            language,
+            files: 0,
+            bytes: 0,
+            dirs: Vec::new(),
+            manifests: Vec::new(),
+            tooling: Vec::new(),
+            test_files: 0,
+            test_example: None,
+        });
+        share.files += 1;
+        share.bytes += file.content.len();
+        if is_test(language, &file.path) {
+            share.test_files += 1;
+            share.test_example.get_or_insert_with(|| display(&file.path));
+        }
+        *dirs.entry(language.name).or_default().entry(top_dir(&file.path)).or_default() += 1;
+    }
+
+    let total: usize = shares.values().map(|s| s.bytes).sum();
+    let mut shares: Vec<Share> = shares
+        .into_values()
+        .filter(|s| s.files >= MIN_FILES && total > 0 && s.bytes as f64 / total as f64 >= MIN_SHARE)
+        .collect();
+    if shares.len() < 2 {
+        return Vec::new();
+    }
+    shares.sort_by_key(|s| std::cmp::Reverse(s.bytes));
+
+    for share in &mut shares {
+        let mut top: Vec<(String, usize)> = dirs.remove(share.language.name).unwrap_or_default().into_iter().collect();
+        top.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        top.truncate(MAX_DIRS);
+        share.dirs = top;
+
+        for file in source_files {
+            let Some(name) = file.path.file_name().and_then(|n| n.to_str()) else {
+                continue;
+            };
+            let path = display(&file.path);
+            let language = share.language;
+            if language.manifests.contains(&name) || (language.name == "C#" && name.ends_with(".csproj")) {
+                share.manifests.push(path);
+            } else if language.tooling.iter().any(|t| name.starts_with(t)) {
+                share.tooling.push(path);
+            }
+        }
+    }
+    shares
+}
+
+// Whether `path` follows any language's test file conventions, whatever
+// its own language (fixtures under `tests/` count).
+pub fn is_test_file(path: &Path) -> bool {
+    LANGUAGES.iter().any(|language| is_test(language, path))
+}
+
+// Whether `path` is one of any language's manifests or lockfiles.
+pub fn is_manifest(path: &Path) -> bool {
+    let name = path.file_name().and_then(|n| n.to_str()).unwrap_or_default();
+    LANGUAGES.iter().any(|language| language.manifests.contains(&name))
+}
+
+fn is_test(language: &Language, path: &Path) -> bool {
+    let path = display(path);
+    let name = path.rsplit('/').next().unwrap_or_default();
+    language.tests.iter().any(|pattern| match pattern.strip_suffix('/') {
+        Some(dir) => path.starts_with(&format!("{dir}/")) || path.contains(&format!("/{dir}/")),
+        None if pattern.ends_with('_') => name.starts_with(pattern),
+        None => name.ends_with(pattern),
+    })
+}
+
+fn top_dir(path: &Path) -> String {
+    let mut components = path.components();
+    match (components.next(), components.next()) {
+        (Some(Component::Normal(dir)), Some(_)) => format!("{}/", dir.to_string_lossy()),
+        _ => ".".to_string(),
+    }
+}
+
+fn display(path: &Path) -> String {
+    path.to_string_lossy().replace('\\', "/")
+}
+
+// Appended to the language map so plans keep the ecosystems apart.
+pub const LABEL_INSTRUCTIONS: &str = "This repository mixes languages. Keep each change in the language and ecosystem that owns the code it touches, follow that ecosystem's conventions and tooling rather than another's, and label every implementation step and file change with its language as named in the table, in brackets, e.g. `[Rust]` or `[Python]`. Changes that span ecosystems (an API and its client, a shared schema) must say how both sides stay in sync.";
+
+pub fn render(shares: &[Share], label: Option<&str>) -> String {
+    let title = match label {
+        Some(label) => format!("Language Map of `{label}`"),
+        None => "Language Map".to_string(),
+    };
+    let total: usize = shares.iter().map(|s| s.bytes).sum::<usize>().max(1);
+    let mut out = format!("{title} (the languages of this repository and where each lives):\n\n| Language | Ecosystem | Files | Share | Main directories |\n|---|---|---|---|---|\n");
+    for share in shares {
+        let dirs: Vec<String> = share.dirs.iter().map(|(dir, files)| format!("`{dir}` ({files})")).collect();
+        out.push_str(&format!(
+            "| {} | {} | {} | {:.0}% | {} |\n",
+            share.language.name,
+            share.language.ecosystem,
+            share.files,
+            share.bytes as f64 * 100.0 / total as f64,
+            dirs.join(", ")
+        ));
+    }
+
+    out.push_str("\nConventions per language:\n");
+    for share in shares {
+        let language = share.language;
+        out.push_str(&format!("\n- **{} ({})**: {}.", language.name, language.ecosystem, language.notes));
+        if !share.manifests.is_empty() {
+            out.push_str(&format!(" Manifests: {}.", list(&share.manifests)));
+        }
+        if !share.tooling.is_empty() {
+            out.push_str(&format!(" Tooling config: {}.", list(&share.tooling)));
+        }
+        match &share.test_example {
+            Some(example) => out.push_str(&format!(" {} test file(s), e.g. `{example}`.", share.test_files)),
+            None => out.push_str(" No test files found."),
+        }
+    }
+    out.push_str(&format!("\n\n{LABEL_INSTRUCTIONS}\n\n"));
+    out
+}
+
+// Up to five paths, then a count of the rest.
+fn list(paths: &[String]) -> String {
+    let mut shown: Vec<String> = paths.iter().take(5).map(|p| format!("`{p}`")).collect();
+    if paths.len() > 5 {
+        shown.push(format!("and {} more", paths.len() - 5));
+    }
+    shown.join(", ")
+}
@@ -0,0 +1,70 @@
+// Public API for embedding the pipelines in a Rust service:
+// - `config::Config` holds the shared state; `Config::from_env` builds it
+//   from the same environment variables as the server binary.
+// - `agent::Agent` runs the pipelines and returns typed results. It is the
+//   stable API; `agent::Agent::run_pipeline` runs any of them by name.
+// - `pipeline` lists the pipelines: each tool's prompts (defined in `llm`)
+//   and the files it always sends in full.
+// - `llm::GeminiClient` is the model client, and `external` runs
+//   codebase_viewer to build the context.
+// - `testing` builds configurations answered by the mock provider, with
+//   temporary repositories, for integration tests of tools and templates.
+// The remaining modules are public for the binary's frontends (MCP, REST,
+// and the CLI) and may change between releases.
+pub mod agent;
+pub mod apply;
+pub mod archive;
+pub mod audit;
+pub mod bitbucket;
+pub mod cache;
+pub mod canary;
+pub mod cli;
+pub mod code_index;
+pub mod config;
+pub mod embeddings;
+pub mod errors;
+pub mod external;
+pub mod failed;
+pub mod fairness;
+pub mod feasibility;
+pub mod files;
+pub mod forge;
+pub mod gc;
+pub mod git;
+pub mod github;
+pub mod gitlab;
+pub mod health;
+pub mod hotspots;
+pub mod imports;
+pub mod jira;
+pub mod keys;
+pub mod languages;
+pub mod llm;
+pub mod manifests;
+pub mod mermaid;
+pub mod mock;
+pub mod packages;
+pub mod paths;
+pub mod patch;
+pub mod pipeline;
+pub mod plan;
+pub mod plans;
+pub mod presets;
+pub mod process;
+pub mod ranking;
+pub mod rate_limit;
+pub mod redact;
+pub mod references;
+pub mod rest;
+pub mod retry;
+pub mod search;
+pub mod segments;
+pub mod server;
+pub mod session;
+pub mod skeleton;
+pub mod spec;
+pub mod templates;
+pub mod testing;
+pub mod tree_hash;
+pub mod usage;
+pub mod workspace;
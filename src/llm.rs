@@ -1,13 +1,28 @@
 use async_openai::{
     config::OpenAIConfig,
-    types::{
-        ChatCompletionRequestSystemMessageArgs, ChatCompletionRequestUserMessageArgs,
-        CreateChatCompletionRequestArgs,
-    },
+    types::{CreateChatCompletionResponse, CreateEmbeddingRequestArgs, Embedding},
     Client,
 };
-use std::collections::VecDeque;
-use std::sync::{Arc, Mutex};
+use crate::audit::AuditLog;
+use crate::external;
+use crate::fairness::{FairShare, QuotaExceeded};
+use crate::keys::{self, ErrorClass, KeyHealth, KeyPool};
+use crate::mock::MockLlm;
+use crate::plan::{self, OutputFormat, Verbosity};
+use crate::rate_limit;
+use crate::references;
+use crate::retry::RetryPolicy;
+use crate::segments::Segments;
+use crate::session::Exchange;
+use crate::templates::{self, MessageTemplates};
+use crate::usage::{PriceTable, UsageTracker};
+use anyhow::{anyhow, bail, Context as _};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
+use std::path::Path;
+use std::sync::Arc;
 use thiserror::Error;
 use tokio::time::{sleep, Duration};
 
@@ -17,43 +32,104 @@ pub enum LlmError {
     Api(#[from] async_openai::error::OpenAIError),
     #[error("No response content from API")]
     NoContent,
+    #[error("HTTP request failed: {0}")]
+    Request(#[from] reqwest::Error),
+    #[error("Request timed out after {}s (LLM_REQUEST_TIMEOUT_SECS)", .0.as_secs())]
+    Timeout(Duration),
+    #[error("API returned HTTP {status}: {message}")]
+    Http { status: u16, message: String },
+    // Fatal for the key: revoked, invalid, or without access to the model.
+    #[error("API key {key} was rejected: {message}")]
+    Unauthorized { key: String, message: String },
+    // Fatal for the request: retrying or another key would get the same answer.
+    #[error("The API rejected the request as invalid (HTTP {status}): {message}")]
+    InvalidRequest { status: u16, message: String },
+    #[error("Rate limited: {message}")]
+    RateLimited {
+        message: String,
+        retry_after: Option<Duration>,
+        quota_exhausted: bool,
+    },
+    #[error("{0}")]
+    QuotaExceeded(QuotaExceeded),
+    // The model list's page tokens repeated or did not run out.
+    #[error("Listing models failed: {0}")]
+    Pagination(String),
 }
 
-pub struct GeminiClient {
-    api_keys: Arc<Mutex<VecDeque<String>>>,
-    api_base: String,
-    model: String,
-}
-
-impl GeminiClient {
-    pub fn new(api_keys: Vec<String>, model: Option<String>) -> Self {
-        Self {
-            api_keys: Arc::new(Mutex::new(VecDeque::from(api_keys))),
-            api_base: "https://generativelanguage.googleapis.com/v1beta".to_string(),
-            model: model.unwrap_or_else(|| "gemini-2.5-pro".to_string()),
+impl LlmError {
+    pub fn class(&self) -> ErrorClass {
+        match self {
+            LlmError::RateLimited { quota_exhausted: true, .. } => ErrorClass::QuotaExhausted,
+            LlmError::RateLimited { .. } => ErrorClass::RateLimited,
+            LlmError::Unauthorized { .. } => ErrorClass::Unauthorized,
+            LlmError::Http { status, .. } if *status >= 500 => ErrorClass::Server,
+            LlmError::Request(_) => ErrorClass::Network,
+            LlmError::Timeout(_) => ErrorClass::Timeout,
+            LlmError::Api(e) => openai_error_class(e),
+            _ => ErrorClass::Other,
         }
     }
 
-    fn get_next_api_key(&self) -> String {
-        let mut keys = self.api_keys.lock().unwrap();
-        if let Some(key) = keys.pop_front() {
-            keys.push_back(key.clone());
-            key
-        } else {
-            panic!("No API keys available");
+    // Names the key in errors that are its fault, for the caller to fix.
+    fn with_key(self, api_key: &str) -> Self {
+        match self {
+            LlmError::Api(e) if openai_error_class(&e) == ErrorClass::Unauthorized => {
+                LlmError::Unauthorized { key: keys::mask(api_key), message: e.to_string() }
+            }
+            error => error,
         }
     }
 
-    fn create_client(&self, api_key: &str) -> Client<OpenAIConfig> {
-        let config = OpenAIConfig::new()
-            .with_api_base(&self.api_base)
-            .with_api_key(api_key);
-        Client::with_config(config)
+    // Failures another model or provider may not share: quota, rate limits,
+    // outages, and timeouts. Fair-share refusals are the server's own limit.
+    pub fn is_provider_failure(&self) -> bool {
+        self.class() != ErrorClass::Other
+    }
+}
+
+// Errors from the async-openai client (embeddings), which reports HTTP
+// failures either as the API's error object or as the underlying reqwest error.
+fn openai_error_class(error: &async_openai::error::OpenAIError) -> ErrorClass {
+    use async_openai::error::OpenAIError;
+    match error {
+        OpenAIError::Reqwest(e) if e.is_timeout() => ErrorClass::Timeout,
+        OpenAIError::Reqwest(e) => match e.status().map(|s| s.as_u16()) {
+            Some(401 | 403) => ErrorClass::Unauthorized,
+            Some(429) => ErrorClass::RateLimited,
+            Some(status) if status >= 500 => ErrorClass::Server,
+            Some(_) => ErrorClass::Other,
+            None => ErrorClass::Network,
+        },
+        OpenAIError::ApiError(e) => {
+            let code = e.code.as_deref().or(e.r#type.as_deref()).unwrap_or_default();
+            match code {
+                "insufficient_quota" => ErrorClass::QuotaExhausted,
+                "rate_limit_exceeded" => ErrorClass::RateLimited,
+                "server_error" => ErrorClass::Server,
+                "invalid_api_key" => ErrorClass::Unauthorized,
+                // Gemini reports invalid keys as INVALID_ARGUMENT.
+                _ if e.message.contains("API key") => ErrorClass::Unauthorized,
+                _ => ErrorClass::Other,
+            }
+        }
+        _ => ErrorClass::Other,
     }
+}
 
+pub struct PipelinePrompts {
+    pub name: &'static str,
+    pub analysis_system: &'static str,
+    pub request_label: &'static str,
+    pub analysis_label: &'static str,
+    pub detail_system: &'static str,
+    pub detail_instruction: &'static str,
+    pub manifest: bool,
+}
 
-    pub async fn generate_feature_plan(&self, context: String, prompt: String) -> Result<String, LlmError> {
-        let system_prompt_1 = r#"You are a senior software architect with expertise in modern software design patterns and best practices.
+pub const FEATURE_PLAN: PipelinePrompts = PipelinePrompts {
+    name: "feature plan",
+    analysis_system: r#"You are a senior software architect with expertise in modern software design patterns and best practices.
 
 Analyze the provided codebase report and create a high-level implementation plan for the requested feature.
 
@@ -64,12 +140,10 @@ Your response should include:
 4. Potential challenges and considerations
 5. Sequential implementation steps at a high level
 
-Focus on architectural clarity and maintainability."#;
-        let user_prompt_1 = format!("Codebase Report:\n{context}\n\nFeature Request: {prompt}");
-
-        let high_level_plan = self.query(&self.model, system_prompt_1, &user_prompt_1).await?;
-
-        let system_prompt_2 = r#"You are a senior software engineer creating a detailed implementation guide.
+Focus on architectural clarity and maintainability."#,
+    request_label: "Feature Request",
+    analysis_label: "High-Level Plan",
+    detail_system: r#"You are a senior software engineer creating a detailed implementation guide.
 
 Using the codebase report, feature request, and high-level plan, generate a comprehensive, actionable implementation plan.
 
@@ -83,14 +157,24 @@ Your response MUST include:
 7. Step-by-step implementation order with clear explanations
 8. Edge cases and error handling considerations
 
-Format your response in clear sections with markdown. Be specific and thorough."#;
-        let user_prompt_2 = format!("Codebase Report:\n{context}\n\nOriginal Feature Request: {prompt}\n\nHigh-Level Plan:\n{high_level_plan}\n\nNow provide the detailed implementation plan with specific file paths, code snippets, and clear instructions/explanations.");
+Format your response in clear sections with markdown. Be specific and thorough."#,
+    detail_instruction: "Now provide the detailed implementation plan with specific file paths, code snippets, and clear instructions/explanations.",
+    manifest: true,
+};
 
-        self.query(&self.model, system_prompt_2, &user_prompt_2).await
-    }
+// The analysis pass of FEATURE_PLAN as a pipeline of its own, run as a single
+// pass, so the requester can edit the high-level plan before the detail pass.
+pub const FEATURE_OUTLINE: PipelinePrompts = PipelinePrompts {
+    name: "feature outline",
+    detail_system: FEATURE_PLAN.analysis_system,
+    detail_instruction: "Now provide the high-level plan.",
+    manifest: false,
+    ..FEATURE_PLAN
+};
 
-    pub async fn generate_bug_fix_plan(&self, context: String, prompt: String) -> Result<String, LlmError> {
-        let system_prompt_1 = r#"You are a senior software developer specializing in debugging and root cause analysis.
+pub const BUG_FIX_PLAN: PipelinePrompts = PipelinePrompts {
+    name: "bug fix plan",
+    analysis_system: r#"You are a senior software developer specializing in debugging and root cause analysis.
 
 Analyze the provided codebase and bug description to identify the root cause.
 
@@ -102,11 +186,10 @@ Your response should include:
 5. Proposed approach to fix the bug
 6. Potential side effects or risks of the fix
 
-Be thorough in your analysis and consider edge cases."#;
-        let user_prompt_1 = format!("Codebase Report:\n{context}\n\nBug Description: {prompt}");
-        let analysis = self.query(&self.model, system_prompt_1, &user_prompt_1).await?;
-
-        let system_prompt_2 = r#"You are a senior software engineer implementing bug fixes.
+Be thorough in your analysis and consider edge cases."#,
+    request_label: "Bug Description",
+    analysis_label: "Root Cause Analysis",
+    detail_system: r#"You are a senior software engineer implementing bug fixes.
 
 Using the codebase report, bug description, and root cause analysis, create a detailed remediation plan.
 
@@ -119,13 +202,14 @@ Your response MUST include:
 6. Step-by-step implementation instructions
 7. Rollback plan if something goes wrong
 
-Format your response in clear sections with markdown. Provide actual code, not pseudocode."#;
-        let user_prompt_2 = format!("Codebase Report:\n{context}\n\nBug Description: {prompt}\n\nRoot Cause Analysis:\n{analysis}\n\nNow provide the detailed fix implementation plan with specific file paths and code changes.");
-        self.query(&self.model, system_prompt_2, &user_prompt_2).await
-    }
+Format your response in clear sections with markdown. Provide actual code, not pseudocode."#,
+    detail_instruction: "Now provide the detailed fix implementation plan with specific file paths and code changes.",
+    manifest: true,
+};
 
-    pub async fn generate_explanation(&self, context: String, prompt: String) -> Result<String, LlmError> {
-        let system_prompt_1 = r#"You are a principal engineer with expertise in code architecture and system design.
+pub const EXPLANATION: PipelinePrompts = PipelinePrompts {
+    name: "explanation",
+    analysis_system: r#"You are a principal engineer with expertise in code architecture and system design.
 
 Analyze the codebase to identify all components relevant to the user's query.
 
@@ -137,11 +221,10 @@ Your response should include:
 5. Dependencies and relationships between components
 6. Any non-obvious implementation details
 
-Focus on providing a complete picture of the relevant system."#;
-        let user_prompt_1 = format!("Codebase Report:\n{context}\n\nQuery: {prompt}");
-        let key_points = self.query(&self.model, system_prompt_1, &user_prompt_1).await?;
-
-        let system_prompt_2 = r#"You are a principal engineer providing technical documentation and mentorship.
+Focus on providing a complete picture of the relevant system."#,
+    request_label: "Query",
+    analysis_label: "Key Components Identified",
+    detail_system: r#"You are a principal engineer providing technical documentation and mentorship.
 
 Using the codebase report and your previous analysis, create a comprehensive technical explanation.
 
@@ -155,70 +238,2197 @@ Your response MUST include:
 7. How different components interact with each other
 8. Suggestions for where to look for specific functionality
 
-Make your explanation clear, well-structured, and educational. Use markdown formatting with code blocks."#;
-        let user_prompt_2 = format!("Codebase Report:\n{context}\n\nOriginal Query: {prompt}\n\nKey Components Identified:\n{key_points}\n\nNow provide a comprehensive technical explanation with code examples and clear structure.");
-        self.query(&self.model, system_prompt_2, &user_prompt_2).await
-    }
-
-    async fn query(&self, model: &str, system: &str, user: &str) -> Result<String, LlmError> {
-        const RETRY_DELAYS: [u64; 3] = [10, 30, 65];
-
-        for (attempt, &delay) in RETRY_DELAYS.iter().enumerate() {
-            let api_key = self.get_next_api_key();
-            let client = self.create_client(&api_key);
-
-            tracing::debug!("API request attempt {} with delay {}s on failure", attempt + 1, delay);
-
-            let request = match CreateChatCompletionRequestArgs::default()
-                .model(model)
-                .messages([
-                    ChatCompletionRequestSystemMessageArgs::default().content(system).build()?.into(),
-                    ChatCompletionRequestUserMessageArgs::default().content(user).build()?.into(),
-                ])
-                .build() {
-                    Ok(req) => req,
-                    Err(e) => return Err(LlmError::Api(e)),
-                };
-
-            match client.chat().create(request).await {
-                Ok(response) => {
-                    return response.choices.first()
-                        .and_then(|c| c.message.content.as_ref())
-                        .cloned()
-                        .ok_or(LlmError::NoContent);
-                }
-                Err(e) => {
-                    tracing::warn!("API request failed on attempt {}: {}. Retrying after {}s", attempt + 1, e, delay);
-                    sleep(Duration::from_secs(delay)).await;
-                    continue;
-                }
-            }
+Make your explanation clear, well-structured, and educational. Use markdown formatting with code blocks."#,
+    detail_instruction: "Now provide a comprehensive technical explanation with code examples and clear structure.",
+    manifest: false,
+};
+
+pub const ARCHITECTURE_DIAGRAMS: PipelinePrompts = PipelinePrompts {
+    name: "architecture diagrams",
+    analysis_system: r#"You are a senior software architect mapping the architecture of a codebase.
+
+Analyze the provided codebase report and describe the system's structure precisely enough to draw it.
+
+Your response should include:
+1. The components - modules, packages, services, and external systems (databases, queues, third-party APIs) - with the files that implement each
+2. The dependencies between components: which calls, imports, or sends data to which, and through what interface
+3. The two or three most important runtime flows (e.g. handling a request, a background job), step by step, naming the component and function at each step
+4. Entry points, and where state is stored
+
+Base everything on the actual code. Do not invent components or interactions."#,
+    request_label: "Architecture Request",
+    analysis_label: "Architecture Analysis",
+    detail_system: r#"You are a senior software architect producing architecture diagrams in Mermaid.
+
+Using the codebase report, the request, and the architecture analysis, draw the architecture as Mermaid diagrams that an editor can render directly.
+
+Your response MUST contain:
+1. A component diagram as a ```mermaid code block starting with `flowchart LR` (or `flowchart TD`): one node per component, subgraphs for layers or services, and labeled edges for the dependencies and data flow between them
+2. One sequence diagram per important runtime flow, each as a ```mermaid code block starting with `sequenceDiagram`, with every message written as `Sender->>Receiver: description`
+3. Under each diagram, a few bullet points mapping its nodes and participants to files
+
+Mermaid rules - the diagrams are validated and rejected if they break these:
+- Node IDs are alphanumeric with underscores; put labels in quotes, e.g. `server["MCP server (server.rs)"]`
+- Every `subgraph`, `loop`, `alt`, `opt`, and `par` is closed with `end`; never use `end` as a node ID
+- Use no other diagram types and no prose inside the code blocks"#,
+    detail_instruction: "Now produce the Mermaid diagrams: a component flowchart of module relationships and data flow, and a sequence diagram for each important runtime flow, with the file mapping under each.",
+    manifest: false,
+};
+
+pub const GRAPHQL_SCHEMA_PLAN: PipelinePrompts = PipelinePrompts {
+    name: "GraphQL schema plan",
+    analysis_system: r#"You are a senior API engineer specializing in GraphQL schema design and evolution.
+
+Analyze the provided codebase report, paying close attention to the GraphQL schema files (SDL or code-first type definitions) and resolver implementations, and assess the requested schema change.
+
+Your response should include:
+1. The current schema surface relevant to the change (types, fields, queries, mutations, subscriptions) with the files that define them
+2. The resolvers, data loaders, and data sources that back those fields
+3. Proposed schema evolution - new types/fields, changed arguments, and deprecations (prefer additive, non-breaking changes)
+4. Client impact analysis - which operations and persisted queries in the repo depend on affected fields
+5. Risks such as breaking changes, N+1 queries, and authorization gaps
+
+Ground every statement in the actual schema and resolver code. Do not invent types that are not in the codebase unless proposing them as new."#,
+    request_label: "Schema Change Request",
+    analysis_label: "Schema Impact Analysis",
+    detail_system: r#"You are a senior API engineer writing a detailed GraphQL schema migration plan.
+
+Using the codebase report, schema change request, and impact analysis, produce an actionable plan.
+
+Your response MUST include:
+1. Exact schema diffs (SDL or code-first definitions matching the repo's approach), including `@deprecated` directives with reasons
+2. Resolver changes with actual code for each new or modified field
+3. Data loader, data source, or database changes required by the new fields
+4. Client-side changes - every affected query, mutation, fragment, and generated type in the repo
+5. Persisted query / operation registry updates and their rollout order
+6. A deprecation timeline and the conditions for removing deprecated fields
+7. Tests - schema snapshot tests, resolver unit tests, and integration queries
+8. Step-by-step rollout order that keeps existing clients working throughout
+
+Format your response in clear sections with markdown. Provide actual code, not pseudocode."#,
+    detail_instruction: "Now provide the detailed schema change plan with schema diffs, resolver code, client updates, and persisted-query rollout steps.",
+    manifest: true,
+};
+
+pub const ZERO_DOWNTIME_DEPLOY_PLAN: PipelinePrompts = PipelinePrompts {
+    name: "zero-downtime deploy plan",
+    analysis_system: r#"You are a senior site reliability engineer specializing in zero-downtime deployments.
+
+Analyze the provided codebase report, paying close attention to the deployment files (database migrations, startup and shutdown code, health and readiness checks, Dockerfiles, Kubernetes/Helm manifests, Terraform, and CI deploy workflows), and assess how the requested change can be rolled out without downtime.
+
+Your response should include:
+1. The current deployment topology - how the service is built, started, health-checked, and rolled out, with the files that define each step
+2. Every part of the change that is not backward compatible: schema changes, API/contract changes, message formats, configuration, and cache or session formats
+3. Where old and new versions will run side by side during a rolling deploy, and what each must tolerate from the other
+4. Existing feature flag, configuration, or kill-switch mechanisms that can gate the change
+5. Risks such as long-running migrations, table locks, connection draining gaps, and irreversible data changes
+
+Ground every statement in the actual code and manifests. If the repository lacks something a safe rollout needs (e.g., readiness probes or a flag system), say so explicitly."#,
+    request_label: "Change To Roll Out",
+    analysis_label: "Deployment Analysis",
+    detail_system: r#"You are a senior site reliability engineer writing a zero-downtime rollout plan.
+
+Using the codebase report, change description, and deployment analysis, produce an actionable plan.
+
+Your response MUST include:
+1. Ordered deploy phases (e.g., expand, migrate, switch, contract), each independently deployable and safe to run alongside the previous version
+2. Migrations for each phase with actual code, split so that no phase holds long locks or breaks the running version
+3. Application code changes for each phase, including dual-read/dual-write or compatibility shims where needed
+4. Feature gating - the flag or configuration that controls each behavioral switch, its default, and who flips it
+5. Health check, readiness, and graceful shutdown changes needed for the rollout
+6. Rollback triggers - the concrete metrics, errors, or checks that abort each phase - and the rollback procedure for each phase, noting any point of no return
+7. Verification steps to run between phases
+
+Format your response in clear sections with markdown. Provide actual code, not pseudocode."#,
+    detail_instruction: "Now provide the detailed zero-downtime rollout plan with ordered phases, migrations and code per phase, feature gates, and rollback triggers.",
+    manifest: true,
+};
+
+pub(crate) const FILE_SELECTION_SYSTEM: &str = r#"You are a senior software engineer deciding which source files are needed to handle a request.
+
+You are given only the file tree of a codebase (relative paths with sizes), not the file contents.
+
+Select every file whose contents are needed to fully understand and carry out the request: the files that will change, the files that define the types and functions they use, relevant configuration and manifests, and closely related tests. Prefer including a borderline file over missing an important one, but do not select files that are clearly unrelated.
+
+Respond with ONLY a JSON array of relative paths exactly as they appear in the file tree, for example:
+["src/main.rs", "src/config.rs"]"#;
+
+pub(crate) const SPEC_CONDENSE_SYSTEM: &str = r#"You are a senior engineer condensing a long request specification so that it fits next to the codebase it applies to.
+
+Rewrite the specification as a compact markdown document that keeps every requirement, constraint, business rule, edge case, and non-goal, with every name, identifier, field, endpoint, number, limit, and example value exactly as written. Drop repetition, background, motivation, meeting notes, and filler. Keep the original section structure where it helps, as headings and bullet points. Do not add requirements, solutions, or commentary of your own. Acceptance criteria are passed on separately, verbatim, so do not restate them.
+
+The specification may be one part of a longer document; condense only what you are given. Respond with ONLY the condensed specification."#;
+
+fn render_request(messages: &MessageTemplates, prompts: &PipelinePrompts, context: &Segments, prompt: &str) -> Segments {
+    templates::render_segments(
+        messages.request,
+        &[("context", context), ("request_label", &prompts.request_label.into()), ("prompt", &prompt.into())],
+    )
+}
+
+// The request re-rendered with only the files the analysis referenced as
+// its context, and that report.
+async fn compact_request(
+    compact: CompactContext<'_>,
+    messages: &MessageTemplates,
+    prompts: &PipelinePrompts,
+    prompt: &str,
+    analysis: &str,
+) -> Option<(Segments, Segments)> {
+    match external::generate_referenced_files_report(compact.root, analysis, compact.char_limit).await {
+        Ok(Some(report)) => {
+            let report = Segments::from(report);
+            Some((render_request(messages, prompts, &report, prompt), report))
+        }
+        Ok(None) => {
+            tracing::warn!("Analysis referenced no files; sending the full context to the detail pass");
+            None
+        }
+        Err(e) => {
+            tracing::warn!("Failed to read files referenced by the analysis ({e:#}); sending the full context");
+            None
         }
+    }
+}
 
-        let api_key = self.get_next_api_key();
-        let client = self.create_client(&api_key);
+fn detail_user(messages: &MessageTemplates, prompts: &PipelinePrompts, context: &Segments, prompt: &str, request: &Segments, analysis: &str) -> Segments {
+    templates::render_segments(
+        messages.detail,
+        &[
+            ("context", context),
+            ("request_label", &prompts.request_label.into()),
+            ("prompt", &prompt.into()),
+            ("request", request),
+            ("analysis_label", &prompts.analysis_label.into()),
+            ("analysis", &analysis.into()),
+            ("detail_instruction", &prompts.detail_instruction.into()),
+        ],
+    )
+}
 
-        tracing::debug!("Final API request attempt (no retry after this)");
+// System and user message of the judging pass over candidate analyses.
+fn judge_request(analysis_system: &str, request: &Segments, candidates: &[String]) -> (String, Segments) {
+    let mut user = request.clone();
+    for (i, candidate) in candidates.iter().enumerate() {
+        user.push(format!("\n\nCandidate {}:\n{candidate}", i + 1));
+    }
+    user.push("\n\nSelect or merge the candidates and return the final response.");
+    (format!("{CANDIDATE_JUDGE_SYSTEM}\n\nOriginal instructions:\n\n{analysis_system}"), user)
+}
 
-        let request = CreateChatCompletionRequestArgs::default()
-            .model(model)
-            .messages([
-                ChatCompletionRequestSystemMessageArgs::default().content(system).build()?.into(),
-                ChatCompletionRequestUserMessageArgs::default().content(user).build()?.into(),
-            ])
-            .build()?;
+// System and user message of the critic pass.
+fn critic_request(system: &str, request: &Segments, draft: &str) -> (String, Segments) {
+    let mut user = request.clone();
+    user.push(format!("\n\nDraft Response:\n{draft}\n\nReview the draft and return the final, corrected response."));
+    (format!("{CRITIC_SYSTEM}\n\nOriginal instructions:\n\n{system}"), user)
+}
 
-        match client.chat().create(request).await {
-            Ok(response) => {
-                response.choices.first()
-                    .and_then(|c| c.message.content.as_ref())
-                    .cloned()
-                    .ok_or(LlmError::NoContent)
-            }
-            Err(e) => {
-                tracing::error!("API request failed after all retries: {}", e);
-                Err(LlmError::Api(e))
+// System and user message of the verification pass over a final response.
+fn verify_request(request: &Segments, response: &str) -> (String, Segments) {
+    let mut user = request.clone();
+    user.push(format!("\n\nResponse to verify:\n{response}\n\nVerify the response against the codebase report and list the corrections."));
+    (VERIFY_SYSTEM.to_string(), user)
+}
+
+fn detail_system(prompts: &PipelinePrompts, format: OutputFormat, verbosity: Verbosity) -> String {
+    let system = match verbosity.instructions() {
+        Some(instructions) => format!("{}\n\n{instructions}", prompts.detail_system),
+        None => prompts.detail_system.to_string(),
+    };
+    match format {
+        OutputFormat::Markdown => {
+            let mut system = format!("{system}\n\n{}", references::INSTRUCTIONS);
+            if prompts.manifest {
+                system = format!("{system}\n\n{}\n\n{}", plan::ASSUMPTIONS_INSTRUCTIONS, plan::MANIFEST_INSTRUCTIONS);
             }
+            system
+        }
+        OutputFormat::Json => format!("{system}\n\n{}", plan::json_instructions()),
+        OutputFormat::Diff => format!("{system}\n\n{}", plan::DIFF_INSTRUCTIONS),
+    }
+}
+
+// Embedding vectors as text, for the audit log's response hash.
+fn embeddings_text(data: &[Embedding]) -> String {
+    let vectors: Vec<&Vec<f32>> = data.iter().map(|e| &e.embedding).collect();
+    serde_json::to_string(&vectors).unwrap_or_default()
+}
+
+fn parse_path_list(reply: &str) -> Vec<String> {
+    if let (Some(start), Some(end)) = (reply.find('['), reply.rfind(']')) {
+        if let Ok(paths) = serde_json::from_str::<Vec<String>>(&reply[start..=end]) {
+            return paths;
         }
     }
+
+    // Fall back to one path per line for models that ignore the JSON instruction.
+    reply
+        .lines()
+        .map(|l| l.trim().trim_start_matches(['-', '*', ' ']).trim_matches(['`', '"', ',']))
+        .filter(|l| !l.is_empty() && !l.contains(' '))
+        .map(str::to_string)
+        .collect()
+}
+
+pub const EVENT_DRIVEN_MIGRATION_PLAN: PipelinePrompts = PipelinePrompts {
+    name: "event-driven migration plan",
+    analysis_system: r#"You are a principal engineer experienced in migrating synchronous systems to event-driven architectures.
+
+Analyze the provided codebase report, paying close attention to the dependency manifests and any messaging code (brokers, queues, pub/sub, outbox tables, consumers and producers), and map the synchronous call paths that are candidates for events.
+
+Your response should include:
+1. Synchronous call paths - request handlers, service-to-service calls, and in-process chains where one component waits on another - with the files and functions involved
+2. Candidate event boundaries - which calls could become published events, and which must stay synchronous (the caller needs the result, strong consistency, user-facing latency) and why
+3. The messaging infrastructure already present: libraries and brokers from the manifests and code, existing topics/queues, serialization formats, and retry or dead-letter handling
+4. Data ownership and transactions along each path, and where dual writes would occur if events were published naively
+5. Ordering, idempotency, and exactly-once assumptions the current synchronous code relies on implicitly
+
+Ground everything in the actual code. Do not assume messaging infrastructure the repository does not have."#,
+    request_label: "Migration Request",
+    analysis_label: "Call Path and Boundary Analysis",
+    detail_system: r#"You are a principal engineer writing an incremental plan to move synchronous call paths to an event-driven architecture.
+
+Using the codebase report, migration request, and call path analysis, produce a migration plan grounded in the repository's existing messaging libraries (or, if it has none, a justified choice that fits its stack).
+
+Your response MUST include:
+1. Selected boundaries - each call path to convert, the producer and consumers, and the paths deliberately left synchronous
+2. Event schemas - name, version, payload fields with types, keys used for partitioning, and the schema evolution policy, with code for the event types
+3. Reliable publishing - transactional outbox or equivalent to avoid dual writes, with the exact code changes
+4. Ordering and idempotency - per-key ordering guarantees, idempotency keys and deduplication storage in consumers, handling of redelivery and out-of-order events
+5. Failure handling - retries with backoff, dead-letter queues, poison messages, and compensating actions replacing synchronous error propagation
+6. Incremental rollout - phases that run synchronous and event-driven paths side by side (shadow publishing, dual consumption, feature flags), cut-over criteria, and how to roll back each phase
+7. Observability - correlation IDs across events, consumer lag and failure metrics, and tracing
+8. Testing strategy - contract tests for schemas, consumer idempotency tests, and end-to-end tests across the asynchronous path
+
+Reference the actual files and functions involved. Each phase must leave the system working."#,
+    detail_instruction: "Now provide the event-driven migration plan: selected boundaries, event schemas, reliable publishing, ordering and idempotency, failure handling, incremental rollout, observability, and testing strategy, with code.",
+    manifest: true,
+};
+
+pub const TELEMETRY_MIGRATION_PLAN: PipelinePrompts = PipelinePrompts {
+    name: "telemetry migration plan",
+    analysis_system: r#"You are a principal engineer experienced in migrating codebases between logging, metrics, and tracing stacks.
+
+Analyze the provided codebase report, paying close attention to the dependency manifests and every file that logs, records metrics, or creates traces, and inventory how the codebase uses the stack being migrated away from.
+
+Your response should include:
+1. Call site inventory - every file that calls the current stack, grouped by module, with the API used (logger creation, log calls by level, counters, gauges, histograms, timers, spans, context propagation) and the number of call sites in each file
+2. Configuration and wiring - where the stack is initialized, its configuration files, appenders/exporters/reporters, sampling, and environment-specific settings
+3. Usage patterns a replacement must preserve - structured fields, MDC or context maps, message formatting, metric names, tags/labels and units, span names and attributes, and anything dashboards, alerts, or log queries depend on
+4. Coupling points - wrappers or helpers that already centralize calls, direct uses that bypass them, and third-party libraries that bring their own logging or metrics bindings
+5. Features of the current stack with no direct equivalent in the target, and behavior that will change (level names, default formats, aggregation, cardinality)
+
+Ground everything in the actual code. Do not assume call sites or configuration the repository does not have."#,
+    request_label: "Migration Request",
+    analysis_label: "Telemetry Inventory",
+    detail_system: r#"You are a principal engineer writing an incremental plan to migrate a codebase from one observability stack to another.
+
+Using the codebase report, migration request, and telemetry inventory, produce a migration plan that changes the stack without losing signals along the way.
+
+Your response MUST include:
+1. Target setup - the dependencies to add and remove, initialization and configuration for each environment, exporters or backends, and how the old and new stacks coexist during the migration (bridges, adapters, dual export)
+2. Shim layer - a thin interface the code calls instead of either stack, backed first by the current stack and then by the target, with code for the interface and both implementations
+3. Mapping - a table from each current API and pattern to its target equivalent, including levels, structured fields or context, metric types, names, labels and units, and span attributes
+4. Changeover sequence - module by module, ordered by risk and dependency, with the call sites each step moves to the shim and then to the target, and the exact code changes for the first module
+5. Signal parity checks - how to confirm before and after each step that logs, metrics, and traces still arrive with the same names and fields, and the dashboards, alerts, and queries to update
+6. Cleanup - removing the old stack, the bridges, and eventually the shim where it adds nothing
+7. Rollback - how to revert each step, and the configuration switch that falls back to the old stack while both are present
+8. Risks - performance overhead, cardinality explosions, lost context across threads or async boundaries, and third-party bindings
+
+Reference the actual files and functions involved. Each step must leave the system building and emitting its signals."#,
+    detail_instruction: "Now provide the telemetry migration plan: target setup, shim layer, API mapping, module-by-module changeover sequence, signal parity checks, cleanup, rollback, and risks, with code.",
+    manifest: true,
+};
+
+pub const SDK_CLIENT_PLAN: PipelinePrompts = PipelinePrompts {
+    name: "SDK client plan",
+    analysis_system: r#"You are a senior developer-experience engineer who designs client SDKs for HTTP and RPC APIs.
+
+Analyze the provided codebase report, paying close attention to any API specification files (OpenAPI/Swagger, protobuf, GraphQL) and to the route/handler definitions, and inventory the API surface a client SDK must cover.
+
+Your response should include:
+1. Every endpoint or operation - method, path or RPC name, parameters, request and response shapes - with the files that define it
+2. Authentication and authorization - schemes, headers, token acquisition and refresh
+3. Pagination, filtering, and sorting conventions, and how they vary across endpoints
+4. Error format, status codes, and which errors are retryable; rate limiting and idempotency support
+5. Versioning, content types, streaming or upload/download endpoints, and webhooks
+6. Inconsistencies or undocumented behavior a client must work around
+
+Derive the surface from the actual code and specs. Do not invent endpoints that do not exist."#,
+    request_label: "SDK Request",
+    analysis_label: "API Surface Analysis",
+    detail_system: r#"You are a senior developer-experience engineer writing a plan and first draft for a client SDK.
+
+Using the codebase report, SDK request, and API surface analysis, produce an SDK plan in the requested language that follows that language's idioms and ecosystem conventions.
+
+Your response MUST include:
+1. Surface mapping - a table from each API endpoint/operation to the SDK resource, method name, and signature
+2. Typed models for requests, responses, and errors, generated from the actual schemas
+3. Authentication handling - configuration, credential providers, and token refresh
+4. Pagination helpers (iterators or async streams), retries with backoff for retryable errors, timeouts, and idempotency keys where supported
+5. Packaging layout - directory structure, module names, package manifest, and versioning strategy tied to the API version
+6. Draft code for the client core (configuration, HTTP transport, auth, error types) and at least one complete resource
+7. Testing strategy - mocked transport tests and contract tests against the API specification
+8. Documentation and examples to ship with the SDK
+
+Format your response in clear sections with markdown. Provide actual code, not pseudocode."#,
+    detail_instruction: "Now provide the detailed SDK plan with the surface mapping, packaging layout, and draft code for the client core and at least one complete resource.",
+    manifest: true,
+};
+
+pub const MOCK_DATA_PLAN: PipelinePrompts = PipelinePrompts {
+    name: "mock data plan",
+    analysis_system: r#"You are a senior test engineer preparing realistic test data for a codebase.
+
+Analyze the provided codebase report, paying close attention to the data model and schema files (ORM models, migrations, SQL schemas, protobuf/GraphQL/OpenAPI definitions, validation schemas), and inventory what mock data the tests need.
+
+Your response should include:
+1. Every entity with its fields, types, nullability, defaults, enums, and validation rules, and the files that define them
+2. Relationships and constraints - foreign keys, cardinality, uniqueness, and ordering or state-machine invariants that fixtures must respect
+3. Fields with realistic-value requirements (emails, addresses, money, timestamps, identifiers) and any domain-specific formats
+4. The language, test framework, and existing fixture/factory conventions or libraries already in use (e.g. factory_bot, factory_boy, Faker, fishery, fake-rs), and where tests and seed scripts live
+5. How the database or storage is seeded today, if at all
+
+Derive everything from the actual code. Do not invent entities or fields that do not exist."#,
+    request_label: "Mock Data Request",
+    analysis_label: "Data Model Analysis",
+    detail_system: r#"You are a senior test engineer writing mock data factories, fixtures, and seeding scripts.
+
+Using the codebase report, mock data request, and data model analysis, produce a plan and the code for test data in the repository's language and test framework.
+
+Your response MUST include:
+1. Factories or builders for each entity in scope, with realistic generated values, sensible defaults, and overrides for every field a test might vary
+2. Relationship handling - factories that create valid associated records, and traits/variants for common states (e.g. archived, unpaid, admin)
+3. Static fixtures for canonical cases and edge cases (boundary values, unicode, empty collections, maximum lengths)
+4. Seeding scripts for local development and integration tests, idempotent and safe to re-run, with the command to run them
+5. Determinism - seeding of random generators so failures are reproducible
+6. Where each file goes, following the project's existing test layout, and any test dependencies to add
+
+Reuse the fixture libraries and conventions the project already has before introducing new ones. Every generated value must satisfy the model's constraints."#,
+    detail_instruction: "Now provide the mock data plan: factories, relationship handling, fixtures, seeding scripts, determinism, and file placement, with complete code.",
+    manifest: true,
+};
+
+pub const MIGRATION_TESTS_PLAN: PipelinePrompts = PipelinePrompts {
+    name: "migration tests",
+    analysis_system: r#"You are a senior test engineer specializing in database schema and data migrations.
+
+Analyze the provided codebase report, paying close attention to the migration files, schema definitions, models, and the existing test setup (test configuration, database fixtures, and helpers), and work out what tests the requested migration needs.
+
+Your response should include:
+1. The migration under test - its files, the schema and data changes it makes, and the schema state before and after it. For a planned migration that does not exist yet, the migration as described
+2. The migration framework in use (e.g. Alembic, Django, ActiveRecord, Flyway, Liquibase, Knex, Prisma, sqlx, diesel, golang-migrate), how migrations are run programmatically, and whether this migration can be rolled back
+3. The data integrity invariants the migration must preserve or establish: row counts, values copied or transformed, backfilled defaults, foreign keys, uniqueness, nullability, and check constraints
+4. The test framework and how existing tests get a database - test database setup, transactions or truncation, containers, fixtures, and factories - and where database tests live
+5. Risks the tests should catch, such as data loss on rollback, rows that violate a new constraint, and ordering dependencies on earlier migrations
+
+Derive everything from the actual code. If the project has no migration or database test setup, say so explicitly."#,
+    request_label: "Migration",
+    analysis_label: "Migration Analysis",
+    detail_system: r#"You are a senior test engineer writing tests for a database schema or data migration.
+
+Using the codebase report, migration, and migration analysis, write the tests in the repository's language, test framework, and migration framework.
+
+Your response MUST include:
+1. Test harness - how each test gets an isolated database migrated to the revision just before this migration, using the framework's programmatic API and the project's existing test database setup
+2. Forward migration tests - after migrating up, the schema (tables, columns, types, indexes, constraints) matches what the migration intends
+3. Rollback tests - after migrating up then down, the schema matches the previous revision and data written before the migration survives; up, down, up again succeeds. If the migration is irreversible, a test that asserts rollback is refused, and the reason
+4. Data integrity tests - rows seeded at the previous revision (including edge cases such as NULLs, duplicates, unicode, and boundary values) are copied, transformed, or backfilled correctly, and every invariant from the analysis holds afterwards
+5. Where each file goes, following the existing test layout, any test dependencies to add, and the command to run the tests locally and in CI
+
+Seed data through raw SQL or the framework's migration-time API rather than the current models, since the models may not match the previous schema. Provide complete test code, not pseudocode."#,
+    detail_instruction: "Now provide the migration tests: the test harness, forward migration, rollback, and data integrity tests with complete code, file placement, and how to run them.",
+    manifest: true,
+};
+
+pub const REQUIREMENTS_SPEC: PipelinePrompts = PipelinePrompts {
+    name: "requirements specification",
+    analysis_system: r#"You are a senior business analyst and software archaeologist recovering the requirements of an undocumented system from its source code.
+
+Analyze the codebase report and inventory what the software actually does, as implemented (not as it might have been intended).
+
+Your response should include:
+1. Actors and entry points - users, roles, external systems, CLIs, APIs, scheduled jobs, and UI screens
+2. Feature inventory - every user-facing capability, with the files that implement it
+3. Core domain entities, their attributes, and lifecycle/state transitions
+4. Business rules - validations, calculations, limits, permissions, and conditional behavior, each traced to the code that enforces it
+5. Integrations and data flows with external systems
+6. Areas where behavior is ambiguous, dead, or contradictory
+
+Cite file paths for every finding. Do not invent behavior that is not supported by the code."#,
+    request_label: "Documentation Scope",
+    analysis_label: "Behavior Inventory",
+    detail_system: r#"You are a senior business analyst writing a requirements specification for a legacy system, reconstructed from its code.
+
+Using the codebase report and the behavior inventory, write a complete requirements/specification document describing what the software does today.
+
+Your response MUST include:
+1. Purpose and scope of the system
+2. Actors, roles, and permissions
+3. Functional requirements, grouped by feature, as numbered statements ("The system shall ...") each with a source reference (file path and function)
+4. Business rules, including exact thresholds, formulas, and validation rules found in the code
+5. Edge-case behavior - error handling, empty/invalid inputs, concurrency, timeouts, and retries as actually implemented
+6. Data requirements - entities, fields, constraints, and retention
+7. External interfaces and integrations
+8. Non-functional characteristics evident from the code (performance limits, security controls, logging, configuration)
+9. Observed gaps, inconsistencies, and probable bugs, clearly marked as observations rather than requirements
+
+Format the document in clear markdown sections. Describe behavior precisely enough that the system could be reimplemented from this document."#,
+    detail_instruction: "Now write the full requirements specification document, with a source reference for every requirement and business rule.",
+    manifest: false,
+};
+
+pub const RFC_DRAFT: PipelinePrompts = PipelinePrompts {
+    name: "RFC",
+    analysis_system: r#"You are a principal engineer preparing to write an RFC (design document) for a proposed feature.
+
+Analyze the provided codebase report and gather the material the RFC needs. Do not write the RFC yet.
+
+Your response should include:
+1. The current state - how the system handles this area today, with the files and functions involved
+2. The underlying problem and who is affected, as evidenced by the code (limitations, workarounds, missing extension points)
+3. Constraints the design must respect - existing interfaces, data models, dependencies, deployment and compatibility requirements
+4. Candidate designs, including at least one credible alternative, with how each fits the existing architecture
+5. Migration, rollout, and backward-compatibility concerns
+6. Open questions that the code cannot answer
+
+Ground every statement in the actual code. If the codebase contains existing RFCs or design documents, note their conventions."#,
+    request_label: "RFC Request",
+    analysis_label: "Design Research",
+    detail_system: r#"You are a principal engineer writing an RFC (design document) for review by your team.
+
+Using the codebase report, the RFC request, and the design research, write a complete RFC. This is a design document for discussion and decision, not an implementation plan: explain the problem, the proposed design and its trade-offs, and why it was chosen over the alternatives, at the level of detail reviewers need to approve it.
+
+If the request includes a team template, follow it exactly: keep its headings, their order, and any front matter or metadata fields, fill in every section, and follow the instructions written in it. Otherwise, if the codebase contains an RFC template or earlier RFCs, follow their structure and conventions. Otherwise use these sections:
+1. Summary
+2. Problem statement and motivation
+3. Goals and non-goals
+4. Detailed design - components, interfaces, data model and API changes, with the existing files and modules they touch
+5. Alternatives considered, and why each was rejected
+6. Rollout - migration, backward compatibility, feature gating, and how to roll back
+7. Risks and mitigations, including security, performance, and operational impact
+8. Unresolved questions
+
+Refer to the actual code when describing current behavior and affected areas. Use short code or interface sketches only where they clarify the design. Format the document in markdown."#,
+    detail_instruction: "Now write the complete RFC, following the team template if one was provided.",
+    manifest: false,
+};
+
+pub const ISSUE_CLUSTERING: PipelinePrompts = PipelinePrompts {
+    name: "issue clustering",
+    analysis_system: r#"You are a senior maintainer triaging a batch of issue reports against the codebase they were filed on.
+
+For each issue, use the codebase report to determine which code areas it implicates: the modules, files, and functions whose behavior the issue describes, whether it is a bug report, feature request, or question, and the most likely underlying cause for bugs.
+
+Your response should include, for every issue by its ID:
+1. The implicated code areas, with file paths and functions
+2. The issue type and a one-sentence restatement of the actual problem
+3. The probable root cause (for bugs), grounded in the code
+4. Any other issues in the batch that describe the same symptom or the same code path
+
+Base every mapping on the actual code. If an issue cannot be mapped to any code in the report, say so rather than guessing."#,
+    request_label: "Issues",
+    analysis_label: "Per-Issue Code Mapping",
+    detail_system: r#"You are a senior maintainer producing a triage report for a batch of issues.
+
+Using the codebase report, the issues, and the per-issue code mapping, group the issues into clusters by the code area and underlying cause they share.
+
+Your response MUST include:
+1. Clusters - for each cluster: a short name, the shared code area (file paths and functions), the shared root cause or theme, and the member issue IDs
+2. Probable duplicates - sets of issues that describe the same problem, with the issue that should be kept open and why
+3. Shared root causes - cases where distinct symptoms trace back to one defect, with the evidence from the code
+4. Unclustered issues - issues that stand alone or could not be mapped to code, with the reason
+5. Suggested triage order - which clusters to address first, weighing the number of issues, severity, and how contained the fix is
+
+Every issue in the batch must appear in exactly one cluster or in the unclustered list. Format your response in clear markdown sections."#,
+    detail_instruction: "Now provide the triage report: clusters by shared code area and root cause, probable duplicates, shared root causes, unclustered issues, and a suggested triage order.",
+    manifest: false,
+};
+
+pub const DEPENDENCY_AUDIT: PipelinePrompts = PipelinePrompts {
+    name: "dependency audit",
+    analysis_system: r#"You are a senior engineer responsible for the dependency health and supply-chain security of a codebase.
+
+You are given a codebase report and the parsed dependency manifests: every declared dependency with its version requirement, kind (normal, dev, build, optional, peer), source (registry, git, or path), whether its name appears anywhere in the code, and the graph of internal packages.
+
+Analyze how each dependency is actually used. Your response should include:
+1. For each significant dependency, what the code uses it for and how deeply (one call site or woven through the codebase), citing files
+2. Dependencies that appear unused or redundant - including those marked as not referenced (verify against the code; import names can differ from package names) and pairs that provide overlapping functionality
+3. Version requirements that are unusually loose, unusually tight, pinned to exact versions, or pulled from git or local paths, and whether that looks deliberate
+4. Dependencies you know to be outdated, unmaintained, deprecated, or affected by known security advisories as of your knowledge, stating your uncertainty
+5. Inconsistencies across manifests - the same dependency at different versions, or duplicated functionality across packages
+
+Ground every usage claim in the code. Do not claim a package is unused without checking for it."#,
+    request_label: "Audit Request and Dependency Manifests",
+    analysis_label: "Dependency Usage Analysis",
+    detail_system: r#"You are a senior engineer producing a dependency audit report for the maintainers of a codebase.
+
+Using the codebase report, the dependency manifests, and the usage analysis, produce an actionable audit.
+
+Your response MUST include:
+1. Upgrade risks - for each dependency worth upgrading: the current requirement, the target, the breaking changes that affect this codebase (with the affected files), and a risk rating
+2. Unused or redundant dependencies - what to remove or consolidate, with the evidence, and any that only look unused (macros, plugins, side-effect imports, runtime loading)
+3. Security-relevant pins - exact pins, git/path sources, overly loose requirements on security-sensitive packages, and packages with known advisories, with a recommendation for each
+4. Manifest hygiene - version drift across manifests, misclassified dev/normal dependencies, missing lockfiles
+5. Suggested order of work - which changes to make first, grouped into independently shippable batches
+
+Be explicit about which findings depend on your training knowledge (release history, advisories) and should be confirmed with a tool such as cargo audit, npm audit, or pip-audit. Format your response in clear markdown sections."#,
+    detail_instruction: "Now provide the dependency audit: upgrade risks, unused or redundant dependencies, security-relevant pins, manifest hygiene, and the suggested order of work.",
+    manifest: false,
+};
+
+pub const MULTITENANCY_PLAN: PipelinePrompts = PipelinePrompts {
+    name: "multi-tenancy plan",
+    analysis_system: r#"You are a principal engineer who has converted single-tenant applications into multi-tenant SaaS products.
+
+Analyze the provided codebase report, paying close attention to the persistence and auth files (schemas, migrations, models, repositories, authentication and authorization code, sent ahead of the report), and map what the application assumes about having a single tenant.
+
+Your response should include:
+1. The persistence layer - databases, ORM or query layer, every table or collection with the code that reads and writes it, and caches, search indexes, queues, and file storage that hold customer data
+2. The auth layer - how users are authenticated, where the current user is resolved, how permissions are checked, and where a tenant or organization concept could attach (existing accounts, organizations, or workspaces)
+3. Tenant ID threading - the request entry points (HTTP handlers, jobs, consumers, CLI commands) and the call paths from them to data access, and where request-scoped context is or could be carried
+4. Global state that would leak between tenants - singletons, in-memory caches, global configuration, unique constraints, sequences, and background jobs that iterate over all data
+5. Data that is genuinely shared across tenants (reference data, system configuration) and must stay global
+
+Ground every statement in the actual code. If a layer is absent (e.g., no auth), say so explicitly."#,
+    request_label: "Multi-Tenancy Request",
+    analysis_label: "Single-Tenancy Analysis",
+    detail_system: r#"You are a principal engineer writing the plan to make a single-tenant application multi-tenant.
+
+Using the codebase report, multi-tenancy request, and single-tenancy analysis, produce an actionable plan.
+
+Your response MUST include:
+1. Data isolation strategy - shared schema with a tenant ID column, schema per tenant, or database per tenant - with the recommendation and its trade-offs for this codebase (isolation, cost, migrations, noisy neighbors, compliance), honoring any strategy the request specifies
+2. Tenant ID threading - how the tenant is resolved per request (subdomain, header, token claim) and carried to data access, with code for the resolution middleware and the request context
+3. Data access changes - every repository, query, and model that must be scoped, with code, plus a safeguard against unscoped queries (e.g., row-level security, a scoped query builder, or a default ORM filter)
+4. Auth changes - tenant membership, roles per tenant, tenant claims in sessions or tokens, and cross-tenant access checks
+5. Other tenant-aware state - cache keys, file storage paths, queues and background jobs, search indexes, unique constraints, and rate limits
+6. Migration steps - schema migrations, backfilling existing data into a default tenant, and a rollout order that keeps the current single-tenant deployment working throughout
+7. Tests - tenant isolation tests that prove one tenant cannot read or write another's data
+
+Format your response in clear sections with markdown. Provide actual code, not pseudocode."#,
+    detail_instruction: "Now provide the detailed multi-tenancy plan with the isolation strategy, tenant threading code, scoped data access, auth changes, migration steps, and isolation tests.",
+    manifest: true,
+};
+
+pub const RATE_LIMITING_PLAN: PipelinePrompts = PipelinePrompts {
+    name: "rate limiting plan",
+    analysis_system: r#"You are a senior backend engineer who designs rate limiting for production APIs.
+
+Analyze the provided codebase report, paying close attention to the entry point files (dependency manifests, routers, handlers, controllers, and middleware, sent ahead of the report), and map the traffic the application accepts.
+
+Your response should include:
+1. The framework and runtime - the web framework, its version from the dependency manifests, and how middleware is registered (layers, interceptors, decorators, filters)
+2. Every entry point - HTTP routes with their methods, plus GraphQL, gRPC, WebSocket, webhook, and queue consumers - with the handler file and what each one costs (database queries, outbound calls, LLM or other paid APIs, file uploads)
+3. Caller identity - how clients are identified (API keys, sessions, tokens, IP addresses) and where that identity becomes available in the request pipeline
+4. Existing limits - any rate limiting, throttling, quotas, timeouts, or body size limits already in place, in code or in proxy/gateway configuration
+5. Shared infrastructure - caches and data stores (e.g., Redis) that could hold limiter state, and whether the application runs as multiple instances
+6. The most abuse-prone or expensive routes (login, password reset, signup, search, exports, uploads)
+
+Ground every statement in the actual code. If the application has no network entry points, say so explicitly."#,
+    request_label: "Rate Limiting Request",
+    analysis_label: "Entry Point Analysis",
+    detail_system: r#"You are a senior backend engineer writing the rate limiting design for this application.
+
+Using the codebase report, rate limiting request, and entry point analysis, produce an actionable design.
+
+Your response MUST include:
+1. Algorithm - token bucket, leaky bucket, fixed window, sliding window log, or sliding window counter - with the recommendation and its trade-offs for this traffic (burst tolerance, accuracy, memory), honoring any constraints the request specifies
+2. Storage - in-process memory versus a shared store such as Redis, chosen from whether the application runs as multiple instances, with atomic update logic (e.g., a Lua script or an atomic increment with expiry) and behavior when the store is unavailable (fail open or fail closed, per route)
+3. Keys and budgets - the limiter key (API key, user, tenant, IP, or a combination) and a table of per-route budgets with their windows and burst sizes, stricter for expensive and abuse-prone routes, plus any global limit
+4. Middleware - complete, working code for the detected framework, using its idiomatic extension point and a well-maintained rate limiting library already compatible with the project's dependencies where one exists, and how it is registered on each route group
+5. Responses - the 429 status, Retry-After and RateLimit headers, and the error body, consistent with the application's existing error format
+6. Configuration and observability - how budgets are configured per environment, exemptions for internal callers and health checks, and logging and metrics for rejected requests
+7. Tests - tests that exhaust a budget, verify the 429 response and headers, and verify the budget resets after its window
+
+Format your response in clear sections with markdown. Provide actual code, not pseudocode."#,
+    detail_instruction: "Now provide the detailed rate limiting design with the algorithm, storage, per-route budget table, middleware code for the detected framework, response headers, configuration, and tests.",
+    manifest: true,
+};
+
+pub const AUTH_OVERHAUL_PLAN: PipelinePrompts = PipelinePrompts {
+    name: "auth overhaul plan",
+    analysis_system: r#"You are a security engineer who has migrated production applications between authentication and authorization systems.
+
+Analyze the provided codebase report, paying close attention to the authentication and authorization files (login, session, token, identity, permission, and middleware code, sent ahead of the report), and map the existing auth flow end to end.
+
+Your response should include:
+1. Authentication - every way a caller proves who they are (passwords, API keys, OAuth/OIDC or SAML providers, magic links, service credentials), with the files that implement each, the libraries used, and how credentials are stored and verified
+2. Sessions and tokens - what is issued after login (server-side sessions, signed cookies, JWTs, refresh tokens), where it is stored, its lifetime and rotation, how it is validated on each request, and how logout and revocation work
+3. Identity model - the user, account, organization, and membership models, the identifiers other data references, and any linkage to external identities
+4. Authorization - where access decisions are made (middleware, decorators, guards, inline checks, database policies), the roles or permissions that exist, and routes or operations with no check at all
+5. Entry points and clients - web pages, APIs, mobile or CLI clients, service-to-service calls, and background jobs, and which auth mechanism each relies on
+6. Weaknesses relevant to the requested change - hard-coded secrets, missing CSRF protection, unbounded sessions, inconsistent checks, and logic duplicated across handlers
+
+Ground every statement in the actual code. If a layer is absent (e.g., no authorization beyond being logged in), say so explicitly."#,
+    request_label: "Auth Change Request",
+    analysis_label: "Current Auth Flow Analysis",
+    detail_system: r#"You are a security engineer writing the migration plan for an authentication or authorization overhaul (e.g., adding SSO, moving to OAuth2/OIDC, or introducing RBAC).
+
+Using the codebase report, auth change request, and current auth flow analysis, produce a staged plan that never locks out existing users.
+
+Your response MUST include:
+1. Target design - the end state for authentication, sessions or tokens, and authorization, with the protocol flows (e.g., authorization code with PKCE), the identity provider or library to use (preferring ones compatible with the project's dependencies), and how it maps onto the current identity model
+2. Staged migration - ordered, independently deployable stages, each with its code changes, how the old and new mechanisms run side by side, the compatibility window, and the criteria for moving to the next stage
+3. Session handling - what happens to sessions and tokens issued before each stage (honored until expiry, exchanged, or revoked), token and cookie formats, lifetimes, refresh and rotation, logout and revocation across both mechanisms, and CSRF protection
+4. Account linking and data migration - matching existing users to external identities, handling conflicts and unverified emails, and schema changes with backfills; for RBAC, the role and permission model and how existing users are assigned roles
+5. Authorization changes - where checks move, a central enforcement point, and a default-deny safeguard for routes that are not covered
+6. Clients and integrations - changes for each client type, API keys and service accounts, and communication to users
+7. Rollback - how each stage is reverted without invalidating sessions created since it shipped
+8. Tests - tests for each flow, for mixed old and new sessions during the compatibility window, and for authorization checks on every protected route
+
+Format your response in clear sections with markdown. Provide actual code, not pseudocode."#,
+    detail_instruction: "Now provide the staged auth migration plan with the target design, stages and compatibility windows, session handling, account linking, authorization changes, client changes, rollback, and tests.",
+    manifest: true,
+};
+
+pub const LLM_PROMPT_REVIEW: PipelinePrompts = PipelinePrompts {
+    name: "LLM prompt review",
+    analysis_system: r#"You are a senior engineer who builds and secures applications that call large language models.
+
+Analyze the provided codebase report, paying close attention to the files that call LLM APIs or define prompts (sent ahead of the report), and inventory the repository's AI integration.
+
+Your response should include:
+1. Every prompt - system prompts, templates, few-shot examples, and prompts assembled at runtime - with its file, its purpose, and where its inputs come from
+2. LLM call sites - provider and SDK, model names, parameters (temperature, max tokens, response format, tools), streaming, and how responses are parsed and used
+3. Untrusted data flowing into prompts - user input, retrieved documents, web content, tool results, file contents - and whether it is delimited, escaped, or mixed with instructions
+4. What the model's output is allowed to do - rendered as HTML, executed, used in queries or shell commands, trigger tool calls or writes - and what validation happens first
+5. Cost drivers - context sizes, repeated or unbounded calls, retries, model choice per task, and missing caching or truncation
+
+Ground everything in the actual code. Quote prompt text only as much as needed to make a point."#,
+    request_label: "Review Request",
+    analysis_label: "AI Integration Inventory",
+    detail_system: r#"You are a senior engineer reviewing the LLM prompts and AI integration code of a repository for its maintainers.
+
+Using the codebase report, review request, and integration inventory, produce an actionable review.
+
+Your response MUST include:
+1. Prompt injection risks - each place untrusted input can override instructions or exfiltrate data, the potential impact given what the output can do, and a concrete mitigation (delimiting, separate message roles, output validation, least-privilege tools), ranked by severity
+2. Cost pitfalls - oversized or duplicated context, unbounded loops or retries, expensive models on simple tasks, and missing caching, with an estimate of the saving for each fix
+3. Brittleness - parsing of free-form output that should use structured output or a schema, missing handling for refusals, truncation, and malformed responses, hard-coded model names, and prompts that depend on one model's quirks
+4. Prompt quality - ambiguous or conflicting instructions, missing output format specifications, and examples that do not match the instructions
+5. Proposed improvements - rewritten prompts or code changes for the most important findings, as before/after snippets with file references
+6. Suggested order of work
+
+Reference the actual files and functions involved. Format your response in clear markdown sections."#,
+    detail_instruction: "Now provide the review: prompt injection risks, cost pitfalls, brittleness, prompt quality, proposed improvements with before/after snippets, and the suggested order of work.",
+    manifest: false,
+};
+
+pub const POST_INCIDENT_REVIEW: PipelinePrompts = PipelinePrompts {
+    name: "post-incident review",
+    analysis_system: r#"You are a site reliability engineer who runs blameless post-incident reviews for production services.
+
+Analyze the provided codebase report and incident record (a timeline, logs, alerts, and notes), paying close attention to the logging, metrics, alerting, health check, retry, timeout, and configuration files (sent ahead of the report), and reconstruct what happened in terms of the code.
+
+Your response should include:
+1. Timeline in code - each event in the record matched to the code path, configuration, or deploy that produced it, with file references, and events that cannot be matched
+2. Failure chain - the trigger, how the failure propagated (callers, retries, queues, shared resources), and what finally limited or ended it
+3. Contributing causes - code defects, missing guards (timeouts, limits, circuit breakers, validation), risky defaults, and configuration, each tied to the files and functions involved and to the evidence in the record
+4. Detection - which signals fired and when, relative to the start of impact; signals the code emits that should have fired earlier; and failure modes the code does not log, measure, or alert on at all
+5. Response - what slowed diagnosis or mitigation: missing context in logs, absent runbooks or feature flags, manual steps, and irreversible operations
+
+Ground every statement in the code and the record. Where the record is incomplete or the cause is uncertain, say so and name what would confirm it rather than guessing."#,
+    request_label: "Incident Record",
+    analysis_label: "Incident Analysis",
+    detail_system: r#"You are a site reliability engineer writing the post-incident review for an incident, for the engineers who own the service.
+
+Using the codebase report, incident record, and incident analysis, write a blameless review that focuses on systems, not people.
+
+Your response MUST include:
+1. Summary - what happened, the impact and its duration, and how it was resolved, in a few sentences
+2. Timeline - the key events with times from the record, annotated with the code or configuration involved
+3. Contributing causes - each cause mapped to the files and functions involved, with the evidence from the record and how confident the mapping is
+4. Detection gaps - for each, the signal that was missing or late, where in the code it should be emitted (log line, metric, health check, alert rule), and the time it would have saved
+5. Preventive changes - a prioritized list of code and configuration changes (fixes, guards, limits, timeouts, alerts, tests that reproduce the failure), each with its priority (P0 to P2), the cause or gap it addresses, the files to change, and a code or config snippet
+6. Open questions - what the record does not settle and the data that would settle it
+
+Format your response in clear sections with markdown. Order preventive changes by priority, then by effort."#,
+    detail_instruction: "Now provide the post-incident review: summary, timeline, contributing causes mapped to code, detection gaps, prioritized preventive code and config changes, and open questions.",
+    manifest: false,
+};
+
+pub const SPOF_ANALYSIS: PipelinePrompts = PipelinePrompts {
+    name: "single point of failure analysis",
+    analysis_system: r#"You are a site reliability engineer who reviews service architectures for resilience before they fail in production.
+
+Analyze the provided codebase report, paying close attention to the external call sites, resilience code, and configuration and deployment files (sent ahead of the report), and map what the system depends on to keep working.
+
+Your response should include:
+1. Dependency inventory - every external dependency the code calls (databases, caches, queues, HTTP and gRPC services, third-party APIs, file and object storage, DNS, secrets and config stores), with the files and functions that call it and whether the call sits on a request path, a background job, or startup
+2. Resilience per call site - the timeout (connect and overall, or none), retries (count, backoff, jitter, idempotency), circuit breakers, bulkheads or connection pool limits, fallbacks or cached responses, and error handling, each as configured in code or configuration, with file references
+3. Redundancy - components deployed as a single instance or without failover (replica counts, single database or broker nodes, one region or zone), leader or singleton jobs, in-process state that prevents scaling out, and shared resources every request goes through
+4. Failure propagation - for each dependency, what happens to callers when it is slow, down, or returning errors: blocked threads or event loops, exhausted pools, retry storms, cascading timeouts, and crashes at startup
+5. Observability of failure - health and readiness checks and whether they test dependencies, and the logs, metrics, and alerts that would reveal each failure
+
+Ground everything in the actual code and configuration. Where a setting falls back to a library default, say which default applies and that it is one."#,
+    request_label: "Review Request",
+    analysis_label: "Dependency and Resilience Inventory",
+    detail_system: r#"You are a site reliability engineer reporting the single points of failure in a system to the engineers who own it.
+
+Using the codebase report, review request, and dependency inventory, produce a reliability report.
+
+Your response MUST include:
+1. Single points of failure - each component or dependency whose failure takes down the system or a critical path, ranked by likelihood times impact, with the evidence in code or configuration, what fails with it, and the blast radius
+2. Missing resilience patterns - each call site lacking a timeout, bounded and jittered retries, a circuit breaker, a bulkhead, a fallback, or idempotency where it needs one, with the file and function, the failure mode it leaves open, and its severity
+3. Remediation - for each finding, the change to make with code in the codebase's language and libraries (or the resilience library to adopt, such as resilience4j, Polly, tenacity, or tower), configuration or deployment changes such as replicas, failover, and health checks, and the trade-offs
+4. Verification - how to prove each fix works: fault injection or chaos experiments, tests with a stubbed dependency that hangs or errors, and the metrics and alerts to watch
+5. Suggested order of work - quick wins first, then by risk reduced per unit of effort
+
+Reference the actual files and functions involved. Format your response in clear markdown sections."#,
+    detail_instruction: "Now provide the reliability report: single points of failure ranked by risk, missing resilience patterns by call site, remediation with code and configuration, verification, and the suggested order of work.",
+    manifest: false,
+};
+
+pub const CHANGE_SUMMARY: PipelinePrompts = PipelinePrompts {
+    name: "change summary",
+    analysis_system: r#"You are a senior engineer who maintains a project's commit history and release notes.
+
+Analyze the provided codebase report and the changes in a git range (the commit log, a diffstat, and the diff), paying close attention to any changelog, contributing guide, or commit convention configuration (sent ahead of the report), and work out what the changes do.
+
+Your response should include:
+1. Logical changes - the distinct changes in the range, each with the files and commits involved, grouping commits that belong together and splitting commits that mix unrelated work
+2. Classification - for each change, its Conventional Commits type (feat, fix, perf, refactor, docs, test, build, ci, chore, style, revert) and a scope taken from the module or package it touches
+3. User-visible effect - what users, API consumers, or operators will notice, or that the change is internal only
+4. Breaking changes - removed or renamed public APIs, changed defaults, configuration or schema changes, and new requirements, with what users must do
+5. Conventions - the project's existing commit message and changelog style (format, scopes, headings, tense, issue references), or that none is evident
+
+Ground everything in the diff. Where a commit message and its diff disagree, trust the diff and say so."#,
+    request_label: "Changes",
+    analysis_label: "Change Analysis",
+    detail_system: r#"You are a senior engineer writing the commit messages and changelog entry for a set of changes.
+
+Using the codebase report, the changes, and the change analysis, write text that can be pasted as is. Follow the project's own conventions where they exist; otherwise use Conventional Commits 1.0 and Keep a Changelog.
+
+Your response MUST include:
+1. Commit messages - one conventional-commit message per logical change, in a fenced code block each: a `type(scope): subject` header in the imperative mood under 72 characters, a body explaining what changed and why, wrapped at 72 columns, and a `BREAKING CHANGE:` footer where one applies
+2. Squash message - a single message for the whole range, for squash merges
+3. Changelog section - a markdown section headed with the version (or `Unreleased`) and grouped under Added, Changed, Deprecated, Removed, Fixed, and Security, written for users rather than developers, with internal-only changes left out and breaking changes listed first
+4. Notes - commits whose messages misdescribe their diff, changes that look unfinished or accidental, and diffs that were left out for size
+
+Do not invent changes that are not in the diff."#,
+    detail_instruction: "Now provide the conventional-commit messages, the squash message, the changelog section, and notes.",
+    manifest: false,
+};
+
+pub const DOCUMENTATION: PipelinePrompts = PipelinePrompts {
+    name: "documentation",
+    analysis_system: r#"You are a senior engineer and technical writer documenting a codebase for the developers who will maintain it.
+
+Analyze the provided codebase report and work out what needs documenting and what the documentation must say.
+
+Your response should include:
+1. The purpose of the project and of each module/package, and how the modules fit together
+2. The public API of each module - types, functions, and their contracts (inputs, outputs, errors, side effects, invariants)
+3. Non-obvious behavior worth a doc comment: concurrency, lifetimes/ownership, performance characteristics, panics, and surprising edge cases
+4. How to build, configure, run, and test the project, from the actual manifests, scripts, and configuration code
+5. The documentation conventions already in use (doc comment syntax, existing READMEs and docs), and existing documentation that is missing, stale, or wrong
+
+Describe only what the code actually does. Do not invent features, options, or guarantees."#,
+    request_label: "Documentation Request",
+    analysis_label: "Documentation Analysis",
+    detail_system: r#"You are a senior engineer and technical writer producing documentation for a codebase.
+
+Using the codebase report, the documentation request, and the analysis, write the documentation itself.
+
+Your response MUST include:
+1. Module-level documentation for each module/package in scope, in the language's native form (e.g. `//!` for Rust, package docstrings for Python and Go)
+2. Doc comments for public types and functions that lack them or whose existing comments are stale, in the language's native doc comment syntax, with the exact item each attaches to
+3. A README draft - overview, features, installation, configuration, usage examples, and development instructions. If the project already has a README.md, write the draft as README.draft.md instead of replacing it
+4. Notes on any existing documentation that contradicts the code
+
+Follow the doc conventions the project already uses. Documentation must not change behavior: never alter code other than adding or replacing comments. Keep comments concise and accurate rather than exhaustive."#,
+    detail_instruction: "Now write the documentation: module-level docs, doc comments for public items, a README draft, and notes on documentation that contradicts the code.",
+    manifest: false,
+};
+
+pub const TECH_DEBT_HOTSPOTS: PipelinePrompts = PipelinePrompts {
+    name: "tech debt report",
+    analysis_system: r#"You are a principal engineer assessing where technical debt is costing a team the most.
+
+You are given a codebase report and a table of per-file metrics: commit count over the measured period (churn), non-blank lines, branch points, and maximum nesting depth, with a combined score. Files that are both complex and frequently changed are the strongest hotspot candidates, but the metrics are heuristics - confirm them against the code.
+
+Your response should include, for each of the highest-scoring files and any other files you find to be problematic:
+1. What the file is responsible for and why it changes often
+2. Concrete debt indicators in the code - long functions, deep nesting, duplicated logic, mixed responsibilities, missing abstractions, weak error handling, missing tests
+3. Files whose metrics overstate the problem (e.g. generated code, data tables, simple but long files), and why
+4. Coupling between hotspots - files that change together or depend on each other's internals
+
+Cite the specific functions and code involved. Do not invent problems the code does not have."#,
+    request_label: "Hotspot Request and Metrics",
+    analysis_label: "Hotspot Analysis",
+    detail_system: r#"You are a principal engineer producing a tech-debt hotspot report for a team deciding where to invest refactoring time.
+
+Using the codebase report, the metrics, and the hotspot analysis, rank the hotspots by the cost the debt imposes on ongoing development.
+
+Your response MUST include:
+1. Ranked hotspots - for each: file path, rank, the metrics that flagged it, and a justification grounded in the code (specific functions and patterns)
+2. Suggested remediation for each hotspot - the concrete refactoring, its scope, and the risk of doing it
+3. Remediation order - the sequence to tackle the hotspots in, weighing payoff against effort and noting which refactorings unblock others
+4. Quick wins - small, low-risk changes with disproportionate payoff
+5. Files deliberately excluded despite high scores, and why
+
+Format your response in clear markdown sections."#,
+    detail_instruction: "Now provide the tech-debt hotspot report: ranked hotspots with justification, suggested remediation for each, the remediation order, quick wins, and excluded files.",
+    manifest: false,
+};
+
+pub const PATCH_GENERATION: PipelinePrompts = PipelinePrompts {
+    name: "patch",
+    analysis_system: r#"You are a senior software engineer preparing a precise code change.
+
+Analyze the codebase report and the requested change, and determine exactly what must be edited.
+
+Your response should include:
+1. The exact files that must change, and any files that must be created or deleted
+2. For each file, the specific functions, types, or blocks to edit, quoting the current code verbatim
+3. The intended new behavior of each edit
+4. Any follow-on changes required for the code to compile and tests to pass (imports, call sites, tests)
+
+Be exhaustive about call sites and keep the change minimal and focused."#,
+    request_label: "Change Request",
+    analysis_label: "Change Analysis",
+    detail_system: r#"You are a senior software engineer producing a ready-to-apply patch.
+
+Using the codebase report, change request, and change analysis, write the complete change as a patch against the current files. Include every edit needed for the change to compile and work, including updated or new tests."#,
+    detail_instruction: "Now produce the unified diff implementing the change.",
+    manifest: false,
+};
+
+pub(crate) const SELF_EVALUATION_SYSTEM: &str = r#"You are a strict reviewer grading an AI-generated response to a software engineering request.
+
+Grade how well the response answers the request on a scale of 1 to 10, considering:
+- Completeness: does it address every part of the request?
+- Specificity: does it reference concrete files, functions, and code rather than generic advice?
+- Internal consistency: do the steps and code fit together, without contradictions or placeholders?
+- Confidence: does the response hedge, refuse, or admit it lacks information?
+
+Respond with ONLY a JSON object: {"score": <1-10>, "reason": "<one sentence>"}"#;
+
+pub(crate) const FOLLOW_UP_SYSTEM: &str = r#"You are a principal engineer continuing a conversation about a codebase. The first message contains the codebase report and the original request, followed by your previous answers and the user's follow-up questions.
+
+Answer the latest question directly, building on your previous answers rather than repeating them. Reference specific files and code from the codebase report, include code snippets where helpful, and say explicitly if the report does not contain enough information to answer. Use markdown formatting."#;
+
+pub(crate) const CRITIC_SYSTEM: &str = r#"You are a meticulous principal engineer reviewing a draft response before it is delivered.
+
+Check the draft against the codebase report and the original request: incorrect claims about the code, references to files, functions, or APIs that do not exist, missing steps or affected files, internal contradictions, and code that would not compile or work.
+
+Return the final, corrected response in full - not a list of review comments. Keep everything in the draft that is correct, fix what is wrong, and fill in what is missing. The final response must satisfy the original instructions below, including their output format."#;
+
+pub(crate) const CANDIDATE_JUDGE_SYSTEM: &str = r#"You are a principal engineer choosing between independently written candidate responses to the same request.
+
+Compare the candidates against the codebase report and the request: which approach fits the existing code best, which claims about the code are correct, and which steps or affected areas each one misses.
+
+Return the single best response in full - not a comparison. Select the strongest candidate, or merge candidates when combining their parts is clearly better, correcting anything wrong and keeping the approach coherent. The response must satisfy the original instructions below, including their output format."#;
+
+pub(crate) const VERIFY_SYSTEM: &str = r#"You are a meticulous principal engineer verifying a finished response against the codebase it is about. Do not rewrite the response.
+
+Check it against the codebase report:
+1. Every file, module, and path it references exists in the report (new files it proposes to create excepted)
+2. Every function, type, method, and API it calls or changes exists with the signature it assumes
+3. Its steps are in a workable order: nothing is used before the step that creates it, and migrations, dependencies, and configuration come before the code that needs them
+4. Its claims about how the existing code behaves are correct
+
+Return only a markdown list of corrections, one per problem, each naming the part of the response it corrects, what is wrong, and what is right according to the codebase report. Do not repeat correct parts or add new suggestions. If everything checks out, return exactly: None"#;
+
+// Heading of the section the verification pass appends.
+const CORRECTIONS_HEADING: &str = "## Corrections";
+
+const MIN_CONFIDENT_CHARS: usize = 800;
+
+pub const DEFAULT_PASSES: u8 = 2;
+
+// Upper bound on `candidates`; each one re-sends the full context.
+pub const MAX_CANDIDATES: u8 = 5;
+
+// Rough ratio for source code and English prose, used to estimate a call's
+// token usage before anything is sent.
+pub const CHARS_PER_TOKEN: u64 = 4;
+
+// Output tokens reserved for an analysis pass when estimating a call; the
+// detail pass's reserve depends on the verbosity.
+const ANALYSIS_RESERVE_TOKENS: u64 = 4_000;
+
+// Worst-case token usage of `run_pipeline` for a context of a given size.
+pub struct PipelineEstimate {
+    pub context_tokens: u64,
+    // How many passes receive the full context.
+    pub context_sends: u64,
+    // Prompts, instructions, earlier passes' output, and reserved output.
+    pub fixed_tokens: u64,
+    // 2 with escalation, where a rejected fast-model draft is redone in full.
+    pub runs: u64,
+}
+
+impl PipelineEstimate {
+    pub fn total(&self) -> u64 {
+        (self.context_tokens * self.context_sends + self.fixed_tokens) * self.runs
+    }
+
+    // The largest context, in characters, whose estimate fits `budget`, or
+    // None if the prompts and reserves alone exceed it.
+    pub fn context_chars_within(&self, budget: u64) -> Option<usize> {
+        let per_run = budget / self.runs;
+        let available = per_run.checked_sub(self.fixed_tokens).filter(|&t| t > 0)?;
+        Some((available / self.context_sends * CHARS_PER_TOKEN) as usize)
+    }
+}
+
+// Stand-ins for earlier passes' output in a `PipelinePreview`.
+const ANALYSIS_PLACEHOLDER: &str = "[output of the analysis pass]";
+const CANDIDATE_PLACEHOLDER: &str = "[output of one candidate analysis]";
+const DRAFT_PLACEHOLDER: &str = "[output of the detail pass]";
+const RESPONSE_PLACEHOLDER: &str = "[final response]";
+
+pub struct PassPreview {
+    pub pass: &'static str,
+    pub system: String,
+    pub user: Segments,
+}
+
+pub struct PipelinePreview {
+    // The model each run would use; with escalation, the fast model first.
+    pub models: Vec<String>,
+    pub passes: Vec<PassPreview>,
+}
+
+struct ChatMessage {
+    role: &'static str,
+    content: Segments,
+}
+
+impl ChatMessage {
+    fn system(content: &str) -> Self {
+        Self { role: "system", content: content.into() }
+    }
+}
+
+// A chat completion request whose JSON body is written while it is sent, so
+// the codebase report is never copied into a serialized request (once per
+// attempt) on top of the messages that share it.
+struct ChatRequest {
+    model: String,
+    messages: Vec<ChatMessage>,
+    generation: GenerationParams,
+}
+
+impl ChatRequest {
+    fn head(&self) -> Vec<u8> {
+        let model = serde_json::to_string(&self.model).unwrap_or_default();
+        let mut fields = String::new();
+        let generation = &self.generation;
+        if let Some(temperature) = generation.temperature {
+            fields.push_str(&format!(r#""temperature":{temperature},"#));
+        }
+        if let Some(top_p) = generation.top_p {
+            fields.push_str(&format!(r#""top_p":{top_p},"#));
+        }
+        if let Some(max_tokens) = generation.max_tokens {
+            fields.push_str(&format!(r#""max_tokens":{max_tokens},"#));
+        }
+        if let Some(stop) = &generation.stop {
+            fields.push_str(&format!(r#""stop":{},"#, serde_json::to_string(stop).unwrap_or_default()));
+        }
+        format!(r#"{{"model":{model},{fields}"messages":["#).into_bytes()
+    }
+
+    fn message_head(i: usize, role: &str) -> Vec<u8> {
+        let separator = if i > 0 { "," } else { "" };
+        format!(r#"{separator}{{"role":"{role}","content":""#).into_bytes()
+    }
+
+    const MESSAGE_TAIL: &'static [u8] = b"\"}";
+    const TAIL: &'static [u8] = b"]}";
+
+    fn body_len(&self) -> usize {
+        let messages: usize = self.messages
+            .iter()
+            .enumerate()
+            .map(|(i, m)| Self::message_head(i, m.role).len() + m.content.json_len() + Self::MESSAGE_TAIL.len())
+            .sum();
+        self.head().len() + messages + Self::TAIL.len()
+    }
+
+    fn body(&self) -> impl Iterator<Item = Vec<u8>> + Send + 'static {
+        let messages: Vec<_> = self.messages
+            .iter()
+            .enumerate()
+            .map(|(i, m)| {
+                std::iter::once(Self::message_head(i, m.role))
+                    .chain(m.content.json_chunks())
+                    .chain(std::iter::once(Self::MESSAGE_TAIL.to_vec()))
+            })
+            .collect();
+        std::iter::once(self.head())
+            .chain(messages.into_iter().flatten())
+            .chain(std::iter::once(Self::TAIL.to_vec()))
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct PipelineOptions<'a> {
+    pub format: OutputFormat,
+    // 1 = detail only, 2 = analysis then detail, 3 = adds a critic pass.
+    pub passes: u8,
+    // Independent analyses generated before the detail pass, then selected
+    // or merged by a judging pass; 1 = a single analysis.
+    pub candidates: u8,
+    // Adds a pass that checks the final response against the full context
+    // and appends its corrections.
+    pub verify: bool,
+    // When set, passes after the analysis get only the files it referenced
+    // instead of the full context.
+    pub compact: Option<CompactContext<'a>>,
+    pub messages: MessageTemplates,
+    // Detail and output budget of the final pass.
+    pub verbosity: Verbosity,
+    // Sampling and output settings, resolved from the request, the tool's
+    // defaults, and the server's (`GenerationDefaults::resolve`).
+    pub generation: &'a GenerationParams,
+}
+
+// Generation parameters sent with chat requests; unset fields are left to the
+// provider. Temperature and top_p apply to every pass of a pipeline;
+// max_tokens and stop only to the passes that write the response (detail and
+// critic), since earlier passes' output is intermediate.
+#[derive(Clone, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct GenerationParams {
+    #[schemars(description = "Sampling temperature, 0 to 2. Lower is more deterministic.")]
+    pub temperature: Option<f32>,
+    #[schemars(description = "Nucleus sampling: only tokens within this cumulative probability, above 0 and at most 1, are considered.")]
+    pub top_p: Option<f32>,
+    #[schemars(description = "Maximum tokens in the response. With a 'verbosity' cap, the smaller applies.")]
+    pub max_tokens: Option<u64>,
+    #[schemars(description = "Up to 4 sequences at which the response stops (the sequence is not included).")]
+    pub stop: Option<Vec<String>>,
+}
+
+pub const MAX_STOP_SEQUENCES: usize = 4;
+
+impl GenerationParams {
+    // Fields set here win over `fallback`'s.
+    pub fn or(&self, fallback: &GenerationParams) -> GenerationParams {
+        GenerationParams {
+            temperature: self.temperature.or(fallback.temperature),
+            top_p: self.top_p.or(fallback.top_p),
+            max_tokens: self.max_tokens.or(fallback.max_tokens),
+            stop: self.stop.clone().or_else(|| fallback.stop.clone()),
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.temperature.is_some_and(|t| !(0.0..=2.0).contains(&t)) {
+            return Err("'temperature' must be between 0 and 2".to_string());
+        }
+        if self.top_p.is_some_and(|p| !(p > 0.0 && p <= 1.0)) {
+            return Err("'top_p' must be above 0 and at most 1".to_string());
+        }
+        if self.max_tokens == Some(0) {
+            return Err("'max_tokens' must be at least 1".to_string());
+        }
+        if let Some(stop) = &self.stop {
+            if stop.len() > MAX_STOP_SEQUENCES || stop.iter().any(String::is_empty) {
+                return Err(format!("'stop' takes at most {MAX_STOP_SEQUENCES} non-empty sequences"));
+            }
+        }
+        Ok(())
+    }
+
+    // e.g. "temperature 0.2, max_tokens 2000", for cache keys and dry runs;
+    // empty when nothing is set.
+    pub fn describe(&self) -> String {
+        let mut parts = Vec::new();
+        if let Some(temperature) = self.temperature {
+            parts.push(format!("temperature {temperature}"));
+        }
+        if let Some(top_p) = self.top_p {
+            parts.push(format!("top_p {top_p}"));
+        }
+        if let Some(max_tokens) = self.max_tokens {
+            parts.push(format!("max_tokens {max_tokens}"));
+        }
+        if let Some(stop) = &self.stop {
+            parts.push(format!("stop {}", serde_json::to_string(stop).unwrap_or_default()));
+        }
+        parts.join(", ")
+    }
+
+    // The settings for passes whose output is intermediate.
+    fn sampling(&self) -> GenerationParams {
+        GenerationParams { temperature: self.temperature, top_p: self.top_p, ..Default::default() }
+    }
+
+    // The settings for passes that write the response, with `max_tokens`
+    // lowered to `cap` (the verbosity's) if that is smaller.
+    fn capped(&self, cap: Option<u64>) -> GenerationParams {
+        let max_tokens = match (self.max_tokens, cap) {
+            (Some(max_tokens), Some(cap)) => Some(max_tokens.min(cap)),
+            (max_tokens, cap) => max_tokens.or(cap),
+        };
+        GenerationParams { max_tokens, ..self.clone() }
+    }
+}
+
+// Server-wide and per-tool generation parameters (GENERATION_* and
+// TOOL_GENERATION_PARAMS).
+#[derive(Default)]
+pub struct GenerationDefaults {
+    pub global: GenerationParams,
+    pub per_tool: HashMap<String, GenerationParams>,
+}
+
+impl GenerationDefaults {
+    // `per_tool` is a JSON object of tool name to parameters, e.g.
+    // `{"explain_code": {"temperature": 0.7}}`.
+    pub fn new(global: GenerationParams, per_tool: Option<&str>) -> anyhow::Result<Self> {
+        global.validate().map_err(|e| anyhow!("Invalid GENERATION_* setting: {e}"))?;
+        let per_tool: HashMap<String, GenerationParams> = match per_tool {
+            Some(json) => serde_json::from_str(json).context("TOOL_GENERATION_PARAMS must be a JSON object of tool name to parameters")?,
+            None => HashMap::new(),
+        };
+        for (tool, params) in &per_tool {
+            if let Err(e) = params.validate() {
+                bail!("Invalid TOOL_GENERATION_PARAMS for '{tool}': {e}");
+            }
+        }
+        Ok(Self { global, per_tool })
+    }
+
+    // The request's parameters, then the tool's, then the server's.
+    pub fn resolve(&self, tool: &str, request: Option<&GenerationParams>) -> GenerationParams {
+        let configured = match self.per_tool.get(tool) {
+            Some(params) => params.or(&self.global),
+            None => self.global.clone(),
+        };
+        match request {
+            Some(request) => request.or(&configured),
+            None => configured,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct CompactContext<'a> {
+    pub root: &'a Path,
+    pub char_limit: usize,
+}
+
+
+pub struct Escalation {
+    pub fast_model: String,
+    pub threshold: u8,
+}
+
+// A model a request degrades to when the previous one in the chain fails
+// after its retries (MODEL_FALLBACKS). Without `api_base`, it is served by
+// the same provider and keys as GEMINI_MODEL.
+pub struct FallbackModel {
+    pub model: String,
+    // OpenAI-compatible endpoint, e.g. https://api.openai.com/v1
+    pub api_base: Option<String>,
+    pub api_key: Option<String>,
+}
+
+impl FallbackModel {
+    // `model` or `model@api_base`; `api_key` is used for entries with an API base.
+    pub fn parse(entry: &str, api_key: Option<&str>) -> Self {
+        match entry.split_once('@') {
+            Some((model, api_base)) => FallbackModel {
+                model: model.trim().to_string(),
+                api_base: Some(api_base.trim().trim_end_matches('/').to_string()),
+                api_key: api_key.map(str::to_string),
+            },
+            None => FallbackModel { model: entry.trim().to_string(), api_base: None, api_key: None },
+        }
+    }
+
+    pub fn label(&self) -> String {
+        match &self.api_base {
+            Some(api_base) => format!("{}@{api_base}", self.model),
+            None => self.model.clone(),
+        }
+    }
+}
+
+// OpenAI-compatible embeddings server (llama.cpp, text-embeddings-inference,
+// Ollama) used instead of Gemini for every embedding, so source code is never
+// sent out of the network to be embedded.
+pub struct EmbeddingSidecar {
+    // e.g. http://localhost:8081/v1
+    pub api_base: String,
+    pub api_key: Option<String>,
+}
+
+pub struct GeminiClient {
+    keys: Arc<KeyPool>,
+    http: reqwest::Client,
+    api_base: String,
+    model: String,
+    escalation: Option<Escalation>,
+    fallbacks: Vec<FallbackModel>,
+    retry: RetryPolicy,
+    fan_out: bool,
+    request_timeout: Duration,
+    usage: UsageTracker,
+    fair_share: Option<FairShare>,
+    embedding_sidecar: Option<EmbeddingSidecar>,
+    audit: Option<AuditLog>,
+    mock: Option<MockLlm>,
+}
+
+impl GeminiClient {
+    pub fn new(api_keys: Vec<String>, model: Option<String>, max_in_flight_per_key: Option<usize>) -> Self {
+        Self {
+            keys: Arc::new(KeyPool::new(api_keys, max_in_flight_per_key)),
+            http: reqwest::Client::new(),
+            api_base: "https://generativelanguage.googleapis.com/v1beta".to_string(),
+            model: model.unwrap_or_else(|| "gemini-2.5-pro".to_string()),
+            escalation: None,
+            fallbacks: Vec::new(),
+            retry: RetryPolicy::default(),
+            fan_out: max_in_flight_per_key.is_some(),
+            request_timeout: Duration::MAX,
+            usage: UsageTracker::new(PriceTable::default()),
+            fair_share: None,
+            embedding_sidecar: None,
+            audit: None,
+            mock: None,
+        }
+    }
+
+    pub fn with_fair_share(mut self, fair_share: FairShare) -> Self {
+        self.fair_share = Some(fair_share);
+        self
+    }
+
+    pub fn with_prices(mut self, prices: PriceTable) -> Self {
+        self.usage = UsageTracker::new(prices);
+        self
+    }
+
+    pub fn with_embedding_sidecar(mut self, sidecar: EmbeddingSidecar) -> Self {
+        self.embedding_sidecar = Some(sidecar);
+        self
+    }
+
+    // Answers every request from `mock` instead of the network.
+    pub fn with_mock(mut self, mock: MockLlm) -> Self {
+        self.mock = Some(mock);
+        self
+    }
+
+    // The mock provider, when requests are answered by one.
+    pub fn mock(&self) -> Option<&MockLlm> {
+        self.mock.as_ref()
+    }
+
+    pub fn with_audit_log(mut self, audit: AuditLog) -> Self {
+        self.audit = Some(audit);
+        self
+    }
+
+    pub fn with_escalation(mut self, escalation: Escalation) -> Self {
+        self.escalation = Some(escalation);
+        self
+    }
+
+    pub fn with_fallbacks(mut self, fallbacks: Vec<FallbackModel>) -> Self {
+        self.fallbacks = fallbacks;
+        self
+    }
+
+    pub fn with_retry_policy(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    pub fn with_request_timeout(mut self, timeout: Duration) -> Self {
+        self.http = reqwest::Client::builder()
+            .timeout(timeout)
+            .build()
+            .expect("failed to build HTTP client");
+        self.request_timeout = timeout;
+        self
+    }
+
+    // Runs independent requests concurrently across keys when fan-out is
+    // enabled (the per-key in-flight limit bounds concurrency), otherwise one
+    // at a time. Results are returned in job order.
+    pub async fn fan_out<F: Future>(&self, jobs: Vec<F>) -> Vec<F::Output> {
+        if self.fan_out {
+            return futures::future::join_all(jobs).await;
+        }
+        let mut results = Vec::with_capacity(jobs.len());
+        for job in jobs {
+            results.push(job.await);
+        }
+        results
+    }
+
+    pub fn api_base(&self) -> &str {
+        &self.api_base
+    }
+
+    // The primary model; with escalation, some answers come from the fast model.
+    pub fn model(&self) -> &str {
+        &self.model
+    }
+
+    pub fn key_health(&self) -> Vec<KeyHealth> {
+        self.keys.health()
+    }
+
+    pub fn usage(&self) -> &UsageTracker {
+        &self.usage
+    }
+
+    pub fn fair_share(&self) -> Option<&FairShare> {
+        self.fair_share.as_ref()
+    }
+
+    // Identifies the vectors `embed` produces for `model`, so indexes built
+    // by Gemini and by a sidecar serving a same-named model are not mixed.
+    pub fn embedding_id(&self, model: &str) -> String {
+        match &self.embedding_sidecar {
+            Some(sidecar) => format!("{model}@{}", sidecar.api_base),
+            None => model.to_string(),
+        }
+    }
+
+    // Waits while the calling consumer is over its token quota, or refuses
+    // the request when the wait would be too long.
+    async fn admit(&self) -> Result<(), LlmError> {
+        match &self.fair_share {
+            Some(fair_share) => fair_share.admit().await.map_err(LlmError::QuotaExceeded),
+            None => Ok(()),
+        }
+    }
+
+    fn record_usage(&self, api_key: &str, model: &str, prompt_tokens: u64, completion_tokens: u64) {
+        self.usage.record(api_key, model, prompt_tokens, completion_tokens);
+        if let Some(fair_share) = &self.fair_share {
+            fair_share.record(prompt_tokens + completion_tokens);
+        }
+    }
+
+    fn create_client(&self, api_key: &str) -> Client<OpenAIConfig> {
+        let config = OpenAIConfig::new()
+            .with_api_base(&self.api_base)
+            .with_api_key(api_key);
+        Client::with_config(config).with_http_client(self.http.clone())
+    }
+
+    pub async fn embed(&self, model: &str, inputs: Vec<String>) -> Result<Vec<Vec<f32>>, LlmError> {
+        if let Some(mock) = &self.mock {
+            return Ok(mock.embed(&inputs));
+        }
+        if let Some(sidecar) = &self.embedding_sidecar {
+            return self.embed_with_sidecar(sidecar, model, inputs).await;
+        }
+        self.admit().await?;
+        let lease = self.keys.lease().await;
+        let api_key = lease.key.clone();
+        let client = self.create_client(&api_key);
+
+        let sent = self.audit.is_some().then(|| Segments::from(inputs.join("\n")));
+        let request = CreateEmbeddingRequestArgs::default()
+            .model(model)
+            .input(inputs)
+            .build()?;
+
+        let response = client.embeddings().create(request).await;
+        if let (Some(audit), Some(sent)) = (&self.audit, &sent) {
+            let outcome = response.as_ref().map(|r| embeddings_text(&r.data)).map_err(|e| e.to_string());
+            audit.record("embeddings", &self.api_base, model, sent, outcome.as_deref().map_err(String::clone)).await;
+        }
+        let mut data = match response {
+            Ok(response) => {
+                self.keys.record_success(&api_key);
+                self.record_usage(&api_key, model, u64::from(response.usage.prompt_tokens), 0);
+                response.data
+            }
+            Err(e) => {
+                let error = LlmError::Api(e).with_key(&api_key);
+                if error.class().reflects_on_key() {
+                    self.keys.record_failure(&api_key, error.class());
+                }
+                return Err(error);
+            }
+        };
+        data.sort_by_key(|e| e.index);
+        Ok(data.into_iter().map(|e| e.embedding).collect())
+    }
+
+    // Local requests bypass the key pool, quotas, and usage accounting: they
+    // use no Gemini key and cost nothing.
+    async fn embed_with_sidecar(&self, sidecar: &EmbeddingSidecar, model: &str, inputs: Vec<String>) -> Result<Vec<Vec<f32>>, LlmError> {
+        let config = OpenAIConfig::new()
+            .with_api_base(&sidecar.api_base)
+            .with_api_key(sidecar.api_key.as_deref().unwrap_or_default());
+        let client = Client::with_config(config).with_http_client(self.http.clone());
+        let sent = self.audit.is_some().then(|| Segments::from(inputs.join("\n")));
+        let request = CreateEmbeddingRequestArgs::default()
+            .model(model)
+            .input(inputs)
+            .build()?;
+        let response = client.embeddings().create(request).await;
+        if let (Some(audit), Some(sent)) = (&self.audit, &sent) {
+            let outcome = response.as_ref().map(|r| embeddings_text(&r.data)).map_err(|e| e.to_string());
+            audit.record("embeddings", &sidecar.api_base, model, sent, outcome.as_deref().map_err(String::clone)).await;
+        }
+        let mut data = response.map_err(LlmError::Api)?.data;
+        data.sort_by_key(|e| e.index);
+        Ok(data.into_iter().map(|e| e.embedding).collect())
+    }
+
+    pub async fn select_files(&self, file_tree: &str, prompt: &str) -> Result<Vec<String>, LlmError> {
+        let user = format!("File Tree:\n{file_tree}\n\nRequest: {prompt}");
+        let reply = self.query(&self.model, FILE_SELECTION_SYSTEM, user.into(), &GenerationParams::default()).await?;
+        Ok(parse_path_list(&reply))
+    }
+
+    // A condensed rewrite of `spec` (one part of a long request) in about
+    // `target_chars`.
+    pub async fn condense_spec(&self, spec: &str, target_chars: usize) -> Result<String, LlmError> {
+        let user = format!("Condense this to about {target_chars} characters.\n\nSpecification:\n{spec}");
+        self.query(&self.model, SPEC_CONDENSE_SYSTEM, user.into(), &GenerationParams::default()).await
+    }
+
+    // Compaction is ignored: the analysis decides how much it saves, so the
+    // estimate assumes every pass gets the full context.
+    pub fn estimate_pipeline(&self, prompts: &PipelinePrompts, context_chars: usize, prompt: &str, options: PipelineOptions<'_>) -> PipelineEstimate {
+        let tokens = |chars: usize| (chars as u64).div_ceil(CHARS_PER_TOKEN);
+        let request = tokens(render_request(&options.messages, prompts, &Segments::new(), prompt).len());
+        let system = tokens(detail_system(prompts, options.format, options.verbosity).len());
+        let reserve = options.verbosity.reserve_tokens();
+        let detail_reserve = options.generation.max_tokens.map_or(reserve, |max_tokens| max_tokens.min(reserve));
+        let detail = system + request + tokens(prompts.detail_instruction.len()) + detail_reserve;
+        let (mut context_sends, mut fixed_tokens) = match options.passes {
+            0 | 1 => (1, detail),
+            passes => {
+                let mut analysis = tokens(prompts.analysis_system.len()) + request + ANALYSIS_RESERVE_TOKENS;
+                let mut analysis_sends = 1;
+                let candidates = u64::from(options.candidates);
+                if candidates > 1 {
+                    // The judge reads the context again with every candidate.
+                    let judge = tokens(CANDIDATE_JUDGE_SYSTEM.len()) + analysis + candidates * ANALYSIS_RESERVE_TOKENS;
+                    analysis = candidates * analysis + judge;
+                    analysis_sends = candidates + 1;
+                }
+                // The detail pass also reads the analysis.
+                let two = analysis + detail + ANALYSIS_RESERVE_TOKENS;
+                if passes < 3 {
+                    (analysis_sends + 1, two)
+                } else {
+                    // The critic reads the draft and rewrites it.
+                    let critic = tokens(CRITIC_SYSTEM.len()) + system + request + 2 * detail_reserve;
+                    (analysis_sends + 2, two + critic)
+                }
+            }
+        };
+        if options.verify {
+            // The verifier reads the final response and lists corrections.
+            context_sends += 1;
+            fixed_tokens += tokens(VERIFY_SYSTEM.len()) + request + detail_reserve + ANALYSIS_RESERVE_TOKENS;
+        }
+        PipelineEstimate {
+            context_tokens: tokens(context_chars),
+            context_sends,
+            fixed_tokens,
+            runs: if self.escalation.is_some() { 2 } else { 1 },
+        }
+    }
+
+    pub async fn run_pipeline(
+        &self,
+        prompts: &PipelinePrompts,
+        context: &Segments,
+        prompt: &str,
+        options: PipelineOptions<'_>,
+    ) -> Result<String, LlmError> {
+        let response = self.run_escalating(prompts, context, prompt, options).await?;
+        if !options.verify {
+            return Ok(response);
+        }
+        Ok(self.verify(prompts, context, prompt, options, response).await)
+    }
+
+    async fn run_escalating(
+        &self,
+        prompts: &PipelinePrompts,
+        context: &Segments,
+        prompt: &str,
+        options: PipelineOptions<'_>,
+    ) -> Result<String, LlmError> {
+        let Some(escalation) = &self.escalation else {
+            return self.run_passes(&self.model, prompts, context, prompt, options).await;
+        };
+
+        let draft = match self.run_passes(&escalation.fast_model, prompts, context, prompt, options).await {
+            Ok(draft) => draft,
+            Err(e) => {
+                tracing::warn!("Fast model '{}' failed ({e}); escalating to '{}'", escalation.fast_model, self.model);
+                return self.run_passes(&self.model, prompts, context, prompt, options).await;
+            }
+        };
+
+        let score = self.score_output(&escalation.fast_model, prompts, prompt, &draft, options.format).await;
+        if score >= escalation.threshold {
+            tracing::info!("Fast model '{}' output accepted with confidence {}/10", escalation.fast_model, score);
+            return Ok(draft);
+        }
+
+        tracing::info!(
+            "Fast model '{}' output scored {}/10 (threshold {}); escalating to '{}'",
+            escalation.fast_model, score, escalation.threshold, self.model
+        );
+        self.run_passes(&self.model, prompts, context, prompt, options).await
+    }
+
+    // Appends the verification pass's corrections to `response`, checked by
+    // the main model against the full context. A failed verification is noted
+    // in the section instead of failing the call, since the response itself
+    // is complete.
+    async fn verify(&self, prompts: &PipelinePrompts, context: &Segments, prompt: &str, options: PipelineOptions<'_>, response: String) -> String {
+        let request = render_request(&options.messages, prompts, context, prompt);
+        let (system, user) = verify_request(&request, &response);
+        let corrections = match self.query(&self.model, &system, user, &options.generation.sampling()).await {
+            Ok(reply) => {
+                let reply = reply.trim();
+                reply.strip_prefix(CORRECTIONS_HEADING).unwrap_or(reply).trim().to_string()
+            }
+            Err(e) => {
+                tracing::warn!("Verification pass failed: {e}");
+                format!("Verification failed ({e}); the response above has not been checked.")
+            }
+        };
+        format!("{}\n\n{CORRECTIONS_HEADING}\n\n{corrections}\n", response.trim_end())
+    }
+
+    // Cheap heuristics gate the self-evaluation call: obviously weak or
+    // malformed drafts are escalated without spending another request.
+    async fn score_output(&self, model: &str, prompts: &PipelinePrompts, prompt: &str, output: &str, format: OutputFormat) -> u8 {
+        let well_formed = match format {
+            OutputFormat::Markdown => true,
+            OutputFormat::Json => plan::parse_structured_plan(output).is_ok(),
+            OutputFormat::Diff => output.contains("@@"),
+        };
+        if !well_formed || output.trim().len() < MIN_CONFIDENT_CHARS {
+            return 0;
+        }
+
+        let user = format!("{}: {prompt}\n\nResponse to grade:\n{output}", prompts.request_label);
+        let reply = match self.query(model, SELF_EVALUATION_SYSTEM, user.into(), &GenerationParams::default()).await {
+            Ok(reply) => reply,
+            Err(e) => {
+                tracing::warn!("Self-evaluation failed: {e}");
+                return 0;
+            }
+        };
+
+        let json = match (reply.find('{'), reply.rfind('}')) {
+            (Some(start), Some(end)) if start < end => &reply[start..=end],
+            _ => reply.as_str(),
+        };
+        serde_json::from_str::<serde_json::Value>(json)
+            .ok()
+            .and_then(|v| v.get("score").and_then(|s| s.as_u64()))
+            .map(|s| s.min(10) as u8)
+            .unwrap_or(0)
+    }
+
+    async fn run_passes(
+        &self,
+        model: &str,
+        prompts: &PipelinePrompts,
+        context: &Segments,
+        prompt: &str,
+        options: PipelineOptions<'_>,
+    ) -> Result<String, LlmError> {
+        let messages = &options.messages;
+        let request = render_request(messages, prompts, context, prompt);
+        let system = detail_system(prompts, options.format, options.verbosity);
+        let response = options.generation.capped(options.verbosity.max_output_tokens());
+
+        if options.passes <= 1 {
+            let mut user = request;
+            user.push(format!("\n\n{}", prompts.detail_instruction));
+            return self.query(model, &system, user, &response).await;
+        }
+
+        let analysis = self.analyze(model, prompts, &request, options.candidates, &options.generation.sampling()).await?;
+        let compacted = match options.compact {
+            Some(compact) => compact_request(compact, messages, prompts, prompt, &analysis).await,
+            None => None,
+        };
+        let (request, context) = match &compacted {
+            Some((request, report)) => (request, report),
+            None => (&request, context),
+        };
+
+        let user = detail_user(messages, prompts, context, prompt, request, &analysis);
+        let draft = self.query(model, &system, user, &response).await?;
+
+        if options.passes < 3 {
+            return Ok(draft);
+        }
+
+        let (critic_system, user) = critic_request(&system, request, &draft);
+        self.query(model, &critic_system, user, &response).await
+    }
+
+    // The analysis pass. With several candidates, they are generated
+    // independently (concurrently with fan-out) and a judging pass selects or
+    // merges them; failed candidates are skipped.
+    async fn analyze(&self, model: &str, prompts: &PipelinePrompts, request: &Segments, candidates: u8, generation: &GenerationParams) -> Result<String, LlmError> {
+        if candidates <= 1 {
+            return self.query(model, prompts.analysis_system, request.clone(), generation).await;
+        }
+        let jobs = (0..candidates).map(|_| self.query(model, prompts.analysis_system, request.clone(), generation)).collect();
+        let mut results = self.fan_out(jobs).await;
+        if results.iter().all(Result::is_err) {
+            return results.swap_remove(0);
+        }
+        let drafts: Vec<String> = results
+            .into_iter()
+            .filter_map(|result| result.inspect_err(|e| tracing::warn!("Candidate analysis failed: {e}")).ok())
+            .collect();
+        if let [draft] = drafts.as_slice() {
+            return Ok(draft.clone());
+        }
+
+        tracing::info!("Judging {} candidate analyses", drafts.len());
+        let (system, user) = judge_request(prompts.analysis_system, request, &drafts);
+        self.query(model, &system, user, generation).await
+    }
+
+    // The requests `run_pipeline` would send, without sending them. Outputs
+    // of earlier passes are shown as placeholders, and with `compact` the
+    // later passes show the full context although they would get less.
+    pub fn preview_pipeline(&self, prompts: &PipelinePrompts, context: &Segments, prompt: &str, options: PipelineOptions<'_>) -> PipelinePreview {
+        let messages = &options.messages;
+        let request = render_request(messages, prompts, context, prompt);
+        let system = detail_system(prompts, options.format, options.verbosity);
+        let mut passes = Vec::new();
+        if options.passes <= 1 {
+            let mut user = request.clone();
+            user.push(format!("\n\n{}", prompts.detail_instruction));
+            passes.push(PassPreview { pass: "detail", system, user });
+        } else {
+            let analysis = PassPreview { pass: "analysis", system: prompts.analysis_system.to_string(), user: request.clone() };
+            if options.candidates > 1 {
+                let candidates = vec![CANDIDATE_PLACEHOLDER.to_string(); options.candidates as usize];
+                let (system, user) = judge_request(prompts.analysis_system, &request, &candidates);
+                passes.push(PassPreview { pass: "analysis (once per candidate)", ..analysis });
+                passes.push(PassPreview { pass: "judge", system, user });
+            } else {
+                passes.push(analysis);
+            }
+            let user = detail_user(messages, prompts, context, prompt, &request, ANALYSIS_PLACEHOLDER);
+            let critic = (options.passes >= 3).then(|| critic_request(&system, &request, DRAFT_PLACEHOLDER));
+            passes.push(PassPreview { pass: "detail", system, user });
+            if let Some((system, user)) = critic {
+                passes.push(PassPreview { pass: "critic", system, user });
+            }
+        }
+        if options.verify {
+            let (system, user) = verify_request(&request, RESPONSE_PLACEHOLDER);
+            passes.push(PassPreview { pass: "verify", system, user });
+        }
+        let models = match &self.escalation {
+            Some(escalation) => vec![escalation.fast_model.clone(), self.model.clone()],
+            None => vec![self.model.clone()],
+        };
+        PipelinePreview { models, passes }
+    }
+
+    pub async fn follow_up(&self, context: &Segments, exchanges: &[Exchange], question: &str, generation: &GenerationParams) -> Result<String, LlmError> {
+        let mut messages = vec![ChatMessage::system(FOLLOW_UP_SYSTEM)];
+
+        for (i, exchange) in exchanges.iter().enumerate() {
+            let user = if i == 0 {
+                let mut user = Segments::from("Codebase Report:\n");
+                user.append(context);
+                user.push(format!("\n\n{}", exchange.question));
+                user
+            } else {
+                exchange.question.as_str().into()
+            };
+            messages.push(ChatMessage { role: "user", content: user });
+            messages.push(ChatMessage { role: "assistant", content: exchange.answer.as_str().into() });
+        }
+        messages.push(ChatMessage { role: "user", content: question.into() });
+
+        self.chat(&self.model, messages, generation).await
+    }
+
+    async fn query(&self, model: &str, system: &str, user: Segments, generation: &GenerationParams) -> Result<String, LlmError> {
+        let messages = vec![ChatMessage::system(system), ChatMessage { role: "user", content: user }];
+        self.chat(model, messages, generation).await
+    }
+
+    // Sends to `model`, then down the fallback chain (MODEL_FALLBACKS) while
+    // each model fails after its retries with a provider failure.
+    async fn chat(&self, model: &str, messages: Vec<ChatMessage>, generation: &GenerationParams) -> Result<String, LlmError> {
+        let mut request = ChatRequest { model: model.to_string(), messages, generation: generation.clone() };
+
+        self.admit().await?;
+        let mut result = self.send_retrying(&request, None).await;
+        for fallback in &self.fallbacks {
+            let error = match &result {
+                Err(error) if error.is_provider_failure() => error,
+                _ => break,
+            };
+            if fallback.api_base.is_none() && fallback.model == request.model {
+                continue;
+            }
+            tracing::warn!("Model '{}' failed ({error}); falling back to '{}'", request.model, fallback.label());
+            request.model = fallback.model.clone();
+            let endpoint = fallback.api_base.as_deref().map(|api_base| (api_base, fallback.api_key.as_deref()));
+            result = self.send_retrying(&request, endpoint).await;
+        }
+        result
+    }
+
+    // Retries one model. With an `endpoint` (API base and key), the request
+    // goes to that provider instead of through the key pool.
+    async fn send_retrying(&self, request: &ChatRequest, endpoint: Option<(&str, Option<&str>)>) -> Result<String, LlmError> {
+        let key_count = self.keys.len();
+        // Keys tried since the last backoff, which are not leased again
+        // before it, and keys that were rejected, which are not leased again.
+        let mut rotated_keys = HashSet::new();
+        let mut rejected_keys = HashSet::new();
+        let mut attempt = 1;
+
+        loop {
+            let (result, api_key) = match endpoint {
+                Some((api_base, api_key)) => (self.send_chat(api_base, api_key, request).await, None),
+                None => {
+                    let lease = self.keys.lease_excluding(&rotated_keys).await;
+                    let api_key = lease.key.clone();
+                    (self.send_chat(&self.api_base, Some(&api_key), request).await, Some(api_key))
+                }
+            };
+            let error = match result {
+                Ok(content) => {
+                    if let Some(api_key) = &api_key {
+                        self.keys.record_success(api_key);
+                    }
+                    return Ok(content);
+                }
+                Err(e) => e,
+            };
+            let class = error.class();
+            if let Some(api_key) = api_key.as_ref().filter(|_| class.reflects_on_key()) {
+                self.keys.record_failure(api_key, class);
+            }
+
+            // Another key may still work; try each once before waiting.
+            if let Some(api_key) = api_key.filter(|_| matches!(class, ErrorClass::QuotaExhausted | ErrorClass::Unauthorized)) {
+                if class == ErrorClass::Unauthorized {
+                    rejected_keys.insert(api_key.clone());
+                    if rejected_keys.len() >= key_count {
+                        tracing::error!("Every API key was rejected: {}", error);
+                        return Err(error);
+                    }
+                }
+                rotated_keys.insert(api_key);
+                if rotated_keys.len() < key_count {
+                    tracing::warn!("API key failed ({class}): {}. Rotating to the next key", error);
+                    continue;
+                }
+            }
+
+            if !self.retry.retries(class) {
+                tracing::error!("API request failed ({class}, not retried): {}", error);
+                return Err(error);
+            }
+            if attempt >= self.retry.max_attempts {
+                tracing::error!("API request failed after all retries: {}", error);
+                return Err(error);
+            }
+
+            let retry_after = match &error {
+                LlmError::RateLimited { retry_after, .. } => *retry_after,
+                _ => None,
+            };
+            if let Some(delay) = retry_after.filter(|&delay| delay > self.retry.max_delay) {
+                tracing::error!("API asked to retry after {}s, giving up: {}", delay.as_secs(), error);
+                return Err(error);
+            }
+            let delay = retry_after.unwrap_or_else(|| self.retry.delay(attempt - 1));
+
+            tracing::warn!("API request failed on attempt {}: {}. Retrying after {:.1}s", attempt, error, delay.as_secs_f64());
+            sleep(delay).await;
+            rotated_keys.clone_from(&rejected_keys);
+            attempt += 1;
+        }
+    }
+
+    async fn send_chat(&self, api_base: &str, api_key: Option<&str>, request: &ChatRequest) -> Result<String, LlmError> {
+        // Usage of a fallback endpoint without a key is attributed to its URL.
+        let usage_key = api_key.unwrap_or(api_base);
+        if let Some(mock) = &self.mock {
+            return Ok(self.mock_chat(mock, usage_key, request));
+        }
+        let result = self.post_chat(api_base, api_key, usage_key, request).await;
+        if let Some(audit) = &self.audit {
+            let mut sent = Segments::new();
+            for message in &request.messages {
+                sent.append(&message.content);
+            }
+            let outcome = result.as_deref().map_err(ToString::to_string);
+            audit.record("chat", api_base, &request.model, &sent, outcome).await;
+        }
+        result
+    }
+
+    // Usage is recorded from the estimated token counts, so quotas, budgets,
+    // and get_usage_stats behave as they would against a real provider.
+    fn mock_chat(&self, mock: &MockLlm, api_key: &str, request: &ChatRequest) -> String {
+        let messages: Vec<(String, String)> = request.messages
+            .iter()
+            .map(|m| (m.role.to_string(), m.content.to_string()))
+            .collect();
+        let response = mock.chat(&request.model, &messages);
+        let sent: usize = messages.iter().map(|(_, content)| content.len()).sum();
+        let tokens = |chars: usize| (chars as u64).div_ceil(CHARS_PER_TOKEN);
+        self.record_usage(api_key, &request.model, tokens(sent), tokens(response.len()));
+        response
+    }
+
+    async fn post_chat(&self, api_base: &str, api_key: Option<&str>, usage_key: &str, request: &ChatRequest) -> Result<String, LlmError> {
+        let timed_out = |e: reqwest::Error| {
+            if e.is_timeout() {
+                LlmError::Timeout(self.request_timeout)
+            } else {
+                LlmError::Request(e)
+            }
+        };
+
+        let mut post = self.http.post(format!("{api_base}/chat/completions"));
+        if let Some(api_key) = api_key {
+            post = post.bearer_auth(api_key);
+        }
+        let response = post
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .header(reqwest::header::CONTENT_LENGTH, request.body_len())
+            .body(reqwest::Body::wrap_stream(futures::stream::iter(request.body().map(Ok::<_, std::io::Error>))))
+            .send()
+            .await
+            .map_err(timed_out)?;
+
+        let status = response.status();
+        let headers = response.headers().clone();
+        let body = response.text().await.map_err(timed_out)?;
+        if !status.is_success() {
+            return Err(status_error(status.as_u16(), &headers, &body, usage_key));
+        }
+
+        let response: CreateChatCompletionResponse = serde_json::from_str(&body)
+            .map_err(|e| LlmError::Api(async_openai::error::OpenAIError::JSONDeserialize(e)))?;
+        if let Some(usage) = &response.usage {
+            self.record_usage(usage_key, &request.model, u64::from(usage.prompt_tokens), u64::from(usage.completion_tokens));
+        }
+        response.choices.into_iter().next()
+            .and_then(|c| c.message.content)
+            .ok_or(LlmError::NoContent)
+    }
+
+    // Lists the models with each API key, which costs no tokens, to confirm
+    // the keys authenticate. Results leave key health alone, so probes cannot
+    // quarantine a key that requests are using. No keys are checked with the
+    // mock provider.
+    pub async fn check_keys(&self) -> Vec<(String, Result<Duration, LlmError>)> {
+        if self.mock.is_some() {
+            return Vec::new();
+        }
+        let checks = self.keys.keys().into_iter().map(|key| async move {
+            let started = std::time::Instant::now();
+            let result = self.list_models(&key, None).await.map(|_| started.elapsed());
+            (keys::mask(&key), result)
+        });
+        futures::future::join_all(checks).await
+    }
+
+    // The models the provider serves for chat, sorted by ID. Fails rather
+    // than looping when the page tokens repeat or run past MAX_MODEL_PAGES.
+    // With the mock provider, only the configured model is listed.
+    pub async fn models(&self) -> Result<Vec<ModelInfo>, LlmError> {
+        if self.mock.is_some() {
+            return Ok(vec![ModelInfo { id: self.model.clone(), display_name: None, context_window: None, output_limit: None }]);
+        }
+        let lease = self.keys.lease().await;
+        let mut models = Vec::new();
+        let mut page_token = None;
+        let mut seen_tokens = HashSet::new();
+        for _ in 0..MAX_MODEL_PAGES {
+            let page = match self.list_models(&lease.key, page_token.as_deref()).await {
+                Ok(page) => page,
+                Err(e) => {
+                    if e.class().reflects_on_key() {
+                        self.keys.record_failure(&lease.key, e.class());
+                    }
+                    return Err(e);
+                }
+            };
+            page_token = page.next_page_token.clone().filter(|token| !token.is_empty());
+            models.extend(page.models());
+            let Some(token) = &page_token else {
+                self.keys.record_success(&lease.key);
+                models.sort_by(|a, b| a.id.cmp(&b.id));
+                return Ok(models);
+            };
+            if !seen_tokens.insert(token.clone()) {
+                return Err(LlmError::Pagination(format!("the provider returned page token '{token}' twice")));
+            }
+        }
+        Err(LlmError::Pagination(format!("the provider returned more than {MAX_MODEL_PAGES} pages")))
+    }
+
+    async fn list_models(&self, api_key: &str, page_token: Option<&str>) -> Result<ModelPage, LlmError> {
+        let timed_out = |e: reqwest::Error| {
+            if e.is_timeout() {
+                LlmError::Timeout(self.request_timeout)
+            } else {
+                LlmError::Request(e)
+            }
+        };
+
+        let response = self.models_request(api_key, page_token).send().await.map_err(timed_out)?;
+        let status = response.status();
+        let headers = response.headers().clone();
+        let body = response.text().await.map_err(timed_out)?;
+        if !status.is_success() {
+            return Err(status_error(status.as_u16(), &headers, &body, api_key));
+        }
+        serde_json::from_str(&body).map_err(|e| LlmError::Api(async_openai::error::OpenAIError::JSONDeserialize(e)))
+    }
+
+    // Gemini's native API takes an API key in `x-goog-api-key` and reads a
+    // bearer token as OAuth; OpenAI-compatible servers, Gemini's `/openai`
+    // endpoint among them, take the key as a bearer token.
+    fn models_request(&self, api_key: &str, page_token: Option<&str>) -> reqwest::RequestBuilder {
+        let mut get = self.http.get(format!("{}/models", self.api_base));
+        get = if self.api_base.contains("generativelanguage.googleapis.com") && !self.api_base.ends_with("/openai") {
+            get.header("x-goog-api-key", api_key)
+        } else {
+            get.bearer_auth(api_key)
+        };
+        if let Some(page_token) = page_token {
+            get = get.query(&[("pageToken", page_token)]);
+        }
+        get
+    }
+}
+
+// Gemini lists well under a thousand models at its default page size.
+const MAX_MODEL_PAGES: usize = 50;
+
+// A model that can be set as GEMINI_MODEL, from the provider's model list.
+#[derive(Serialize, JsonSchema)]
+pub struct ModelInfo {
+    pub id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub display_name: Option<String>,
+    // Input tokens, when the provider reports them.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub context_window: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output_limit: Option<u64>,
+}
+
+// One page of `GET {api_base}/models`: Gemini's native `models` with token
+// limits, or the OpenAI-compatible `data`, where some servers add a context
+// length under one of several names.
+#[derive(Deserialize)]
+struct ModelPage {
+    #[serde(default)]
+    models: Vec<GeminiModel>,
+    #[serde(default)]
+    data: Vec<OpenAiModel>,
+    #[serde(default, rename = "nextPageToken")]
+    next_page_token: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct GeminiModel {
+    // "models/gemini-2.5-pro"
+    name: String,
+    #[serde(default, rename = "displayName")]
+    display_name: Option<String>,
+    #[serde(default, rename = "inputTokenLimit")]
+    input_token_limit: Option<u64>,
+    #[serde(default, rename = "outputTokenLimit")]
+    output_token_limit: Option<u64>,
+    #[serde(default, rename = "supportedGenerationMethods")]
+    supported_generation_methods: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct OpenAiModel {
+    id: String,
+    // `context_length` on OpenRouter, `max_model_len` on vLLM, and
+    // `context_window` on Groq.
+    #[serde(default, alias = "max_model_len", alias = "context_window")]
+    context_length: Option<u64>,
+}
+
+impl ModelPage {
+    // Embedding-only and other models that cannot answer a chat request are
+    // left out.
+    fn models(self) -> impl Iterator<Item = ModelInfo> {
+        let gemini = self
+            .models
+            .into_iter()
+            .filter(|m| m.supported_generation_methods.is_empty() || m.supported_generation_methods.iter().any(|method| method == "generateContent"))
+            .map(|m| ModelInfo {
+                id: m.name.strip_prefix("models/").unwrap_or(&m.name).to_string(),
+                display_name: m.display_name,
+                context_window: m.input_token_limit,
+                output_limit: m.output_token_limit,
+            });
+        let openai = self.data.into_iter().map(|m| ModelInfo {
+            id: m.id.strip_prefix("models/").unwrap_or(&m.id).to_string(),
+            display_name: None,
+            context_window: m.context_length,
+            output_limit: None,
+        });
+        gemini.chain(openai)
+    }
+}
+
+// The error for a non-2xx response made with `usage_key`.
+fn status_error(status: u16, headers: &reqwest::header::HeaderMap, body: &str, usage_key: &str) -> LlmError {
+    if status == 429 {
+        let limit = rate_limit::parse(headers, body);
+        return LlmError::RateLimited {
+            message: limit.message,
+            retry_after: limit.retry_after,
+            quota_exhausted: limit.quota_exhausted,
+        };
+    }
+    let message = rate_limit::error_message(body);
+    match status {
+        401 | 403 => LlmError::Unauthorized { key: keys::mask(usage_key), message },
+        // Gemini reports invalid keys as 400 INVALID_ARGUMENT.
+        400 if message.contains("API key") => LlmError::Unauthorized { key: keys::mask(usage_key), message },
+        400 | 404 | 413 | 422 => LlmError::InvalidRequest { status, message },
+        _ => LlmError::Http { status, message },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::{HeaderMap, HeaderValue, AUTHORIZATION, RETRY_AFTER};
+
+    const KEY: &str = "AIzaSyExampleKey1234";
+
+    fn classify(status: u16, body: &str) -> LlmError {
+        status_error(status, &HeaderMap::new(), body, KEY)
+    }
+
+    fn error_body(message: &str) -> String {
+        serde_json::json!({ "error": { "message": message } }).to_string()
+    }
+
+    #[test]
+    fn rejected_keys_are_unauthorized_and_masked() {
+        for (status, message) in [(401, "Unauthorized"), (403, "Permission denied"), (400, "API key not valid. Please pass a valid API key.")] {
+            match classify(status, &error_body(message)) {
+                LlmError::Unauthorized { key, message: reported } => {
+                    assert_eq!(key, "…1234");
+                    assert_eq!(reported, message);
+                }
+                other => panic!("HTTP {status} classified as {other:?}"),
+            }
+        }
+        assert_eq!(classify(401, "").class(), ErrorClass::Unauthorized);
+        assert!(!classify(401, "").to_string().contains(KEY));
+    }
+
+    #[test]
+    fn invalid_requests_fail_fast() {
+        for status in [400, 404, 413, 422] {
+            let error = classify(status, &error_body("models/gemini-9 is not found"));
+            assert!(matches!(error, LlmError::InvalidRequest { status: s, .. } if s == status), "{error:?}");
+            assert_eq!(error.class(), ErrorClass::Other);
+        }
+    }
+
+    #[test]
+    fn rate_limits_and_quotas() {
+        let mut headers = HeaderMap::new();
+        headers.insert(RETRY_AFTER, HeaderValue::from_static("7"));
+        match status_error(429, &headers, &error_body("Too many requests"), KEY) {
+            LlmError::RateLimited { retry_after, quota_exhausted, .. } => {
+                assert_eq!(retry_after, Some(Duration::from_secs(7)));
+                assert!(!quota_exhausted);
+            }
+            other => panic!("{other:?}"),
+        }
+        let quota = classify(429, &error_body("You exceeded your current quota. Please retry in 23.5s."));
+        assert_eq!(quota.class(), ErrorClass::QuotaExhausted);
+        assert!(matches!(quota, LlmError::RateLimited { retry_after: Some(delay), .. } if delay == Duration::from_secs_f64(23.5)));
+    }
+
+    #[test]
+    fn streamed_chat_bodies_are_json_of_the_stated_length() {
+        let text = "quote \" backslash \\ controls \u{0}\u{1f}\t\r\n multi-byte é → 🦀";
+        let mut report = Segments::from("fn main() {}\n");
+        // Longer than a chunk, so characters are split between chunks.
+        report.push(text.repeat(2_000));
+        let request = ChatRequest {
+            model: "gemini-\"2.5\"-pro".to_string(),
+            messages: vec![
+                ChatMessage::system(text),
+                ChatMessage { role: "user", content: report.clone() },
+                ChatMessage { role: "assistant", content: Segments::new() },
+            ],
+            generation: GenerationParams { temperature: Some(0.7), top_p: Some(0.95), max_tokens: Some(8192), stop: Some(vec!["\"END\"".to_string(), "é\n".to_string()]) },
+        };
+
+        let body: Vec<u8> = request.body().flatten().collect();
+        assert_eq!(request.body_len(), body.len());
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(json["model"], request.model);
+        assert!((json["temperature"].as_f64().unwrap() - 0.7).abs() < 1e-6);
+        assert!((json["top_p"].as_f64().unwrap() - 0.95).abs() < 1e-6);
+        assert_eq!(json["max_tokens"], 8192);
+        assert_eq!(json["stop"], serde_json::json!(["\"END\"", "é\n"]));
+        let messages = json["messages"].as_array().unwrap();
+        let expected = [("system", text.to_string()), ("user", report.to_string()), ("assistant", String::new())];
+        assert_eq!(messages.len(), expected.len());
+        for (message, (role, content)) in messages.iter().zip(expected) {
+            assert_eq!(message["role"], role);
+            assert_eq!(message["content"], content.as_str());
+        }
+
+        let plain = ChatRequest { model: "m".to_string(), messages: vec![ChatMessage::system(text)], generation: GenerationParams::default() };
+        let body: Vec<u8> = plain.body().flatten().collect();
+        assert_eq!(plain.body_len(), body.len());
+        assert_eq!(serde_json::from_slice::<serde_json::Value>(&body).unwrap(), serde_json::json!({ "model": "m", "messages": [{ "role": "system", "content": text }] }));
+    }
+
+    #[test]
+    fn model_listing_authenticates_as_the_endpoint_expects() {
+        let mut client = GeminiClient::new(vec![KEY.to_string()], None, None);
+        let request = client.models_request(KEY, Some("p2")).build().unwrap();
+        assert_eq!(request.url().as_str(), "https://generativelanguage.googleapis.com/v1beta/models?pageToken=p2");
+        assert_eq!(request.headers()["x-goog-api-key"], KEY);
+        assert!(request.headers().get(AUTHORIZATION).is_none());
+
+        client.api_base = "http://localhost:8000/v1".to_string();
+        let request = client.models_request(KEY, None).build().unwrap();
+        assert_eq!(request.headers()[AUTHORIZATION], format!("Bearer {KEY}").as_str());
+        assert!(request.headers().get("x-goog-api-key").is_none());
+    }
+
+    #[test]
+    fn other_statuses_are_http_errors() {
+        assert_eq!(classify(503, &error_body("overloaded")).class(), ErrorClass::Server);
+        assert!(matches!(classify(503, "upstream down"), LlmError::Http { status: 503, message } if message == "upstream down"));
+        assert_eq!(classify(418, "").class(), ErrorClass::Other);
+    }
 }
@@ -1,15 +1,16 @@
-mod config;
-mod external;
-mod llm;
-mod server;
-
+use ai_code_agent::agent::Agent;
+use ai_code_agent::cli::{self, Command};
+use ai_code_agent::config::Config;
+use ai_code_agent::gc;
+use ai_code_agent::llm;
+use ai_code_agent::presets::Preset;
+use ai_code_agent::rest;
+use ai_code_agent::server::CodeAgentServer;
 use anyhow::Result;
 use clap::Parser;
-use config::Config;
 use rmcp::ServiceExt;
-use server::CodeAgentServer;
+use std::net::SocketAddr;
 use std::path::PathBuf;
-use std::sync::Arc;
 use tracing_subscriber::EnvFilter;
 
 #[derive(Parser, Debug)]
@@ -17,53 +18,75 @@ use tracing_subscriber::EnvFilter;
 struct Cli {
     #[arg(long)]
     codebase_viewer_path: Option<PathBuf>,
+    /// `rest` serves the Rust API over HTTP instead of MCP over stdio.
+    /// Defaults to `stdio`, or `rest` with `--preset team-server`.
+    #[arg(long, value_enum)]
+    transport: Option<Transport>,
+    /// Address for `--transport rest`.
+    #[arg(long, default_value = "127.0.0.1:8080")]
+    listen: SocketAddr,
+    /// Defaults for a deployment mode; environment variables that are set
+    /// override the preset's.
+    #[arg(long, value_enum)]
+    preset: Option<Preset>,
+    /// Run one pipeline from the command line and exit instead of serving.
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+enum Transport {
+    Stdio,
+    Rest,
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
     dotenvy::dotenv().ok();
 
-    tracing_subscriber::fmt()
-        .with_env_filter(EnvFilter::try_from_default_env().unwrap_or_else(|_| "info".into()))
-        .init();
-
     let cli = Cli::parse();
 
-    let codebase_viewer_path = cli.codebase_viewer_path
-        .or_else(|| std::env::var("CODEBASE_VIEWER_PATH").ok().map(PathBuf::from))
-        .expect("CODEBASE_VIEWER_PATH must be set via --codebase-viewer-path flag or environment variable");
-
-    let api_keys = if let Ok(keys_str) = std::env::var("GEMINI_API_KEYS") {
-        keys_str
-            .split(',')
-            .map(|s| s.trim().to_string())
-            .filter(|s| !s.is_empty())
-            .collect::<Vec<String>>()
-    } else if let Ok(single_key) = std::env::var("GEMINI_API_KEY") {
-        vec![single_key]
+    let logs = tracing_subscriber::fmt().with_env_filter(EnvFilter::try_from_default_env().unwrap_or_else(|_| "info".into()));
+    // Subcommands print their result on stdout.
+    if cli.command.is_some() {
+        logs.with_writer(std::io::stderr).init();
     } else {
-        panic!("Either GEMINI_API_KEY or GEMINI_API_KEYS environment variable must be set");
-    };
-
-    if api_keys.is_empty() {
-        panic!("No valid API keys found in environment variables");
+        logs.init();
     }
 
-    tracing::info!("Initialized with {} API key(s) for rotation", api_keys.len());
+    if let Some(preset) = cli.preset {
+        let kept = preset.apply();
+        if kept.is_empty() {
+            tracing::info!("Using preset {}", preset.name());
+        } else {
+            tracing::info!("Using preset {}; already set, so not changed: {}", preset.name(), kept.join(", "));
+        }
+    }
 
-    let gemini_model = std::env::var("GEMINI_MODEL").ok();
-    let gemini_client = Arc::new(llm::GeminiClient::new(api_keys, gemini_model));
+    let config = Config::from_env(cli.codebase_viewer_path)?;
+    gc::spawn(config.gc.clone());
+    let usage_client = config.gemini_client.clone();
+    if let Some(interval) = config.tree_hash_refresh {
+        config.tree_hashes.clone().spawn_refresher(interval);
+    }
 
-    let token_char_limit = std::env::var("TOKEN_CHAR_LIMIT")
-        .ok()
-        .and_then(|s| s.parse().ok())
-        .unwrap_or(200_000);
+    if let Some(command) = cli.command {
+        let result = cli::run(&Agent::new(config), command).await;
+        log_usage(&usage_client);
+        return result;
+    }
 
-    let config = Config {
-        codebase_viewer_path: Arc::new(codebase_viewer_path),
-        gemini_client,
-        token_char_limit,
-    };
+    let transport = cli.transport.unwrap_or(match cli.preset {
+        Some(preset) if preset.serves_rest() => Transport::Rest,
+        _ => Transport::Stdio,
+    });
+    if let Transport::Rest = transport {
+        tracing::info!("Starting AI Code Agent REST server...");
+        rest::serve(Agent::new(config), cli.listen).await?;
+        tracing::info!("Server shut down.");
+        log_usage(&usage_client);
+        return Ok(());
+    }
 
     tracing::info!("Starting AI Code Agent MCP Server...");
     let server = CodeAgentServer::new(config)
@@ -73,6 +96,13 @@ async fn main() -> Result<()> {
     tracing::info!("Server initialized and listening on stdio.");
     server.waiting().await?;
     tracing::info!("Server shut down.");
+    log_usage(&usage_client);
 
     Ok(())
 }
+
+fn log_usage(client: &llm::GeminiClient) {
+    for line in client.usage().summary_lines() {
+        tracing::info!("{line}");
+    }
+}
@@ -0,0 +1,285 @@
+use crate::files::SourceFile;
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Ecosystem {
+    Cargo,
+    Npm,
+    Pip,
+}
+
+impl Ecosystem {
+    fn name(self) -> &'static str {
+        match self {
+            Ecosystem::Cargo => "Cargo",
+            Ecosystem::Npm => "npm",
+            Ecosystem::Pip => "pip",
+        }
+    }
+}
+
+pub struct Dependency {
+    pub name: String,
+    // Version requirement as written, or "*" when none is given.
+    pub requirement: String,
+    // normal, dev, build, optional, peer, or workspace.
+    pub kind: &'static str,
+    // Non-registry source such as a git URL or local path.
+    pub source: Option<String>,
+    // Whether the name appears anywhere in the code; a hint for unused deps.
+    pub referenced: bool,
+}
+
+pub struct Manifest {
+    pub path: PathBuf,
+    pub ecosystem: Ecosystem,
+    pub package: Option<String>,
+    pub dependencies: Vec<Dependency>,
+}
+
+// Parses every Cargo.toml, package.json, and requirements*.txt among
+// `source_files`. Manifests that fail to parse are skipped with a warning.
+pub fn parse_all(source_files: &[SourceFile]) -> Vec<Manifest> {
+    let mut manifests: Vec<Manifest> = source_files
+        .iter()
+        .filter_map(|file| {
+            let ecosystem = ecosystem_of(&file.path)?;
+            let parsed = match ecosystem {
+                Ecosystem::Cargo => parse_cargo(&file.content),
+                Ecosystem::Npm => parse_package_json(&file.content),
+                Ecosystem::Pip => Ok((None, parse_requirements(&file.content))),
+            };
+            match parsed {
+                Ok((package, dependencies)) => Some(Manifest {
+                    path: file.path.clone(),
+                    ecosystem,
+                    package,
+                    dependencies,
+                }),
+                Err(e) => {
+                    tracing::warn!("Skipping unparseable manifest '{}': {e:#}", file.path.display());
+                    None
+                }
+            }
+        })
+        .collect();
+
+    let code: Vec<&SourceFile> = source_files.iter().filter(|f| ecosystem_of(&f.path).is_none()).collect();
+    for manifest in &mut manifests {
+        for dependency in &mut manifest.dependencies {
+            dependency.referenced = is_referenced(manifest.ecosystem, &dependency.name, &code);
+        }
+    }
+    manifests
+}
+
+fn ecosystem_of(path: &Path) -> Option<Ecosystem> {
+    let name = path.file_name()?.to_str()?;
+    match name {
+        "Cargo.toml" => Some(Ecosystem::Cargo),
+        "package.json" => Some(Ecosystem::Npm),
+        _ if name.starts_with("requirements") && name.ends_with(".txt") => Some(Ecosystem::Pip),
+        _ => None,
+    }
+}
+
+type Parsed = (Option<String>, Vec<Dependency>);
+
+fn parse_cargo(content: &str) -> Result<Parsed> {
+    let doc: toml::Table = toml::from_str(content).context("Invalid TOML")?;
+    let package = doc
+        .get("package")
+        .and_then(|p| p.get("name"))
+        .and_then(|n| n.as_str())
+        .map(String::from);
+
+    let mut dependencies = Vec::new();
+    let mut collect = |table: Option<&toml::Value>, kind: &'static str| {
+        let Some(table) = table.and_then(|t| t.as_table()) else {
+            return;
+        };
+        for (name, spec) in table {
+            dependencies.push(cargo_dependency(name, spec, kind));
+        }
+    };
+
+    collect(doc.get("dependencies"), "normal");
+    collect(doc.get("dev-dependencies"), "dev");
+    collect(doc.get("build-dependencies"), "build");
+    collect(doc.get("workspace").and_then(|w| w.get("dependencies")), "workspace");
+    if let Some(targets) = doc.get("target").and_then(|t| t.as_table()) {
+        for target in targets.values() {
+            collect(target.get("dependencies"), "normal");
+            collect(target.get("dev-dependencies"), "dev");
+            collect(target.get("build-dependencies"), "build");
+        }
+    }
+
+    Ok((package, dependencies))
+}
+
+fn cargo_dependency(name: &str, spec: &toml::Value, kind: &'static str) -> Dependency {
+    let str_field = |field: &str| spec.get(field).and_then(|v| v.as_str()).map(String::from);
+    let requirement = match spec {
+        toml::Value::String(version) => version.clone(),
+        _ if spec.get("workspace").and_then(|v| v.as_bool()) == Some(true) => "workspace".to_string(),
+        _ => str_field("version").unwrap_or_else(|| "*".to_string()),
+    };
+    let source = str_field("git")
+        .map(|git| match str_field("rev").or_else(|| str_field("tag")).or_else(|| str_field("branch")) {
+            Some(reference) => format!("git {git} @ {reference}"),
+            None => format!("git {git}"),
+        })
+        .or_else(|| str_field("path").map(|path| format!("path {path}")));
+    let optional = spec.get("optional").and_then(|v| v.as_bool()) == Some(true);
+
+    Dependency {
+        // `package = "..."` renames: the real crate is what matters for audits.
+        name: str_field("package").unwrap_or_else(|| name.to_string()),
+        requirement,
+        kind: if optional && kind == "normal" { "optional" } else { kind },
+        source,
+        referenced: false,
+    }
+}
+
+fn parse_package_json(content: &str) -> Result<Parsed> {
+    let doc: serde_json::Value = serde_json::from_str(content).context("Invalid JSON")?;
+    let package = doc.get("name").and_then(|n| n.as_str()).map(String::from);
+
+    let mut dependencies = Vec::new();
+    for (field, kind) in [
+        ("dependencies", "normal"),
+        ("devDependencies", "dev"),
+        ("peerDependencies", "peer"),
+        ("optionalDependencies", "optional"),
+    ] {
+        let Some(deps) = doc.get(field).and_then(|d| d.as_object()) else {
+            continue;
+        };
+        for (name, requirement) in deps {
+            let requirement = requirement.as_str().unwrap_or("*").to_string();
+            let source = ["git", "github:", "file:", "link:", "http", "workspace:"]
+                .iter()
+                .any(|prefix| requirement.starts_with(prefix))
+                .then(|| requirement.clone());
+            dependencies.push(Dependency {
+                name: name.clone(),
+                requirement,
+                kind,
+                source,
+                referenced: false,
+            });
+        }
+    }
+
+    Ok((package, dependencies))
+}
+
+pub fn parse_requirements(content: &str) -> Vec<Dependency> {
+    content
+        .lines()
+        .map(|line| line.split(" #").next().unwrap_or("").trim())
+        .filter(|line| !line.is_empty() && !line.starts_with('#') && !line.starts_with('-'))
+        .map(|line| {
+            if line.contains("://") {
+                // Direct URL requirement: `name @ git+https://...` or a bare URL.
+                let (name, url) = line.split_once(" @ ").unwrap_or((line, line));
+                return Dependency {
+                    name: name.trim().to_string(),
+                    requirement: "*".to_string(),
+                    kind: "normal",
+                    source: Some(url.trim().to_string()),
+                    referenced: false,
+                };
+            }
+            let end = line.find(|c: char| "<>=!~;[ ".contains(c)).unwrap_or(line.len());
+            let mut rest = &line[end..];
+            // Drop extras (`name[security]`) and environment markers.
+            if let Some(after_extras) = rest.strip_prefix('[').and_then(|r| r.split_once(']')).map(|(_, r)| r) {
+                rest = after_extras;
+            }
+            let requirement = rest.split(';').next().unwrap_or("").trim();
+            Dependency {
+                name: line[..end].to_string(),
+                requirement: if requirement.is_empty() { "*".to_string() } else { requirement.to_string() },
+                kind: "normal",
+                source: None,
+                referenced: false,
+            }
+        })
+        .collect()
+}
+
+// Crates are imported with underscores, npm packages by their exact name in
+// import/require strings, and Python packages usually by their lowercased,
+// underscored name. Python import names can differ from the distribution name
+// (e.g. PyYAML -> yaml), so a miss is only a hint.
+fn is_referenced(ecosystem: Ecosystem, name: &str, code: &[&SourceFile]) -> bool {
+    let needle = match ecosystem {
+        Ecosystem::Cargo => name.replace('-', "_"),
+        Ecosystem::Npm => name.to_string(),
+        Ecosystem::Pip => name.to_lowercase().replace('-', "_"),
+    };
+    code.iter().any(|f| match ecosystem {
+        Ecosystem::Pip => f.content.to_lowercase().contains(&needle),
+        _ => f.content.contains(&needle),
+    })
+}
+
+pub fn render(manifests: &[Manifest]) -> String {
+    let internal: Vec<&str> = manifests.iter().filter_map(|m| m.package.as_deref()).collect();
+
+    let mut out = String::new();
+    for manifest in manifests {
+        out.push_str(&format!("### `{}` ({}", manifest.path.display(), manifest.ecosystem.name()));
+        if let Some(package) = &manifest.package {
+            out.push_str(&format!(", package `{package}`"));
+        }
+        out.push_str(")\n\n");
+
+        if manifest.dependencies.is_empty() {
+            out.push_str("No dependencies declared.\n\n");
+            continue;
+        }
+
+        out.push_str("| Dependency | Requirement | Kind | Source | Referenced in code |\n|---|---|---|---|---|\n");
+        for dependency in &manifest.dependencies {
+            let name = if internal.contains(&dependency.name.as_str()) {
+                format!("{} (internal)", dependency.name)
+            } else {
+                dependency.name.clone()
+            };
+            out.push_str(&format!(
+                "| {} | `{}` | {} | {} | {} |\n",
+                name,
+                dependency.requirement,
+                dependency.kind,
+                dependency.source.as_deref().unwrap_or("registry"),
+                if dependency.referenced { "yes" } else { "no" }
+            ));
+        }
+        out.push('\n');
+    }
+
+    let edges: Vec<String> = manifests
+        .iter()
+        .filter_map(|m| {
+            let package = m.package.as_deref()?;
+            let internal_deps: Vec<&str> = m
+                .dependencies
+                .iter()
+                .map(|d| d.name.as_str())
+                .filter(|name| internal.contains(name) && *name != package)
+                .collect();
+            (!internal_deps.is_empty()).then(|| format!("- `{package}` -> {}", internal_deps.join(", ")))
+        })
+        .collect();
+    if !edges.is_empty() {
+        out.push_str("### Internal dependency graph\n\n");
+        out.push_str(&edges.join("\n"));
+        out.push('\n');
+    }
+    out
+}
@@ -0,0 +1,208 @@
+// Lightweight syntax checks for the Mermaid diagrams returned by
+// explain_architecture. This is not a full parser: it catches the mistakes
+// models commonly make (unknown diagram types, unbalanced brackets, unclosed
+// blocks, malformed sequence messages) that stop editors from rendering.
+
+const FLOWCHART_DIRECTIONS: &[&str] = &["TB", "TD", "BT", "RL", "LR"];
+
+const SEQUENCE_ARROWS: &[&str] = &["-->>", "->>", "--x", "-x", "--)", "-)", "-->", "->"];
+
+const SEQUENCE_BLOCKS: &[&str] = &["loop", "alt", "opt", "par", "critical", "break", "rect", "box"];
+
+const SEQUENCE_STATEMENTS: &[&str] = &[
+    "participant", "actor", "autonumber", "activate", "deactivate", "note", "title", "create", "destroy", "link",
+    "links", "else", "and", "option",
+];
+
+#[derive(Clone, Copy, PartialEq)]
+enum Kind {
+    Flowchart,
+    Sequence,
+    Class,
+    State,
+    Entity,
+}
+
+// Problems with the Mermaid diagrams in `text`; empty when every diagram
+// passes and both a component (flowchart or class) and a sequence diagram are
+// present.
+pub fn check(text: &str) -> Vec<String> {
+    let blocks = extract_blocks(text);
+    if blocks.is_empty() {
+        return vec!["No ```mermaid code blocks found".to_string()];
+    }
+
+    let mut problems = Vec::new();
+    let mut kinds = Vec::new();
+    for (i, block) in blocks.iter().enumerate() {
+        match validate(block) {
+            Ok(kind) => kinds.push(kind),
+            Err(errors) => problems.extend(errors.into_iter().map(|e| format!("Diagram {}: {e}", i + 1))),
+        }
+    }
+
+    if !problems.is_empty() {
+        return problems;
+    }
+    if !kinds.iter().any(|k| matches!(k, Kind::Flowchart | Kind::Class)) {
+        problems.push("No component diagram (flowchart or classDiagram) found".to_string());
+    }
+    if !kinds.contains(&Kind::Sequence) {
+        problems.push("No sequenceDiagram found".to_string());
+    }
+    problems
+}
+
+fn extract_blocks(text: &str) -> Vec<Vec<&str>> {
+    let mut blocks = Vec::new();
+    let mut current: Option<Vec<&str>> = None;
+
+    for line in text.lines() {
+        let trimmed = line.trim();
+        match current.as_mut() {
+            Some(block) if trimmed.starts_with("```") => {
+                blocks.push(std::mem::take(block));
+                current = None;
+            }
+            Some(block) => block.push(line),
+            None if trimmed.starts_with("```mermaid") => current = Some(Vec::new()),
+            None => {}
+        }
+    }
+    if let Some(block) = current {
+        blocks.push(block);
+    }
+    blocks
+}
+
+fn validate(lines: &[&str]) -> Result<Kind, Vec<String>> {
+    let mut body = lines
+        .iter()
+        .enumerate()
+        .map(|(i, l)| (i + 1, l.trim()))
+        .filter(|(_, l)| !l.is_empty() && !l.starts_with("%%"));
+
+    let Some((_, header)) = body.next() else {
+        return Err(vec!["empty diagram".to_string()]);
+    };
+    let kind = parse_header(header).map_err(|e| vec![e])?;
+
+    let mut errors = Vec::new();
+    let mut open_blocks: Vec<&str> = Vec::new();
+    for (number, line) in body {
+        // Entity relationship cardinalities (`}o--||`) are not brackets.
+        if kind != Kind::Entity {
+            if let Err(e) = check_brackets(line) {
+                errors.push(format!("line {number}: {e}: `{line}`"));
+                continue;
+            }
+        }
+
+        let keyword = line.split_whitespace().next().unwrap_or("");
+        match (kind, keyword) {
+            (_, "end") | (Kind::State, "}") => {
+                let closed = open_blocks.pop();
+                if closed.is_none() {
+                    errors.push(format!("line {number}: `{keyword}` without an open block"));
+                }
+            }
+            (Kind::Flowchart, "subgraph") => open_blocks.push("subgraph"),
+            (Kind::State, _) if line.ends_with('{') => open_blocks.push("state"),
+            (Kind::Sequence, k) if SEQUENCE_BLOCKS.contains(&k) => open_blocks.push("block"),
+            (Kind::Sequence, _) => {
+                if let Err(e) = check_sequence_line(line, !open_blocks.is_empty()) {
+                    errors.push(format!("line {number}: {e}: `{line}`"));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    if !open_blocks.is_empty() {
+        errors.push(format!("{} block(s) not closed with `end`", open_blocks.len()));
+    }
+    if errors.is_empty() {
+        Ok(kind)
+    } else {
+        Err(errors)
+    }
+}
+
+fn parse_header(header: &str) -> Result<Kind, String> {
+    let mut words = header.split_whitespace();
+    let kind = match words.next().unwrap_or("") {
+        "flowchart" | "graph" => {
+            if let Some(direction) = words.next() {
+                if !FLOWCHART_DIRECTIONS.contains(&direction.trim_end_matches(';')) {
+                    return Err(format!("unknown flowchart direction `{direction}`"));
+                }
+            }
+            Kind::Flowchart
+        }
+        "sequenceDiagram" => Kind::Sequence,
+        "classDiagram" | "classDiagram-v2" => Kind::Class,
+        "stateDiagram" | "stateDiagram-v2" => Kind::State,
+        "erDiagram" => Kind::Entity,
+        other => return Err(format!("unsupported or missing diagram type `{other}`")),
+    };
+    Ok(kind)
+}
+
+// Brackets must balance outside double-quoted labels.
+fn check_brackets(line: &str) -> Result<(), String> {
+    let mut stack = Vec::new();
+    let mut in_quotes = false;
+    for c in line.chars() {
+        match c {
+            '"' => in_quotes = !in_quotes,
+            _ if in_quotes => {}
+            '(' | '[' | '{' => stack.push(c),
+            ')' | ']' | '}' => {
+                let expected = match c {
+                    ')' => '(',
+                    ']' => '[',
+                    _ => '{',
+                };
+                // A lone `}` closes a state block opened on an earlier line.
+                if stack.is_empty() && c == '}' && line.trim() == "}" {
+                    return Ok(());
+                }
+                if stack.pop() != Some(expected) {
+                    return Err(format!("unbalanced `{c}`"));
+                }
+            }
+            _ => {}
+        }
+    }
+    if in_quotes {
+        return Err("unclosed quote".to_string());
+    }
+    // A trailing `{` opens a state or class body on the following lines.
+    if stack.iter().any(|&c| c != '{') || stack.len() > 1 {
+        return Err("unclosed bracket".to_string());
+    }
+    Ok(())
+}
+
+fn check_sequence_line(line: &str, in_block: bool) -> Result<(), String> {
+    let keyword = line.split_whitespace().next().unwrap_or("").to_lowercase();
+    if SEQUENCE_STATEMENTS.contains(&keyword.as_str()) {
+        if matches!(keyword.as_str(), "else" | "and" | "option") && !in_block {
+            return Err(format!("`{keyword}` outside an alt/par/critical block"));
+        }
+        return Ok(());
+    }
+
+    let (head, _) = line
+        .split_once(':')
+        .ok_or_else(|| "message is missing `: text`".to_string())?;
+    let arrow = SEQUENCE_ARROWS
+        .iter()
+        .find(|a| head.contains(*a))
+        .ok_or_else(|| "not a recognized statement or message arrow".to_string())?;
+    let (from, to) = head.split_once(arrow).unwrap_or_default();
+    if from.trim().is_empty() || to.trim_start_matches(['+', '-']).trim().is_empty() {
+        return Err("message must name both participants".to_string());
+    }
+    Ok(())
+}
@@ -0,0 +1,179 @@
+use crate::llm;
+use crate::plan::{self, StructuredPlan};
+use crate::templates;
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::collections::VecDeque;
+use std::path::Path;
+use std::sync::Mutex;
+
+const EMBEDDING_DIMENSIONS: usize = 256;
+const MAX_SELECTED_FILES: usize = 10;
+// Requests kept for `requests`; older ones are dropped, so a long-running
+// mock server does not grow without bound.
+const MAX_RECORDED_REQUESTS: usize = 200;
+
+// A canned response, used when `match` occurs in any message of a request.
+// `{{ model }}` and `{{ user }}` (the last user message) are substituted.
+#[derive(Deserialize)]
+struct MockRule {
+    #[serde(rename = "match")]
+    pattern: String,
+    response: String,
+}
+
+// A chat request the mock answered.
+#[derive(Clone, Debug)]
+pub struct MockRequest {
+    pub model: String,
+    // (role, content) pairs in request order.
+    pub messages: Vec<(String, String)>,
+    pub response: String,
+}
+
+impl MockRequest {
+    // The content of the request's system message, or "".
+    pub fn system(&self) -> &str {
+        self.messages.iter().find(|(role, _)| role == "system").map_or("", |(_, c)| c.as_str())
+    }
+
+    // The content of the request's last user message, or "".
+    pub fn user(&self) -> &str {
+        self.messages.iter().rev().find(|(role, _)| role == "user").map_or("", |(_, c)| c.as_str())
+    }
+}
+
+// Offline stand-in for the model provider (`LLM_PROVIDER=mock`), for
+// integration tests and demos. Chat requests get the first matching rule's
+// response, or else a built-in response shaped like what the pipeline
+// expects (file list, score, JSON plan, diff, or markdown with its required
+// sections). Embeddings are hashed bags of words, so retrieval still favors
+// files that share words with the prompt.
+#[derive(Default)]
+pub struct MockLlm {
+    rules: Vec<MockRule>,
+    recorded: Mutex<VecDeque<MockRequest>>,
+}
+
+impl MockLlm {
+    // `rules_path` is a JSON array of `{"match": ..., "response": ...}`.
+    pub fn load(rules_path: Option<&Path>) -> Result<Self> {
+        let Some(path) = rules_path else {
+            return Ok(Self::default());
+        };
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read mock responses '{}'", path.display()))?;
+        let rules = serde_json::from_str(&text)
+            .with_context(|| format!("Invalid mock responses in '{}'", path.display()))?;
+        Ok(Self { rules, recorded: Mutex::default() })
+    }
+
+    // `messages` are (role, content) pairs in request order.
+    pub fn chat(&self, model: &str, messages: &[(String, String)]) -> String {
+        let mut request = MockRequest { model: model.to_string(), messages: messages.to_vec(), response: String::new() };
+        let (system, user) = (request.system(), request.user());
+        let response = match self.rules.iter().find(|r| messages.iter().any(|(_, c)| c.contains(&r.pattern))) {
+            Some(rule) => templates::render(&rule.response, &[("model", model), ("user", user)]),
+            None => builtin_response(model, system, user),
+        };
+        request.response = response.clone();
+        let mut recorded = self.recorded.lock().unwrap();
+        if recorded.len() == MAX_RECORDED_REQUESTS {
+            recorded.pop_front();
+        }
+        recorded.push_back(request);
+        response
+    }
+
+    // The most recent chat requests, oldest first.
+    pub fn requests(&self) -> Vec<MockRequest> {
+        self.recorded.lock().unwrap().iter().cloned().collect()
+    }
+
+    pub fn clear_requests(&self) {
+        self.recorded.lock().unwrap().clear();
+    }
+
+    pub fn embed(&self, inputs: &[String]) -> Vec<Vec<f32>> {
+        inputs.iter().map(|input| embedding(input)).collect()
+    }
+}
+
+fn builtin_response(model: &str, system: &str, user: &str) -> String {
+    if system == llm::FILE_SELECTION_SYSTEM {
+        // File tree lines are `path (N bytes)`.
+        let paths: Vec<&str> = user
+            .lines()
+            .filter_map(|l| l.rsplit_once(" (").map(|(path, _)| path))
+            .take(MAX_SELECTED_FILES)
+            .collect();
+        return serde_json::to_string(&paths).unwrap_or_default();
+    }
+    if system == llm::SELF_EVALUATION_SYSTEM {
+        return r#"{"score": 10, "reason": "Mock responses are always accepted."}"#.to_string();
+    }
+    if system.starts_with(llm::CANDIDATE_JUDGE_SYSTEM) {
+        // The judge selects the first candidate.
+        if let Some((_, candidates)) = user.split_once("Candidate 1:\n") {
+            return candidates.split("\n\nCandidate 2:\n").next().unwrap_or(candidates).to_string();
+        }
+    }
+    if system == llm::VERIFY_SYSTEM {
+        return "None".to_string();
+    }
+    if system.starts_with(llm::CRITIC_SYSTEM) {
+        // The critic returns the draft unchanged.
+        if let Some((_, draft)) = user.split_once("Draft Response:\n") {
+            return draft.rsplit_once("\n\nReview the draft").map_or(draft, |(d, _)| d).to_string();
+        }
+    }
+
+    let note = format!(
+        "This response was generated by the mock LLM provider (`LLM_PROVIDER=mock`) for model `{model}`; nothing was sent to a model. The request was {} characters long.",
+        system.len() + user.len()
+    );
+    if system.contains(plan::DIFF_INSTRUCTIONS) {
+        return format!(
+            "```diff\n--- /dev/null\n+++ b/MOCK_RESPONSE.md\n@@ -0,0 +1 @@\n+Mock patch\n```\n\n- {note}"
+        );
+    }
+    if system.ends_with(&plan::json_instructions()) {
+        let plan = StructuredPlan {
+            summary: note,
+            file_changes: Vec::new(),
+            steps: vec!["Replace LLM_PROVIDER=mock with a real provider to get an actual plan.".to_string()],
+            risks: Vec::new(),
+            assumptions: Vec::new(),
+            open_questions: Vec::new(),
+        };
+        return serde_json::to_string_pretty(&plan).unwrap_or_default();
+    }
+
+    let mut response = format!("# Mock Response\n\n{note}\n");
+    if system == llm::FOLLOW_UP_SYSTEM {
+        response.push_str(&format!("\nQuestion: {}\n", user.lines().next().unwrap_or_default()));
+    }
+    if system.contains(plan::ASSUMPTIONS_INSTRUCTIONS) {
+        response.push_str("\n## Assumptions\n\nNone\n\n## Open Questions\n\nNone\n");
+    }
+    if system.contains(plan::MANIFEST_INSTRUCTIONS) {
+        response.push_str("\n## Change Manifest\n\n```json\n[]\n```\n");
+    }
+    response
+}
+
+// Normalized hashed bag of lowercase words.
+fn embedding(text: &str) -> Vec<f32> {
+    let mut vector = vec![0.0f32; EMBEDDING_DIMENSIONS];
+    for word in text.split(|c: char| !c.is_alphanumeric() && c != '_').filter(|w| !w.is_empty()) {
+        let hash = Sha256::digest(word.to_lowercase().as_bytes());
+        let bucket = u16::from_le_bytes([hash[0], hash[1]]) as usize % EMBEDDING_DIMENSIONS;
+        vector[bucket] += 1.0;
+    }
+    let norm = vector.iter().map(|v| v * v).sum::<f32>().sqrt();
+    if norm > 0.0 {
+        vector.iter_mut().for_each(|v| *v /= norm);
+    }
+    vector
+}
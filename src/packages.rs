@@ -0,0 +1,234 @@
+use crate::files::SourceFile;
+use std::path::{Path, PathBuf};
+
+// A package of a multi-package repository: a Cargo workspace member, an npm
+// workspace, or a Go module.
+pub struct Package {
+    pub name: String,
+    pub ecosystem: &'static str,
+    // Directory relative to the repository root; empty for the root itself.
+    pub dir: PathBuf,
+}
+
+// Packages declared by workspace manifests at the root of `source_files`: a
+// `[workspace]` in Cargo.toml, `workspaces` in package.json (or
+// pnpm-workspace.yaml), and go.work or several go.mod files. Returns nothing
+// for single-package repositories and for directories below the repo root.
+pub fn detect(source_files: &[SourceFile]) -> Vec<Package> {
+    let mut packages = Vec::new();
+    packages.extend(cargo_members(source_files));
+    packages.extend(npm_workspaces(source_files));
+    packages.extend(go_modules(source_files));
+    packages
+}
+
+fn root_file<'a>(source_files: &'a [SourceFile], name: &str) -> Option<&'a SourceFile> {
+    source_files.iter().find(|f| f.path == Path::new(name))
+}
+
+// Manifests named `name` whose directory matches one of `include` and none of
+// `exclude`, as (directory, content).
+fn matching_manifests<'a>(
+    source_files: &'a [SourceFile],
+    name: &str,
+    include: &[String],
+    exclude: &[String],
+) -> Vec<(PathBuf, &'a str)> {
+    source_files
+        .iter()
+        .filter(|f| f.path.file_name().is_some_and(|n| n == name))
+        .filter_map(|f| {
+            let dir = f.path.parent().unwrap_or(Path::new("")).to_path_buf();
+            let rel = dir.to_string_lossy().replace('\\', "/");
+            let included = include.iter().any(|p| glob_matches(p, &rel)) && !exclude.iter().any(|p| glob_matches(p, &rel));
+            included.then_some((dir, f.content.as_str()))
+        })
+        .collect()
+}
+
+fn cargo_members(source_files: &[SourceFile]) -> Vec<Package> {
+    let Some(root) = root_file(source_files, "Cargo.toml") else {
+        return Vec::new();
+    };
+    let Ok(doc) = toml::from_str::<toml::Table>(&root.content) else {
+        return Vec::new();
+    };
+    let Some(workspace) = doc.get("workspace") else {
+        return Vec::new();
+    };
+    let patterns = |field: &str| -> Vec<String> {
+        workspace
+            .get(field)
+            .and_then(|v| v.as_array())
+            .map(|a| a.iter().filter_map(|v| v.as_str()).map(String::from).collect())
+            .unwrap_or_default()
+    };
+
+    // A root `[package]` is a member even when `members` omits it.
+    let mut include = patterns("members");
+    include.push(String::new());
+    matching_manifests(source_files, "Cargo.toml", &include, &patterns("exclude"))
+        .into_iter()
+        .filter_map(|(dir, content)| {
+            let doc = toml::from_str::<toml::Table>(content).ok()?;
+            let name = doc.get("package")?.get("name")?.as_str()?.to_string();
+            Some(Package { name, ecosystem: "Cargo", dir })
+        })
+        .collect()
+}
+
+fn npm_workspaces(source_files: &[SourceFile]) -> Vec<Package> {
+    let root = root_file(source_files, "package.json").and_then(|f| serde_json::from_str::<serde_json::Value>(&f.content).ok());
+    // `workspaces` is either an array of globs or `{ "packages": [...] }` (Yarn).
+    let mut patterns: Vec<String> = root
+        .as_ref()
+        .and_then(|doc| doc.get("workspaces"))
+        .and_then(|w| w.as_array().or_else(|| w.get("packages").and_then(|p| p.as_array())))
+        .map(|a| a.iter().filter_map(|v| v.as_str()).map(String::from).collect())
+        .unwrap_or_default();
+    if let Some(pnpm) = root_file(source_files, "pnpm-workspace.yaml") {
+        patterns.extend(pnpm_packages(&pnpm.content));
+    }
+    if patterns.is_empty() {
+        return Vec::new();
+    }
+
+    // Leading `!` negates a glob in both formats.
+    let (exclude, include): (Vec<String>, Vec<String>) = patterns.into_iter().partition(|p| p.starts_with('!'));
+    let exclude: Vec<String> = exclude.iter().map(|p| p[1..].to_string()).collect();
+    matching_manifests(source_files, "package.json", &include, &exclude)
+        .into_iter()
+        .filter_map(|(dir, content)| {
+            let doc = serde_json::from_str::<serde_json::Value>(content).ok()?;
+            let name = doc.get("name")?.as_str()?.to_string();
+            Some(Package { name, ecosystem: "npm", dir })
+        })
+        .collect()
+}
+
+// The `packages:` list of pnpm-workspace.yaml, read line by line rather than
+// with a YAML parser since it is always a flat list of quoted or bare globs.
+fn pnpm_packages(content: &str) -> Vec<String> {
+    let mut in_packages = false;
+    let mut patterns = Vec::new();
+    for line in content.lines() {
+        let trimmed = line.trim();
+        if !line.starts_with([' ', '\t', '-']) && !trimmed.is_empty() {
+            in_packages = trimmed == "packages:";
+            continue;
+        }
+        if let Some(item) = trimmed.strip_prefix('-').filter(|_| in_packages) {
+            let item = item.split(" #").next().unwrap_or("").trim().trim_matches(['\'', '"']);
+            if !item.is_empty() {
+                patterns.push(item.to_string());
+            }
+        }
+    }
+    patterns
+}
+
+// Modules listed by `use` in go.work, or every go.mod when there is more than
+// one (a multi-module repository without a workspace file).
+fn go_modules(source_files: &[SourceFile]) -> Vec<Package> {
+    let include = match root_file(source_files, "go.work") {
+        Some(work) => go_work_uses(&work.content),
+        None if source_files.iter().filter(|f| f.path.file_name().is_some_and(|n| n == "go.mod")).count() > 1 => {
+            vec!["**".to_string()]
+        }
+        None => return Vec::new(),
+    };
+    matching_manifests(source_files, "go.mod", &include, &[])
+        .into_iter()
+        .filter_map(|(dir, content)| {
+            let name = content
+                .lines()
+                .find_map(|l| l.trim().strip_prefix("module "))?
+                .trim()
+                .trim_matches('"')
+                .to_string();
+            Some(Package { name, ecosystem: "Go", dir })
+        })
+        .collect()
+}
+
+// Directories from `use ./dir` and `use ( ... )` blocks.
+fn go_work_uses(content: &str) -> Vec<String> {
+    let mut in_block = false;
+    let mut dirs = Vec::new();
+    for line in content.lines() {
+        let line = line.split("//").next().unwrap_or("").trim();
+        let dir = if in_block {
+            if line == ")" {
+                in_block = false;
+                continue;
+            }
+            line
+        } else if let Some(rest) = line.strip_prefix("use") {
+            let rest = rest.trim();
+            if rest == "(" {
+                in_block = true;
+                continue;
+            }
+            rest
+        } else {
+            continue;
+        };
+        if !dir.is_empty() {
+            dirs.push(dir.trim_matches('"').to_string());
+        }
+    }
+    dirs
+}
+
+// Matches a workspace glob such as `crates/*` or `packages/**` against a
+// relative directory, component by component. `*` matches within one
+// component and `**` matches any number of them.
+fn glob_matches(pattern: &str, dir: &str) -> bool {
+    let pattern = pattern.trim_start_matches("./").trim_end_matches('/');
+    let pattern: Vec<&str> = pattern.split('/').filter(|c| !c.is_empty() && *c != ".").collect();
+    let dir: Vec<&str> = dir.split('/').filter(|c| !c.is_empty()).collect();
+    components_match(&pattern, &dir)
+}
+
+fn components_match(pattern: &[&str], dir: &[&str]) -> bool {
+    match pattern.split_first() {
+        None => dir.is_empty(),
+        Some((&"**", rest)) => (0..=dir.len()).any(|skip| components_match(rest, &dir[skip..])),
+        Some((first, rest)) => dir
+            .split_first()
+            .is_some_and(|(component, dir_rest)| component_matches(first, component) && components_match(rest, dir_rest)),
+    }
+}
+
+fn component_matches(pattern: &str, component: &str) -> bool {
+    match pattern.split_once('*') {
+        None => pattern == component,
+        Some((prefix, rest)) => {
+            let Some(remaining) = component.strip_prefix(prefix) else {
+                return false;
+            };
+            (0..=remaining.len())
+                .filter(|&i| remaining.is_char_boundary(i))
+                .any(|i| component_matches(rest, &remaining[i..]))
+        }
+    }
+}
+
+// Header placed ahead of the codebase report so that plans name the right
+// package even when the context below only contains a subset of the files.
+pub fn render(packages: &[Package], label: Option<&str>) -> String {
+    let title = match label {
+        Some(label) => format!("Workspace Map of `{label}`"),
+        None => "Workspace Map".to_string(),
+    };
+    let mut out = format!(
+        "{title} (every package in this repository; when a change belongs to a package, use that package's name and paths under its directory):\n\n| Package | Ecosystem | Directory |\n|---|---|---|\n"
+    );
+    for package in packages {
+        let dir = package.dir.to_string_lossy().replace('\\', "/");
+        let dir = if dir.is_empty() { ".".to_string() } else { dir };
+        out.push_str(&format!("| `{}` | {} | `{dir}` |\n", package.name, package.ecosystem));
+    }
+    out.push_str("\n\n");
+    out
+}
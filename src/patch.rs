@@ -0,0 +1,340 @@
+use crate::apply;
+use anyhow::{Context, Result};
+use std::path::Path;
+
+const MAX_HUNK_OFFSET: usize = 50;
+
+pub struct FilePatch {
+    pub old_path: Option<String>,
+    pub new_path: Option<String>,
+    pub hunks: Vec<Hunk>,
+}
+
+pub struct Hunk {
+    pub old_start: usize,
+    pub lines: Vec<HunkLine>,
+}
+
+pub enum HunkLine {
+    Context(String),
+    Remove(String),
+    Add,
+}
+
+impl Hunk {
+    fn old_lines(&self) -> Vec<&str> {
+        self.lines
+            .iter()
+            .filter_map(|l| match l {
+                HunkLine::Context(s) | HunkLine::Remove(s) => Some(s.as_str()),
+                HunkLine::Add => None,
+            })
+            .collect()
+    }
+}
+
+pub fn extract_diff(text: &str) -> String {
+    // Prefer the contents of a ```diff fence when the model added prose around it.
+    if let Some(start) = text.find("```diff").or_else(|| text.find("```patch")) {
+        let body = &text[start..];
+        let body = &body[body.find('\n').map(|i| i + 1).unwrap_or(body.len())..];
+        if let Some(end) = body.find("\n```") {
+            return format!("{}\n", &body[..end]);
+        }
+    }
+    text.to_string()
+}
+
+pub fn parse_unified_diff(diff: &str) -> Result<Vec<FilePatch>> {
+    let mut patches: Vec<FilePatch> = Vec::new();
+    let mut lines = diff.lines().peekable();
+
+    while let Some(line) = lines.next() {
+        if let Some(old) = line.strip_prefix("--- ") {
+            let new = lines
+                .next()
+                .and_then(|l| l.strip_prefix("+++ "))
+                .context("'---' header not followed by '+++' header")?;
+            patches.push(FilePatch {
+                old_path: strip_diff_path(old),
+                new_path: strip_diff_path(new),
+                hunks: Vec::new(),
+            });
+        } else if let Some(header) = line.strip_prefix("@@ ") {
+            let patch = patches.last_mut().context("Hunk found before any file header")?;
+            let (old_start, old_len, new_len) = parse_hunk_header(header)
+                .with_context(|| format!("Malformed hunk header: {line}"))?;
+
+            let mut hunk = Hunk { old_start, lines: Vec::new() };
+            let (mut old_seen, mut new_seen) = (0, 0);
+            while old_seen < old_len || new_seen < new_len {
+                let Some(body) = lines.next() else {
+                    return Err(anyhow::anyhow!("Hunk at line {old_start} ends early"));
+                };
+                if body.starts_with('\\') {
+                    continue;
+                }
+                match body.chars().next() {
+                    Some('+') => {
+                        new_seen += 1;
+                        hunk.lines.push(HunkLine::Add);
+                    }
+                    Some('-') => {
+                        old_seen += 1;
+                        hunk.lines.push(HunkLine::Remove(body[1..].to_string()));
+                    }
+                    // Some models drop the leading space on blank context lines.
+                    Some(' ') | None => {
+                        old_seen += 1;
+                        new_seen += 1;
+                        hunk.lines.push(HunkLine::Context(body.get(1..).unwrap_or_default().to_string()));
+                    }
+                    _ => return Err(anyhow::anyhow!("Unexpected line inside hunk: {body}")),
+                }
+            }
+            patch.hunks.push(hunk);
+        }
+    }
+
+    if patches.is_empty() {
+        return Err(anyhow::anyhow!("No file headers ('--- a/...' / '+++ b/...') found in diff"));
+    }
+    Ok(patches)
+}
+
+fn strip_diff_path(raw: &str) -> Option<String> {
+    let path = raw.split('\t').next().unwrap_or(raw).trim();
+    if path == "/dev/null" {
+        return None;
+    }
+    Some(
+        path.strip_prefix("a/")
+            .or_else(|| path.strip_prefix("b/"))
+            .unwrap_or(path)
+            .to_string(),
+    )
+}
+
+fn parse_hunk_header(header: &str) -> Option<(usize, usize, usize)> {
+    let mut parts = header.split_whitespace();
+    let (old_start, old_len) = parse_range(parts.next()?.strip_prefix('-')?)?;
+    let (_, new_len) = parse_range(parts.next()?.strip_prefix('+')?)?;
+    Some((old_start, old_len, new_len))
+}
+
+fn parse_range(range: &str) -> Option<(usize, usize)> {
+    match range.split_once(',') {
+        Some((start, len)) => Some((start.parse().ok()?, len.parse().ok()?)),
+        None => Some((range.parse().ok()?, 1)),
+    }
+}
+
+pub enum HunkStatus {
+    Applies,
+    Offset(isize),
+    Fails,
+}
+
+pub struct PatchCheck {
+    pub path: String,
+    pub problems: Vec<String>,
+    pub hunk_status: Vec<HunkStatus>,
+}
+
+impl PatchCheck {
+    pub fn applies_cleanly(&self) -> bool {
+        self.problems.is_empty() && self.hunk_status.iter().all(|s| !matches!(s, HunkStatus::Fails))
+    }
+}
+
+pub async fn check_patches(root: &Path, patches: &[FilePatch]) -> Vec<PatchCheck> {
+    let mut checks = Vec::new();
+
+    for patch in patches {
+        let path = patch.old_path.clone().or_else(|| patch.new_path.clone()).unwrap_or_default();
+        let mut check = PatchCheck {
+            path: path.clone(),
+            problems: Vec::new(),
+            hunk_status: Vec::new(),
+        };
+
+        // Paths come from model output, so they are held to the codebase
+        // root as apply_plan's are.
+        let target = match apply::safe_relative_path(&path) {
+            Ok(relative) => apply::contained_path(root, &relative).await,
+            Err(e) => Err(e),
+        };
+        let target = match target {
+            Ok(target) => target,
+            Err(e) => {
+                check.problems.push(format!("{e:#}"));
+                checks.push(check);
+                continue;
+            }
+        };
+
+        let existing = tokio::fs::read_to_string(&target).await.ok();
+        match (&patch.old_path, existing) {
+            (None, Some(_)) => check.problems.push("patch creates a file that already exists".to_string()),
+            (None, None) => {}
+            (Some(_), None) => check.problems.push("file does not exist".to_string()),
+            (Some(_), Some(content)) => {
+                let file_lines: Vec<&str> = content.lines().collect();
+                for hunk in &patch.hunks {
+                    check.hunk_status.push(locate_hunk(&file_lines, hunk));
+                }
+            }
+        }
+        checks.push(check);
+    }
+
+    checks
+}
+
+fn locate_hunk(file_lines: &[&str], hunk: &Hunk) -> HunkStatus {
+    let expected = hunk.old_lines();
+    let matches_at = |start: usize| {
+        start + expected.len() <= file_lines.len()
+            && expected
+                .iter()
+                .zip(&file_lines[start..])
+                .all(|(a, b)| a.trim_end() == b.trim_end())
+    };
+
+    let stated = hunk.old_start.saturating_sub(1);
+    if matches_at(stated) {
+        return HunkStatus::Applies;
+    }
+    for offset in 1..=MAX_HUNK_OFFSET {
+        if stated >= offset && matches_at(stated - offset) {
+            return HunkStatus::Offset(-(offset as isize));
+        }
+        if matches_at(stated + offset) {
+            return HunkStatus::Offset(offset as isize);
+        }
+    }
+    HunkStatus::Fails
+}
+
+pub fn render_checks(checks: &[PatchCheck]) -> String {
+    let mut out = String::new();
+    let all_clean = checks.iter().all(PatchCheck::applies_cleanly);
+    out.push_str(if all_clean {
+        "**Patch validation:** all hunks apply cleanly against the current files.\n"
+    } else {
+        "**Patch validation:** WARNING - some hunks do not apply against the current files. Review before applying.\n"
+    });
+
+    for check in checks {
+        let mut notes: Vec<String> = check.problems.clone();
+        for (i, status) in check.hunk_status.iter().enumerate() {
+            match status {
+                HunkStatus::Applies => {}
+                HunkStatus::Offset(n) => notes.push(format!("hunk {} applies with offset {n}", i + 1)),
+                HunkStatus::Fails => notes.push(format!("hunk {} does not match the file contents", i + 1)),
+            }
+        }
+        let status = if check.applies_cleanly() { "OK" } else { "FAILED" };
+        if notes.is_empty() {
+            out.push_str(&format!("- `{}`: {status}\n", check.path));
+        } else {
+            out.push_str(&format!("- `{}`: {status} ({})\n", check.path, notes.join("; ")));
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DIFF: &str = "\
+--- a/src/lib.rs
++++ b/src/lib.rs
+@@ -2,3 +2,4 @@ fn header()
+ fn one() {}
+
+-fn two() {}
++fn two() -> u8 { 2 }
++fn three() {}
+\\ No newline at end of file
+--- /dev/null
++++ b/src/new.rs
+@@ -0,0 +1,1 @@
++pub fn new() {}
+";
+
+    const FILE: &str = "// lib\nfn one() {}\n\nfn two() {}\n";
+
+    fn status(file: &str, diff: &str) -> Vec<String> {
+        let patches = parse_unified_diff(diff).unwrap();
+        let lines: Vec<&str> = file.lines().collect();
+        patches[0]
+            .hunks
+            .iter()
+            .map(|hunk| match locate_hunk(&lines, hunk) {
+                HunkStatus::Applies => "applies".to_string(),
+                HunkStatus::Offset(offset) => format!("offset {offset}"),
+                HunkStatus::Fails => "fails".to_string(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn parses_files_and_hunks() {
+        let patches = parse_unified_diff(DIFF).unwrap();
+        assert_eq!(patches.len(), 2);
+        assert_eq!(patches[0].old_path.as_deref(), Some("src/lib.rs"));
+        assert_eq!(patches[0].hunks[0].old_start, 2);
+        // The blank context line lost its leading space.
+        assert_eq!(patches[0].hunks[0].old_lines(), ["fn one() {}", "", "fn two() {}"]);
+        assert_eq!(patches[0].hunks[0].lines.iter().filter(|l| matches!(l, HunkLine::Add)).count(), 2);
+        assert_eq!(patches[1].old_path, None);
+        assert_eq!(patches[1].new_path.as_deref(), Some("src/new.rs"));
+    }
+
+    #[test]
+    fn rejects_malformed_diffs() {
+        assert!(parse_unified_diff("just prose").is_err());
+        assert!(parse_unified_diff("@@ -1 +1 @@\n-a\n+b\n").is_err());
+        assert!(parse_unified_diff("--- a/x.rs\n+++ b/x.rs\n@@ -1,3 +1,3 @@\n a\n").is_err());
+        assert!(parse_unified_diff("--- a/x.rs\n+++ b/x.rs\n@@ -x +1 @@\n").is_err());
+    }
+
+    #[test]
+    fn extracts_the_fenced_diff() {
+        let text = format!("Here is the patch:\n\n```diff\n{DIFF}```\n\nNotes follow.");
+        assert_eq!(extract_diff(&text), DIFF);
+        assert_eq!(extract_diff(DIFF), DIFF);
+    }
+
+    #[test]
+    fn locates_hunks() {
+        assert_eq!(status(FILE, DIFF), ["applies"]);
+        assert_eq!(status(&format!("// a\n// b\n{FILE}"), DIFF), ["offset 2"]);
+        assert_eq!(status(&FILE[7..], DIFF), ["offset -1"]);
+        assert_eq!(status(&FILE.replace("two", "deux"), DIFF), ["fails"]);
+        // Trailing whitespace is not a mismatch.
+        assert_eq!(status(&FILE.replace("fn one() {}", "fn one() {}  "), DIFF), ["applies"]);
+    }
+
+    #[tokio::test]
+    async fn checks_patches_against_the_files() {
+        let repo = crate::testing::TempRepo::with_files(&[("src/lib.rs", FILE), ("src/new.rs", "")]).unwrap();
+        let checks = check_patches(repo.path(), &parse_unified_diff(DIFF).unwrap()).await;
+        assert!(checks[0].applies_cleanly());
+        assert_eq!(checks[1].problems, ["patch creates a file that already exists"]);
+    }
+
+    #[tokio::test]
+    async fn refuses_paths_outside_the_root() {
+        let repo = crate::testing::TempRepo::with_files(&[("src/lib.rs", FILE)]).unwrap();
+        let diff = "--- /etc/passwd\n+++ /etc/passwd\n@@ -1,1 +1,1 @@\n-root:x:0:0\n+root\n--- a/../outside.rs\n+++ b/../outside.rs\n@@ -1,1 +1,1 @@\n-a\n+b\n";
+        let checks = check_patches(repo.path(), &parse_unified_diff(diff).unwrap()).await;
+        assert_eq!(checks.len(), 2);
+        for check in checks {
+            assert!(check.hunk_status.is_empty());
+            assert_eq!(check.problems, ["path must be relative to the codebase root without '..'"]);
+        }
+    }
+}
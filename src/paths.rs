@@ -0,0 +1,234 @@
+use crate::apply::safe_relative_path;
+use crate::files;
+use crate::plan::{ChangeType, ManifestEntry, StructuredPlan};
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::path::Path;
+
+// Words on a mention's line that mark the file as one the plan creates.
+const CREATION_WORDS: &[&str] = &["create", "creates", "creating", "new", "introduce", "introduces", "scaffold"];
+
+#[derive(Serialize, JsonSchema, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum PathStatus {
+    // Exists in the codebase.
+    Existing,
+    // Does not exist, and the plan creates it.
+    New,
+    // Does not exist and the plan does not create it: likely hallucinated.
+    Missing,
+}
+
+#[derive(Serialize, JsonSchema, Clone)]
+pub struct PathCheck {
+    pub path: String,
+    pub status: PathStatus,
+}
+
+// A file path mentioned in a plan, and whether the plan creates it: a
+// manifest or file_changes operation when there is one, else the wording of
+// the lines that mention it.
+pub struct Mention {
+    path: String,
+    operation: Option<ChangeType>,
+    created: bool,
+}
+
+// File paths in a markdown plan: inline code spans outside fenced code
+// blocks, plus the change manifest's entries.
+pub fn mentioned_in_markdown(text: &str, manifest: &[ManifestEntry]) -> Vec<Mention> {
+    let mut mentions = Vec::new();
+    for entry in manifest {
+        add_operation(&mut mentions, &entry.path, entry.operation);
+    }
+    add_spans(&mut mentions, text);
+    mentions
+}
+
+// File paths in a JSON plan: its file_changes, plus inline code spans in the
+// summary, steps, and risks.
+pub fn mentioned_in_plan(plan: &StructuredPlan) -> Vec<Mention> {
+    let mut mentions = Vec::new();
+    for change in &plan.file_changes {
+        add_operation(&mut mentions, &change.path, change.change_type);
+    }
+    add_spans(&mut mentions, &plan.summary);
+    for text in plan.steps.iter().chain(&plan.risks) {
+        add_spans(&mut mentions, text);
+    }
+    mentions
+}
+
+// Checks every mentioned path against the files under `roots`. A path exists
+// if any root contains it. Bare file names (no directory) that are not at a
+// root are left out unless the plan creates them, since they usually name a
+// file elsewhere in the tree.
+pub async fn check(roots: &[&Path], mentions: Vec<Mention>) -> Vec<PathCheck> {
+    let mut checks = Vec::new();
+    for mention in mentions {
+        let Ok(relative) = safe_relative_path(&mention.path) else {
+            continue;
+        };
+        let mut exists = false;
+        for root in roots {
+            if tokio::fs::metadata(root.join(&relative)).await.is_ok() {
+                exists = true;
+                break;
+            }
+        }
+        let created = match mention.operation {
+            Some(operation) => operation == ChangeType::Create,
+            None => mention.created,
+        };
+        let status = match (exists, created) {
+            (true, _) => PathStatus::Existing,
+            (false, true) => PathStatus::New,
+            (false, false) if mention.operation.is_none() && !mention.path.contains('/') => continue,
+            (false, false) => PathStatus::Missing,
+        };
+        checks.push(PathCheck { path: mention.path, status });
+    }
+    checks
+}
+
+pub fn render(checks: &[PathCheck]) -> Option<String> {
+    if checks.is_empty() {
+        return None;
+    }
+    let listed = |status: PathStatus| {
+        let paths: Vec<String> = checks.iter().filter(|c| c.status == status).map(|c| format!("`{}`", c.path)).collect();
+        paths.join(", ")
+    };
+    let existing = checks.iter().filter(|c| c.status == PathStatus::Existing).count();
+    let mut note = format!("**Referenced files**: {existing} of {} exist in the codebase", checks.len());
+    let new = listed(PathStatus::New);
+    if !new.is_empty() {
+        note.push_str(&format!("; new files created by the plan: {new}"));
+    }
+    let missing = listed(PathStatus::Missing);
+    if !missing.is_empty() {
+        note.push_str(&format!("; not found and not created by the plan (possibly hallucinated; treat with caution): {missing}"));
+    }
+    Some(note)
+}
+
+fn add_operation(mentions: &mut Vec<Mention>, path: &str, operation: ChangeType) {
+    let path = path.trim().trim_start_matches("./");
+    match mentions.iter_mut().find(|m| m.path == path) {
+        Some(mention) => mention.operation = Some(operation),
+        None => mentions.push(Mention { path: path.to_string(), operation: Some(operation), created: false }),
+    }
+}
+
+fn add_spans(mentions: &mut Vec<Mention>, text: &str) {
+    let mut in_fence = false;
+    for line in text.lines() {
+        if line.trim_start().starts_with("```") {
+            in_fence = !in_fence;
+            continue;
+        }
+        if in_fence {
+            continue;
+        }
+        let lowered = line.to_lowercase();
+        let created = lowered.split(|c: char| !c.is_alphanumeric()).any(|word| CREATION_WORDS.contains(&word));
+        for span in line.split('`').skip(1).step_by(2) {
+            let Some(path) = file_path(span) else {
+                continue;
+            };
+            match mentions.iter_mut().find(|m| m.path == path) {
+                Some(mention) => mention.created |= created,
+                None => mentions.push(Mention { path, operation: None, created }),
+            }
+        }
+    }
+}
+
+// The file path in an inline code span, without a `:line` anchor, or None if
+// the span is not one: code, module paths, URLs, globs, directories, and names
+// without a known source or config file type are skipped.
+fn file_path(span: &str) -> Option<String> {
+    let span = span.trim().trim_start_matches("./");
+    let path = match span.rsplit_once(':') {
+        Some((path, lines)) if !lines.is_empty() && lines.chars().all(|c| c.is_ascii_digit() || c == '-') => path,
+        _ => span,
+    };
+    let code_like = path.contains(|c: char| c.is_whitespace() || "*?{}[]()<>$=,;:|\"'@#&!%".contains(c));
+    if path.is_empty() || code_like || path.ends_with('/') || path.starts_with('/') {
+        return None;
+    }
+    files::language(Path::new(path))?;
+    Some(path.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempRepo;
+
+    fn statuses(checks: &[PathCheck]) -> Vec<(&str, &'static str)> {
+        checks
+            .iter()
+            .map(|c| {
+                let status = match c.status {
+                    PathStatus::Existing => "existing",
+                    PathStatus::New => "new",
+                    PathStatus::Missing => "missing",
+                };
+                (c.path.as_str(), status)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn recognizes_file_paths_in_code_spans() {
+        assert_eq!(file_path("src/lib.rs").as_deref(), Some("src/lib.rs"));
+        assert_eq!(file_path("./src/lib.rs:12-20").as_deref(), Some("src/lib.rs"));
+        assert_eq!(file_path("Cargo.toml").as_deref(), Some("Cargo.toml"));
+        for span in ["fn main()", "std::fs::read", "src/", "/etc/hosts.conf", "src/*.rs", "https://example.com/a.js", "notes.unknownext"] {
+            assert_eq!(file_path(span), None, "{span}");
+        }
+    }
+
+    #[tokio::test]
+    async fn checks_markdown_mentions() {
+        let repo = TempRepo::with_files(&[("src/lib.rs", ""), ("README.md", "")]).unwrap();
+        let text = "\
+Update `src/lib.rs:10` and `README.md`.
+Create a new module in `src/auth.rs`.
+Then call it from `src/main.rs`, and check `helpers.rs`.
+
+```rust
+// `src/in_fence.rs` is code, not a mention
+```
+";
+        let checks = check(&[repo.path()], mentioned_in_markdown(text, &[])).await;
+        assert_eq!(
+            statuses(&checks),
+            [("src/lib.rs", "existing"), ("README.md", "existing"), ("src/auth.rs", "new"), ("src/main.rs", "missing")]
+        );
+    }
+
+    #[tokio::test]
+    async fn manifest_operations_override_wording() {
+        let repo = TempRepo::new().unwrap();
+        let manifest = vec![ManifestEntry {
+            path: "./src/api.rs".to_string(),
+            operation: ChangeType::Modify,
+            summary: String::new(),
+            language: None,
+        }];
+        let checks = check(&[repo.path()], mentioned_in_markdown("Create the new `src/api.rs`.", &manifest)).await;
+        assert_eq!(statuses(&checks), [("src/api.rs", "missing")]);
+    }
+
+    #[tokio::test]
+    async fn a_path_exists_if_any_root_has_it() {
+        let first = TempRepo::new().unwrap();
+        let second = TempRepo::with_files(&[("lib/util.py", "")]).unwrap();
+        let checks = check(&[first.path(), second.path()], mentioned_in_markdown("See `lib/util.py`.", &[])).await;
+        assert_eq!(statuses(&checks), [("lib/util.py", "existing")]);
+        assert!(render(&checks).unwrap().contains("1 of 1 exist"));
+        assert!(render(&[]).is_none());
+    }
+}
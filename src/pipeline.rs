@@ -0,0 +1,232 @@
+// The analysis pipelines, keyed by the name of the tool that runs them: the
+// prompts of each pass and the files sent ahead of the report. `agent::Agent`
+// runs any of them by name; the server's tools and replays look them up here.
+use crate::files;
+use crate::llm::{self, PipelinePrompts};
+
+pub struct Pipeline {
+    pub tool: &'static str,
+    pub prompts: &'static PipelinePrompts,
+    pub pinned: Option<&'static PinnedFiles>,
+}
+
+// Files sent ahead of the report, regardless of context mode, up to a
+// quarter of the context limit.
+pub struct PinnedFiles {
+    pub(crate) title: &'static str,
+    pub(crate) extensions: &'static [&'static str],
+    // Case-insensitive substrings of the relative path.
+    pub(crate) path_keywords: &'static [&'static str],
+    // Case-insensitive substrings of the file contents.
+    pub(crate) content_keywords: &'static [&'static str],
+}
+
+impl PinnedFiles {
+    pub(crate) fn matches(&self, file: &files::SourceFile) -> bool {
+        let lowered = file.path.to_string_lossy().to_lowercase();
+        if files::has_extension(&file.path, self.extensions) || self.path_keywords.iter().any(|k| lowered.contains(k)) {
+            return true;
+        }
+        if self.content_keywords.is_empty() {
+            return false;
+        }
+        let content = file.content.to_lowercase();
+        self.content_keywords.iter().any(|k| content.contains(k))
+    }
+}
+
+pub(crate) const GRAPHQL_SCHEMA_FILES: PinnedFiles = PinnedFiles {
+    title: "GraphQL Schema Files",
+    extensions: &["graphql", "graphqls", "gql"],
+    path_keywords: &[],
+    content_keywords: &[],
+};
+
+pub(crate) const API_SPEC_FILES: PinnedFiles = PinnedFiles {
+    title: "API Specification Files",
+    extensions: &["proto", "graphql", "graphqls", "gql"],
+    path_keywords: &["openapi", "swagger", "asyncapi"],
+    content_keywords: &[],
+};
+
+pub(crate) const MESSAGING_FILES: PinnedFiles = PinnedFiles {
+    title: "Dependency Manifests and Messaging Files",
+    extensions: &["avsc", "proto"],
+    path_keywords: &[
+        "cargo.toml", "package.json", "requirements", "pyproject.toml", "go.mod", "pom.xml", "build.gradle", "gemfile",
+        "kafka", "rabbit", "amqp", "nats", "sqs", "sns", "pubsub", "queue", "event", "message", "broker", "outbox",
+        "consumer", "producer", "publisher", "subscriber",
+    ],
+    content_keywords: &[],
+};
+
+pub(crate) const TELEMETRY_FILES: PinnedFiles = PinnedFiles {
+    title: "Dependency Manifests and Telemetry Files",
+    extensions: &[],
+    path_keywords: &[
+        "cargo.toml", "package.json", "requirements", "pyproject.toml", "go.mod", "pom.xml", "build.gradle", "gemfile",
+        "logging", "logger", "log4j", "logback", "metric", "telemetry", "tracing", "tracer", "otel", "instrument",
+        "monitor", "prometheus", "statsd",
+    ],
+    content_keywords: &[
+        "log4j", "slf4j", "logback", "java.util.logging", "winston", "pino", "bunyan", "structlog", "loguru",
+        "go.uber.org/zap", "logrus", "log4net", "serilog", "nlog", "opentelemetry", "opencensus", "prometheus",
+        "statsd", "micrometer", "dropwizard", "datadog", "newrelic", "jaeger", "zipkin",
+    ],
+};
+
+pub(crate) const DATA_MODEL_FILES: PinnedFiles = PinnedFiles {
+    title: "Data Model and Schema Files",
+    extensions: &["sql", "prisma", "proto", "graphql", "graphqls", "gql", "avsc"],
+    path_keywords: &["model", "schema", "entit", "migration", "openapi", "swagger", "fixture", "factor", "seed"],
+    content_keywords: &[],
+};
+
+pub(crate) const MIGRATION_TEST_FILES: PinnedFiles = PinnedFiles {
+    title: "Migration, Schema, and Test Setup Files",
+    extensions: &["sql", "prisma"],
+    path_keywords: &[
+        "migration", "migrate", "alembic", "flyway", "liquibase", "knexfile", "schema", "diesel.toml",
+        "cargo.toml", "package.json", "requirements", "pyproject.toml", "go.mod", "pom.xml", "build.gradle", "gemfile",
+        "conftest", "pytest.ini", "jest.config", "vitest.config", "spec_helper", "rails_helper", "test_helper",
+        "testutil", "test_utils", "tests/common", "fixture", "factor",
+    ],
+    content_keywords: &[],
+};
+
+pub(crate) const DESIGN_DOC_FILES: PinnedFiles = PinnedFiles {
+    title: "Existing RFCs and Design Documents",
+    extensions: &[],
+    path_keywords: &["rfcs/", "docs/rfc", "rfc-template", "rfc_template", "adr/", "adrs/", "design-doc", "design_doc", "docs/design/", "decisions/"],
+    content_keywords: &[],
+};
+
+pub(crate) const DEPLOYMENT_FILES: PinnedFiles = PinnedFiles {
+    title: "Deployment Files",
+    extensions: &["tf", "hcl"],
+    path_keywords: &[
+        "migration", "dockerfile", "docker-compose", "compose.y", "procfile", "k8s", "kubernetes", "helm",
+        "deploy", "health", ".github/workflows", "fly.toml", "app.yaml", "serverless.y",
+    ],
+    content_keywords: &[],
+};
+
+pub(crate) const PERSISTENCE_AND_AUTH_FILES: PinnedFiles = PinnedFiles {
+    title: "Persistence and Auth Files",
+    extensions: &["sql", "prisma"],
+    path_keywords: &[
+        "migration", "schema", "model", "entit", "repositor", "database", "/db/", "dao", "orm", "auth", "session",
+        "permission", "polic", "rbac", "acl", "middleware", "tenant", "account", "organization",
+    ],
+    content_keywords: &[],
+};
+
+pub(crate) const AUTH_FILES: PinnedFiles = PinnedFiles {
+    title: "Authentication and Authorization Files",
+    extensions: &[],
+    path_keywords: &[
+        "auth", "login", "logout", "signin", "sign_in", "signup", "session", "oauth", "oidc", "saml", "sso", "jwt",
+        "token", "password", "credential", "identity", "permission", "polic", "rbac", "acl", "role", "guard",
+        "middleware", "user", "account",
+    ],
+    content_keywords: &[
+        "jsonwebtoken", "passport", "bcrypt", "argon2", "oauth", "openid", "saml", "next-auth", "authlib", "flask_login",
+        "django.contrib.auth", "devise", "springframework.security", "keycloak", "auth0", "cognito", "www-authenticate",
+    ],
+};
+
+pub(crate) const ENTRY_POINT_FILES: PinnedFiles = PinnedFiles {
+    title: "Dependency Manifests and Entry Point Files",
+    extensions: &[],
+    path_keywords: &[
+        "cargo.toml", "package.json", "requirements", "pyproject.toml", "go.mod", "pom.xml", "build.gradle", "gemfile",
+        "route", "router", "handler", "controller", "endpoint", "middleware", "server", "gateway", "urls.py", "views",
+        "nginx", "ratelimit", "rate_limit", "rate-limit", "throttl",
+    ],
+    content_keywords: &[],
+};
+
+pub(crate) const LLM_INTEGRATION_FILES: PinnedFiles = PinnedFiles {
+    title: "LLM Prompt and Integration Files",
+    extensions: &["prompt", "jinja", "j2", "mustache"],
+    path_keywords: &["prompt", "llm", "openai", "anthropic", "gemini", "langchain"],
+    content_keywords: &[
+        "openai", "anthropic", "generativeai", "google.genai", "langchain", "llama_index", "llamaindex", "ollama",
+        "cohere", "mistralai", "bedrock-runtime", "/chat/completions", "chat.completions", "messages.create",
+        "generatecontent", "system_prompt", "system prompt", "prompttemplate",
+    ],
+};
+
+pub(crate) const OBSERVABILITY_FILES: PinnedFiles = PinnedFiles {
+    title: "Observability, Resilience, and Configuration Files",
+    extensions: &[],
+    path_keywords: &[
+        "log", "metric", "monitor", "alert", "telemetry", "tracing", "otel", "prometheus", "grafana", "sentry",
+        "health", "retry", "timeout", "circuit", "config", "settings", ".env", "feature_flag", "runbook",
+    ],
+    content_keywords: &[
+        "opentelemetry", "prometheus", "statsd", "datadog", "newrelic", "sentry", "pagerduty", "circuitbreaker",
+        "circuit_breaker", "backoff",
+    ],
+};
+
+pub(crate) const RESILIENCE_FILES: PinnedFiles = PinnedFiles {
+    title: "External Call Sites, Resilience, and Deployment Files",
+    extensions: &["tf", "hcl"],
+    path_keywords: &[
+        "client", "gateway", "adapter", "integration", "database", "/db/", "redis", "cache", "queue", "retry",
+        "timeout", "circuit", "fallback", "resilien", "health", "config", "settings", "dockerfile", "docker-compose",
+        "compose.y", "k8s", "kubernetes", "helm", "deploy",
+    ],
+    content_keywords: &[
+        "reqwest", "hyper::client", "axios", "fetch(", "requests.", "httpx", "aiohttp", "http.client", "net/http",
+        "okhttp", "resttemplate", "webclient", "httpclient", "grpc", "resilience4j", "hystrix", "polly", "tenacity",
+        "backoff", "circuitbreaker", "circuit_breaker", "bulkhead", "connect_timeout", "read_timeout",
+    ],
+};
+
+pub(crate) const CHANGE_CONVENTION_FILES: PinnedFiles = PinnedFiles {
+    title: "Changelog and Commit Convention Files",
+    extensions: &[],
+    path_keywords: &[
+        "changelog", "changes.md", "history.md", "news.md", "release", "contributing", "commitlint", ".czrc",
+        ".cz.toml", "cliff.toml", ".versionrc", ".changeset/",
+    ],
+    content_keywords: &[],
+};
+
+// Every pipeline that can be run, replayed, and templated by tool name.
+pub const PIPELINES: &[Pipeline] = &[
+    Pipeline { tool: "plan_feature", prompts: &llm::FEATURE_PLAN, pinned: None },
+    Pipeline { tool: "plan_feature_outline", prompts: &llm::FEATURE_OUTLINE, pinned: None },
+    Pipeline { tool: "plan_feature_details", prompts: &llm::FEATURE_PLAN, pinned: None },
+    Pipeline { tool: "plan_bug_fix", prompts: &llm::BUG_FIX_PLAN, pinned: None },
+    Pipeline { tool: "plan_bug_fix_from_issue", prompts: &llm::BUG_FIX_PLAN, pinned: None },
+    Pipeline { tool: "explain_code", prompts: &llm::EXPLANATION, pinned: None },
+    Pipeline { tool: "explain_architecture", prompts: &llm::ARCHITECTURE_DIAGRAMS, pinned: None },
+    Pipeline { tool: "plan_graphql_schema_change", prompts: &llm::GRAPHQL_SCHEMA_PLAN, pinned: Some(&GRAPHQL_SCHEMA_FILES) },
+    Pipeline { tool: "plan_zero_downtime_deploy", prompts: &llm::ZERO_DOWNTIME_DEPLOY_PLAN, pinned: Some(&DEPLOYMENT_FILES) },
+    Pipeline { tool: "plan_event_driven_migration", prompts: &llm::EVENT_DRIVEN_MIGRATION_PLAN, pinned: Some(&MESSAGING_FILES) },
+    Pipeline { tool: "plan_telemetry_migration", prompts: &llm::TELEMETRY_MIGRATION_PLAN, pinned: Some(&TELEMETRY_FILES) },
+    Pipeline { tool: "plan_multitenancy_support", prompts: &llm::MULTITENANCY_PLAN, pinned: Some(&PERSISTENCE_AND_AUTH_FILES) },
+    Pipeline { tool: "plan_rate_limiting", prompts: &llm::RATE_LIMITING_PLAN, pinned: Some(&ENTRY_POINT_FILES) },
+    Pipeline { tool: "plan_auth_overhaul", prompts: &llm::AUTH_OVERHAUL_PLAN, pinned: Some(&AUTH_FILES) },
+    Pipeline { tool: "plan_sdk_client_generation", prompts: &llm::SDK_CLIENT_PLAN, pinned: Some(&API_SPEC_FILES) },
+    Pipeline { tool: "generate_mock_data", prompts: &llm::MOCK_DATA_PLAN, pinned: Some(&DATA_MODEL_FILES) },
+    Pipeline { tool: "generate_migration_tests", prompts: &llm::MIGRATION_TESTS_PLAN, pinned: Some(&MIGRATION_TEST_FILES) },
+    Pipeline { tool: "reverse_engineer_requirements", prompts: &llm::REQUIREMENTS_SPEC, pinned: None },
+    Pipeline { tool: "draft_rfc", prompts: &llm::RFC_DRAFT, pinned: Some(&DESIGN_DOC_FILES) },
+    Pipeline { tool: "cluster_related_issues", prompts: &llm::ISSUE_CLUSTERING, pinned: None },
+    Pipeline { tool: "audit_dependencies", prompts: &llm::DEPENDENCY_AUDIT, pinned: None },
+    Pipeline { tool: "review_llm_prompts_in_repo", prompts: &llm::LLM_PROMPT_REVIEW, pinned: Some(&LLM_INTEGRATION_FILES) },
+    Pipeline { tool: "post_incident_review", prompts: &llm::POST_INCIDENT_REVIEW, pinned: Some(&OBSERVABILITY_FILES) },
+    Pipeline { tool: "identify_single_points_of_failure", prompts: &llm::SPOF_ANALYSIS, pinned: Some(&RESILIENCE_FILES) },
+    Pipeline { tool: "summarize_changes", prompts: &llm::CHANGE_SUMMARY, pinned: Some(&CHANGE_CONVENTION_FILES) },
+    Pipeline { tool: "document_code", prompts: &llm::DOCUMENTATION, pinned: None },
+    Pipeline { tool: "identify_tech_debt_hotspots", prompts: &llm::TECH_DEBT_HOTSPOTS, pinned: None },
+    Pipeline { tool: "generate_patch", prompts: &llm::PATCH_GENERATION, pinned: None },
+];
+
+pub fn lookup(tool: &str) -> Option<&'static Pipeline> {
+    PIPELINES.iter().find(|p| p.tool == tool)
+}
@@ -0,0 +1,299 @@
+use crate::feasibility::FeasibilityReport;
+use crate::paths::PathCheck;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    #[default]
+    Markdown,
+    Json,
+    Diff,
+}
+
+// How much detail the final pass writes, for the same pipeline and context:
+// a summary for reviewing the approach, or every file and step in full for
+// implementing it.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Verbosity {
+    Summary,
+    #[default]
+    Standard,
+    Exhaustive,
+}
+
+impl Verbosity {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Summary => "summary",
+            Self::Standard => "standard",
+            Self::Exhaustive => "exhaustive",
+        }
+    }
+
+    // Added to the final pass's system prompt; the standard prompts are left
+    // as they are.
+    pub fn instructions(self) -> Option<&'static str> {
+        match self {
+            Self::Summary => Some(SUMMARY_INSTRUCTIONS),
+            Self::Standard => None,
+            Self::Exhaustive => Some(EXHAUSTIVE_INSTRUCTIONS),
+        }
+    }
+
+    // Output tokens reserved for the final pass when estimating a call.
+    pub fn reserve_tokens(self) -> u64 {
+        match self {
+            Self::Summary => 2_000,
+            Self::Standard => 8_000,
+            Self::Exhaustive => 32_000,
+        }
+    }
+
+    // The `max_tokens` of the final pass; None leaves the provider's default.
+    pub fn max_output_tokens(self) -> Option<u64> {
+        match self {
+            Self::Summary => Some(4_000),
+            Self::Standard => None,
+            Self::Exhaustive => Some(65_536),
+        }
+    }
+}
+
+const SUMMARY_INSTRUCTIONS: &str = r#"VERBOSITY: SUMMARY. The reader is reviewing the approach, not implementing it. Keep every required section, but reduce each to its essentials as short bullet points: the decisions and why, the files and components affected, the order of work, and the risks. Leave out code except a signature where a decision depends on it. Aim for under 800 words."#;
+
+const EXHAUSTIVE_INSTRUCTIONS: &str = r#"VERBOSITY: EXHAUSTIVE. The reader will implement directly from your response. Cover every affected file and every step in full: complete code for new and changed functions rather than excerpts, every call site to update, error handling and edge cases, tests with their code, and the commands to run. Never abbreviate with "similar to above", "...", or "rest unchanged"; length is not a concern."#;
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
+pub struct StructuredPlan {
+    /// One-paragraph summary of the approach.
+    pub summary: String,
+    /// Every file that must be created, modified, or deleted.
+    pub file_changes: Vec<FileChange>,
+    /// Ordered implementation steps.
+    pub steps: Vec<String>,
+    /// Risks, edge cases, and things that could go wrong.
+    pub risks: Vec<String>,
+    /// Assumptions made where the request was ambiguous or information was missing.
+    #[serde(default)]
+    pub assumptions: Vec<String>,
+    /// Questions for the requester whose answers could change the plan, most important first.
+    #[serde(default)]
+    pub open_questions: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
+pub struct FileChange {
+    /// Path relative to the codebase root.
+    pub path: String,
+    pub change_type: ChangeType,
+    /// What changes in this file and why.
+    pub description: String,
+    /// Actual code for the key changes (not pseudocode).
+    #[serde(default)]
+    pub code_snippets: Vec<String>,
+    /// Complete file content for created files.
+    #[serde(default)]
+    pub content: Option<String>,
+    /// Exact search/replace edits for modified files, applied in order.
+    #[serde(default)]
+    pub edits: Vec<TextEdit>,
+    /// Language of the file as named in the Language Map, when the codebase mixes languages.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
+pub struct TextEdit {
+    /// Text copied verbatim from the current file; must occur exactly once.
+    pub find: String,
+    /// Replacement text.
+    pub replace: String,
+}
+
+#[derive(Serialize)]
+pub struct StoredPlan {
+    pub plan_id: String,
+    pub session_id: String,
+    #[serde(flatten)]
+    pub plan: StructuredPlan,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub feasibility: Option<FeasibilityReport>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub paths: Option<Vec<PathCheck>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub published: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum ChangeType {
+    Create,
+    Modify,
+    Delete,
+}
+
+pub fn json_instructions() -> String {
+    let schema = schemars::schema_for!(StructuredPlan);
+    format!(
+        "IMPORTANT OUTPUT FORMAT: Respond with ONLY a single JSON object (no markdown, no commentary) that validates against this JSON Schema. Put illustrative code in `code_snippets`; for created files also give the complete file in `content`, and for modified files give exact `edits` whose `find` text is copied verbatim from the current file. Order `steps` in the sequence they should be implemented.\n\n{}",
+        serde_json::to_string_pretty(&schema).unwrap_or_default()
+    )
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
+pub struct ManifestEntry {
+    pub path: String,
+    pub operation: ChangeType,
+    pub summary: String,
+    // Set when the codebase mixes languages (see `languages::LABEL_INSTRUCTIONS`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+}
+
+pub const ASSUMPTIONS_INSTRUCTIONS: &str = r#"After the plan itself, include a section titled exactly `## Assumptions` listing, as bullet points, every assumption you made where the request was ambiguous or information was missing, then a section titled exactly `## Open Questions` listing the questions for the requester whose answers could change the plan, numbered `Q1.`, `Q2.`, and so on, most important first. Write `None` under a section if it has nothing to list."#;
+
+#[derive(Serialize, Default)]
+pub struct Clarifications {
+    pub assumptions: Vec<String>,
+    pub open_questions: Vec<String>,
+}
+
+pub fn parse_clarifications(text: &str) -> Clarifications {
+    Clarifications {
+        assumptions: section_items(text, "## Assumptions"),
+        open_questions: section_items(text, "## Open Questions"),
+    }
+}
+
+// List items of the last section with this heading, up to the next heading.
+// Bullet and number markers (`-`, `*`, `1.`, `Q1.`) are stripped, and
+// unmarked lines continue the previous item.
+fn section_items(text: &str, heading: &str) -> Vec<String> {
+    let Some(start) = text.rfind(heading) else {
+        return Vec::new();
+    };
+    let mut items: Vec<String> = Vec::new();
+    for line in text[start..].lines().skip(1) {
+        let line = line.trim();
+        if line.starts_with('#') || line.starts_with("```") {
+            break;
+        }
+        if line.is_empty() {
+            continue;
+        }
+        match strip_list_marker(line) {
+            Some(item) => items.push(item.to_string()),
+            None => match items.last_mut() {
+                Some(last) => {
+                    last.push(' ');
+                    last.push_str(line);
+                }
+                None => items.push(line.to_string()),
+            },
+        }
+    }
+    items.retain(|item| !item.trim_end_matches('.').eq_ignore_ascii_case("none"));
+    items
+}
+
+fn strip_list_marker(line: &str) -> Option<&str> {
+    if let Some(rest) = line.strip_prefix("- ").or_else(|| line.strip_prefix("* ")) {
+        return Some(rest.trim());
+    }
+    let unprefixed = line.strip_prefix('Q').unwrap_or(line);
+    let digits = unprefixed.len() - unprefixed.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+    if digits == 0 {
+        return None;
+    }
+    let rest = &unprefixed[digits..];
+    rest.strip_prefix('.').or_else(|| rest.strip_prefix(')')).or_else(|| rest.strip_prefix(':')).map(str::trim)
+}
+
+pub const MANIFEST_INSTRUCTIONS: &str = r#"After the plan, end your response with a section titled exactly `## Change Manifest` containing a single ```json code block: an array listing EVERY file the plan creates, modifies, or deletes, in implementation order, for example:
+
+```json
+[
+  {"path": "src/auth/middleware.rs", "operation": "create", "summary": "JWT validation middleware"},
+  {"path": "src/main.rs", "operation": "modify", "summary": "Register the auth middleware"}
+]
+```
+
+`operation` must be one of "create", "modify", or "delete". Paths are relative to the codebase root. If the context includes a Language Map, also give each entry a "language" as named in the map. Nothing may follow the manifest."#;
+
+pub fn parse_manifest(text: &str) -> Option<Vec<ManifestEntry>> {
+    let section = &text[text.rfind("## Change Manifest")?..];
+    let array = &section[section.find('[')?..];
+    // Sections appended after the manifest (verification corrections) start
+    // past its closing fence, or at their heading if the fence is missing.
+    let end = ["```", "\n## "].iter().filter_map(|marker| array.find(marker)).min().unwrap_or(array.len());
+    let block = &array[..end];
+    let end = block.rfind(']')?;
+    serde_json::from_str(&block[..=end]).ok()
+}
+
+pub const DIFF_INSTRUCTIONS: &str = r#"IMPORTANT OUTPUT FORMAT: Respond with ONLY a unified diff (as produced by `git diff`) inside a single ```diff code block, followed by at most a short list of notes.
+
+Rules for the diff:
+- Use `--- a/<path>` and `+++ b/<path>` headers with paths relative to the codebase root; use `/dev/null` for created or deleted files
+- Copy context and removed lines EXACTLY from the codebase report, including indentation
+- Include 3 lines of context around each change and correct `@@ -start,count +start,count @@` line numbers
+- The diff will be checked against the actual files; hunks that do not match the current code will be rejected"#;
+
+pub fn parse_structured_plan(text: &str) -> Result<StructuredPlan, serde_json::Error> {
+    // Models frequently wrap JSON in a fenced code block despite instructions.
+    let json = match (text.find('{'), text.rfind('}')) {
+        (Some(start), Some(end)) if start < end => &text[start..=end],
+        _ => text,
+    };
+    serde_json::from_str(json)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PLAN: &str = "# Plan\n\nAdd the middleware.\n\n## Change Manifest\n\n";
+    const MANIFEST: &str = r#"[{"path": "src/auth.rs", "operation": "create", "summary": "Middleware"}]"#;
+    const CORRECTIONS: &str = "\n\n## Corrections\n\n- Step 2 calls `[Router::layer]`, not `add_layer`:\n\n```rust\nlet v = [1, 2];\n```\n";
+
+    fn paths(text: &str) -> Vec<String> {
+        parse_manifest(text).unwrap().into_iter().map(|entry| entry.path).collect()
+    }
+
+    #[test]
+    fn parses_a_fenced_manifest() {
+        assert_eq!(paths(&format!("{PLAN}```json\n{MANIFEST}\n```\n")), ["src/auth.rs"]);
+    }
+
+    #[test]
+    fn parses_a_manifest_followed_by_corrections() {
+        assert_eq!(paths(&format!("{PLAN}```json\n{MANIFEST}\n```{CORRECTIONS}")), ["src/auth.rs"]);
+        // Without its fence, the manifest ends at the next heading.
+        assert_eq!(paths(&format!("{PLAN}{MANIFEST}{CORRECTIONS}")), ["src/auth.rs"]);
+    }
+
+    #[test]
+    fn uses_the_last_manifest() {
+        let earlier = "## Change Manifest\n\n```json\n[{\"path\": \"old.rs\", \"operation\": \"delete\", \"summary\": \"\"}]\n```\n\n";
+        assert_eq!(paths(&format!("{earlier}{PLAN}```json\n{MANIFEST}\n```\n")), ["src/auth.rs"]);
+    }
+
+    #[test]
+    fn rejects_missing_or_invalid_manifests() {
+        assert!(parse_manifest("# Plan\n\nNo manifest here.").is_none());
+        assert!(parse_manifest(&format!("{PLAN}```json\n[{{\"path\": \"a.rs\", \"operation\": \"rename\"}}]\n```\n")).is_none());
+        assert!(parse_manifest(&format!("{PLAN}```json\n[\n```\n")).is_none());
+    }
+
+    #[test]
+    fn parses_clarifications() {
+        let text = "# Plan\n\n## Assumptions\n\n- Postgres is the\n  only database\n* Users have emails\n\n## Open Questions\n\nQ1. Which SSO provider?\n2) Is MFA required?\n\n## Change Manifest\n";
+        let clarifications = parse_clarifications(text);
+        assert_eq!(clarifications.assumptions, ["Postgres is the only database", "Users have emails"]);
+        assert_eq!(clarifications.open_questions, ["Which SSO provider?", "Is MFA required?"]);
+        assert!(parse_clarifications("## Assumptions\n\nNone.\n").assumptions.is_empty());
+    }
+}
@@ -0,0 +1,177 @@
+use crate::plan::OutputFormat;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const MAX_ENTRIES: usize = 500;
+const MAX_QUALITY_COMMENTS: usize = 3;
+
+// A plan returned by a planning tool, kept so it can be retrieved later (by
+// a teammate, or after the session expires) without regenerating it.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct SavedPlan {
+    pub id: String,
+    pub tool: String,
+    pub directory: String,
+    pub prompt: String,
+    pub model: String,
+    pub format: OutputFormat,
+    pub session_id: String,
+    pub created_at: u64,
+    // The prompt templates' fingerprint, or None for the built-in prompts.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub template: Option<String>,
+    // The plan as returned: markdown, the structured plan's JSON, or a diff.
+    pub text: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ratings: Vec<Rating>,
+}
+
+// A user's score for a saved plan, from rate_plan.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Rating {
+    // 1 (unusable) to 5 (implemented as written).
+    pub score: u8,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+    pub rated_at: u64,
+}
+
+// Ratings of the saved plans from one tool, model, and prompt template version.
+pub struct Quality {
+    pub tool: String,
+    pub model: String,
+    pub template: Option<String>,
+    pub plans: usize,
+    pub rated_plans: usize,
+    pub ratings: usize,
+    pub mean_score: f64,
+    // Up to three comments, from the newest plans first.
+    pub comments: Vec<String>,
+}
+
+pub struct PlanStore {
+    path: PathBuf,
+    entries: Mutex<Vec<SavedPlan>>,
+}
+
+impl PlanStore {
+    pub fn load(path: PathBuf) -> Self {
+        let entries = std::fs::read(&path)
+            .ok()
+            .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+            .unwrap_or_default();
+
+        Self {
+            path,
+            entries: Mutex::new(entries),
+        }
+    }
+
+    // Newest first, optionally only those for one tool or directory.
+    pub fn list(&self, tool: Option<&str>, directory: Option<&str>) -> Vec<SavedPlan> {
+        self.entries
+            .lock()
+            .unwrap()
+            .iter()
+            .rev()
+            .filter(|p| tool.is_none_or(|t| p.tool == t))
+            .filter(|p| directory.is_none_or(|d| p.directory == d))
+            .cloned()
+            .collect()
+    }
+
+    pub fn get(&self, id: &str) -> Option<SavedPlan> {
+        self.entries.lock().unwrap().iter().find(|p| p.id == id).cloned()
+    }
+
+    // Stores the plan, dropping the oldest beyond MAX_ENTRIES.
+    pub async fn save(&self, mut plan: SavedPlan) -> Result<()> {
+        plan.created_at = now();
+        let snapshot = {
+            let mut entries = self.entries.lock().unwrap();
+            entries.push(plan);
+            if entries.len() > MAX_ENTRIES {
+                let excess = entries.len() - MAX_ENTRIES;
+                entries.drain(..excess);
+            }
+            serde_json::to_vec(&*entries).context("Failed to serialize plan store")?
+        };
+        self.persist(snapshot).await
+    }
+
+    // Adds a rating to the plan. Returns false if there is no plan with `id`.
+    pub async fn rate(&self, id: &str, score: u8, comment: Option<String>) -> Result<bool> {
+        let snapshot = {
+            let mut entries = self.entries.lock().unwrap();
+            let Some(plan) = entries.iter_mut().find(|p| p.id == id) else {
+                return Ok(false);
+            };
+            plan.ratings.push(Rating { score, comment, rated_at: now() });
+            serde_json::to_vec(&*entries).context("Failed to serialize plan store")?
+        };
+        self.persist(snapshot).await?;
+        Ok(true)
+    }
+
+    // Ratings grouped by tool, model, and template fingerprint, for the groups
+    // with at least one rating; lowest mean score first.
+    pub fn quality(&self) -> Vec<Quality> {
+        let entries = self.entries.lock().unwrap();
+        let mut groups: Vec<Quality> = Vec::new();
+        for plan in entries.iter().rev() {
+            let index = match groups.iter().position(|g| g.tool == plan.tool && g.model == plan.model && g.template == plan.template) {
+                Some(index) => index,
+                None => {
+                    groups.push(Quality {
+                        tool: plan.tool.clone(),
+                        model: plan.model.clone(),
+                        template: plan.template.clone(),
+                        plans: 0,
+                        rated_plans: 0,
+                        ratings: 0,
+                        mean_score: 0.0,
+                        comments: Vec::new(),
+                    });
+                    groups.len() - 1
+                }
+            };
+            let group = &mut groups[index];
+            group.plans += 1;
+            if plan.ratings.is_empty() {
+                continue;
+            }
+            group.rated_plans += 1;
+            for rating in plan.ratings.iter().rev() {
+                group.ratings += 1;
+                group.mean_score += rating.score as f64;
+                if let Some(comment) = rating.comment.as_ref().filter(|_| group.comments.len() < MAX_QUALITY_COMMENTS) {
+                    group.comments.push(comment.clone());
+                }
+            }
+        }
+        groups.retain(|g| g.ratings > 0);
+        for group in &mut groups {
+            group.mean_score /= group.ratings as f64;
+        }
+        groups.sort_by(|a, b| a.mean_score.total_cmp(&b.mean_score));
+        groups
+    }
+
+    async fn persist(&self, snapshot: Vec<u8>) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            tokio::fs::create_dir_all(parent)
+                .await
+                .context("Failed to create plan store directory")?;
+        }
+        tokio::fs::write(&self.path, snapshot)
+            .await
+            .context("Failed to write plan store")
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
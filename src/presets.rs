@@ -0,0 +1,75 @@
+// Startup presets: bundles of environment defaults for common deployment
+// modes, chosen with `--preset`. A preset only fills in variables that are
+// not already set (in the environment or `.env`), so any of its settings can
+// be overridden individually.
+
+#[derive(clap::ValueEnum, Clone, Copy, PartialEq, Debug)]
+pub enum Preset {
+    // One developer on their own machine, over MCP stdio.
+    LocalDev,
+    // A shared REST server in front of a team's key pool.
+    TeamServer,
+    // Unattended runs of the command-line mode in a pipeline.
+    Ci,
+}
+
+impl Preset {
+    pub fn name(self) -> &'static str {
+        match self {
+            Preset::LocalDev => "local-dev",
+            Preset::TeamServer => "team-server",
+            Preset::Ci => "ci",
+        }
+    }
+
+    // Whether the preset serves the REST API rather than MCP over stdio;
+    // `--transport` still takes precedence.
+    pub fn serves_rest(self) -> bool {
+        self == Preset::TeamServer
+    }
+
+    // The environment variables the preset sets, with their values.
+    pub fn defaults(self) -> &'static [(&'static str, &'static str)] {
+        match self {
+            Preset::LocalDev => &[
+                ("MAX_IN_FLIGHT_PER_KEY", "2"),
+                // Repeated questions while exploring a codebase are common.
+                ("SEMANTIC_CACHE_THRESHOLD", "0.97"),
+                ("ALLOW_WRITES", "true"),
+            ],
+            Preset::TeamServer => &[
+                ("MAX_IN_FLIGHT_PER_KEY", "4"),
+                ("SEMANTIC_CACHE_THRESHOLD", "0.95"),
+                ("SESSION_TOKEN_QUOTA", "2000000"),
+                ("SESSION_QUOTA_WINDOW_SECS", "3600"),
+                ("MAX_TOTAL_TOKENS_PER_CALL", "500000"),
+                // Callers' checkouts can change under a long call.
+                ("MIRROR_DIRECTORIES", "true"),
+                ("ALLOW_WRITES", "false"),
+            ],
+            Preset::Ci => &[
+                ("MAX_IN_FLIGHT_PER_KEY", "4"),
+                ("MAX_TOTAL_TOKENS_PER_CALL", "300000"),
+                // Each run is a fresh process: nothing to refresh, and
+                // retrieval should not depend on earlier runs.
+                ("TREE_HASH_REFRESH_SECS", "0"),
+                ("RAG_ADAPTIVE_TOP_K", "false"),
+                ("ALLOW_WRITES", "false"),
+            ],
+        }
+    }
+
+    // Sets each of the preset's variables that is not already set, and
+    // returns the names of those that were, which keep their values.
+    pub fn apply(self) -> Vec<&'static str> {
+        let mut kept = Vec::new();
+        for (name, value) in self.defaults() {
+            if std::env::var_os(name).is_some() {
+                kept.push(*name);
+            } else {
+                std::env::set_var(name, value);
+            }
+        }
+        kept
+    }
+}
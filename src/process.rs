@@ -0,0 +1,237 @@
+use anyhow::{Context, Result};
+use std::collections::BTreeMap;
+use std::process::{Output, Stdio};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::process::Command;
+
+const MEMORY_SAMPLE_INTERVAL: Duration = Duration::from_millis(250);
+
+#[derive(Debug, thiserror::Error)]
+pub enum ProcessError {
+    #[error("{program} timed out after {}s and was killed", .timeout.as_secs())]
+    TimedOut { program: String, timeout: Duration },
+    #[error(transparent)]
+    Spawn(#[from] anyhow::Error),
+}
+
+#[derive(Clone, Copy, Default)]
+pub struct ProgramStats {
+    pub runs: u64,
+    // Exited with a non-zero status or could not be waited on.
+    pub failures: u64,
+    pub timeouts: u64,
+    // Killed because the caller stopped waiting (client cancellation).
+    pub cancelled: u64,
+    pub total_duration: Duration,
+    pub max_duration: Duration,
+    // Peak resident set size in KiB; only sampled on Linux.
+    pub max_peak_rss_kb: u64,
+}
+
+enum Outcome {
+    Exited { success: bool },
+    TimedOut,
+    Cancelled,
+}
+
+// Lifecycle statistics of the external programs run since startup, by
+// program name.
+#[derive(Default)]
+pub struct ProcessMetrics {
+    programs: Mutex<BTreeMap<String, ProgramStats>>,
+}
+
+impl ProcessMetrics {
+    fn record(&self, program: &str, outcome: Outcome, duration: Duration, peak_rss_kb: u64) {
+        let mut programs = self.programs.lock().unwrap();
+        let stats = programs.entry(program.to_string()).or_default();
+        stats.runs += 1;
+        match outcome {
+            Outcome::Exited { success: true } => {}
+            Outcome::Exited { success: false } => stats.failures += 1,
+            Outcome::TimedOut => stats.timeouts += 1,
+            Outcome::Cancelled => stats.cancelled += 1,
+        }
+        stats.total_duration += duration;
+        stats.max_duration = stats.max_duration.max(duration);
+        stats.max_peak_rss_kb = stats.max_peak_rss_kb.max(peak_rss_kb);
+    }
+
+    pub fn stats(&self) -> Vec<(String, ProgramStats)> {
+        self.programs.lock().unwrap().iter().map(|(name, stats)| (name.clone(), *stats)).collect()
+    }
+
+    // Markdown table for get_usage_stats, or None before the first run.
+    pub fn report(&self) -> Option<String> {
+        let stats = self.stats();
+        if stats.is_empty() {
+            return None;
+        }
+        let mut out = String::from(
+            "## External Processes\n\n| Program | Runs | Failures | Timeouts | Cancelled | Mean duration | Max duration | Peak memory |\n|---|---|---|---|---|---|---|---|\n",
+        );
+        for (name, s) in stats {
+            let mean = s.total_duration / s.runs.max(1) as u32;
+            let memory = match s.max_peak_rss_kb {
+                0 => "-".to_string(),
+                kb => format!("{:.1} MiB", kb as f64 / 1024.0),
+            };
+            out.push_str(&format!(
+                "| `{name}` | {} | {} | {} | {} | {:.1}s | {:.1}s | {memory} |\n",
+                s.runs,
+                s.failures,
+                s.timeouts,
+                s.cancelled,
+                mean.as_secs_f64(),
+                s.max_duration.as_secs_f64()
+            ));
+        }
+        Some(out)
+    }
+}
+
+// Kills the child's whole process group unless disarmed, so helpers the
+// program spawned die with it when the run times out or the awaiting future
+// is dropped. `kill_on_drop` alone only reaches the direct child.
+struct GroupGuard<'a> {
+    program: &'a str,
+    pid: Option<u32>,
+    started: Instant,
+    peak_rss_kb: &'a AtomicU64,
+    metrics: &'a ProcessMetrics,
+    armed: bool,
+}
+
+impl GroupGuard<'_> {
+    fn finish(&mut self, outcome: Outcome) {
+        if matches!(outcome, Outcome::TimedOut | Outcome::Cancelled) {
+            if let Some(pid) = self.pid {
+                kill_group(pid);
+            }
+        }
+        self.armed = false;
+        let duration = self.started.elapsed();
+        let peak_rss_kb = self.peak_rss_kb.load(Ordering::Relaxed);
+        match outcome {
+            Outcome::Exited { success } => tracing::info!(
+                program = self.program,
+                pid = self.pid,
+                success,
+                duration_ms = duration.as_millis() as u64,
+                peak_rss_kb,
+                "Process exited"
+            ),
+            Outcome::TimedOut => tracing::warn!(
+                program = self.program,
+                pid = self.pid,
+                duration_ms = duration.as_millis() as u64,
+                peak_rss_kb,
+                "Process timed out; killed its process group"
+            ),
+            Outcome::Cancelled => tracing::warn!(
+                program = self.program,
+                pid = self.pid,
+                duration_ms = duration.as_millis() as u64,
+                peak_rss_kb,
+                "Process cancelled; killed its process group"
+            ),
+        }
+        self.metrics.record(self.program, outcome, duration, peak_rss_kb);
+    }
+}
+
+impl Drop for GroupGuard<'_> {
+    fn drop(&mut self) {
+        if self.armed {
+            self.finish(Outcome::Cancelled);
+        }
+    }
+}
+
+// Runs `cmd` to completion in its own process group with stdout and stderr
+// captured, sampling its peak memory while it runs. On timeout, or if the
+// returned future is dropped, the whole group is killed. Every run is traced
+// and recorded in `metrics` under `program`.
+pub async fn run(program: &str, mut cmd: Command, timeout: Duration, metrics: &ProcessMetrics) -> Result<Output, ProcessError> {
+    cmd.stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+    #[cfg(unix)]
+    cmd.process_group(0);
+
+    let started = Instant::now();
+    let child = cmd.spawn().with_context(|| format!("Failed to execute {program}"))?;
+    let pid = child.id();
+    tracing::info!(program, pid, timeout_secs = timeout.as_secs(), "Process spawned");
+
+    let peak_rss_kb = AtomicU64::new(0);
+    let wait = async {
+        let wait = child.wait_with_output();
+        tokio::pin!(wait);
+        let mut sample = tokio::time::interval(MEMORY_SAMPLE_INTERVAL);
+        loop {
+            tokio::select! {
+                output = &mut wait => break output,
+                _ = sample.tick() => {
+                    if let Some(kb) = pid.and_then(peak_rss_kb_of) {
+                        peak_rss_kb.fetch_max(kb, Ordering::Relaxed);
+                    }
+                }
+            }
+        }
+    };
+    tokio::pin!(wait);
+    // Declared after `wait` so it is dropped first: the group is killed
+    // while the child is still unreaped and its pid cannot be reused.
+    let mut guard = GroupGuard {
+        program,
+        pid,
+        started,
+        peak_rss_kb: &peak_rss_kb,
+        metrics,
+        armed: true,
+    };
+
+    tokio::select! {
+        output = &mut wait => {
+            let output = output.with_context(|| format!("Failed to wait for {program}"));
+            let success = output.as_ref().is_ok_and(|o| o.status.success());
+            guard.finish(Outcome::Exited { success });
+            Ok(output?)
+        }
+        _ = tokio::time::sleep(timeout) => {
+            guard.finish(Outcome::TimedOut);
+            Err(ProcessError::TimedOut { program: program.to_string(), timeout })
+        }
+    }
+}
+
+#[cfg(unix)]
+fn kill_group(pid: u32) {
+    // The child leads its own group (`process_group(0)`), so its pid is the
+    // group id.
+    unsafe {
+        libc::killpg(pid as libc::pid_t, libc::SIGKILL);
+    }
+}
+
+#[cfg(not(unix))]
+fn kill_group(_pid: u32) {
+    // No process groups; `kill_on_drop` still kills the direct child.
+}
+
+// High-water mark of the process's resident set (VmHWM), in KiB.
+#[cfg(target_os = "linux")]
+fn peak_rss_kb_of(pid: u32) -> Option<u64> {
+    let status = std::fs::read_to_string(format!("/proc/{pid}/status")).ok()?;
+    let line = status.lines().find(|l| l.starts_with("VmHWM:"))?;
+    line.split_whitespace().nth(1)?.parse().ok()
+}
+
+#[cfg(not(target_os = "linux"))]
+fn peak_rss_kb_of(_pid: u32) -> Option<u64> {
+    None
+}
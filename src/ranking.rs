@@ -0,0 +1,162 @@
+use crate::files::SourceFile;
+use crate::languages;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+// Characters codebase_viewer adds around each file (heading, fence, and
+// metadata), counted against the limit along with the file's content.
+const PER_FILE_OVERHEAD: usize = 200;
+
+// Files at this size or below cost nothing; each doubling above it costs a
+// point, so one huge file does not crowd out a dozen ordinary ones.
+const SIZE_FREE_BYTES: f64 = 8.0 * 1024.0;
+
+// Entry points and crate, package, or app roots, compared case-insensitively.
+const ENTRY_POINTS: &[&str] = &[
+    "lib.rs", "main.rs", "main.ts", "main.tsx", "main.js", "index.ts", "index.tsx", "index.js", "app.ts", "app.tsx",
+    "app.js", "server.ts", "server.js", "main.py", "__main__.py", "app.py", "manage.py", "main.go", "main.java",
+    "application.java", "program.cs", "main.c", "main.cc", "main.cpp", "main.swift", "main.dart", "main.kt",
+];
+
+// Directories whose files are fixtures, snapshots, vendored, or build output.
+const GENERATED_DIRS: &[&str] = &[
+    "fixtures", "fixture", "testdata", "test-data", "__snapshots__", "snapshots", "vendor", "third_party",
+    "node_modules", "dist", "build", "generated", "__generated__",
+];
+
+// File name endings of generated code, minified bundles, and lockfiles.
+const GENERATED_SUFFIXES: &[&str] = &[
+    ".min.js", ".min.css", ".map", ".pb.go", "_pb2.py", "_pb2_grpc.py", ".pb.ts", ".g.dart", ".freezed.dart",
+    ".generated.ts", ".generated.cs", ".designer.cs", ".snap", ".lock", "-lock.json", "-lock.yaml", "go.sum",
+    "package.resolved",
+];
+
+// Markers generators write near the top of a file.
+const GENERATED_MARKERS: &[&str] = &["@generated", "DO NOT EDIT", "Code generated by", "auto-generated", "autogenerated"];
+
+// Extensions of documentation and configuration, which rank between code
+// and data files.
+const DOC_AND_CONFIG: &[&str] = &["md", "rst", "toml", "yaml", "yml", "ini", "cfg", "gradle", "xml", "proto", "graphql", "sql"];
+
+// How much a file matters to a model reading the codebase, from its path
+// and content alone: entry points, manifests, and widely imported modules
+// rank high; tests, fixtures, generated code, and deep or large files low.
+// `fan_in` is the number of other files importing it.
+pub fn importance(file: &SourceFile, fan_in: usize) -> f64 {
+    let path = display(&file.path);
+    let name = path.rsplit('/').next().unwrap_or_default().to_ascii_lowercase();
+    let mut score = 0.0;
+
+    if ENTRY_POINTS.contains(&name.as_str()) {
+        score += 8.0;
+    }
+    let generated = is_generated(&path, &name, &file.content);
+    if languages::is_manifest(&file.path) && !generated {
+        score += 5.0;
+    }
+    if languages::of(&file.path).is_some() {
+        score += 2.0;
+    } else if !crate::files::has_extension(&file.path, DOC_AND_CONFIG) {
+        score -= 1.0;
+    }
+    if languages::is_test_file(&file.path) {
+        score -= 3.0;
+    }
+    if generated {
+        score -= 8.0;
+    }
+
+    let depth = path.matches('/').count();
+    score -= 0.5 * depth as f64;
+    score -= (file.content.len() as f64 / SIZE_FREE_BYTES).log2().max(0.0);
+    score += 2.0 * (1.0 + fan_in as f64).log2();
+    score
+}
+
+// Indices of `files`, most important first.
+pub fn rank(files: &[SourceFile]) -> Vec<usize> {
+    let fan_in = fan_in(files);
+    let scores: Vec<f64> = files.iter().zip(&fan_in).map(|(file, &fan_in)| importance(file, fan_in)).collect();
+    let mut order: Vec<usize> = (0..files.len()).collect();
+    order.sort_by(|&a, &b| scores[b].total_cmp(&scores[a]).then_with(|| files[a].path.cmp(&files[b].path)));
+    order
+}
+
+// Keeps the most important files whose content, with codebase_viewer's
+// per-file overhead, fits in `char_limit`, in their original order. A file
+// too large for the remaining space is skipped in favor of smaller, less
+// important ones. Returns the paths left out, most important first.
+pub fn trim_to_limit(files: &mut Vec<SourceFile>, char_limit: usize) -> Vec<PathBuf> {
+    let cost = |file: &SourceFile| file.content.len() + PER_FILE_OVERHEAD;
+    if files.iter().map(cost).sum::<usize>() <= char_limit {
+        return Vec::new();
+    }
+
+    let mut used = 0;
+    let mut keep = vec![false; files.len()];
+    let mut left_out = Vec::new();
+    for index in rank(files) {
+        let cost = cost(&files[index]);
+        if used + cost <= char_limit {
+            used += cost;
+            keep[index] = true;
+        } else {
+            left_out.push(files[index].path.clone());
+        }
+    }
+    let mut keep = keep.into_iter();
+    files.retain(|_| keep.next().unwrap_or(false));
+    left_out
+}
+
+// For each file, how many other files mention its module name in an import,
+// `use`, `mod`, `require`, or `#include` line.
+fn fan_in(files: &[SourceFile]) -> Vec<usize> {
+    let mut importers: HashMap<String, usize> = HashMap::new();
+    for file in files {
+        let mut imported = HashSet::new();
+        for line in file.content.lines().map(str::trim_start).filter(|line| is_import(line)) {
+            imported.extend(line.split(|c: char| !c.is_alphanumeric() && c != '_').filter(|w| w.len() > 1).map(str::to_lowercase));
+        }
+        // A file's own module name does not count towards its fan-in.
+        if let Some(own) = module_name(&file.path) {
+            imported.remove(&own);
+        }
+        for word in imported {
+            *importers.entry(word).or_default() += 1;
+        }
+    }
+    files
+        .iter()
+        .map(|file| module_name(&file.path).and_then(|name| importers.get(&name).copied()).unwrap_or(0))
+        .collect()
+}
+
+fn is_import(line: &str) -> bool {
+    ["use ", "pub use ", "mod ", "pub mod ", "import ", "from ", "#include", "require ", "require_relative "]
+        .iter()
+        .any(|prefix| line.starts_with(prefix))
+        || line.contains("require(")
+        || line.contains("import(")
+}
+
+// The name other files import a file by: its stem, or its directory's name
+// for module roots (`mod.rs`, `index.ts`, `__init__.py`).
+pub(crate) fn module_name(path: &Path) -> Option<String> {
+    let stem = path.file_stem()?.to_str()?.to_lowercase();
+    let name = match stem.as_str() {
+        "mod" | "index" | "__init__" => path.parent()?.file_name()?.to_str()?.to_lowercase(),
+        _ => stem,
+    };
+    (name.len() > 1).then_some(name)
+}
+
+fn is_generated(path: &str, name: &str, content: &str) -> bool {
+    path.split('/').rev().skip(1).any(|dir| GENERATED_DIRS.contains(&dir.to_ascii_lowercase().as_str()))
+        || GENERATED_SUFFIXES.iter().any(|suffix| name.ends_with(suffix))
+        || content.lines().take(5).any(|line| GENERATED_MARKERS.iter().any(|marker| line.contains(marker)))
+}
+
+fn display(path: &Path) -> String {
+    path.to_string_lossy().replace('\\', "/")
+}
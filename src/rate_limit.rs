@@ -0,0 +1,85 @@
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+use serde_json::Value;
+use std::time::Duration;
+
+#[derive(Debug)]
+pub struct RateLimit {
+    pub message: String,
+    pub retry_after: Option<Duration>,
+    pub quota_exhausted: bool,
+}
+
+pub fn parse(headers: &HeaderMap, body: &str) -> RateLimit {
+    let error = error_object(body);
+    let message = error
+        .as_ref()
+        .and_then(|e| e.get("message"))
+        .and_then(Value::as_str)
+        .unwrap_or(body)
+        .to_string();
+
+    let retry_after = header_delay(headers)
+        .or_else(|| error.as_ref().and_then(retry_info_delay))
+        .or_else(|| message_delay(&message));
+
+    let quota_exhausted = message.to_lowercase().contains("quota")
+        || error.as_ref().is_some_and(|e| has_detail(e, "QuotaFailure"));
+
+    RateLimit { message, retry_after, quota_exhausted }
+}
+
+pub fn error_message(body: &str) -> String {
+    error_object(body)
+        .and_then(|e| e.get("message").and_then(Value::as_str).map(str::to_string))
+        .unwrap_or_else(|| body.to_string())
+}
+
+// Gemini's OpenAI-compatible endpoint returns either `{"error": {...}}` or `[{"error": {...}}]`.
+fn error_object(body: &str) -> Option<Value> {
+    let value: Value = serde_json::from_str(body).ok()?;
+    let value = match value {
+        Value::Array(mut items) if !items.is_empty() => items.swap_remove(0),
+        other => other,
+    };
+    value.get("error").cloned()
+}
+
+fn header_delay(headers: &HeaderMap) -> Option<Duration> {
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?;
+    parse_seconds(value.trim())
+}
+
+fn retry_info_delay(error: &Value) -> Option<Duration> {
+    error
+        .get("details")?
+        .as_array()?
+        .iter()
+        .find_map(|d| d.get("retryDelay").and_then(Value::as_str))
+        .and_then(|delay| parse_seconds(delay.trim_end_matches('s')))
+}
+
+// e.g. "... Please retry in 23.417s."
+fn message_delay(message: &str) -> Option<Duration> {
+    let rest = &message[message.find("retry in ")? + "retry in ".len()..];
+    let end = rest.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(rest.len());
+    if !rest[end..].starts_with('s') {
+        return None;
+    }
+    parse_seconds(&rest[..end])
+}
+
+fn has_detail(error: &Value, kind: &str) -> bool {
+    error
+        .get("details")
+        .and_then(Value::as_array)
+        .is_some_and(|details| {
+            details.iter().any(|d| {
+                d.get("@type").and_then(Value::as_str).is_some_and(|t| t.ends_with(kind))
+            })
+        })
+}
+
+fn parse_seconds(value: &str) -> Option<Duration> {
+    let seconds: f64 = value.parse().ok()?;
+    (seconds.is_finite() && seconds >= 0.0).then(|| Duration::from_secs_f64(seconds))
+}
@@ -0,0 +1,415 @@
+use crate::files;
+use std::future::Future;
+use std::path::Path;
+
+// Replaces the contents of a redacted string literal.
+const PLACEHOLDER: &str = "…";
+
+// Numeric literals with at least this many digits are treated as data
+// (prices, thresholds, IDs) and replaced by `0`.
+const MIN_DATA_DIGITS: usize = 3;
+
+// Dependency manifests name packages and versions, not business data, and the
+// dependency tools need them intact.
+const EXEMPT_FILES: &[&str] = &[
+    "cargo.toml", "pyproject.toml", "package.json", "package-lock.json", "composer.json", "tsconfig.json", "deno.json",
+];
+
+tokio::task_local! {
+    static ACTIVE: bool;
+}
+
+// Runs `future` with every file rendered into context redacted when `active`.
+pub async fn scope<F: Future>(active: bool, future: F) -> F::Output {
+    ACTIVE.scope(active, future).await
+}
+
+pub fn active() -> bool {
+    ACTIVE.try_with(|active| *active).unwrap_or(false)
+}
+
+struct Syntax {
+    line_comments: &'static [&'static str],
+    block_comment: Option<(&'static str, &'static str)>,
+    quotes: &'static [char],
+    // Python-style """ and ''' strings.
+    triple_quotes: bool,
+    // Strings followed by `:` are object keys (structure), not data.
+    keep_keys: bool,
+    // Go import blocks list one bare path per line.
+    import_blocks: bool,
+    // Rust raw strings (r"..." and r#"..."#) and char literals.
+    rust_literals: bool,
+}
+
+const C_LIKE: Syntax = Syntax {
+    line_comments: &["//"],
+    block_comment: Some(("/*", "*/")),
+    quotes: &['"', '\''],
+    triple_quotes: false,
+    keep_keys: false,
+    import_blocks: false,
+    rust_literals: false,
+};
+
+const HASH_COMMENTS: Syntax = Syntax {
+    line_comments: &["#"],
+    block_comment: None,
+    ..C_LIKE
+};
+
+fn syntax(language: &str) -> Option<Syntax> {
+    let syntax = match language {
+        "rust" => Syntax { quotes: &['"'], rust_literals: true, ..C_LIKE },
+        "go" => Syntax { quotes: &['"', '\'', '`'], import_blocks: true, ..C_LIKE },
+        "javascript" | "jsx" | "typescript" | "tsx" | "vue" | "svelte" => Syntax { quotes: &['"', '\'', '`'], ..C_LIKE },
+        "c" | "cpp" | "java" | "kotlin" | "scala" | "swift" | "csharp" | "dart" | "zig" | "groovy" | "protobuf" => C_LIKE,
+        "php" => Syntax { line_comments: &["//", "#"], ..C_LIKE },
+        "python" => Syntax { triple_quotes: true, ..HASH_COMMENTS },
+        "elixir" => Syntax { quotes: &['"'], triple_quotes: true, ..HASH_COMMENTS },
+        "ruby" | "bash" | "zsh" | "fish" | "r" | "powershell" | "yaml" | "toml" | "graphql" => HASH_COMMENTS,
+        "hcl" => Syntax { line_comments: &["#", "//"], block_comment: Some(("/*", "*/")), quotes: &['"'], ..HASH_COMMENTS },
+        "sql" => Syntax { line_comments: &["--"], quotes: &['\''], ..C_LIKE },
+        "lua" | "haskell" => Syntax { line_comments: &["--"], block_comment: None, ..C_LIKE },
+        "json" => Syntax { quotes: &['"'], keep_keys: true, ..C_LIKE },
+        _ => return None,
+    };
+    Some(syntax)
+}
+
+// `content` with embedded data removed, keeping the code's structure,
+// identifiers, and comments: string literal contents become `…` (except
+// import paths and object keys) and numeric literals of three or more digits
+// become `0`. Line breaks inside literals are kept so `path:line` references
+// still match the file. CSV/TSV files keep only their header row; files in
+// other languages, and dependency manifests, are returned unchanged.
+pub fn source(path: &Path, content: &str) -> String {
+    let name = path.file_name().and_then(|n| n.to_str()).unwrap_or_default().to_ascii_lowercase();
+    if EXEMPT_FILES.contains(&name.as_str()) {
+        return content.to_string();
+    }
+    if name.ends_with(".csv") || name.ends_with(".tsv") {
+        return header_only(content);
+    }
+    match files::language(path).and_then(syntax) {
+        Some(syntax) => redact_code(content, &syntax),
+        None => content.to_string(),
+    }
+}
+
+// The code of `content` as whitespace-separated tokens, without comments
+// and, in languages with triple-quoted strings, docstrings (a triple-quoted
+// string alone on its lines). Two versions of a file have the same tokens
+// when edits only touched comments, which is what document_code's `write`
+// promises. None for languages without a known comment syntax.
+pub fn code_tokens(path: &Path, content: &str) -> Option<Vec<String>> {
+    let syntax = files::language(path).and_then(syntax)?;
+    let mut tokens = Vec::new();
+    let mut word = String::new();
+    let mut pos = 0;
+    while let Some(c) = content[pos..].chars().next() {
+        let rest = &content[pos..];
+
+        let comment = if syntax.line_comments.iter().any(|marker| rest.starts_with(marker)) {
+            Some(rest.find('\n').unwrap_or(rest.len()))
+        } else {
+            syntax
+                .block_comment
+                .filter(|(open, _)| rest.starts_with(open))
+                .map(|(open, close)| rest[open.len()..].find(close).map_or(rest.len(), |i| open.len() + i + close.len()))
+        };
+        if let Some(len) = comment {
+            tokens.extend((!word.is_empty()).then(|| std::mem::take(&mut word)));
+            pos += len;
+            continue;
+        }
+
+        if let Some(len) = literal_len(rest, &syntax, word.ends_with(is_ident)) {
+            let line_start = content[..pos].rfind('\n').map_or(0, |i| i + 1);
+            let line_end = content[pos + len..].find('\n').map_or(content.len(), |i| pos + len + i);
+            let docstring = syntax.triple_quotes
+                && (rest.starts_with("\"\"\"") || rest.starts_with("'''"))
+                && content[line_start..pos].trim().is_empty()
+                && content[pos + len..line_end].trim().is_empty();
+            if !docstring {
+                word.push_str(&rest[..len]);
+            }
+            pos += len;
+            continue;
+        }
+
+        if c.is_whitespace() {
+            tokens.extend((!word.is_empty()).then(|| std::mem::take(&mut word)));
+        } else {
+            word.push(c);
+        }
+        pos += c.len_utf8();
+    }
+    tokens.extend((!word.is_empty()).then_some(word));
+    Some(tokens)
+}
+
+// Length of the string or character literal `text` starts with, if any.
+// `after_ident` is true when it follows an identifier character, where a `'`
+// is not a Rust character literal and `r` is not a raw string prefix.
+fn literal_len(text: &str, syntax: &Syntax, after_ident: bool) -> Option<usize> {
+    if syntax.rust_literals && !after_ident {
+        if let Some(len) = rust_char_literal(text) {
+            return Some(len);
+        }
+        if let Some((open, body_len)) = rust_raw_string(text) {
+            return Some(open * 2 - 1 + body_len);
+        }
+    }
+    let c = text.chars().next()?;
+    if !syntax.quotes.contains(&c) {
+        return None;
+    }
+    let delimiter = if syntax.triple_quotes && text.starts_with(&c.to_string().repeat(3)) { 3 } else { 1 };
+    literal_body(&text[delimiter..], c, delimiter).map(|body_len| delimiter * 2 + body_len)
+}
+
+fn header_only(content: &str) -> String {
+    let mut lines = content.split('\n');
+    let header = lines.next().unwrap_or_default();
+    let blanked = lines.count();
+    format!("{header}{}", "\n".repeat(blanked))
+}
+
+fn redact_code(content: &str, syntax: &Syntax) -> String {
+    let mut out = String::with_capacity(content.len());
+    let mut pos = 0;
+    while let Some(c) = content[pos..].chars().next() {
+        let rest = &content[pos..];
+
+        // Comments are copied as they are.
+        if syntax.line_comments.iter().any(|marker| rest.starts_with(marker)) {
+            let end = rest.find('\n').unwrap_or(rest.len());
+            out.push_str(&rest[..end]);
+            pos += end;
+            continue;
+        }
+        if let Some((open, close)) = syntax.block_comment.filter(|(open, _)| rest.starts_with(open)) {
+            let end = rest[open.len()..].find(close).map_or(rest.len(), |i| open.len() + i + close.len());
+            out.push_str(&rest[..end]);
+            pos += end;
+            continue;
+        }
+
+        if syntax.rust_literals && !out.ends_with(is_ident) {
+            if let Some(len) = rust_char_literal(rest) {
+                out.push_str(&rest[..len]);
+                pos += len;
+                continue;
+            }
+            if let Some((open, body_len)) = rust_raw_string(rest) {
+                let body = &rest[open..open + body_len];
+                let end = open * 2 - 1 + body_len;
+                out.push_str(&rest[..open]);
+                out.push_str(&redacted_body(body));
+                out.push_str(&rest[open + body_len..end]);
+                pos += end;
+                continue;
+            }
+        }
+
+        if syntax.quotes.contains(&c) {
+            let delimiter = if syntax.triple_quotes && rest.starts_with(&c.to_string().repeat(3)) { 3 } else { 1 };
+            if let Some(body_len) = literal_body(&rest[delimiter..], c, delimiter) {
+                let body = &rest[delimiter..delimiter + body_len];
+                let end = delimiter * 2 + body_len;
+                let keep = body.is_empty()
+                    || is_import_line(content, pos, syntax)
+                    || (syntax.keep_keys && rest[end..].trim_start().starts_with(':'));
+                out.push_str(&rest[..delimiter]);
+                if keep {
+                    out.push_str(body);
+                } else {
+                    out.push_str(&redacted_body(body));
+                }
+                out.push_str(&rest[delimiter + body_len..end]);
+                pos += end;
+                continue;
+            }
+        }
+
+        if c.is_ascii_digit() && !out.ends_with(is_ident) {
+            let len = number_len(rest);
+            let number = &rest[..len];
+            if number.chars().filter(char::is_ascii_digit).count() >= MIN_DATA_DIGITS {
+                out.push('0');
+            } else {
+                out.push_str(number);
+            }
+            pos += len;
+            continue;
+        }
+
+        out.push(c);
+        pos += c.len_utf8();
+    }
+    out
+}
+
+fn is_ident(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+// The placeholder plus the body's line breaks, keeping line numbers.
+fn redacted_body(body: &str) -> String {
+    let mut redacted = PLACEHOLDER.to_string();
+    redacted.extend(body.chars().filter(|&ch| ch == '\n'));
+    redacted
+}
+
+// Digits, letters (suffixes, hex, exponents), `_`, and a `.` followed by a
+// digit, so `0..100` keeps its range operator.
+fn number_len(text: &str) -> usize {
+    let bytes = text.as_bytes();
+    let mut len = 0;
+    while len < bytes.len() {
+        let b = bytes[len];
+        let decimal_point = b == b'.' && bytes.get(len + 1).is_some_and(u8::is_ascii_digit);
+        if !(b.is_ascii_alphanumeric() || b == b'_' || decimal_point) {
+            break;
+        }
+        len += 1;
+    }
+    len
+}
+
+// `'x'` or an escape such as `'\n'` or `'\u{1F600}'`; a lifetime is not one.
+fn rust_char_literal(text: &str) -> Option<usize> {
+    let body = text.strip_prefix('\'')?;
+    let mut chars = body.char_indices();
+    let (_, first) = chars.next()?;
+    if first == '\\' {
+        return body.char_indices().skip(2).take(10).find(|&(_, ch)| ch == '\'').map(|(i, _)| i + 2);
+    }
+    match chars.next() {
+        Some((i, '\'')) => Some(i + 2),
+        _ => None,
+    }
+}
+
+// For `r#"..."#`, the length of the opening `r#"` and of the body.
+fn rust_raw_string(text: &str) -> Option<(usize, usize)> {
+    let hashes = text.strip_prefix('r')?.bytes().take_while(|&b| b == b'#').count();
+    if text.as_bytes().get(1 + hashes) != Some(&b'"') {
+        return None;
+    }
+    let open = hashes + 2;
+    let closing = format!("\"{}", "#".repeat(hashes));
+    let body_len = text[open..].find(&closing)?;
+    Some((open, body_len))
+}
+
+// Length of a literal's body up to its closing delimiter, or None if it is
+// not closed. Single-quoted literals end at the line, so apostrophes and
+// lifetimes are not taken for strings.
+fn literal_body(text: &str, quote: char, delimiter: usize) -> Option<usize> {
+    let closing = quote.to_string().repeat(delimiter);
+    let mut chars = text.char_indices();
+    while let Some((i, ch)) = chars.next() {
+        match ch {
+            '\\' => {
+                chars.next();
+            }
+            '\n' if quote == '\'' && delimiter == 1 => return None,
+            _ if text[i..].starts_with(&closing) => return Some(i),
+            _ => {}
+        }
+    }
+    None
+}
+
+// Import paths name modules and packages, which is structure.
+fn is_import_line(content: &str, pos: usize, syntax: &Syntax) -> bool {
+    let start = content[..pos].rfind('\n').map_or(0, |i| i + 1);
+    let end = content[pos..].find('\n').map_or(content.len(), |i| pos + i);
+    let line = &content[start..end];
+    let trimmed = line.trim_start();
+    ["import", "from ", "#include", "#import", "@import", "use ", "require", "package "]
+        .iter()
+        .any(|prefix| trimmed.starts_with(prefix))
+        || line.contains("require(")
+        || (trimmed.starts_with("export ") && line.contains(" from "))
+        || (syntax.import_blocks && is_bare_import(trimmed))
+}
+
+// `"net/http"` or `alias "net/http"`, alone on its line.
+fn is_bare_import(line: &str) -> bool {
+    let line = line.trim_end();
+    let path = line.split_once(' ').map_or(line, |(alias, path)| {
+        if alias.chars().all(|c| c.is_alphanumeric() || c == '_' || c == '.') { path.trim_start() } else { line }
+    });
+    path.len() >= 2 && path.starts_with('"') && path.ends_with('"') && !path[1..path.len() - 1].contains('"')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokens(path: &str, content: &str) -> Vec<String> {
+        code_tokens(Path::new(path), content).unwrap()
+    }
+
+    fn redacted(path: &str, content: &str) -> String {
+        source(Path::new(path), content)
+    }
+
+    #[test]
+    fn redacts_literals_and_keeps_structure() {
+        let code = "use crate::db;\nlet rate = 0.5 * 1000; // keep \"this\"\nlet name = \"Acme\nCorp\";\nlet empty = \"\";\nlet i = 42;\n";
+        let out = redacted("billing.rs", code);
+        assert_eq!(out, "use crate::db;\nlet rate = 0.5 * 0; // keep \"this\"\nlet name = \"…\n\";\nlet empty = \"\";\nlet i = 42;\n");
+        assert_eq!(out.lines().count(), code.lines().count());
+    }
+
+    #[test]
+    fn keeps_import_paths_keys_and_rust_lifetimes() {
+        assert_eq!(redacted("app.ts", "import { x } from './pricing';\nconst y = 'secret';"), "import { x } from './pricing';\nconst y = '…';");
+        assert_eq!(redacted("config.json", "{\"price\": \"9.99\", \"id\": 12345}"), "{\"price\": \"…\", \"id\": 0}");
+        assert_eq!(redacted("lib.rs", "fn f<'a>(s: &'a str) -> char { 'x' }"), "fn f<'a>(s: &'a str) -> char { 'x' }");
+        assert_eq!(redacted("lib.rs", "let s = r#\"raw \"quoted\"\"#;"), "let s = r#\"…\"#;");
+        assert_eq!(redacted("main.go", "import (\n\t\"net/http\"\n)\nvar k = \"key\""), "import (\n\t\"net/http\"\n)\nvar k = \"…\"");
+    }
+
+    #[test]
+    fn keeps_csv_headers_and_exempt_files() {
+        assert_eq!(redacted("customers.csv", "name,email\nAda,ada@example.com\nBob,bob@example.com"), "name,email\n\n");
+        let manifest = "[package]\nname = \"app\"\nversion = \"1.2.3\"\n";
+        assert_eq!(redacted("Cargo.toml", manifest), manifest);
+        assert_eq!(redacted("notes.unknownext", "price = \"9.99\""), "price = \"9.99\"");
+    }
+
+    #[test]
+    fn comments_do_not_change_code_tokens() {
+        let before = "fn add(a: i32) -> i32 {\n    a + 1\n}\n";
+        let after = "/// Adds one.\n///\n/* block */ fn add(a: i32) -> i32 {\n    // the increment\n    a + 1\n}\n";
+        assert_eq!(tokens("lib.rs", before), tokens("lib.rs", after));
+    }
+
+    #[test]
+    fn comment_markers_inside_strings_are_code() {
+        let before = "let url = \"http://example.com\";";
+        assert_eq!(tokens("lib.rs", before), ["let", "url", "=", "\"http://example.com\";"]);
+        assert_ne!(tokens("lib.rs", before), tokens("lib.rs", "let url = \"http://example.org\";"));
+        assert_ne!(tokens("lib.rs", "let s = \"a  b\";"), tokens("lib.rs", "let s = \"a b\";"));
+    }
+
+    #[test]
+    fn python_docstrings_are_not_code() {
+        let before = "def add(a):\n    return a + 1\n";
+        let after = "def add(a):\n    \"\"\"Adds one.\n\n    Returns a + 1.\n    \"\"\"\n    # the increment\n    return a + 1\n";
+        assert_eq!(tokens("lib.py", before), tokens("lib.py", after));
+        // A triple-quoted string that is part of a statement is code.
+        assert_ne!(tokens("lib.py", "x = 1\n"), tokens("lib.py", "x = \"\"\"doc\"\"\"\n"));
+    }
+
+    #[test]
+    fn code_changes_change_tokens() {
+        assert_ne!(tokens("app.ts", "const a = 1;"), tokens("app.ts", "// note\nconst a = 2;"));
+        assert!(code_tokens(Path::new("notes.unknown"), "text").is_none());
+    }
+}
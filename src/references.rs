@@ -0,0 +1,104 @@
+use crate::apply::safe_relative_path;
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::Path;
+
+pub const INSTRUCTIONS: &str = r#"Whenever you refer to existing code, cite its location as an inline code anchor of the form `path:line` or `path:start-end` (for example `src/server.rs:42` or `src/server.rs:42-58`), using paths relative to the codebase root exactly as they appear in the codebase report. Count line numbers from the first line of each file. Only cite files that appear in the report; do not anchor files that do not exist yet."#;
+
+#[derive(Serialize, JsonSchema, Clone, PartialEq)]
+pub struct Reference {
+    pub path: String,
+    pub line: usize,
+    pub end_line: Option<usize>,
+    pub verified: bool,
+}
+
+// Finds `path:line` and `path:start-end` anchors in inline code spans outside
+// fenced code blocks, then checks each against the files under `roots`. With
+// several roots, a path is resolved against the first root that contains it.
+pub async fn verify(roots: &[&Path], text: &str) -> Vec<Reference> {
+    let mut line_counts: HashMap<String, Option<usize>> = HashMap::new();
+    let mut references = Vec::new();
+
+    for (path, line, end_line) in extract(text) {
+        if !line_counts.contains_key(&path) {
+            let mut count = None;
+            if let Ok(relative) = safe_relative_path(&path) {
+                for root in roots {
+                    if let Ok(content) = tokio::fs::read_to_string(root.join(&relative)).await {
+                        count = Some(content.lines().count());
+                        break;
+                    }
+                }
+            }
+            line_counts.insert(path.clone(), count);
+        }
+
+        let last = end_line.unwrap_or(line);
+        let verified = line_counts[&path].is_some_and(|count| line >= 1 && line <= last && last <= count);
+        let reference = Reference { path, line, end_line, verified };
+        if !references.contains(&reference) {
+            references.push(reference);
+        }
+    }
+
+    references
+}
+
+pub fn render_unverified(references: &[Reference]) -> Option<String> {
+    let unverified: Vec<_> = references.iter().filter(|r| !r.verified).map(anchor).collect();
+    if unverified.is_empty() {
+        return None;
+    }
+    Some(format!(
+        "**Unverified references** (file not found or line out of range; treat with caution): {}",
+        unverified.join(", ")
+    ))
+}
+
+fn anchor(reference: &Reference) -> String {
+    match reference.end_line {
+        Some(end) => format!("`{}:{}-{}`", reference.path, reference.line, end),
+        None => format!("`{}:{}`", reference.path, reference.line),
+    }
+}
+
+fn extract(text: &str) -> Vec<(String, usize, Option<usize>)> {
+    let mut anchors = Vec::new();
+    let mut in_fence = false;
+
+    for line in text.lines() {
+        if line.trim_start().starts_with("```") {
+            in_fence = !in_fence;
+            continue;
+        }
+        if in_fence {
+            continue;
+        }
+        for span in line.split('`').skip(1).step_by(2) {
+            if let Some(anchor) = parse_anchor(span) {
+                anchors.push(anchor);
+            }
+        }
+    }
+
+    anchors
+}
+
+fn parse_anchor(span: &str) -> Option<(String, usize, Option<usize>)> {
+    let (path, lines) = span.trim().rsplit_once(':')?;
+    // Skip URLs, host:port pairs, and other colon-separated values.
+    let looks_like_path = (path.contains('.') || path.contains('/'))
+        && path.contains(|c: char| c.is_ascii_alphabetic())
+        && !path.contains(char::is_whitespace)
+        && !path.contains("://");
+    if !looks_like_path {
+        return None;
+    }
+    let (line, end_line) = match lines.split_once('-') {
+        Some((start, end)) => (start.parse().ok()?, Some(end.parse().ok()?)),
+        None => (lines.parse().ok()?, None),
+    };
+    Some((path.trim_start_matches("./").to_string(), line, end_line))
+}
@@ -0,0 +1,334 @@
+// HTTP frontend over the `agent` facade, for consumers that do not speak MCP
+// (scripts, CI jobs, portals). Request and response bodies are JSON; the
+// OpenAPI document at /openapi.json is generated from the same types.
+use crate::agent::{Agent, AgentError, Answer, PlanResult, RunOptions, Scope};
+use crate::errors::ErrorKind;
+use crate::fairness;
+use crate::health::HealthReport;
+use crate::forge::PublishTarget;
+use crate::llm::GenerationParams;
+use crate::plan::{OutputFormat, Verbosity};
+use anyhow::{Context, Result};
+use axum::extract::{ConnectInfo, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use schemars::generate::SchemaSettings;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::net::SocketAddr;
+
+#[derive(Deserialize, JsonSchema)]
+struct RunRequest {
+    #[schemars(description = "Full absolute path to the codebase directory, a .zip/.tar.gz/.tgz/.tar archive of one, or a git URL (e.g., https://github.com/org/repo#branch).")]
+    directory: String,
+    #[schemars(description = "Additional directories analyzed together with 'directory', each with an equal share of the token limit.")]
+    #[serde(default)]
+    directories: Vec<String>,
+    #[schemars(description = "Analyze 'directory' as of this commit (SHA, tag, or branch) instead of the working tree.")]
+    commit: Option<String>,
+    #[schemars(description = "The feature request, bug description, question, or change request.")]
+    prompt: String,
+    #[schemars(description = "Send only the files most relevant to the prompt instead of the full codebase report: those defining or using the symbols it names, from the repository's ctags/LSIF/SCIP index if it has one, otherwise selected via embeddings.")]
+    #[serde(default)]
+    use_retrieval: bool,
+    #[schemars(description = "Let the model pick the files it needs from the file tree first. Defaults to the server's FILE_SELECTION_TOOLS setting.")]
+    file_selection: Option<bool>,
+    #[schemars(description = "Send the declarations of every file plus the full contents of the files defining symbols named in the prompt, instead of the full codebase report.")]
+    #[serde(default)]
+    skeleton: bool,
+    #[schemars(description = "Copy the directory to local storage before analysis. Defaults to the server's MIRROR_DIRECTORIES setting.")]
+    mirror: Option<bool>,
+    #[schemars(description = "Number of LLM passes: 1, 2 (default), or 3 (adds a critic pass).")]
+    passes: Option<u8>,
+    #[schemars(description = "Independent analyses (1-5, default 1) judged and merged before the detail pass; needs passes 2 or 3.")]
+    candidates: Option<u8>,
+    #[schemars(description = "Append a '## Corrections' section from a pass that checks the response against the codebase (files, APIs, step order). Markdown output only.")]
+    #[serde(default)]
+    verify: bool,
+    #[schemars(description = "After the analysis pass, send later passes only the files it referenced.")]
+    #[serde(default)]
+    compact_detail: bool,
+    #[schemars(description = "Detail of the final answer: 'summary' (decisions, affected files, and risks, little code), 'standard' (default), or 'exhaustive' (complete code for every step). Not available for generate_patch.")]
+    #[serde(default)]
+    verbosity: Verbosity,
+    #[schemars(description = "Generation parameters: 'temperature', 'top_p', 'max_tokens', and 'stop' sequences. Unset fields use the server's per-tool and global settings.")]
+    generation: Option<GenerationParams>,
+    #[schemars(description = "Redact string literal contents and multi-digit numbers from the context before it is sent; not available for generate_patch.")]
+    #[serde(default)]
+    redact_literals: bool,
+    #[schemars(description = "Hard cap on the tokens this call may use across all passes; the context is shrunk to fit it. Defaults to the server's MAX_TOTAL_TOKENS_PER_CALL setting.")]
+    max_total_tokens: Option<u64>,
+    #[schemars(description = "Context size limit in tokens for this call, instead of the server's TOKEN_CHAR_LIMIT; bounded by MAX_TOKEN_CHAR_LIMIT.")]
+    token_limit: Option<usize>,
+    #[schemars(description = "Check the plan's new dependencies and library APIs against the package registries and the project's locked versions; findings are returned in 'feasibility'. Plans only.")]
+    #[serde(default)]
+    verify_feasibility: bool,
+    #[schemars(description = "Return the prompts each pass would send, with context statistics, in 'text' instead of calling Gemini. No session or plan is created.")]
+    #[serde(default)]
+    dry_run: bool,
+    #[schemars(description = "Publish the result as a comment on a GitHub, GitLab, or Bitbucket issue, pull request, or merge request, or on GitHub as a pull request's description or a new draft pull request; the URL is returned in 'published'. Needs the server's token for that forge.")]
+    publish: Option<PublishTarget>,
+}
+
+impl RunRequest {
+    fn scope(&self) -> Scope {
+        Scope {
+            directory: self.directory.clone(),
+            directories: self.directories.clone(),
+            commit: self.commit.clone(),
+        }
+    }
+
+    fn options(&self, format: OutputFormat) -> RunOptions {
+        RunOptions {
+            format,
+            use_retrieval: self.use_retrieval,
+            file_selection: self.file_selection,
+            skeleton: self.skeleton,
+            mirror: self.mirror,
+            passes: self.passes,
+            candidates: self.candidates,
+            verify: self.verify,
+            compact_detail: self.compact_detail,
+            verbosity: self.verbosity,
+            generation: self.generation.clone().unwrap_or_default(),
+            redact_literals: self.redact_literals,
+            max_total_tokens: self.max_total_tokens,
+            token_limit: self.token_limit,
+            verify_feasibility: self.verify_feasibility,
+            dry_run: self.dry_run,
+            publish: self.publish.clone(),
+        }
+    }
+}
+
+#[derive(Deserialize, JsonSchema)]
+struct PlanRequest {
+    #[serde(flatten)]
+    run: RunRequest,
+    #[schemars(description = "'markdown' (default) or 'json' for a structured plan, returned parsed in 'plan'.")]
+    #[serde(default)]
+    format: OutputFormat,
+}
+
+#[derive(Deserialize, JsonSchema)]
+struct FollowUpRequest {
+    #[schemars(description = "The session_id returned by a previous call.")]
+    session_id: String,
+    question: String,
+}
+
+#[derive(Serialize, JsonSchema)]
+struct FollowUpResponse {
+    text: String,
+}
+
+#[derive(Serialize, JsonSchema)]
+struct ErrorResponse {
+    error: String,
+    kind: ErrorKind,
+    retryable: bool,
+}
+
+struct ApiError(AgentError);
+
+impl From<AgentError> for ApiError {
+    fn from(e: AgentError) -> Self {
+        Self(e)
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        // An unparseable plan is the model's fault, not the server's.
+        let kind = match &self.0 {
+            AgentError::Pipeline(e) => e.kind,
+            AgentError::InvalidPlan(_) => ErrorKind::InvalidOutput,
+        };
+        let status = match kind {
+            ErrorKind::InvalidParams | ErrorKind::InvalidPath => StatusCode::BAD_REQUEST,
+            ErrorKind::NotFound => StatusCode::NOT_FOUND,
+            ErrorKind::ContextTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+            ErrorKind::LlmQuota | ErrorKind::LlmRateLimited => StatusCode::TOO_MANY_REQUESTS,
+            ErrorKind::LlmTimeout => StatusCode::GATEWAY_TIMEOUT,
+            ErrorKind::Disabled => StatusCode::FORBIDDEN,
+            ErrorKind::LlmUnauthorized
+            | ErrorKind::LlmInvalidRequest
+            | ErrorKind::LlmUnavailable
+            | ErrorKind::InvalidOutput
+            | ErrorKind::ExternalService => StatusCode::BAD_GATEWAY,
+            ErrorKind::ReportGeneration | ErrorKind::Internal => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        let error = ErrorResponse { error: self.0.to_string(), kind, retryable: kind.retryable() };
+        (status, Json(error)).into_response()
+    }
+}
+
+pub async fn serve(agent: Agent, addr: SocketAddr) -> Result<()> {
+    let app = Router::new()
+        .route("/openapi.json", get(openapi_document))
+        .route("/healthz", get(healthz))
+        .route("/v1/plan_feature", post(plan_feature))
+        .route("/v1/plan_bug_fix", post(plan_bug_fix))
+        .route("/v1/explain_code", post(explain_code))
+        .route("/v1/generate_patch", post(generate_patch))
+        .route("/v1/follow_up", post(follow_up))
+        .with_state(agent);
+
+    let listener = tokio::net::TcpListener::bind(addr)
+        .await
+        .with_context(|| format!("Failed to listen on {addr}"))?;
+    tracing::info!("REST API listening on http://{addr} (OpenAPI document at /openapi.json)");
+    axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>())
+        .with_graceful_shutdown(async {
+            tokio::signal::ctrl_c().await.ok();
+        })
+        .await
+        .context("REST server failed")
+}
+
+// Fair-share quotas apply per caller: the `X-Client-Id` header, or else the
+// caller's IP address.
+fn consumer(headers: &HeaderMap, addr: SocketAddr) -> String {
+    match headers.get("x-client-id").and_then(|v| v.to_str().ok()) {
+        Some(id) => format!("client {id}"),
+        None => format!("client {}", addr.ip()),
+    }
+}
+
+async fn plan_feature(
+    State(agent): State<Agent>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Json(request): Json<PlanRequest>,
+) -> Result<Json<PlanResult>, ApiError> {
+    tracing::info!("Received REST 'plan_feature' request");
+    let options = request.run.options(request.format);
+    let scope = request.run.scope();
+    let call = agent.plan_feature(&scope, &request.run.prompt, options);
+    Ok(Json(fairness::as_consumer(consumer(&headers, addr), call).await?))
+}
+
+async fn plan_bug_fix(
+    State(agent): State<Agent>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Json(request): Json<PlanRequest>,
+) -> Result<Json<PlanResult>, ApiError> {
+    tracing::info!("Received REST 'plan_bug_fix' request");
+    let options = request.run.options(request.format);
+    let scope = request.run.scope();
+    let call = agent.plan_bug_fix(&scope, &request.run.prompt, options);
+    Ok(Json(fairness::as_consumer(consumer(&headers, addr), call).await?))
+}
+
+async fn explain_code(
+    State(agent): State<Agent>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Json(request): Json<RunRequest>,
+) -> Result<Json<Answer>, ApiError> {
+    tracing::info!("Received REST 'explain_code' request");
+    let options = request.options(OutputFormat::Markdown);
+    let scope = request.scope();
+    let call = agent.explain_code(&scope, &request.prompt, options);
+    Ok(Json(fairness::as_consumer(consumer(&headers, addr), call).await?))
+}
+
+async fn generate_patch(
+    State(agent): State<Agent>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Json(request): Json<RunRequest>,
+) -> Result<Json<Answer>, ApiError> {
+    tracing::info!("Received REST 'generate_patch' request");
+    let options = request.options(OutputFormat::Diff);
+    let scope = request.scope();
+    let call = agent.generate_patch(&scope, &request.prompt, options);
+    Ok(Json(fairness::as_consumer(consumer(&headers, addr), call).await?))
+}
+
+// Follow-ups count against the session, like MCP follow_up calls.
+async fn follow_up(State(agent): State<Agent>, Json(request): Json<FollowUpRequest>) -> Result<Json<FollowUpResponse>, ApiError> {
+    tracing::info!("Received REST 'follow_up' request");
+    let call = agent.follow_up(&request.session_id, &request.question);
+    let text = fairness::as_consumer(format!("session {}", request.session_id), call).await?;
+    Ok(Json(FollowUpResponse { text }))
+}
+
+// 503 when any check fails, for load balancers and orchestrators.
+async fn healthz(State(agent): State<Agent>) -> (StatusCode, Json<HealthReport>) {
+    let report = agent.cached_health().await;
+    let status = if report.healthy { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+    (status, Json(report))
+}
+
+async fn openapi_document() -> Json<serde_json::Value> {
+    Json(openapi())
+}
+
+// OpenAPI 3.0 description of the routes in `serve`, with body schemas
+// generated from the request and response types.
+pub fn openapi() -> serde_json::Value {
+    let mut generator = SchemaSettings::openapi3().into_generator();
+    let plan_request = generator.subschema_for::<PlanRequest>();
+    let run_request = generator.subschema_for::<RunRequest>();
+    let follow_up_request = generator.subschema_for::<FollowUpRequest>();
+    let plan_result = generator.subschema_for::<PlanResult>();
+    let answer = generator.subschema_for::<Answer>();
+    let follow_up_response = generator.subschema_for::<FollowUpResponse>();
+    let error = generator.subschema_for::<ErrorResponse>();
+    let health = generator.subschema_for::<HealthReport>();
+    let schemas = generator.take_definitions(true);
+
+    let operation = |summary: &str, request: &schemars::Schema, response: &schemars::Schema| {
+        json!({
+            "post": {
+                "summary": summary,
+                "requestBody": {
+                    "required": true,
+                    "content": { "application/json": { "schema": request } }
+                },
+                "responses": {
+                    "200": {
+                        "description": "Success",
+                        "content": { "application/json": { "schema": response } }
+                    },
+                    "default": {
+                        "description": "The request failed",
+                        "content": { "application/json": { "schema": error } }
+                    }
+                }
+            }
+        })
+    };
+
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "AI Code Agent",
+            "version": env!("CARGO_PKG_VERSION"),
+            "description": "Codebase analysis and planning with Gemini. Every call analyzes the codebase at 'directory' and can take minutes."
+        },
+        "paths": {
+            "/healthz": {
+                "get": {
+                    "summary": "Check codebase_viewer and every API key without a planning call",
+                    "responses": {
+                        "200": { "description": "Every check passed", "content": { "application/json": { "schema": health } } },
+                        "503": { "description": "A check failed", "content": { "application/json": { "schema": health } } }
+                    }
+                }
+            },
+            "/v1/plan_feature": operation("Plan a new feature", &plan_request, &plan_result),
+            "/v1/plan_bug_fix": operation("Plan a bug fix", &plan_request, &plan_result),
+            "/v1/explain_code": operation("Answer a question about the code", &run_request, &answer),
+            "/v1/generate_patch": operation("Generate a validated unified diff", &run_request, &answer),
+            "/v1/follow_up": operation("Continue a previous call's session", &follow_up_request, &follow_up_response)
+        },
+        "components": { "schemas": schemas }
+    })
+}
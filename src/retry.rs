@@ -0,0 +1,130 @@
+use crate::keys::ErrorClass;
+use anyhow::{bail, Result};
+use std::time::Duration;
+
+// Error classes retried by default: the ones that clear up on their own.
+// Unauthorized keys and rejected or malformed requests fail the same way
+// every time.
+pub const DEFAULT_RETRY_ON: [ErrorClass; 5] = [
+    ErrorClass::RateLimited,
+    ErrorClass::QuotaExhausted,
+    ErrorClass::Server,
+    ErrorClass::Network,
+    ErrorClass::Timeout,
+];
+
+// How failed model requests are retried (RETRY_* settings). Delays grow
+// exponentially from `base_delay` unless the provider says how long to wait.
+#[derive(Clone)]
+pub struct RetryPolicy {
+    // Attempts per model, including the first.
+    pub max_attempts: u32,
+    pub base_delay: Duration,
+    pub multiplier: f64,
+    // Each computed delay is moved by up to this fraction either way, so
+    // requests that failed together do not retry together.
+    pub jitter: f64,
+    // Computed delays are capped here, and a provider-specified delay longer
+    // than this (e.g. a daily quota reset) is not worth waiting for.
+    pub max_delay: Duration,
+    pub retry_on: Vec<ErrorClass>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 4,
+            base_delay: Duration::from_secs(10),
+            multiplier: 2.5,
+            jitter: 0.2,
+            max_delay: Duration::from_secs(300),
+            retry_on: DEFAULT_RETRY_ON.to_vec(),
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn validate(&self) -> Result<()> {
+        if self.max_attempts == 0 {
+            bail!("RETRY_MAX_ATTEMPTS must be at least 1");
+        }
+        if !(1.0..=f64::MAX).contains(&self.multiplier) {
+            bail!("RETRY_MULTIPLIER must be at least 1");
+        }
+        if !(0.0..=1.0).contains(&self.jitter) {
+            bail!("RETRY_JITTER must be between 0 and 1");
+        }
+        Ok(())
+    }
+
+    pub fn retries(&self, class: ErrorClass) -> bool {
+        self.retry_on.contains(&class)
+    }
+
+    // The wait before retry number `retry` (0 for the first), without a
+    // provider-specified delay.
+    pub fn delay(&self, retry: u32) -> Duration {
+        let exponential = self.base_delay.as_secs_f64() * self.multiplier.powi(retry as i32);
+        // uuid's v4 generator is the crate's only source of randomness.
+        let random = uuid::Uuid::new_v4().as_u128() as f64 / u128::MAX as f64;
+        let jittered = exponential * (1.0 + self.jitter * (2.0 * random - 1.0));
+        Duration::from_secs_f64(jittered.clamp(0.0, self.max_delay.as_secs_f64()))
+    }
+}
+
+// A comma-separated list of error class names for RETRY_ON, e.g.
+// `rate_limited,server,timeout`.
+pub fn parse_classes(spec: &str) -> Result<Vec<ErrorClass>> {
+    spec.split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(|name| match ErrorClass::ALL.into_iter().find(|class| class.name() == name) {
+            Some(class) => Ok(class),
+            None => {
+                let names: Vec<&str> = ErrorClass::ALL.iter().map(|c| c.name()).collect();
+                bail!("Unknown error class '{name}' in RETRY_ON; expected one of {}", names.join(", "))
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(jitter: f64) -> RetryPolicy {
+        RetryPolicy { base_delay: Duration::from_secs(2), multiplier: 3.0, jitter, max_delay: Duration::from_secs(60), ..RetryPolicy::default() }
+    }
+
+    #[test]
+    fn delays_grow_exponentially_up_to_the_cap() {
+        let policy = policy(0.0);
+        let delays: Vec<u64> = (0..5).map(|retry| policy.delay(retry).as_secs()).collect();
+        assert_eq!(delays, [2, 6, 18, 54, 60]);
+    }
+
+    #[test]
+    fn jitter_stays_within_its_fraction() {
+        let policy = policy(0.5);
+        for _ in 0..200 {
+            let delay = policy.delay(1).as_secs_f64();
+            assert!((3.0..=9.0).contains(&delay), "{delay}");
+        }
+    }
+
+    #[test]
+    fn validates_settings() {
+        assert!(RetryPolicy::default().validate().is_ok());
+        assert!(RetryPolicy { max_attempts: 0, ..RetryPolicy::default() }.validate().is_err());
+        assert!(RetryPolicy { multiplier: 0.5, ..RetryPolicy::default() }.validate().is_err());
+        assert!(RetryPolicy { multiplier: f64::NAN, ..RetryPolicy::default() }.validate().is_err());
+        assert!(RetryPolicy { jitter: 1.5, ..RetryPolicy::default() }.validate().is_err());
+    }
+
+    #[test]
+    fn parses_error_classes() {
+        assert_eq!(parse_classes("rate_limited, server,").unwrap(), [ErrorClass::RateLimited, ErrorClass::Server]);
+        assert!(parse_classes("server,flaky").unwrap_err().to_string().contains("Unknown error class 'flaky'"));
+        assert!(!RetryPolicy::default().retries(ErrorClass::Unauthorized));
+    }
+}
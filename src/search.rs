@@ -0,0 +1,182 @@
+use crate::code_index;
+use crate::files::SourceFile;
+use crate::ranking;
+use aho_corasick::{AhoCorasick, MatchKind};
+use std::collections::HashSet;
+
+// Identifiers searched for per request; long prompts such as incident logs
+// name many more than are worth a full-text search.
+const MAX_TERMS: usize = 20;
+
+// Words next to which a plain word in the prompt names a module.
+const MODULE_WORDS: &[&str] = &["module", "mod", "file", "package", "crate", "component", "service", "class"];
+
+// Keywords that directly precede the name they define.
+const DEFINITION_KEYWORDS: &[&str] = &[
+    "fn", "def", "class", "struct", "enum", "trait", "interface", "type", "func", "const", "static", "let", "var", "impl",
+    "mod", "module", "object", "record", "union", "macro_rules!",
+];
+
+// A file the prompt refers to, and how.
+pub struct Mentioned<'a> {
+    pub file: &'a SourceFile,
+    pub reason: String,
+}
+
+// Files the prompt refers to, most directly first: files named by path or
+// file name, then modules named as such, then files defining the code-like
+// identifiers it mentions, then files using the most of them. At most
+// `max_files` are returned.
+pub fn mentioned_files<'a>(files: &'a [SourceFile], prompt: &str, max_files: usize) -> Vec<Mentioned<'a>> {
+    let mut mentioned: Vec<Mentioned<'a>> = Vec::new();
+    let mut taken = vec![false; files.len()];
+    let mut take = |index: usize, reason: String, mentioned: &mut Vec<Mentioned<'a>>| {
+        if !taken[index] && mentioned.len() < max_files {
+            taken[index] = true;
+            mentioned.push(Mentioned { file: &files[index], reason });
+        }
+    };
+
+    // Named by path, possibly as the tail of an absolute path in a stack trace.
+    let tokens = path_tokens(prompt);
+    let mut named: Vec<(usize, usize)> = files
+        .iter()
+        .enumerate()
+        .filter_map(|(index, file)| {
+            let relative = file.path.to_string_lossy().replace('\\', "/");
+            tokens.iter().position(|token| names_path(token, &relative)).map(|position| (position, index))
+        })
+        .collect();
+    named.sort();
+    for (_, index) in named {
+        take(index, "named in the request".to_string(), &mut mentioned);
+    }
+
+    let modules = module_terms(prompt);
+    for (index, file) in files.iter().enumerate() {
+        if let Some(module) = ranking::module_name(&file.path).filter(|name| modules.contains(name)) {
+            take(index, format!("module `{module}` named in the request"), &mut mentioned);
+        }
+    }
+
+    let terms = code_terms(prompt);
+    if terms.is_empty() {
+        return mentioned;
+    }
+    let Ok(searcher) = AhoCorasick::builder().match_kind(MatchKind::LeftmostLongest).build(&terms) else {
+        return mentioned;
+    };
+    let hits: Vec<Hits> = files.iter().map(|file| search(&searcher, &file.content)).collect();
+
+    let mut defining: Vec<usize> = (0..files.len()).filter(|&i| !hits[i].defined.is_empty()).collect();
+    defining.sort_by_key(|&i| std::cmp::Reverse(hits[i].defined.len()));
+    for index in defining {
+        let defined: Vec<String> = hits[index].defined.iter().map(|&t| format!("`{}`", terms[t])).collect();
+        take(index, format!("defines {}", defined.join(", ")), &mut mentioned);
+    }
+
+    let mut using: Vec<usize> = (0..files.len()).filter(|&i| !hits[i].used.is_empty()).collect();
+    using.sort_by_key(|&i| std::cmp::Reverse((hits[i].used.len(), hits[i].occurrences)));
+    for index in using {
+        let used: Vec<String> = hits[index].used.iter().map(|&t| format!("`{}`", terms[t])).collect();
+        take(index, format!("uses {}", used.join(", ")), &mut mentioned);
+    }
+    mentioned
+}
+
+#[derive(Default)]
+struct Hits {
+    // Indices into the terms, in order of first occurrence.
+    defined: Vec<usize>,
+    used: Vec<usize>,
+    occurrences: usize,
+}
+
+// Whole-word occurrences of `terms` in `content`.
+fn search(searcher: &AhoCorasick, content: &str) -> Hits {
+    let mut hits = Hits::default();
+    let is_word = |c: char| c.is_alphanumeric() || c == '_';
+    for found in searcher.find_iter(content) {
+        let before = content[..found.start()].chars().next_back();
+        let after = content[found.end()..].chars().next();
+        if before.is_some_and(is_word) || after.is_some_and(is_word) {
+            continue;
+        }
+        hits.occurrences += 1;
+        let term = found.pattern().as_usize();
+        let line_start = content[..found.start()].rfind('\n').map_or(0, |i| i + 1);
+        let keyword = content[line_start..found.start()].split_whitespace().next_back().unwrap_or_default();
+        if DEFINITION_KEYWORDS.contains(&keyword) {
+            if !hits.defined.contains(&term) {
+                hits.defined.push(term);
+            }
+        } else if !hits.used.contains(&term) {
+            hits.used.push(term);
+        }
+    }
+    hits
+}
+
+// Words of the prompt that could be paths or file names, without the quotes,
+// brackets, and punctuation around them.
+fn path_tokens(prompt: &str) -> Vec<&str> {
+    prompt
+        .split(|c: char| c.is_whitespace() || "`'\"()[]{}<>,;".contains(c))
+        .map(|token| token.trim_end_matches([':', '.', '!', '?']))
+        .map(|token| token.split(':').next().unwrap_or(token))
+        .filter(|token| token.contains('.') || token.contains('/'))
+        .collect()
+}
+
+// Whether `token` names the file at `relative`: the whole path, a suffix of it
+// starting at a directory boundary (`server.rs`, `src/server.rs`), or an
+// absolute path ending in it.
+fn names_path(token: &str, relative: &str) -> bool {
+    let token = token.trim_start_matches("./").replace('\\', "/");
+    if token.is_empty() || !token.contains('.') {
+        return false;
+    }
+    token == relative
+        || token.ends_with(&format!("/{relative}"))
+        || relative.strip_suffix(token.as_str()).is_some_and(|rest| rest.ends_with('/'))
+}
+
+// Lowercased words next to a word such as "module" or "package", e.g.
+// "the config module" or "package billing".
+fn module_terms(prompt: &str) -> HashSet<String> {
+    let words: Vec<String> = prompt
+        .split(|c: char| !(c.is_alphanumeric() || c == '_'))
+        .filter(|w| !w.is_empty())
+        .map(str::to_lowercase)
+        .collect();
+    let mut terms = HashSet::new();
+    for (i, word) in words.iter().enumerate() {
+        if MODULE_WORDS.contains(&word.as_str()) {
+            let neighbors = [i.checked_sub(1), Some(i + 1)];
+            for neighbor in neighbors.into_iter().flatten().filter_map(|j| words.get(j)) {
+                if !MODULE_WORDS.contains(&neighbor.as_str()) && neighbor.len() > 2 {
+                    terms.insert(neighbor.clone());
+                }
+            }
+        }
+    }
+    terms
+}
+
+// Identifiers in the prompt that look like code rather than prose: quoted in
+// backticks, called, or written in snake_case, camelCase, or PascalCase with
+// an inner capital.
+fn code_terms(prompt: &str) -> Vec<String> {
+    let quoted: HashSet<&str> = prompt.split('`').skip(1).step_by(2).flat_map(code_index::identifiers).collect();
+    code_index::identifiers(prompt)
+        .into_iter()
+        .filter(|word| {
+            quoted.contains(word)
+                || prompt.contains(&format!("{word}("))
+                || word.trim_matches('_').contains('_')
+                || word.chars().skip(1).any(|c| c.is_uppercase()) && word.chars().any(|c| c.is_lowercase())
+        })
+        .take(MAX_TERMS)
+        .map(str::to_string)
+        .collect()
+}
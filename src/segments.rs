@@ -0,0 +1,125 @@
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::sync::Arc;
+
+// Bytes of escaped JSON per chunk of a streamed request body.
+const JSON_CHUNK_BYTES: usize = 64 * 1024;
+
+// Text assembled from shared pieces. A codebase report can run to hundreds of
+// megabytes; every message, retry, session, and background job that includes
+// it points at the same piece instead of holding a copy.
+#[derive(Clone, Default)]
+pub struct Segments {
+    // `Arc<String>` rather than `Arc<str>`: wrapping a String moves it
+    // instead of copying it into a new allocation.
+    parts: Vec<Arc<String>>,
+    len: usize,
+}
+
+impl Segments {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, text: impl Into<String>) {
+        let text = text.into();
+        if !text.is_empty() {
+            self.len += text.len();
+            self.parts.push(Arc::new(text));
+        }
+    }
+
+    // Shares `other`'s pieces rather than copying them.
+    pub fn append(&mut self, other: &Segments) {
+        self.len += other.len;
+        self.parts.extend(other.parts.iter().cloned());
+    }
+
+    // Length in bytes, as `String::len`.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn chars(&self) -> usize {
+        self.parts().map(|part| part.chars().count()).sum()
+    }
+
+    pub fn parts(&self) -> impl Iterator<Item = &str> {
+        self.parts.iter().map(|part| part.as_str())
+    }
+
+    // Same as `files::content_hash` of the joined text.
+    pub fn content_hash(&self) -> String {
+        let mut hasher = Sha256::new();
+        for part in self.parts() {
+            hasher.update(part.as_bytes());
+        }
+        format!("{:x}", hasher.finalize())
+    }
+
+    // Length of the text once escaped as a JSON string, without the quotes.
+    pub fn json_len(&self) -> usize {
+        self.parts().flat_map(str::bytes).map(|b| escaped(b).map_or(1, str::len)).sum()
+    }
+
+    // The text escaped as a JSON string (without the quotes), produced a
+    // chunk at a time so no escaped copy of the whole text is ever held.
+    pub fn json_chunks(&self) -> impl Iterator<Item = Vec<u8>> + Send + 'static {
+        self.parts.clone().into_iter().flat_map(|part| {
+            let chunks = part.len().div_ceil(JSON_CHUNK_BYTES);
+            (0..chunks).map(move |i| {
+                let bytes = &part.as_bytes()[i * JSON_CHUNK_BYTES..part.len().min((i + 1) * JSON_CHUNK_BYTES)];
+                let mut out = Vec::with_capacity(bytes.len() + bytes.len() / 8);
+                for &b in bytes {
+                    match escaped(b) {
+                        Some(escape) => out.extend_from_slice(escape.as_bytes()),
+                        None => out.push(b),
+                    }
+                }
+                out
+            })
+        })
+    }
+}
+
+impl fmt::Display for Segments {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.parts().try_for_each(|part| f.write_str(part))
+    }
+}
+
+impl From<String> for Segments {
+    fn from(text: String) -> Self {
+        let mut segments = Self::new();
+        segments.push(text);
+        segments
+    }
+}
+
+impl From<&str> for Segments {
+    fn from(text: &str) -> Self {
+        Self::from(text.to_string())
+    }
+}
+
+// JSON escape for a byte, or None when it is written as is. Every byte that
+// needs escaping is ASCII, so text can be escaped byte by byte and split at
+// any byte boundary.
+fn escaped(b: u8) -> Option<&'static str> {
+    const CONTROL: [&str; 32] = [
+        "\\u0000", "\\u0001", "\\u0002", "\\u0003", "\\u0004", "\\u0005", "\\u0006", "\\u0007",
+        "\\b", "\\t", "\\n", "\\u000b", "\\f", "\\r", "\\u000e", "\\u000f",
+        "\\u0010", "\\u0011", "\\u0012", "\\u0013", "\\u0014", "\\u0015", "\\u0016", "\\u0017",
+        "\\u0018", "\\u0019", "\\u001a", "\\u001b", "\\u001c", "\\u001d", "\\u001e", "\\u001f",
+    ];
+    match b {
+        b'"' => Some("\\\""),
+        b'\\' => Some("\\\\"),
+        0..=0x1f => Some(CONTROL[usize::from(b)]),
+        _ => None,
+    }
+}
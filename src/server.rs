@@ -1,110 +1,3097 @@
+use crate::apply;
+use crate::archive;
+use crate::audit;
+use crate::code_index;
 use crate::config::Config;
+use crate::embeddings;
+use crate::errors::{ErrorKind, ToolError};
 use crate::external;
+use crate::failed::FailedRequest;
+use crate::fairness;
+use crate::feasibility::{self, FeasibilityReport};
+use crate::files;
+use crate::forge::PublishTarget;
+use crate::gc;
+use crate::git;
+use crate::health::{self, HealthReport};
+use crate::hotspots;
+use crate::languages;
+use crate::llm::{self, CompactContext, GenerationParams, LlmError, PipelineEstimate, PipelineOptions, PipelinePreview, PipelinePrompts};
+use crate::manifests;
+use crate::mermaid;
+use crate::packages;
+use crate::patch;
+use crate::pipeline::{self, PinnedFiles, Pipeline};
+use crate::paths::{self, PathCheck};
+use crate::plan::{self, OutputFormat, StoredPlan, StructuredPlan, Verbosity};
+use crate::plans::SavedPlan;
+use crate::redact;
+use crate::references::{self, Reference};
+use crate::search;
+use crate::segments::Segments;
+use crate::session::Exchange;
+use crate::skeleton;
+use crate::spec;
+use crate::templates::{self, MessageTemplates};
+use crate::workspace::{self, Workspace};
+use crate::usage::{self, TokenUsage};
+use rmcp::handler::server::tool::ToolCallContext;
 use rmcp::handler::server::wrapper::Parameters;
-use rmcp::model::{ServerCapabilities, ServerInfo};
-use rmcp::{tool, tool_handler, tool_router, ServerHandler};
+use rmcp::model::{
+    AnnotateAble, CallToolRequestParam, CallToolResult, Content, ListResourcesResult, ListToolsResult, PaginatedRequestParam,
+    RawResource, ReadResourceRequestParam, ReadResourceResult, ResourceContents, ServerCapabilities, ServerInfo,
+};
+use rmcp::service::RequestContext;
+use rmcp::{tool, tool_router, ErrorData as McpError, RoleServer, ServerHandler};
 use schemars::JsonSchema;
-use serde::Deserialize;
-use std::path::PathBuf;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+#[derive(Serialize, Deserialize, JsonSchema, Default)]
+pub struct ContextParams {
+    #[schemars(description = "Send only the files most relevant to the prompt instead of the full codebase report: those defining or using the symbols it names, from the repository's ctags/LSIF/SCIP index if it has one, otherwise selected via embeddings. Use for codebases too large for the token limit.")]
+    pub use_retrieval: Option<bool>,
+    #[schemars(description = "Run a preliminary pass where Gemini sees only the file tree and picks the files it needs; only those files' contents are sent. Cuts token usage substantially. Defaults to the server's FILE_SELECTION_TOOLS setting for this tool.")]
+    pub file_selection: Option<bool>,
+    #[schemars(description = "Send a skeleton of the codebase instead of the full report: the declarations of every file (function and method signatures, types, fields, and constants, parsed with tree-sitter for Rust, Python, JavaScript, TypeScript, Go, and Java) with their line numbers, plus the full contents of the files that define symbols named in the request. Typically an order of magnitude smaller than the full report. Takes precedence over file_selection; ignored with use_retrieval.")]
+    pub skeleton: Option<bool>,
+    #[schemars(description = "Analyze the git repository as of this commit (SHA, tag, or branch) instead of the working tree, e.g. to explain how something worked before a rewrite or to compare against the last known-good commit. Checked out into a temporary worktree that is removed afterwards. Not supported for archives.")]
+    pub commit: Option<String>,
+    #[schemars(description = "Additional codebase directories (absolute paths or archives) to analyze together with 'directory', e.g. the frontend repository when 'directory' is the backend. Each directory gets its own labeled report and an equal share of the token limit. 'commit', diff validation, compact_detail, and change tracking apply to 'directory' only.")]
+    pub directories: Option<Vec<String>>,
+    #[schemars(description = "Copy the directory (minus ignored files) to fast local storage before analysis, for directories on slow network filesystems (NFS/SMB). The source is read once and never written. Defaults to the server's MIRROR_DIRECTORIES setting.")]
+    pub mirror: Option<bool>,
+    #[schemars(description = "Also return every `path:line` code reference in the output as structured content (path, line, end_line, and whether it was verified against the actual files), for editor clients that turn references into clickable jumps.")]
+    pub references: Option<bool>,
+    #[schemars(description = "Number of LLM passes: 1 = single pass straight to the detailed answer (cheapest), 2 = analysis then detail (default), 3 = adds a critic pass that reviews and corrects the detailed answer against the codebase.")]
+    pub passes: Option<u8>,
+    #[schemars(description = "Generate this many independent high-level analyses (1-5, default 1), then have a judging pass select or merge the best before the detail pass. Trades tokens and latency for plan quality: each candidate and the judge re-send the context. Needs passes 2 or 3.")]
+    pub candidates: Option<u8>,
+    #[schemars(description = "Add a verification pass after the final response: the model checks it against the full codebase context (referenced files exist, APIs and signatures match, steps are in a workable order) and its findings are appended as a '## Corrections' section. Costs one more pass over the context. Markdown output only.")]
+    pub verify: Option<bool>,
+    #[schemars(description = "Token-efficient mode: after the analysis pass, later passes receive the analysis plus only the files it referenced (re-read from disk) instead of the full context again. Roughly halves token usage per call.")]
+    pub compact_detail: Option<bool>,
+    #[schemars(description = "How much detail the final answer gives: 'summary' (key decisions, affected files, order of work, and risks as short bullets, little or no code, output capped at about 4k tokens; for architects reviewing an approach), 'standard' (default), or 'exhaustive' (complete code for every file and step, call sites, edge cases, and tests, with a larger output budget; for implementers). Adjusts the final pass only; the analysis is the same. Not available with diff output.")]
+    pub verbosity: Option<Verbosity>,
+    #[schemars(description = "Generation parameters for this call: 'temperature', 'top_p', 'max_tokens', and 'stop' sequences. Temperature and top_p apply to every pass; max_tokens and stop only to the passes that write the response. Fields left out fall back to the server's per-tool (TOOL_GENERATION_PARAMS) and global (GENERATION_*) settings, then the provider's defaults.")]
+    pub generation: Option<GenerationParams>,
+    #[schemars(description = "Redact embedded data before anything is sent: string literal contents become '…' (import paths and JSON keys are kept) and numeric literals of three or more digits become 0, keeping the code's structure, identifiers, and comments. For codebases whose business data must not reach the model provider. Not available with diff output, whose hunks must match the original files. Always on when the server sets REDACT_LITERALS.")]
+    pub redact_literals: Option<bool>,
+    #[schemars(description = "Hard cap on the tokens this call may use across all passes (prompt and output). The context is counted and room reserved for each pass before anything is sent; over budget, the context is shrunk to fit or the call is refused with a breakdown of the estimate. Defaults to the server's MAX_TOTAL_TOKENS_PER_CALL setting, if any.")]
+    pub max_total_tokens: Option<u64>,
+    #[schemars(description = "Size limit of the codebase context for this call, in tokens (about 4 characters each), instead of the server's TOKEN_CHAR_LIMIT: raise it for a one-off large analysis or lower it for a cheap quick question. Bounded by the server's MAX_TOKEN_CHAR_LIMIT; calls above it are refused.")]
+    pub token_limit: Option<usize>,
+    #[schemars(description = "When the context does not fit 'max_total_tokens', cut it down to fit (default true) instead of refusing the call.")]
+    pub shrink_to_budget: Option<bool>,
+    #[schemars(description = "Before returning a markdown or JSON plan, run static feasibility checks: dependencies the plan adds must exist on crates.io/npm/PyPI with the requested version, and library APIs its code uses must exist in the project's locked versions (Cargo.lock, node_modules). Infeasible items and the steps that rely on them are reported with the plan. Makes no model calls.")]
+    pub verify_feasibility: Option<bool>,
+    #[schemars(description = "Build the context and return the exact system and user prompts each pass would send, with context size statistics, instead of calling Gemini. For inspecting what the model sees and debugging truncation. File selection and retrieval still make their API calls, since they decide the context.")]
+    pub dry_run: Option<bool>,
+    #[schemars(description = "Publish the result when it is ready: as a comment on a GitHub, GitLab, or Bitbucket issue, pull request, or merge request, or (GitHub only) as a pull request's description or the description of a new draft pull request. Needs the server's GITHUB_TOKEN, GITLAB_TOKEN, or BITBUCKET_TOKEN. The published URL is returned with the result; a failed publish is reported but does not fail the call.")]
+    pub publish: Option<PublishTarget>,
+}
 
 #[derive(Deserialize, JsonSchema)]
 pub struct FeatureParams {
-    #[schemars(description = "Full absolute path to the codebase directory (e.g., /workspace/myapp or C:/projects/myapp). Must NOT be a relative path.")]
+    #[schemars(description = "Full absolute path to the codebase directory (e.g., /workspace/myapp or C:/projects/myapp), to a .zip/.tar.gz/.tgz/.tar archive of one, or a git URL (e.g., https://github.com/org/repo#branch). Must NOT be a relative path.")]
+    pub directory: String,
+    pub feature_prompt: String,
+    #[schemars(description = "Jira issue key (e.g. PROJ-123) or browse URL. The ticket's summary, description, and acceptance criteria are fetched from the server's JIRA_URL and put before 'feature_prompt', which may then be empty or add notes.")]
+    pub ticket: Option<String>,
+    #[schemars(description = "Output format: 'markdown' (default), 'json', or 'diff'. JSON returns a validated structured plan (summary, file_changes with path/change_type/description/code_snippets, ordered steps, risks) as structured content. Diff returns a unified diff against the actual files, checked hunk-by-hunk to confirm it applies cleanly.")]
+    pub output_format: Option<OutputFormat>,
+    #[serde(flatten)]
+    pub context: ContextParams,
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct FeatureOutlineParams {
+    #[schemars(description = "Full absolute path to the codebase directory (e.g., /workspace/myapp or C:/projects/myapp), to a .zip/.tar.gz/.tgz/.tar archive of one, or a git URL (e.g., https://github.com/org/repo#branch). Must NOT be a relative path.")]
+    pub directory: String,
+    pub feature_prompt: String,
+    #[schemars(description = "Jira issue key (e.g. PROJ-123) or browse URL. The ticket's summary, description, and acceptance criteria are fetched from the server's JIRA_URL and put before 'feature_prompt', which may then be empty or add notes.")]
+    pub ticket: Option<String>,
+    #[serde(flatten)]
+    pub context: ContextParams,
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct FeatureDetailsParams {
+    #[schemars(description = "Full absolute path to the codebase directory (e.g., /workspace/myapp or C:/projects/myapp), to a .zip/.tar.gz/.tgz/.tar archive of one, or a git URL (e.g., https://github.com/org/repo#branch). Must NOT be a relative path.")]
     pub directory: String,
+    #[schemars(description = "The feature request the outline was made for.")]
     pub feature_prompt: String,
+    #[schemars(description = "The high-level plan to detail, typically from plan_feature_outline and edited by the requester. It is followed as given.")]
+    pub outline: String,
+    #[schemars(description = "Output format: 'markdown' (default), 'json', or 'diff'. JSON returns a validated structured plan (summary, file_changes with path/change_type/description/code_snippets, ordered steps, risks) as structured content. Diff returns a unified diff against the actual files, checked hunk-by-hunk to confirm it applies cleanly.")]
+    pub output_format: Option<OutputFormat>,
+    #[serde(flatten)]
+    pub context: ContextParams,
 }
 
 #[derive(Deserialize, JsonSchema)]
 pub struct BugFixParams {
-    #[schemars(description = "Full absolute path to the codebase directory (e.g., /workspace/myapp or C:/projects/myapp). Must NOT be a relative path.")]
+    #[schemars(description = "Full absolute path to the codebase directory (e.g., /workspace/myapp or C:/projects/myapp), to a .zip/.tar.gz/.tgz/.tar archive of one, or a git URL (e.g., https://github.com/org/repo#branch). Must NOT be a relative path.")]
     pub directory: String,
     pub bug_description: String,
+    #[schemars(description = "Append the recent git history of the files involved to the bug description for the root cause analysis: each file's last commits with their diffs (git log -p) and the lines those commits last changed (git blame). The files are 'history_files' if given, else the files the description mentions (e.g. in a stack trace), else the files a preliminary file selection pass picks. Needs a git checkout; not available with redacted literals.")]
+    pub history: Option<bool>,
+    #[schemars(description = "Files (relative to 'directory') whose git history to append, e.g. ['src/billing/retry.rs']. Implies 'history'.")]
+    pub history_files: Option<Vec<String>>,
+    #[schemars(description = "Output format: 'markdown' (default), 'json', or 'diff'. JSON returns a validated structured plan (summary, file_changes with path/change_type/description/code_snippets, ordered steps, risks) as structured content. Diff returns a unified diff against the actual files, checked hunk-by-hunk to confirm it applies cleanly.")]
+    pub output_format: Option<OutputFormat>,
+    #[serde(flatten)]
+    pub context: ContextParams,
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct IssueBugFixParams {
+    #[schemars(description = "Full absolute path to the codebase directory (e.g., /workspace/myapp or C:/projects/myapp), to a .zip/.tar.gz/.tgz/.tar archive of one, or a git URL (e.g., https://github.com/org/repo#branch). Must NOT be a relative path.")]
+    pub directory: String,
+    #[schemars(description = "URL of the GitHub, GitLab, or Bitbucket issue describing the bug, e.g. https://github.com/org/repo/issues/123, https://gitlab.com/group/project/-/issues/45, or https://bitbucket.org/workspace/repo/issues/6.")]
+    pub issue_url: String,
+    #[schemars(description = "Extra notes appended to the issue, e.g. a stack trace you reproduced locally or which part of the report to focus on.")]
+    pub notes: Option<String>,
+    #[schemars(description = "Append the recent git history of the files involved, as for plan_bug_fix.")]
+    pub history: Option<bool>,
+    #[schemars(description = "Files (relative to 'directory') whose git history to append, as for plan_bug_fix. Implies 'history'.")]
+    pub history_files: Option<Vec<String>>,
+    #[schemars(description = "Output format: 'markdown' (default), 'json', or 'diff', as for plan_bug_fix.")]
+    pub output_format: Option<OutputFormat>,
+    #[serde(flatten)]
+    pub context: ContextParams,
 }
 
 #[derive(Deserialize, JsonSchema)]
 pub struct ExplanationParams {
-    #[schemars(description = "Full absolute path to the codebase directory (e.g., /workspace/myapp or C:/projects/myapp). Must NOT be a relative path.")]
+    #[schemars(description = "Full absolute path to the codebase directory (e.g., /workspace/myapp or C:/projects/myapp), to a .zip/.tar.gz/.tgz/.tar archive of one, or a git URL (e.g., https://github.com/org/repo#branch). Must NOT be a relative path.")]
     pub directory: String,
     pub explanation_query: String,
+    #[serde(flatten)]
+    pub context: ContextParams,
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct ArchitectureParams {
+    #[schemars(description = "Full absolute path to the codebase directory (e.g., /workspace/myapp or C:/projects/myapp), to a .zip/.tar.gz/.tgz/.tar archive of one, or a git URL (e.g., https://github.com/org/repo#branch). Must NOT be a relative path.")]
+    pub directory: String,
+    #[schemars(description = "Optional flows or area to emphasize, e.g. 'the checkout flow' or 'how jobs move through the queue'. Defaults to the overall architecture.")]
+    pub focus: Option<String>,
+    #[serde(flatten)]
+    pub context: ContextParams,
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct GraphqlSchemaParams {
+    #[schemars(description = "Full absolute path to the codebase directory (e.g., /workspace/myapp or C:/projects/myapp), to a .zip/.tar.gz/.tgz/.tar archive of one, or a git URL (e.g., https://github.com/org/repo#branch). Must NOT be a relative path.")]
+    pub directory: String,
+    #[schemars(description = "The schema change to plan, e.g. 'add a paginated orders field to User and deprecate User.recentOrders'")]
+    pub schema_change: String,
+    #[schemars(description = "Output format: 'markdown' (default), 'json', or 'diff'. JSON returns a validated structured plan (summary, file_changes with path/change_type/description/code_snippets, ordered steps, risks) as structured content. Diff returns a unified diff against the actual files, checked hunk-by-hunk to confirm it applies cleanly.")]
+    pub output_format: Option<OutputFormat>,
+    #[serde(flatten)]
+    pub context: ContextParams,
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct ZeroDowntimeDeployParams {
+    #[schemars(description = "Full absolute path to the codebase directory (e.g., /workspace/myapp or C:/projects/myapp), to a .zip/.tar.gz/.tgz/.tar archive of one, or a git URL (e.g., https://github.com/org/repo#branch). Must NOT be a relative path.")]
+    pub directory: String,
+    #[schemars(description = "The change to roll out, e.g. 'rename the users.email column to users.primary_email' or 'split the orders service out of the monolith'")]
+    pub change_description: String,
+    #[schemars(description = "Output format: 'markdown' (default), 'json', or 'diff'. JSON returns a validated structured plan (summary, file_changes with path/change_type/description/code_snippets, ordered steps, risks) as structured content. Diff returns a unified diff against the actual files, checked hunk-by-hunk to confirm it applies cleanly.")]
+    pub output_format: Option<OutputFormat>,
+    #[serde(flatten)]
+    pub context: ContextParams,
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct EventDrivenMigrationParams {
+    #[schemars(description = "Full absolute path to the codebase directory (e.g., /workspace/myapp or C:/projects/myapp), to a .zip/.tar.gz/.tgz/.tar archive of one, or a git URL (e.g., https://github.com/org/repo#branch). Must NOT be a relative path.")]
+    pub directory: String,
+    #[schemars(description = "Optional call paths or goals to focus on, e.g. 'order placement calling inventory and email synchronously'. Defaults to identifying the best candidates.")]
+    pub scope: Option<String>,
+    #[schemars(description = "Output format: 'markdown' (default), 'json', or 'diff'. JSON returns a validated structured plan (summary, file_changes with path/change_type/description/code_snippets, ordered steps, risks) as structured content. Diff returns a unified diff against the actual files, checked hunk-by-hunk to confirm it applies cleanly.")]
+    pub output_format: Option<OutputFormat>,
+    #[serde(flatten)]
+    pub context: ContextParams,
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct TelemetryMigrationParams {
+    #[schemars(description = "Full absolute path to the codebase directory (e.g., /workspace/myapp or C:/projects/myapp), to a .zip/.tar.gz/.tgz/.tar archive of one, or a git URL (e.g., https://github.com/org/repo#branch). Must NOT be a relative path.")]
+    pub directory: String,
+    #[schemars(description = "The observability migration to plan, e.g. 'log4j to slf4j with logback' or 'our custom metrics registry to OpenTelemetry metrics'")]
+    pub migration: String,
+    #[schemars(description = "Output format: 'markdown' (default), 'json', or 'diff'. JSON returns a validated structured plan (summary, file_changes with path/change_type/description/code_snippets, ordered steps, risks) as structured content. Diff returns a unified diff against the actual files, checked hunk-by-hunk to confirm it applies cleanly.")]
+    pub output_format: Option<OutputFormat>,
+    #[serde(flatten)]
+    pub context: ContextParams,
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct SdkClientParams {
+    #[schemars(description = "Full absolute path to the codebase directory (e.g., /workspace/myapp or C:/projects/myapp), to a .zip/.tar.gz/.tgz/.tar archive of one, or a git URL (e.g., https://github.com/org/repo#branch). Must NOT be a relative path.")]
+    pub directory: String,
+    #[schemars(description = "Target language (and optionally runtime/framework) for the SDK, e.g. 'TypeScript (Node and browser)', 'Python 3.11 with httpx', 'Go'.")]
+    pub language: String,
+    #[schemars(description = "Optional additional requirements, e.g. 'only the billing endpoints', 'async-only', 'must support custom HTTP clients'.")]
+    pub requirements: Option<String>,
+    #[schemars(description = "Output format: 'markdown' (default), 'json', or 'diff'. JSON returns a validated structured plan (summary, file_changes with path/change_type/description/code_snippets, ordered steps, risks) as structured content. Diff returns a unified diff against the actual files, checked hunk-by-hunk to confirm it applies cleanly.")]
+    pub output_format: Option<OutputFormat>,
+    #[serde(flatten)]
+    pub context: ContextParams,
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct MockDataParams {
+    #[schemars(description = "Full absolute path to the codebase directory (e.g., /workspace/myapp or C:/projects/myapp), to a .zip/.tar.gz/.tgz/.tar archive of one, or a git URL (e.g., https://github.com/org/repo#branch). Must NOT be a relative path.")]
+    pub directory: String,
+    #[schemars(description = "Optional entities or scenario to cover, e.g. 'orders with line items and refunds' or 'a demo tenant with 50 users'. Defaults to every model.")]
+    pub scope: Option<String>,
+    #[schemars(description = "Output format: 'markdown' (default), 'json', or 'diff'. JSON returns a validated structured plan (summary, file_changes with path/change_type/description/code_snippets, ordered steps, risks) as structured content. Diff returns a unified diff against the actual files, checked hunk-by-hunk to confirm it applies cleanly.")]
+    pub output_format: Option<OutputFormat>,
+    #[serde(flatten)]
+    pub context: ContextParams,
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct MigrationTestsParams {
+    #[schemars(description = "Full absolute path to the codebase directory (e.g., /workspace/myapp or C:/projects/myapp), to a .zip/.tar.gz/.tgz/.tar archive of one, or a git URL (e.g., https://github.com/org/repo#branch). Must NOT be a relative path.")]
+    pub directory: String,
+    #[schemars(description = "The migration to test: the path or name of an existing migration (e.g. 'migrations/20240501_split_user_names.sql'), or a description or the SQL of a planned one (e.g. 'split users.name into first_name and last_name, backfilling existing rows'). Defaults to the most recent migration in the codebase.")]
+    pub migration: Option<String>,
+    #[schemars(description = "Output format: 'markdown' (default), 'json', or 'diff'. JSON returns a validated structured plan (summary, file_changes with path/change_type/description/code_snippets, ordered steps, risks) as structured content. Diff returns a unified diff against the actual files, checked hunk-by-hunk to confirm it applies cleanly.")]
+    pub output_format: Option<OutputFormat>,
+    #[serde(flatten)]
+    pub context: ContextParams,
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct RequirementsParams {
+    #[schemars(description = "Full absolute path to the codebase directory (e.g., /workspace/myapp or C:/projects/myapp), to a .zip/.tar.gz/.tgz/.tar archive of one, or a git URL (e.g., https://github.com/org/repo#branch). Must NOT be a relative path.")]
+    pub directory: String,
+    #[schemars(description = "Optional area to focus the specification on (e.g., 'billing and invoicing'). Defaults to the whole system.")]
+    pub scope: Option<String>,
+    #[serde(flatten)]
+    pub context: ContextParams,
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct RfcParams {
+    #[schemars(description = "Full absolute path to the codebase directory (e.g., /workspace/myapp or C:/projects/myapp), to a .zip/.tar.gz/.tgz/.tar archive of one, or a git URL (e.g., https://github.com/org/repo#branch). Must NOT be a relative path.")]
+    pub directory: String,
+    pub feature_prompt: String,
+    #[schemars(description = "The team's RFC template as markdown (headings, front matter, and any instructions in it). The RFC follows it exactly. Defaults to an RFC template found in the codebase (e.g. rfcs/0000-template.md), else a standard structure: summary, problem statement, goals/non-goals, detailed design, alternatives, rollout, risks, unresolved questions.")]
+    pub template: Option<String>,
+    #[serde(flatten)]
+    pub context: ContextParams,
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct PatchParams {
+    #[schemars(description = "Full absolute path to the codebase directory (e.g., /workspace/myapp or C:/projects/myapp), to a .zip/.tar.gz/.tgz/.tar archive of one, or a git URL (e.g., https://github.com/org/repo#branch). Must NOT be a relative path.")]
+    pub directory: String,
+    #[schemars(description = "The change to make, as specifically as possible (e.g., 'make parse_config return an error instead of panicking on a missing key').")]
+    pub change_request: String,
+    #[serde(flatten)]
+    pub context: ContextParams,
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct DependencyAuditParams {
+    #[schemars(description = "Full absolute path to the codebase directory (e.g., /workspace/myapp or C:/projects/myapp), to a .zip/.tar.gz/.tgz/.tar archive of one, or a git URL (e.g., https://github.com/org/repo#branch). Must NOT be a relative path.")]
+    pub directory: String,
+    #[schemars(description = "Optional concern to focus on, e.g. 'preparing the upgrade to tokio 2' or 'reducing bundle size'.")]
+    pub focus: Option<String>,
+    #[serde(flatten)]
+    pub context: ContextParams,
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct MultitenancyParams {
+    #[schemars(description = "Full absolute path to the codebase directory (e.g., /workspace/myapp or C:/projects/myapp), to a .zip/.tar.gz/.tgz/.tar archive of one, or a git URL (e.g., https://github.com/org/repo#branch). Must NOT be a relative path.")]
+    pub directory: String,
+    #[schemars(description = "Optional isolation strategy or requirements, e.g. 'shared schema with Postgres row-level security', 'database per tenant for enterprise customers', or 'tenants resolved from the subdomain'. Defaults to recommending a strategy.")]
+    pub requirements: Option<String>,
+    #[schemars(description = "Output format: 'markdown' (default), 'json', or 'diff'. JSON returns a validated structured plan (summary, file_changes with path/change_type/description/code_snippets, ordered steps, risks) as structured content. Diff returns a unified diff against the actual files, checked hunk-by-hunk to confirm it applies cleanly.")]
+    pub output_format: Option<OutputFormat>,
+    #[serde(flatten)]
+    pub context: ContextParams,
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct RateLimitingParams {
+    #[schemars(description = "Full absolute path to the codebase directory (e.g., /workspace/myapp or C:/projects/myapp), to a .zip/.tar.gz/.tgz/.tar archive of one, or a git URL (e.g., https://github.com/org/repo#branch). Must NOT be a relative path.")]
+    pub directory: String,
+    #[schemars(description = "Optional constraints or goals, e.g. 'per API key, 100 requests per minute for paid plans', 'Redis is available', or 'protect the login and export endpoints'. Defaults to recommending budgets for every route.")]
+    pub requirements: Option<String>,
+    #[schemars(description = "Output format: 'markdown' (default), 'json', or 'diff'. JSON returns a validated structured plan (summary, file_changes with path/change_type/description/code_snippets, ordered steps, risks) as structured content. Diff returns a unified diff against the actual files, checked hunk-by-hunk to confirm it applies cleanly.")]
+    pub output_format: Option<OutputFormat>,
+    #[serde(flatten)]
+    pub context: ContextParams,
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct AuthOverhaulParams {
+    #[schemars(description = "Full absolute path to the codebase directory (e.g., /workspace/myapp or C:/projects/myapp), to a .zip/.tar.gz/.tgz/.tar archive of one, or a git URL (e.g., https://github.com/org/repo#branch). Must NOT be a relative path.")]
+    pub directory: String,
+    #[schemars(description = "The authentication or authorization change, e.g. 'add SSO through Okta via SAML for enterprise customers', 'migrate from session cookies to OAuth2/OIDC with Auth0', or 'introduce RBAC with admin, editor, and viewer roles'.")]
+    pub change: String,
+    #[schemars(description = "Output format: 'markdown' (default), 'json', or 'diff'. JSON returns a validated structured plan (summary, file_changes with path/change_type/description/code_snippets, ordered steps, risks) as structured content. Diff returns a unified diff against the actual files, checked hunk-by-hunk to confirm it applies cleanly.")]
+    pub output_format: Option<OutputFormat>,
+    #[serde(flatten)]
+    pub context: ContextParams,
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct PromptReviewParams {
+    #[schemars(description = "Full absolute path to the codebase directory (e.g., /workspace/myapp or C:/projects/myapp), to a .zip/.tar.gz/.tgz/.tar archive of one, or a git URL (e.g., https://github.com/org/repo#branch). Must NOT be a relative path.")]
+    pub directory: String,
+    #[schemars(description = "Optional concern to focus on, e.g. 'prompt injection through retrieved documents' or 'reducing token spend'.")]
+    pub focus: Option<String>,
+    #[serde(flatten)]
+    pub context: ContextParams,
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct DocumentCodeParams {
+    #[schemars(description = "Full absolute path to the codebase directory (e.g., /workspace/myapp or C:/projects/myapp), to a .zip/.tar.gz/.tgz/.tar archive of one, or a git URL (e.g., https://github.com/org/repo#branch). Must NOT be a relative path.")]
+    pub directory: String,
+    #[schemars(description = "Optional area to document (e.g., 'the storage module' or 'public API only'). Defaults to the whole codebase.")]
+    pub scope: Option<String>,
+    #[schemars(description = "Write the documentation into the files (doc comments inserted in place, README.md or README.draft.md created) instead of returning markdown. Only supported for plain directories at the working tree, not archives or 'commit'.")]
+    pub write: Option<bool>,
+    #[serde(flatten)]
+    pub context: ContextParams,
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct TechDebtParams {
+    #[schemars(description = "Full absolute path to the codebase directory (e.g., /workspace/myapp or C:/projects/myapp), to a .zip/.tar.gz/.tgz/.tar archive of one, or a git URL (e.g., https://github.com/org/repo#branch). Must NOT be a relative path.")]
+    pub directory: String,
+    #[schemars(description = "How many days of git history to count commits over (default 365). Ignored when the directory is not a git checkout.")]
+    pub since_days: Option<u32>,
+    #[schemars(description = "Optional area or concern to focus on, e.g. 'the payments module' or 'error handling'.")]
+    pub focus: Option<String>,
+    #[serde(flatten)]
+    pub context: ContextParams,
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct IssueInput {
+    #[schemars(description = "Issue identifier used to refer to it in the report (e.g., '#123' or 'PROJ-45'). Defaults to its position in the list.")]
+    pub id: Option<String>,
+    pub title: Option<String>,
+    #[schemars(description = "Issue text: description, reproduction steps, error messages, logs.")]
+    pub body: String,
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct IncidentReviewParams {
+    #[schemars(description = "Full absolute path to the codebase directory (e.g., /workspace/myapp or C:/projects/myapp), to a .zip/.tar.gz/.tgz/.tar archive of one, or a git URL (e.g., https://github.com/org/repo#branch). Must NOT be a relative path.")]
+    pub directory: String,
+    #[schemars(description = "The incident record: a timeline with timestamps, relevant log lines and stack traces, alerts that fired, and responders' notes. Include times and exact error messages where available.")]
+    pub incident: String,
+    #[schemars(description = "Optional known impact or scope, e.g. 'checkout failed for 40% of EU users for 35 minutes'.")]
+    pub impact: Option<String>,
+    #[serde(flatten)]
+    pub context: ContextParams,
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct SpofParams {
+    #[schemars(description = "Full absolute path to the codebase directory (e.g., /workspace/myapp or C:/projects/myapp), to a .zip/.tar.gz/.tgz/.tar archive of one, or a git URL (e.g., https://github.com/org/repo#branch). Must NOT be a relative path.")]
+    pub directory: String,
+    #[schemars(description = "Optional area or concern to focus on, e.g. 'the checkout path' or 'what happens when Redis is down'.")]
+    pub focus: Option<String>,
+    #[serde(flatten)]
+    pub context: ContextParams,
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct SummarizeChangesParams {
+    #[schemars(description = "Full absolute path to a local git checkout (e.g., /workspace/myapp). Only changes under this directory are summarized. Archives and git URLs have no history and are not supported.")]
+    pub directory: String,
+    #[schemars(description = "Git revision range, e.g. 'v1.4.0..HEAD', 'main...feature/login', or a single revision such as 'v1.4.0', meaning everything since it.")]
+    pub range: String,
+    #[schemars(description = "Optional version for the changelog heading, e.g. '1.5.0'. Defaults to 'Unreleased'.")]
+    pub version: Option<String>,
+    #[serde(flatten)]
+    pub context: ContextParams,
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct ClusterIssuesParams {
+    #[schemars(description = "Full absolute path to the codebase directory (e.g., /workspace/myapp or C:/projects/myapp), to a .zip/.tar.gz/.tgz/.tar archive of one, or a git URL (e.g., https://github.com/org/repo#branch). Must NOT be a relative path.")]
+    pub directory: String,
+    #[schemars(description = "The issues to triage, typically the open issues from a tracker.")]
+    pub issues: Vec<IssueInput>,
+    #[serde(flatten)]
+    pub context: ContextParams,
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct FollowUpParams {
+    #[schemars(description = "Session ID returned by a previous tool call.")]
+    pub session_id: String,
+    #[schemars(description = "The follow-up question or instruction, e.g. 'now explain the second component in more depth'.")]
+    pub question: String,
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct QuestionAnswer {
+    #[schemars(description = "ID of the open question, e.g. 'Q1' (questions are numbered in the order the plan lists them).")]
+    pub id: String,
+    pub answer: String,
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct AnswerQuestionsParams {
+    #[schemars(description = "Session ID returned by the planning tool whose open questions are being answered.")]
+    pub session_id: String,
+    #[schemars(description = "Answers to some or all of the plan's open questions. Unanswered questions stay open in the finalized plan.")]
+    pub answers: Vec<QuestionAnswer>,
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct ReplayFailedParams {
+    #[schemars(description = "ID of a queued failed request to re-run. Omit to list the queue.")]
+    pub id: Option<String>,
+    #[schemars(description = "Re-run every queued request in order. Ignored when 'id' is given.")]
+    pub all: Option<bool>,
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct GcParams {
+    #[schemars(description = "Report what would be removed without deleting anything. Defaults to false.")]
+    pub dry_run: Option<bool>,
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct ListPlansParams {
+    #[schemars(description = "Only list plans from this tool, e.g. 'plan_feature'.")]
+    pub tool: Option<String>,
+    #[schemars(description = "Only list plans for this directory, exactly as it was passed to the planning tool.")]
+    pub directory: Option<String>,
+    #[schemars(description = "Maximum number of plans to list, newest first (default 20).")]
+    pub limit: Option<usize>,
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct GetPlanParams {
+    #[schemars(description = "Plan ID returned by a planning tool or listed by list_plans.")]
+    pub plan_id: String,
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct RatePlanParams {
+    #[schemars(description = "ID of the saved plan to rate (from a planning tool's output or list_plans).")]
+    pub plan_id: String,
+    #[schemars(description = "1 (unusable) to 5 (implemented as written, no corrections needed).")]
+    pub score: u8,
+    #[schemars(description = "What was wrong or missing, or what made the plan useful.")]
+    pub comment: Option<String>,
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct VerifyFeasibilityParams {
+    #[schemars(description = "Full absolute path to the codebase directory the plan was generated for; its manifests, Cargo.lock, and node_modules are what the plan is checked against. Must NOT be a relative path.")]
+    pub directory: String,
+    #[schemars(description = "ID of a saved plan (from a planning tool's output or list_plans). Provide this or 'plan'.")]
+    pub plan_id: Option<String>,
+    #[schemars(description = "Plan text, markdown or JSON, e.g. one written by hand or by another tool. Provide this or 'plan_id'.")]
+    pub plan: Option<String>,
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct ApplyPlanParams {
+    #[schemars(description = "Full absolute path to the codebase directory the plan was generated for. Must NOT be a relative path.")]
+    pub directory: String,
+    #[schemars(description = "ID of a plan previously returned by a planning tool with output_format 'json'. Provide this or 'plan'.")]
+    pub plan_id: Option<String>,
+    #[schemars(description = "A structured plan object (as returned with output_format 'json'). Provide this or 'plan_id'.")]
+    pub plan: Option<StructuredPlan>,
+    #[schemars(description = "Confirmation token from a previous dry run of this plan. Omit to perform a dry run that returns the diff and a token; pass the token to write the changes. Writing is refused if the diff changed since the dry run.")]
+    pub confirm_token: Option<String>,
+}
+
+struct ToolRequest<'a> {
+    tool: &'static str,
+    prompts: &'a PipelinePrompts,
+    directory: &'a str,
+    prompt: &'a str,
+    context: &'a ContextParams,
+    format: OutputFormat,
+    pinned: Option<&'static PinnedFiles>,
+}
+
+pub(crate) struct ToolOutput {
+    pub(crate) text: String,
+    pub(crate) session_id: String,
+    // Only populated when the caller asked for structured references.
+    pub(crate) references: Option<Vec<Reference>>,
+    // ID in the plan store, for plans that were saved.
+    pub(crate) plan_id: Option<String>,
+    // API usage of the pipeline run.
+    pub(crate) usage: TokenUsage,
+    // Only populated when the caller asked for feasibility checks.
+    pub(crate) feasibility: Option<FeasibilityReport>,
+    // Existence checks of the files a markdown or JSON plan mentions.
+    pub(crate) paths: Option<Vec<PathCheck>>,
+    // The text is a prompt preview; there is no session or plan.
+    pub(crate) dry_run: bool,
+    // URL of the comment or pull request the result was published to.
+    pub(crate) published: Option<String>,
 }
 
+impl ToolOutput {
+    fn into_text(self) -> String {
+        if self.dry_run {
+            return self.text;
+        }
+        let mut text = format!(
+            "{}\n\n---\nSession ID: `{}` (pass to `follow_up` to ask further questions about this result)",
+            self.text, self.session_id
+        );
+        if let Some(plan_id) = &self.plan_id {
+            text.push_str(&format!("\nPlan ID: `{plan_id}` (pass to `get_plan` to retrieve this plan later)"));
+        }
+        text
+    }
+}
+
+// Rows of the metrics table sent to identify_tech_debt_hotspots.
+const HOTSPOT_TABLE_ROWS: usize = 30;
+
+// Files, commits per file, and diff characters per file in the git history
+// added to bug descriptions.
+const MAX_HISTORY_FILES: usize = 5;
+const HISTORY_COMMITS: usize = 5;
+const HISTORY_DIFF_CHARS: usize = 20_000;
+// Starts the git history section `with_history` appends.
+const HISTORY_HEADING: &str = "\n\n## Recent Git History\n\nRecent commits to the files involved, for finding the change that introduced the bug.\n";
+
+// `prompt` split into the text the caller wrote and the data appended to it
+// (git history), which is sent as is.
+fn split_appended_data(prompt: &str) -> (&str, &str) {
+    match prompt.rfind(HISTORY_HEADING) {
+        Some(start) => prompt.split_at(start),
+        None => (prompt, ""),
+    }
+}
+
+// Diff sent to summarize_changes; larger file diffs are listed by name only.
+const MAX_CHANGE_DIFF_CHARS: usize = 200_000;
+
+const WRITES_DISABLED: &str = "Writing files is disabled on this server (ALLOW_WRITES=false); review the changes without writing them and apply them manually.";
+
+// Below this, a context shrunk to fit max_total_tokens is too thin to plan
+// from and the call is refused instead.
+const MIN_BUDGET_CONTEXT_CHARS: usize = 20_000;
+
+// Tools whose request is built from data (issue lists, diffs, metrics) that
+// condensing would lose detail from; their inputs have limits of their own.
+const UNCONDENSED_TOOLS: &[&str] = &["cluster_related_issues", "summarize_changes", "identify_tech_debt_hotspots"];
+
 #[derive(Clone)]
 pub struct CodeAgentServer {
     config: Config,
+    plans: Arc<Mutex<HashMap<String, StructuredPlan>>>,
     tool_router: rmcp::handler::server::router::tool::ToolRouter<Self>,
 }
 
 #[tool_router]
 impl CodeAgentServer {
     pub fn new(config: Config) -> Self {
+        if let Some(canary) = &config.prompt_canary {
+            for tool in canary.tools() {
+                if pipeline::lookup(tool).is_none() {
+                    tracing::warn!("Prompt canary has templates for '{tool}', which is not a pipeline tool; ignoring them");
+                }
+            }
+        }
+        if let Some(templates) = &config.prompt_templates {
+            for tool in templates.tools() {
+                if pipeline::lookup(tool).is_none() {
+                    tracing::warn!("Prompt templates directory has templates for '{tool}', which is not a pipeline tool; ignoring them");
+                }
+            }
+        }
         Self {
             config,
+            plans: Arc::new(Mutex::new(HashMap::new())),
             tool_router: Self::tool_router(),
         }
     }
 
-    #[tool(description = "Generates a comprehensive, two-step feature implementation plan using Gemini 2.5 Pro. Analyzes codebase structure, creates high-level architecture plan, then produces detailed implementation guide with file references and code snippets. For large projects, split requests by concern (e.g., separate frontend/backend or by module) to stay within 200k token limit. Best for small-medium codebases or focused subdirectories.")]
-    async fn plan_feature(&self, params: Parameters<FeatureParams>) -> Result<String, String> {
-        tracing::info!("Received 'plan_feature' request for directory: {}", params.0.directory);
+    async fn execute(&self, request: ToolRequest<'_>) -> Result<ToolOutput, ToolError> {
+        let tool = request.tool;
+        let redacting = self.config.redact_literals || request.context.redact_literals.unwrap_or(false);
+        // Boxed: the request future is too large for a worker thread's stack
+        // in debug builds.
+        let run = audit::in_directory(request.directory, Box::pin(self.execute_request(request, None)));
+        let run = redact::scope(redacting, run);
+        let (result, used) = usage::scope(tool, run).await;
+        result.map(|output| ToolOutput { usage: used, ..output })
+    }
 
-        let report = match external::generate_codebase_report(
-            &self.config.codebase_viewer_path,
-            &PathBuf::from(params.0.directory),
-            self.config.token_char_limit,
-        ).await {
-            Ok(r) => r,
-            Err(e) => return Err(format!("Failed to generate codebase report: {e}")),
+    // Runs a pipeline tool by name with an already-built prompt, for callers
+    // outside MCP (the `agent` facade).
+    pub(crate) async fn run_tool(&self, tool: &str, directory: &str, prompt: &str, context: &ContextParams, format: OutputFormat) -> Result<ToolOutput, ToolError> {
+        let pipeline = pipeline::lookup(tool).ok_or_else(|| ToolError::not_found(format!("Unknown pipeline tool '{tool}'")))?;
+        let Pipeline { tool, prompts, pinned } = *pipeline;
+        self.execute(ToolRequest { tool, prompts, directory, prompt, context, format, pinned }).await
+    }
+
+    // `replay_of` is the failed-queue ID when re-running a queued request.
+    async fn execute_request(&self, request: ToolRequest<'_>, replay_of: Option<&str>) -> Result<ToolOutput, ToolError> {
+        if request.context.passes.is_some_and(|p| !(1..=3).contains(&p)) {
+            return Err(ToolError::invalid_params("'passes' must be 1, 2, or 3"));
+        }
+        if request.context.candidates.is_some_and(|c| !(1..=llm::MAX_CANDIDATES).contains(&c)) {
+            return Err(ToolError::invalid_params(format!("'candidates' must be between 1 and {}", llm::MAX_CANDIDATES)));
+        }
+        if redact::active() && request.format == OutputFormat::Diff {
+            return Err(ToolError::invalid_params("Diff output is not available with redacted literals: the hunks would not match the original files. Use markdown or JSON output."));
+        }
+        if request.context.candidates.is_some_and(|c| c > 1) && request.context.passes == Some(1) {
+            return Err(ToolError::invalid_params("'candidates' needs the analysis pass; use passes 2 or 3"));
+        }
+        if request.context.verbosity.is_some_and(|v| v != Verbosity::Standard) && request.format == OutputFormat::Diff {
+            return Err(ToolError::invalid_params("'verbosity' applies to markdown and JSON output; a diff always covers every change"));
+        }
+        if let Some(generation) = &request.context.generation {
+            generation.validate().map_err(ToolError::invalid_params)?;
+        }
+        if request.context.verify.unwrap_or(false) && request.format != OutputFormat::Markdown {
+            return Err(ToolError::invalid_params("'verify' appends a markdown section and needs markdown output"));
+        }
+        if let Some(target) = &request.context.publish {
+            self.config.forges.check_target(target).map_err(|e| ToolError::invalid_params(format!("Invalid 'publish': {e:#}")))?;
+        }
+        let char_limit = self.char_limit(request.context.token_limit)?;
+        let templated;
+        let request = match self.config.prompt_templates.as_ref().and_then(|t| t.prompts(request.tool, request.prompts)) {
+            Some(prompts) => {
+                templated = prompts;
+                ToolRequest { prompts: &templated, ..request }
+            }
+            None => request,
+        };
+
+        let workspace = workspace::open(request.directory, request.context.commit.as_deref(), request.context.mirror, &self.config.workspace)
+            .await
+            .map_err(|e| ToolError::invalid_path(format!("Failed to prepare directory '{}': {e:#}", request.directory)))?;
+        let mut workspaces = vec![(request.directory, workspace)];
+        for directory in request.context.directories.iter().flatten() {
+            let extra = workspace::open(directory, None, request.context.mirror, &self.config.workspace)
+                .await
+                .map_err(|e| ToolError::invalid_path(format!("Failed to prepare directory '{directory}': {e:#}")))?;
+            workspaces.push((directory.as_str(), extra));
+        }
+        let workspace = &workspaces[0].1;
+        let roots: Vec<&Path> = workspaces.iter().map(|(_, w)| w.path()).collect();
+
+        // Condensing costs a model call, so it waits until the directories
+        // are known to be usable.
+        let condensed;
+        let mut prompt_note = None;
+        let request = match self.condense_prompt(request.tool, request.prompt, request.context.dry_run.unwrap_or(false)).await {
+            Some((prompt, note)) => {
+                condensed = prompt;
+                prompt_note = Some(note);
+                ToolRequest { prompt: &condensed, ..request }
+            }
+            None => request,
+        };
+
+        let generation = self.config.generation.resolve(request.tool, request.context.generation.as_ref());
+        let options = PipelineOptions {
+            format: request.format,
+            passes: request.context.passes.unwrap_or(llm::DEFAULT_PASSES),
+            candidates: request.context.candidates.unwrap_or(1),
+            verify: request.context.verify.unwrap_or(false),
+            compact: request.context.compact_detail.unwrap_or(false).then(|| CompactContext {
+                root: workspace.path(),
+                char_limit,
+            }),
+            messages: self.message_templates(request.tool),
+            verbosity: request.context.verbosity.unwrap_or_default(),
+            generation: &generation,
+        };
+        let mut notes: Vec<String> = prompt_note.into_iter().collect();
+        let mut context = self.assemble_context(&request, &workspaces, char_limit).await?;
+        if let Some(budget) = request.context.max_total_tokens.or(self.config.max_total_tokens) {
+            let client = &self.config.gemini_client;
+            let estimate = client.estimate_pipeline(request.prompts, context.len(), request.prompt, options);
+            if estimate.total() > budget {
+                if !request.context.shrink_to_budget.unwrap_or(true) {
+                    return Err(budget_error(&estimate, budget, options.passes, "shrink_to_budget is off"));
+                }
+                let fitted = estimate.context_chars_within(budget).filter(|&chars| chars >= MIN_BUDGET_CONTEXT_CHARS);
+                let Some(fitted) = fitted else {
+                    return Err(budget_error(&estimate, budget, options.passes, "too little of the budget is left for a useful context"));
+                };
+                // Pinned files get a quarter of the report's limit on top of it.
+                let shrunk = self.assemble_context(&request, &workspaces, fitted * 4 / 5).await?;
+                let shrunk_estimate = client.estimate_pipeline(request.prompts, shrunk.len(), request.prompt, options);
+                if shrunk_estimate.total() > budget {
+                    return Err(budget_error(&shrunk_estimate, budget, options.passes, "the codebase context could not be shrunk enough"));
+                }
+                tracing::info!(
+                    "Shrank the context from ~{} to ~{} tokens to fit the {budget}-token budget",
+                    estimate.context_tokens, shrunk_estimate.context_tokens
+                );
+                notes.push(format!(
+                    "Note: the codebase context was cut from ~{} to ~{} tokens to fit max_total_tokens ({budget}); files beyond that were left out.",
+                    estimate.context_tokens, shrunk_estimate.context_tokens
+                ));
+                context = shrunk;
+            }
+        }
+
+        if request.context.dry_run.unwrap_or(false) {
+            let preview = self.config.gemini_client.preview_pipeline(request.prompts, &context, request.prompt, options);
+            let estimate = self.config.gemini_client.estimate_pipeline(request.prompts, context.len(), request.prompt, options);
+            let mut text = render_dry_run(&preview, &context, &estimate, char_limit, options);
+            if !notes.is_empty() {
+                text = format!("{}\n\n{text}", notes.join("\n"));
+            }
+            return Ok(ToolOutput {
+                text,
+                session_id: String::new(),
+                references: None,
+                plan_id: None,
+                usage: TokenUsage::default(),
+                feasibility: None,
+                paths: None,
+                dry_run: true,
+                published: None,
+            });
+        }
+
+        let output = match self.run_pipeline(request.tool, request.prompts, &context, request.prompt, options).await {
+            Ok(output) => output,
+            // Not a provider failure: replaying would hit the same quota.
+            Err(LlmError::QuotaExceeded(e)) => return Err(ToolError::new(ErrorKind::LlmQuota, e.to_string())),
+            Err(e) => {
+                let error = ToolError::llm(&format!("Failed to generate {} from Gemini", request.prompts.name), &e);
+                // Replaying would be rejected the same way.
+                if matches!(e, LlmError::InvalidRequest { .. }) {
+                    return Err(error);
+                }
+                return Err(self.queue_failure(&request, &context, error, replay_of).await);
+            }
+        };
+        self.spawn_canary(&request, &context, options, &output);
+
+        let feasibility = if request.context.verify_feasibility.unwrap_or(false)
+            && request.format != OutputFormat::Diff
+            && is_plan(request.prompts, request.format)
+        {
+            match feasibility::check(&self.config.registries, workspace.path(), &output).await {
+                Ok(report) => Some(report),
+                Err(e) => {
+                    tracing::warn!("Feasibility check failed: {e:#}");
+                    None
+                }
+            }
+        } else {
+            None
+        };
+
+        if let Some(id) = replay_of {
+            if let Some(entry) = self.config.failed_requests.get(id) {
+                if entry.report_hash != context.content_hash() {
+                    notes.insert(0, "Note: the codebase context changed since this request originally failed.".to_string());
+                }
+            }
+            if let Err(e) = self.config.failed_requests.remove(id).await {
+                tracing::warn!("Failed to remove replayed request '{id}' from the queue: {e:#}");
+            }
+        }
+
+        let mut references = Vec::new();
+        let mut path_checks = None;
+        let text = match request.format {
+            OutputFormat::Diff => validate_diff(workspace.path(), &output).await?,
+            OutputFormat::Json => {
+                if let Ok(plan) = plan::parse_structured_plan(&output) {
+                    path_checks = Some(paths::check(&roots, paths::mentioned_in_plan(&plan)).await);
+                }
+                output
+            }
+            OutputFormat::Markdown => {
+                references = references::verify(&roots, &output).await;
+                if request.context.use_retrieval.unwrap_or(false) && workspaces.len() == 1 {
+                    self.record_grounding(workspace, &references).await;
+                }
+                if is_plan(request.prompts, request.format) {
+                    let manifest = plan::parse_manifest(&output).unwrap_or_default();
+                    path_checks = Some(paths::check(&roots, paths::mentioned_in_markdown(&output, &manifest)).await);
+                }
+                let mut text = match references::render_unverified(&references) {
+                    Some(note) => format!("{output}\n\n{note}"),
+                    None => output,
+                };
+                if let Some(note) = path_checks.as_deref().and_then(paths::render) {
+                    text = format!("{text}\n\n{note}");
+                }
+                if let Some(report) = &feasibility {
+                    text = format!("{text}\n\n{}", feasibility::render(report));
+                }
+                text
+            }
+        };
+
+        // Archives, historical commits, and remote clones are not the caller's
+        // working tree, so there is nothing to track.
+        let snapshot = if request.context.commit.is_none()
+            && !archive::is_archive(Path::new(request.directory))
+            && !workspace::is_remote(request.directory)
+        {
+            match external::collect_files(workspace.path()).await {
+                Ok(source_files) => Some(files::snapshot(&source_files)),
+                Err(e) => {
+                    tracing::warn!("Failed to snapshot '{}' for change tracking: {e:#}", request.directory);
+                    None
+                }
+            }
+        } else {
+            None
+        };
+
+        let session_id = self.config.sessions.create(
+            request.tool,
+            request.directory,
+            request.format,
+            context,
+            snapshot,
+            Exchange {
+                question: format!("{}: {}", request.prompts.request_label, request.prompt),
+                answer: text.clone(),
+            },
+        );
+
+        let plan_id = if is_plan(request.prompts, request.format) {
+            self.save_plan(request.tool, request.directory, request.prompt, request.format, &session_id, &text).await
+        } else {
+            None
         };
 
-        match self.config.gemini_client.generate_feature_plan(report, params.0.feature_prompt).await {
-            Ok(plan) => Ok(plan),
-            Err(e) => Err(format!("Failed to generate feature plan from Gemini: {e}")),
+        let published = match &request.context.publish {
+            Some(target) => match self.publish(target, &request, plan_id.as_deref(), &text).await {
+                Ok(url) => {
+                    notes.push(format!("Published to {url}"));
+                    Some(url)
+                }
+                Err(e) => {
+                    tracing::warn!("Failed to publish the '{}' result to {}: {e:#}", request.tool, target.url);
+                    let forge = self.config.forges.for_url(&target.url).name();
+                    notes.push(format!("Warning: the result was not published to {forge}: {e:#}"));
+                    None
+                }
+            },
+            None => None,
+        };
+
+        let text = if notes.is_empty() { text } else { format!("{}\n\n{text}", notes.join("\n")) };
+        let references = request.context.references.unwrap_or(false).then_some(references);
+        Ok(ToolOutput {
+            text,
+            session_id,
+            references,
+            plan_id,
+            usage: TokenUsage::default(),
+            feasibility,
+            paths: path_checks,
+            dry_run: false,
+            published,
+        })
+    }
+
+    // `prompt` condensed to PROMPT_CHAR_LIMIT, with a note for the result,
+    // when the caller's part of it is longer: everything but its acceptance
+    // criteria is rewritten by the model, a part at a time, and the criteria
+    // are appended verbatim. Data appended to the request, such as git
+    // history, is neither counted nor condensed. If condensing fails, or in a
+    // dry run, which makes no model calls for it, the start of the prompt is
+    // kept instead. None when the prompt fits or is built from data rather
+    // than written by the caller.
+    async fn condense_prompt(&self, tool: &str, prompt: &str, dry_run: bool) -> Option<(String, String)> {
+        let limit = self.config.prompt_char_limit?;
+        let (prompt, data) = split_appended_data(prompt);
+        if prompt.len() <= limit || UNCONDENSED_TOOLS.contains(&tool) {
+            return None;
         }
+        let (criteria, rest) = spec::split_criteria(prompt);
+        let criteria_len: usize = criteria.iter().map(String::len).sum();
+        let rest_limit = limit.saturating_sub(criteria_len).max(limit / 4);
+        let verbatim = if criteria.is_empty() { String::new() } else { "; its acceptance criteria are verbatim".to_string() };
+        if dry_run {
+            let head = spec::head(&rest, rest_limit);
+            let note = format!("Note: the {}-character request would be condensed to about {limit} characters before planning; this dry run shows its first {} characters instead{verbatim}.", prompt.len(), head.len());
+            return Some((format!("{}{data}", spec::assemble(head, &criteria)), note));
+        }
+
+        let mut condensed = String::new();
+        let mut failure = None;
+        for part in spec::chunks(&rest, spec::MAX_CHUNK_CHARS) {
+            let target = rest_limit * part.len() / rest.len().max(1);
+            match self.config.gemini_client.condense_spec(part, target).await {
+                Ok(text) => condensed.push_str(&format!("{}\n\n", text.trim())),
+                Err(e) => {
+                    failure = Some(e);
+                    break;
+                }
+            }
+        }
+        let (text, note) = match failure {
+            None => {
+                let text = spec::assemble(&condensed, &criteria);
+                tracing::info!("Condensed the {}-character '{tool}' request to {} characters", prompt.len(), text.len());
+                let note = format!("Note: the {}-character request was condensed to {} characters before planning{verbatim}.", prompt.len(), text.len());
+                (text, note)
+            }
+            Some(e) => {
+                tracing::warn!("Failed to condense the {}-character '{tool}' request: {e}", prompt.len());
+                let head = spec::head(&rest, rest_limit);
+                let note = format!("Warning: the {}-character request could not be condensed ({e}); only its first {} characters were used{verbatim}.", prompt.len(), head.len());
+                (spec::assemble(head, &criteria), note)
+            }
+        };
+        Some((format!("{text}{data}"), note))
     }
 
-    #[tool(description = "Analyzes bugs and generates detailed fix implementation plans using Gemini 2.5 Pro. Performs root cause analysis, identifies affected files, and provides step-by-step remediation with code examples. For large projects, narrow scope to relevant subsystem (e.g., just authentication module or API layer) to stay within 200k token limit. Include error messages, stack traces, or reproduction steps in bug_description for best results.")]
-    async fn plan_bug_fix(&self, params: Parameters<BugFixParams>) -> Result<String, String> {
-        tracing::info!("Received 'plan_bug_fix' request for directory: {}", params.0.directory);
-        let report = match external::generate_codebase_report(
-            &self.config.codebase_viewer_path,
-            &PathBuf::from(params.0.directory),
-            self.config.token_char_limit,
-        ).await {
-            Ok(r) => r,
-            Err(e) => return Err(format!("Failed to generate codebase report: {e}")),
+    // The context limit in characters: `token_limit` if the call sets one,
+    // else TOKEN_CHAR_LIMIT.
+    fn char_limit(&self, token_limit: Option<usize>) -> Result<usize, ToolError> {
+        let Some(tokens) = token_limit else {
+            return Ok(self.config.token_char_limit);
         };
+        let chars_per_token = llm::CHARS_PER_TOKEN as usize;
+        let max_tokens = self.config.max_token_char_limit / chars_per_token;
+        if !(1..=max_tokens).contains(&tokens) {
+            return Err(ToolError::invalid_params(format!("'token_limit' must be between 1 and {max_tokens} tokens, the server's MAX_TOKEN_CHAR_LIMIT")));
+        }
+        Ok(tokens * chars_per_token)
+    }
 
-        match self.config.gemini_client.generate_bug_fix_plan(report, params.0.bug_description).await {
-            Ok(plan) => Ok(plan),
-            Err(e) => Err(format!("Failed to generate bug fix plan from Gemini: {e}")),
+    // The codebase report with workspace maps, pinned files, and the files
+    // the request mentions. The pinned files are limited to a quarter of
+    // `char_limit`; the mentioned files take up to a quarter of it out of the
+    // report's share.
+    async fn assemble_context(&self, request: &ToolRequest<'_>, workspaces: &[(&str, Workspace)], char_limit: usize) -> Result<Segments, ToolError> {
+        let mut mentioned = Vec::new();
+        let mut report_limit = char_limit;
+        if self.config.include_mentioned_files {
+            let mentioned_limit = char_limit / 4 / workspaces.len();
+            for (label, mentioned_workspace) in workspaces {
+                let label = (workspaces.len() > 1).then_some(*label);
+                let section = self.mentioned_files_section(mentioned_workspace.path(), label, request.prompt, mentioned_limit).await;
+                report_limit -= section.len().min(report_limit);
+                mentioned.push(section);
+            }
+        }
+        let report = self.build_context(request.tool, workspaces, request.prompt, request.context, report_limit).await?;
+        let mut context = Segments::new();
+        for (label, map_workspace) in workspaces {
+            let label = (workspaces.len() > 1).then_some(*label);
+            context.push(repository_map_sections(map_workspace.path(), label).await);
         }
+        if let Some(pinned) = request.pinned {
+            let pinned_limit = char_limit / 4 / workspaces.len();
+            for (label, pinned_workspace) in workspaces {
+                let label = (workspaces.len() > 1).then_some(*label);
+                context.push(self.pinned_files_section(pinned_workspace.path(), label, pinned, pinned_limit).await);
+            }
+        }
+        mentioned.into_iter().for_each(|section| context.push(section));
+        context.push(report);
+        Ok(context)
     }
 
-    #[tool(description = "Provides detailed technical explanations of codebase components using Gemini 2.5 Pro. Identifies key files, explains architecture patterns, data flow, and inter-component relationships with code examples. For large projects, target specific subsystems (e.g., 'explain the authentication system' vs 'explain the entire backend') to stay within 200k token limit. Best for onboarding, documentation, or understanding complex logic.")]
-    async fn explain_code(&self, params: Parameters<ExplanationParams>) -> Result<String, String> {
-        tracing::info!("Received 'explain_code' request for directory: {}", params.0.directory);
-        let report = match external::generate_codebase_report(
-            &self.config.codebase_viewer_path,
-            &PathBuf::from(params.0.directory),
-            self.config.token_char_limit,
-        ).await {
-            Ok(r) => r,
-            Err(e) => return Err(format!("Failed to generate codebase report: {e}")),
+    // The feature prompt preceded by the Jira ticket's requirements, if one is given.
+    async fn with_ticket(&self, ticket: Option<&str>, prompt: &str) -> Result<String, ToolError> {
+        let Some(ticket) = ticket.filter(|t| !t.trim().is_empty()) else {
+            return Ok(prompt.to_string());
         };
+        let ticket = self.config.jira.ticket(ticket).await.map_err(|e| ToolError::new(ErrorKind::ExternalService, format!("Failed to fetch Jira ticket: {e:#}")))?;
+        tracing::info!("Fetched Jira ticket {} ('{}')", ticket.key, ticket.summary);
+        let mut prompt_with_ticket = ticket.render();
+        if !prompt.trim().is_empty() {
+            prompt_with_ticket.push_str(&format!("\n## Notes from the requester\n\n{}\n", prompt.trim()));
+        }
+        Ok(prompt_with_ticket)
+    }
 
-        match self.config.gemini_client.generate_explanation(report, params.0.explanation_query).await {
-            Ok(explanation) => Ok(explanation),
-            Err(e) => Err(format!("Failed to generate explanation from Gemini: {e}")),
+    // `description` followed by the recent git history of the files involved:
+    // `named` if given, else the files it mentions (as stack traces do), else
+    // the files a file selection pass picks. Read from the checkout itself,
+    // never a mirror, since mirrors carry no history.
+    async fn with_history(&self, directory: &str, context: &ContextParams, description: &str, named: &[String]) -> Result<String, ToolError> {
+        if self.config.redact_literals || context.redact_literals.unwrap_or(false) {
+            return Err(ToolError::invalid_params("Git history is not available with redacted literals: its diffs would send the code unredacted. Drop 'history' and 'history_files', or 'redact_literals'."));
         }
+        let workspace = workspace::open(directory, context.commit.as_deref(), Some(false), &self.config.workspace)
+            .await
+            .map_err(|e| ToolError::invalid_path(format!("Failed to prepare directory '{directory}': {e:#}")))?;
+        let root = workspace.path();
+        if !git::is_work_tree(root).await {
+            return Err(ToolError::invalid_path(format!("'history' needs a git checkout, and '{directory}' is not one")));
+        }
+
+        let mut paths = Vec::new();
+        for file in named {
+            let path = apply::safe_relative_path(file.trim()).map_err(|e| ToolError::invalid_params(format!("Invalid history file '{file}': {e}")))?;
+            paths.push(path.to_string_lossy().into_owned());
+        }
+        if paths.is_empty() {
+            paths = mentioned_files(root, description).await.map_err(|e| ToolError::report(format!("Failed to list files in '{directory}': {e:#}")))?;
+        }
+        if paths.is_empty() {
+            let tree = external::generate_file_tree(root).await.map_err(|e| ToolError::report(format!("Failed to list codebase files: {e:#}")))?;
+            paths = self.config.gemini_client
+                .select_files(&tree, description)
+                .await
+                .map_err(|e| ToolError::llm("Failed to pick the files to read history for", &e))?;
+        }
+        paths.truncate(MAX_HISTORY_FILES);
+        tracing::info!("Adding git history of {:?} to the bug description", paths);
+
+        let mut history = String::from(HISTORY_HEADING);
+        for path in &paths {
+            match git::file_history(root, path, HISTORY_COMMITS, HISTORY_DIFF_CHARS).await {
+                Ok(file) => history.push_str(&format!("\n{file}\n")),
+                Err(e) => history.push_str(&format!("\n### `{path}`\n\nNo history available: {e:#}\n")),
+            }
+        }
+        Ok(format!("{}{history}", description.trim_end()))
     }
-}
 
-#[tool_handler]
-impl ServerHandler for CodeAgentServer {
-    fn get_info(&self) -> ServerInfo {
-        ServerInfo {
-            capabilities: ServerCapabilities::builder().enable_tools().build(),
-            ..Default::default()
+    // Posts the result to the target's forge and returns the URL it was published at.
+    async fn publish(&self, target: &PublishTarget, request: &ToolRequest<'_>, plan_id: Option<&str>, text: &str) -> anyhow::Result<String> {
+        let result = match request.format {
+            OutputFormat::Markdown => text.to_string(),
+            OutputFormat::Json => files::fenced("json", text),
+            OutputFormat::Diff => files::fenced("diff", text),
+        };
+        let source = match plan_id {
+            Some(id) => format!("`{}` (plan `{id}`)", request.tool),
+            None => format!("`{}`", request.tool),
+        };
+        let body = format!("{result}\n\n---\n<sub>Generated by ai-code-agent with {source}.</sub>");
+        let title = text
+            .lines()
+            .find_map(|line| line.strip_prefix("# "))
+            .map(|heading| heading.trim().to_string())
+            .unwrap_or_else(|| format!("{}: {}", request.tool, request.prompt.lines().next().unwrap_or_default().chars().take(72).collect::<String>()));
+        let url = self.config.forges.publish(target, &title, &body).await?;
+        tracing::info!("Published the '{}' result to {url}", request.tool);
+        Ok(url)
+    }
+
+    // Returns the plan's ID, or None if it could not be persisted.
+    async fn save_plan(&self, tool: &str, directory: &str, prompt: &str, format: OutputFormat, session_id: &str, text: &str) -> Option<String> {
+        let id = uuid::Uuid::new_v4().to_string();
+        let plan = SavedPlan {
+            id: id.clone(),
+            tool: tool.to_string(),
+            directory: directory.to_string(),
+            prompt: prompt.to_string(),
+            model: self.config.gemini_client.model().to_string(),
+            format,
+            session_id: session_id.to_string(),
+            created_at: 0,
+            template: self.config.prompt_templates.as_ref().and_then(|t| t.fingerprint(tool)),
+            text: text.to_string(),
+            ratings: Vec::new(),
+        };
+        match self.config.saved_plans.save(plan).await {
+            Ok(()) => Some(id),
+            Err(e) => {
+                tracing::warn!("Failed to save plan: {e:#}");
+                None
+            }
+        }
+    }
+
+    async fn record_grounding(&self, workspace: &Workspace, references: &[Reference]) {
+        let verified = references.iter().filter(|r| r.verified).count();
+        if let Err(e) = embeddings::record_grounding(&self.config.embedding, workspace.cache_key(), verified, references.len()).await {
+            tracing::warn!("Failed to update retrieval tuning for '{}': {e:#}", workspace.cache_key().display());
+        }
+    }
+
+    async fn queue_failure(&self, request: &ToolRequest<'_>, context: &Segments, error: ToolError, replay_of: Option<&str>) -> ToolError {
+        let queue = &self.config.failed_requests;
+        if let Some(id) = replay_of {
+            if let Err(e) = queue.record_retry(id, &error.message).await {
+                tracing::warn!("Failed to update queued request '{id}': {e:#}");
+            }
+            let message = format!("{}\n\nThe request remains queued as `{id}`.", error.message);
+            return ToolError { message, queued_as: Some(id.to_string()), ..error };
+        }
+
+        let id = uuid::Uuid::new_v4().to_string();
+        let entry = FailedRequest {
+            id: id.clone(),
+            tool: request.tool.to_string(),
+            directory: request.directory.to_string(),
+            prompt: request.prompt.to_string(),
+            format: request.format,
+            options: serde_json::to_value(request.context).unwrap_or_default(),
+            report_hash: context.content_hash(),
+            error: error.message.clone(),
+            failed_at: 0,
+            attempts: 1,
+        };
+        match queue.push(entry).await {
+            Ok(()) => {
+                let message = format!("{}\n\nThe request was queued as `{id}`; run `replay_failed` with this ID once the provider recovers.", error.message);
+                ToolError { message, queued_as: Some(id), ..error }
+            }
+            Err(e) => {
+                tracing::warn!("Failed to queue failed request: {e:#}");
+                error
+            }
+        }
+    }
+
+    async fn replay(&self, entry: &FailedRequest) -> Result<ToolOutput, ToolError> {
+        let Pipeline { tool, prompts, pinned } = *pipeline::lookup(&entry.tool)
+            .ok_or_else(|| ToolError::not_found(format!("Tool '{}' can no longer be replayed", entry.tool)))?;
+        let context: ContextParams = serde_json::from_value(entry.options.clone()).unwrap_or_default();
+
+        tracing::info!("Replaying failed '{}' request '{}'", tool, entry.id);
+        let run = Box::pin(self.execute_request(ToolRequest {
+            tool,
+            prompts,
+            directory: &entry.directory,
+            prompt: &entry.prompt,
+            context: &context,
+            format: entry.format,
+            pinned,
+        }, Some(&entry.id)));
+        let redacting = self.config.redact_literals || context.redact_literals.unwrap_or(false);
+        redact::scope(redacting, audit::in_directory(&entry.directory, run)).await
+    }
+
+    // Re-runs a sampled request with the canary templates in the background;
+    // the caller's result never waits for or depends on it.
+    fn spawn_canary(&self, request: &ToolRequest<'_>, context: &Segments, options: PipelineOptions<'_>, baseline: &str) {
+        let Some(canary) = self.config.prompt_canary.clone() else {
+            return;
+        };
+        let Some(prompts) = canary.sample(request.tool, request.prompts) else {
+            return;
+        };
+
+        let client = self.config.gemini_client.clone();
+        let tool = request.tool;
+        let (format, passes, verbosity) = (request.format, options.passes, options.verbosity);
+        let messages = self.message_templates(tool);
+        let generation = options.generation.clone();
+        let (context, prompt, baseline) = (context.clone(), request.prompt.to_string(), baseline.to_string());
+        tokio::spawn(async move {
+            tracing::info!("Running prompt canary for '{tool}'");
+            let started = std::time::Instant::now();
+            let options = PipelineOptions { format, passes, candidates: 1, verify: false, compact: None, messages, verbosity, generation: &generation };
+            let run = client.run_pipeline(&prompts, &context, &prompt, options);
+            let result = usage::scope(&format!("{tool} (canary)"), run).await.0.map_err(|e| e.to_string());
+            let elapsed = started.elapsed().as_millis();
+            if let Err(e) = canary.record(tool, &prompt, &baseline, result, elapsed).await {
+                tracing::warn!("Failed to record prompt canary comparison: {e:#}");
+            }
+        });
+    }
+
+    fn message_templates(&self, tool: &str) -> MessageTemplates {
+        match &self.config.prompt_templates {
+            Some(templates) => templates.messages(tool),
+            None => templates::DEFAULT_MESSAGES,
         }
     }
+
+    async fn run_pipeline(
+        &self,
+        tool: &str,
+        prompts: &PipelinePrompts,
+        context: &Segments,
+        prompt: &str,
+        options: PipelineOptions<'_>,
+    ) -> Result<String, LlmError> {
+        let client = &self.config.gemini_client;
+        let Some(cache) = &self.config.semantic_cache else {
+            return client.run_pipeline(prompts, context, prompt, options).await;
+        };
+
+        let mut cache_tool = match options.format {
+            OutputFormat::Markdown => tool.to_string(),
+            OutputFormat::Json => format!("{tool}:json"),
+            OutputFormat::Diff => format!("{tool}:diff"),
+        };
+        if options.passes != llm::DEFAULT_PASSES {
+            cache_tool = format!("{cache_tool}:passes{}", options.passes);
+        }
+        if options.candidates > 1 {
+            cache_tool = format!("{cache_tool}:candidates{}", options.candidates);
+        }
+        if options.verify {
+            cache_tool = format!("{cache_tool}:verify");
+        }
+        if options.compact.is_some() {
+            cache_tool = format!("{cache_tool}:compact");
+        }
+        if options.verbosity != Verbosity::Standard {
+            cache_tool = format!("{cache_tool}:{}", options.verbosity.name());
+        }
+        let generation = options.generation.describe();
+        if !generation.is_empty() {
+            cache_tool = format!("{cache_tool}:{generation}");
+        }
+        if let Some(fingerprint) = self.config.prompt_templates.as_ref().and_then(|t| t.fingerprint(tool)) {
+            cache_tool = format!("{cache_tool}:templates{fingerprint}");
+        }
+        // The cache is an optimisation: without embeddings, run uncached.
+        let key = match cache.key(client, &cache_tool, context, prompt).await {
+            Ok(key) => key,
+            Err(e) => {
+                tracing::warn!("Failed to embed the request for the semantic cache; running uncached: {e}");
+                return client.run_pipeline(prompts, context, prompt, options).await;
+            }
+        };
+        if let Some(response) = cache.lookup(&key) {
+            return Ok(response);
+        }
+
+        let response = client.run_pipeline(prompts, context, prompt, options).await?;
+        let cacheable = options.format != OutputFormat::Json || plan::parse_structured_plan(&response).is_ok();
+        if cacheable {
+            if let Err(e) = cache.insert(key, &response).await {
+                tracing::warn!("Failed to store response in semantic cache: {e:#}");
+            }
+        }
+        Ok(response)
+    }
+
+    async fn build_context(&self, tool: &str, workspaces: &[(&str, Workspace)], prompt: &str, options: &ContextParams, char_limit: usize) -> Result<String, ToolError> {
+        if let [(_, workspace)] = workspaces {
+            return self.build_directory_context(tool, workspace, prompt, options, char_limit).await;
+        }
+
+        let targeted = options.use_retrieval.unwrap_or(false)
+            || options.skeleton.unwrap_or(false)
+            || options.file_selection.unwrap_or_else(|| self.config.file_selection_tools.contains(tool));
+        let share = char_limit / workspaces.len();
+        let mut reports = Vec::new();
+        for (label, workspace) in workspaces {
+            let report = if targeted {
+                self.build_directory_context(tool, workspace, prompt, options, share).await
+            } else {
+                self.codebase_report(workspace, share).await
+            };
+            reports.push((label.to_string(), report.map_err(|e| e.context(label))?));
+        }
+        Ok(external::label_reports(&reports))
+    }
+
+    async fn build_directory_context(&self, tool: &str, workspace: &Workspace, prompt: &str, options: &ContextParams, char_limit: usize) -> Result<String, ToolError> {
+        if options.use_retrieval.unwrap_or(false) {
+            if let Some(report) = self.index_context(workspace, prompt, char_limit).await {
+                return Ok(report);
+            }
+            let tree_hash = self.tree_hash(workspace).await;
+            return embeddings::retrieve_context(
+                &self.config.gemini_client,
+                &self.config.embedding,
+                workspace.path(),
+                workspace.cache_key(),
+                tree_hash.as_deref(),
+                prompt,
+                char_limit,
+            ).await
+            .map_err(|e| ToolError::report(format!("Failed to retrieve relevant files: {e:#}")));
+        }
+
+        if options.skeleton.unwrap_or(false) {
+            return skeleton::skeleton_context(workspace.path(), prompt, self.config.embedding.top_k, char_limit)
+                .await
+                .map_err(|e| ToolError::report(format!("Failed to build the codebase skeleton: {e:#}")));
+        }
+
+        let file_selection = options
+            .file_selection
+            .unwrap_or_else(|| self.config.file_selection_tools.contains(tool));
+        if file_selection {
+            return self.build_selected_context(workspace, prompt, char_limit).await;
+        }
+
+        self.codebase_report(workspace, char_limit).await
+    }
+
+    // The code index the repository already has (ctags, LSIF, or SCIP), looked
+    // up in the workspace and, for mirrors, the directory it was copied from,
+    // since index files are usually ignored and not mirrored.
+    async fn code_index(&self, workspace: &Workspace) -> Option<Arc<code_index::SymbolIndex>> {
+        let indexes = self.config.code_indexes.as_ref()?;
+        indexes.find(&[workspace.path(), workspace.cache_key()]).await
+    }
+
+    // Retrieval through the repository's code index, which needs no embedding
+    // of every file first. None if there is no usable index or the request
+    // names none of its symbols; retrieval then falls back to embeddings.
+    async fn index_context(&self, workspace: &Workspace, prompt: &str, char_limit: usize) -> Option<String> {
+        let index = self.code_index(workspace).await?;
+        match code_index::retrieve_context(&index, workspace.path(), prompt, self.config.embedding.top_k, char_limit, self.config.embedding.import_depth).await {
+            Ok(report) => report,
+            Err(e) => {
+                tracing::warn!("Failed to select files from the code index of '{}': {e:#}", workspace.path().display());
+                None
+            }
+        }
+    }
+
+    // The codebase_viewer report of `workspace`, reused from an earlier call
+    // while none of its files changed.
+    async fn codebase_report(&self, workspace: &Workspace, char_limit: usize) -> Result<String, ToolError> {
+        let tree_hash = self.tree_hash(workspace).await;
+        let variant = format!("report:{char_limit}:{}", redact::active());
+        let hashes = &self.config.tree_hashes;
+        if let Some(report) = tree_hash.as_deref().and_then(|hash| hashes.derived(workspace.cache_key(), &variant, hash)) {
+            tracing::info!("Reusing the codebase report for '{}': no files changed", workspace.cache_key().display());
+            return Ok(report);
+        }
+
+        let report = external::generate_codebase_report(
+            &self.config.codebase_viewer_path,
+            workspace.path(),
+            char_limit,
+            self.config.viewer_timeout,
+            &self.config.process_metrics,
+            &self.config.report_check,
+        ).await
+        .map_err(|e| ToolError::report(format!("Failed to generate codebase report: {e:#}")))?;
+        if let Some(hash) = tree_hash {
+            hashes.store_derived(workspace.cache_key(), &variant, hash, report.clone());
+        }
+        Ok(report)
+    }
+
+    // None if the directory could not be hashed; caches are then bypassed.
+    async fn tree_hash(&self, workspace: &Workspace) -> Option<String> {
+        match self.config.tree_hashes.root_hash(workspace.path()).await {
+            Ok(hash) => Some(hash),
+            Err(e) => {
+                tracing::warn!("Failed to hash '{}': {e:#}", workspace.path().display());
+                None
+            }
+        }
+    }
+
+    async fn build_selected_context(&self, workspace: &Workspace, prompt: &str, char_limit: usize) -> Result<String, ToolError> {
+        let directory = workspace.path();
+        let tree = external::generate_file_tree(directory)
+            .await
+            .map_err(|e| ToolError::report(format!("Failed to list codebase files: {e:#}")))?;
+
+        // Where the request's symbols live, so the model need not guess from file names.
+        let hint = self.code_index(workspace).await.and_then(|index| index.render_hint(prompt));
+        let request = match hint {
+            Some(hint) => format!("{prompt}\n\n{hint}"),
+            None => prompt.to_string(),
+        };
+        let requested = self.config.gemini_client
+            .select_files(&tree, &request)
+            .await
+            .map_err(|e| ToolError::llm("Failed to select relevant files with Gemini", &e))?;
+
+        external::generate_selected_files_report(directory, &requested, char_limit, self.config.embedding.import_depth)
+            .await
+            .map_err(|e| ToolError::report(format!("Failed to generate report for selected files: {e:#}")))
+    }
+
+    #[tool(description = "Generates a comprehensive, two-step feature implementation plan using Gemini 2.5 Pro. Analyzes codebase structure, creates high-level architecture plan, then produces detailed implementation guide with file references and code snippets. For large projects, split requests by concern (e.g., separate frontend/backend or by module) to stay within 200k token limit. Best for small-medium codebases or focused subdirectories.")]
+    async fn plan_feature(&self, params: Parameters<FeatureParams>) -> Result<CallToolResult, McpError> {
+        tracing::info!("Received 'plan_feature' request for directory: {}", params.0.directory);
+        let format = params.0.output_format.unwrap_or_default();
+        let prompt = match self.with_ticket(params.0.ticket.as_deref(), &params.0.feature_prompt).await {
+            Ok(prompt) => prompt,
+            Err(e) => return Ok(e.into()),
+        };
+
+        let result = self.execute(ToolRequest {
+            tool: "plan_feature",
+            prompts: &llm::FEATURE_PLAN,
+            directory: &params.0.directory,
+            prompt: &prompt,
+            context: &params.0.context,
+            format,
+            pinned: None,
+        }).await;
+
+        Ok(self.plan_output(result, format))
+    }
+
+    #[tool(description = "First half of plan_feature, for reviewing the approach before the details: produces only the high-level feature plan (architecture fit, affected components, design decisions, challenges, and implementation steps) using Gemini 2.5 Pro. Edit the outline as needed, then pass it to plan_feature_details. 'passes' is ignored; this is always a single pass.")]
+    async fn plan_feature_outline(&self, params: Parameters<FeatureOutlineParams>) -> Result<CallToolResult, McpError> {
+        tracing::info!("Received 'plan_feature_outline' request for directory: {}", params.0.directory);
+        let params = params.0;
+        let context = ContextParams { passes: Some(1), ..params.context };
+        let prompt = match self.with_ticket(params.ticket.as_deref(), &params.feature_prompt).await {
+            Ok(prompt) => prompt,
+            Err(e) => return Ok(e.into()),
+        };
+
+        let result = self.execute(ToolRequest {
+            tool: "plan_feature_outline",
+            prompts: &llm::FEATURE_OUTLINE,
+            directory: &params.directory,
+            prompt: &prompt,
+            context: &context,
+            format: OutputFormat::Markdown,
+            pinned: None,
+        }).await;
+
+        Ok(text_output(result))
+    }
+
+    #[tool(description = "Second half of plan_feature: turns a high-level plan - typically from plan_feature_outline, edited by the requester - into the detailed implementation plan with file paths, code snippets, dependencies, tests, and implementation order, using Gemini 2.5 Pro. The outline is followed as given instead of being regenerated. 'passes' is ignored; this is always a single pass.")]
+    async fn plan_feature_details(&self, params: Parameters<FeatureDetailsParams>) -> Result<CallToolResult, McpError> {
+        tracing::info!("Received 'plan_feature_details' request for directory: {}", params.0.directory);
+        let params = params.0;
+        let format = params.output_format.unwrap_or_default();
+        let context = ContextParams { passes: Some(1), ..params.context };
+        let prompt = format!(
+            "{}\n\n{} (reviewed by the requester; follow it as given):\n{}",
+            params.feature_prompt, llm::FEATURE_PLAN.analysis_label, params.outline
+        );
+
+        let result = self.execute(ToolRequest {
+            tool: "plan_feature_details",
+            prompts: &llm::FEATURE_PLAN,
+            directory: &params.directory,
+            prompt: &prompt,
+            context: &context,
+            format,
+            pinned: None,
+        }).await;
+
+        Ok(self.plan_output(result, format))
+    }
+
+    #[tool(description = "Analyzes bugs and generates detailed fix implementation plans using Gemini 2.5 Pro. Performs root cause analysis, identifies affected files, and provides step-by-step remediation with code examples. For large projects, narrow scope to relevant subsystem (e.g., just authentication module or API layer) to stay within 200k token limit. Include error messages, stack traces, or reproduction steps in bug_description for best results.")]
+    async fn plan_bug_fix(&self, params: Parameters<BugFixParams>) -> Result<CallToolResult, McpError> {
+        tracing::info!("Received 'plan_bug_fix' request for directory: {}", params.0.directory);
+        let format = params.0.output_format.unwrap_or_default();
+        let history_files = params.0.history_files.as_deref().unwrap_or_default();
+        let bug_description = if params.0.history.unwrap_or(false) || !history_files.is_empty() {
+            match self.with_history(&params.0.directory, &params.0.context, &params.0.bug_description, history_files).await {
+                Ok(description) => description,
+                Err(e) => return Ok(e.into()),
+            }
+        } else {
+            params.0.bug_description.clone()
+        };
+
+        let result = self.execute(ToolRequest {
+            tool: "plan_bug_fix",
+            prompts: &llm::BUG_FIX_PLAN,
+            directory: &params.0.directory,
+            prompt: &bug_description,
+            context: &params.0.context,
+            format,
+            pinned: None,
+        }).await;
+
+        Ok(self.plan_output(result, format))
+    }
+
+    #[tool(description = "Plans a bug fix from a GitHub, GitLab, or Bitbucket Cloud issue using Gemini 2.5 Pro. Fetches the issue's title, labels, description, and comments through the forge's API (GITHUB_TOKEN, GITLAB_TOKEN, or BITBUCKET_TOKEN is used for private repositories and higher rate limits) and uses them as the bug description for plan_bug_fix's root cause analysis against the codebase.")]
+    async fn plan_bug_fix_from_issue(&self, params: Parameters<IssueBugFixParams>) -> Result<CallToolResult, McpError> {
+        tracing::info!("Received 'plan_bug_fix_from_issue' request for {} in directory: {}", params.0.issue_url, params.0.directory);
+        let format = params.0.output_format.unwrap_or_default();
+
+        let issue = match self.config.forges.issue(&params.0.issue_url).await {
+            Ok(issue) => issue,
+            Err(e) => return Ok(ToolError::new(ErrorKind::ExternalService, format!("Failed to fetch issue: {e:#}")).into()),
+        };
+        tracing::info!("Fetched issue '{}' with {} comments", issue.title, issue.comments.len());
+        let mut bug_description = issue.render();
+        if let Some(notes) = params.0.notes.as_deref().filter(|n| !n.trim().is_empty()) {
+            bug_description.push_str(&format!("\n## Notes from the requester\n\n{}\n", notes.trim()));
+        }
+        let history_files = params.0.history_files.as_deref().unwrap_or_default();
+        if params.0.history.unwrap_or(false) || !history_files.is_empty() {
+            bug_description = match self.with_history(&params.0.directory, &params.0.context, &bug_description, history_files).await {
+                Ok(description) => description,
+                Err(e) => return Ok(e.into()),
+            };
+        }
+
+        let result = self.execute(ToolRequest {
+            tool: "plan_bug_fix_from_issue",
+            prompts: &llm::BUG_FIX_PLAN,
+            directory: &params.0.directory,
+            prompt: &bug_description,
+            context: &params.0.context,
+            format,
+            pinned: None,
+        }).await;
+
+        Ok(self.plan_output(result, format))
+    }
+
+    #[tool(description = "Provides detailed technical explanations of codebase components using Gemini 2.5 Pro. Identifies key files, explains architecture patterns, data flow, and inter-component relationships with code examples. For large projects, target specific subsystems (e.g., 'explain the authentication system' vs 'explain the entire backend') to stay within 200k token limit. Best for onboarding, documentation, or understanding complex logic.")]
+    async fn explain_code(&self, params: Parameters<ExplanationParams>) -> Result<CallToolResult, McpError> {
+        tracing::info!("Received 'explain_code' request for directory: {}", params.0.directory);
+        let result = self.execute(ToolRequest {
+            tool: "explain_code",
+            prompts: &llm::EXPLANATION,
+            directory: &params.0.directory,
+            prompt: &params.0.explanation_query,
+            context: &params.0.context,
+            format: OutputFormat::Markdown,
+            pinned: None,
+        }).await;
+
+        Ok(text_output(result))
+    }
+
+    #[tool(description = "Generates architecture diagrams in Mermaid using Gemini 2.5 Pro: a component flowchart of module relationships and data flow, plus sequence diagrams for the main runtime flows, each mapped to files. The diagrams are syntax-checked before returning (with one automatic repair attempt) so editors can render them directly.")]
+    async fn explain_architecture(&self, params: Parameters<ArchitectureParams>) -> Result<CallToolResult, McpError> {
+        tracing::info!("Received 'explain_architecture' request for directory: {}", params.0.directory);
+        let prompt = match &params.0.focus {
+            Some(focus) => format!("Diagram the architecture, emphasizing: {focus}"),
+            None => "Diagram the overall architecture.".to_string(),
+        };
+
+        let result = self.execute(ToolRequest {
+            tool: "explain_architecture",
+            prompts: &llm::ARCHITECTURE_DIAGRAMS,
+            directory: &params.0.directory,
+            prompt: &prompt,
+            context: &params.0.context,
+            format: OutputFormat::Markdown,
+            pinned: None,
+        }).await;
+
+        let result = match result {
+            Ok(output) => Ok(self.repair_diagrams(output).await),
+            Err(e) => Err(e),
+        };
+        Ok(text_output(result))
+    }
+
+    // Gives the model one chance to fix diagrams that fail validation, in the
+    // same session, then notes any problems that remain.
+    async fn repair_diagrams(&self, mut output: ToolOutput) -> ToolOutput {
+        if output.dry_run {
+            return output;
+        }
+        let problems = mermaid::check(&output.text);
+        if problems.is_empty() {
+            return output;
+        }
+        tracing::warn!("Mermaid validation failed with {} problem(s); requesting a repair", problems.len());
+
+        let question = format!(
+            "The Mermaid diagrams in your answer failed validation:\n- {}\n\nReturn your complete answer again with every diagram corrected.",
+            problems.join("\n- ")
+        );
+        let Some(session) = self.config.sessions.get(&output.session_id) else {
+            return output;
+        };
+
+        let generation = self.config.generation.resolve(&session.tool, None);
+        let remaining = match self.config.gemini_client.follow_up(&session.context, &session.exchanges, &question, &generation).await {
+            Ok(answer) => {
+                let remaining = mermaid::check(&answer);
+                self.config.sessions.append(&output.session_id, Exchange { question, answer: answer.clone() }, None);
+                output.text = answer;
+                remaining
+            }
+            Err(e) => {
+                tracing::warn!("Mermaid repair request failed: {e}");
+                problems
+            }
+        };
+        if !remaining.is_empty() {
+            output.text.push_str(&format!(
+                "\n\n**Mermaid validation warnings** (these diagrams may not render):\n- {}",
+                remaining.join("\n- ")
+            ));
+        }
+        output
+    }
+
+    #[tool(description = "Plans GraphQL schema evolution using Gemini 2.5 Pro: new types/fields, deprecations, resolver changes, client impact analysis, and persisted-query updates, grounded in the repository's actual schema files (.graphql/.gql, sent ahead of the codebase report) and resolvers. Use on GraphQL-based repos; for large projects, point at the API service directory.")]
+    async fn plan_graphql_schema_change(&self, params: Parameters<GraphqlSchemaParams>) -> Result<CallToolResult, McpError> {
+        tracing::info!("Received 'plan_graphql_schema_change' request for directory: {}", params.0.directory);
+        let format = params.0.output_format.unwrap_or_default();
+
+        let result = self.execute(ToolRequest {
+            tool: "plan_graphql_schema_change",
+            prompts: &llm::GRAPHQL_SCHEMA_PLAN,
+            directory: &params.0.directory,
+            prompt: &params.0.schema_change,
+            context: &params.0.context,
+            format,
+            pinned: Some(&pipeline::GRAPHQL_SCHEMA_FILES),
+        }).await;
+
+        Ok(self.plan_output(result, format))
+    }
+
+    #[tool(description = "Plans a zero-downtime rollout for a described change using Gemini 2.5 Pro: expand/contract migration ordering, backward-compatible deploy phases, feature gating, health-check and readiness considerations, and explicit rollback triggers. Migrations, startup/health-check code, and infra manifests (Dockerfiles, Kubernetes/Helm, Terraform, CI deploy workflows) are sent ahead of the codebase report. For large projects, point at the deployable service directory.")]
+    async fn plan_zero_downtime_deploy(&self, params: Parameters<ZeroDowntimeDeployParams>) -> Result<CallToolResult, McpError> {
+        tracing::info!("Received 'plan_zero_downtime_deploy' request for directory: {}", params.0.directory);
+        let format = params.0.output_format.unwrap_or_default();
+
+        let result = self.execute(ToolRequest {
+            tool: "plan_zero_downtime_deploy",
+            prompts: &llm::ZERO_DOWNTIME_DEPLOY_PLAN,
+            directory: &params.0.directory,
+            prompt: &params.0.change_description,
+            context: &params.0.context,
+            format,
+            pinned: Some(&pipeline::DEPLOYMENT_FILES),
+        }).await;
+
+        Ok(self.plan_output(result, format))
+    }
+
+    #[tool(description = "Plans introducing multi-tenancy into a single-tenant codebase using Gemini 2.5 Pro: the data isolation strategy (shared schema with tenant ID, schema per tenant, or database per tenant) and its trade-offs, tenant ID threading from request entry points to data access, scoped queries with a guard against unscoped ones, auth and membership changes, tenant-aware caches, storage, and jobs, and migration steps that backfill existing data into a default tenant. Schemas, migrations, models, repositories, and auth code are sent ahead of the codebase report so the plan builds on the actual persistence and auth layers.")]
+    async fn plan_multitenancy_support(&self, params: Parameters<MultitenancyParams>) -> Result<CallToolResult, McpError> {
+        tracing::info!("Received 'plan_multitenancy_support' request for directory: {}", params.0.directory);
+        let format = params.0.output_format.unwrap_or_default();
+        let mut prompt = String::from("Plan introducing multi-tenancy into this single-tenant codebase.");
+        if let Some(requirements) = &params.0.requirements {
+            prompt.push_str(&format!(" Requirements: {requirements}"));
+        }
+
+        let result = self.execute(ToolRequest {
+            tool: "plan_multitenancy_support",
+            prompts: &llm::MULTITENANCY_PLAN,
+            directory: &params.0.directory,
+            prompt: &prompt,
+            context: &params.0.context,
+            format,
+            pinned: Some(&pipeline::PERSISTENCE_AND_AUTH_FILES),
+        }).await;
+
+        Ok(self.plan_output(result, format))
+    }
+
+    #[tool(description = "Plans rate limiting for a service using Gemini 2.5 Pro: analyzes its entry points (routes, handlers, consumers) and their cost, then proposes the algorithm (token bucket, sliding window, ...) and its trade-offs, limiter storage (in-process or shared, e.g. Redis) with atomic updates and failure behavior, limiter keys and a per-route budget table, complete middleware code for the detected framework, 429 responses with Retry-After and RateLimit headers, and tests. Dependency manifests, routers, handlers, controllers, and middleware are sent ahead of the codebase report so the design fits the actual framework and routes.")]
+    async fn plan_rate_limiting(&self, params: Parameters<RateLimitingParams>) -> Result<CallToolResult, McpError> {
+        tracing::info!("Received 'plan_rate_limiting' request for directory: {}", params.0.directory);
+        let format = params.0.output_format.unwrap_or_default();
+        let mut prompt = String::from("Design rate limiting for this codebase's entry points.");
+        if let Some(requirements) = &params.0.requirements {
+            prompt.push_str(&format!(" Requirements: {requirements}"));
+        }
+
+        let result = self.execute(ToolRequest {
+            tool: "plan_rate_limiting",
+            prompts: &llm::RATE_LIMITING_PLAN,
+            directory: &params.0.directory,
+            prompt: &prompt,
+            context: &params.0.context,
+            format,
+            pinned: Some(&pipeline::ENTRY_POINT_FILES),
+        }).await;
+
+        Ok(self.plan_output(result, format))
+    }
+
+    #[tool(description = "Plans authentication and authorization overhauls (adding SSO, migrating to OAuth2/OIDC, introducing RBAC) using Gemini 2.5 Pro: maps the existing auth flow from the code (credentials, sessions and tokens, identity model, where access is checked), then produces a staged migration plan with compatibility windows where old and new mechanisms run side by side, session and token handling across stages, account linking, a central default-deny authorization point, rollback, and tests. Login, session, token, identity, permission, and middleware code is sent ahead of the codebase report so the plan builds on the actual auth layer.")]
+    async fn plan_auth_overhaul(&self, params: Parameters<AuthOverhaulParams>) -> Result<CallToolResult, McpError> {
+        tracing::info!("Received 'plan_auth_overhaul' request for directory: {}", params.0.directory);
+        let format = params.0.output_format.unwrap_or_default();
+        let prompt = format!("Plan this authentication/authorization change: {}", params.0.change);
+
+        let result = self.execute(ToolRequest {
+            tool: "plan_auth_overhaul",
+            prompts: &llm::AUTH_OVERHAUL_PLAN,
+            directory: &params.0.directory,
+            prompt: &prompt,
+            context: &params.0.context,
+            format,
+            pinned: Some(&pipeline::AUTH_FILES),
+        }).await;
+
+        Ok(self.plan_output(result, format))
+    }
+
+    #[tool(description = "Plans migrating synchronous call paths to an event-driven architecture using Gemini 2.5 Pro: identifies candidate event boundaries (and paths that must stay synchronous), proposes versioned event schemas, outbox-based publishing, ordering and idempotency handling, failure handling, and an incremental side-by-side rollout. Dependency manifests and messaging code (brokers, queues, consumers/producers, outbox) are sent ahead of the codebase report so the plan uses the repo's existing messaging libraries.")]
+    async fn plan_event_driven_migration(&self, params: Parameters<EventDrivenMigrationParams>) -> Result<CallToolResult, McpError> {
+        tracing::info!("Received 'plan_event_driven_migration' request for directory: {}", params.0.directory);
+        let format = params.0.output_format.unwrap_or_default();
+        let prompt = match &params.0.scope {
+            Some(scope) => format!("Plan migrating these synchronous call paths to an event-driven architecture: {scope}"),
+            None => "Identify the synchronous call paths that would benefit most from an event-driven architecture and plan their migration.".to_string(),
+        };
+
+        let result = self.execute(ToolRequest {
+            tool: "plan_event_driven_migration",
+            prompts: &llm::EVENT_DRIVEN_MIGRATION_PLAN,
+            directory: &params.0.directory,
+            prompt: &prompt,
+            context: &params.0.context,
+            format,
+            pinned: Some(&pipeline::MESSAGING_FILES),
+        }).await;
+
+        Ok(self.plan_output(result, format))
+    }
+
+    #[tool(description = "Plans migrating between observability stacks (e.g. log4j to slf4j, custom metrics to OpenTelemetry) using Gemini 2.5 Pro: inventories every logging, metrics, and tracing call site by module, proposes a shim layer backed first by the old stack and then the new one, maps each API to its target equivalent, and sequences the changeover module by module with signal parity checks and rollback. Dependency manifests and logging, metrics, and tracing code are sent ahead of the codebase report.")]
+    async fn plan_telemetry_migration(&self, params: Parameters<TelemetryMigrationParams>) -> Result<CallToolResult, McpError> {
+        tracing::info!("Received 'plan_telemetry_migration' request for directory: {}", params.0.directory);
+        let format = params.0.output_format.unwrap_or_default();
+
+        let result = self.execute(ToolRequest {
+            tool: "plan_telemetry_migration",
+            prompts: &llm::TELEMETRY_MIGRATION_PLAN,
+            directory: &params.0.directory,
+            prompt: &params.0.migration,
+            context: &params.0.context,
+            format,
+            pinned: Some(&pipeline::TELEMETRY_FILES),
+        }).await;
+
+        Ok(self.plan_output(result, format))
+    }
+
+    #[tool(description = "Plans and drafts a client SDK for the API this repository exposes, in a requested language, using Gemini 2.5 Pro: endpoint-to-method surface mapping, typed models, auth handling, pagination and retries, and packaging layout, all consistent with the API's actual endpoints. API specification files (OpenAPI/Swagger, protobuf, GraphQL) are sent ahead of the codebase report. For large projects, point at the API service directory.")]
+    async fn plan_sdk_client_generation(&self, params: Parameters<SdkClientParams>) -> Result<CallToolResult, McpError> {
+        tracing::info!("Received 'plan_sdk_client_generation' request for directory: {}", params.0.directory);
+        let format = params.0.output_format.unwrap_or_default();
+        let prompt = match &params.0.requirements {
+            Some(requirements) => format!("Generate a {} client SDK. Additional requirements: {requirements}", params.0.language),
+            None => format!("Generate a {} client SDK covering the full API.", params.0.language),
+        };
+
+        let result = self.execute(ToolRequest {
+            tool: "plan_sdk_client_generation",
+            prompts: &llm::SDK_CLIENT_PLAN,
+            directory: &params.0.directory,
+            prompt: &prompt,
+            context: &params.0.context,
+            format,
+            pinned: Some(&pipeline::API_SPEC_FILES),
+        }).await;
+
+        Ok(self.plan_output(result, format))
+    }
+
+    #[tool(description = "Generates realistic mock data factories, fixtures, and seeding scripts using Gemini 2.5 Pro, in the repository's language and test framework and reusing its existing fixture libraries. Data model and schema files (ORM models, migrations, SQL, protobuf/GraphQL/OpenAPI) are sent ahead of the codebase report so generated values respect the real fields, relationships, and constraints.")]
+    async fn generate_mock_data(&self, params: Parameters<MockDataParams>) -> Result<CallToolResult, McpError> {
+        tracing::info!("Received 'generate_mock_data' request for directory: {}", params.0.directory);
+        let format = params.0.output_format.unwrap_or_default();
+        let prompt = match &params.0.scope {
+            Some(scope) => format!("Generate mock data factories, fixtures, and seeding scripts for: {scope}"),
+            None => "Generate mock data factories, fixtures, and seeding scripts for every data model.".to_string(),
+        };
+
+        let result = self.execute(ToolRequest {
+            tool: "generate_mock_data",
+            prompts: &llm::MOCK_DATA_PLAN,
+            directory: &params.0.directory,
+            prompt: &prompt,
+            context: &params.0.context,
+            format,
+            pinned: Some(&pipeline::DATA_MODEL_FILES),
+        }).await;
+
+        Ok(self.plan_output(result, format))
+    }
+
+    #[tool(description = "Generates tests for a planned or recent schema/data migration using Gemini 2.5 Pro: forward migration (the resulting schema), rollback (up, down, and up again restores the previous schema and keeps existing data), and data integrity invariants (rows seeded before the migration are copied, transformed, or backfilled correctly), wired into the repository's existing test framework and migration tooling. Migrations, schemas, dependency manifests, and test setup files (conftest, jest/vitest config, test helpers, fixtures) are sent ahead of the codebase report.")]
+    async fn generate_migration_tests(&self, params: Parameters<MigrationTestsParams>) -> Result<CallToolResult, McpError> {
+        tracing::info!("Received 'generate_migration_tests' request for directory: {}", params.0.directory);
+        let format = params.0.output_format.unwrap_or_default();
+        let prompt = match &params.0.migration {
+            Some(migration) => format!("Generate tests for this migration: {migration}"),
+            None => "Generate tests for the most recent migration in the codebase.".to_string(),
+        };
+
+        let result = self.execute(ToolRequest {
+            tool: "generate_migration_tests",
+            prompts: &llm::MIGRATION_TESTS_PLAN,
+            directory: &params.0.directory,
+            prompt: &prompt,
+            context: &params.0.context,
+            format,
+            pinned: Some(&pipeline::MIGRATION_TEST_FILES),
+        }).await;
+
+        Ok(self.plan_output(result, format))
+    }
+
+    #[tool(description = "Reverse-engineers a requirements/specification document from the codebase using Gemini 2.5 Pro: actors, features, business rules, edge-case behavior, data requirements, and integrations, each traced to the implementing code. Ideal for legacy systems with no documentation. For large systems, use 'scope' or point at one subsystem to stay within the token limit.")]
+    async fn reverse_engineer_requirements(&self, params: Parameters<RequirementsParams>) -> Result<CallToolResult, McpError> {
+        tracing::info!("Received 'reverse_engineer_requirements' request for directory: {}", params.0.directory);
+        let scope = params.0.scope.as_deref().unwrap_or("The entire system");
+
+        let result = self.execute(ToolRequest {
+            tool: "reverse_engineer_requirements",
+            prompts: &llm::REQUIREMENTS_SPEC,
+            directory: &params.0.directory,
+            prompt: scope,
+            context: &params.0.context,
+            format: OutputFormat::Markdown,
+            pinned: None,
+        }).await;
+
+        Ok(text_output(result))
+    }
+
+    #[tool(description = "Drafts an RFC (design document) for a proposed feature using Gemini 2.5 Pro, for team review before any implementation plan: problem statement, goals and non-goals, detailed design grounded in the existing code, alternatives considered, rollout, risks, and unresolved questions. Pass the team's RFC template in 'template' to have its structure followed exactly; otherwise an RFC template or earlier RFCs in the codebase are followed. Existing RFCs and design documents are sent ahead of the codebase report.")]
+    async fn draft_rfc(&self, params: Parameters<RfcParams>) -> Result<CallToolResult, McpError> {
+        tracing::info!("Received 'draft_rfc' request for directory: {}", params.0.directory);
+        let prompt = match &params.0.template {
+            Some(template) => format!("{}\n\nTeam RFC template (follow it exactly):\n\n{template}", params.0.feature_prompt),
+            None => params.0.feature_prompt.clone(),
+        };
+
+        let result = self.execute(ToolRequest {
+            tool: "draft_rfc",
+            prompts: &llm::RFC_DRAFT,
+            directory: &params.0.directory,
+            prompt: &prompt,
+            context: &params.0.context,
+            format: OutputFormat::Markdown,
+            pinned: Some(&pipeline::DESIGN_DOC_FILES),
+        }).await;
+
+        Ok(text_output(result))
+    }
+
+    #[tool(description = "Generates a ready-to-apply unified diff for a requested change using Gemini 2.5 Pro. The patch is parsed and every hunk is checked against the actual files; the response includes a validation report noting hunks that apply with an offset or do not apply. Best for focused, well-specified changes in small-medium codebases.")]
+    async fn generate_patch(&self, params: Parameters<PatchParams>) -> Result<CallToolResult, McpError> {
+        tracing::info!("Received 'generate_patch' request for directory: {}", params.0.directory);
+        let result = self.execute(ToolRequest {
+            tool: "generate_patch",
+            prompts: &llm::PATCH_GENERATION,
+            directory: &params.0.directory,
+            prompt: &params.0.change_request,
+            context: &params.0.context,
+            format: OutputFormat::Diff,
+            pinned: None,
+        }).await;
+
+        Ok(text_result(result.map(ToolOutput::into_text)))
+    }
+
+    #[tool(description = "Triage assistant: clusters a batch of issues (bug reports, feature requests) by the code areas they implicate using Gemini 2.5 Pro, identifying probable duplicates, shared root causes, and a suggested triage order. Pass the issue texts (e.g., the open issues from your tracker); each is mapped to files and functions in the codebase.")]
+    async fn cluster_related_issues(&self, params: Parameters<ClusterIssuesParams>) -> Result<CallToolResult, McpError> {
+        tracing::info!("Received 'cluster_related_issues' request for directory: {} ({} issues)", params.0.directory, params.0.issues.len());
+        if params.0.issues.is_empty() {
+            return Ok(ToolError::invalid_params("'issues' must contain at least one issue").into());
+        }
+
+        let issues = render_issues(&params.0.issues);
+        let result = self.execute(ToolRequest {
+            tool: "cluster_related_issues",
+            prompts: &llm::ISSUE_CLUSTERING,
+            directory: &params.0.directory,
+            prompt: &issues,
+            context: &params.0.context,
+            format: OutputFormat::Markdown,
+            pinned: None,
+        }).await;
+
+        Ok(text_output(result))
+    }
+
+    #[tool(description = "Audits a repository's dependencies using Gemini 2.5 Pro. Parses Cargo.toml, package.json, and requirements*.txt files into a dependency table (requirement, kind, source, whether the name appears in the code) plus the internal package graph, and returns upgrade risks, unused or redundant dependencies, security-relevant pins, manifest hygiene issues, and a suggested order of work.")]
+    async fn audit_dependencies(&self, params: Parameters<DependencyAuditParams>) -> Result<CallToolResult, McpError> {
+        tracing::info!("Received 'audit_dependencies' request for directory: {}", params.0.directory);
+
+        let manifests = {
+            let workspace = match workspace::open(&params.0.directory, params.0.context.commit.as_deref(), params.0.context.mirror, &self.config.workspace).await {
+                Ok(workspace) => workspace,
+                Err(e) => return Ok(ToolError::invalid_path(format!("Failed to prepare directory '{}': {e:#}", params.0.directory)).into()),
+            };
+            match external::collect_files(workspace.path()).await {
+                Ok(source_files) => manifests::parse_all(&source_files),
+                Err(e) => return Ok(ToolError::report(format!("Failed to read '{}': {e:#}", params.0.directory)).into()),
+            }
+        };
+        if manifests.is_empty() {
+            return Ok(ToolError::invalid_path(format!(
+                "No Cargo.toml, package.json, or requirements*.txt found in '{}'",
+                params.0.directory
+            )).into());
+        }
+
+        let mut prompt = String::from("Audit the dependencies of this codebase.");
+        if let Some(focus) = &params.0.focus {
+            prompt.push_str(&format!(" Focus on: {focus}"));
+        }
+        prompt.push_str(&format!("\n\nDependency manifests:\n\n{}", manifests::render(&manifests)));
+
+        let result = self.execute(ToolRequest {
+            tool: "audit_dependencies",
+            prompts: &llm::DEPENDENCY_AUDIT,
+            directory: &params.0.directory,
+            prompt: &prompt,
+            context: &params.0.context,
+            format: OutputFormat::Markdown,
+            pinned: None,
+        }).await;
+
+        Ok(text_output(result))
+    }
+
+    #[tool(description = "Reviews the LLM prompts and AI-integration code in a repository using Gemini 2.5 Pro: inventories every prompt and LLM call site, then audits them for prompt injection risks (untrusted input reaching instructions, unchecked output), cost pitfalls, and brittle output parsing, and proposes rewritten prompts and code fixes. Files that call LLM APIs or define prompts are sent ahead of the codebase report. Use on codebases that integrate LLMs.")]
+    async fn review_llm_prompts_in_repo(&self, params: Parameters<PromptReviewParams>) -> Result<CallToolResult, McpError> {
+        tracing::info!("Received 'review_llm_prompts_in_repo' request for directory: {}", params.0.directory);
+        let mut prompt = String::from("Review the LLM prompts and AI-integration code in this codebase.");
+        if let Some(focus) = &params.0.focus {
+            prompt.push_str(&format!(" Focus on: {focus}"));
+        }
+
+        let result = self.execute(ToolRequest {
+            tool: "review_llm_prompts_in_repo",
+            prompts: &llm::LLM_PROMPT_REVIEW,
+            directory: &params.0.directory,
+            prompt: &prompt,
+            context: &params.0.context,
+            format: OutputFormat::Markdown,
+            pinned: Some(&pipeline::LLM_INTEGRATION_FILES),
+        }).await;
+
+        Ok(text_output(result))
+    }
+
+    #[tool(description = "Drafts a blameless post-incident review from an incident timeline and logs using Gemini 2.5 Pro: matches each event in the record to the code and configuration that produced it, maps contributing causes to files and functions, identifies detection gaps (signals that were late or never emitted), and returns a prioritized list of preventive code and config changes with snippets. Logging, metrics, alerting, health check, retry, timeout, and configuration files are sent ahead of the codebase report.")]
+    async fn post_incident_review(&self, params: Parameters<IncidentReviewParams>) -> Result<CallToolResult, McpError> {
+        tracing::info!("Received 'post_incident_review' request for directory: {}", params.0.directory);
+        if params.0.incident.trim().is_empty() {
+            return Ok(ToolError::invalid_params("'incident' must contain the incident timeline or logs").into());
+        }
+        let mut prompt = String::new();
+        if let Some(impact) = &params.0.impact {
+            prompt.push_str(&format!("Impact: {impact}\n\n"));
+        }
+        prompt.push_str(&params.0.incident);
+
+        let result = self.execute(ToolRequest {
+            tool: "post_incident_review",
+            prompts: &llm::POST_INCIDENT_REVIEW,
+            directory: &params.0.directory,
+            prompt: &prompt,
+            context: &params.0.context,
+            format: OutputFormat::Markdown,
+            pinned: Some(&pipeline::OBSERVABILITY_FILES),
+        }).await;
+
+        Ok(text_output(result))
+    }
+
+    #[tool(description = "Finds single points of failure and missing resilience patterns using Gemini 2.5 Pro: inventories every external call (databases, caches, queues, HTTP and gRPC services, third-party APIs) with its timeouts, retries, circuit breakers, and fallbacks as configured in code and config, identifies components without redundancy and how failures propagate, and returns SPOFs ranked by risk with remediation code, configuration changes, and fault-injection checks. External call sites, resilience code, and configuration and deployment files are sent ahead of the codebase report.")]
+    async fn identify_single_points_of_failure(&self, params: Parameters<SpofParams>) -> Result<CallToolResult, McpError> {
+        tracing::info!("Received 'identify_single_points_of_failure' request for directory: {}", params.0.directory);
+        let mut prompt = String::from("Identify the single points of failure and missing resilience patterns in this codebase.");
+        if let Some(focus) = &params.0.focus {
+            prompt.push_str(&format!(" Focus on: {focus}"));
+        }
+
+        let result = self.execute(ToolRequest {
+            tool: "identify_single_points_of_failure",
+            prompts: &llm::SPOF_ANALYSIS,
+            directory: &params.0.directory,
+            prompt: &prompt,
+            context: &params.0.context,
+            format: OutputFormat::Markdown,
+            pinned: Some(&pipeline::RESILIENCE_FILES),
+        }).await;
+
+        Ok(text_output(result))
+    }
+
+    #[tool(description = "Writes conventional-commit messages and a changelog section for a git range (e.g. 'v1.4.0..HEAD') using Gemini 2.5 Pro: the server gathers the range's commit log, diffstat, and diff, and Gemini groups them into logical changes, classifies each (feat, fix, refactor, ...) with a scope and breaking changes, and returns one commit message per change, a squash message, and a Keep a Changelog section. Changelogs, contributing guides, and commit convention configs are sent ahead of the codebase report so the output follows the project's own style.")]
+    async fn summarize_changes(&self, params: Parameters<SummarizeChangesParams>) -> Result<CallToolResult, McpError> {
+        tracing::info!("Received 'summarize_changes' request for directory: {} ({})", params.0.directory, params.0.range);
+        if git::parse_remote(&params.0.directory).is_some() || archive::is_archive(Path::new(&params.0.directory)) {
+            return Ok(ToolError::invalid_path("summarize_changes needs a local git checkout; archives and git URLs have no history").into());
+        }
+
+        // Gathered without mirroring: the range needs the original git checkout.
+        let changes = {
+            let workspace = match workspace::open(&params.0.directory, params.0.context.commit.as_deref(), Some(false), &self.config.workspace).await {
+                Ok(workspace) => workspace,
+                Err(e) => return Ok(ToolError::invalid_path(format!("Failed to prepare directory '{}': {e:#}", params.0.directory)).into()),
+            };
+            match git::range_changes(workspace.path(), &params.0.range, MAX_CHANGE_DIFF_CHARS).await {
+                Ok(changes) => changes,
+                Err(e) => return Ok(ToolError::invalid_params(format!("Failed to read '{}' in '{}': {e:#}", params.0.range, params.0.directory)).into()),
+            }
+        };
+
+        let version = params.0.version.as_deref().unwrap_or("Unreleased");
+        let mut prompt = format!("Summarize the changes in `{}` for the changelog section '{version}'.\n\n", params.0.range);
+        prompt.push_str(&format!("## Commits (oldest first)\n\n{}\n\n", if changes.log.is_empty() { "(none)" } else { &changes.log }));
+        prompt.push_str(&format!("## Diffstat\n\n```\n{}\n```\n\n## Diff\n\n```diff\n{}\n```\n", changes.stat, changes.diff));
+        if !changes.omitted.is_empty() {
+            prompt.push_str(&format!("\nDiffs left out for size (see the diffstat): {}\n", changes.omitted.join(", ")));
+        }
+
+        let result = self.execute(ToolRequest {
+            tool: "summarize_changes",
+            prompts: &llm::CHANGE_SUMMARY,
+            directory: &params.0.directory,
+            prompt: &prompt,
+            context: &params.0.context,
+            format: OutputFormat::Markdown,
+            pinned: Some(&pipeline::CHANGE_CONVENTION_FILES),
+        }).await;
+
+        Ok(text_output(result))
+    }
+
+    #[tool(description = "Generates documentation for a codebase using Gemini 2.5 Pro: module-level docs, doc comments for public items in the language's native syntax, and a README draft, all following the project's existing conventions. By default returns markdown; with 'write' set, the documentation is written into the files (comments only - code is never changed) and the applied diff is returned.")]
+    async fn document_code(&self, params: Parameters<DocumentCodeParams>) -> Result<CallToolResult, McpError> {
+        tracing::info!("Received 'document_code' request for directory: {}", params.0.directory);
+        let write = params.0.write.unwrap_or(false);
+        if write
+            && (params.0.context.commit.is_some()
+                || archive::is_archive(Path::new(&params.0.directory))
+                || workspace::is_remote(&params.0.directory))
+        {
+            return Ok(ToolError::invalid_params("'write' requires a plain directory; it cannot be used with an archive, a git URL, or 'commit'").into());
+        }
+        if write && !self.config.allow_writes {
+            return Ok(ToolError::new(ErrorKind::Disabled, WRITES_DISABLED).into());
+        }
+
+        let prompt = match &params.0.scope {
+            Some(scope) => format!("Document this part of the codebase: {scope}"),
+            None => "Document the entire codebase.".to_string(),
+        };
+        let request = ToolRequest {
+            tool: "document_code",
+            prompts: &llm::DOCUMENTATION,
+            directory: &params.0.directory,
+            prompt: &prompt,
+            context: &params.0.context,
+            format: if write { OutputFormat::Json } else { OutputFormat::Markdown },
+            pinned: None,
+        };
+
+        if !write || request.context.dry_run.unwrap_or(false) {
+            return Ok(text_output(self.execute(request).await));
+        }
+        Ok(match self.execute(request).await {
+            Ok(output) => match write_documentation(Path::new(&params.0.directory), &output.text).await {
+                Ok(text) => CallToolResult::success(vec![Content::text(ToolOutput { text, ..output }.into_text())]),
+                Err(e) => e.into(),
+            },
+            Err(e) => e.into(),
+        })
+    }
+
+    #[tool(description = "Ranks tech-debt hotspots using Gemini 2.5 Pro: combines git churn (commits per file over 'since_days'), file size, and branching/nesting heuristics into a metrics table, then has the model confirm the hotspots against the code and return a ranked list with justification, suggested remediation, and a remediation order. Without git history, files are ranked by complexity alone.")]
+    async fn identify_tech_debt_hotspots(&self, params: Parameters<TechDebtParams>) -> Result<CallToolResult, McpError> {
+        tracing::info!("Received 'identify_tech_debt_hotspots' request for directory: {}", params.0.directory);
+        let since_days = params.0.since_days.unwrap_or(365);
+
+        // Measured without mirroring: churn needs the original git checkout.
+        let metrics = {
+            let workspace = match workspace::open(&params.0.directory, params.0.context.commit.as_deref(), Some(false), &self.config.workspace).await {
+                Ok(workspace) => workspace,
+                Err(e) => return Ok(ToolError::invalid_path(format!("Failed to prepare directory '{}': {e:#}", params.0.directory)).into()),
+            };
+            match hotspots::collect(workspace.path(), since_days).await {
+                Ok(metrics) => metrics,
+                Err(e) => return Ok(ToolError::report(format!("Failed to measure '{}': {e:#}", params.0.directory)).into()),
+            }
+        };
+        if metrics.is_empty() {
+            return Ok(ToolError::invalid_path(format!("No source files found in '{}'", params.0.directory)).into());
+        }
+
+        let mut prompt = format!("Rank the tech-debt hotspots in this codebase, with churn measured over the last {since_days} days.");
+        if let Some(focus) = &params.0.focus {
+            prompt.push_str(&format!(" Focus on: {focus}"));
+        }
+        prompt.push_str(&format!("\n\nFile metrics (highest score first):\n\n{}", hotspots::render_table(&metrics, HOTSPOT_TABLE_ROWS)));
+
+        let result = self.execute(ToolRequest {
+            tool: "identify_tech_debt_hotspots",
+            prompts: &llm::TECH_DEBT_HOTSPOTS,
+            directory: &params.0.directory,
+            prompt: &prompt,
+            context: &params.0.context,
+            format: OutputFormat::Markdown,
+            pinned: None,
+        }).await;
+
+        Ok(text_output(result))
+    }
+
+    #[tool(description = "Continues a previous conversation with Gemini about a codebase without regenerating or re-reading the codebase. Pass the session ID returned by any planning/explanation tool and a follow-up question; the cached codebase context and all prior exchanges in the session are reused. If files in the directory changed since the last call, only the added/modified files and the list of deleted files are sent along with the question, keeping plan-implement-replan loops cheap. Sessions expire after a period of inactivity.")]
+    async fn follow_up(&self, params: Parameters<FollowUpParams>) -> Result<CallToolResult, McpError> {
+        tracing::info!("Received 'follow_up' request for session: {}", params.0.session_id);
+        Ok(text_result(self.continue_session(&params.0.session_id, params.0.question).await))
+    }
+
+    pub(crate) async fn continue_session(&self, session_id: &str, question: String) -> Result<String, ToolError> {
+        let session = self.config.sessions.get(session_id).ok_or_else(|| {
+            ToolError::not_found(format!("Session '{session_id}' not found or expired. Start a new session with a planning or explanation tool."))
+        })?;
+        tracing::debug!("Continuing '{}' session for {}", session.tool, session.directory);
+
+        let (question, snapshot) = match &session.snapshot {
+            Some(previous) => {
+                let changes = self.with_codebase_changes(&session.directory, previous, question);
+                redact::scope(session.redacted, changes).await
+            }
+            None => (question, None),
+        };
+
+        let generation = self.config.generation.resolve(&session.tool, None);
+        let answer = self.config.gemini_client.follow_up(&session.context, &session.exchanges, &question, &generation);
+        let answer = audit::in_directory(&session.directory, answer)
+            .await
+            .map_err(|e| ToolError::llm("Failed to generate follow-up answer from Gemini", &e))?;
+
+        self.config.sessions.append(session_id, Exchange {
+            question,
+            answer: answer.clone(),
+        }, snapshot);
+
+        Ok(answer)
+    }
+
+    #[tool(description = "Finalizes a plan by answering its open questions. Every plan ends with explicit assumptions and open questions for the requester (returned as 'assumptions' and 'open_questions' in structured output, with questions numbered Q1, Q2, ...). Pass the plan's session ID and answers by question ID; Gemini revises the plan in the same session, resolving the answered questions and the assumptions they affect, and returns the complete finalized plan in the original format.")]
+    async fn answer_questions(&self, params: Parameters<AnswerQuestionsParams>) -> Result<CallToolResult, McpError> {
+        tracing::info!("Received 'answer_questions' request for session: {}", params.0.session_id);
+        if params.0.answers.is_empty() {
+            return Ok(ToolError::invalid_params("'answers' must contain at least one answer").into());
+        }
+        let Some(session) = self.config.sessions.get(&params.0.session_id) else {
+            return Ok(ToolError::not_found(format!(
+                "Session '{}' not found or expired. Start a new session with a planning tool.",
+                params.0.session_id
+            )).into());
+        };
+        let is_plan = pipeline::lookup(&session.tool).is_some_and(|p| p.prompts.manifest) || session.format == OutputFormat::Json;
+        if !is_plan || session.format == OutputFormat::Diff {
+            return Ok(ToolError::invalid_params(format!(
+                "Session '{}' is not a markdown or JSON plan from a planning tool",
+                params.0.session_id
+            )).into());
+        }
+
+        let latest = session.exchanges.last().map(|e| e.answer.as_str()).unwrap_or_default();
+        let open_questions = match session.format {
+            OutputFormat::Json => plan::parse_structured_plan(latest).map(|p| p.open_questions).unwrap_or_default(),
+            _ => plan::parse_clarifications(latest).open_questions,
+        };
+        if open_questions.is_empty() {
+            return Ok(ToolError::invalid_params("The latest plan in this session has no open questions").into());
+        }
+
+        let mut answered = String::new();
+        for answer in &params.0.answers {
+            let index = answer.id.trim().trim_start_matches(['Q', 'q']).parse::<usize>().ok().filter(|&i| (1..=open_questions.len()).contains(&i));
+            let Some(index) = index else {
+                return Ok(ToolError::invalid_params(format!(
+                    "Unknown question ID '{}'; the plan has questions Q1 to Q{}",
+                    answer.id,
+                    open_questions.len()
+                )).into());
+            };
+            answered.push_str(&format!("Q{index}. {}\nAnswer: {}\n\n", open_questions[index - 1], answer.answer));
+        }
+
+        let instructions = match session.format {
+            OutputFormat::Json => plan::json_instructions(),
+            _ => format!("{}\n\n{}\n\n{}", references::INSTRUCTIONS, plan::ASSUMPTIONS_INSTRUCTIONS, plan::MANIFEST_INSTRUCTIONS),
+        };
+        let question = format!(
+            "The requester answered some of your open questions:\n\n{answered}Produce the finalized plan. Apply these answers throughout, drop or revise the assumptions they resolve, and keep only the questions that are still unanswered (renumbered from Q1). Return the complete plan, not just the changes.\n\n{instructions}"
+        );
+
+        let generation = self.config.generation.resolve(&session.tool, None);
+        let answer = match self.config.gemini_client.follow_up(&session.context, &session.exchanges, &question, &generation).await {
+            Ok(answer) => answer,
+            Err(e) => return Ok(ToolError::llm("Failed to generate finalized plan from Gemini", &e).into()),
+        };
+        self.config.sessions.append(&params.0.session_id, Exchange { question, answer: answer.clone() }, None);
+
+        let prompt = format!("Finalize the plan with these answers:\n\n{}", answered.trim_end());
+        let plan_id = self.save_plan(&session.tool, &session.directory, &prompt, session.format, &params.0.session_id, &answer).await;
+        let output = ToolOutput {
+            text: answer,
+            session_id: params.0.session_id,
+            references: None,
+            plan_id,
+            usage: TokenUsage::default(),
+            feasibility: None,
+            paths: None,
+            dry_run: false,
+            published: None,
+        };
+        Ok(self.plan_output(Ok(output), session.format))
+    }
+
+    // Prefixes the question with the files that changed since the model last saw
+    // the directory, so iterative sessions send only the delta.
+    async fn with_codebase_changes(&self, directory: &str, previous: &files::Snapshot, question: String) -> (String, Option<files::Snapshot>) {
+        let source_files = match external::collect_files(Path::new(directory)).await {
+            Ok(source_files) => source_files,
+            Err(e) => {
+                tracing::warn!("Failed to check '{directory}' for changes: {e:#}");
+                return (question, None);
+            }
+        };
+
+        let changes = files::changes_since(previous, &source_files);
+        if changes.is_empty() {
+            return (question, None);
+        }
+        tracing::info!(
+            "Session directory changed: {} file(s) added or modified, {} deleted",
+            changes.changed.len(),
+            changes.deleted.len()
+        );
+
+        let mut update = String::from("The codebase has changed since your previous answer. Current contents of added or modified files:\n\n");
+        let (rendered, shown) = files::render_files_counted(&changes.changed, self.config.token_char_limit / 2);
+        update.push_str(&rendered);
+        let omitted = &changes.changed[shown..];
+        if !omitted.is_empty() {
+            update.push_str(&format!("\n{} more added or modified files did not fit and are not shown; do not assume their contents:\n", omitted.len()));
+            for file in omitted {
+                update.push_str(&format!("- `{}`\n", file.path.display()));
+            }
+        }
+        if !changes.deleted.is_empty() {
+            update.push_str("\nDeleted files:\n");
+            for path in &changes.deleted {
+                update.push_str(&format!("- `{}`\n", path.display()));
+            }
+        }
+
+        // Files that were not shown keep their previous state, so they count
+        // as changed again on the next follow-up.
+        let mut snapshot = files::snapshot(&source_files);
+        for file in omitted {
+            match previous.get(&file.path) {
+                Some(hash) => snapshot.insert(file.path.clone(), hash.clone()),
+                None => snapshot.remove(&file.path),
+            };
+        }
+        let question = format!("{update}\n\nQuestion: {question}");
+        (question, Some(snapshot))
+    }
+
+    #[tool(description = "Lists or re-runs requests that failed at the Gemini stage (provider outage, quota exhaustion, timeouts). Failed requests from every planning/analysis tool are persisted with their prompt and options. Call without arguments to list the queue, with 'id' to re-run one request (returns that tool's normal output), or with 'all' to re-run the whole queue. Successful replays are removed from the queue.")]
+    async fn replay_failed(&self, params: Parameters<ReplayFailedParams>) -> Result<CallToolResult, McpError> {
+        tracing::info!("Received 'replay_failed' request");
+        let queue = &self.config.failed_requests;
+
+        if let Some(id) = &params.0.id {
+            let Some(entry) = queue.get(id) else {
+                return Ok(ToolError::not_found(format!("No queued request with ID '{id}'")).into());
+            };
+            let result = self.replay(&entry).await;
+            return Ok(match pipeline::lookup(&entry.tool) {
+                Some(pipeline) if is_plan(pipeline.prompts, entry.format) => self.plan_output(result, entry.format),
+                _ => text_output(result),
+            });
+        }
+
+        let entries = queue.list();
+        if entries.is_empty() {
+            return Ok(CallToolResult::success(vec![Content::text("The failed request queue is empty.")]));
+        }
+
+        if !params.0.all.unwrap_or(false) {
+            let mut output = String::from("| ID | Tool | Directory | Attempts | Last error |\n|---|---|---|---|---|\n");
+            for entry in &entries {
+                output.push_str(&format!(
+                    "| `{}` | {} | {} | {} | {} |\n",
+                    entry.id, entry.tool, entry.directory, entry.attempts, entry.error.lines().next().unwrap_or_default()
+                ));
+            }
+            return Ok(CallToolResult::success(vec![Content::text(output)]));
+        }
+
+        let mut output = String::new();
+        for entry in &entries {
+            output.push_str(&format!("## Replay `{}` ({})\n\n", entry.id, entry.tool));
+            match self.replay(entry).await {
+                Ok(result) if entry.format == OutputFormat::Json => match plan::parse_structured_plan(&result.text) {
+                    Ok(plan) => {
+                        let plan_id = result.plan_id.clone().unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+                        self.plans.lock().unwrap().insert(plan_id.clone(), plan);
+                        output.push_str(&format!("Succeeded. Plan ID: `{plan_id}`, session ID: `{}`\n\n", result.session_id));
+                    }
+                    Err(e) => output.push_str(&format!("Gemini returned a plan that does not match the expected JSON schema: {e}\n\n")),
+                },
+                Ok(result) => output.push_str(&format!("{}\n\n", result.into_text())),
+                Err(e) => output.push_str(&format!("Failed again: {e}\n\n")),
+            }
+        }
+        Ok(CallToolResult::success(vec![Content::text(output)]))
+    }
+
+    #[tool(description = "Lists saved plans, newest first. Every plan generated by a planning tool (and every plan finalized with answer_questions) is saved with its tool, directory, request, model, and time, so it can be retrieved later with get_plan - e.g. by a teammate, or after its session expired - without regenerating it.")]
+    async fn list_plans(&self, params: Parameters<ListPlansParams>) -> String {
+        tracing::info!("Received 'list_plans' request");
+        let plans = self.config.saved_plans.list(params.0.tool.as_deref(), params.0.directory.as_deref());
+        if plans.is_empty() {
+            return "No saved plans match.".to_string();
+        }
+
+        let limit = params.0.limit.unwrap_or(20);
+        let mut output = String::from("| Plan ID | Tool | Directory | Format | Model | Created | Rating | Request |\n|---|---|---|---|---|---|---|---|\n");
+        for plan in plans.iter().take(limit) {
+            let request: String = plan.prompt.lines().next().unwrap_or_default().chars().take(80).collect();
+            let rating = match plan.ratings.len() {
+                0 => "-".to_string(),
+                n => format!("{:.1}/5 ({n})", plan.ratings.iter().map(|r| r.score as f64).sum::<f64>() / n as f64),
+            };
+            output.push_str(&format!(
+                "| `{}` | {} | {} | {} | {} | {} | {rating} | {} |\n",
+                plan.id, plan.tool, plan.directory, format_name(plan.format), plan.model, format_age(plan.created_at), request
+            ));
+        }
+        if plans.len() > limit {
+            output.push_str(&format!("\n{} older plan(s) not shown.", plans.len() - limit));
+        }
+        output
+    }
+
+    #[tool(description = "Retrieves a saved plan by ID (from a planning tool's output or list_plans) with its metadata: tool, directory, request, model, and time. JSON plans are returned as structured content and can be passed to apply_plan by ID. The plan's session is only available for follow_up if it has not expired.")]
+    async fn get_plan(&self, params: Parameters<GetPlanParams>) -> Result<CallToolResult, McpError> {
+        tracing::info!("Received 'get_plan' request for plan: {}", params.0.plan_id);
+        let Some(saved) = self.config.saved_plans.get(&params.0.plan_id) else {
+            return Ok(ToolError::not_found(format!("No saved plan with ID '{}'", params.0.plan_id)).into());
+        };
+
+        if saved.format == OutputFormat::Json {
+            return Ok(match plan::parse_structured_plan(&saved.text) {
+                Ok(plan) => {
+                    let stored = StoredPlan { plan_id: saved.id, session_id: saved.session_id, plan, feasibility: None, paths: None, published: None };
+                    CallToolResult::structured(serde_json::to_value(stored).unwrap_or_default())
+                }
+                Err(e) => ToolError::new(ErrorKind::InvalidOutput, format!("Saved plan is not a valid structured plan: {e}")).into(),
+            });
+        }
+
+        let session = if self.config.sessions.get(&saved.session_id).is_some() {
+            format!("Session ID: `{}` (still active; pass to `follow_up`)", saved.session_id)
+        } else {
+            "Session: expired".to_string()
+        };
+        let text = format!(
+            "**Plan `{}`** from `{}` on {} ({}, {})\n\nRequest: {}\n\n{session}\n\n---\n\n{}",
+            saved.id, saved.tool, saved.directory, saved.model, format_age(saved.created_at), saved.prompt, saved.text
+        );
+        Ok(CallToolResult::success(vec![Content::text(text)]))
+    }
+
+    #[tool(description = "Records a user's rating of a saved plan: a score from 1 (unusable) to 5 (implemented as written) and an optional comment. Ratings are stored with the plan and aggregated per tool, model, and prompt template version by plan_quality_report, to tune prompts and model choices with data. A plan can be rated more than once, e.g. after review and again after implementation.")]
+    async fn rate_plan(&self, params: Parameters<RatePlanParams>) -> Result<CallToolResult, McpError> {
+        tracing::info!("Received 'rate_plan' request for plan: {}", params.0.plan_id);
+        let RatePlanParams { plan_id, score, comment } = params.0;
+        if !(1..=5).contains(&score) {
+            return Ok(ToolError::invalid_params("'score' must be between 1 and 5").into());
+        }
+        let comment = comment.map(|c| c.trim().to_string()).filter(|c| !c.is_empty());
+        Ok(match self.config.saved_plans.rate(&plan_id, score, comment).await {
+            Ok(true) => CallToolResult::success(vec![Content::text(format!("Recorded a rating of {score}/5 for plan `{plan_id}`."))]),
+            Ok(false) => ToolError::not_found(format!("No saved plan with ID '{plan_id}'")).into(),
+            Err(e) => ToolError::new(ErrorKind::Internal, format!("Failed to save the rating: {e:#}")).into(),
+        })
+    }
+
+    #[tool(description = "Runs static feasibility checks on a plan without calling a model: every dependency the plan adds (Cargo.toml/package.json/requirements snippets, cargo add/npm install/pip install commands) must exist on crates.io, npm, or PyPI with a version matching the requested one, and every library API its code uses (Rust paths like tokio::sync::Semaphore, named JS/TS imports) must exist in the versions locked by the project's Cargo.lock or installed in node_modules. Reports infeasible items with the plan steps that rely on them, catching hallucinated packages and APIs before implementation starts.")]
+    async fn verify_plan_feasibility(&self, params: Parameters<VerifyFeasibilityParams>) -> Result<CallToolResult, McpError> {
+        tracing::info!("Received 'verify_plan_feasibility' request for directory: {}", params.0.directory);
+        let text = match (params.0.plan, &params.0.plan_id) {
+            (Some(plan), _) => plan,
+            (None, Some(id)) => match self.config.saved_plans.get(id) {
+                Some(saved) => saved.text,
+                None => match self.plans.lock().unwrap().get(id) {
+                    Some(plan) => serde_json::to_string(plan).unwrap_or_default(),
+                    None => return Ok(ToolError::not_found(format!("No plan found with ID '{id}'")).into()),
+                },
+            },
+            (None, None) => return Ok(ToolError::invalid_params("Either 'plan_id' or 'plan' must be provided").into()),
+        };
+
+        let workspace = match workspace::open(&params.0.directory, None, None, &self.config.workspace).await {
+            Ok(workspace) => workspace,
+            Err(e) => return Ok(ToolError::invalid_path(format!("Failed to prepare directory '{}': {e:#}", params.0.directory)).into()),
+        };
+        let report = match feasibility::check(&self.config.registries, workspace.path(), &text).await {
+            Ok(report) => report,
+            Err(e) => return Ok(ToolError::new(ErrorKind::ExternalService, format!("Feasibility check failed: {e:#}")).into()),
+        };
+
+        let mut result = CallToolResult::success(vec![Content::text(feasibility::render(&report))]);
+        result.structured_content = Some(serde_json::to_value(&report).unwrap_or_default());
+        Ok(result)
+    }
+
+    #[tool(description = "Materializes a structured plan (from a planning tool called with output_format 'json') as file changes in the target directory. Always run first without confirm_token: this is a dry run that returns the unified diff of every change plus a confirmation token, and writes nothing. Call again with the token to write the files. Created files need full content; modified files need exact find/replace edits - changes without them are reported as skipped for manual application.")]
+    async fn apply_plan(&self, params: Parameters<ApplyPlanParams>) -> Result<CallToolResult, McpError> {
+        tracing::info!("Received 'apply_plan' request for directory: {}", params.0.directory);
+        Ok(text_result(self.apply(params.0).await))
+    }
+
+    #[tool(description = "Diagnostic: summarizes the prompt canary - for each tool with candidate prompt templates, how many sampled requests were also run through the candidate, how often it failed, and the mean similarity, length ratio, and latency of its output versus the built-in templates. Use it to judge a prompt change before making it the default.")]
+    async fn prompt_canary_report(&self) -> Result<CallToolResult, McpError> {
+        tracing::info!("Received 'prompt_canary_report' request");
+        let Some(canary) = self.config.prompt_canary.as_ref() else {
+            return Ok(ToolError::new(ErrorKind::Disabled, "The prompt canary is not enabled; set PROMPT_CANARY_FILE to a file of candidate templates.").into());
+        };
+        let report = canary.report().await.map_err(|e| ToolError::new(ErrorKind::Internal, format!("Failed to read canary comparisons: {e:#}")));
+        Ok(text_result(report))
+    }
+
+    // apply_plan's dry run, or its write with a matching token.
+    async fn apply(&self, params: ApplyPlanParams) -> Result<String, ToolError> {
+        let directory = PathBuf::from(&params.directory);
+        workspace::check_local(&directory, &self.config.workspace)
+            .map_err(|e| ToolError::invalid_path(format!("Invalid directory '{}': {e:#}", params.directory)))?;
+
+        let plan = match (params.plan, &params.plan_id) {
+            (Some(plan), _) => plan,
+            (None, Some(id)) => match self.plans.lock().unwrap().get(id).cloned() {
+                Some(plan) => plan,
+                None => self.saved_structured_plan(id)?,
+            },
+            (None, None) => return Err(ToolError::invalid_params("Either 'plan_id' or 'plan' must be provided")),
+        };
+
+        let prepared = apply::prepare_plan(&directory, &plan)
+            .await
+            .map_err(|e| ToolError::invalid_params(format!("Failed to prepare plan changes: {e:#}")))?;
+        let token = prepared.confirmation_token();
+
+        let mut output = String::new();
+        if !prepared.skipped.is_empty() {
+            output.push_str("Skipped changes (apply manually):\n");
+            for skipped in &prepared.skipped {
+                output.push_str(&format!("- {skipped}\n"));
+            }
+            output.push('\n');
+        }
+
+        match params.confirm_token {
+            None => {
+                output.push_str(&format!(
+                    "DRY RUN - no files were written. {} change(s) prepared.\n\n```diff\n{}```\n\nTo write these changes, call apply_plan again with confirm_token: \"{token}\"",
+                    prepared.changes.len(),
+                    prepared.diff()
+                ));
+                Ok(output)
+            }
+            Some(_) if !self.config.allow_writes => Err(ToolError::new(ErrorKind::Disabled, WRITES_DISABLED)),
+            Some(confirm) if confirm == token => {
+                apply::write_plan(&directory, &prepared)
+                    .await
+                    .map_err(|e| ToolError::new(ErrorKind::Internal, format!("Failed to write plan changes: {e:#}")))?;
+                output.push_str(&format!("Applied {} change(s) to {}", prepared.changes.len(), directory.display()));
+                Ok(output)
+            }
+            Some(_) => Err(ToolError::invalid_params("Confirmation token does not match the current diff. The plan or files changed since the dry run; run a new dry run and review the diff.")),
+        }
+    }
+
+    #[tool(description = "Diagnostic: summarizes the ratings given with rate_plan, grouped by tool, model, and prompt template version (a fingerprint of the PROMPT_TEMPLATES_DIR templates, or 'built-in'): mean score, number of ratings, share of plans rated, and recent comments, lowest-scoring first. Use it to compare prompt templates and models on real feedback.")]
+    async fn plan_quality_report(&self) -> String {
+        tracing::info!("Received 'plan_quality_report' request");
+        let groups = self.config.saved_plans.quality();
+        if groups.is_empty() {
+            return "No plans have been rated yet; rate saved plans with rate_plan.".to_string();
+        }
+        let mut output = String::from("| Tool | Model | Templates | Mean score | Ratings | Plans rated | Recent comments |\n|---|---|---|---|---|---|---|\n");
+        for group in groups {
+            let template = group.template.map_or_else(|| "built-in".to_string(), |t| format!("`{t}`"));
+            let comments: Vec<String> = group.comments.iter().map(|c| c.replace(['\n', '|'], " ")).collect();
+            output.push_str(&format!(
+                "| {} | {} | {template} | {:.2} | {} | {} of {} | {} |\n",
+                group.tool,
+                group.model,
+                group.mean_score,
+                group.ratings,
+                group.rated_plans,
+                group.plans,
+                if comments.is_empty() { "-".to_string() } else { comments.join("; ") }
+            ));
+        }
+        output
+    }
+
+    #[tool(description = "Diagnostic: reports Gemini API token usage since the server started - requests, prompt and completion tokens, and estimated cost in USD - totaled and broken down per tool, per API key (shown by its last 4 characters), and per model. Costs are estimates from the server's price table (MODEL_PRICES). When fair-share quotas are enabled, also lists each session's or client's usage in the current quota window. Also reports codebase_viewer runs: failures, timeouts, cancellations, duration, and peak memory.")]
+    async fn get_usage_stats(&self) -> String {
+        tracing::info!("Received 'get_usage_stats' request");
+        let client = &self.config.gemini_client;
+        let mut report = client.usage().report();
+        if let Some(fair_share) = client.fair_share() {
+            report.push_str(&format!(
+                "\n## Fair-Share Quotas\n\nEach session or client may use {} tokens per {}s.\n\n| Consumer | Tokens in window | Share of quota |\n|---|---|---|\n",
+                fair_share.quota(),
+                fair_share.window().as_secs()
+            ));
+            for consumer in fair_share.usage() {
+                let share = consumer.used as f64 / fair_share.quota().max(1) as f64 * 100.0;
+                report.push_str(&format!("| `{}` | {} | {share:.0}% |\n", consumer.consumer, consumer.used));
+            }
+        }
+        if let Some(processes) = self.config.process_metrics.report() {
+            report.push('\n');
+            report.push_str(&processes);
+        }
+        report
+    }
+
+    #[tool(description = "Diagnostic: reports the health of each configured Gemini API key (shown by its last 4 characters) - successes, failures, last error class, and whether it is currently quarantined. Keys that are revoked, over quota, or failing repeatedly are quarantined with an exponential cool-down and skipped in rotation.")]
+    async fn key_health(&self) -> String {
+        tracing::info!("Received 'key_health' request");
+        let mut output = String::from("| Key | Status | Successes | Failures | Consecutive | Last error |\n|---|---|---|---|---|---|\n");
+        for key in self.config.gemini_client.key_health() {
+            let status = match key.quarantined_for {
+                Some(remaining) => format!("quarantined ({}s left)", remaining.as_secs()),
+                None => "healthy".to_string(),
+            };
+            let last_error = key.last_error.map(|e| e.to_string()).unwrap_or_else(|| "-".to_string());
+            output.push_str(&format!(
+                "| `{}` | {} | {} | {} | {} | {} |\n",
+                key.key, status, key.successes, key.failures, key.consecutive_failures, last_error
+            ));
+        }
+        output
+    }
+
+    #[tool(description = "Diagnostic: checks the server's setup without a planning call. Runs codebase_viewer on a one-file sample, lists the models with every API key (no tokens are used) to confirm each one authenticates, and reports the server, model, and codebase_viewer versions. Key health is not affected. Returns a table and the same report as structured content, with 'healthy' true only if every check passed.")]
+    async fn health_check(&self) -> Result<CallToolResult, McpError> {
+        tracing::info!("Received 'health_check' request");
+        let report = self.health().await;
+        let mut result = CallToolResult::success(vec![Content::text(report.render())]);
+        result.structured_content = Some(serde_json::to_value(&report).unwrap_or_default());
+        Ok(result)
+    }
+
+    pub(crate) async fn health(&self) -> HealthReport {
+        health::check(&self.config).await
+    }
+
+    pub(crate) async fn cached_health(&self) -> HealthReport {
+        self.config.health_cache.get(&self.config, self.config.health_cache_ttl).await
+    }
+
+    #[tool(description = "Diagnostic: lists the models the configured provider serves, with their context window and output token limits where the provider reports them, to choose GEMINI_MODEL or MODEL_FALLBACKS entries. Queries the provider's model listing (no tokens are used); models that cannot answer chat requests, such as embedding models, are left out. Returns a table and the same list as structured content under 'models'.")]
+    async fn list_models(&self) -> Result<CallToolResult, McpError> {
+        tracing::info!("Received 'list_models' request");
+        let client = &self.config.gemini_client;
+        let models = match client.models().await {
+            Ok(models) => models,
+            Err(e) => return Ok(ToolError::llm("Failed to list models", &e).into()),
+        };
+        let tokens = |limit: Option<u64>| limit.map(|t| t.to_string()).unwrap_or_else(|| "-".to_string());
+        let mut output = format!("Models served by {}; GEMINI_MODEL is `{}`.\n\n| Model | Name | Context window | Output limit |\n|---|---|---|---|\n", client.api_base(), client.model());
+        for model in &models {
+            let current = if model.id == client.model() { " (current)" } else { "" };
+            output.push_str(&format!(
+                "| `{}`{} | {} | {} | {} |\n",
+                model.id,
+                current,
+                model.display_name.as_deref().unwrap_or("-"),
+                tokens(model.context_window),
+                tokens(model.output_limit)
+            ));
+        }
+        let mut result = CallToolResult::success(vec![Content::text(output)]);
+        result.structured_content = Some(serde_json::json!({ "models": models }));
+        Ok(result)
+    }
+
+    #[tool(description = "Admin: collects the server's disk garbage now instead of waiting for the background pass (GC_INTERVAL_SECS). Evicts the least recently written embedding indexes while the cache exceeds MAX_CACHE_BYTES, removes workspaces (archives, mirrors, clones, and commit worktrees) left by crashed calls once older than MAX_CLONE_AGE_SECS, and removes orphaned codebase_viewer temp files. Returns what was removed per store and the bytes freed; 'dry_run' only reports it.")]
+    async fn gc(&self, params: Parameters<GcParams>) -> Result<CallToolResult, McpError> {
+        tracing::info!("Received 'gc' request");
+        let config = self.config.gc.clone();
+        let dry_run = params.0.dry_run.unwrap_or(false);
+        let report = match tokio::task::spawn_blocking(move || gc::collect(&config, dry_run).render(&config)).await {
+            Ok(report) => report,
+            Err(e) => return Ok(ToolError::new(ErrorKind::Internal, format!("Garbage collection panicked: {e}")).into()),
+        };
+        Ok(CallToolResult::success(vec![Content::text(report)]))
+    }
+
+    // A JSON plan from the plan store, e.g. one generated before a restart.
+    fn saved_structured_plan(&self, id: &str) -> Result<StructuredPlan, ToolError> {
+        let saved = self.config.saved_plans.get(id).ok_or_else(|| ToolError::not_found(format!("No plan found with ID '{id}'")))?;
+        if saved.format != OutputFormat::Json {
+            return Err(ToolError::invalid_params(format!(
+                "Plan '{id}' is a {} plan; apply_plan needs a plan generated with output_format 'json'",
+                format_name(saved.format)
+            )));
+        }
+        plan::parse_structured_plan(&saved.text)
+            .map_err(|e| ToolError::new(ErrorKind::InvalidOutput, format!("Saved plan '{id}' is not a valid structured plan: {e}")))
+    }
+
+    fn plan_output(&self, result: Result<ToolOutput, ToolError>, format: OutputFormat) -> CallToolResult {
+        let output = match result {
+            Ok(output) => output,
+            Err(e) => return e.into(),
+        };
+        if output.dry_run {
+            return CallToolResult::success(vec![Content::text(output.text)]);
+        }
+
+        match format {
+            OutputFormat::Markdown => {
+                let manifest = plan::parse_manifest(&output.text);
+                if manifest.is_none() {
+                    tracing::warn!("Plan did not end with a parseable change manifest");
+                }
+                let clarifications = plan::parse_clarifications(&output.text);
+                let mut structured = serde_json::json!({
+                    "session_id": output.session_id,
+                    "plan_id": output.plan_id,
+                    "manifest": manifest,
+                    "assumptions": clarifications.assumptions,
+                    "open_questions": clarifications.open_questions,
+                });
+                if let Some(references) = &output.references {
+                    structured["references"] = serde_json::json!(references);
+                }
+                if let Some(feasibility) = &output.feasibility {
+                    structured["feasibility"] = serde_json::json!(feasibility);
+                }
+                if let Some(paths) = &output.paths {
+                    structured["paths"] = serde_json::json!(paths);
+                }
+                if let Some(published) = &output.published {
+                    structured["published"] = serde_json::json!(published);
+                }
+                let mut result = CallToolResult::success(vec![Content::text(output.into_text())]);
+                result.structured_content = Some(structured);
+                result
+            }
+            OutputFormat::Diff => CallToolResult::success(vec![Content::text(output.into_text())]),
+            OutputFormat::Json => match plan::parse_structured_plan(&output.text) {
+                Ok(plan) => {
+                    let plan_id = output.plan_id.unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+                    self.plans.lock().unwrap().insert(plan_id.clone(), plan.clone());
+                    let stored = StoredPlan {
+                        plan_id,
+                        session_id: output.session_id,
+                        plan,
+                        feasibility: output.feasibility,
+                        paths: output.paths,
+                        published: output.published,
+                    };
+                    CallToolResult::structured(serde_json::to_value(stored).unwrap_or_default())
+                }
+                Err(e) => ToolError::new(ErrorKind::InvalidOutput, format!(
+                    "Gemini returned a plan that does not match the expected JSON schema: {e}"
+                )).into(),
+            },
+        }
+    }
+
+    // Files `prompt` names by path or module, or whose identifiers it
+    // mentions (`search::mentioned_files`), so they are sent whichever
+    // context mode is used and however the report is trimmed. `label` names
+    // the directory when several are analyzed together.
+    async fn mentioned_files_section(&self, directory: &Path, label: Option<&str>, prompt: &str, char_limit: usize) -> String {
+        let all = match external::collect_files(directory).await {
+            Ok(all) => all,
+            Err(e) => {
+                tracing::warn!("Failed to collect the files mentioned in the request from '{}': {e:#}", directory.display());
+                return String::new();
+            }
+        };
+        let prompt = prompt.to_string();
+        let max_files = self.config.embedding.top_k;
+        let label = label.map(|l| format!(" from `{l}`")).unwrap_or_default();
+        let section = tokio::task::spawn_blocking(move || {
+            let mentioned = search::mentioned_files(&all, &prompt, max_files);
+            if mentioned.is_empty() {
+                return String::new();
+            }
+            let mut list = String::new();
+            for found in &mentioned {
+                list.push_str(&format!("- `{}` ({})\n", found.file.path.display(), found.reason));
+            }
+            let refs: Vec<_> = mentioned.iter().map(|found| found.file).collect();
+            let rendered = files::render_files(&refs, char_limit.saturating_sub(list.len()));
+            format!("Files mentioned in the request{label}:\n{list}\n{rendered}\n\n")
+        })
+        .await;
+        section.unwrap_or_else(|e| {
+            tracing::warn!("Searching for the files mentioned in the request failed: {e}");
+            String::new()
+        })
+    }
+
+    // `label` names the directory when several are analyzed together.
+    async fn pinned_files_section(&self, directory: &Path, label: Option<&str>, pinned: &PinnedFiles, char_limit: usize) -> String {
+        let matching: Vec<_> = match external::collect_files(directory).await {
+            Ok(all) => all
+                .into_iter()
+                .filter(|f| pinned.matches(f))
+                .collect(),
+            Err(e) => {
+                tracing::warn!("Failed to collect {} from '{}': {e:#}", pinned.title, directory.display());
+                return String::new();
+            }
+        };
+
+        if matching.is_empty() {
+            return String::new();
+        }
+
+        let refs: Vec<_> = matching.iter().collect();
+        let title = match label {
+            Some(label) => format!("{} from `{label}`", pinned.title),
+            None => pinned.title.to_string(),
+        };
+        format!("{title}:\n{}\n\n", files::render_files(&refs, char_limit))
+    }
+}
+
+// The workspace map, empty unless `directory` is the root of a multi-package
+// repository, and the language map, empty unless it mixes languages.
+async fn repository_map_sections(directory: &Path, label: Option<&str>) -> String {
+    match external::collect_files(directory).await {
+        Ok(source_files) => {
+            let mut sections = String::new();
+            let packages = packages::detect(&source_files);
+            if !packages.is_empty() {
+                tracing::info!("Detected {} workspace package(s) in '{}'", packages.len(), directory.display());
+                sections.push_str(&packages::render(&packages, label));
+            }
+            let languages = languages::detect(&source_files);
+            if !languages.is_empty() {
+                let names: Vec<&str> = languages.iter().map(|l| l.language.name).collect();
+                tracing::info!("Detected languages {names:?} in '{}'", directory.display());
+                sections.push_str(&languages::render(&languages, label));
+            }
+            sections
+        }
+        Err(e) => {
+            tracing::warn!("Failed to map the repository '{}': {e:#}", directory.display());
+            String::new()
+        }
+    }
+}
+
+fn budget_error(estimate: &PipelineEstimate, budget: u64, passes: u8, reason: &str) -> ToolError {
+    let escalation = match estimate.runs {
+        1 => String::new(),
+        runs => format!(", times {runs} for model escalation"),
+    };
+    let message = format!(
+        "Estimated usage of ~{} tokens exceeds max_total_tokens ({budget}), and {reason}: the ~{}-token codebase context is sent {} time(s) over {passes} pass(es), plus ~{} tokens of prompts and reserved output{escalation}. \
+         Lower 'passes', set 'compact_detail', 'use_retrieval', 'skeleton', or 'file_selection', point 'directory' at a subdirectory, or raise the budget.",
+        estimate.total(),
+        estimate.context_tokens,
+        estimate.context_sends,
+        estimate.fixed_tokens,
+    );
+    ToolError::context_too_large(message, estimate.total(), budget)
+}
+
+// Outputs that are saved to the plan store.
+fn is_plan(prompts: &PipelinePrompts, format: OutputFormat) -> bool {
+    prompts.manifest || format == OutputFormat::Json
+}
+
+fn format_name(format: OutputFormat) -> &'static str {
+    match format {
+        OutputFormat::Markdown => "markdown",
+        OutputFormat::Json => "json",
+        OutputFormat::Diff => "diff",
+    }
+}
+
+fn format_age(timestamp: u64) -> String {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    match now.saturating_sub(timestamp) {
+        secs if secs < 60 => "just now".to_string(),
+        secs if secs < 3600 => format!("{}m ago", secs / 60),
+        secs if secs < 86_400 => format!("{}h ago", secs / 3600),
+        secs => format!("{}d ago", secs / 86_400),
+    }
+}
+
+fn render_issues(issues: &[IssueInput]) -> String {
+    let mut out = String::new();
+    for (i, issue) in issues.iter().enumerate() {
+        let id = issue.id.clone().unwrap_or_else(|| format!("#{}", i + 1));
+        match &issue.title {
+            Some(title) => out.push_str(&format!("\n\n### Issue {id}: {title}\n\n{}", issue.body)),
+            None => out.push_str(&format!("\n\n### Issue {id}\n\n{}", issue.body)),
+        }
+    }
+    out
+}
+
+// Markdown for a dry run: context statistics, then every pass's messages.
+// Files under `root` whose relative paths appear in `text`, in order of first
+// mention. Absolute paths in stack traces match by their relative suffix.
+async fn mentioned_files(root: &Path, text: &str) -> anyhow::Result<Vec<String>> {
+    let walk_root = root.to_path_buf();
+    let paths = tokio::task::spawn_blocking(move || files::source_paths(&walk_root)).await??;
+    let mut mentioned: Vec<(usize, String)> = paths
+        .iter()
+        .filter_map(|(path, _)| {
+            let relative = path.strip_prefix(root).ok()?.to_string_lossy().replace('\\', "/");
+            text.find(&relative).map(|position| (position, relative))
+        })
+        .collect();
+    mentioned.sort();
+    Ok(mentioned.into_iter().map(|(_, path)| path).collect())
+}
+
+fn render_dry_run(preview: &PipelinePreview, context: &Segments, estimate: &PipelineEstimate, char_limit: usize, options: PipelineOptions<'_>) -> String {
+    // The marker is written inside the report, which is a single piece.
+    let truncated = context.parts().any(|part| part.contains("TRUNCATED DUE TO TOKEN LIMIT"));
+    let mut out = format!(
+        "## Dry Run\n\nNothing was sent to Gemini. These are the requests this call would make.\n\n\
+         | | |\n|---|---|\n\
+         | Context | {} characters (~{} tokens) of the {char_limit}-character limit |\n\
+         | Truncated | {} |\n\
+         | Passes | {} |\n\
+         | Models | {} |\n\
+         | Estimated tokens for the call | up to ~{} |\n",
+        context.len(),
+        estimate.context_tokens,
+        if truncated { "yes - files beyond the limit were left out" } else { "no" },
+        preview.passes.iter().map(|p| p.pass).collect::<Vec<_>>().join(", "),
+        preview.models.iter().map(|m| format!("`{m}`")).collect::<Vec<_>>().join(" then "),
+        estimate.total()
+    );
+    if options.compact.is_some() && preview.passes.len() > 1 {
+        out.push_str("\nWith compact_detail, the passes after the analysis would receive only the files it references instead of the full context shown here.\n");
+    }
+    if let Some(max_tokens) = options.verbosity.max_output_tokens() {
+        out.push_str(&format!("\nWith verbosity `{}`, the final pass's response would be capped at {max_tokens} tokens.\n", options.verbosity.name()));
+    }
+    let generation = options.generation.describe();
+    if !generation.is_empty() {
+        out.push_str(&format!("\nGeneration parameters: {generation}.\n"));
+    }
+    for (i, pass) in preview.passes.iter().enumerate() {
+        out.push_str(&format!(
+            "\n### Pass {}: {}\n\n#### System\n\n{}\n\n#### User\n\n{}\n",
+            i + 1,
+            pass.pass,
+            files::fenced("text", &pass.system),
+            files::fenced("text", &pass.user.to_string())
+        ));
+    }
+    out
+}
+
+fn text_result(result: Result<String, ToolError>) -> CallToolResult {
+    match result {
+        Ok(text) => CallToolResult::success(vec![Content::text(text)]),
+        Err(e) => e.into(),
+    }
+}
+
+fn text_output(result: Result<ToolOutput, ToolError>) -> CallToolResult {
+    let output = match result {
+        Ok(output) => output,
+        Err(e) => return e.into(),
+    };
+
+    let structured = output.references.as_ref().map(|references| {
+        serde_json::json!({
+            "session_id": output.session_id,
+            "references": references,
+        })
+    });
+    let mut result = CallToolResult::success(vec![Content::text(output.into_text())]);
+    result.structured_content = structured;
+    result
+}
+
+async fn validate_diff(root: &Path, output: &str) -> Result<String, ToolError> {
+    let diff = patch::extract_diff(output);
+    let patches = patch::parse_unified_diff(&diff).map_err(|e| {
+        ToolError::new(ErrorKind::InvalidOutput, format!("Gemini did not return a valid unified diff: {e:#}\n\nRaw response:\n{output}"))
+    })?;
+    let checks = patch::check_patches(root, &patches).await;
+
+    Ok(format!("```diff\n{}```\n\n{}", diff, patch::render_checks(&checks)))
+}
+
+// Applies documentation returned as a structured plan. Changes that cannot
+// be applied exactly, or would touch more than comments, are reported rather
+// than failing the whole write.
+async fn write_documentation(directory: &Path, json: &str) -> Result<String, ToolError> {
+    let plan = plan::parse_structured_plan(json)
+        .map_err(|e| ToolError::new(ErrorKind::InvalidOutput, format!("Gemini returned documentation that does not match the expected JSON schema: {e}")))?;
+    let prepared = apply::prepare_documentation(directory, &plan)
+        .await
+        .map_err(|e| ToolError::new(ErrorKind::Internal, format!("Failed to prepare documentation changes: {e:#}")))?;
+    apply::write_plan(directory, &prepared)
+        .await
+        .map_err(|e| ToolError::new(ErrorKind::Internal, format!("Failed to write documentation: {e:#}")))?;
+
+    let mut output = format!("{}\n\nWrote {} file change(s) to {}.\n", plan.summary, prepared.changes.len(), directory.display());
+    if !prepared.skipped.is_empty() {
+        output.push_str("\nSkipped changes (apply manually):\n");
+        for skipped in &prepared.skipped {
+            output.push_str(&format!("- {skipped}\n"));
+        }
+    }
+    output.push_str(&format!("\n```diff\n{}```", prepared.diff()));
+    Ok(output)
+}
+
+const REPORT_SCHEME: &str = "report://";
+const PLAN_SCHEME: &str = "plan://";
+
+// Resource ID of a directory's cached report: a hash, since directories can
+// be paths or URLs that do not fit in a URI as-is.
+fn report_id(directory: &str) -> String {
+    files::content_hash(directory)[..16].to_string()
+}
+
+fn plan_mime_type(format: OutputFormat) -> &'static str {
+    match format {
+        OutputFormat::Markdown => "text/markdown",
+        OutputFormat::Json => "application/json",
+        OutputFormat::Diff => "text/x-diff",
+    }
+}
+
+impl ServerHandler for CodeAgentServer {
+    // Every tool call is a usage scope, so `get_usage_stats` can attribute
+    // API usage to the tool that caused it, and runs as a fair-share consumer:
+    // the session it continues, or else the MCP client.
+    async fn call_tool(&self, request: CallToolRequestParam, context: RequestContext<RoleServer>) -> Result<CallToolResult, McpError> {
+        let tool = request.name.to_string();
+        let session = request.arguments.as_ref().and_then(|args| args.get("session_id")).and_then(|id| id.as_str());
+        let consumer = match (session, context.peer.peer_info()) {
+            (Some(session), _) => format!("session {session}"),
+            (None, Some(client)) => format!("client {}", client.client_info.name),
+            (None, None) => "client".to_string(),
+        };
+        let call = self.tool_router.call(ToolCallContext::new(self, request, context));
+        fairness::as_consumer(consumer, usage::scope(&tool, call)).await.0
+    }
+
+    async fn list_tools(&self, _request: Option<PaginatedRequestParam>, _context: RequestContext<RoleServer>) -> Result<ListToolsResult, McpError> {
+        Ok(ListToolsResult::with_all_items(self.tool_router.list_all()))
+    }
+
+    fn get_info(&self) -> ServerInfo {
+        ServerInfo {
+            capabilities: ServerCapabilities::builder().enable_tools().enable_resources().build(),
+            ..Default::default()
+        }
+    }
+
+    // Reports are the context of each directory's most recent live session
+    // (exactly what the model saw); plans come from the plan store.
+    async fn list_resources(&self, _request: Option<PaginatedRequestParam>, _context: RequestContext<RoleServer>) -> Result<ListResourcesResult, McpError> {
+        let mut resources = Vec::new();
+        for session in self.config.sessions.latest_by_directory() {
+            let mut resource = RawResource::new(format!("{REPORT_SCHEME}{}", report_id(&session.directory)), format!("Codebase report: {}", session.directory));
+            resource.description = Some(format!("Context sent to Gemini for the latest '{}' call on {}", session.tool, session.directory));
+            resource.mime_type = Some("text/markdown".to_string());
+            resource.size = u32::try_from(session.context.len()).ok();
+            resources.push(resource.no_annotation());
+        }
+        for saved in self.config.saved_plans.list(None, None) {
+            let request: String = saved.prompt.lines().next().unwrap_or_default().chars().take(80).collect();
+            let mut resource = RawResource::new(format!("{PLAN_SCHEME}{}", saved.id), format!("{} plan: {request}", saved.tool));
+            resource.description = Some(format!("{} on {} ({}, {})", saved.tool, saved.directory, saved.model, format_age(saved.created_at)));
+            resource.mime_type = Some(plan_mime_type(saved.format).to_string());
+            resource.size = u32::try_from(saved.text.len()).ok();
+            resources.push(resource.no_annotation());
+        }
+        Ok(ListResourcesResult { resources, next_cursor: None })
+    }
+
+    async fn read_resource(&self, request: ReadResourceRequestParam, _context: RequestContext<RoleServer>) -> Result<ReadResourceResult, McpError> {
+        let uri = request.uri;
+        let text = if let Some(id) = uri.strip_prefix(REPORT_SCHEME) {
+            self.config.sessions
+                .latest_by_directory()
+                .into_iter()
+                .find(|s| report_id(&s.directory) == id)
+                .map(|s| s.context.to_string())
+        } else if let Some(id) = uri.strip_prefix(PLAN_SCHEME) {
+            self.config.saved_plans.get(id).map(|p| p.text)
+        } else {
+            None
+        };
+
+        match text {
+            Some(text) => Ok(ReadResourceResult { contents: vec![ResourceContents::text(text, uri)] }),
+            None => Err(McpError::resource_not_found(
+                format!("No resource '{uri}'; reports expire with their session"),
+                None,
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{Harness, TempRepo};
+
+    #[tokio::test]
+    async fn condensing_keeps_git_history_verbatim() {
+        let harness = Harness::builder()
+            .var("PROMPT_CHAR_LIMIT", "1000")
+            .respond("Condense this", "CONDENSED DESCRIPTION")
+            .build()
+            .unwrap();
+        let diff = "### `src/auth.rs`\n\n```diff\n-    if token.expired() {\n+    if token.expired() && !token.refreshable() {\n```\n".repeat(40);
+        let description = format!("{}{HISTORY_HEADING}\n{diff}", "Logins fail after the token refresh.\n".repeat(50));
+
+        let (prompt, _) = harness.server().condense_prompt("plan_bug_fix", &description, false).await.unwrap();
+        assert!(prompt.starts_with("CONDENSED DESCRIPTION"));
+        assert!(prompt.ends_with(&format!("{HISTORY_HEADING}\n{diff}")));
+        assert!(harness.requests().iter().all(|r| !r.user().contains("token.refreshable()")));
+
+        // The history alone never triggers condensing.
+        let short = format!("Logins fail.{HISTORY_HEADING}\n{diff}");
+        assert!(harness.server().condense_prompt("plan_bug_fix", &short, false).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn follow_ups_offer_changed_files_that_did_not_fit_again() {
+        let harness = Harness::builder().var("TOKEN_CHAR_LIMIT", "600").build().unwrap();
+        let repo = TempRepo::with_files(&[("small.rs", "fn small() {}"), ("big.rs", &"// filler\n".repeat(100))]).unwrap();
+        let server = harness.server();
+
+        let (question, snapshot) = server.with_codebase_changes(&repo.directory(), &files::Snapshot::new(), "Why?".to_string()).await;
+        let snapshot = snapshot.unwrap();
+        assert!(question.contains("did not fit and are not shown"));
+        assert!(question.contains("- `big.rs`"));
+        assert!(!snapshot.contains_key(Path::new("big.rs")));
+        // Only the files that were shown are recorded as seen.
+        assert_eq!(question.contains("### `small.rs`"), snapshot.contains_key(Path::new("small.rs")));
+
+        let (question, _) = server.with_codebase_changes(&repo.directory(), &snapshot, "Why?".to_string()).await;
+        assert!(question.contains("`big.rs`"));
+    }
 }
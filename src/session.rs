@@ -0,0 +1,113 @@
+use crate::files::Snapshot;
+use crate::plan::OutputFormat;
+use crate::redact;
+use crate::segments::Segments;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+const MAX_SESSIONS: usize = 100;
+
+#[derive(Clone)]
+pub struct Exchange {
+    pub question: String,
+    pub answer: String,
+}
+
+#[derive(Clone)]
+pub struct Session {
+    pub tool: String,
+    pub directory: String,
+    // Format of the session's first answer, which later answers must keep.
+    pub format: OutputFormat,
+    pub context: Segments,
+    pub exchanges: Vec<Exchange>,
+    // State of the directory as last seen by the model; None when it cannot
+    // change (archives and historical commits).
+    pub snapshot: Option<Snapshot>,
+    // Files sent in this session have their literals redacted; taken from
+    // the `redact::scope` the session is created in.
+    pub redacted: bool,
+    last_used: Instant,
+}
+
+pub struct SessionStore {
+    sessions: Mutex<HashMap<String, Session>>,
+    ttl: Duration,
+}
+
+impl SessionStore {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            sessions: Mutex::new(HashMap::new()),
+            ttl,
+        }
+    }
+
+    pub fn create(&self, tool: &str, directory: &str, format: OutputFormat, context: Segments, snapshot: Option<Snapshot>, first: Exchange) -> String {
+        let id = uuid::Uuid::new_v4().to_string();
+        let mut sessions = self.sessions.lock().unwrap();
+        self.evict(&mut sessions);
+
+        sessions.insert(
+            id.clone(),
+            Session {
+                tool: tool.to_string(),
+                directory: directory.to_string(),
+                format,
+                context,
+                exchanges: vec![first],
+                snapshot,
+                redacted: redact::active(),
+                last_used: Instant::now(),
+            },
+        );
+        id
+    }
+
+    pub fn get(&self, id: &str) -> Option<Session> {
+        let mut sessions = self.sessions.lock().unwrap();
+        self.evict(&mut sessions);
+        let session = sessions.get_mut(id)?;
+        session.last_used = Instant::now();
+        Some(session.clone())
+    }
+
+    // The most recently used live session for each directory, newest first,
+    // without refreshing their expiry.
+    pub fn latest_by_directory(&self) -> Vec<Session> {
+        let mut sessions = self.sessions.lock().unwrap();
+        self.evict(&mut sessions);
+        let mut latest: HashMap<&str, &Session> = HashMap::new();
+        for session in sessions.values() {
+            let entry = latest.entry(session.directory.as_str()).or_insert(session);
+            if session.last_used > entry.last_used {
+                *entry = session;
+            }
+        }
+        let mut latest: Vec<Session> = latest.into_values().cloned().collect();
+        latest.sort_by_key(|s| std::cmp::Reverse(s.last_used));
+        latest
+    }
+
+    pub fn append(&self, id: &str, exchange: Exchange, snapshot: Option<Snapshot>) {
+        if let Some(session) = self.sessions.lock().unwrap().get_mut(id) {
+            session.exchanges.push(exchange);
+            if snapshot.is_some() {
+                session.snapshot = snapshot;
+            }
+            session.last_used = Instant::now();
+        }
+    }
+
+    fn evict(&self, sessions: &mut HashMap<String, Session>) {
+        sessions.retain(|_, s| s.last_used.elapsed() < self.ttl);
+
+        while sessions.len() >= MAX_SESSIONS {
+            let Some(oldest) = sessions.iter().min_by_key(|(_, s)| s.last_used).map(|(id, _)| id.clone()) else {
+                break;
+            };
+            sessions.remove(&oldest);
+        }
+    }
+}
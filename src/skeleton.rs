@@ -0,0 +1,331 @@
+// Compact context for large codebases: the declarations of every file,
+// extracted with tree-sitter, plus the full contents of the files that
+// define the symbols a request names.
+use crate::code_index::{self, MAX_DEFINING_FILES};
+use crate::files::{self, SourceFile};
+use crate::redact;
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::path::Path;
+use tree_sitter::{Language, Node, Parser};
+
+// Declarations longer than this (large type definitions, constant tables)
+// are cut.
+const MAX_DECLARATION_CHARS: usize = 300;
+// How deep below a declaration its body, or the name of an assigned
+// function, is looked for.
+const MAX_SEARCH_DEPTH: usize = 3;
+
+// Which node kinds of a grammar appear in the skeleton, and how.
+struct Kinds {
+    // Shown up to their body: functions and methods.
+    signatures: &'static [&'static str],
+    // Shown up to their body, followed by the declarations inside it:
+    // classes, impls, traits, modules.
+    containers: &'static [&'static str],
+    // Shown whole: type definitions, fields, and constants.
+    definitions: &'static [&'static str],
+    // Searched for declarations but not shown: exports, decorators.
+    wrappers: &'static [&'static str],
+}
+
+const RUST: Kinds = Kinds {
+    signatures: &["function_item", "function_signature_item"],
+    containers: &["impl_item", "trait_item", "mod_item"],
+    definitions: &["struct_item", "enum_item", "union_item", "type_item", "const_item", "static_item"],
+    wrappers: &[],
+};
+
+const PYTHON: Kinds = Kinds {
+    signatures: &["function_definition"],
+    containers: &["class_definition"],
+    definitions: &[],
+    wrappers: &["decorated_definition"],
+};
+
+// Also TypeScript's, whose grammar extends JavaScript's.
+const JAVASCRIPT: Kinds = Kinds {
+    signatures: &[
+        "function_declaration", "generator_function_declaration", "method_definition", "lexical_declaration",
+        "variable_declaration", "function_signature", "method_signature", "abstract_method_signature",
+    ],
+    containers: &["class_declaration", "abstract_class_declaration", "internal_module", "module"],
+    definitions: &[
+        "field_definition", "public_field_definition", "interface_declaration", "type_alias_declaration",
+        "enum_declaration",
+    ],
+    wrappers: &["export_statement"],
+};
+
+const GO: Kinds = Kinds {
+    signatures: &["function_declaration", "method_declaration"],
+    containers: &[],
+    definitions: &["type_declaration", "const_declaration"],
+    wrappers: &[],
+};
+
+const JAVA: Kinds = Kinds {
+    signatures: &["method_declaration", "constructor_declaration"],
+    containers: &["class_declaration", "interface_declaration", "enum_declaration", "record_declaration"],
+    definitions: &["field_declaration", "constant_declaration"],
+    wrappers: &["enum_body_declarations"],
+};
+
+// The grammar for a code fence language; None for languages without one.
+fn grammar(language: &str) -> Option<(Language, &'static Kinds)> {
+    let grammar = match language {
+        "rust" => (tree_sitter_rust::LANGUAGE.into(), &RUST),
+        "python" => (tree_sitter_python::LANGUAGE.into(), &PYTHON),
+        "javascript" | "jsx" => (tree_sitter_javascript::LANGUAGE.into(), &JAVASCRIPT),
+        "typescript" => (tree_sitter_typescript::LANGUAGE_TYPESCRIPT.into(), &JAVASCRIPT),
+        "tsx" => (tree_sitter_typescript::LANGUAGE_TSX.into(), &JAVASCRIPT),
+        "go" => (tree_sitter_go::LANGUAGE.into(), &GO),
+        "java" => (tree_sitter_java::LANGUAGE.into(), &JAVA),
+        _ => return None,
+    };
+    Some(grammar)
+}
+
+// A declaration as shown in the skeleton.
+pub struct Declaration {
+    // 1-based line of the declaration's first line.
+    pub line: usize,
+    // Nesting inside containers; 0 at the top level.
+    pub depth: usize,
+    pub name: Option<String>,
+    // The declaration up to its body, on one line.
+    pub text: String,
+}
+
+// The declarations of a file, in order; None if its language has no
+// grammar or it could not be parsed.
+pub fn declarations(path: &Path, content: &str) -> Option<Vec<Declaration>> {
+    let (language, kinds) = grammar(files::language(path)?)?;
+    let mut parser = Parser::new();
+    parser.set_language(&language).ok()?;
+    let tree = parser.parse(content, None)?;
+    let mut found = Vec::new();
+    collect(tree.root_node(), content.as_bytes(), kinds, 0, &mut found);
+    Some(found)
+}
+
+fn collect(parent: Node, source: &[u8], kinds: &Kinds, depth: usize, found: &mut Vec<Declaration>) {
+    let mut cursor = parent.walk();
+    for node in parent.named_children(&mut cursor) {
+        if !kinds.wrappers.contains(&node.kind()) {
+            add(node, node, source, kinds, depth, found);
+            continue;
+        }
+        // Exports and decorators are shown with the declaration they wrap.
+        match node.child_by_field_name("declaration").or_else(|| node.child_by_field_name("definition")) {
+            Some(wrapped) => add(wrapped, node, source, kinds, depth, found),
+            None => collect(node, source, kinds, depth, found),
+        }
+    }
+}
+
+// Adds `node` if it is a declaration, shown from the start of `shown_from`.
+fn add(node: Node, shown_from: Node, source: &[u8], kinds: &Kinds, depth: usize, found: &mut Vec<Declaration>) {
+    let kind = node.kind();
+    let container = kinds.containers.contains(&kind);
+    let end = if container || kinds.signatures.contains(&kind) {
+        body(node, 0).map_or(node.end_byte(), |body| body.start_byte())
+    } else if kinds.definitions.contains(&kind) {
+        node.end_byte()
+    } else {
+        return;
+    };
+    found.push(Declaration {
+        line: shown_from.start_position().row + 1,
+        depth,
+        name: name(node, source, 0),
+        text: one_line(&without_comments(shown_from, source, end)),
+    });
+    if let Some(body) = container.then(|| node.child_by_field_name("body")).flatten() {
+        collect(body, source, kinds, depth + 1, found);
+    }
+}
+
+// The node's body, or for declarations that assign a function
+// (`const handler = async (req) => {...}`), the function's.
+fn body(node: Node, depth: usize) -> Option<Node> {
+    if let Some(body) = node.child_by_field_name("body") {
+        return Some(body);
+    }
+    if depth == MAX_SEARCH_DEPTH {
+        return None;
+    }
+    let mut cursor = node.walk();
+    let children: Vec<Node> = node.named_children(&mut cursor).collect();
+    children.into_iter().find_map(|child| body(child, depth + 1))
+}
+
+// The declared name; for Rust impls, the implementing type.
+fn name(node: Node, source: &[u8], depth: usize) -> Option<String> {
+    if let Some(name) = node.child_by_field_name("name").or_else(|| node.child_by_field_name("type")) {
+        return name.utf8_text(source).ok().map(str::to_string);
+    }
+    if depth == MAX_SEARCH_DEPTH {
+        return None;
+    }
+    let mut cursor = node.walk();
+    let children: Vec<Node> = node.named_children(&mut cursor).collect();
+    children.into_iter().find_map(|child| name(child, source, depth + 1))
+}
+
+// The source of `node` up to `end`, with comments left out: on one line, a
+// `//` comment would swallow the code after it.
+fn without_comments(node: Node, source: &[u8], end: usize) -> String {
+    fn comments(node: Node, end: usize, found: &mut Vec<(usize, usize)>) {
+        let mut cursor = node.walk();
+        for child in node.children(&mut cursor).take_while(|c| c.start_byte() < end) {
+            if child.kind().contains("comment") {
+                found.push((child.start_byte(), child.end_byte().min(end)));
+            } else {
+                comments(child, end, found);
+            }
+        }
+    }
+    let mut skipped = Vec::new();
+    comments(node, end, &mut skipped);
+    let mut text = Vec::new();
+    let mut from = node.start_byte();
+    for (start, stop) in skipped {
+        text.extend_from_slice(&source[from..start]);
+        text.push(b' ');
+        from = stop;
+    }
+    text.extend_from_slice(&source[from..end]);
+    String::from_utf8_lossy(&text).into_owned()
+}
+
+fn one_line(text: &str) -> String {
+    let line = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    if line.chars().count() <= MAX_DECLARATION_CHARS {
+        return line;
+    }
+    let cut: String = line.chars().take(MAX_DECLARATION_CHARS).collect();
+    format!("{cut} …")
+}
+
+struct Parsed {
+    file: SourceFile,
+    declarations: Option<Vec<Declaration>>,
+}
+
+// The skeleton of every file under `root`, after the full contents of up to
+// `top_k` files defining symbols named in `prompt`, within `char_limit`.
+// Files sent in full get at most half of the limit.
+pub async fn skeleton_context(root: &Path, prompt: &str, top_k: usize, char_limit: usize) -> Result<String> {
+    let started = std::time::Instant::now();
+    let walk_root = root.to_path_buf();
+    let parsed = tokio::task::spawn_blocking(move || -> Result<Vec<Parsed>> {
+        let files = files::collect_source_files(&walk_root)?;
+        Ok(files
+            .into_iter()
+            .map(|file| {
+                let declarations = declarations(&file.path, &file.content);
+                Parsed { file, declarations }
+            })
+            .collect())
+    })
+    .await
+    .context("Skeleton task panicked")??;
+    let parsed_count = parsed.iter().filter(|p| p.declarations.is_some()).count();
+    tracing::info!("Parsed {parsed_count} of {} files in '{}' for the skeleton in {:.1?}", parsed.len(), root.display(), started.elapsed());
+
+    let full: Vec<&Parsed> = ranked(&parsed, prompt).into_iter().take(top_k).collect();
+    let mut context = String::new();
+    if !full.is_empty() {
+        context.push_str(&format!(
+            "The following {} files define symbols named in the request and are included in full; every other file is shown as a skeleton below:\n",
+            full.len()
+        ));
+        for file in &full {
+            context.push_str(&format!("- `{}`\n", file.file.path.display()));
+        }
+        context.push('\n');
+        let sources: Vec<&SourceFile> = full.iter().map(|p| &p.file).collect();
+        context.push_str(&files::render_files(&sources, (char_limit / 2).saturating_sub(context.len())));
+        context.push_str("\n\n");
+    }
+
+    let rest: Vec<&Parsed> = parsed.iter().filter(|p| !full.iter().any(|f| f.file.path == p.file.path)).collect();
+    context.push_str(&render(&rest, char_limit.saturating_sub(context.len())));
+    Ok(context)
+}
+
+// Files defining the identifiers in `prompt`, best first. A symbol weighs
+// less the more files define it, and is ignored if too many do.
+fn ranked<'a>(parsed: &'a [Parsed], prompt: &str) -> Vec<&'a Parsed> {
+    let mut defined_in: HashMap<String, Vec<usize>> = HashMap::new();
+    for (i, file) in parsed.iter().enumerate() {
+        for name in file.declarations.iter().flatten().filter_map(|d| d.name.as_deref()) {
+            let files = defined_in.entry(name.to_lowercase()).or_default();
+            if files.last() != Some(&i) {
+                files.push(i);
+            }
+        }
+    }
+
+    let mut scores: HashMap<usize, f32> = HashMap::new();
+    for identifier in code_index::identifiers(prompt) {
+        let Some(files) = defined_in.get(&identifier.to_lowercase()).filter(|f| f.len() <= MAX_DEFINING_FILES) else {
+            continue;
+        };
+        for i in files {
+            *scores.entry(*i).or_default() += 1.0 / files.len() as f32;
+        }
+    }
+    let mut ranked: Vec<(usize, f32)> = scores.into_iter().collect();
+    ranked.sort_by(|a, b| b.1.total_cmp(&a.1).then_with(|| parsed[a.0].file.path.cmp(&parsed[b.0].file.path)));
+    ranked.into_iter().map(|(i, _)| &parsed[i]).collect()
+}
+
+// Declarations per file with their line numbers, then the files without
+// any (other languages, configuration, docs) by path and size.
+fn render(parsed: &[&Parsed], char_limit: usize) -> String {
+    let mut out = String::from(
+        "Codebase skeleton: the declarations of each file (functions and methods up to their body, types, fields, and constants), each prefixed with its line number. Bodies are omitted.\n\n",
+    );
+    let mut others: Vec<&SourceFile> = Vec::new();
+    let mut omitted = 0;
+    for parsed in parsed {
+        let declarations = match &parsed.declarations {
+            Some(declarations) if !declarations.is_empty() => declarations,
+            _ => {
+                others.push(&parsed.file);
+                continue;
+            }
+        };
+        let mut lines = String::new();
+        for declaration in declarations {
+            let text = if redact::active() { redact::source(&parsed.file.path, &declaration.text) } else { declaration.text.clone() };
+            lines.push_str(&format!("{}: {}{text}\n", declaration.line, "    ".repeat(declaration.depth)));
+        }
+        let language = files::language(&parsed.file.path).unwrap_or("");
+        let block = format!("### `{}`\n\n{}\n\n", parsed.file.path.display(), files::fenced(language, lines.trim_end()));
+        if out.len() + block.len() > char_limit {
+            omitted += 1;
+            continue;
+        }
+        out.push_str(&block);
+    }
+
+    if !others.is_empty() {
+        out.push_str("Other files (not parsed):\n");
+        for file in others {
+            let line = format!("- `{}` ({} bytes)\n", file.path.display(), file.content.len());
+            if out.len() + line.len() > char_limit {
+                omitted += 1;
+                continue;
+            }
+            out.push_str(&line);
+        }
+    }
+    if omitted > 0 {
+        tracing::warn!("Skeleton reached character limit ({char_limit}); {omitted} files left out");
+        out.push_str(&format!("\n--- SKELETON TRUNCATED DUE TO TOKEN LIMIT: {omitted} files left out ---"));
+    }
+    out
+}
@@ -0,0 +1,96 @@
+// Long request specifications. A spec over PROMPT_CHAR_LIMIT is condensed
+// before the pipeline runs so it does not crowd the code out of the context;
+// its acceptance criteria are kept verbatim, since plans are checked
+// against them.
+
+// Parts of a spec condensed by one request; longer specs are condensed a
+// part at a time.
+pub const MAX_CHUNK_CHARS: usize = 100_000;
+
+// A heading-like line longer than this is prose, not a label.
+const MAX_LABEL_CHARS: usize = 80;
+
+// The acceptance criteria sections of `spec`, verbatim and in order, and the
+// rest of it. A section starts at a markdown heading or a label line (such as
+// `Acceptance criteria:` or `**Acceptance Criteria**`) naming acceptance
+// criteria, and runs to the next markdown heading of the same or a higher
+// level (any heading, for labels).
+pub fn split_criteria(spec: &str) -> (Vec<String>, String) {
+    let mut criteria: Vec<String> = Vec::new();
+    let mut rest = String::new();
+    // The level of the criteria section being read, if any.
+    let mut inside: Option<usize> = None;
+    let mut previous_blank = true;
+    for line in spec.split_inclusive('\n') {
+        let level = heading_level(line, previous_blank);
+        previous_blank = line.trim().is_empty();
+        // Labels inside the criteria (`Scenario 2:`) do not end them.
+        if let (Some(open), Some(level)) = (inside, level) {
+            if level <= open.min(6) {
+                inside = None;
+            }
+        }
+        if inside.is_none() {
+            if let Some(level) = level.filter(|_| line.to_lowercase().contains("acceptance criteria")) {
+                inside = Some(level);
+                criteria.push(String::new());
+            }
+        }
+        match inside {
+            Some(_) => criteria.last_mut().expect("a section was opened").push_str(line),
+            None => rest.push_str(line),
+        }
+    }
+    (criteria.into_iter().map(|c| c.trim_end().to_string()).collect(), rest)
+}
+
+// Markdown headings have their level (1-6); labels, short lines after a
+// blank line that end in `:` or are wholly bold, rank below every heading.
+fn heading_level(line: &str, previous_blank: bool) -> Option<usize> {
+    let line = line.trim();
+    let hashes = line.chars().take_while(|&c| c == '#').count();
+    if (1..=6).contains(&hashes) && line[hashes..].starts_with(' ') {
+        return Some(hashes);
+    }
+    let list_item = line.starts_with(['-', '*', '+']) && !line.starts_with("**");
+    let bold = line.starts_with("**") && line.ends_with("**") && line.len() > 4;
+    let label = !list_item && line.len() <= MAX_LABEL_CHARS && (line.ends_with(':') || bold);
+    (previous_blank && label).then_some(7)
+}
+
+// `text` in consecutive parts of at most `max_chars`, split at blank lines
+// where possible, else at line ends, else anywhere on a character boundary.
+pub fn chunks(text: &str, max_chars: usize) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut rest = text;
+    while rest.len() > max_chars {
+        let mut end = max_chars;
+        while !rest.is_char_boundary(end) {
+            end -= 1;
+        }
+        let window = &rest[..end];
+        let cut = window.rfind("\n\n").map(|i| i + 2).or_else(|| window.rfind('\n').map(|i| i + 1)).filter(|&i| i > 0).unwrap_or(end);
+        parts.push(&rest[..cut]);
+        rest = &rest[cut..];
+    }
+    if !rest.is_empty() {
+        parts.push(rest);
+    }
+    parts
+}
+
+// The condensed spec with its acceptance criteria appended verbatim.
+pub fn assemble(condensed: &str, criteria: &[String]) -> String {
+    let mut out = condensed.trim().to_string();
+    if !criteria.is_empty() {
+        out.push_str("\n\n");
+        out.push_str(&criteria.join("\n\n"));
+    }
+    out
+}
+
+// The start of `text`, cut at a line end to at most `max_chars`, for when
+// the spec could not be condensed.
+pub fn head(text: &str, max_chars: usize) -> &str {
+    chunks(text, max_chars).first().copied().unwrap_or_default()
+}
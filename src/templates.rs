@@ -0,0 +1,224 @@
+use crate::files;
+use crate::llm::PipelinePrompts;
+use crate::segments::Segments;
+use anyhow::{bail, Context, Result};
+use std::collections::HashMap;
+use std::path::Path;
+
+// Placeholders each message template may use.
+const REQUEST_VARS: &[&str] = &["context", "request_label", "prompt"];
+const DETAIL_VARS: &[&str] = &["context", "request_label", "prompt", "request", "analysis_label", "analysis", "detail_instruction"];
+
+// User messages of the pipeline passes, with `{{name}}` placeholders.
+#[derive(Clone, Copy)]
+pub struct MessageTemplates {
+    // The codebase report and request: the analysis pass's message, and the
+    // start of the detail pass's.
+    pub request: &'static str,
+    // The detail pass's message after an analysis; `{{request}}` is the
+    // rendered request template.
+    pub detail: &'static str,
+}
+
+pub const DEFAULT_MESSAGES: MessageTemplates = MessageTemplates {
+    request: "Codebase Report:\n{{context}}\n\n{{request_label}}: {{prompt}}",
+    detail: "{{request}}\n\n{{analysis_label}}:\n{{analysis}}\n\n{{detail_instruction}}",
+};
+
+// Overrides for one tool; omitted files keep the built-in (or shared) text.
+#[derive(Default)]
+struct ToolTemplates {
+    analysis_system: Option<&'static str>,
+    detail_system: Option<&'static str>,
+    detail_instruction: Option<&'static str>,
+    request: Option<&'static str>,
+    detail: Option<&'static str>,
+}
+
+// Prompt text loaded from PROMPT_TEMPLATES_DIR in place of the constants in
+// llm.rs. `request.md` and `detail.md` at the top level apply to every tool;
+// a directory named after a tool overrides its system prompts, instruction,
+// and messages.
+pub struct PromptTemplates {
+    shared: ToolTemplates,
+    tools: HashMap<String, ToolTemplates>,
+}
+
+impl PromptTemplates {
+    pub fn load(dir: &Path) -> Result<Self> {
+        let shared = read_templates(dir)?;
+        if shared.analysis_system.is_some() || shared.detail_system.is_some() || shared.detail_instruction.is_some() {
+            tracing::warn!(
+                "System prompts and instructions at the top of '{}' are ignored; put them in a directory named after the tool",
+                dir.display()
+            );
+        }
+
+        let entries = std::fs::read_dir(dir)
+            .with_context(|| format!("Failed to read prompt templates directory '{}'", dir.display()))?;
+        let mut tools = HashMap::new();
+        for entry in entries {
+            let path = entry?.path();
+            if !path.is_dir() {
+                continue;
+            }
+            let Some(tool) = path.file_name().and_then(|n| n.to_str()) else {
+                continue;
+            };
+            tools.insert(tool.to_string(), read_templates(&path)?);
+        }
+
+        Ok(Self {
+            shared: ToolTemplates {
+                request: shared.request,
+                detail: shared.detail,
+                ..Default::default()
+            },
+            tools,
+        })
+    }
+
+    pub fn tools(&self) -> Vec<&str> {
+        self.tools.keys().map(String::as_str).collect()
+    }
+
+    // The tool's prompts with its overrides applied, or None if it has none.
+    pub fn prompts(&self, tool: &str, builtin: &PipelinePrompts) -> Option<PipelinePrompts> {
+        let t = self.tools.get(tool)?;
+        Some(PipelinePrompts {
+            analysis_system: t.analysis_system.unwrap_or(builtin.analysis_system),
+            detail_system: t.detail_system.unwrap_or(builtin.detail_system),
+            detail_instruction: t.detail_instruction.unwrap_or(builtin.detail_instruction),
+            ..*builtin
+        })
+    }
+
+    pub fn messages(&self, tool: &str) -> MessageTemplates {
+        let t = self.tools.get(tool);
+        MessageTemplates {
+            request: t.and_then(|t| t.request).or(self.shared.request).unwrap_or(DEFAULT_MESSAGES.request),
+            detail: t.and_then(|t| t.detail).or(self.shared.detail).unwrap_or(DEFAULT_MESSAGES.detail),
+        }
+    }
+
+    // Hash of every template that applies to `tool`, or None when it runs on
+    // the built-in prompts. Keeps cached answers from outliving a template edit.
+    pub fn fingerprint(&self, tool: &str) -> Option<String> {
+        let t = self.tools.get(tool);
+        let parts = [
+            t.and_then(|t| t.analysis_system),
+            t.and_then(|t| t.detail_system),
+            t.and_then(|t| t.detail_instruction),
+            t.and_then(|t| t.request).or(self.shared.request),
+            t.and_then(|t| t.detail).or(self.shared.detail),
+        ];
+        if parts.iter().all(Option::is_none) {
+            return None;
+        }
+        let joined: Vec<&str> = parts.iter().map(|p| p.unwrap_or("")).collect();
+        Some(files::content_hash(&joined.join("\0"))[..12].to_string())
+    }
+}
+
+fn read_templates(dir: &Path) -> Result<ToolTemplates> {
+    let request = read_template(dir, "request.md")?;
+    let detail = read_template(dir, "detail.md")?;
+    if let Some(template) = request {
+        check_placeholders(dir, "request.md", template, REQUEST_VARS)?;
+    }
+    if let Some(template) = detail {
+        check_placeholders(dir, "detail.md", template, DETAIL_VARS)?;
+    }
+    Ok(ToolTemplates {
+        analysis_system: read_template(dir, "analysis_system.md")?,
+        detail_system: read_template(dir, "detail_system.md")?,
+        detail_instruction: read_template(dir, "detail_instruction.md")?,
+        request,
+        detail,
+    })
+}
+
+// Loaded once at startup, so leaking gives the &'static str that
+// PipelinePrompts uses without copying the templates per request.
+fn read_template(dir: &Path, name: &str) -> Result<Option<&'static str>> {
+    let path = dir.join(name);
+    match std::fs::read_to_string(&path) {
+        Ok(text) => Ok(Some(Box::leak(text.trim_end().to_string().into_boxed_str()))),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e).with_context(|| format!("Failed to read prompt template '{}'", path.display())),
+    }
+}
+
+// A misspelled placeholder would otherwise reach the model verbatim.
+fn check_placeholders(dir: &Path, name: &str, template: &str, allowed: &[&str]) -> Result<()> {
+    for placeholder in placeholders(template) {
+        if !allowed.contains(&placeholder) {
+            bail!(
+                "Prompt template '{}' uses unknown placeholder '{{{{{placeholder}}}}}'; allowed: {}",
+                dir.join(name).display(),
+                allowed.join(", ")
+            );
+        }
+    }
+    Ok(())
+}
+
+fn placeholders(template: &str) -> Vec<&str> {
+    let mut names = Vec::new();
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        let after = &rest[start + 2..];
+        let Some(end) = after.find("}}") else {
+            break;
+        };
+        names.push(after[..end].trim());
+        rest = &after[end + 2..];
+    }
+    names
+}
+
+// Substitutes `{{ name }}` placeholders in a single pass, so placeholder-like
+// text inside the values (source code in the context, say) is left alone.
+// Unknown placeholders are kept as written.
+pub fn render(template: &str, vars: &[(&str, &str)]) -> String {
+    let mut out = String::with_capacity(template.len() + vars.iter().map(|(_, v)| v.len()).sum::<usize>());
+    substitute(template, vars, |piece| match piece {
+        Piece::Text(text) => out.push_str(text),
+        Piece::Value(value) => out.push_str(value),
+    });
+    out
+}
+
+// As `render`, sharing the values' pieces instead of copying them.
+pub fn render_segments(template: &str, vars: &[(&str, &Segments)]) -> Segments {
+    let mut out = Segments::new();
+    substitute(template, vars, |piece| match piece {
+        Piece::Text(text) => out.push(text),
+        Piece::Value(value) => out.append(value),
+    });
+    out
+}
+
+enum Piece<'a, V> {
+    Text(&'a str),
+    Value(&'a V),
+}
+
+// Emits the template's text and the values of its known placeholders in order.
+fn substitute<'a, V>(template: &'a str, vars: &'a [(&str, V)], mut emit: impl FnMut(Piece<'a, V>)) {
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        let after = &rest[start + 2..];
+        let Some(end) = after.find("}}") else {
+            break;
+        };
+        emit(Piece::Text(&rest[..start]));
+        let name = after[..end].trim();
+        match vars.iter().find(|(n, _)| *n == name) {
+            Some((_, value)) => emit(Piece::Value(value)),
+            None => emit(Piece::Text(&rest[start..start + end + 4])),
+        }
+        rest = &after[end + 2..];
+    }
+    emit(Piece::Text(rest));
+}
@@ -0,0 +1,234 @@
+// Test harness for embedders and contributors: integration tests for custom
+// tools, prompt templates, and pipelines, without a model, a codebase_viewer
+// install, or changes to the process environment.
+//
+//     let repo = TempRepo::with_files(&[("src/lib.rs", "pub fn add() {}")])?;
+//     let harness = Harness::builder().respond("Feature Request", "# Plan\n...").build()?;
+//     let result = harness.agent().plan_feature(&Scope::new(repo.directory()), "Add subtraction", RunOptions::default()).await?;
+//     assert!(harness.requests().iter().any(|r| r.user().contains("pub fn add")));
+//
+// The fake codebase_viewer is a shell script, so the harness needs a Unix
+// shell; `TempRepo::commit` needs git.
+use crate::agent::Agent;
+use crate::config::Config;
+use crate::mock::MockRequest;
+use crate::server::CodeAgentServer;
+use anyhow::{bail, Context, Result};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+// Reads its arguments as codebase_viewer does and writes each file under
+// `--path` to `--output`, fenced under its relative path.
+const LISTING_VIEWER: &str = r#"#!/bin/sh
+while [ $# -gt 0 ]; do case "$1" in --path) p="$2"; shift;; --output) o="$2"; shift;; esac; shift; done
+cd "$p" || exit 1
+find . -type f | sed 's|^\./||' | sort | while IFS= read -r f; do
+  printf '### `%s`\n\n```\n' "$f"; cat "$f"; printf '\n```\n\n'
+done > "$o"
+"#;
+
+// Writes the canned report next to the script, whatever `--path` is.
+const CANNED_VIEWER: &str = r#"#!/bin/sh
+while [ $# -gt 0 ]; do case "$1" in --output) o="$2"; shift;; esac; shift; done
+cat "$(dirname "$0")/report.md" > "$o"
+"#;
+
+// A temporary directory, removed when dropped.
+pub struct TempRepo {
+    path: PathBuf,
+}
+
+impl TempRepo {
+    pub fn new() -> Result<Self> {
+        let path = std::env::temp_dir().join(format!("ai_code_agent-test-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&path).with_context(|| format!("Failed to create '{}'", path.display()))?;
+        Ok(Self { path })
+    }
+
+    // A directory holding `files`, as (relative path, content) pairs.
+    pub fn with_files(files: &[(&str, &str)]) -> Result<Self> {
+        let repo = Self::new()?;
+        for (path, content) in files {
+            repo.write(path, content)?;
+        }
+        Ok(repo)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // The path as the `directory` argument of the tools.
+    pub fn directory(&self) -> String {
+        self.path.display().to_string()
+    }
+
+    // Writes `content` to `relative`, creating its parent directories.
+    pub fn write(&self, relative: &str, content: &str) -> Result<()> {
+        let path = self.path.join(relative);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).with_context(|| format!("Failed to create '{}'", parent.display()))?;
+        }
+        std::fs::write(&path, content).with_context(|| format!("Failed to write '{}'", path.display()))
+    }
+
+    // Commits every file, making the directory a git repository first if it
+    // is not one, and returns the commit's hash.
+    pub fn commit(&self, message: &str) -> Result<String> {
+        if !self.path.join(".git").exists() {
+            self.git(&["init", "-q"])?;
+        }
+        self.git(&["add", "-A"])?;
+        self.git(&["-c", "user.name=test", "-c", "user.email=test@example.com", "commit", "-q", "--allow-empty", "-m", message])?;
+        Ok(self.git(&["rev-parse", "HEAD"])?.trim().to_string())
+    }
+
+    fn git(&self, args: &[&str]) -> Result<String> {
+        let output = Command::new("git").args(args).current_dir(&self.path).output().context("Failed to run git")?;
+        if !output.status.success() {
+            bail!("git {} failed: {}", args.join(" "), String::from_utf8_lossy(&output.stderr).trim());
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+}
+
+impl Drop for TempRepo {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.path);
+    }
+}
+
+// Builds a `Harness`.
+#[derive(Default)]
+pub struct HarnessBuilder {
+    vars: HashMap<String, String>,
+    report: Option<String>,
+    rules: Vec<serde_json::Value>,
+    templates: Vec<(String, String)>,
+}
+
+impl HarnessBuilder {
+    // Sets a configuration variable, as in the environment; these override
+    // the harness's own defaults.
+    pub fn var(mut self, name: &str, value: &str) -> Self {
+        self.vars.insert(name.to_string(), value.to_string());
+        self
+    }
+
+    // Every codebase_viewer run returns `report` instead of the directory's
+    // files.
+    pub fn report(mut self, report: &str) -> Self {
+        self.report = Some(report.to_string());
+        self
+    }
+
+    // Requests with a message containing `pattern` get `response`, which may
+    // use `{{ model }}` and `{{ user }}`. Rules are tried in the order added.
+    pub fn respond(mut self, pattern: &str, response: &str) -> Self {
+        self.rules.push(serde_json::json!({ "match": pattern, "response": response }));
+        self
+    }
+
+    // Adds a prompt template, as `relative` in PROMPT_TEMPLATES_DIR
+    // (`request.md`, or `<tool>/analysis_system.md` and the like).
+    pub fn template(mut self, relative: &str, text: &str) -> Self {
+        self.templates.push((relative.to_string(), text.to_string()));
+        self
+    }
+
+    pub fn build(self) -> Result<Harness> {
+        let scratch = TempRepo::new()?;
+        let mut vars: HashMap<String, String> = [
+            ("LLM_PROVIDER", "mock"),
+            // Test directories are smaller than any real report would be.
+            ("VIEWER_REPORT_MIN_RATIO", "0"),
+            ("TREE_HASH_REFRESH_SECS", "0"),
+        ]
+        .into_iter()
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect();
+        vars.insert("AGENT_DATA_DIR".to_string(), scratch.path().join("data").display().to_string());
+
+        if !self.rules.is_empty() {
+            scratch.write("mock_responses.json", &serde_json::to_string(&self.rules)?)?;
+            vars.insert("MOCK_RESPONSES_FILE".to_string(), scratch.path().join("mock_responses.json").display().to_string());
+        }
+        if !self.templates.is_empty() {
+            for (relative, text) in &self.templates {
+                scratch.write(&format!("templates/{relative}"), text)?;
+            }
+            vars.insert("PROMPT_TEMPLATES_DIR".to_string(), scratch.path().join("templates").display().to_string());
+        }
+        let viewer = match &self.report {
+            Some(report) => {
+                scratch.write("viewer/report.md", report)?;
+                CANNED_VIEWER
+            }
+            None => LISTING_VIEWER,
+        };
+        scratch.write("viewer/codebase_viewer", viewer)?;
+        let viewer_path = scratch.path().join("viewer/codebase_viewer");
+        make_executable(&viewer_path)?;
+        vars.extend(self.vars);
+
+        let config = Config::from_vars(Some(viewer_path), |name| vars.get(name).cloned())?;
+        Ok(Harness { config, scratch })
+    }
+}
+
+// A configuration answered by the mock provider (`LLM_PROVIDER=mock`), with
+// a fake codebase_viewer and its data directory in a temporary directory.
+pub struct Harness {
+    config: Config,
+    // Holds the viewer, mock rules, templates, and data directory.
+    scratch: TempRepo,
+}
+
+impl Harness {
+    pub fn builder() -> HarnessBuilder {
+        HarnessBuilder::default()
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    pub fn agent(&self) -> Agent {
+        Agent::new(self.config.clone())
+    }
+
+    // The MCP server, for calling tools by their MCP parameters.
+    pub fn server(&self) -> CodeAgentServer {
+        CodeAgentServer::new(self.config.clone())
+    }
+
+    // AGENT_DATA_DIR, where saved plans and failed requests are kept.
+    pub fn data_dir(&self) -> PathBuf {
+        self.scratch.path().join("data")
+    }
+
+    // The chat requests sent so far, oldest first, by every agent and server
+    // built from this harness.
+    pub fn requests(&self) -> Vec<MockRequest> {
+        self.config.gemini_client.mock().map(|mock| mock.requests()).unwrap_or_default()
+    }
+
+    pub fn clear_requests(&self) {
+        if let Some(mock) = self.config.gemini_client.mock() {
+            mock.clear_requests();
+        }
+    }
+}
+
+#[cfg(unix)]
+fn make_executable(path: &Path) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o755))
+        .with_context(|| format!("Failed to make '{}' executable", path.display()))
+}
+
+#[cfg(not(unix))]
+fn make_executable(_path: &Path) -> Result<()> {
+    bail!("the testing harness's fake codebase_viewer needs a Unix shell")
+}
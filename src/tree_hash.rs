@@ -0,0 +1,227 @@
+use crate::files;
+use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+// Trees and derived values no call has used for this long are dropped.
+const IDLE_EVICTION: Duration = Duration::from_secs(3600);
+
+// A file modified this close to the previous scan may have changed again
+// within the same timestamp tick, so its cached hash is not trusted.
+const RACY_WINDOW: Duration = Duration::from_secs(2);
+
+#[derive(Clone)]
+struct FileHash {
+    len: u64,
+    modified: Option<SystemTime>,
+    // None for files that are not UTF-8 text, which `collect_source_files`
+    // skips and which do not count towards the directory hashes.
+    hash: Option<String>,
+}
+
+#[derive(Clone)]
+struct Tree {
+    // By path relative to the root.
+    files: HashMap<PathBuf, FileHash>,
+    // Hash of each directory's subtree, by path relative to the root (empty
+    // for the root itself).
+    dirs: HashMap<PathBuf, String>,
+    scanned_at: SystemTime,
+    last_used: Instant,
+}
+
+impl Tree {
+    fn root_hash(&self) -> String {
+        self.dirs.get(Path::new("")).cloned().unwrap_or_else(|| files::content_hash(""))
+    }
+}
+
+// A value computed from a tree, such as a codebase report.
+struct Derived {
+    root_hash: String,
+    value: String,
+    last_used: Instant,
+}
+
+// Merkle-style content hashes of the directories tools analyze, kept up to
+// date incrementally. A scan walks the tree with the same filtering as
+// `files::collect_source_files` but only reads and hashes the files whose size
+// or modification time changed since the last scan, and recomputes directory
+// hashes only along the paths to changed files. A directory's hash covers the
+// names and hashes of its entries, so the root hash changes exactly when the
+// analyzed files do, and caches of anything derived from them can be
+// validated by comparing one hash.
+#[derive(Default)]
+pub struct TreeHashes {
+    trees: Mutex<HashMap<PathBuf, Tree>>,
+    derived: Mutex<HashMap<(PathBuf, String), Derived>>,
+}
+
+impl TreeHashes {
+    // Rescans `root` and returns its hash.
+    pub async fn root_hash(&self, root: &Path) -> Result<String> {
+        self.evict_idle();
+        let previous = self.trees.lock().unwrap().get(root).cloned();
+        let scan_root = root.to_path_buf();
+        let (tree, rehashed) = tokio::task::spawn_blocking(move || scan(&scan_root, previous))
+            .await
+            .context("Tree hashing task panicked")??;
+        if rehashed > 0 {
+            tracing::debug!("Hashed {rehashed} new or changed files of {} in '{}'", tree.files.len(), root.display());
+        }
+        let hash = tree.root_hash();
+        self.trees.lock().unwrap().insert(root.to_path_buf(), tree);
+        Ok(hash)
+    }
+
+    // The value stored for `key` and `name`, if it was derived from a tree
+    // with hash `root_hash`. `key` identifies the directory across mirrors
+    // (`Workspace::cache_key`).
+    pub fn derived(&self, key: &Path, name: &str, root_hash: &str) -> Option<String> {
+        let mut derived = self.derived.lock().unwrap();
+        let entry = derived.get_mut(&(key.to_path_buf(), name.to_string()))?;
+        if entry.root_hash != root_hash {
+            return None;
+        }
+        entry.last_used = Instant::now();
+        Some(entry.value.clone())
+    }
+
+    pub fn store_derived(&self, key: &Path, name: &str, root_hash: String, value: String) {
+        let entry = Derived { root_hash, value, last_used: Instant::now() };
+        self.derived.lock().unwrap().insert((key.to_path_buf(), name.to_string()), entry);
+    }
+
+    // Rescans every tree used in the last hour each `interval`, so calls find
+    // their hashes current and only hash what changed since the last pass.
+    // Trees whose directory is gone (e.g. removed mirrors) are dropped.
+    pub fn spawn_refresher(self: Arc<Self>, interval: Duration) {
+        tokio::spawn(async move {
+            let mut ticks = tokio::time::interval(interval);
+            ticks.tick().await;
+            loop {
+                ticks.tick().await;
+                self.evict_idle();
+                let roots: Vec<PathBuf> = self.trees.lock().unwrap().keys().cloned().collect();
+                for root in roots {
+                    let last_used = self.trees.lock().unwrap().get(&root).map(|t| t.last_used);
+                    match self.root_hash(&root).await {
+                        // A background scan is not a use.
+                        Ok(_) => {
+                            if let (Some(tree), Some(last_used)) = (self.trees.lock().unwrap().get_mut(&root), last_used) {
+                                tree.last_used = last_used;
+                            }
+                        }
+                        Err(e) => {
+                            tracing::debug!("Dropping tree hashes of '{}': {e:#}", root.display());
+                            self.trees.lock().unwrap().remove(&root);
+                        }
+                    }
+                }
+            }
+        });
+    }
+
+    fn evict_idle(&self) {
+        self.trees.lock().unwrap().retain(|_, tree| tree.last_used.elapsed() < IDLE_EVICTION);
+        self.derived.lock().unwrap().retain(|_, entry| entry.last_used.elapsed() < IDLE_EVICTION);
+    }
+}
+
+// Scans `root`, reusing `previous` hashes of files whose size and
+// modification time are unchanged. Returns the tree and how many files were
+// read.
+fn scan(root: &Path, previous: Option<Tree>) -> Result<(Tree, usize)> {
+    let scanned_at = SystemTime::now();
+    let (old_files, old_dirs, old_scanned_at) = match previous {
+        Some(tree) => (tree.files, tree.dirs, Some(tree.scanned_at)),
+        None => Default::default(),
+    };
+
+    let mut tree_files = HashMap::new();
+    let mut changed: Vec<PathBuf> = Vec::new();
+    let mut rehashed = 0;
+    for (path, metadata) in files::source_paths(root)? {
+        let relative = path.strip_prefix(root).unwrap_or(&path).to_path_buf();
+        let modified = metadata.modified().ok();
+        let old = old_files.get(&relative);
+        let trusted = old.is_some_and(|old| old.len == metadata.len() && old.modified == modified)
+            && modified.zip(old_scanned_at).is_some_and(|(modified, scanned)| modified + RACY_WINDOW < scanned);
+        let entry = match old {
+            Some(old) if trusted => old.clone(),
+            _ => {
+                rehashed += 1;
+                let hash = std::fs::read_to_string(&path).ok().map(|content| files::content_hash(&content));
+                if old.is_none_or(|old| old.hash != hash) {
+                    changed.push(relative.clone());
+                }
+                FileHash { len: metadata.len(), modified, hash }
+            }
+        };
+        tree_files.insert(relative, entry);
+    }
+    changed.extend(old_files.keys().filter(|path| !tree_files.contains_key(*path)).cloned());
+
+    // Directories whose hash must be recomputed: every ancestor of a changed
+    // file. On the first scan every file is new, so that is all of them.
+    let mut dirty: HashSet<PathBuf> = HashSet::new();
+    for path in &changed {
+        dirty.extend(path.ancestors().skip(1).map(Path::to_path_buf));
+    }
+
+    // Entries of each directory: (name, is_directory).
+    let mut children: HashMap<PathBuf, Vec<(String, bool)>> = HashMap::new();
+    children.entry(PathBuf::new()).or_default();
+    for (path, file) in &tree_files {
+        if file.hash.is_none() {
+            continue;
+        }
+        let mut child = path.as_path();
+        let mut is_dir = false;
+        while let Some(parent) = child.parent() {
+            let name = child.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+            let entries = children.entry(parent.to_path_buf()).or_default();
+            let known = is_dir && entries.iter().any(|(n, d)| *d && *n == name);
+            if !known {
+                entries.push((name, is_dir));
+            }
+            if known {
+                break;
+            }
+            child = parent;
+            is_dir = true;
+        }
+    }
+
+    let mut dirs: Vec<PathBuf> = children.keys().cloned().collect();
+    dirs.sort_by_key(|dir| std::cmp::Reverse(dir.components().count()));
+    let mut dir_hashes = HashMap::new();
+    for dir in dirs {
+        let reused = old_dirs.get(&dir).filter(|_| !dirty.contains(&dir));
+        let hash = match reused {
+            Some(hash) => hash.clone(),
+            None => {
+                let mut entries = children.remove(&dir).unwrap_or_default();
+                entries.sort();
+                let mut hasher = Sha256::new();
+                for (name, is_dir) in entries {
+                    let path = dir.join(&name);
+                    let hash = if is_dir {
+                        dir_hashes.get(&path).cloned().unwrap_or_default()
+                    } else {
+                        tree_files.get(&path).and_then(|f| f.hash.clone()).unwrap_or_default()
+                    };
+                    hasher.update(format!("{}\0{name}\0{hash}\n", if is_dir { 'd' } else { 'f' }).as_bytes());
+                }
+                format!("{:x}", hasher.finalize())
+            }
+        };
+        dir_hashes.insert(dir, hash);
+    }
+
+    let tree = Tree { files: tree_files, dirs: dir_hashes, scanned_at, last_used: Instant::now() };
+    Ok((tree, rehashed))
+}
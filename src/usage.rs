@@ -0,0 +1,220 @@
+use crate::keys;
+use anyhow::{bail, Context, Result};
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::future::Future;
+use std::sync::Mutex;
+
+// USD per million tokens, input then output. Gemini 2.5 Pro charges more for
+// prompts over 200k tokens; the lower tier is used, so estimates for very
+// large reports are low.
+const DEFAULT_PRICES: &[(&str, f64, f64)] = &[
+    ("gemini-2.5-pro", 1.25, 10.0),
+    ("gemini-2.5-flash-lite", 0.10, 0.40),
+    ("gemini-2.5-flash", 0.30, 2.50),
+    ("gemini-2.0-flash-lite", 0.075, 0.30),
+    ("gemini-2.0-flash", 0.10, 0.40),
+    ("text-embedding-004", 0.0, 0.0),
+    ("gemini-embedding-001", 0.15, 0.0),
+];
+
+#[derive(Clone, Copy, Default, Serialize, JsonSchema)]
+pub struct TokenUsage {
+    pub requests: u64,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    // Estimated from the price table; requests to unpriced models add nothing.
+    pub cost_usd: f64,
+}
+
+impl TokenUsage {
+    fn add(&mut self, other: &TokenUsage) {
+        self.requests += other.requests;
+        self.prompt_tokens += other.prompt_tokens;
+        self.completion_tokens += other.completion_tokens;
+        self.cost_usd += other.cost_usd;
+    }
+}
+
+#[derive(Clone, Copy)]
+struct ModelPrice {
+    input: f64,
+    output: f64,
+}
+
+pub struct PriceTable {
+    prices: HashMap<String, ModelPrice>,
+}
+
+impl Default for PriceTable {
+    fn default() -> Self {
+        let prices = DEFAULT_PRICES
+            .iter()
+            .map(|&(model, input, output)| (model.to_string(), ModelPrice { input, output }))
+            .collect();
+        Self { prices }
+    }
+}
+
+impl PriceTable {
+    // The built-in prices, overridden or extended by `spec`:
+    // comma-separated `model=input:output` in USD per million tokens.
+    pub fn parse(spec: &str) -> Result<Self> {
+        let mut table = Self::default();
+        for entry in spec.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let Some((model, price)) = entry.split_once('=') else {
+                bail!("Invalid model price '{entry}'; expected model=input:output");
+            };
+            let (input, output) = price.split_once(':').unwrap_or((price, "0"));
+            let parse = |s: &str| s.trim().parse::<f64>().with_context(|| format!("Invalid price in '{entry}'"));
+            table.prices.insert(model.trim().to_string(), ModelPrice { input: parse(input)?, output: parse(output)? });
+        }
+        Ok(table)
+    }
+
+    // Exact model name first, then the longest configured prefix, so dated
+    // and preview variants (`gemini-2.5-flash-preview-05-20`) are priced too.
+    fn price(&self, model: &str) -> Option<ModelPrice> {
+        let model = model.trim_start_matches("models/");
+        self.prices.get(model).copied().or_else(|| {
+            self.prices
+                .iter()
+                .filter(|(name, _)| model.starts_with(name.as_str()))
+                .max_by_key(|(name, _)| name.len())
+                .map(|(_, price)| *price)
+        })
+    }
+}
+
+tokio::task_local! {
+    static SCOPE: Scope;
+}
+
+struct Scope {
+    tool: String,
+    used: Mutex<TokenUsage>,
+}
+
+// Runs `future` with the API calls it makes attributed to `tool`, and returns
+// what they used. A nested scope's usage also counts toward the enclosing one.
+// Tasks spawned inside do not inherit the scope.
+pub async fn scope<F: Future>(tool: &str, future: F) -> (F::Output, TokenUsage) {
+    let scope = Scope {
+        tool: tool.to_string(),
+        used: Mutex::new(TokenUsage::default()),
+    };
+    let (output, used) = SCOPE
+        .scope(scope, async {
+            let output = future.await;
+            let used = SCOPE.with(|s| *s.used.lock().unwrap());
+            (output, used)
+        })
+        .await;
+    let _ = SCOPE.try_with(|outer| outer.used.lock().unwrap().add(&used));
+    (output, used)
+}
+
+// The tool of the innermost enclosing scope, if any.
+pub fn current_tool() -> Option<String> {
+    SCOPE.try_with(|s| s.tool.clone()).ok()
+}
+
+#[derive(Default)]
+struct Totals {
+    all: TokenUsage,
+    by_tool: BTreeMap<String, TokenUsage>,
+    by_key: BTreeMap<String, TokenUsage>,
+    by_model: BTreeMap<String, TokenUsage>,
+}
+
+// Token usage of every API response since startup, per tool, key, and model.
+pub struct UsageTracker {
+    prices: PriceTable,
+    totals: Mutex<Totals>,
+}
+
+impl UsageTracker {
+    pub fn new(prices: PriceTable) -> Self {
+        Self {
+            prices,
+            totals: Mutex::new(Totals::default()),
+        }
+    }
+
+    pub fn record(&self, api_key: &str, model: &str, prompt_tokens: u64, completion_tokens: u64) {
+        let cost_usd = self
+            .prices
+            .price(model)
+            .map(|p| (prompt_tokens as f64 * p.input + completion_tokens as f64 * p.output) / 1_000_000.0)
+            .unwrap_or(0.0);
+        let usage = TokenUsage { requests: 1, prompt_tokens, completion_tokens, cost_usd };
+        let tool = SCOPE
+            .try_with(|s| {
+                s.used.lock().unwrap().add(&usage);
+                s.tool.clone()
+            })
+            .unwrap_or_else(|_| "(background)".to_string());
+
+        let mut totals = self.totals.lock().unwrap();
+        totals.all.add(&usage);
+        totals.by_tool.entry(tool).or_default().add(&usage);
+        totals.by_key.entry(keys::mask(api_key)).or_default().add(&usage);
+        totals.by_model.entry(model.to_string()).or_default().add(&usage);
+    }
+
+    // Markdown tables per tool, key, and model, with models missing from the
+    // price table called out since their cost counts as zero.
+    pub fn report(&self) -> String {
+        let totals = self.totals.lock().unwrap();
+        if totals.all.requests == 0 {
+            return "No API requests have been made since the server started.".to_string();
+        }
+
+        let mut out = format!("## Usage Since Startup\n\n{}\n", summary(&totals.all));
+        for (title, rows) in [("Tool", &totals.by_tool), ("Key", &totals.by_key), ("Model", &totals.by_model)] {
+            out.push_str(&format!(
+                "\n### By {title}\n\n| {title} | Requests | Prompt tokens | Completion tokens | Est. cost (USD) |\n|---|---|---|---|---|\n"
+            ));
+            let mut rows: Vec<_> = rows.iter().collect();
+            rows.sort_by(|a, b| b.1.cost_usd.total_cmp(&a.1.cost_usd).then(b.1.prompt_tokens.cmp(&a.1.prompt_tokens)));
+            for (name, usage) in rows {
+                out.push_str(&format!(
+                    "| `{name}` | {} | {} | {} | {:.4} |\n",
+                    usage.requests, usage.prompt_tokens, usage.completion_tokens, usage.cost_usd
+                ));
+            }
+        }
+
+        let unpriced: Vec<&str> = totals
+            .by_model
+            .keys()
+            .filter(|m| self.prices.price(m).is_none())
+            .map(String::as_str)
+            .collect();
+        if !unpriced.is_empty() {
+            out.push_str(&format!(
+                "\nNo price is configured for {}; their cost is counted as zero. Add them to MODEL_PRICES.\n",
+                unpriced.join(", ")
+            ));
+        }
+        out
+    }
+
+    // One line per tool for the shutdown log.
+    pub fn summary_lines(&self) -> Vec<String> {
+        let totals = self.totals.lock().unwrap();
+        let mut lines = vec![format!("Usage since startup: {}", summary(&totals.all))];
+        for (tool, usage) in &totals.by_tool {
+            lines.push(format!("  {tool}: {}", summary(usage)));
+        }
+        lines
+    }
+}
+
+fn summary(usage: &TokenUsage) -> String {
+    format!(
+        "{} requests, {} prompt tokens, {} completion tokens, est. ${:.4}",
+        usage.requests, usage.prompt_tokens, usage.completion_tokens, usage.cost_usd
+    )
+}
@@ -0,0 +1,299 @@
+use crate::archive;
+use crate::external;
+use crate::gc;
+use crate::git;
+use crate::process::ProcessMetrics;
+use anyhow::{bail, Context, Result};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+#[derive(Clone)]
+pub struct WorkspaceConfig {
+    pub root_dir: PathBuf,
+    pub max_archive_bytes: u64,
+    // Time limit for cloning a git URL (CLONE_TIMEOUT_SECS).
+    pub clone_timeout: Duration,
+    // Largest clone that is analyzed (MAX_CLONE_BYTES), checked once the
+    // clone finishes.
+    pub max_clone_bytes: u64,
+    pub process_metrics: Arc<ProcessMetrics>,
+    // Copy plain directories to local storage before analysis by default.
+    pub mirror: bool,
+    // Local directories and archives must resolve to a path under one of
+    // these (ALLOWED_WORKSPACE_ROOTS, canonicalized); None allows any path.
+    pub allowed_roots: Option<Vec<PathBuf>>,
+}
+
+// A directory to analyze. Temporary workspaces (extracted archives, local
+// mirrors, remote clones, and historical git worktrees) are removed when the
+// value is dropped.
+pub struct Workspace {
+    path: PathBuf,
+    // The directory a mirror was copied from, or the URL a clone came from.
+    source: Option<PathBuf>,
+    cleanup: Cleanup,
+}
+
+enum Cleanup {
+    None,
+    TempDir(PathBuf),
+    Worktree { repo: PathBuf, dir: PathBuf },
+}
+
+impl Workspace {
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // Stable identity for per-directory caches such as the embedding index,
+    // which would otherwise be rebuilt for every fresh mirror.
+    pub fn cache_key(&self) -> &Path {
+        self.source.as_deref().unwrap_or(&self.path)
+    }
+}
+
+impl Drop for Workspace {
+    fn drop(&mut self) {
+        match &self.cleanup {
+            Cleanup::None => {}
+            Cleanup::TempDir(dir) => {
+                if let Err(e) = std::fs::remove_dir_all(dir) {
+                    tracing::warn!("Failed to clean up workspace '{}': {}", dir.display(), e);
+                }
+            }
+            Cleanup::Worktree { repo, dir } => {
+                let removed = std::process::Command::new("git")
+                    .arg("-C")
+                    .arg(repo)
+                    .args(["worktree", "remove", "--force"])
+                    .arg(dir)
+                    .status()
+                    .is_ok_and(|s| s.success());
+                if !removed {
+                    tracing::warn!("Failed to remove git worktree '{}'; deleting it and pruning", dir.display());
+                    let _ = std::fs::remove_dir_all(dir);
+                    let _ = std::process::Command::new("git")
+                        .arg("-C")
+                        .arg(repo)
+                        .args(["worktree", "prune"])
+                        .status();
+                }
+            }
+        }
+    }
+}
+
+// Whether `directory` is a git URL rather than a local path.
+pub fn is_remote(directory: &str) -> bool {
+    git::parse_remote(directory).is_some()
+}
+
+// Rejects a local path that is relative, missing, not a directory (or an
+// archive file), or outside `WorkspaceConfig::allowed_roots`, before anything
+// reads it or hands it to a subprocess. Symlinks are resolved first, so a
+// link cannot lead out of an allowed root.
+pub fn check_local(path: &Path, config: &WorkspaceConfig) -> Result<()> {
+    if !path.is_absolute() {
+        bail!("must be an absolute path");
+    }
+    let is_archive = archive::is_archive(path);
+    let resolved = match path.canonicalize() {
+        Ok(resolved) => resolved,
+        Err(_) if is_archive => bail!("no such archive"),
+        Err(_) => bail!("no such directory"),
+    };
+    if is_archive && !resolved.is_file() {
+        bail!("not an archive file");
+    }
+    if !is_archive && !resolved.is_dir() {
+        bail!("not a directory");
+    }
+    if let Some(roots) = &config.allowed_roots {
+        if !roots.iter().any(|root| resolved.starts_with(root)) {
+            bail!("outside the allowed workspace roots (ALLOWED_WORKSPACE_ROOTS)");
+        }
+    }
+    Ok(())
+}
+
+// `mirror` overrides `WorkspaceConfig::mirror` for this call. It only affects
+// plain directories; archives, clones, and commits are already local copies.
+pub async fn open(directory: &str, commit: Option<&str>, mirror: Option<bool>, config: &WorkspaceConfig) -> Result<Workspace> {
+    if let Some((url, reference)) = git::parse_remote(directory) {
+        if commit.is_some() {
+            bail!("'commit' cannot be used with a git URL; append '#<branch or tag>' to the URL instead");
+        }
+        return open_remote(directory, url, reference, config).await;
+    }
+
+    let path = PathBuf::from(directory);
+    check_local(&path, config)?;
+    let is_archive = archive::is_archive(&path);
+    match commit {
+        Some(_) if is_archive => bail!("'commit' cannot be used with an archive"),
+        Some(commit) => return open_commit(&path, commit, config).await,
+        None if !is_archive && mirror.unwrap_or(config.mirror) => return open_mirror(&path, config).await,
+        None if !is_archive => return Ok(Workspace { path, source: None, cleanup: Cleanup::None }),
+        None => {}
+    }
+
+    let temp_dir = config.root_dir.join(format!("archive-{}", uuid::Uuid::new_v4()));
+    let mut workspace = Workspace {
+        path: temp_dir.clone(),
+        source: None,
+        cleanup: Cleanup::TempDir(temp_dir.clone()),
+    };
+
+    tracing::info!("Extracting archive '{}' to '{}'", path.display(), temp_dir.display());
+    let max_bytes = config.max_archive_bytes;
+    let dest = temp_dir.clone();
+    tokio::task::spawn_blocking(move || {
+        std::fs::create_dir_all(&dest)?;
+        archive::extract(&path, &dest, max_bytes)
+    })
+    .await
+    .context("Archive extraction task panicked")??;
+
+    if let Some(inner) = single_top_level_dir(&temp_dir) {
+        workspace.path = inner;
+    }
+    Ok(workspace)
+}
+
+// Copies the files that pass ignore filtering to local storage, so every later
+// read (report generation, retrieval, reference checks) avoids slow network
+// filesystems. The source directory is only walked once and never written.
+async fn open_mirror(directory: &Path, config: &WorkspaceConfig) -> Result<Workspace> {
+    let temp_dir = config.root_dir.join(format!("mirror-{}", uuid::Uuid::new_v4()));
+    let name = directory.file_name().map(|n| n.to_os_string()).unwrap_or_else(|| "codebase".into());
+    let workspace = Workspace {
+        path: temp_dir.join(name),
+        source: Some(directory.to_path_buf()),
+        cleanup: Cleanup::TempDir(temp_dir),
+    };
+
+    let started = std::time::Instant::now();
+    external::stage_filtered_copy(directory, &workspace.path, false)
+        .await
+        .with_context(|| format!("Failed to mirror '{}' to local storage", directory.display()))?;
+    tracing::info!(
+        "Mirrored '{}' to '{}' in {:.1}s",
+        directory.display(),
+        workspace.path.display(),
+        started.elapsed().as_secs_f64()
+    );
+    Ok(workspace)
+}
+
+// Shallow-clones a remote repository, for clients running on a different
+// machine than the code.
+async fn open_remote(directory: &str, url: &str, reference: Option<&str>, config: &WorkspaceConfig) -> Result<Workspace> {
+    let temp_dir = config.root_dir.join(format!("remote-{}", uuid::Uuid::new_v4()));
+    let name = url
+        .trim_end_matches('/')
+        .rsplit(['/', ':'])
+        .next()
+        .map(|n| n.trim_end_matches(".git"))
+        .filter(|n| !n.is_empty())
+        .unwrap_or("codebase");
+    let workspace = Workspace {
+        path: temp_dir.join(name),
+        // Keeps the embedding index stable across fresh clones.
+        source: Some(PathBuf::from(directory)),
+        cleanup: Cleanup::TempDir(temp_dir.clone()),
+    };
+
+    tokio::fs::create_dir_all(&temp_dir)
+        .await
+        .with_context(|| format!("Failed to create '{}'", temp_dir.display()))?;
+    let started = std::time::Instant::now();
+    git::shallow_clone(url, reference, &workspace.path, config.clone_timeout, &config.process_metrics).await?;
+    let clone_path = workspace.path.clone();
+    let size = tokio::task::spawn_blocking(move || gc::disk_usage(&clone_path))
+        .await
+        .context("Clone size task panicked")?;
+    if size > config.max_clone_bytes {
+        bail!("The clone of '{url}' is {size} bytes, over the {}-byte limit (MAX_CLONE_BYTES)", config.max_clone_bytes);
+    }
+    tracing::info!(
+        "Cloned '{}' to '{}' in {:.1}s",
+        directory,
+        workspace.path.display(),
+        started.elapsed().as_secs_f64()
+    );
+    Ok(workspace)
+}
+
+// Checks out `commit` into a detached worktree of the repository containing
+// `directory`, and points the workspace at the same subdirectory within it.
+async fn open_commit(directory: &Path, commit: &str, config: &WorkspaceConfig) -> Result<Workspace> {
+    if commit.is_empty() || commit.starts_with('-') {
+        bail!("Invalid commit '{commit}'");
+    }
+
+    let repo = PathBuf::from(git::run(directory, &["rev-parse", "--show-toplevel"]).await?);
+    let prefix = git::run(directory, &["rev-parse", "--show-prefix"]).await?;
+    let sha = git::run(directory, &["rev-parse", "--verify", "--quiet", &format!("{commit}^{{commit}}")])
+        .await
+        .with_context(|| format!("Commit '{commit}' not found in '{}'", repo.display()))?;
+
+    tokio::fs::create_dir_all(&config.root_dir)
+        .await
+        .with_context(|| format!("Failed to create '{}'", config.root_dir.display()))?;
+    let dir = config.root_dir.join(format!("commit-{}", uuid::Uuid::new_v4()));
+
+    tracing::info!("Checking out {} of '{}' to '{}'", sha, repo.display(), dir.display());
+    git::run(&repo, &["worktree", "add", "--detach", &dir.to_string_lossy(), &sha]).await?;
+
+    Ok(Workspace {
+        path: dir.join(prefix),
+        source: None,
+        cleanup: Cleanup::Worktree { repo, dir },
+    })
+}
+
+// Most archives wrap everything in one folder (e.g. `project-1.2/`); analyze
+// that folder so report paths match the original layout.
+fn single_top_level_dir(dir: &Path) -> Option<PathBuf> {
+    let mut entries = std::fs::read_dir(dir).ok()?.filter_map(|e| e.ok());
+    let only = entries.next()?;
+    if entries.next().is_some() || !only.file_type().ok()?.is_dir() {
+        return None;
+    }
+    Some(only.path())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempRepo;
+
+    fn config(root: &Path, max_clone_bytes: u64) -> WorkspaceConfig {
+        WorkspaceConfig {
+            root_dir: root.to_path_buf(),
+            max_archive_bytes: 0,
+            clone_timeout: Duration::from_secs(60),
+            max_clone_bytes,
+            process_metrics: Arc::default(),
+            mirror: false,
+            allowed_roots: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn removes_clones_over_the_size_limit() {
+        let repo = TempRepo::with_files(&[("src/lib.rs", &"// filler\n".repeat(1000))]).unwrap();
+        repo.commit("Initial commit").unwrap();
+        let url = format!("file://{}", repo.path().display());
+        let scratch = TempRepo::new().unwrap();
+
+        let workspace = open_remote(&url, &url, None, &config(scratch.path(), 1024 * 1024)).await.unwrap();
+        assert!(workspace.path().join("src/lib.rs").is_file());
+        drop(workspace);
+
+        let error = open_remote(&url, &url, None, &config(scratch.path(), 1000)).await.err().unwrap();
+        assert!(error.to_string().contains("MAX_CLONE_BYTES"), "{error:#}");
+        assert_eq!(std::fs::read_dir(scratch.path()).unwrap().count(), 0);
+    }
+}
@@ -0,0 +1,36 @@
+use ai_code_agent::agent::{RunOptions, Scope};
+use ai_code_agent::testing::{Harness, TempRepo};
+
+#[tokio::test]
+async fn plans_with_templates_and_canned_responses() {
+    let repo = TempRepo::with_files(&[("src/lib.rs", "pub fn add(a: i32, b: i32) -> i32 { a + b }")]).unwrap();
+    let harness = Harness::builder()
+        .respond("Feature Request: Add subtraction", "# Plan\n\nAdd `sub` next to `add` in `src/lib.rs`.")
+        .template("plan_feature/analysis_system.md", "You are reviewing a calculator library.")
+        .build()
+        .unwrap();
+
+    let result = harness.agent().plan_feature(&Scope::new(repo.directory()), "Add subtraction", RunOptions::default()).await.unwrap();
+    assert!(result.text.contains("Add `sub`"));
+    assert!(result.plan_id.is_some());
+
+    let requests = harness.requests();
+    assert!(requests.iter().any(|r| r.system() == "You are reviewing a calculator library."));
+    // The fake viewer lists the repository's files.
+    assert!(requests.iter().any(|r| r.user().contains("pub fn add")));
+    assert!(harness.data_dir().join("plans.json").is_file());
+}
+
+#[tokio::test]
+async fn answers_from_a_canned_report() {
+    let repo = TempRepo::with_files(&[("main.py", "print('hi')")]).unwrap();
+    repo.commit("Initial commit").unwrap();
+    let harness = Harness::builder().report("### `main.py`\n\nCANNED REPORT\n").build().unwrap();
+
+    let answer = harness.agent().explain_code(&Scope::new(repo.directory()), "What does main.py do?", RunOptions::default()).await.unwrap();
+    assert!(!answer.text.is_empty());
+    assert!(harness.requests().iter().any(|r| r.user().contains("CANNED REPORT")));
+
+    harness.clear_requests();
+    assert!(harness.requests().is_empty());
+}